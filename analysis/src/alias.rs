@@ -0,0 +1,230 @@
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AliasResult {
+    NoAlias,
+    MayAlias,
+    MustAlias,
+}
+
+/// Where the memory a pointer points into comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    /// The address of a local in the current frame.
+    Local(ir::Local),
+    /// The result of a `stack_alloc` at the given statement.
+    StackAlloc(ir::Location),
+    /// The result of a `box_alloc` at the given statement.
+    Box(ir::Location),
+    /// A pointer passed in as an argument that is never reassigned.
+    Arg(ir::Local),
+    Unknown,
+}
+
+/// Answers whether two places can refer to overlapping memory within a single body.
+pub struct AliasAnalysis<'a> {
+    body: &'a ir::Body,
+    noalias_args: bool,
+    origins: HashMap<ir::Local, Origin>,
+    escaped: HashSet<ir::Local>,
+}
+
+enum Object<'p> {
+    Local(ir::Local, &'p [ir::PlaceElem]),
+    Deref(ir::Local, &'p [ir::PlaceElem]),
+    Unknown,
+}
+
+impl<'a> AliasAnalysis<'a> {
+    pub fn new(module: &ir::Module, body: &'a ir::Body) -> Self {
+        let mut analysis = AliasAnalysis {
+            body,
+            noalias_args: module.decls[body.decl].attrs.noalias,
+            origins: HashMap::new(),
+            escaped: HashSet::new(),
+        };
+
+        analysis.compute();
+        analysis
+    }
+
+    pub fn origin(&self, local: ir::Local) -> Origin {
+        self.origins.get(&local).copied().unwrap_or(Origin::Unknown)
+    }
+
+    /// Whether the address of this local is ever taken.
+    pub fn is_escaped(&self, local: ir::Local) -> bool {
+        self.escaped.contains(&local)
+    }
+
+    /// Whether accessing the place goes through a pointer or box.
+    pub fn is_indirect(&self, place: &ir::Place) -> bool {
+        !matches!(self.object(place), Object::Local(..))
+    }
+
+    /// Whether the place is part of a local that no pointer can refer to.
+    pub fn is_private(&self, place: &ir::Place) -> bool {
+        match self.object(place) {
+            | Object::Local(local, _) => !self.is_escaped(local),
+            | _ => false,
+        }
+    }
+
+    pub fn may_alias(&self, a: &ir::Place, b: &ir::Place) -> bool {
+        self.alias(a, b) != AliasResult::NoAlias
+    }
+
+    pub fn alias(&self, a: &ir::Place, b: &ir::Place) -> AliasResult {
+        match (self.object(a), self.object(b)) {
+            | (Object::Local(x, xs), Object::Local(y, ys)) => {
+                if x != y {
+                    AliasResult::NoAlias
+                } else {
+                    projections(xs, ys)
+                }
+            },
+            | (Object::Local(local, _), Object::Deref(ptr, _)) | (Object::Deref(ptr, _), Object::Local(local, _)) => {
+                if !self.is_escaped(local) {
+                    return AliasResult::NoAlias;
+                }
+
+                match self.origin(ptr) {
+                    | Origin::Local(l) if l == local => AliasResult::MayAlias,
+                    | Origin::Unknown => AliasResult::MayAlias,
+                    | _ => AliasResult::NoAlias,
+                }
+            },
+            | (Object::Deref(x, xs), Object::Deref(y, ys)) => {
+                if x == y {
+                    return projections(xs, ys);
+                }
+
+                match (self.origin(x), self.origin(y)) {
+                    | (Origin::Unknown, _) | (_, Origin::Unknown) => AliasResult::MayAlias,
+                    | (Origin::Arg(_), Origin::Arg(_)) if !self.noalias_args => AliasResult::MayAlias,
+                    | (ox, oy) if ox == oy => AliasResult::MayAlias,
+                    | _ => AliasResult::NoAlias,
+                }
+            },
+            | _ => AliasResult::MayAlias,
+        }
+    }
+
+    fn compute(&mut self) {
+        let mut assigns = HashMap::<ir::Local, Vec<(&ir::RValue, ir::Location)>>::new();
+        let mut clobbered = HashSet::new();
+
+        for (block, data) in self.body.blocks.iter_enumerated() {
+            for (i, stmt) in data.stmts.iter().enumerate() {
+                let loc = ir::Location {
+                    body: self.body.id,
+                    block,
+                    stmt: i,
+                };
+
+                match stmt {
+                    | ir::Stmt::Assign(place, rvalue) => {
+                        if place.elems.is_empty() {
+                            assigns.entry(place.local).or_default().push((rvalue, loc));
+                        } else {
+                            clobbered.insert(place.local);
+                        }
+
+                        if let ir::RValue::AddrOf(place) = rvalue {
+                            self.escaped.insert(place.local);
+                        }
+                    },
+                    | ir::Stmt::SetDiscr(place, _) => {
+                        clobbered.insert(place.local);
+                    },
+                    | ir::Stmt::Call(rets, ..) => {
+                        clobbered.extend(rets.iter().map(|r| r.local));
+                    },
+                    | _ => {},
+                }
+            }
+        }
+
+        for local in &self.body.locals {
+            if local.kind == ir::LocalKind::Arg && !assigns.contains_key(&local.id) && !clobbered.contains(&local.id) && !self.is_escaped(local.id) {
+                self.origins.insert(local.id, Origin::Arg(local.id));
+            }
+        }
+
+        let mut changed = true;
+
+        while changed {
+            changed = false;
+
+            for (local, defs) in &assigns {
+                if clobbered.contains(local) || self.is_escaped(*local) {
+                    continue;
+                }
+
+                let mut origins = defs.iter().map(|(rvalue, loc)| self.rvalue_origin(rvalue, *loc));
+                let first = origins.next().unwrap();
+                let origin = if origins.all(|o| o == first) { first } else { Origin::Unknown };
+
+                if self.origins.get(local) != Some(&origin) {
+                    self.origins.insert(*local, origin);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    fn rvalue_origin(&self, rvalue: &ir::RValue, loc: ir::Location) -> Origin {
+        match rvalue {
+            | ir::RValue::AddrOf(place) => match self.object(place) {
+                | Object::Local(local, _) => Origin::Local(local),
+                | Object::Deref(ptr, _) => self.origin(ptr),
+                | Object::Unknown => Origin::Unknown,
+            },
+            | ir::RValue::Use(op) | ir::RValue::Lea { base: op, .. } => self.op_origin(op),
+            | ir::RValue::Intrinsic(name, args) => match name.as_str() {
+                | "stack_alloc" => Origin::StackAlloc(loc),
+                | "box_alloc" => Origin::Box(loc),
+                | "ptr_offset" => self.op_origin(&args[0]),
+                | _ => Origin::Unknown,
+            },
+            | _ => Origin::Unknown,
+        }
+    }
+
+    fn object<'p>(&self, place: &'p ir::Place) -> Object<'p> {
+        // projecting a box implicitly dereferences it
+        let is_box = matches!(self.body.locals[place.local].ty.access().kind, ir::Type::Box(_));
+
+        match place.elems.iter().position(|e| matches!(e, ir::PlaceElem::Deref)) {
+            | None if is_box && !place.elems.is_empty() => Object::Deref(place.local, &place.elems),
+            | None => Object::Local(place.local, &place.elems),
+            | Some(0) if !place.elems[1..].iter().any(|e| matches!(e, ir::PlaceElem::Deref)) => Object::Deref(place.local, &place.elems[1..]),
+            | Some(_) => Object::Unknown,
+        }
+    }
+
+    fn op_origin(&self, op: &ir::Operand) -> Origin {
+        match op {
+            | ir::Operand::Place(place) if place.elems.is_empty() => self.origin(place.local),
+            | _ => Origin::Unknown,
+        }
+    }
+}
+
+fn projections(a: &[ir::PlaceElem], b: &[ir::PlaceElem]) -> AliasResult {
+    for (x, y) in a.iter().zip(b) {
+        match (x, y) {
+            | (ir::PlaceElem::Field(i), ir::PlaceElem::Field(j)) if i != j => return AliasResult::NoAlias,
+            | (ir::PlaceElem::Field(_), ir::PlaceElem::Field(_)) => {},
+            | (ir::PlaceElem::Index(i), ir::PlaceElem::Index(j)) if i == j => {},
+            | (ir::PlaceElem::Downcast(i), ir::PlaceElem::Downcast(j)) if i == j => {},
+            | _ => return AliasResult::MayAlias,
+        }
+    }
+
+    if a.len() == b.len() {
+        AliasResult::MustAlias
+    } else {
+        AliasResult::MayAlias
+    }
+}
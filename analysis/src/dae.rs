@@ -0,0 +1,76 @@
+use crate::Analyzer;
+use ir::visitor::Visitor;
+use std::collections::HashSet;
+use transform::signature;
+use transform::Transform;
+
+/// Dead argument elimination. Removes the parameters of local functions that
+/// their body never uses, along with the arguments passed for them.
+pub struct DaeAnalyzer;
+
+pub struct DaeTransform {
+    params: Vec<(ir::DeclId, Vec<usize>)>,
+}
+
+#[derive(Default)]
+struct Uses {
+    locals: HashSet<ir::Local>,
+}
+
+impl Analyzer for DaeAnalyzer {
+    type Output = DaeTransform;
+
+    fn analyze(&mut self, module: &ir::Module) -> Self::Output {
+        let escaped = signature::escaped(module);
+        let mut params = Vec::new();
+
+        for body in &module.bodies {
+            let decl = &module.decls[body.decl];
+
+            if decl.linkage != ir::Linkage::Local || decl.attrs.c_abi || escaped.contains(&decl.id) {
+                continue;
+            }
+
+            let mut uses = Uses::default();
+
+            uses.visit_body(body);
+
+            let dead = body
+                .args()
+                .enumerate()
+                .filter(|(_, arg)| !uses.locals.contains(&arg.id))
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>();
+
+            if !dead.is_empty() {
+                params.push((decl.id, dead));
+            }
+        }
+
+        DaeTransform { params }
+    }
+}
+
+impl Transform for DaeTransform {
+    fn apply(&mut self, module: &mut ir::Module) {
+        for (decl, dead) in self.params.drain(..) {
+            signature::remove_params(module, decl, &dead);
+        }
+    }
+}
+
+impl Visitor for Uses {
+    fn visit_place(&mut self, place: &ir::Place) {
+        self.locals.insert(place.local);
+        self.super_place(place);
+    }
+
+    // an argument that is only dropped is still needed, its drop would read an uninitialized local otherwise
+    fn visit_init(&mut self, local: ir::Local, _: ir::Location) {
+        self.locals.insert(local);
+    }
+
+    fn visit_drop(&mut self, local: ir::Local, _: ir::Location) {
+        self.locals.insert(local);
+    }
+}
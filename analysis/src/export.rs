@@ -0,0 +1,129 @@
+use crate::Analyzer;
+use transform::Transform;
+
+/// Creates a wrapper following the C calling convention for every function marked `@extern_c_export`.
+///
+/// The wrapper of `@name` is exported as `@name_c` and calls the original function, which stays as it is so that
+/// other lowlang modules can keep calling it. Since a C function returns at most one value, multiple return values
+/// are returned as a tuple.
+pub struct ExportAnalyzer;
+
+pub struct ExportTransform {
+    exports: Vec<ir::DeclId>,
+}
+
+impl Analyzer for ExportAnalyzer {
+    type Output = ExportTransform;
+
+    fn analyze(&mut self, module: &ir::Module) -> Self::Output {
+        // the exports that can not be wrapped are reported by `check`
+        let exports = module
+            .decls
+            .iter()
+            .filter(|decl| decl.attrs.extern_c_export && export_error(decl).is_none())
+            .map(|decl| decl.id)
+            .collect();
+
+        ExportTransform { exports }
+    }
+}
+
+/// Returns an error for every function marked `@extern_c_export` that can not be exported to C.
+/// `ExportAnalyzer` leaves these functions as they are, so this is checked before the mandatory passes.
+pub fn check(module: &ir::Module) -> Vec<String> {
+    module
+        .decls
+        .iter()
+        .filter(|decl| decl.attrs.extern_c_export)
+        .filter_map(export_error)
+        .collect()
+}
+
+fn export_error(decl: &ir::Decl) -> Option<String> {
+    match &decl.ty.kind {
+        | ir::Type::Func(sig) if sig.call_conv == ir::CallConv::Fluix => {
+            if sig.params.iter().chain(&sig.rets).any(is_generic) {
+                Some(format!("'{}' can not be exported to C because it is generic", decl.name))
+            } else {
+                None
+            }
+        },
+        | ir::Type::Func(_) => Some(format!("'{}' already has a foreign calling convention", decl.name)),
+        | _ => Some(format!("'{}' can not be exported to C because it is not a function", decl.name)),
+    }
+}
+
+impl Transform for ExportTransform {
+    fn apply(&mut self, module: &mut ir::Module) {
+        for for_ in self.exports.drain(..) {
+            let sig = module.decls[for_].ty.signature();
+            let id = module.decls.next_idx();
+            let name = format!("{}_c", module.decls[for_].name);
+            let packed = sig.rets.len() > 1;
+            let into = ir::Signature {
+                params: sig.params.clone(),
+                rets: if packed {
+                    vec![ir::Ty::new(ir::Type::Tuple(sig.rets.clone()))]
+                } else {
+                    sig.rets.clone()
+                },
+                call_conv: ir::CallConv::C,
+                variadic: false,
+            };
+
+            module.decls.insert(
+                id,
+                ir::Decl {
+                    id,
+                    name,
+                    linkage: ir::Linkage::Export,
+                    ty: ir::Ty::new(ir::Type::Func(into.clone())),
+                    attrs: ir::Attrs {
+                        c_abi: true,
+                        visibility: module.decls[for_].attrs.visibility,
+                        ..ir::Attrs::default()
+                    },
+                },
+            );
+
+            let bodyid = module.bodies.next_idx();
+            let mut body = ir::Body {
+                id: bodyid,
+                decl: id,
+                locals: Default::default(),
+                blocks: Default::default(),
+            };
+
+            let mut builder = ir::Builder::new(&mut body);
+            let rets = into.rets.into_iter().map(|r| builder.create_ret(r)).collect::<Vec<_>>();
+            let params = into.params.into_iter().map(|p| builder.create_arg(p)).collect::<Vec<_>>();
+            let entry = builder.create_block();
+
+            builder.set_block(entry);
+
+            let args = params.into_iter().map(|p| ir::Operand::Place(ir::Place::new(p))).collect();
+            let rets = if packed {
+                (0..sig.rets.len()).map(|i| ir::Place::new(rets[0]).field(i)).collect()
+            } else {
+                rets.into_iter().map(ir::Place::new).collect()
+            };
+
+            let func = ir::Operand::Const(ir::Const::Addr(for_));
+
+            builder.call(rets, func, args);
+            builder.return_();
+
+            module.bodies.insert(bodyid, body);
+        }
+    }
+}
+
+fn is_generic(ty: &ir::Ty) -> bool {
+    match &ty.kind {
+        | ir::Type::Opaque(_) | ir::Type::Type(_) | ir::Type::Vwt(_) => true,
+        | ir::Type::Ptr(to) | ir::Type::Box(to) | ir::Type::Discr(to) | ir::Type::Newtype(_, to) => is_generic(to),
+        | ir::Type::Tuple(tys) | ir::Type::Union(tys) | ir::Type::Tagged(tys) => tys.iter().any(is_generic),
+        | ir::Type::Func(sig) => sig.params.iter().chain(&sig.rets).any(is_generic),
+        | _ => false,
+    }
+}
@@ -156,12 +156,13 @@ impl VisitorMut for GenericFixer {
                 lhs.elems.push(ir::PlaceElem::Deref);
                 rhs.elems.push(ir::PlaceElem::Deref);
             }
-        } else if let ir::Stmt::Call(rets, func, args) = stmt {
+        } else if let ir::Stmt::Call(rets, func, args, _) = stmt {
             let func_ty = ir::operand_type(self.module(), self.body(), func);
 
             if let ir::Type::Func(ir::Signature {
                 params: param_tys,
                 rets: ret_tys,
+                ..
             }) = func_ty.kind
             {
                 for (i, param_ty) in param_tys.iter().enumerate() {
@@ -203,7 +204,7 @@ impl VisitorMut for GenericFixer {
             *ty = ir::Ty::new(ir::Type::Ptr(Box::new(ir::Ty::new(ir::Type::Opaque(
                 name.clone(),
             )))));
-        } else if let ir::Type::Func(ir::Signature { params, rets }) = &mut ty.kind {
+        } else if let ir::Type::Func(ir::Signature { params, rets, .. }) = &mut ty.kind {
             for ty in params.iter_mut() {
                 self.visit_type(ty);
             }
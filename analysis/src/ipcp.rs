@@ -0,0 +1,99 @@
+use crate::Analyzer;
+use ir::visitor::Visitor;
+use std::collections::HashMap;
+use transform::signature;
+use transform::Transform;
+
+/// Interprocedural constant propagation. For local functions whose every call site
+/// is known, parameters that always receive the same constant are bound to it in the
+/// callee and removed.
+pub struct IpcpAnalyzer;
+
+pub struct IpcpTransform {
+    /// The parameters to remove from each function, with the constant to bind them to.
+    params: Vec<(ir::DeclId, Vec<(usize, ir::Const)>)>,
+}
+
+#[derive(Default)]
+struct CallSites {
+    calls: HashMap<ir::DeclId, Vec<Vec<ir::Operand>>>,
+}
+
+impl Analyzer for IpcpAnalyzer {
+    type Output = IpcpTransform;
+
+    fn analyze(&mut self, module: &ir::Module) -> Self::Output {
+        let mut sites = CallSites::default();
+        let escaped = signature::escaped(module);
+        let mut params = Vec::new();
+
+        sites.visit_module(module);
+
+        for body in &module.bodies {
+            let decl = &module.decls[body.decl];
+
+            if decl.linkage != ir::Linkage::Local || decl.attrs.c_abi || escaped.contains(&decl.id) {
+                continue;
+            }
+
+            let args = body.args().map(|a| a.id).collect::<Vec<_>>();
+            let calls = sites.calls.get(&decl.id).map(|c| &c[..]).unwrap_or(&[]);
+
+            if calls.iter().any(|c| c.len() != args.len()) {
+                continue;
+            }
+
+            let mut removed = Vec::new();
+
+            for i in 0..args.len() {
+                if let Some(ir::Operand::Const(c)) = calls.first().map(|c| &c[i]) {
+                    if calls.iter().all(|c2| matches!(&c2[i], ir::Operand::Const(c2) if c2 == c)) {
+                        removed.push((i, c.clone()));
+                    }
+                }
+            }
+
+            if !removed.is_empty() {
+                params.push((decl.id, removed));
+            }
+        }
+
+        IpcpTransform { params }
+    }
+}
+
+impl Transform for IpcpTransform {
+    fn apply(&mut self, module: &mut ir::Module) {
+        for (decl, removed) in self.params.drain(..) {
+            for body in module.bodies.iter_mut().filter(|b| b.decl == decl) {
+                let args = body.args().map(|a| a.id).collect::<Vec<_>>();
+
+                for (i, c) in &removed {
+                    let init = ir::Stmt::Assign(ir::Place::new(args[*i]), ir::RValue::Use(ir::Operand::Const(c.clone())));
+
+                    body.blocks.iter_mut().next().unwrap().stmts.insert(0, init);
+                }
+            }
+
+            signature::remove_params(module, decl, &removed.iter().map(|(i, _)| *i).collect::<Vec<_>>());
+        }
+    }
+}
+
+impl Visitor for CallSites {
+    fn visit_stmt(&mut self, stmt: &ir::Stmt, loc: ir::Location) {
+        if let ir::Stmt::Call(rets, ir::Operand::Const(ir::Const::Addr(decl)), args, _) = stmt {
+            self.calls.entry(*decl).or_default().push(args.clone());
+
+            for place in rets {
+                self.visit_place(place);
+            }
+
+            for op in args {
+                self.visit_op(op);
+            }
+        } else {
+            self.super_stmt(stmt, loc);
+        }
+    }
+}
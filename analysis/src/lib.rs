@@ -2,14 +2,27 @@
 #![feature(bindings_after_at)]
 #![feature(box_patterns)]
 
+pub mod alias;
 pub mod copy;
+pub mod dae;
+pub mod export;
 pub mod generic;
+pub mod ipcp;
 pub mod lifetime;
+pub mod liveness;
+pub mod mono;
+pub mod peephole;
+pub mod profile;
+pub mod range;
+pub mod rvo;
+pub mod slots;
 pub mod stack_alloc;
+pub mod store;
 pub mod thunk;
 pub mod type_info;
 pub mod witness;
 
+use ir::progress::{CancellationToken, Cancelled, NoProgress, PassChanges, Progress};
 use transform::Transform;
 
 pub trait Analyzer {
@@ -24,12 +37,103 @@ pub fn analyze<A: Analyzer>(mut analyzer: A, module: &mut ir::Module) {
     transform.apply(module);
 }
 
+/// Options for the mandatory passes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Options {
+    pub witnesses: type_info::Witnesses,
+}
+
 pub fn mandatory(module: &mut ir::Module, target: &target_lexicon::Triple) {
-    analyze(witness::WitnessAnalyzer, module);
-    analyze(thunk::ThunkAnalyzer::new(), module);
-    analyze(generic::GenericAnalyzer, module);
-    analyze(copy::CopyAnalyzer, module);
-    analyze(type_info::TypeInfoAnalyzer::new(target), module);
-    analyze(lifetime::LifetimeAnalyzer::new(), module);
-    analyze(stack_alloc::AllocAnalyzer::new(target), module);
+    mandatory_with(module, target, Options::default(), &mut NoProgress, &CancellationToken::new()).unwrap();
+}
+
+pub fn mandatory_with(
+    module: &mut ir::Module,
+    target: &target_lexicon::Triple,
+    options: Options,
+    progress: &mut dyn Progress,
+    cancel: &CancellationToken,
+) -> Result<(), Cancelled> {
+    let mut passes = Passes { progress, cancel };
+
+    passes.run("export", export::ExportAnalyzer, module)?;
+    passes.run("mono", mono::MonoAnalyzer, module)?;
+    passes.run("witness", witness::WitnessAnalyzer, module)?;
+    passes.run("thunk", thunk::ThunkAnalyzer::new(), module)?;
+    passes.run("generic", generic::GenericAnalyzer, module)?;
+    passes.run("copy", copy::CopyAnalyzer, module)?;
+    passes.run("rvo", rvo::RvoAnalyzer::new(target), module)?;
+    passes.run("type_info", type_info::TypeInfoAnalyzer::new(target, options.witnesses), module)?;
+    passes.run("lifetime", lifetime::LifetimeAnalyzer::new(), module)?;
+    passes.run("stack_alloc", stack_alloc::AllocAnalyzer::new(target), module)
+}
+
+/// Applies a profile gathered by the interpreter, see `profile::ProfileAnalyzer`.
+/// The profile describes the module as it was written, so this runs before the mandatory passes.
+pub fn apply_profile(
+    module: &mut ir::Module,
+    profile: &ir::profile::ProfileData,
+    progress: &mut dyn Progress,
+    cancel: &CancellationToken,
+) -> Result<(), Cancelled> {
+    let mut passes = Passes { progress, cancel };
+
+    passes.run("profile", profile::ProfileAnalyzer::new(profile), module)
+}
+
+pub fn optimize(module: &mut ir::Module, target: &target_lexicon::Triple) {
+    optimize_with(module, target, &mut NoProgress, &CancellationToken::new()).unwrap();
+}
+
+pub fn optimize_with(
+    module: &mut ir::Module,
+    target: &target_lexicon::Triple,
+    progress: &mut dyn Progress,
+    cancel: &CancellationToken,
+) -> Result<(), Cancelled> {
+    let mut passes = Passes { progress, cancel };
+
+    passes.run("ipcp", ipcp::IpcpAnalyzer, module)?;
+    passes.run("dae", dae::DaeAnalyzer, module)?;
+    passes.run("store", store::StoreAnalyzer, module)?;
+    passes.run("range", range::RangeAnalyzer, module)?;
+    passes.run("peephole", peephole::PeepholeAnalyzer::new(), module)?;
+    passes.run("slots", slots::SlotAnalyzer::new(target), module)
+}
+
+struct Passes<'a> {
+    progress: &'a mut dyn Progress,
+    cancel: &'a CancellationToken,
+}
+
+impl Passes<'_> {
+    fn run<A: Analyzer>(&mut self, name: &str, mut analyzer: A, module: &mut ir::Module) -> Result<(), Cancelled> {
+        self.cancel.check()?;
+
+        let before = if self.progress.wants_pass_changes() {
+            Some(module.bodies.as_raw_slice().to_vec())
+        } else {
+            None
+        };
+
+        let start = std::time::Instant::now();
+
+        let mut transform = analyzer.analyze(module);
+
+        transform.apply(module);
+
+        let time = start.elapsed();
+
+        for stat in transform.stats() {
+            self.progress.on_pass_stat(name, &module.decls[stat.decl], &stat);
+        }
+
+        if let Some(before) = before {
+            self.progress.on_pass_changes(name, &PassChanges::new(&before, module.bodies.as_raw_slice()));
+        }
+
+        self.progress.on_pass_finish(name, time);
+
+        Ok(())
+    }
 }
@@ -63,6 +63,8 @@ impl Transform for LifetimeAnnotator {
 
 impl Visitor for LifetimeAnalyzer {
     fn visit_body(&mut self, body: &ir::Body) {
+        let first = self.annotations.len();
+
         self.alive.clear();
         self.ties.clear();
 
@@ -81,6 +83,22 @@ impl Visitor for LifetimeAnalyzer {
         }
 
         self.reverse_anns(start);
+
+        // locals the front end initializes or drops itself already have their lifetime
+        let explicit = body
+            .blocks
+            .iter()
+            .flat_map(|block| &block.stmts)
+            .filter_map(|stmt| match stmt {
+                ir::Stmt::Init(local) | ir::Stmt::Drop(local) => Some(*local),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+
+        let mut anns = self.annotations.split_off(first);
+
+        anns.retain(|ann| !explicit.contains(&ann.local));
+        self.annotations.extend(anns);
     }
 }
 
@@ -130,6 +148,7 @@ impl LifetimeAnalyzer {
             match stmt {
                 ir::Stmt::Init(_) => {}
                 ir::Stmt::Drop(_) => {}
+                ir::Stmt::Loc(_) => {}
                 ir::Stmt::Assign(place, rvalue) => {
                     self.place_lifetime(place, loc, true);
                     self.rvalue_lifetime(rvalue, loc, true);
@@ -137,7 +156,7 @@ impl LifetimeAnalyzer {
                 ir::Stmt::SetDiscr(place, _) => {
                     self.place_lifetime(place, loc, true);
                 }
-                ir::Stmt::Call(rets, func, args) => {
+                ir::Stmt::Call(rets, func, args, _) => {
                     for ret in rets {
                         self.place_lifetime(ret, loc, true);
                     }
@@ -152,7 +171,7 @@ impl LifetimeAnalyzer {
         }
 
         match &data.term {
-            ir::Term::Switch(op, _, _) => {
+            ir::Term::Switch(op, _, _) | ir::Term::BrIf(op, _, _) => {
                 self.op_lifetime(
                     op,
                     ir::Location {
@@ -207,7 +226,7 @@ impl LifetimeAnalyzer {
         // let start = self.annotations.len();
 
         match &data.term {
-            ir::Term::Switch(op, _, _) => {
+            ir::Term::Switch(op, _, _) | ir::Term::BrIf(op, _, _) => {
                 if let Some(end) = end {
                     self.op_lifetime(
                         op,
@@ -233,6 +252,7 @@ impl LifetimeAnalyzer {
             match stmt {
                 ir::Stmt::Init(_) => {}
                 ir::Stmt::Drop(_) => {}
+                ir::Stmt::Loc(_) => {}
                 ir::Stmt::Assign(place, rvalue) => {
                     self.place_lifetime(place, loc, false);
                     self.rvalue_lifetime(rvalue, loc, false);
@@ -240,7 +260,7 @@ impl LifetimeAnalyzer {
                 ir::Stmt::SetDiscr(place, _) => {
                     self.place_lifetime(place, loc, false);
                 }
-                ir::Stmt::Call(rets, func, args) => {
+                ir::Stmt::Call(rets, func, args, _) => {
                     for ret in rets {
                         self.place_lifetime(ret, loc, false);
                     }
@@ -285,6 +305,7 @@ impl LifetimeAnalyzer {
             match stmt {
                 ir::Stmt::Init(_) => {}
                 ir::Stmt::Drop(_) => {}
+                ir::Stmt::Loc(_) => {}
                 ir::Stmt::Assign(place, rvalue) => {
                     Self::find_vars_place(place, vars);
                     Self::find_vars_rvalue(rvalue, vars);
@@ -292,7 +313,7 @@ impl LifetimeAnalyzer {
                 ir::Stmt::SetDiscr(place, _) => {
                     Self::find_vars_place(place, vars);
                 }
-                ir::Stmt::Call(rets, func, args) => {
+                ir::Stmt::Call(rets, func, args, _) => {
                     for ret in rets {
                         Self::find_vars_place(ret, vars);
                     }
@@ -306,7 +327,7 @@ impl LifetimeAnalyzer {
             }
         }
 
-        if let ir::Term::Switch(op, _, _) = &data.term {
+        if let ir::Term::Switch(op, _, _) | ir::Term::BrIf(op, _, _) = &data.term {
             Self::find_vars_op(op, vars);
         }
     }
@@ -331,6 +352,14 @@ impl LifetimeAnalyzer {
         match rvalue {
             ir::RValue::Use(op) => Self::find_vars_op(op, vars),
             ir::RValue::AddrOf(place) => Self::find_vars_place(place, vars),
+            ir::RValue::Lea { base, index, .. } => {
+                Self::find_vars_op(base, vars);
+
+                if let Some(index) = index {
+                    Self::find_vars_op(index, vars);
+                }
+            }
+            ir::RValue::UnOp(_, op) => Self::find_vars_op(op, vars),
             ir::RValue::GetDiscr(place) => Self::find_vars_place(place, vars),
             ir::RValue::Cast(place, _) => Self::find_vars_place(place, vars),
             ir::RValue::Intrinsic(_, args) => {
@@ -338,6 +367,7 @@ impl LifetimeAnalyzer {
                     Self::find_vars_op(arg, vars);
                 }
             }
+            ir::RValue::NullOp(..) => {}
         }
     }
 
@@ -349,6 +379,14 @@ impl LifetimeAnalyzer {
         match rvalue {
             ir::RValue::Use(op) => self.op_lifetime(op, loc, state),
             ir::RValue::AddrOf(place) => self.place_lifetime(place, loc, state),
+            ir::RValue::Lea { base, index, .. } => {
+                self.op_lifetime(base, loc, state);
+
+                if let Some(index) = index {
+                    self.op_lifetime(index, loc, state);
+                }
+            }
+            ir::RValue::UnOp(_, op) => self.op_lifetime(op, loc, state),
             ir::RValue::GetDiscr(place) => self.place_lifetime(place, loc, state),
             ir::RValue::Cast(place, _) => self.place_lifetime(place, loc, state),
             ir::RValue::Intrinsic(_, args) => {
@@ -356,6 +394,7 @@ impl LifetimeAnalyzer {
                     self.op_lifetime(arg, loc, state);
                 }
             }
+            ir::RValue::NullOp(..) => {}
         }
     }
 
@@ -0,0 +1,185 @@
+use std::collections::HashSet;
+
+/// The locals of a body that may still be read before they are overwritten, at the start and the end of every block.
+/// A local is live from its `Init` until its last use or `Drop`, so storage markers never end up in a range
+/// where another local uses the same storage.
+pub struct Liveness {
+    live_in: Vec<HashSet<ir::Local>>,
+    live_out: Vec<HashSet<ir::Local>>,
+}
+
+/// The locals a statement reads and writes.
+#[derive(Debug, Default)]
+pub struct Effects {
+    pub uses: Vec<ir::Local>,
+    pub defs: Vec<ir::Local>,
+    /// The defined locals that are overwritten completely, so their old value is dead before the statement.
+    pub kills: Vec<ir::Local>,
+}
+
+impl Liveness {
+    pub fn new(body: &ir::Body) -> Self {
+        let mut liveness = Liveness {
+            live_in: vec![HashSet::new(); body.blocks.len()],
+            live_out: vec![HashSet::new(); body.blocks.len()],
+        };
+
+        // the post order visits successors first, so most blocks are final after one round
+        let order = body.post_order().collect::<Vec<_>>();
+        let mut changed = true;
+
+        while changed {
+            changed = false;
+
+            for &block in &order {
+                let data = &body.blocks[block];
+                let live_out = ir::cfg::edges(data)
+                    .into_iter()
+                    .flat_map(|succ| liveness.live_in[succ.index()].iter().copied())
+                    .collect::<HashSet<_>>();
+
+                let mut live = live_out.clone();
+
+                liveness.step_term(body, &data.term, &mut live);
+
+                for stmt in data.stmts.iter().rev() {
+                    liveness.step(stmt, &mut live);
+                }
+
+                if live != liveness.live_in[block.index()] || live_out != liveness.live_out[block.index()] {
+                    liveness.live_in[block.index()] = live;
+                    liveness.live_out[block.index()] = live_out;
+                    changed = true;
+                }
+            }
+        }
+
+        liveness
+    }
+
+    pub fn live_in(&self, block: ir::Block) -> &HashSet<ir::Local> {
+        &self.live_in[block.index()]
+    }
+
+    pub fn live_out(&self, block: ir::Block) -> &HashSet<ir::Local> {
+        &self.live_out[block.index()]
+    }
+
+    /// Turns the locals live after `stmt` into the locals live before it.
+    pub fn step(&self, stmt: &ir::Stmt, live: &mut HashSet<ir::Local>) {
+        let effects = Effects::of(stmt);
+
+        // a call can unwind before it writes its results
+        if let ir::Stmt::Call(_, _, _, Some(cleanup)) = stmt {
+            live.extend(self.live_in[cleanup.index()].iter().copied());
+        }
+
+        for local in &effects.kills {
+            live.remove(local);
+        }
+
+        live.extend(effects.uses);
+    }
+
+    /// Turns the locals live after `term` into the locals live before it.
+    pub fn step_term(&self, body: &ir::Body, term: &ir::Term, live: &mut HashSet<ir::Local>) {
+        match term {
+            ir::Term::Switch(op, _, _) | ir::Term::BrIf(op, _, _) => {
+                let mut effects = Effects::default();
+
+                effects.read_op(op);
+                live.extend(effects.uses);
+            }
+            ir::Term::Return => live.extend(body.rets().map(|ret| ret.id)),
+            _ => {}
+        }
+    }
+}
+
+impl Effects {
+    pub fn of(stmt: &ir::Stmt) -> Self {
+        let mut effects = Effects::default();
+
+        match stmt {
+            ir::Stmt::Init(local) => {
+                effects.defs.push(*local);
+                effects.kills.push(*local);
+            }
+            ir::Stmt::Drop(local) => effects.uses.push(*local),
+            ir::Stmt::Assign(place, rvalue) => {
+                effects.write(place);
+                effects.read_rvalue(rvalue);
+            }
+            ir::Stmt::SetDiscr(place, _) => effects.write(place),
+            ir::Stmt::Loc(_) => {}
+            ir::Stmt::Call(rets, func, args, _) => {
+                for place in rets {
+                    effects.write(place);
+                }
+
+                effects.read_op(func);
+
+                for arg in args {
+                    effects.read_op(arg);
+                }
+            }
+        }
+
+        effects
+    }
+
+    fn write(&mut self, place: &ir::Place) {
+        self.read_indices(place);
+
+        if place.elems.is_empty() {
+            self.defs.push(place.local);
+            self.kills.push(place.local);
+        } else if place.elems.iter().any(|e| matches!(e, ir::PlaceElem::Deref)) {
+            // only the pointer is read, the local itself is not written
+            self.uses.push(place.local);
+        } else {
+            // the rest of the local keeps its value
+            self.defs.push(place.local);
+            self.uses.push(place.local);
+        }
+    }
+
+    fn read(&mut self, place: &ir::Place) {
+        self.read_indices(place);
+        self.uses.push(place.local);
+    }
+
+    fn read_indices(&mut self, place: &ir::Place) {
+        for elem in &place.elems {
+            if let ir::PlaceElem::Index(op) = elem {
+                self.read_op(op);
+            }
+        }
+    }
+
+    fn read_op(&mut self, op: &ir::Operand) {
+        if let ir::Operand::Place(place) = op {
+            self.read(place);
+        }
+    }
+
+    fn read_rvalue(&mut self, rvalue: &ir::RValue) {
+        match rvalue {
+            ir::RValue::Use(op) | ir::RValue::UnOp(_, op) => self.read_op(op),
+            ir::RValue::AddrOf(place) | ir::RValue::GetDiscr(place) | ir::RValue::Cast(place, _) => self.read(place),
+            ir::RValue::Lea { base, index, .. } => {
+                self.read_op(base);
+
+                if let Some(index) = index {
+                    self.read_op(index);
+                }
+            }
+            ir::RValue::Intrinsic(_, args) => {
+                for arg in args {
+                    self.read_op(arg);
+                }
+            }
+            ir::RValue::NullOp(..) => {}
+        }
+    }
+}
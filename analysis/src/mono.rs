@@ -0,0 +1,179 @@
+use crate::Analyzer;
+use ir::visitor::VisitorMut;
+use std::collections::HashMap;
+use transform::Transform;
+
+/// Specializes the generic functions marked `@mono` for the types they are called with. Calls are only
+/// rewritten when all types of the callee are known, other calls keep using the generic function and pass
+/// type info like any other generic call. Specializations are specialized in turn, so calls from one `@mono`
+/// function to another are resolved as well.
+pub struct MonoAnalyzer;
+
+pub struct MonoTransform {
+    /// The specialization of a function for the types substituted for its type parameters, sorted by name.
+    instances: HashMap<(ir::DeclId, Vec<(String, ir::Ty)>), ir::DeclId>,
+}
+
+impl Analyzer for MonoAnalyzer {
+    type Output = MonoTransform;
+
+    fn analyze(&mut self, _: &ir::Module) -> Self::Output {
+        MonoTransform { instances: HashMap::new() }
+    }
+}
+
+impl Transform for MonoTransform {
+    fn apply(&mut self, module: &mut ir::Module) {
+        let mut i = 0;
+
+        // the loop also visits the specializations, which are added to the end
+        while i < module.bodies.len() {
+            let id = ir::BodyId::from_usize(i);
+            let mut calls = Vec::new();
+
+            for block in &module.bodies[id].blocks {
+                for (j, stmt) in block.stmts.iter().enumerate() {
+                    if let ir::Stmt::Call(rets, ir::Operand::Const(ir::Const::Addr(func)), args, _) = stmt {
+                        let decl = &module.decls[*func];
+
+                        if decl.attrs.mono {
+                            let sig = decl.ty.clone().signature();
+                            let subst = sig.find_type_instances(args, rets, module, &module.bodies[id]);
+
+                            calls.push((block.id, j, *func, subst));
+                        }
+                    }
+                }
+            }
+
+            for (block, j, func, subst) in calls {
+                if let Some(instance) = self.instance(module, func, subst) {
+                    if let ir::Stmt::Call(_, callee, _, _) = &mut module.bodies[id].blocks[block].stmts[j] {
+                        *callee = ir::Operand::Const(ir::Const::Addr(instance));
+                    }
+                }
+            }
+
+            i += 1;
+        }
+    }
+}
+
+impl MonoTransform {
+    /// Returns the specialization of `func` for `subst`, or `None` if the types are not all known
+    /// or `func` is not defined in the module.
+    fn instance(&mut self, module: &mut ir::Module, func: ir::DeclId, subst: HashMap<String, ir::Ty>) -> Option<ir::DeclId> {
+        let decl = &module.decls[func];
+        let ty = substitute(&decl.ty, &subst);
+
+        if is_generic(&ty) {
+            return None;
+        }
+
+        let mut key = subst.into_iter().collect::<Vec<_>>();
+
+        key.sort_by(|a, b| a.0.cmp(&b.0));
+
+        if let Some(instance) = self.instances.get(&(func, key.clone())) {
+            return Some(*instance);
+        }
+
+        let body = module.bodies.iter().find(|b| b.decl == func)?.clone();
+        let subst = key.iter().cloned().collect::<HashMap<_, _>>();
+        let name = format!("{}__{}", decl.name, key.iter().map(|(_, ty)| mangle(ty)).collect::<Vec<_>>().join("_"));
+        let attrs = ir::Attrs {
+            mono: false,
+            ..decl.attrs.clone()
+        };
+
+        let instance = module.decls.next_idx();
+
+        module.decls.insert(instance, ir::Decl {
+            id: instance,
+            linkage: ir::Linkage::Local,
+            name,
+            ty,
+            attrs,
+        });
+
+        let mut body = ir::Body {
+            id: module.bodies.next_idx(),
+            decl: instance,
+            ..body
+        };
+
+        Substitute(&subst).visit_body(&mut body);
+        module.bodies.push(body);
+        self.instances.insert((func, key), instance);
+
+        Some(instance)
+    }
+}
+
+struct Substitute<'a>(&'a HashMap<String, ir::Ty>);
+
+impl VisitorMut for Substitute<'_> {
+    fn visit_type(&mut self, ty: &mut ir::Ty) {
+        *ty = substitute(ty, self.0);
+    }
+
+    fn visit_const(&mut self, c: &mut ir::Const) {
+        match c {
+            ir::Const::Undefined(ty) | ir::Const::Scalar(_, ty) => self.visit_type(ty),
+            _ => self.super_const(c),
+        }
+    }
+}
+
+fn substitute(ty: &ir::Ty, subst: &HashMap<String, ir::Ty>) -> ir::Ty {
+    let kind = match &ty.kind {
+        ir::Type::Opaque(name) => return subst.get(name).unwrap_or(ty).clone(),
+        ir::Type::Ptr(to) => ir::Type::Ptr(Box::new(substitute(to, subst))),
+        ir::Type::Box(to) => ir::Type::Box(Box::new(substitute(to, subst))),
+        ir::Type::Discr(of) => ir::Type::Discr(Box::new(substitute(of, subst))),
+        ir::Type::Newtype(name, inner) => ir::Type::Newtype(name.clone(), Box::new(substitute(inner, subst))),
+        ir::Type::Tuple(tys) => ir::Type::Tuple(tys.iter().map(|t| substitute(t, subst)).collect()),
+        ir::Type::Union(tys) => ir::Type::Union(tys.iter().map(|t| substitute(t, subst)).collect()),
+        ir::Type::Tagged(tys) => ir::Type::Tagged(tys.iter().map(|t| substitute(t, subst)).collect()),
+        ir::Type::Func(sig) => ir::Type::Func(ir::Signature {
+            params: sig.params.iter().map(|t| substitute(t, subst)).collect(),
+            rets: sig.rets.iter().map(|t| substitute(t, subst)).collect(),
+            ..sig.clone()
+        }),
+        _ => return ty.clone(),
+    };
+
+    ir::Ty { kind, info: ty.info.clone() }
+}
+
+pub(crate) fn is_generic(ty: &ir::Ty) -> bool {
+    match &ty.kind {
+        ir::Type::Opaque(_) => true,
+        ir::Type::Ptr(ty) | ir::Type::Box(ty) | ir::Type::Discr(ty) | ir::Type::Newtype(_, ty) => is_generic(ty),
+        ir::Type::Tuple(tys) | ir::Type::Union(tys) | ir::Type::Tagged(tys) => tys.iter().any(is_generic),
+        ir::Type::Func(sig) => sig.params.iter().chain(&sig.rets).any(is_generic),
+        _ => false,
+    }
+}
+
+/// Encodes a type in the characters allowed in the name of a declaration.
+fn mangle(ty: &ir::Ty) -> String {
+    match &ty.kind {
+        ir::Type::Ptr(to) => format!("P{}", mangle(to)),
+        ir::Type::Box(to) => format!("B{}", mangle(to)),
+        ir::Type::Discr(of) => format!("D{}", mangle(of)),
+        ir::Type::Vector(elem, lanes) => format!("V{}_{}", lanes, mangle(elem)),
+        ir::Type::Newtype(name, inner) => format!("W{}{}{}", name.len(), name, mangle(inner)),
+        ir::Type::Tuple(tys) => format!("T{}_{}", tys.len(), tys.iter().map(mangle).collect::<String>()),
+        ir::Type::Union(tys) => format!("U{}_{}", tys.len(), tys.iter().map(mangle).collect::<String>()),
+        ir::Type::Tagged(tys) => format!("E{}_{}", tys.len(), tys.iter().map(mangle).collect::<String>()),
+        ir::Type::Func(sig) => {
+            let tys = sig.params.iter().chain(&sig.rets).map(mangle).collect::<String>();
+
+            format!("F{}_{}_{}", sig.params.len(), sig.rets.len(), tys)
+        }
+        ir::Type::Opaque(name) | ir::Type::Type(name) | ir::Type::Vwt(name) => format!("N{}{}", name.len(), name),
+        ir::Type::Recurse(n) => format!("R{}", n),
+        _ => ir::display_ty(ty),
+    }
+}
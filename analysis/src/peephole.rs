@@ -0,0 +1,571 @@
+use crate::Analyzer;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use transform::Transform;
+
+pub struct PeepholeAnalyzer {
+    rules: Vec<Rule>,
+}
+
+pub struct PeepholeTransform {
+    rewrites: Vec<(ir::Location, ir::RValue)>,
+    stats: Vec<ir::progress::Stat>,
+}
+
+/// The number of times a body is rewritten at most. Rules are expected to simplify,
+/// so this only stops a set of rules that undo each other.
+const MAX_ROUNDS: usize = 16;
+
+/// A single rewrite rule. `op` is matched against the name of an intrinsic
+/// without its type suffix, so `"add"` matches `add_i32`, `add_u8`, etc.
+/// Unary operations match as `"neg"`, `"not"` and `"bitnot"`.
+pub struct Rule {
+    pub name: &'static str,
+    pub op: &'static str,
+    pub args: &'static [Pat],
+    pub rewrite: fn(&Match) -> Option<ir::RValue>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pat {
+    Any,
+    Scalar,
+    Const(u128),
+    Pow2,
+}
+
+pub struct Match<'a> {
+    pub suffix: &'a str,
+    pub args: &'a [ir::Operand],
+    pub dest: ir::Ty,
+    defs: &'a HashMap<ir::Local, &'a ir::RValue>,
+    stable: &'a HashSet<ir::Local>,
+}
+
+impl PeepholeAnalyzer {
+    pub fn new() -> Self {
+        PeepholeAnalyzer { rules: default_rules() }
+    }
+
+    pub fn with_rules(rules: Vec<Rule>) -> Self {
+        PeepholeAnalyzer { rules }
+    }
+
+    pub fn add_rule(&mut self, rule: Rule) {
+        self.rules.push(rule);
+    }
+
+    /// Rewrites the body until no rule applies anymore, so that a rewrite can enable another one.
+    /// Only the final rvalue of every rewritten statement is recorded.
+    fn analyze_body(&self, body: &ir::Body, rewrites: &mut Vec<(ir::Location, ir::RValue)>, stats: &mut Vec<ir::progress::Stat>) {
+        let mut round = self.rewrite_once(body);
+
+        if round.is_empty() {
+            return;
+        }
+
+        let mut body = body.clone();
+        let mut changed = HashMap::new();
+        let mut count = 0;
+
+        for _ in 0..MAX_ROUNDS {
+            if round.is_empty() {
+                break;
+            }
+
+            count += round.len();
+
+            for (loc, rvalue) in round {
+                if let ir::Stmt::Assign(_, rhs) = &mut body.blocks[loc.block].stmts[loc.stmt] {
+                    *rhs = rvalue.clone();
+                }
+
+                changed.insert((loc.block, loc.stmt), (loc, rvalue));
+            }
+
+            round = self.rewrite_once(&body);
+        }
+
+        let mut changed = changed.into_iter().map(|(_, rewrite)| rewrite).collect::<Vec<_>>();
+
+        changed.sort_by_key(|(loc, _)| (loc.block, loc.stmt));
+        rewrites.extend(changed);
+        stats.push(ir::progress::Stat {
+            decl: body.decl,
+            name: "rewrites",
+            value: count as u64,
+        });
+    }
+
+    fn rewrite_once(&self, body: &ir::Body) -> Vec<(ir::Location, ir::RValue)> {
+        let (defs, stable) = single_defs(body);
+        let mut rewrites = Vec::new();
+
+        for (block, data) in body.blocks.iter_enumerated() {
+            for (i, stmt) in data.stmts.iter().enumerate() {
+                if let ir::Stmt::Assign(place, rvalue) = stmt {
+                    let (op, suffix, args) = match rvalue {
+                        | ir::RValue::Intrinsic(name, args) => {
+                            let (op, suffix) = split_intrinsic(name);
+
+                            (op, suffix, &args[..])
+                        },
+                        | ir::RValue::UnOp(op, val) => (unop_name(*op), "", std::slice::from_ref(val)),
+                        | _ => continue,
+                    };
+
+                    // temporaries holding a constant are matched as that constant
+                    let args = args.iter().map(|arg| resolve_const(arg, &defs)).collect::<Vec<_>>();
+                    let m = Match {
+                        suffix,
+                        args: &args,
+                        dest: ir::place_type(body, place),
+                        defs: &defs,
+                        stable: &stable,
+                    };
+
+                    let rewrite = self
+                        .rules
+                        .iter()
+                        .filter(|rule| rule.op == op && matches(rule.args, &args))
+                        .find_map(|rule| (rule.rewrite)(&m));
+
+                    if let Some(rvalue) = rewrite.filter(|new| new != rvalue) {
+                        let loc = ir::Location { body: body.id, block, stmt: i };
+
+                        rewrites.push((loc, rvalue));
+                    }
+                }
+            }
+        }
+
+        rewrites
+    }
+}
+
+impl Analyzer for PeepholeAnalyzer {
+    type Output = PeepholeTransform;
+
+    fn analyze(&mut self, module: &ir::Module) -> Self::Output {
+        let mut rewrites = Vec::new();
+        let mut stats = Vec::new();
+
+        for body in &module.bodies {
+            self.analyze_body(body, &mut rewrites, &mut stats);
+        }
+
+        PeepholeTransform { rewrites, stats }
+    }
+}
+
+impl Transform for PeepholeTransform {
+    fn apply(&mut self, module: &mut ir::Module) {
+        for (loc, rvalue) in self.rewrites.drain(..) {
+            if let ir::Stmt::Assign(_, rhs) = &mut module.bodies[loc.body].blocks[loc.block].stmts[loc.stmt] {
+                *rhs = rvalue;
+            }
+        }
+    }
+
+    fn stats(&self) -> Vec<ir::progress::Stat> {
+        self.stats.clone()
+    }
+}
+
+impl<'a> Match<'a> {
+    pub fn arg(&self, idx: usize) -> ir::RValue {
+        ir::RValue::Use(self.args[idx].clone())
+    }
+
+    pub fn scalar(&self, idx: usize) -> Option<u128> {
+        match &self.args[idx] {
+            | ir::Operand::Const(ir::Const::Scalar(val, _)) => Some(*val),
+            | _ => None,
+        }
+    }
+
+    /// The single definition of a temporary, if the operand refers to one.
+    /// Copies of other temporaries are looked through, so a rewrite into a copy does not hide the original definition.
+    pub fn def(&self, op: &ir::Operand) -> Option<&'a ir::RValue> {
+        let mut def = match op {
+            | ir::Operand::Place(place) if place.elems.is_empty() => self.defs.get(&place.local).copied()?,
+            | _ => return None,
+        };
+
+        // bounded, in case the copies form a cycle
+        for _ in 0..self.defs.len() {
+            match def {
+                | ir::RValue::Use(ir::Operand::Place(place)) if place.elems.is_empty() => match self.defs.get(&place.local) {
+                    | Some(inner) => def = inner,
+                    | None => break,
+                },
+                | _ => break,
+            }
+        }
+
+        Some(def)
+    }
+
+    /// Whether the value of the operand cannot change between its definition and this use.
+    pub fn is_stable(&self, op: &ir::Operand) -> bool {
+        match op {
+            | ir::Operand::Const(_) => true,
+            | ir::Operand::Place(place) => place.elems.is_empty() && self.stable.contains(&place.local),
+        }
+    }
+
+    pub fn int_info(&self) -> Option<(bool, u32)> {
+        int_info(self.suffix)
+    }
+}
+
+fn matches(pats: &[Pat], args: &[ir::Operand]) -> bool {
+    pats.len() == args.len()
+        && pats.iter().zip(args).all(|(pat, arg)| match (pat, arg) {
+            | (Pat::Any, _) => true,
+            | (Pat::Scalar, ir::Operand::Const(ir::Const::Scalar(_, _))) => true,
+            | (Pat::Const(c), ir::Operand::Const(ir::Const::Scalar(val, _))) => c == val,
+            | (Pat::Pow2, ir::Operand::Const(ir::Const::Scalar(val, _))) => val.is_power_of_two(),
+            | _ => false,
+        })
+}
+
+fn resolve_const(op: &ir::Operand, defs: &HashMap<ir::Local, &ir::RValue>) -> ir::Operand {
+    match op {
+        | ir::Operand::Place(place) if place.elems.is_empty() => match defs.get(&place.local) {
+            | Some(ir::RValue::Use(c @ ir::Operand::Const(ir::Const::Scalar(..)))) => c.clone(),
+            | _ => op.clone(),
+        },
+        | _ => op.clone(),
+    }
+}
+
+fn single_defs(body: &ir::Body) -> (HashMap<ir::Local, &ir::RValue>, HashSet<ir::Local>) {
+    let mut defs = HashMap::new();
+    let mut counts = HashMap::new();
+
+    for block in &body.blocks {
+        for stmt in &block.stmts {
+            match stmt {
+                | ir::Stmt::Assign(place, rvalue) => {
+                    *counts.entry(place.local).or_insert(0) += 1;
+
+                    if place.elems.is_empty() {
+                        defs.insert(place.local, rvalue);
+                    }
+
+                    if let ir::RValue::AddrOf(place) = rvalue {
+                        *counts.entry(place.local).or_insert(0) += 2;
+                    }
+                },
+                | ir::Stmt::SetDiscr(place, _) => *counts.entry(place.local).or_insert(0) += 2,
+                | ir::Stmt::Call(rets, ..) => {
+                    for ret in rets {
+                        *counts.entry(ret.local).or_insert(0) += 2;
+                    }
+                },
+                | _ => {},
+            }
+        }
+    }
+
+    defs.retain(|local, _| counts[local] == 1 && body.locals[*local].kind == ir::LocalKind::Tmp);
+
+    let stable = body
+        .locals
+        .iter()
+        .filter(|l| !counts.contains_key(&l.id) || defs.contains_key(&l.id))
+        .map(|l| l.id)
+        .collect();
+
+    (defs, stable)
+}
+
+fn unop_name(op: ir::UnOp) -> &'static str {
+    match op {
+        | ir::UnOp::Neg => "neg",
+        | ir::UnOp::Not => "not",
+        | ir::UnOp::BitNot => "bitnot",
+    }
+}
+
+fn double_unop(m: &Match, op: ir::UnOp) -> Option<ir::RValue> {
+    match m.def(&m.args[0]) {
+        | Some(ir::RValue::UnOp(inner, val)) if *inner == op && m.is_stable(val) => Some(ir::RValue::Use(val.clone())),
+        | _ => None,
+    }
+}
+
+pub(crate) fn split_intrinsic(name: &str) -> (&str, &str) {
+    match name.rfind('_') {
+        | Some(idx) => (&name[..idx], &name[idx + 1..]),
+        | None => (name, ""),
+    }
+}
+
+pub(crate) fn int_info(suffix: &str) -> Option<(bool, u32)> {
+    let signed = match suffix.chars().next()? {
+        | 'i' => true,
+        | 'u' => false,
+        | _ => return None,
+    };
+
+    suffix[1..].parse().ok().filter(|bits| (1..=128).contains(bits)).map(|bits| (signed, bits))
+}
+
+fn compare(m: &Match, f: fn(Ordering) -> bool) -> Option<ir::RValue> {
+    let (signed, bits) = m.int_info()?;
+    let (a, b) = (m.scalar(0)?, m.scalar(1)?);
+    let ord = if signed {
+        let shift = 128 - bits;
+
+        (((a << shift) as i128) >> shift).cmp(&(((b << shift) as i128) >> shift))
+    } else {
+        a.cmp(&b)
+    };
+
+    Some(ir::RValue::Use(ir::Operand::Const(ir::Const::Scalar(f(ord) as u128, m.dest.clone()))))
+}
+
+/// Returns argument `idx`, for rules that only hold for integers. Adding zero to a float does not
+/// return it as is when it is -0.0.
+fn int_arg(m: &Match, idx: usize) -> Option<ir::RValue> {
+    m.int_info()?;
+
+    Some(m.arg(idx))
+}
+
+fn mul_pow2(m: &Match, x: usize, c: usize) -> Option<ir::RValue> {
+    // the constant of a float is its bits, which are not the power of two it looks like
+    m.int_info()?;
+
+    if let ir::Operand::Const(ir::Const::Scalar(val, ty)) = &m.args[c] {
+        let shift = ir::Const::Scalar(val.trailing_zeros() as u128, ty.clone());
+
+        Some(ir::RValue::Intrinsic(format!("shl_{}", m.suffix), vec![
+            m.args[x].clone(),
+            ir::Operand::Const(shift),
+        ]))
+    } else {
+        None
+    }
+}
+
+pub fn default_rules() -> Vec<Rule> {
+    vec![
+        Rule {
+            name: "add-zero",
+            op: "add",
+            args: &[Pat::Any, Pat::Const(0)],
+            rewrite: |m| int_arg(m, 0),
+        },
+        Rule {
+            name: "zero-add",
+            op: "add",
+            args: &[Pat::Const(0), Pat::Any],
+            rewrite: |m| int_arg(m, 1),
+        },
+        Rule {
+            name: "sub-zero",
+            op: "sub",
+            args: &[Pat::Any, Pat::Const(0)],
+            rewrite: |m| int_arg(m, 0),
+        },
+        Rule {
+            name: "mul-one",
+            op: "mul",
+            args: &[Pat::Any, Pat::Const(1)],
+            rewrite: |m| int_arg(m, 0),
+        },
+        Rule {
+            name: "one-mul",
+            op: "mul",
+            args: &[Pat::Const(1), Pat::Any],
+            rewrite: |m| int_arg(m, 1),
+        },
+        Rule {
+            name: "mul-pow2",
+            op: "mul",
+            args: &[Pat::Any, Pat::Pow2],
+            rewrite: |m| mul_pow2(m, 0, 1),
+        },
+        Rule {
+            name: "pow2-mul",
+            op: "mul",
+            args: &[Pat::Pow2, Pat::Any],
+            rewrite: |m| mul_pow2(m, 1, 0),
+        },
+        Rule {
+            name: "neg-neg",
+            op: "neg",
+            args: &[Pat::Any],
+            rewrite: |m| double_unop(m, ir::UnOp::Neg),
+        },
+        Rule {
+            name: "bitnot-bitnot",
+            op: "bitnot",
+            args: &[Pat::Any],
+            rewrite: |m| double_unop(m, ir::UnOp::BitNot),
+        },
+        Rule {
+            name: "fold-lt",
+            op: "lt",
+            args: &[Pat::Scalar, Pat::Scalar],
+            rewrite: |m| compare(m, |o| o == Ordering::Less),
+        },
+        Rule {
+            name: "fold-le",
+            op: "le",
+            args: &[Pat::Scalar, Pat::Scalar],
+            rewrite: |m| compare(m, |o| o != Ordering::Greater),
+        },
+        Rule {
+            name: "fold-gt",
+            op: "gt",
+            args: &[Pat::Scalar, Pat::Scalar],
+            rewrite: |m| compare(m, |o| o == Ordering::Greater),
+        },
+        Rule {
+            name: "fold-ge",
+            op: "ge",
+            args: &[Pat::Scalar, Pat::Scalar],
+            rewrite: |m| compare(m, |o| o != Ordering::Less),
+        },
+        Rule {
+            name: "fold-eq",
+            op: "eq",
+            args: &[Pat::Scalar, Pat::Scalar],
+            rewrite: |m| compare(m, |o| o == Ordering::Equal),
+        },
+        Rule {
+            name: "fold-ne",
+            op: "ne",
+            args: &[Pat::Scalar, Pat::Scalar],
+            rewrite: |m| compare(m, |o| o != Ordering::Equal),
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a function `@f` taking `_1 :: <ty>`, with temporaries `_2` and `_3` of the same type, made of `stmts`.
+    fn module(ty: &str, stmts: &str) -> ir::Module {
+        let src = format!(
+            "local @f :: ({0}) -> ({0})\nfn @f {{\n    ret _0 :: {0}\n    arg _1 :: {0}\n    tmp _2 :: {0}\n    tmp _3 :: {0}\n%0:\n{1}\n    return\n}}\n",
+            ty, stmts
+        );
+
+        ir::parser::parse(&src).unwrap()
+    }
+
+    /// Checks that the default rules rewrite `before` into `after`.
+    fn check(ty: &str, before: &str, after: &str) {
+        let mut module = module(ty, before);
+
+        crate::analyze(PeepholeAnalyzer::new(), &mut module);
+        assert_eq!(module, self::module(ty, after), "{}", before);
+    }
+
+    #[test]
+    fn add_zero() {
+        check("i32", "_0 = #add_i32(_1, 0 :: i32)", "_0 = _1");
+        check("i32", "_0 = #add_i32(0 :: i32, _1)", "_0 = _1");
+        check("u8", "_0 = #sub_u8(_1, 0 :: u8)", "_0 = _1");
+    }
+
+    #[test]
+    fn mul_one() {
+        check("i64", "_0 = #mul_i64(_1, 1 :: i64)", "_0 = _1");
+        check("i64", "_0 = #mul_i64(1 :: i64, _1)", "_0 = _1");
+    }
+
+    #[test]
+    fn mul_pow2() {
+        check("u32", "_0 = #mul_u32(_1, 8 :: u32)", "_0 = #shl_u32(_1, 3 :: u32)");
+        check("u32", "_0 = #mul_u32(16 :: u32, _1)", "_0 = #shl_u32(_1, 4 :: u32)");
+    }
+
+    #[test]
+    fn double_negation() {
+        check("i32", "_2 = neg _1\n_0 = neg _2", "_2 = neg _1\n_0 = _1");
+        check("u8", "_2 = bitnot _1\n_0 = bitnot _2", "_2 = bitnot _1\n_0 = _1");
+    }
+
+    #[test]
+    fn fold_compare() {
+        check("i8", "_0 = #lt_i8(255 :: i8, 1 :: i8)", "_0 = 1 :: i8");
+        check("u8", "_0 = #lt_u8(255 :: u8, 1 :: u8)", "_0 = 0 :: u8");
+        check("u8", "_0 = #ge_u8(3 :: u8, 3 :: u8)", "_0 = 1 :: u8");
+        check("u8", "_0 = #le_u8(3 :: u8, 2 :: u8)", "_0 = 0 :: u8");
+        check("i16", "_0 = #le_i16(65535 :: i16, 0 :: i16)", "_0 = 1 :: i16");
+        check("u32", "_0 = #gt_u32(7 :: u32, 2 :: u32)", "_0 = 1 :: u32");
+        check("i32", "_0 = #gt_i32(4294967295 :: i32, 2 :: i32)", "_0 = 0 :: i32");
+        check("u64", "_0 = #eq_u64(5 :: u64, 5 :: u64)", "_0 = 1 :: u64");
+        check("u64", "_0 = #ne_u64(5 :: u64, 5 :: u64)", "_0 = 0 :: u64");
+        check("u64", "_0 = #ne_u64(5 :: u64, _1)", "_0 = #ne_u64(5 :: u64, _1)");
+    }
+
+    #[test]
+    fn constant_temporaries() {
+        // a temporary assigned a constant once is matched as that constant
+        check("i32", "_2 = 0 :: i32\n_0 = #add_i32(_1, _2)", "_2 = 0 :: i32\n_0 = _1");
+        check("u8", "_2 = 2 :: u8\n_3 = 3 :: u8\n_0 = #lt_u8(_2, _3)", "_2 = 2 :: u8\n_3 = 3 :: u8\n_0 = 1 :: u8");
+        // not when it is assigned more than once
+        check("i32", "_2 = 0 :: i32\n_2 = _1\n_0 = #add_i32(_1, _2)", "_2 = 0 :: i32\n_2 = _1\n_0 = #add_i32(_1, _2)");
+    }
+
+    #[test]
+    fn copies_are_looked_through() {
+        check("i32", "_2 = neg _1\n_3 = _2\n_0 = neg _3", "_2 = neg _1\n_3 = _2\n_0 = _1");
+    }
+
+    #[test]
+    fn unstable_operands_are_kept() {
+        // `_1` changes between the negations, so the second one does not give back the value negated by the first
+        let stmts = "_2 = neg _1\n_1 = 5 :: i32\n_0 = neg _2";
+
+        check("i32", stmts, stmts);
+    }
+
+    #[test]
+    fn rewrites_enable_others() {
+        // the first round turns `_3` into a copy of `_2`, after which the negations cancel out
+        check("i32", "_2 = neg _1\n_3 = #mul_i32(_2, 1 :: i32)\n_0 = neg _3", "_2 = neg _1\n_3 = _2\n_0 = _1");
+        // and here into a constant, which the next round matches as zero
+        check("i32", "_2 = #mul_i32(1 :: i32, 0 :: i32)\n_0 = #add_i32(_1, _2)", "_2 = 0 :: i32\n_0 = _1");
+    }
+
+    #[test]
+    fn rounds_are_bounded() {
+        // a rule that always applies, which would never reach a fixpoint
+        let swap = Rule {
+            name: "swap",
+            op: "add",
+            args: &[Pat::Any, Pat::Any],
+            rewrite: |m| Some(ir::RValue::Intrinsic(format!("add_{}", m.suffix), vec![m.args[1].clone(), m.args[0].clone()])),
+        };
+
+        let module = module("i32", "_0 = #add_i32(_1, 2 :: i32)");
+        let transform = PeepholeAnalyzer::with_rules(vec![swap]).analyze(&module);
+        let stats = transform.stats();
+
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].value, MAX_ROUNDS as u64);
+    }
+
+    #[test]
+    fn floats_are_kept() {
+        // 2.0, 1.0 and 0.0 as the bits of an f32
+        for stmt in &[
+            "_0 = #mul_f32(_1, 1073741824 :: f32)",
+            "_0 = #mul_f32(_1, 1065353216 :: f32)",
+            "_0 = #add_f32(_1, 0 :: f32)",
+        ] {
+            check("f32", stmt, stmt);
+        }
+
+        check("f64", "_0 = #mul_f64(1 :: f64, _1)", "_0 = #mul_f64(1 :: f64, _1)");
+    }
+}
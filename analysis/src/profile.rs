@@ -0,0 +1,185 @@
+use crate::Analyzer;
+use ir::profile::{FuncProfile, ProfileData};
+use std::collections::HashMap;
+use transform::Transform;
+
+/// Marks the functions that ran often in `profile` as `@hot` and lays out their blocks along the hot path.
+/// Hot generic functions are also marked `@mono`, so the mandatory passes specialize them instead of passing
+/// them type info. In every function that ran, the blocks that never did are marked `@cold`. Functions whose
+/// number of blocks differs from the profile have changed since it was gathered and are left alone.
+pub struct ProfileAnalyzer<'a> {
+    profile: &'a ProfileData,
+    /// The share of all blocks entered in the profile a function has to account for to be hot.
+    pub hot_share: f64,
+}
+
+pub struct ProfileTransform {
+    hot: Vec<ir::DeclId>,
+    /// The blocks that never ran in bodies that did.
+    cold: Vec<(ir::BodyId, Vec<ir::Block>)>,
+    /// The new order of the blocks of every hot body, by their current index.
+    orders: Vec<(ir::BodyId, Vec<ir::Block>)>,
+    stats: Vec<ir::progress::Stat>,
+}
+
+impl<'a> ProfileAnalyzer<'a> {
+    pub fn new(profile: &'a ProfileData) -> Self {
+        ProfileAnalyzer { profile, hot_share: 0.05 }
+    }
+}
+
+impl Analyzer for ProfileAnalyzer<'_> {
+    type Output = ProfileTransform;
+
+    fn analyze(&mut self, module: &ir::Module) -> Self::Output {
+        let total = self.profile.total();
+        let mut hot = Vec::new();
+        let mut cold = Vec::new();
+        let mut orders = Vec::new();
+        let mut stats = Vec::new();
+
+        for body in &module.bodies {
+            let decl = &module.decls[body.decl];
+            let profile = match self.profile.funcs.get(&decl.name) {
+                Some(profile) if profile.blocks.len() == body.blocks.len() => profile,
+                _ => continue,
+            };
+
+            let never = body.blocks.iter().map(|b| b.id).filter(|b| profile.blocks[b.index()] == 0).collect::<Vec<_>>();
+
+            if profile.calls > 0 && !never.is_empty() {
+                stats.push(ir::progress::Stat {
+                    decl: decl.id,
+                    name: "cold_blocks",
+                    value: never.len() as u64,
+                });
+
+                cold.push((body.id, never));
+            }
+
+            let share = profile.total() as f64 / total as f64;
+
+            if !matches!(decl.ty.kind, ir::Type::Func(_)) || profile.total() == 0 || share < self.hot_share {
+                continue;
+            }
+
+            let order = layout(body, profile);
+            let moved = order.iter().enumerate().filter(|(i, block)| *i != block.index()).count();
+
+            hot.push(decl.id);
+            stats.push(ir::progress::Stat {
+                decl: decl.id,
+                name: "calls",
+                value: profile.calls,
+            });
+
+            if moved > 0 {
+                stats.push(ir::progress::Stat {
+                    decl: decl.id,
+                    name: "moved_blocks",
+                    value: moved as u64,
+                });
+
+                orders.push((body.id, order));
+            }
+        }
+
+        ProfileTransform { hot, cold, orders, stats }
+    }
+}
+
+impl Transform for ProfileTransform {
+    fn apply(&mut self, module: &mut ir::Module) {
+        for decl in self.hot.drain(..) {
+            let decl = &mut module.decls[decl];
+            let generic = decl.ty.signature().params.iter().any(|param| match &param.kind {
+                ir::Type::Ptr(to) => matches!(to.kind, ir::Type::Type(_)),
+                _ => false,
+            });
+
+            decl.attrs.hot = true;
+            decl.attrs.mono |= generic;
+        }
+
+        // the hints move with the blocks when they are ordered below
+        for (body, blocks) in self.cold.drain(..) {
+            for block in blocks {
+                module.bodies[body].blocks[block].hint = ir::BranchHint::Cold;
+            }
+        }
+
+        for (body, order) in self.orders.drain(..) {
+            let body = &mut module.bodies[body];
+            let renumber = order
+                .iter()
+                .enumerate()
+                .map(|(i, block)| (*block, ir::Block::new(i)))
+                .collect::<HashMap<_, _>>();
+
+            let mut blocks = std::mem::take(&mut body.blocks).into_iter().map(Some).collect::<Vec<_>>();
+
+            for block in &order {
+                let mut data = blocks[block.index()].take().unwrap();
+
+                data.id = renumber[&data.id];
+
+                for stmt in &mut data.stmts {
+                    if let ir::Stmt::Call(_, _, _, Some(cleanup)) = stmt {
+                        *cleanup = renumber[cleanup];
+                    }
+                }
+
+                match &mut data.term {
+                    ir::Term::Jump(target) => *target = renumber[target],
+                    ir::Term::Switch(_, _, targets) => targets.iter_mut().for_each(|target| *target = renumber[target]),
+                    ir::Term::BrIf(_, then, else_) => {
+                        *then = renumber[then];
+                        *else_ = renumber[else_];
+                    }
+                    ir::Term::Abort | ir::Term::Return | ir::Term::Resume => {}
+                }
+
+                body.blocks.push(data);
+            }
+        }
+    }
+
+    fn stats(&self) -> Vec<ir::progress::Stat> {
+        self.stats.clone()
+    }
+}
+
+/// Orders the blocks of `body` so that every block is followed by its most frequent successor, starting with
+/// the entry block. When every successor of a block has been placed or never ran, the most frequent block
+/// that is left comes next. Blocks that never ran keep their order at the end.
+fn layout(body: &ir::Body, profile: &FuncProfile) -> Vec<ir::Block> {
+    let count = |block: ir::Block| profile.blocks.get(block.index()).copied().unwrap_or(0);
+    let mut placed = vec![false; body.blocks.len()];
+    let mut order = Vec::with_capacity(body.blocks.len());
+    let mut next = body.blocks.first().map(|b| b.id);
+
+    while let Some(block) = next {
+        placed[block.index()] = true;
+        order.push(block);
+
+        let mut successors = body.blocks[block].successors();
+
+        successors.retain(|succ| !placed[succ.index()] && count(*succ) > 0);
+
+        // the first of equally frequent blocks wins, which keeps the original order where the profile does not decide
+        next = max_first(successors.into_iter(), count).or_else(|| {
+            let left = body.blocks.iter().map(|b| b.id).filter(|b| !placed[b.index()]);
+
+            max_first(left, count)
+        });
+    }
+
+    order
+}
+
+fn max_first(blocks: impl Iterator<Item = ir::Block>, count: impl Fn(ir::Block) -> u64) -> Option<ir::Block> {
+    blocks.fold(None, |best, block| match best {
+        Some(best) if count(best) >= count(block) => Some(best),
+        _ => Some(block),
+    })
+}
@@ -0,0 +1,666 @@
+use crate::peephole::{int_info, split_intrinsic};
+use crate::Analyzer;
+use std::collections::{HashMap, HashSet};
+use transform::Transform;
+
+const WIDEN_AFTER: usize = 4;
+
+pub struct RangeAnalyzer;
+
+pub struct RangeTransform {
+    folds: Vec<(ir::Location, ir::RValue)>,
+    terms: Vec<(ir::BodyId, ir::Block, ir::Term)>,
+}
+
+/// An inclusive interval of integer values. Unsigned values are stored as is,
+/// signed values are sign-extended, which works for all types up to 64 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range {
+    pub lo: i128,
+    pub hi: i128,
+}
+
+type State = HashMap<ir::Local, Range>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cmp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+struct Cond {
+    cmp: Cmp,
+    lhs: ir::Operand,
+    rhs: ir::Operand,
+    info: (bool, u32),
+}
+
+struct BodyRanges<'a> {
+    body: &'a ir::Body,
+    escaped: HashSet<ir::Local>,
+    ins: HashMap<ir::Block, State>,
+    visits: HashMap<ir::Block, usize>,
+}
+
+impl Analyzer for RangeAnalyzer {
+    type Output = RangeTransform;
+
+    fn analyze(&mut self, module: &ir::Module) -> Self::Output {
+        let mut transform = RangeTransform {
+            folds: Vec::new(),
+            terms: Vec::new(),
+        };
+
+        for body in &module.bodies {
+            let mut ranges = BodyRanges::new(body);
+
+            ranges.run();
+            ranges.simplify(&mut transform);
+        }
+
+        transform
+    }
+}
+
+impl Transform for RangeTransform {
+    fn apply(&mut self, module: &mut ir::Module) {
+        for (loc, rvalue) in self.folds.drain(..) {
+            if let ir::Stmt::Assign(_, rhs) = &mut module.bodies[loc.body].blocks[loc.block].stmts[loc.stmt] {
+                *rhs = rvalue;
+            }
+        }
+
+        for (body, block, term) in self.terms.drain(..) {
+            module.bodies[body].blocks[block].term = term;
+        }
+    }
+}
+
+impl Range {
+    pub fn new(lo: i128, hi: i128) -> Self {
+        Range { lo, hi }
+    }
+
+    pub fn single(val: i128) -> Self {
+        Range { lo: val, hi: val }
+    }
+
+    pub fn of_int(signed: bool, bits: u32) -> Self {
+        if signed {
+            Range::new(-(1 << (bits - 1)), (1 << (bits - 1)) - 1)
+        } else {
+            Range::new(0, (1 << bits) - 1)
+        }
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.lo > self.hi
+    }
+
+    pub fn is_single(self) -> bool {
+        self.lo == self.hi
+    }
+
+    pub fn contains(self, val: i128) -> bool {
+        self.lo <= val && val <= self.hi
+    }
+
+    pub fn contains_range(self, other: Range) -> bool {
+        self.lo <= other.lo && other.hi <= self.hi
+    }
+
+    pub fn hull(self, other: Range) -> Self {
+        Range::new(self.lo.min(other.lo), self.hi.max(other.hi))
+    }
+
+    pub fn intersect(self, other: Range) -> Self {
+        Range::new(self.lo.max(other.lo), self.hi.min(other.hi))
+    }
+
+    fn exclude(self, other: Range) -> Self {
+        if !other.is_single() {
+            self
+        } else if other.lo == self.lo {
+            Range::new(self.lo + 1, self.hi)
+        } else if other.lo == self.hi {
+            Range::new(self.lo, self.hi - 1)
+        } else {
+            self
+        }
+    }
+}
+
+impl Cmp {
+    fn from_op(op: &str) -> Option<Self> {
+        match op {
+            | "lt" => Some(Cmp::Lt),
+            | "le" => Some(Cmp::Le),
+            | "gt" => Some(Cmp::Gt),
+            | "ge" => Some(Cmp::Ge),
+            | "eq" => Some(Cmp::Eq),
+            | "ne" => Some(Cmp::Ne),
+            | _ => None,
+        }
+    }
+
+    fn negate(self) -> Self {
+        match self {
+            | Cmp::Lt => Cmp::Ge,
+            | Cmp::Le => Cmp::Gt,
+            | Cmp::Gt => Cmp::Le,
+            | Cmp::Ge => Cmp::Lt,
+            | Cmp::Eq => Cmp::Ne,
+            | Cmp::Ne => Cmp::Eq,
+        }
+    }
+
+    fn eval(self, a: Range, b: Range) -> Option<bool> {
+        match self {
+            | Cmp::Lt if a.hi < b.lo => Some(true),
+            | Cmp::Lt if a.lo >= b.hi => Some(false),
+            | Cmp::Le if a.hi <= b.lo => Some(true),
+            | Cmp::Le if a.lo > b.hi => Some(false),
+            | Cmp::Gt => Cmp::Lt.eval(b, a),
+            | Cmp::Ge => Cmp::Le.eval(b, a),
+            | Cmp::Eq if a.is_single() && a == b => Some(true),
+            | Cmp::Eq if a.intersect(b).is_empty() => Some(false),
+            | Cmp::Ne => Cmp::Eq.eval(a, b).map(|t| !t),
+            | _ => None,
+        }
+    }
+
+    /// Narrow both ranges under the assumption that `a <cmp> b` holds.
+    fn constrain(self, a: Range, b: Range) -> (Range, Range) {
+        match self {
+            | Cmp::Lt => (Range::new(a.lo, a.hi.min(b.hi - 1)), Range::new(b.lo.max(a.lo + 1), b.hi)),
+            | Cmp::Le => (Range::new(a.lo, a.hi.min(b.hi)), Range::new(b.lo.max(a.lo), b.hi)),
+            | Cmp::Gt => {
+                let (b, a) = Cmp::Lt.constrain(b, a);
+
+                (a, b)
+            },
+            | Cmp::Ge => {
+                let (b, a) = Cmp::Le.constrain(b, a);
+
+                (a, b)
+            },
+            | Cmp::Eq => (a.intersect(b), a.intersect(b)),
+            | Cmp::Ne => (a.exclude(b), b.exclude(a)),
+        }
+    }
+}
+
+impl Cond {
+    fn new(name: &str, args: &[ir::Operand]) -> Option<Self> {
+        let (op, suffix) = split_intrinsic(name);
+        let cmp = Cmp::from_op(op)?;
+        let info = int_info(suffix).filter(|(_, bits)| *bits <= 64)?;
+
+        if let [lhs, rhs] = args {
+            if is_simple(lhs) && is_simple(rhs) {
+                return Some(Cond {
+                    cmp,
+                    lhs: lhs.clone(),
+                    rhs: rhs.clone(),
+                    info,
+                });
+            }
+        }
+
+        None
+    }
+
+    fn mentions(&self, local: ir::Local) -> bool {
+        let mentions = |op: &ir::Operand| matches!(op, ir::Operand::Place(p) if p.local == local);
+
+        mentions(&self.lhs) || mentions(&self.rhs)
+    }
+}
+
+impl<'a> BodyRanges<'a> {
+    fn new(body: &'a ir::Body) -> Self {
+        let mut escaped = HashSet::new();
+
+        for block in &body.blocks {
+            for stmt in &block.stmts {
+                if let ir::Stmt::Assign(_, ir::RValue::AddrOf(place)) = stmt {
+                    escaped.insert(place.local);
+                }
+            }
+        }
+
+        BodyRanges {
+            body,
+            escaped,
+            ins: HashMap::new(),
+            visits: HashMap::new(),
+        }
+    }
+
+    fn tracked(&self, local: ir::Local) -> Option<(bool, u32)> {
+        if self.escaped.contains(&local) {
+            None
+        } else {
+            int_type(&self.body.locals[local].ty.kind)
+        }
+    }
+
+    fn range_of(&self, state: &State, op: &ir::Operand, expect: (bool, u32)) -> Option<Range> {
+        match op {
+            | ir::Operand::Place(place) if place.elems.is_empty() => {
+                let info = self.tracked(place.local).filter(|info| *info == expect)?;
+
+                Some(state.get(&place.local).copied().unwrap_or_else(|| Range::of_int(info.0, info.1)))
+            },
+            | ir::Operand::Const(ir::Const::Scalar(val, ty)) => {
+                let info = int_type(&ty.kind).filter(|info| *info == expect)?;
+
+                Some(Range::single(sext(*val, info)))
+            },
+            | _ => None,
+        }
+    }
+
+    fn binary(&self, state: &State, name: &str, args: &[ir::Operand]) -> Option<(&'static str, Range, Range)> {
+        let (op, suffix) = split_intrinsic(name);
+        let info = int_info(suffix)?;
+
+        if let [a, b] = args {
+            let op = match op {
+                | "add" => "add",
+                | "sub" => "sub",
+                | "mul" => "mul",
+                | _ if Cmp::from_op(op).is_some() => "cmp",
+                | _ => return None,
+            };
+
+            Some((op, self.range_of(state, a, info)?, self.range_of(state, b, info)?))
+        } else {
+            None
+        }
+    }
+
+    fn rvalue_range(&self, state: &State, rvalue: &ir::RValue, info: (bool, u32)) -> Range {
+        let full = Range::of_int(info.0, info.1);
+        let range = match rvalue {
+            | ir::RValue::Use(op) => self.range_of(state, op, info),
+            | ir::RValue::Intrinsic(name, args) => match self.binary(state, name, args) {
+                | Some(("add", a, b)) => Some(Range::new(a.lo + b.lo, a.hi + b.hi)),
+                | Some(("sub", a, b)) => Some(Range::new(a.lo - b.hi, a.hi - b.lo)),
+                | Some(("mul", a, b)) => {
+                    // the products of 64 bit values do not always fit, those that do not can be anything
+                    let products = [(a.lo, b.lo), (a.lo, b.hi), (a.hi, b.lo), (a.hi, b.hi)]
+                        .iter()
+                        .map(|(x, y)| x.checked_mul(*y))
+                        .collect::<Option<Vec<_>>>();
+
+                    products.map(|p| Range::new(*p.iter().min().unwrap(), *p.iter().max().unwrap()))
+                },
+                | Some(("cmp", a, b)) => match Cmp::from_op(split_intrinsic(name).0).unwrap().eval(a, b) {
+                    | Some(t) => Some(Range::single(t as i128)),
+                    | None => Some(Range::new(0, 1)),
+                },
+                | _ => None,
+            },
+            | _ => None,
+        };
+
+        range.filter(|r| full.contains_range(*r)).unwrap_or(full)
+    }
+
+    fn transfer(&self, state: &mut State, conds: &mut HashMap<ir::Local, Cond>, stmt: &ir::Stmt) {
+        match stmt {
+            | ir::Stmt::Assign(place, rvalue) => {
+                let range = self.tracked(place.local).filter(|_| place.elems.is_empty()).map(|info| {
+                    let range = self.rvalue_range(state, rvalue, info);
+
+                    (range, range == Range::of_int(info.0, info.1))
+                });
+
+                kill(state, conds, place.local);
+
+                if let Some((range, false)) = range {
+                    state.insert(place.local, range);
+                }
+
+                if let (true, ir::RValue::Intrinsic(name, args)) = (place.elems.is_empty(), rvalue) {
+                    if let Some(cond) = Cond::new(name, args) {
+                        conds.insert(place.local, cond);
+                    }
+                }
+            },
+            | ir::Stmt::Call(rets, ..) => {
+                for ret in rets {
+                    kill(state, conds, ret.local);
+                }
+            },
+            | ir::Stmt::SetDiscr(place, _) => kill(state, conds, place.local),
+            | ir::Stmt::Init(local) | ir::Stmt::Drop(local) => kill(state, conds, *local),
+            | ir::Stmt::Loc(_) => {},
+        }
+    }
+
+    fn restrict(&self, state: &mut State, op: &ir::Operand, range: Range) -> bool {
+        if range.is_empty() {
+            return false;
+        }
+
+        if let ir::Operand::Place(place) = op {
+            if self.tracked(place.local).is_some() {
+                state.insert(place.local, range);
+            }
+        }
+
+        true
+    }
+
+    /// Narrow `state` for the edge of a switch on `op` taken when it equals `case`,
+    /// or for the otherwise edge when `case` is `None`. Returns false when the edge
+    /// can never be taken.
+    fn refine(&self, state: &mut State, conds: &HashMap<ir::Local, Cond>, op: &ir::Operand, case: Option<u128>, vals: &[u128]) -> bool {
+        let place = match op {
+            | ir::Operand::Place(place) if place.elems.is_empty() => place,
+            | ir::Operand::Const(ir::Const::Scalar(val, ty)) => {
+                let info = int_type(&ty.kind).unwrap_or((false, 128));
+                let val = sext(*val, info);
+
+                return match case {
+                    | Some(case) => val == sext(case, info),
+                    | None => !vals.iter().any(|v| sext(*v, info) == val),
+                };
+            },
+            | _ => return true,
+        };
+
+        if let Some(info) = self.tracked(place.local) {
+            let current = state.get(&place.local).copied().unwrap_or_else(|| Range::of_int(info.0, info.1));
+            let range = match case {
+                | Some(case) => current.intersect(Range::single(sext(case, info))),
+                | None => {
+                    let mut range = current;
+
+                    while !range.is_empty() && vals.iter().any(|v| sext(*v, info) == range.lo) {
+                        range.lo += 1;
+                    }
+
+                    while !range.is_empty() && vals.iter().any(|v| sext(*v, info) == range.hi) {
+                        range.hi -= 1;
+                    }
+
+                    range
+                },
+            };
+
+            if range.is_empty() {
+                return false;
+            }
+
+            state.insert(place.local, range);
+        }
+
+        if let Some(cond) = conds.get(&place.local) {
+            let truth = match case {
+                | Some(case) => Some(case != 0),
+                | None => match (vals.contains(&0), vals.contains(&1)) {
+                    | (true, true) => return false,
+                    | (true, false) => Some(true),
+                    | (false, true) => Some(false),
+                    | (false, false) => None,
+                },
+            };
+
+            if let Some(truth) = truth {
+                let cmp = if truth { cond.cmp } else { cond.cmp.negate() };
+                let a = self.range_of(state, &cond.lhs, cond.info);
+                let b = self.range_of(state, &cond.rhs, cond.info);
+
+                if let (Some(a), Some(b)) = (a, b) {
+                    let (a, b) = cmp.constrain(a, b);
+
+                    return self.restrict(state, &cond.lhs, a) && self.restrict(state, &cond.rhs, b);
+                }
+            }
+        }
+
+        true
+    }
+
+    fn successors(&self, state: &State, conds: &HashMap<ir::Local, Cond>, term: &ir::Term) -> Vec<(ir::Block, Option<State>)> {
+        match term {
+            | ir::Term::Jump(to) => vec![(*to, Some(state.clone()))],
+            | ir::Term::Switch(op, vals, targets) => {
+                let (otherwise, cases) = targets.split_last().unwrap();
+                let mut succs = Vec::new();
+
+                for (val, target) in vals.iter().zip(cases) {
+                    let mut state = state.clone();
+                    let feasible = self.refine(&mut state, conds, op, Some(*val), vals);
+
+                    succs.push((*target, Some(state).filter(|_| feasible)));
+                }
+
+                let mut state = state.clone();
+                let feasible = self.refine(&mut state, conds, op, None, vals);
+
+                succs.push((*otherwise, Some(state).filter(|_| feasible)));
+                succs
+            },
+            | ir::Term::BrIf(op, then, else_) => {
+                let mut taken = state.clone();
+                let mut not_taken = state.clone();
+                let then_feasible = self.refine(&mut taken, conds, op, None, &[0]);
+                let else_feasible = self.refine(&mut not_taken, conds, op, Some(0), &[0]);
+
+                vec![(*then, Some(taken).filter(|_| then_feasible)), (*else_, Some(not_taken).filter(|_| else_feasible))]
+            },
+            | _ => Vec::new(),
+        }
+    }
+
+    fn run(&mut self) {
+        let entry = match self.body.blocks.iter().next() {
+            | Some(block) => block.id,
+            | None => return,
+        };
+
+        let mut worklist = vec![entry];
+
+        self.ins.insert(entry, State::new());
+
+        while let Some(block) = worklist.pop() {
+            let data = &self.body.blocks[block];
+            let mut state = self.ins[&block].clone();
+            let mut conds = HashMap::new();
+
+            for stmt in &data.stmts {
+                // a call can unwind to its cleanup block before it writes its results
+                if let ir::Stmt::Call(_, _, _, Some(cleanup)) = stmt {
+                    self.propagate(*cleanup, state.clone(), &mut worklist);
+                }
+
+                self.transfer(&mut state, &mut conds, stmt);
+            }
+
+            for (target, state) in self.successors(&state, &conds, &data.term) {
+                if let Some(state) = state {
+                    self.propagate(target, state, &mut worklist);
+                }
+            }
+        }
+    }
+
+    fn propagate(&mut self, target: ir::Block, state: State, worklist: &mut Vec<ir::Block>) {
+        let visits = self.visits.entry(target).or_insert(0);
+
+        *visits += 1;
+
+        let new = match self.ins.get(&target) {
+            | None => state,
+            | Some(old) => {
+                let mut joined = old
+                    .iter()
+                    .filter_map(|(local, a)| state.get(local).map(|b| (*local, a.hull(*b))))
+                    .collect::<State>();
+
+                if self.visits[&target] > WIDEN_AFTER {
+                    joined.retain(|local, range| old.get(local) == Some(range));
+                }
+
+                if &joined == old {
+                    return;
+                }
+
+                joined
+            },
+        };
+
+        self.ins.insert(target, new);
+        worklist.push(target);
+    }
+
+    fn simplify(&self, transform: &mut RangeTransform) {
+        for (block, data) in self.body.blocks.iter_enumerated() {
+            let mut state = match self.ins.get(&block) {
+                | Some(state) => state.clone(),
+                | None => continue,
+            };
+
+            let mut conds = HashMap::new();
+
+            for (i, stmt) in data.stmts.iter().enumerate() {
+                if let ir::Stmt::Assign(place, ir::RValue::Intrinsic(name, args)) = stmt {
+                    if let Some(("cmp", a, b)) = self.binary(&state, name, args) {
+                        if let Some(t) = Cmp::from_op(split_intrinsic(name).0).unwrap().eval(a, b) {
+                            let ty = ir::place_type(self.body, place);
+                            let loc = ir::Location {
+                                body: self.body.id,
+                                block,
+                                stmt: i,
+                            };
+
+                            transform.folds.push((loc, ir::RValue::Use(ir::Operand::Const(ir::Const::Scalar(t as u128, ty)))));
+                        }
+                    }
+                }
+
+                self.transfer(&mut state, &mut conds, stmt);
+            }
+
+            if let ir::Term::Switch(op, vals, targets) = &data.term {
+                let succs = self.successors(&state, &conds, &data.term);
+                let (other, cases) = succs.split_last().unwrap();
+                let mut otherwise = *targets.last().unwrap();
+                let mut cases = vals
+                    .iter()
+                    .zip(cases)
+                    .filter(|(_, (_, state))| state.is_some())
+                    .map(|(val, (target, _))| (*val, *target))
+                    .collect::<Vec<_>>();
+
+                if other.1.is_none() {
+                    if let Some((_, target)) = cases.pop() {
+                        otherwise = target;
+                    }
+                }
+
+                let term = if cases.is_empty() {
+                    ir::Term::Jump(otherwise)
+                } else {
+                    let (vals, mut targets): (Vec<_>, Vec<_>) = cases.into_iter().unzip();
+
+                    targets.push(otherwise);
+                    ir::Term::Switch(op.clone(), vals, targets)
+                };
+
+                if term != data.term {
+                    transform.terms.push((self.body.id, block, term));
+                }
+            } else if let ir::Term::BrIf(..) = &data.term {
+                let succs = self.successors(&state, &conds, &data.term);
+
+                match (&succs[0], &succs[1]) {
+                    | ((then, Some(_)), (_, None)) => transform.terms.push((self.body.id, block, ir::Term::Jump(*then))),
+                    | ((_, None), (else_, Some(_))) => transform.terms.push((self.body.id, block, ir::Term::Jump(*else_))),
+                    | _ => {},
+                }
+            }
+        }
+    }
+}
+
+fn kill(state: &mut State, conds: &mut HashMap<ir::Local, Cond>, local: ir::Local) {
+    state.remove(&local);
+    conds.retain(|l, cond| *l != local && !cond.mentions(local));
+}
+
+fn is_simple(op: &ir::Operand) -> bool {
+    match op {
+        | ir::Operand::Place(place) => place.elems.is_empty(),
+        | ir::Operand::Const(c) => matches!(c, ir::Const::Scalar(_, _)),
+    }
+}
+
+fn int_type(ty: &ir::Type) -> Option<(bool, u32)> {
+    match ty {
+        | ir::Type::U8 => Some((false, 8)),
+        | ir::Type::U16 => Some((false, 16)),
+        | ir::Type::U32 => Some((false, 32)),
+        | ir::Type::U64 => Some((false, 64)),
+        | ir::Type::I8 => Some((true, 8)),
+        | ir::Type::I16 => Some((true, 16)),
+        | ir::Type::I32 => Some((true, 32)),
+        | ir::Type::I64 => Some((true, 64)),
+        | _ => None,
+    }
+}
+
+fn sext(val: u128, (signed, bits): (bool, u32)) -> i128 {
+    let shift = 128 - bits;
+
+    if signed {
+        ((val << shift) as i128) >> shift
+    } else {
+        ((val << shift) >> shift) as i128
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `_1` is 0 when `@g` unwinds to `%1` and 5 when it returns, so `%1` can not know the result of `lt`.
+    const SRC: &str = "import @g :: () -> ()
+local @f :: () -> (bool)
+fn @f {
+    ret _0 :: bool
+    var _1 :: i32
+%0:
+    _1 = 0 :: i32
+    call @g() unwind %1
+    _1 = 5 :: i32
+    jump %1
+%1:
+    _0 = #lt_i32(_1, 3 :: i32)
+    return
+}
+";
+
+    #[test]
+    fn cleanup_edges() {
+        let mut module = ir::parser::parse(SRC).unwrap();
+
+        crate::analyze(RangeAnalyzer, &mut module);
+        assert_eq!(module, ir::parser::parse(SRC).unwrap());
+
+        // without the unwind edge the comparison is folded
+        let src = SRC.replace(" unwind %1", "");
+        let mut module = ir::parser::parse(&src).unwrap();
+
+        crate::analyze(RangeAnalyzer, &mut module);
+        assert_eq!(module, ir::parser::parse(&src.replace("#lt_i32(_1, 3 :: i32)", "0 :: bool")).unwrap());
+    }
+}
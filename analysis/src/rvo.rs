@@ -0,0 +1,261 @@
+use crate::liveness::Effects;
+use crate::mono::is_generic;
+use crate::slots::merge_locals;
+use crate::Analyzer;
+use std::collections::{HashMap, HashSet};
+use transform::Transform;
+
+/// Guaranteed copy elision for returned aggregates. An aggregate is returned by writing it to memory the
+/// caller provides, so a local that is only built to be copied into a return value when the function returns
+/// is merged into that return value, constructing it in the caller's memory directly.
+///
+/// The callee now writes its return value while it still reads its arguments, so a call whose return value
+/// goes to a local that an argument may point to returns into a fresh temporary, which is copied to the local
+/// after the call.
+pub struct RvoAnalyzer<'a> {
+    target: &'a target_lexicon::Triple,
+}
+
+pub struct RvoTransform {
+    /// The locals of every body that are replaced by the return value they are copied to.
+    merges: Vec<(ir::BodyId, HashMap<ir::Local, ir::Local>)>,
+    /// The calls of every body, by block and statement, along with the index of the return value
+    /// that is stored in a temporary first.
+    temporaries: Vec<(ir::BodyId, Vec<(ir::Block, usize, usize)>)>,
+    stats: Vec<ir::progress::Stat>,
+}
+
+impl<'a> RvoAnalyzer<'a> {
+    pub fn new(target: &'a target_lexicon::Triple) -> Self {
+        RvoAnalyzer { target }
+    }
+
+    /// Whether values of `ty` are aggregates that live in memory.
+    fn in_memory(&self, ty: &ir::Ty) -> bool {
+        if is_generic(ty) {
+            return false;
+        }
+
+        let layout = ir::layout::layout_of(ty, self.target);
+
+        matches!(layout.abi, ir::layout::Abi::Aggregate { sized: true }) && layout.size.bytes() > 0
+    }
+}
+
+impl Analyzer for RvoAnalyzer<'_> {
+    type Output = RvoTransform;
+
+    fn analyze(&mut self, module: &ir::Module) -> Self::Output {
+        let mut merges = Vec::new();
+        let mut temporaries = Vec::new();
+        let mut stats = Vec::new();
+
+        for body in &module.bodies {
+            let merged = self.elide(body);
+            let calls = self.temporaries(body);
+
+            if !merged.is_empty() {
+                stats.push(ir::progress::Stat {
+                    decl: body.decl,
+                    name: "elided_copies",
+                    value: merged.len() as u64,
+                });
+
+                merges.push((body.id, merged));
+            }
+
+            if !calls.is_empty() {
+                stats.push(ir::progress::Stat {
+                    decl: body.decl,
+                    name: "call_temporaries",
+                    value: calls.len() as u64,
+                });
+
+                temporaries.push((body.id, calls));
+            }
+        }
+
+        RvoTransform {
+            merges,
+            temporaries,
+            stats,
+        }
+    }
+}
+
+impl Transform for RvoTransform {
+    fn apply(&mut self, module: &mut ir::Module) {
+        for (body, calls) in self.temporaries.drain(..) {
+            let body = &mut module.bodies[body];
+
+            // later calls first, so the statements of earlier calls keep their index
+            for (block, stmt, ret) in calls.into_iter().rev() {
+                let tmp = body.locals.next_idx();
+                let place = match &mut body.blocks[block].stmts[stmt] {
+                    ir::Stmt::Call(rets, _, _, _) => std::mem::replace(&mut rets[ret], ir::Place::new(tmp)),
+                    _ => unreachable!(),
+                };
+
+                let ty = ir::place_type(body, &place);
+
+                body.locals.insert(tmp, ir::LocalData {
+                    id: tmp,
+                    kind: ir::LocalKind::Tmp,
+                    ty,
+                });
+
+                let copy = ir::Stmt::Assign(place, ir::RValue::Use(ir::Operand::Place(ir::Place::new(tmp))));
+
+                body.blocks[block].stmts.insert(stmt + 1, copy);
+            }
+        }
+
+        for (body, merged) in self.merges.drain(..) {
+            let body = &mut module.bodies[body];
+
+            for block in body.blocks.iter_mut() {
+                block.stmts.retain(|stmt| match stmt {
+                    ir::Stmt::Assign(to, ir::RValue::Use(ir::Operand::Place(from))) => {
+                        !(to.elems.is_empty() && from.elems.is_empty() && merged.get(&from.local) == Some(&to.local))
+                    }
+                    _ => true,
+                });
+            }
+
+            merge_locals(body, &merged);
+        }
+    }
+
+    fn stats(&self) -> Vec<ir::progress::Stat> {
+        self.stats.clone()
+    }
+}
+
+impl RvoAnalyzer<'_> {
+    /// Returns the locals that can be built in the return value they are copied to, along with that return value.
+    /// A return value qualifies when it is only ever written by copying one and the same local to it right before
+    /// returning, and that local is not used in any way that would observe it being in the caller's memory.
+    fn elide(&self, body: &ir::Body) -> HashMap<ir::Local, ir::Local> {
+        let addressed = address_taken(body);
+        let mut merged = HashMap::new();
+
+        for ret in body.rets().filter(|ret| self.in_memory(&ret.ty)) {
+            let source = match copied_from(body, ret.id) {
+                Some(source) => source,
+                None => continue,
+            };
+
+            let local = &body.locals[source];
+            let lifetime = body
+                .blocks
+                .iter()
+                .flat_map(|b| &b.stmts)
+                .any(|stmt| matches!(stmt, ir::Stmt::Init(l) | ir::Stmt::Drop(l) if *l == source));
+
+            if matches!(local.kind, ir::LocalKind::Var | ir::LocalKind::Tmp)
+                && local.ty == ret.ty
+                && !addressed.contains(&source)
+                && !lifetime
+                && !merged.contains_key(&source)
+            {
+                merged.insert(source, ret.id);
+            }
+        }
+
+        merged
+    }
+
+    /// Returns the calls that store a return value in memory an argument may point to.
+    fn temporaries(&self, body: &ir::Body) -> Vec<(ir::Block, usize, usize)> {
+        let addressed = address_taken(body);
+        let mut calls = Vec::new();
+
+        for block in &body.blocks {
+            for (i, stmt) in block.stmts.iter().enumerate() {
+                if let ir::Stmt::Call(rets, _, args, _) = stmt {
+                    let passed = args
+                        .iter()
+                        .filter_map(|arg| match arg {
+                            ir::Operand::Place(place) => Some(place.local),
+                            ir::Operand::Const(_) => None,
+                        })
+                        .collect::<HashSet<_>>();
+
+                    for (j, place) in rets.iter().enumerate() {
+                        let aliased = addressed.contains(&place.local)
+                            || passed.contains(&place.local)
+                            || place.elems.iter().any(|e| matches!(e, ir::PlaceElem::Deref));
+
+                        if aliased && self.in_memory(&ir::place_type(body, place)) {
+                            calls.push((block.id, i, j));
+                        }
+                    }
+                }
+            }
+        }
+
+        calls
+    }
+}
+
+/// Returns the local that is copied to `ret` at the end of every block that writes it,
+/// or `None` if `ret` is used in any other way.
+fn copied_from(body: &ir::Body, ret: ir::Local) -> Option<ir::Local> {
+    let mut source = None;
+
+    for block in &body.blocks {
+        for (i, stmt) in block.stmts.iter().enumerate() {
+            let effects = Effects::of(stmt);
+
+            if !effects.uses.contains(&ret) && !effects.defs.contains(&ret) {
+                continue;
+            }
+
+            let last = block.stmts[i + 1..].iter().all(|stmt| matches!(stmt, ir::Stmt::Loc(_))) && block.term == ir::Term::Return;
+
+            match stmt {
+                ir::Stmt::Assign(to, ir::RValue::Use(ir::Operand::Place(from)))
+                    if last
+                        && to.local == ret
+                        && to.elems.is_empty()
+                        && from.elems.is_empty()
+                        && from.local != ret
+                        && source.map_or(true, |s| s == from.local) =>
+                {
+                    source = Some(from.local);
+                }
+                _ => return None,
+            }
+        }
+
+        let op = match &block.term {
+            ir::Term::Switch(op, _, _) | ir::Term::BrIf(op, _, _) => op,
+            _ => continue,
+        };
+
+        if let ir::Operand::Place(place) = op {
+            let indices = place.elems.iter().any(|e| match e {
+                ir::PlaceElem::Index(ir::Operand::Place(index)) => index.local == ret,
+                _ => false,
+            });
+
+            if place.local == ret || indices {
+                return None;
+            }
+        }
+    }
+
+    source
+}
+
+/// Returns the locals whose address is taken somewhere in `body`.
+fn address_taken(body: &ir::Body) -> HashSet<ir::Local> {
+    body.blocks
+        .iter()
+        .flat_map(|block| &block.stmts)
+        .filter_map(|stmt| match stmt {
+            ir::Stmt::Assign(_, ir::RValue::AddrOf(place)) => Some(place.local),
+            _ => None,
+        })
+        .collect()
+}
@@ -0,0 +1,181 @@
+use crate::liveness::{Effects, Liveness};
+use crate::Analyzer;
+use ir::visitor::VisitorMut;
+use std::collections::{HashMap, HashSet};
+use transform::Transform;
+
+/// Stack slot coloring. Every local gets its own slot in the frame, so locals of the same type whose
+/// values are never live at the same time are merged into one. Locals whose address is taken are
+/// left alone, because a pointer to them may be used after their last direct use.
+pub struct SlotAnalyzer<'a> {
+    target: &'a target_lexicon::Triple,
+}
+
+pub struct SlotTransform {
+    /// The locals of every body that are replaced by another local of the same type.
+    merges: Vec<(ir::BodyId, HashMap<ir::Local, ir::Local>)>,
+    stats: Vec<ir::progress::Stat>,
+}
+
+impl<'a> SlotAnalyzer<'a> {
+    pub fn new(target: &'a target_lexicon::Triple) -> Self {
+        SlotAnalyzer { target }
+    }
+}
+
+impl Analyzer for SlotAnalyzer<'_> {
+    type Output = SlotTransform;
+
+    fn analyze(&mut self, module: &ir::Module) -> Self::Output {
+        let mut merges = Vec::new();
+        let mut stats = Vec::new();
+
+        for body in &module.bodies {
+            let merged = color(body);
+
+            if merged.is_empty() {
+                continue;
+            }
+
+            let saved = merged
+                .keys()
+                .map(|local| ir::layout::layout_of(&body.locals[*local].ty, self.target).size.bytes())
+                .sum();
+
+            stats.push(ir::progress::Stat {
+                decl: body.decl,
+                name: "merged_locals",
+                value: merged.len() as u64,
+            });
+
+            stats.push(ir::progress::Stat {
+                decl: body.decl,
+                name: "saved_bytes",
+                value: saved,
+            });
+
+            merges.push((body.id, merged));
+        }
+
+        SlotTransform { merges, stats }
+    }
+}
+
+impl Transform for SlotTransform {
+    fn apply(&mut self, module: &mut ir::Module) {
+        for (body, merged) in self.merges.drain(..) {
+            merge_locals(&mut module.bodies[body], &merged);
+        }
+    }
+
+    fn stats(&self) -> Vec<ir::progress::Stat> {
+        self.stats.clone()
+    }
+}
+
+/// Replaces every local in `merged` with the local it is mapped to, removing it from the body
+/// and renumbering the locals that are left.
+pub(crate) fn merge_locals(body: &mut ir::Body, merged: &HashMap<ir::Local, ir::Local>) {
+    let mut renumber = HashMap::new();
+
+    body.locals.retain(|local| !merged.contains_key(&local.id));
+
+    for (i, local) in body.locals.iter_mut().enumerate() {
+        renumber.insert(local.id, ir::Local::new(i));
+        local.id = ir::Local::new(i);
+    }
+
+    for (local, into) in merged {
+        renumber.insert(*local, renumber[into]);
+    }
+
+    Rename(&renumber).visit_body(body);
+}
+
+/// Returns the locals that can be merged into another local, along with that local.
+fn color(body: &ir::Body) -> HashMap<ir::Local, ir::Local> {
+    let mut candidates = body
+        .locals
+        .iter()
+        .filter(|local| matches!(local.kind, ir::LocalKind::Var | ir::LocalKind::Tmp))
+        .map(|local| local.id)
+        .collect::<HashSet<_>>();
+
+    for block in &body.blocks {
+        for stmt in &block.stmts {
+            if let ir::Stmt::Assign(_, ir::RValue::AddrOf(place)) = stmt {
+                candidates.remove(&place.local);
+            }
+        }
+    }
+
+    let interference = interference(body, &candidates);
+    let none = HashSet::new();
+    let mut slots = Vec::<(ir::Local, Vec<ir::Local>)>::new();
+    let mut merged = HashMap::new();
+
+    for local in body.locals.iter().filter(|local| candidates.contains(&local.id)) {
+        let conflicts = interference.get(&local.id).unwrap_or(&none);
+        let slot = slots.iter_mut().find(|(first, members)| {
+            body.locals[*first].ty == local.ty && members.iter().all(|member| !conflicts.contains(member))
+        });
+
+        match slot {
+            Some((first, members)) => {
+                merged.insert(local.id, *first);
+                members.push(local.id);
+            }
+            None => slots.push((local.id, vec![local.id])),
+        }
+    }
+
+    merged
+}
+
+/// Finds the pairs of candidates that can not share a slot. A local interferes with the locals that are live
+/// where it is written, and with the other locals of the statement writing it, so a call never gets the same
+/// memory for an argument and a result.
+fn interference(body: &ir::Body, candidates: &HashSet<ir::Local>) -> HashMap<ir::Local, HashSet<ir::Local>> {
+    let liveness = Liveness::new(body);
+    let mut interference = HashMap::<_, HashSet<_>>::new();
+
+    for block in &body.blocks {
+        let mut live = liveness.live_out(block.id).clone();
+
+        liveness.step_term(body, &block.term, &mut live);
+
+        for stmt in block.stmts.iter().rev() {
+            let effects = Effects::of(stmt);
+
+            for def in effects.defs.iter().filter(|def| candidates.contains(def)) {
+                let others = live.iter().chain(&effects.uses).chain(&effects.defs);
+
+                for other in others.filter(|other| *other != def && candidates.contains(other)) {
+                    interference.entry(*def).or_default().insert(*other);
+                    interference.entry(*other).or_default().insert(*def);
+                }
+            }
+
+            liveness.step(stmt, &mut live);
+        }
+    }
+
+    interference
+}
+
+struct Rename<'a>(&'a HashMap<ir::Local, ir::Local>);
+
+impl VisitorMut for Rename<'_> {
+    fn visit_stmt(&mut self, stmt: &mut ir::Stmt, loc: ir::Location) {
+        if let ir::Stmt::Init(local) | ir::Stmt::Drop(local) = stmt {
+            *local = self.0[local];
+        }
+
+        self.super_stmt(stmt, loc);
+    }
+
+    fn visit_place(&mut self, place: &mut ir::Place) {
+        place.local = self.0[&place.local];
+        self.super_place(place);
+    }
+}
@@ -0,0 +1,307 @@
+use crate::alias::AliasAnalysis;
+use crate::Analyzer;
+use transform::Transform;
+
+/// Forwards stored values to later loads of the same place and removes stores
+/// that are overwritten or freed before they are read. Both work within a
+/// single block.
+pub struct StoreAnalyzer;
+
+pub struct StoreTransform {
+    blocks: Vec<(ir::BodyId, ir::Block, Vec<ir::Stmt>)>,
+}
+
+struct BodyStores<'a> {
+    module: &'a ir::Module,
+    body: &'a ir::Body,
+    alias: AliasAnalysis<'a>,
+}
+
+impl Analyzer for StoreAnalyzer {
+    type Output = StoreTransform;
+
+    fn analyze(&mut self, module: &ir::Module) -> Self::Output {
+        let mut blocks = Vec::new();
+
+        for body in &module.bodies {
+            let stores = BodyStores {
+                module,
+                body,
+                alias: AliasAnalysis::new(module, body),
+            };
+
+            for (block, data) in body.blocks.iter_enumerated() {
+                let mut stmts = data.stmts.clone();
+                let forwarded = stores.forward(&mut stmts);
+                let removed = stores.remove_dead(&data.term, &mut stmts);
+
+                if forwarded || removed {
+                    blocks.push((body.id, block, stmts));
+                }
+            }
+        }
+
+        StoreTransform { blocks }
+    }
+}
+
+impl Transform for StoreTransform {
+    fn apply(&mut self, module: &mut ir::Module) {
+        for (body, block, stmts) in self.blocks.drain(..) {
+            module.bodies[body].blocks[block].stmts = stmts;
+        }
+    }
+}
+
+impl<'a> BodyStores<'a> {
+    fn forward(&self, stmts: &mut [ir::Stmt]) -> bool {
+        let mut avail = Vec::<(ir::Place, ir::Operand)>::new();
+        let mut changed = false;
+
+        for stmt in stmts {
+            match stmt {
+                | ir::Stmt::Assign(place, rvalue) => {
+                    changed |= forward_rvalue(&avail, rvalue);
+
+                    if self.touches_memory(rvalue) {
+                        avail.retain(|(k, v)| self.alias.is_private(k) && self.is_private_op(v));
+                    }
+
+                    self.kill(&mut avail, place);
+
+                    if let ir::RValue::Use(op) = rvalue {
+                        let overlaps = matches!(op, ir::Operand::Place(p) if self.clobbers(place, p));
+
+                        if is_trackable(place) && is_trackable_op(op) && !overlaps {
+                            avail.push((place.clone(), op.clone()));
+                        }
+                    }
+                },
+                | ir::Stmt::Call(rets, func, args, _) => {
+                    changed |= forward_op(&avail, func);
+
+                    for arg in args {
+                        changed |= forward_op(&avail, arg);
+                    }
+
+                    avail.retain(|(k, v)| self.alias.is_private(k) && self.is_private_op(v));
+
+                    for ret in rets.iter() {
+                        self.kill(&mut avail, ret);
+                    }
+                },
+                | ir::Stmt::SetDiscr(place, _) => self.kill(&mut avail, place),
+                | ir::Stmt::Init(local) | ir::Stmt::Drop(local) => self.kill(&mut avail, &ir::Place::new(*local)),
+                | ir::Stmt::Loc(_) => {},
+            }
+        }
+
+        changed
+    }
+
+    fn remove_dead(&self, term: &ir::Term, stmts: &mut Vec<ir::Stmt>) -> bool {
+        // places that are overwritten or freed before they are read again
+        let mut dead = match term {
+            | ir::Term::Return | ir::Term::Abort | ir::Term::Resume => self
+                .body
+                .locals
+                .iter()
+                .filter(|l| l.kind != ir::LocalKind::Ret)
+                .map(|l| ir::Place::new(l.id))
+                .collect(),
+            | _ => Vec::new(),
+        };
+
+        let mut removed = vec![false; stmts.len()];
+        let mut reads = Vec::new();
+
+        for (i, stmt) in stmts.iter().enumerate().rev() {
+            match stmt {
+                | ir::Stmt::Assign(place, rvalue) => {
+                    if !matches!(rvalue, ir::RValue::Intrinsic(..)) && dead.iter().any(|w| self.covers(w, place)) {
+                        removed[i] = true;
+                        continue;
+                    }
+
+                    if self.touches_memory(rvalue) {
+                        dead.retain(|w| self.alias.is_private(w));
+                    }
+
+                    self.define(&mut dead, place);
+                    self.address_reads(place, &mut reads);
+                    self.rvalue_reads(rvalue, &mut reads);
+
+                    if let ir::RValue::Intrinsic(name, args) = rvalue {
+                        if let ("stack_free", [ir::Operand::Place(ptr)]) = (name.as_str(), &args[..]) {
+                            self.define(&mut dead, &ptr.clone().deref());
+                        }
+                    }
+                },
+                | ir::Stmt::Call(rets, func, args, cleanup) => {
+                    dead.retain(|w| self.alias.is_private(w));
+
+                    for ret in rets {
+                        self.define(&mut dead, ret);
+                        self.address_reads(ret, &mut reads);
+                    }
+
+                    // the cleanup block can read anything, including the rets the call did not get to write
+                    if cleanup.is_some() {
+                        dead.clear();
+                    }
+
+                    for op in std::iter::once(func).chain(args) {
+                        self.op_reads(op, &mut reads);
+                    }
+                },
+                | ir::Stmt::SetDiscr(place, _) => {
+                    self.define(&mut dead, place);
+                    self.address_reads(place, &mut reads);
+                },
+                | ir::Stmt::Init(local) | ir::Stmt::Drop(local) => {
+                    self.define(&mut dead, &ir::Place::new(*local));
+                },
+                | ir::Stmt::Loc(_) => {},
+            }
+
+            for read in reads.drain(..) {
+                dead.retain(|w| !self.alias.may_alias(w, &read));
+            }
+        }
+
+        let changed = removed.contains(&true);
+        let mut removed = removed.into_iter();
+
+        stmts.retain(|_| !removed.next().unwrap());
+        changed
+    }
+
+    /// Whether writing to `written` can change the value of `place`,
+    /// either by overlapping it or by changing the pointer it goes through.
+    fn clobbers(&self, written: &ir::Place, place: &ir::Place) -> bool {
+        self.alias.may_alias(written, place)
+            || (written.local == place.local && !self.alias.is_indirect(written) && self.alias.is_indirect(place))
+    }
+
+    /// Whether a store to `place` is fully overwritten by a store to `by`.
+    fn covers(&self, by: &ir::Place, place: &ir::Place) -> bool {
+        by.local == place.local
+            && place.elems.starts_with(&by.elems)
+            && self.alias.is_indirect(by) == self.alias.is_indirect(place)
+    }
+
+    fn kill(&self, avail: &mut Vec<(ir::Place, ir::Operand)>, written: &ir::Place) {
+        avail.retain(|(k, v)| !self.clobbers(written, k) && !matches!(v, ir::Operand::Place(p) if self.clobbers(written, p)));
+    }
+
+    fn define(&self, dead: &mut Vec<ir::Place>, place: &ir::Place) {
+        if !self.alias.is_indirect(place) {
+            dead.retain(|w| w.local != place.local || !self.alias.is_indirect(w));
+        }
+
+        if is_trackable(place) {
+            dead.push(place.clone());
+        }
+    }
+
+    fn touches_memory(&self, rvalue: &ir::RValue) -> bool {
+        match rvalue {
+            | ir::RValue::Intrinsic(_, args) => args.iter().any(|op| {
+                matches!(
+                    ir::operand_type(self.module, self.body, op).access().kind,
+                    ir::Type::Ptr(_) | ir::Type::Box(_)
+                )
+            }),
+            | _ => false,
+        }
+    }
+
+    fn is_private_op(&self, op: &ir::Operand) -> bool {
+        match op {
+            | ir::Operand::Place(place) => self.alias.is_private(place),
+            | ir::Operand::Const(_) => true,
+        }
+    }
+
+    fn rvalue_reads(&self, rvalue: &ir::RValue, reads: &mut Vec<ir::Place>) {
+        match rvalue {
+            | ir::RValue::Use(op) | ir::RValue::UnOp(_, op) => self.op_reads(op, reads),
+            | ir::RValue::AddrOf(place) | ir::RValue::GetDiscr(place) | ir::RValue::Cast(place, _) => {
+                reads.push(place.clone());
+                self.address_reads(place, reads);
+            },
+            | ir::RValue::Lea { base, index, .. } => {
+                self.op_reads(base, reads);
+
+                if let Some(index) = index {
+                    self.op_reads(index, reads);
+                }
+            },
+            | ir::RValue::Intrinsic(_, args) => {
+                for arg in args {
+                    self.op_reads(arg, reads);
+                }
+            },
+            | ir::RValue::NullOp(..) => {},
+        }
+    }
+
+    fn op_reads(&self, op: &ir::Operand, reads: &mut Vec<ir::Place>) {
+        if let ir::Operand::Place(place) = op {
+            reads.push(place.clone());
+            self.address_reads(place, reads);
+        }
+    }
+
+    /// The places read to compute the address of `place`.
+    fn address_reads(&self, place: &ir::Place, reads: &mut Vec<ir::Place>) {
+        if self.alias.is_indirect(place) {
+            reads.push(ir::Place::new(place.local));
+        }
+
+        for elem in &place.elems {
+            if let ir::PlaceElem::Index(op) = elem {
+                self.op_reads(op, reads);
+            }
+        }
+    }
+}
+
+fn forward_rvalue(avail: &[(ir::Place, ir::Operand)], rvalue: &mut ir::RValue) -> bool {
+    match rvalue {
+        | ir::RValue::Use(op) | ir::RValue::UnOp(_, op) => forward_op(avail, op),
+        | ir::RValue::Lea { base, index, .. } => {
+            let changed = forward_op(avail, base);
+
+            match index {
+                | Some(index) => forward_op(avail, index) || changed,
+                | None => changed,
+            }
+        },
+        | ir::RValue::Intrinsic(_, args) => args.iter_mut().fold(false, |changed, op| forward_op(avail, op) || changed),
+        | _ => false,
+    }
+}
+
+fn forward_op(avail: &[(ir::Place, ir::Operand)], op: &mut ir::Operand) -> bool {
+    if let ir::Operand::Place(place) = op {
+        if let Some((_, val)) = avail.iter().find(|(k, _)| k == place) {
+            *op = val.clone();
+
+            return true;
+        }
+    }
+
+    false
+}
+
+fn is_trackable(place: &ir::Place) -> bool {
+    place.elems.iter().all(|e| !matches!(e, ir::PlaceElem::Index(_)))
+}
+
+fn is_trackable_op(op: &ir::Operand) -> bool {
+    match op {
+        | ir::Operand::Place(place) => is_trackable(place),
+        | ir::Operand::Const(_) => true,
+    }
+}
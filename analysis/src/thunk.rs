@@ -69,7 +69,7 @@ impl Visitor for ThunkAnalyzer {
     }
 
     fn visit_stmt(&mut self, stmt: &ir::Stmt, _: ir::Location) {
-        if let ir::Stmt::Call(_, func, args) = stmt {
+        if let ir::Stmt::Call(_, func, args, _) = stmt {
             let func_ty = ir::operand_type(self.module(), self.body(), func);
 
             if let ir::Type::Func(ir::Signature { params, .. }) = func_ty.kind {
@@ -184,7 +184,8 @@ fn type_matches(a: &Type, b: &Type) -> bool {
             | (Type::I64, Type::I64)
             | (Type::I128, Type::I128)
             | (Type::F32, Type::F32)
-            | (Type::F64, Type::F64) => true,
+            | (Type::F64, Type::F64)
+            | (Type::Bool, Type::Bool) => true,
             (Type::Ptr(a), Type::Ptr(b)) => rec(a, b, env),
             (Type::Tuple(a), Type::Tuple(b)) if a.len() == b.len() => {
                 a.iter().zip(b).all(|(a, b)| rec(a, b, env))
@@ -192,6 +193,8 @@ fn type_matches(a: &Type, b: &Type) -> bool {
             (Type::Union(a), Type::Union(b)) if a.len() == b.len() => {
                 a.iter().zip(b).all(|(a, b)| rec(a, b, env))
             }
+            (Type::Vector(a, n), Type::Vector(b, m)) if n == m => rec(a, b, env),
+            (Type::Newtype(a_name, a), Type::Newtype(b_name, b)) if a_name == b_name => rec(a, b, env),
             (Type::Func(a), Type::Func(b))
                 if a.params.len() == b.params.len() && a.rets.len() == b.rets.len() =>
             {
@@ -1,9 +1,15 @@
 use crate::Analyzer;
 use ir::visitor::Visitor;
+use std::collections::HashMap;
 use transform::Transform;
 
+/// Trivial types up to this size are copied by glue functions that load and store the value inline
+/// instead of calling `copy_trivial`.
+pub const MAX_INLINE_COPY: u64 = 32;
+
 pub struct TypeInfoAnalyzer<'a> {
     target: &'a target_lexicon::Triple,
+    witnesses: Witnesses,
     module: *const ir::Module,
     body: *const ir::Body,
     infos: Vec<TypeInfo>,
@@ -11,18 +17,35 @@ pub struct TypeInfoAnalyzer<'a> {
 
 pub struct TypeInfoTransform<'a> {
     target: &'a target_lexicon::Triple,
+    witnesses: Witnesses,
     infos: Vec<TypeInfo>,
 }
 
+/// Where the functions in the value witness table of trivial types come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Witnesses {
+    /// `copy_trivial`, `move_trivial`, `copy_move_nop` and `drop_nop` are imported from the runtime library.
+    Import,
+    /// The witness functions are defined in the module itself, so it can be linked without the runtime library.
+    Define,
+}
+
+impl Default for Witnesses {
+    fn default() -> Self {
+        Witnesses::Import
+    }
+}
+
 pub struct TypeInfo {
     ty: ir::Ty,
     ops: Vec<*mut ir::Const>,
 }
 
 impl<'a> TypeInfoAnalyzer<'a> {
-    pub fn new(target: &'a target_lexicon::Triple) -> Self {
+    pub fn new(target: &'a target_lexicon::Triple, witnesses: Witnesses) -> Self {
         TypeInfoAnalyzer {
             target,
+            witnesses,
             module: std::ptr::null(),
             body: std::ptr::null(),
             infos: Vec::new(),
@@ -48,6 +71,7 @@ impl<'a> Analyzer for TypeInfoAnalyzer<'a> {
 
         TypeInfoTransform {
             target: self.target,
+            witnesses: self.witnesses,
             infos: std::mem::replace(&mut self.infos, Vec::new()),
         }
     }
@@ -65,7 +89,7 @@ impl<'a> Visitor for TypeInfoAnalyzer<'a> {
     }
 
     fn visit_stmt(&mut self, stmt: &ir::Stmt, _: ir::Location) {
-        if let ir::Stmt::Call(rets, func, args) = stmt {
+        if let ir::Stmt::Call(rets, func, args, _) = stmt {
             let sig = ir::operand_type(self.module(), self.body(), func).signature();
             let tys = sig.find_type_instances(args, rets, self.module(), self.body());
 
@@ -106,6 +130,7 @@ impl<'a> TypeInfoTransform<'a> {
         module: &mut ir::Module,
         decl: ir::DeclId,
         ty: ir::Ty,
+        vwt: Option<ir::DeclId>,
     ) {
         let bodyid = module.bodies.next_idx();
         let mut body = ir::Body::new(bodyid, decl);
@@ -141,6 +166,19 @@ impl<'a> TypeInfoTransform<'a> {
             )),
         );
 
+        if let Some(vwt) = vwt {
+            builder.use_op(place.clone().field(3), ir::Operand::Const(ir::Const::Addr(vwt)));
+        }
+
+        // the same type has the same id in every module, which is what `dyn_cast` compares first
+        let id_ty = ir::layout::ptr_sized_int(target);
+        let id = ir::layout::layout_of(&id_ty, target).size.truncate(ir::serialize::stable_hash(&ty) as u128);
+
+        builder.use_op(place.clone().field(4), ir::Operand::Const(ir::Const::Scalar(id, id_ty)));
+
+        // ids are truncated hashes, so types with the same id are only the same if their names are too
+        builder.use_op(place.clone().field(5), ir::Operand::Const(ir::Const::Ptr(Box::new(ir::Const::Bytes(type_name(&ty))))));
+
         builder.return_();
 
         module.bodies.insert(bodyid, body);
@@ -149,6 +187,10 @@ impl<'a> TypeInfoTransform<'a> {
 
 impl<'a> Transform for TypeInfoTransform<'a> {
     fn apply(&mut self, module: &mut ir::Module) {
+        let (target, witnesses) = (self.target, self.witnesses);
+        let mut vwts = Vwts::default();
+        let mut infos = Vec::new();
+
         for info in self.infos.drain(..) {
             let decl = module.decls.next_idx();
 
@@ -163,7 +205,16 @@ impl<'a> Transform for TypeInfoTransform<'a> {
                 },
             );
 
-            Self::gen_type_info(self.target, module, decl, info.ty);
+            let vwt = if is_trivial(&info.ty) {
+                let layout = ir::layout::layout_of(&info.ty, target);
+
+                Some(vwts.get(module, witnesses, &layout))
+            } else {
+                Some(vwts.get_glue(module, &info.ty))
+            };
+
+            Self::gen_type_info(target, module, decl, info.ty, vwt);
+            infos.push(decl);
 
             for op in info.ops {
                 // SAFETY: we have exclusive access to module and all `op` pointers point into
@@ -173,5 +224,418 @@ impl<'a> Transform for TypeInfoTransform<'a> {
                 }
             }
         }
+
+        // the registry lives in the runtime library, which modules with their own witnesses do not link with
+        if witnesses == Witnesses::Import && !infos.is_empty() {
+            gen_registration(module, target, &infos);
+        }
+    }
+}
+
+/// Defines a constructor that registers the type info of the module with the runtime when the module is loaded.
+fn gen_registration(module: &mut ir::Module, target: &target_lexicon::Triple, infos: &[ir::DeclId]) {
+    let info = ir::Ty::new(ir::Type::Ptr(Box::new(ir::Ty::new(ir::Type::Type(String::new())))));
+    let sig = |params| {
+        ir::Ty::new(ir::Type::Func(ir::Signature {
+            params,
+            rets: Vec::new(),
+            call_conv: ir::CallConv::C,
+            variadic: false,
+        }))
+    };
+
+    let register = module.declare_global("type_info_register", ir::Linkage::Import, sig(vec![info]));
+    let init = module.declare_global("__lowlang_register_types", ir::Linkage::Local, sig(Vec::new()));
+
+    module.decls[register].attrs.c_abi = true;
+    module.decls[init].attrs.c_abi = true;
+
+    let bodyid = module.bodies.next_idx();
+    let mut body = ir::Body::new(bodyid, init);
+    let mut builder = ir::Builder::new(&mut body);
+    let entry = builder.create_block();
+
+    builder.set_block(entry);
+
+    for info in infos {
+        builder.call(Vec::new(), ir::Operand::Const(ir::Const::Addr(register)), vec![ir::Operand::Const(ir::Const::Addr(*info))]);
+    }
+
+    builder.return_();
+    module.bodies.insert(bodyid, body);
+
+    // a pointer to the constructor in the section the loader runs constructors from
+    let ptr = ir::Ty::new(ir::Type::Ptr(Box::new(ir::Ty::new(ir::Type::U8))));
+    let ctor = module.declare_global("__lowlang_register_types_ctor", ir::Linkage::Local, ptr.clone());
+
+    module.decls[ctor].attrs.section = Some(String::from(match target.binary_format {
+        target_lexicon::BinaryFormat::Macho => "__mod_init_func",
+        target_lexicon::BinaryFormat::Coff => ".CRT$XCU",
+        _ => ".init_array",
+    }));
+
+    let bodyid = module.bodies.next_idx();
+    let mut body = ir::Body::new(bodyid, ctor);
+    let mut builder = ir::Builder::new(&mut body);
+    let ret = builder.create_ret(ptr);
+    let entry = builder.create_block();
+
+    builder.set_block(entry);
+    builder.use_op(ir::Place::new(ret), ir::Operand::Const(ir::Const::Addr(init)));
+    builder.return_();
+    module.bodies.insert(bodyid, body);
+}
+
+/// The encoding of `ty` in hex, which is the same for the same type in every module and never contains a nul.
+fn type_name(ty: &ir::Ty) -> Box<[u8]> {
+    let mut bytes = Vec::new();
+
+    ir::serialize::Encode::encode(ty, &mut bytes).unwrap();
+
+    let mut name = bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>().into_bytes();
+
+    name.push(0);
+    name.into_boxed_slice()
+}
+
+fn is_trivial(ty: &ir::Ty) -> bool {
+    match &ty.kind {
+        ir::Type::Box(_) => false,
+        ir::Type::Tuple(tys) | ir::Type::Union(tys) | ir::Type::Tagged(tys) => tys.iter().all(is_trivial),
+        ir::Type::Newtype(_, inner) => is_trivial(inner),
+        _ => true,
+    }
+}
+
+/// The value witness tables of trivial types: one for zero sized types, one for every size class up to
+/// `MAX_INLINE_COPY` and one for all larger types. Each is declared when the first type needs it.
+#[derive(Default)]
+struct Vwts {
+    trivial: Option<ir::DeclId>,
+    nop: Option<ir::DeclId>,
+    drop_nop: Option<ir::DeclId>,
+    /// Keyed by the size and the size of the chunks the value is copied in.
+    small: HashMap<(u64, u64), ir::DeclId>,
+    /// The value witness tables of types containing boxes, which have glue functions of their own.
+    glue: HashMap<ir::Ty, ir::DeclId>,
+}
+
+impl Vwts {
+    fn get(&mut self, module: &mut ir::Module, witnesses: Witnesses, layout: &ir::layout::TyLayout) -> ir::DeclId {
+        let size = layout.size.bytes();
+        let drop_nop = *self.drop_nop.get_or_insert_with(|| gen_witness(module, witnesses, "drop_nop", WitnessKind::Drop));
+
+        if size == 0 {
+            *self.nop.get_or_insert_with(|| {
+                let copy_move_nop = gen_witness(module, witnesses, "copy_move_nop", WitnessKind::Nop);
+
+                gen_vwt(module, "__vwt_nop", [copy_move_nop, copy_move_nop, drop_nop])
+            })
+        } else if size > MAX_INLINE_COPY {
+            *self.trivial.get_or_insert_with(|| {
+                let copy_trivial = gen_witness(module, witnesses, "copy_trivial", WitnessKind::Copy);
+                let move_trivial = gen_witness(module, witnesses, "move_trivial", WitnessKind::Copy);
+
+                gen_vwt(module, "__vwt_trivial", [copy_trivial, move_trivial, drop_nop])
+            })
+        } else {
+            // the size is a multiple of the alignment, so chunks of the alignment cover the value exactly
+            let chunk = layout.align.bytes().min(8);
+
+            *self.small.entry((size, chunk)).or_insert_with(|| {
+                let copy = gen_copy_glue(module, size, chunk);
+
+                gen_vwt(module, &format!("__vwt_{}_{}", size, chunk), [copy, copy, drop_nop])
+            })
+        }
+    }
+
+    fn get_glue(&mut self, module: &mut ir::Module, ty: &ir::Ty) -> ir::DeclId {
+        if let Some(vwt) = self.glue.get(ty) {
+            return *vwt;
+        }
+
+        let copy = gen_glue(module, ty, GlueKind::Copy);
+        let move_ = gen_glue(module, ty, GlueKind::Move);
+        let drop = gen_glue(module, ty, GlueKind::Drop);
+        let vwt = gen_vwt(module, &format!("__vwt_glue_{}", copy.index()), [copy, move_, drop]);
+
+        self.glue.insert(ty.clone(), vwt);
+        vwt
+    }
+}
+
+fn gen_vwt(module: &mut ir::Module, name: &str, funcs: [ir::DeclId; 3]) -> ir::DeclId {
+    let decl = module.decls.next_idx();
+
+    module.decls.insert(
+        decl,
+        ir::Decl {
+            id: decl,
+            name: name.into(),
+            linkage: ir::Linkage::Hidden,
+            ty: ir::Ty::new(ir::Type::Vwt(String::new())),
+            attrs: ir::Attrs::default(),
+        },
+    );
+
+    let bodyid = module.bodies.next_idx();
+    let mut body = ir::Body::new(bodyid, decl);
+    let mut builder = ir::Builder::new(&mut body);
+    let ret = builder.create_ret(ir::Ty::new(ir::Type::Vwt(String::new())));
+    let entry = builder.create_block();
+
+    builder.set_block(entry);
+
+    for (i, func) in funcs.iter().enumerate() {
+        builder.use_op(ir::Place::new(ret).field(i), ir::Operand::Const(ir::Const::Addr(*func)));
+    }
+
+    builder.return_();
+    module.bodies.insert(bodyid, body);
+
+    decl
+}
+
+enum WitnessKind {
+    /// Copies the value with `memcpy`.
+    Copy,
+    /// Copies or moves a zero sized value, which does nothing.
+    Nop,
+    /// Drops a value that needs no cleanup.
+    Drop,
+}
+
+/// Defines a copy witness for values of `size` bytes, which copies them as a tuple of `chunk` sized integers
+/// one field at a time. Copying and moving a trivial value is the same, so it is used for both.
+fn gen_copy_glue(module: &mut ir::Module, size: u64, chunk: u64) -> ir::DeclId {
+    let int = match chunk {
+        1 => ir::Type::U8,
+        2 => ir::Type::U16,
+        4 => ir::Type::U32,
+        _ => ir::Type::U64,
+    };
+
+    let count = (size / chunk) as usize;
+    let chunks = ir::Ty::new(ir::Type::Ptr(Box::new(ir::Ty::new(ir::Type::Tuple(vec![ir::Ty::new(int); count])))));
+    let ptr = ir::Ty::new(ir::Type::Ptr(Box::new(ir::Ty::new(ir::Type::U8))));
+    let info = ir::Ty::new(ir::Type::Ptr(Box::new(ir::Ty::new(ir::Type::Type(String::new())))));
+    let params = vec![ptr.clone(), ptr, info];
+    let decl = module.decls.next_idx();
+
+    module.decls.insert(
+        decl,
+        ir::Decl {
+            id: decl,
+            name: format!("__copy_{}_{}", size, chunk),
+            linkage: ir::Linkage::Local,
+            ty: ir::Ty::new(ir::Type::Func(ir::Signature {
+                params: params.clone(),
+                rets: Vec::new(),
+                call_conv: ir::CallConv::Fluix,
+                variadic: false,
+            })),
+            attrs: ir::Attrs::default(),
+        },
+    );
+
+    let bodyid = module.bodies.next_idx();
+    let mut body = ir::Body::new(bodyid, decl);
+    let mut builder = ir::Builder::new(&mut body);
+    let args = params.into_iter().map(|p| builder.create_arg(p)).collect::<Vec<_>>();
+    let dst = builder.create_tmp(chunks.clone());
+    let src = builder.create_tmp(chunks.clone());
+    let entry = builder.create_block();
+
+    builder.set_block(entry);
+    builder.cast(ir::Place::new(dst), ir::Place::new(args[0]), chunks.clone());
+    builder.cast(ir::Place::new(src), ir::Place::new(args[1]), chunks);
+
+    for i in 0..count {
+        let val = ir::Operand::Place(ir::Place::new(src).deref().field(i));
+
+        builder.use_op(ir::Place::new(dst).deref().field(i), val);
+    }
+
+    builder.return_();
+    module.bodies.insert(bodyid, body);
+
+    decl
+}
+
+#[derive(Clone, Copy)]
+enum GlueKind {
+    /// Copies the value and increments the strong count of its boxes.
+    Copy,
+    /// Copies the value without touching its boxes, as the source is not used afterwards.
+    Move,
+    /// Frees the boxes of the value.
+    Drop,
+}
+
+/// Defines a witness function of `ty`, which contains boxes. The functions take the same arguments
+/// as the witnesses of trivial types.
+fn gen_glue(module: &mut ir::Module, ty: &ir::Ty, kind: GlueKind) -> ir::DeclId {
+    let ptr = ir::Ty::new(ir::Type::Ptr(Box::new(ir::Ty::new(ir::Type::U8))));
+    let info = ir::Ty::new(ir::Type::Ptr(Box::new(ir::Ty::new(ir::Type::Type(String::new())))));
+    let params = match kind {
+        GlueKind::Drop => vec![ptr, info],
+        _ => vec![ptr.clone(), ptr, info],
+    };
+
+    let decl = module.decls.next_idx();
+    let name = match kind {
+        GlueKind::Copy => "copy",
+        GlueKind::Move => "move",
+        GlueKind::Drop => "drop",
+    };
+
+    module.decls.insert(
+        decl,
+        ir::Decl {
+            id: decl,
+            name: format!("__{}_glue_{}", name, decl.index()),
+            linkage: ir::Linkage::Local,
+            ty: ir::Ty::new(ir::Type::Func(ir::Signature {
+                params: params.clone(),
+                rets: Vec::new(),
+                call_conv: ir::CallConv::Fluix,
+                variadic: false,
+            })),
+            attrs: ir::Attrs::default(),
+        },
+    );
+
+    let bodyid = module.bodies.next_idx();
+    let mut body = ir::Body::new(bodyid, decl);
+    let mut builder = ir::Builder::new(&mut body);
+    let ty_ptr = ir::Ty::new(ir::Type::Ptr(Box::new(ty.clone())));
+    let args = params.into_iter().map(|p| builder.create_arg(p)).collect::<Vec<_>>();
+    let dst = builder.create_tmp(ty_ptr.clone());
+    let entry = builder.create_block();
+
+    builder.set_block(entry);
+    builder.cast(ir::Place::new(dst), ir::Place::new(args[0]), ty_ptr.clone());
+
+    match kind {
+        GlueKind::Drop => visit_boxes(&mut builder, ir::Place::new(dst).deref(), ty, "box_free"),
+        _ => {
+            let src = builder.create_tmp(ty_ptr.clone());
+
+            builder.cast(ir::Place::new(src), ir::Place::new(args[1]), ty_ptr);
+            builder.use_op(ir::Place::new(dst).deref(), ir::Operand::Place(ir::Place::new(src).deref()));
+
+            if let GlueKind::Copy = kind {
+                visit_boxes(&mut builder, ir::Place::new(dst).deref(), ty, "box_copy");
+            }
+        }
+    }
+
+    builder.return_();
+    module.bodies.insert(bodyid, body);
+
+    decl
+}
+
+/// Calls the box intrinsic `name` on every box in `place`, switching on the discriminant of tagged unions.
+/// The active field of an untagged union is not known, so the boxes in it are skipped.
+fn visit_boxes(builder: &mut ir::Builder, place: ir::Place, ty: &ir::Ty, name: &str) {
+    match &ty.kind {
+        ir::Type::Newtype(_, inner) => visit_boxes(builder, place, inner, name),
+        ir::Type::Box(_) => {
+            let tmp = builder.create_tmp(ir::Ty::new(ir::Type::Tuple(Vec::new())));
+
+            builder.intrinsic(ir::Place::new(tmp), name, vec![ir::Operand::Place(place)]);
+        }
+        ir::Type::Tuple(tys) => {
+            for (i, ty) in tys.iter().enumerate() {
+                if !is_trivial(ty) {
+                    visit_boxes(builder, place.clone().field(i), ty, name);
+                }
+            }
+        }
+        ir::Type::Tagged(tys) => {
+            let tag = builder.get_tag(place.clone());
+            let next = builder.create_block();
+            let mut blocks = Vec::with_capacity(tys.len() + 1);
+
+            for (i, ty) in tys.iter().enumerate() {
+                if is_trivial(ty) {
+                    blocks.push(next);
+                } else {
+                    let block = builder.create_block();
+                    let prev = builder.get_block();
+
+                    builder.set_block(block);
+                    visit_boxes(builder, place.clone().downcast(i), ty, name);
+                    builder.jump(next);
+                    builder.set_block(prev);
+                    blocks.push(block);
+                }
+            }
+
+            blocks.push(next);
+            builder.switch(ir::Operand::Place(tag), (0..tys.len() as u128).collect(), blocks);
+            builder.set_block(next);
+        }
+        _ => {}
     }
 }
+
+/// Declares a witness function. Drop witnesses take the value and its type info,
+/// copy and move witnesses take the destination first.
+fn gen_witness(module: &mut ir::Module, witnesses: Witnesses, name: &str, kind: WitnessKind) -> ir::DeclId {
+    let ptr = ir::Ty::new(ir::Type::Ptr(Box::new(ir::Ty::new(ir::Type::U8))));
+    let info = ir::Ty::new(ir::Type::Ptr(Box::new(ir::Ty::new(ir::Type::Type(String::new())))));
+    let params = match kind {
+        WitnessKind::Drop => vec![ptr, info],
+        _ => vec![ptr.clone(), ptr, info],
+    };
+
+    let decl = module.decls.next_idx();
+
+    module.decls.insert(
+        decl,
+        ir::Decl {
+            id: decl,
+            name: name.into(),
+            linkage: match witnesses {
+                Witnesses::Import => ir::Linkage::Import,
+                Witnesses::Define => ir::Linkage::Local,
+            },
+            ty: ir::Ty::new(ir::Type::Func(ir::Signature {
+                params: params.clone(),
+                rets: Vec::new(),
+                call_conv: ir::CallConv::Fluix,
+                variadic: false,
+            })),
+            attrs: ir::Attrs::default(),
+        },
+    );
+
+    if witnesses == Witnesses::Define {
+        let bodyid = module.bodies.next_idx();
+        let mut body = ir::Body::new(bodyid, decl);
+        let mut builder = ir::Builder::new(&mut body);
+        let args = params.into_iter().map(|p| builder.create_arg(p)).collect::<Vec<_>>();
+        let entry = builder.create_block();
+
+        builder.set_block(entry);
+
+        if let WitnessKind::Copy = kind {
+            let tmp = builder.create_tmp(ir::Ty::new(ir::Type::Tuple(Vec::new())));
+            let size = ir::Place::new(args[2]).deref().field(0);
+
+            builder.intrinsic(ir::Place::new(tmp), "memcpy", vec![
+                ir::Operand::Place(ir::Place::new(args[0])),
+                ir::Operand::Place(ir::Place::new(args[1])),
+                ir::Operand::Place(size),
+            ]);
+        }
+
+        builder.return_();
+        module.bodies.insert(bodyid, body);
+    }
+
+    decl
+}
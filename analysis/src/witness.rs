@@ -81,7 +81,7 @@ impl VisitorMut for WitnessTransform {
     }
 
     fn visit_stmt(&mut self, stmt: &mut ir::Stmt, _loc: ir::Location) {
-        if let ir::Stmt::Call(_, func, args) = stmt {
+        if let ir::Stmt::Call(_, func, args, _) = stmt {
             let func_ty = ir::operand_type(self.module(), self.body(), func);
 
             if let ir::Type::Func(ir::Signature { params, .. }) = func_ty.kind {
@@ -106,7 +106,7 @@ impl VisitorMut for WitnessTransform {
     }
 
     fn visit_type(&mut self, ty: &mut ir::Ty) {
-        if let ir::Type::Func(ir::Signature { params, rets }) = &mut ty.kind {
+        if let ir::Type::Func(ir::Signature { params, rets, .. }) = &mut ty.kind {
             let mut gen = IndexSet::new();
 
             for ty in params.iter_mut() {
@@ -144,7 +144,7 @@ fn collect_generic(ty: &ir::Ty, gen: &mut IndexSet<String>) {
             }
         }
         ir::Type::Ptr(to) => collect_generic(to, gen),
-        ir::Type::Func(ir::Signature { params, rets }) => {
+        ir::Type::Func(ir::Signature { params, rets, .. }) => {
             for ty in params {
                 collect_generic(ty, gen);
             }
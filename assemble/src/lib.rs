@@ -1,10 +1,117 @@
+#[cfg(feature = "cranelift")]
+pub mod validate;
+
 #[cfg(feature = "cranelift")]
 pub fn assemble(
     module: &ir::Module,
     target: target_lexicon::Triple,
 ) -> codegen::obj_file::ObjectFile {
     let backend = codegen_cranelift::ClifBackend::new();
-    let mcx = codegen::ModuleCtx::new(module, target, backend);
+    let mcx = codegen::ModuleCtx::new(module, target, backend).unwrap_or_else(|e| panic!("{}", e));
 
     mcx.build()
 }
+
+/// Assembles `module` for a target other than the default one.
+#[cfg(feature = "cranelift")]
+pub fn assemble_for(
+    module: &ir::Module,
+    spec: &codegen_cranelift::target::TargetSpec,
+) -> Result<codegen::obj_file::ObjectFile, codegen_cranelift::target::TargetError> {
+    let backend = codegen_cranelift::ClifBackend::with_target(spec)?;
+    let mcx = codegen::ModuleCtx::new(module, spec.triple.clone(), backend).unwrap_or_else(|e| panic!("{}", e));
+
+    Ok(mcx.build())
+}
+
+#[cfg(feature = "cranelift")]
+pub fn assemble_with(
+    module: &ir::Module,
+    target: target_lexicon::Triple,
+    options: codegen::CodegenOptions,
+    progress: &mut dyn ir::progress::Progress,
+    cancel: &ir::progress::CancellationToken,
+) -> Result<codegen::obj_file::ObjectFile, codegen::CodegenError> {
+    let backend = codegen_cranelift::ClifBackend::new();
+    let mcx = codegen::ModuleCtx::with_options(module, target, backend, options)?;
+
+    mcx.build_with(progress, cancel)
+}
+
+/// Compiles `module` in memory and runs its `main` function, which must have the signature `() -> (i32)`.
+#[cfg(feature = "cranelift")]
+pub fn run_jit(module: &ir::Module) -> Result<i32, codegen_cranelift::jit::JitError> {
+    let jit = codegen_cranelift::jit::JitBackend::new().load(module)?;
+
+    jit.run_main().ok_or_else(|| codegen_cranelift::jit::JitError::UndefinedSymbol(String::from("main")))
+}
+
+/// Lists what `module` uses that the default backend can not lower for `target`.
+#[cfg(feature = "cranelift")]
+pub fn supports(module: &ir::Module, target: &target_lexicon::Triple) -> codegen::support::SupportReport {
+    use codegen::Backend;
+
+    codegen_cranelift::ClifBackend::new().supports(module, target)
+}
+
+/// Starts a compilation that can be cancelled and later resumed.
+#[cfg(feature = "cranelift")]
+pub fn session<'ir, 'ctx>(
+    module: &'ir ir::Module,
+    target: target_lexicon::Triple,
+) -> Result<codegen::session::Session<'ir, 'ctx, codegen_cranelift::ClifBackend<'ctx>>, codegen::CodegenError> {
+    let backend = codegen_cranelift::ClifBackend::new();
+    let mcx = codegen::ModuleCtx::new(module, target, backend)?;
+
+    codegen::session::Session::new(mcx)
+}
+
+/// Compiles the function `name` of `module` on its own, returning its machine code and relocations.
+/// The other declarations of the module are only declared, so no object file is built.
+#[cfg(feature = "cranelift")]
+pub fn compile_function(
+    module: &ir::Module,
+    target: target_lexicon::Triple,
+    name: &str,
+) -> Result<codegen::artifact::FuncArtifact, codegen::CodegenError> {
+    let decl = module.decls.iter().find(|d| d.name == name).ok_or_else(|| codegen::CodegenError::InvalidBody {
+        func: name.to_string(),
+        block: None,
+        message: String::from("the function is not declared"),
+    })?;
+
+    session(module, target)?.compile_function(decl.id)
+}
+
+/// The target of the wasm backend.
+#[cfg(feature = "wasm")]
+pub fn wasm_target() -> target_lexicon::Triple {
+    "wasm32-unknown-unknown".parse().unwrap()
+}
+
+/// Compiles `module` to a WebAssembly module for `wasm32-unknown-unknown`.
+#[cfg(feature = "wasm")]
+pub fn assemble_wasm(
+    module: &ir::Module,
+    options: codegen::CodegenOptions,
+    progress: &mut dyn ir::progress::Progress,
+    cancel: &ir::progress::CancellationToken,
+) -> Result<codegen::obj_file::ObjectFile, codegen::CodegenError> {
+    let backend = codegen_wasm::WasmBackend::new();
+    let mcx = codegen::ModuleCtx::with_options(module, wasm_target(), backend, options)?;
+
+    mcx.build_with(progress, cancel)
+}
+
+/// Lists what `module` uses that the wasm backend can not lower.
+#[cfg(feature = "wasm")]
+pub fn supports_wasm(module: &ir::Module) -> codegen::support::SupportReport {
+    use codegen::Backend;
+
+    codegen_wasm::WasmBackend::new().supports(module, &wasm_target())
+}
+
+/// Generates a C header for the functions and globals of `module` that can be used from C.
+pub fn c_header(module: &ir::Module, target: &target_lexicon::Triple, guard: &str) -> String {
+    codegen::header::c_header(module, target, guard)
+}
@@ -0,0 +1,299 @@
+//! Translation validation: runs the functions of a module in the interpreter and compiled in memory
+//! on generated arguments, and checks that they return the same.
+
+use codegen_cranelift::jit::{JitBackend, JitError};
+use eval::log::{Access, AccessKind, Owner};
+use ir::rand::SplitMix64;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// How many argument sets to try for every function and how to generate them.
+#[derive(Debug, Clone, Copy)]
+pub struct ValidateOptions {
+    /// The number of argument sets to run every function with.
+    pub sets: usize,
+    pub seed: u64,
+    /// The number of statements and terminators the interpreter may run for one set. Sets that need more,
+    /// like those that never return, are skipped.
+    pub fuel: u64,
+}
+
+impl Default for ValidateOptions {
+    fn default() -> Self {
+        ValidateOptions {
+            sets: 16,
+            seed: 0,
+            fuel: 100_000,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ValidateError {
+    Jit(JitError),
+    /// The compiled function returned something else than the interpreter for the same arguments,
+    /// or trapped if `jit` is `None`.
+    Mismatch { func: String, args: Vec<u64>, eval: u64, jit: Option<u64> },
+}
+
+/// Runs every exported function of `source` that only takes and returns integers and booleans in the interpreter,
+/// and the function of the same name in `compiled` as compiled with `options`, returning how many functions
+/// were compared. `compiled` is normally `source` after the passes, so this checks those as well.
+///
+/// Compiled functions are called as C functions of six integers, which is only sound for functions with the C calling
+/// convention on x86_64 and aarch64 hosts, so only those are compared and nothing is on other hosts.
+///
+/// Argument sets for which the interpreter fails, aborts, runs out of fuel or calls an import are skipped,
+/// because compiled code would trap or have effects there. So are functions that write to globals, since the
+/// compiled module keeps what earlier calls wrote while the interpreter starts over for every set. On unix,
+/// compiled functions run in a child process, so that one that traps where the interpreter did not is a mismatch
+/// instead of ending the process.
+pub fn validate(
+    source: &ir::Module,
+    compiled: &ir::Module,
+    options: codegen::CodegenOptions,
+    settings: ValidateOptions,
+) -> Result<usize, ValidateError> {
+    let target = target_lexicon::Triple::host();
+
+    if !matches!(target.architecture, target_lexicon::Architecture::X86_64 | target_lexicon::Architecture::Aarch64(_)) {
+        return Ok(0);
+    }
+
+    let jit = JitBackend::new().with_options(options).load(compiled).map_err(ValidateError::Jit)?;
+    let mut gen = ArgGen { rng: SplitMix64::new(settings.seed) };
+    let mut checked = 0;
+
+    for body in &source.bodies {
+        let decl = &source.decls[body.decl];
+        let (params, ret) = match testable(&decl.ty) {
+            // the passes may specialize other functions for the calls in the module
+            Some(sig) if decl.linkage == ir::Linkage::Export => sig,
+            _ => continue,
+        };
+
+        let func = match jit.get(&decl.name) {
+            Some(func) => func,
+            None => continue,
+        };
+
+        let mut compared = false;
+
+        for _ in 0..settings.sets {
+            let args = params.iter().map(|ty| gen.arg(ty)).collect::<Vec<_>>();
+            let expected = match interpret(source, body, &args, &params, &target, settings.fuel) {
+                Outcome::Returned(val) => truncate(val, &ret),
+                Outcome::Skipped => continue,
+                Outcome::Impure => break,
+            };
+
+            let mut regs = [0; 6];
+
+            regs[..args.len()].copy_from_slice(&args);
+
+            let actual = call(func, regs).map(|val| truncate(val, &ret));
+
+            if actual != Some(expected) {
+                return Err(ValidateError::Mismatch {
+                    func: decl.name.clone(),
+                    args,
+                    eval: expected,
+                    jit: actual,
+                });
+            }
+
+            compared = true;
+        }
+
+        checked += compared as usize;
+    }
+
+    Ok(checked)
+}
+
+/// Calls the compiled function at `func` with the integer registers `regs` in a child process,
+/// returning what it returned or `None` if it trapped.
+#[cfg(unix)]
+fn call(func: *const u8, regs: [u64; 6]) -> Option<u64> {
+    let mut fds = [0; 2];
+
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Some(call_here(func, regs));
+    }
+
+    let pid = unsafe { libc::fork() };
+
+    if pid < 0 {
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+
+        return Some(call_here(func, regs));
+    }
+
+    if pid == 0 {
+        let val = call_here(func, regs).to_ne_bytes();
+
+        unsafe {
+            libc::write(fds[1], val.as_ptr() as *const libc::c_void, val.len());
+            libc::_exit(0);
+        }
+    }
+
+    let mut val = [0u8; 8];
+    let mut read = 0;
+    let mut status = 0;
+
+    unsafe {
+        libc::close(fds[1]);
+
+        while read < val.len() {
+            match libc::read(fds[0], val[read..].as_mut_ptr() as *mut libc::c_void, val.len() - read) {
+                n if n > 0 => read += n as usize,
+                _ => break,
+            }
+        }
+
+        libc::close(fds[0]);
+        libc::waitpid(pid, &mut status, 0);
+    }
+
+    if read == val.len() && libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0 {
+        Some(u64::from_ne_bytes(val))
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+fn call(func: *const u8, regs: [u64; 6]) -> Option<u64> {
+    Some(call_here(func, regs))
+}
+
+fn call_here(func: *const u8, regs: [u64; 6]) -> u64 {
+    // the integer arguments of C functions are passed in registers on the hosts that get here,
+    // so passing more than the function takes is harmless
+    let func: extern "C" fn(u64, u64, u64, u64, u64, u64) -> u64 = unsafe { std::mem::transmute(func) };
+
+    func(regs[0], regs[1], regs[2], regs[3], regs[4], regs[5])
+}
+
+enum Outcome {
+    Returned(u64),
+    Skipped,
+    /// The function wrote to a global, so it can not be compared at all.
+    Impure,
+}
+
+fn interpret(
+    module: &ir::Module,
+    body: &ir::Body,
+    args: &[u64],
+    params: &[ir::Ty],
+    target: &target_lexicon::Triple,
+    fuel: u64,
+) -> Outcome {
+    let args = args.iter().zip(params).map(|(arg, ty)| ir::Const::Scalar(*arg as u128, ty.clone())).collect();
+    let log = Rc::new(RefCell::new(Vec::<Access>::new()));
+    let mut ctx = eval::EvalCtx::new(module, body, target)
+        .with_args(args)
+        .with_meter(Rc::new(RefCell::new(eval::meter::Fuel::new(fuel))))
+        .with_access_log(log.clone());
+
+    let result = ctx.eval();
+
+    if log.borrow().iter().any(|a| a.kind == AccessKind::Write && matches!(a.owner, Owner::Global(_))) {
+        return Outcome::Impure;
+    }
+
+    if result.is_err() || ctx.aborted() {
+        return Outcome::Skipped;
+    }
+
+    match ctx.finish().as_slice() {
+        [ir::Const::Scalar(val, _)] => Outcome::Returned(*val as u64),
+        _ => Outcome::Skipped,
+    }
+}
+
+/// Returns the parameters and the return type of functions that can be called with integers in registers
+/// using the default calling convention of the host.
+fn testable(ty: &ir::Ty) -> Option<(Vec<ir::Ty>, ir::Ty)> {
+    let sig = match &ty.kind {
+        ir::Type::Func(sig) if sig.call_conv == ir::CallConv::C => sig,
+        _ => return None,
+    };
+
+    match sig.rets.as_slice() {
+        [ret] if !sig.variadic && sig.params.len() <= 6 && sig.params.iter().chain(&sig.rets).all(is_int) => Some((sig.params.clone(), ret.clone())),
+        _ => None,
+    }
+}
+
+fn is_int(ty: &ir::Ty) -> bool {
+    bits(ty).is_some()
+}
+
+fn bits(ty: &ir::Ty) -> Option<u32> {
+    match ty.kind {
+        ir::Type::Bool | ir::Type::U8 | ir::Type::I8 => Some(8),
+        ir::Type::U16 | ir::Type::I16 => Some(16),
+        ir::Type::U32 | ir::Type::I32 => Some(32),
+        ir::Type::U64 | ir::Type::I64 => Some(64),
+        _ => None,
+    }
+}
+
+/// Keeps the bits of `val` that are part of a value of `ty`, the rest of a register is undefined.
+fn truncate(val: u64, ty: &ir::Ty) -> u64 {
+    match bits(ty) {
+        Some(64) | None => val,
+        Some(bits) => val & ((1 << bits) - 1),
+    }
+}
+
+/// Generates arguments, mostly the small numbers and extremes that hit edge cases.
+struct ArgGen {
+    rng: SplitMix64,
+}
+
+impl ArgGen {
+    fn arg(&mut self, ty: &ir::Ty) -> u64 {
+        if let ir::Type::Bool = ty.kind {
+            return self.rng.next_u64() & 1;
+        }
+
+        let val = match self.rng.below(4) {
+            0 => self.rng.below(33).wrapping_sub(16),
+            1 => [0, 1, u64::MAX, i64::MAX as u64, i64::MIN as u64][self.rng.below(5) as usize],
+            _ => self.rng.next_u64(),
+        };
+
+        // signed extremes of narrower types are the bits of the 64 bit ones that fit
+        let val = match (bits(ty), self.rng.below(3)) {
+            (Some(bits), 0) if bits < 64 => (val as i64 >> (64 - bits)) as u64,
+            _ => val,
+        };
+
+        truncate(val, ty)
+    }
+}
+
+impl std::fmt::Display for ValidateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ValidateError::Jit(e) => write!(f, "could not load the compiled module: {}", e),
+            ValidateError::Mismatch { func, args, eval, jit } => {
+                let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+                match jit {
+                    Some(jit) => write!(f, "@{}({}) returned {} in the interpreter and {} compiled", func, args.join(", "), eval, jit),
+                    None => write!(f, "@{}({}) returned {} in the interpreter and trapped compiled", func, args.join(", "), eval),
+                }
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidateError {}
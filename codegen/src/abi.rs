@@ -1,6 +1,8 @@
+pub mod c;
 pub mod pass;
 pub mod ret;
 
 use super::*;
+pub use c::*;
 pub use pass::*;
 pub use ret::*;
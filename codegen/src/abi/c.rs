@@ -0,0 +1,271 @@
+use super::*;
+use ir::layout::{Abi, Primitive, Scalar, Size, TyLayout, Variants};
+
+/// The rules for passing aggregates that apply to a calling convention on some target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateRules {
+    /// The rules of `get_pass_mode`, which are only used between lowlang functions.
+    Default,
+    /// The System V x86_64 ABI.
+    SysV,
+    /// The Windows x64 ABI.
+    Win64,
+    /// The procedure call standard for aarch64.
+    Aapcs,
+    /// The C ABI of WebAssembly as implemented by clang.
+    Wasm,
+}
+
+/// The class of an eightbyte of an aggregate, as defined by the System V x86_64 ABI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Class {
+    Int,
+    Sse,
+}
+
+impl AggregateRules {
+    pub fn for_conv(conv: ir::CallConv, target: &target_lexicon::Triple) -> Self {
+        match conv {
+            ir::CallConv::Fluix | ir::CallConv::Fastcall => AggregateRules::Default,
+            ir::CallConv::Win64 => AggregateRules::Win64,
+            ir::CallConv::Aapcs => AggregateRules::Aapcs,
+            ir::CallConv::C => match (target.architecture, target.operating_system) {
+                (target_lexicon::Architecture::Wasm32, _) | (target_lexicon::Architecture::Wasm64, _) => AggregateRules::Wasm,
+                (_, target_lexicon::OperatingSystem::Windows) => AggregateRules::Win64,
+                (target_lexicon::Architecture::Aarch64(_), _) => AggregateRules::Aapcs,
+                _ => AggregateRules::SysV,
+            },
+        }
+    }
+
+    /// Whether a function returning in memory also returns the pointer it was given.
+    pub fn returns_sret_ptr(self) -> bool {
+        self == AggregateRules::SysV || self == AggregateRules::Win64
+    }
+}
+
+/// Returns how a value is passed to or returned from a function following the System V x86_64 ABI.
+///
+/// Aggregates of up to 16 bytes are split into one or two registers according to their fields,
+/// larger ones are passed in memory.
+pub fn get_sysv_pass_mode<'ctx, B: Backend<'ctx>>(mcx: &ModuleCtx<'_, 'ctx, B>, layout: &TyLayout) -> PassMode<'ctx, B> {
+    if layout.is_zst() {
+        return PassMode::NoPass;
+    }
+
+    match &layout.abi {
+        Abi::Uninhabited => PassMode::NoPass,
+        Abi::Scalar(scalar) => get_scalar_pass_mode(mcx, scalar),
+        Abi::Aggregate { sized: false } => PassMode::ByRef { size: None },
+        _ if layout.size.bytes() > 16 => PassMode::ByRef { size: Some(layout.size) },
+        Abi::ScalarPair(a, b) if a.value.size(&mcx.target).bytes() == 8 && b.value.size(&mcx.target).bytes() == 8 => {
+            PassMode::ByValPair(mcx.scalar_ty(a), mcx.scalar_ty(b))
+        }
+        _ => {
+            let mut classes = [None; 2];
+            let size = layout.size.bytes();
+
+            classify(layout, Size::ZERO, &mut classes, &mcx.target);
+
+            let first = reg_ty(mcx, classes[0], size.min(8));
+            let second = if size > 8 { Some(reg_ty(mcx, classes[1], size - 8)) } else { None };
+
+            PassMode::Cast(first, second)
+        }
+    }
+}
+
+/// Returns how a value is passed to or returned from a function following the Windows x64 ABI.
+///
+/// Aggregates of exactly 1, 2, 4 or 8 bytes are passed in an integer register,
+/// all others are passed as a pointer to a copy.
+pub fn get_win64_pass_mode<'ctx, B: Backend<'ctx>>(mcx: &ModuleCtx<'_, 'ctx, B>, layout: &TyLayout) -> PassMode<'ctx, B> {
+    if layout.is_zst() {
+        return PassMode::NoPass;
+    }
+
+    match &layout.abi {
+        Abi::Uninhabited => PassMode::NoPass,
+        // 128-bit integers are passed as a pointer to a copy like other aggregates of 16 bytes
+        Abi::Scalar(scalar) if scalar.value.size(&mcx.target).bytes() == 16 => PassMode::ByRef { size: Some(layout.size) },
+        Abi::Scalar(scalar) => PassMode::ByVal(mcx.scalar_ty(scalar)),
+        Abi::Aggregate { sized: false } => PassMode::ByRef { size: None },
+        _ => match layout.size.bytes() {
+            size @ 1 | size @ 2 | size @ 4 | size @ 8 => PassMode::Cast(reg_ty(mcx, Some(Class::Int), size), None),
+            _ => PassMode::ByRef { size: Some(layout.size) },
+        },
+    }
+}
+
+/// Returns how a value is passed to or returned from a function following the aarch64 procedure call standard.
+///
+/// Aggregates of up to two floats of the same type are passed in float registers, other aggregates
+/// of up to 16 bytes in one or two integer registers and larger ones as a pointer to a copy.
+pub fn get_aapcs_pass_mode<'ctx, B: Backend<'ctx>>(mcx: &ModuleCtx<'_, 'ctx, B>, layout: &TyLayout) -> PassMode<'ctx, B> {
+    if layout.is_zst() {
+        return PassMode::NoPass;
+    }
+
+    match &layout.abi {
+        Abi::Uninhabited => PassMode::NoPass,
+        Abi::Scalar(scalar) => get_scalar_pass_mode(mcx, scalar),
+        Abi::Aggregate { sized: false } => PassMode::ByRef { size: None },
+        _ => {
+            if let Some((float, count)) = homogeneous_float_aggregate(layout, &mcx.target) {
+                let ty = mcx.scalar_ty(&Scalar::unit(float, &mcx.target));
+
+                match count {
+                    1 => return PassMode::Cast(ty, None),
+                    2 => return PassMode::Cast(ty, Some(ty)),
+                    _ => unreachable!("homogeneous float aggregates of {} members are rejected by `check_signature`", count),
+                }
+            }
+
+            match layout.size.bytes() {
+                size if size <= 8 => PassMode::Cast(reg_ty(mcx, Some(Class::Int), size), None),
+                size if size <= 16 => PassMode::Cast(reg_ty(mcx, Some(Class::Int), 8), Some(reg_ty(mcx, Some(Class::Int), size - 8))),
+                _ => PassMode::ByRef { size: Some(layout.size) },
+            }
+        }
+    }
+}
+
+/// Returns how a value is passed to or returned from a function following the C ABI of WebAssembly.
+///
+/// Scalars are passed as they are, with 128-bit integers split into two 64-bit halves.
+/// Every other aggregate is passed as a pointer to a copy and returned through a pointer passed by the caller.
+pub fn get_wasm_pass_mode<'ctx, B: Backend<'ctx>>(mcx: &ModuleCtx<'_, 'ctx, B>, layout: &TyLayout) -> PassMode<'ctx, B> {
+    if layout.is_zst() {
+        return PassMode::NoPass;
+    }
+
+    match &layout.abi {
+        Abi::Uninhabited => PassMode::NoPass,
+        Abi::Scalar(scalar) if scalar.value.size(&mcx.target).bytes() == 16 => {
+            let half = int_reg_ty(mcx, 8);
+
+            PassMode::Cast(half, Some(half))
+        }
+        Abi::Scalar(scalar) => PassMode::ByVal(mcx.scalar_ty(scalar)),
+        Abi::Aggregate { sized: false } => PassMode::ByRef { size: None },
+        _ => PassMode::ByRef { size: Some(layout.size) },
+    }
+}
+
+/// Checks that `sig` only passes values in ways the pass modes of its calling convention can express.
+///
+/// Under aapcs, homogeneous float aggregates of three or four members are passed in as many float registers,
+/// and values returned in memory take their pointer in x8 instead of the first argument register.
+/// Neither is implemented, so such signatures are rejected instead of silently disagreeing with C.
+pub fn check_signature(sig: &ir::Signature, target: &target_lexicon::Triple) -> Result<(), String> {
+    if AggregateRules::for_conv(sig.call_conv, target) != AggregateRules::Aapcs {
+        return Ok(());
+    }
+
+    let values = sig.params.iter().map(|ty| (ty, false)).chain(sig.rets.iter().map(|ty| (ty, true)));
+
+    for (ty, is_ret) in values {
+        let layout = ir::layout::layout_of(ty, target);
+
+        if layout.is_zst() || matches!(layout.abi, Abi::Uninhabited | Abi::Scalar(_)) {
+            continue;
+        }
+
+        match homogeneous_float_aggregate(&layout, target) {
+            Some((_, count)) if count > 2 => {
+                return Err(format!(
+                    "{} is a homogeneous float aggregate of {} members, which can not be passed with calling convention {} yet",
+                    ir::display_ty(ty),
+                    count,
+                    sig.call_conv.name()
+                ))
+            }
+            Some(_) => {}
+            None if is_ret && (matches!(layout.abi, Abi::Aggregate { sized: false }) || layout.size.bytes() > 16) => {
+                return Err(format!(
+                    "{} is returned in memory, which is not supported with calling convention {} yet",
+                    ir::display_ty(ty),
+                    sig.call_conv.name()
+                ))
+            }
+            None => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the type and number of members of an aggregate that consists of up to four floats of the same type.
+fn homogeneous_float_aggregate(layout: &TyLayout, target: &target_lexicon::Triple) -> Option<(Primitive, usize)> {
+    let mut floats = Vec::new();
+
+    if homogeneous_floats(layout, &mut floats, target) && (1..=4).contains(&floats.len()) {
+        Some((floats[0], floats.len()))
+    } else {
+        None
+    }
+}
+
+fn classify(layout: &TyLayout, offset: Size, classes: &mut [Option<Class>; 2], target: &target_lexicon::Triple) {
+    if let Abi::Scalar(scalar) = &layout.abi {
+        let class = match scalar.value {
+            Primitive::F32 | Primitive::F64 => Class::Sse,
+            _ => Class::Int,
+        };
+
+        mark(classes, offset, scalar.value.size(target), class);
+    } else if let Variants::Multiple { .. } = layout.variants {
+        // there is nothing like a tagged union in C, so treat it like an array of bytes
+        mark(classes, offset, layout.size, Class::Int);
+    } else {
+        for i in 0..layout.fields.count() {
+            classify(&layout.field(i, target), offset + layout.fields.offset(i), classes, target);
+        }
+    }
+}
+
+fn mark(classes: &mut [Option<Class>; 2], offset: Size, size: Size, class: Class) {
+    if size.bytes() == 0 {
+        return;
+    }
+
+    for eightbyte in offset.bytes() / 8..=(offset.bytes() + size.bytes() - 1) / 8 {
+        let slot = &mut classes[eightbyte as usize];
+
+        // an eightbyte that contains both integers and floats is passed in an integer register
+        *slot = match *slot {
+            Some(Class::Int) => Some(Class::Int),
+            _ => Some(class),
+        };
+    }
+}
+
+/// Collects the floats of an aggregate, returning false if it contains anything else or floats of different sizes.
+fn homogeneous_floats(layout: &TyLayout, floats: &mut Vec<Primitive>, target: &target_lexicon::Triple) -> bool {
+    match &layout.abi {
+        Abi::Scalar(Scalar { value: value @ Primitive::F32, .. }) | Abi::Scalar(Scalar { value: value @ Primitive::F64, .. }) => {
+            floats.push(*value);
+            floats[0] == *value
+        }
+        Abi::Scalar(_) => false,
+        _ if layout.fields.count() == 0 || !matches!(layout.variants, Variants::Single { .. }) => false,
+        _ => {
+            let elem = layout.size.bytes() / layout.fields.count() as u64;
+
+            // padding between the members is not allowed
+            (0..layout.fields.count()).all(|i| {
+                let field = layout.field(i, target);
+
+                field.size.bytes() == elem && homogeneous_floats(&field, floats, target)
+            })
+        }
+    }
+}
+
+fn reg_ty<'ctx, B: Backend<'ctx>>(mcx: &ModuleCtx<'_, 'ctx, B>, class: Option<Class>, bytes: u64) -> <B::Type as Type<'ctx>>::Raw {
+    match class {
+        Some(Class::Sse) if bytes <= 4 => mcx.scalar_ty(&Scalar::unit(Primitive::F32, &mcx.target)),
+        Some(Class::Sse) => mcx.scalar_ty(&Scalar::unit(Primitive::F64, &mcx.target)),
+        _ => int_reg_ty(mcx, bytes),
+    }
+}
@@ -1,11 +1,14 @@
 use crate::*;
-use ir::layout::{Abi, Size, TyLayout};
+use ir::layout::{Abi, Integer, Primitive, Scalar, Size, TyLayout};
 
 #[derive(Clone, Copy)]
 pub enum PassMode<'ctx, B: Backend<'ctx>> {
     NoPass,
     ByVal(<B::Type as Type<'ctx>>::Raw),
     ByValPair(<B::Type as Type<'ctx>>::Raw, <B::Type as Type<'ctx>>::Raw),
+    /// An aggregate that is stored to memory and reloaded as one or two registers of these types,
+    /// the second register starting right after the first.
+    Cast(<B::Type as Type<'ctx>>::Raw, Option<<B::Type as Type<'ctx>>::Raw>),
     ByRef { size: Option<Size> },
 }
 
@@ -25,6 +28,7 @@ where
             PassMode::NoPass => write!(f, "NoPass"),
             PassMode::ByVal(ty) => write!(f, "ByVal({:?})", ty),
             PassMode::ByValPair(a, b) => write!(f, "ByValPair({:?}, {:?})", a, b),
+            PassMode::Cast(a, b) => write!(f, "Cast({:?}, {:?})", a, b),
             PassMode::ByRef { size } => write!(f, "ByRef {{ size: {:?} }}", size),
         }
     }
@@ -103,6 +107,33 @@ pub fn get_pass_mode<'ctx, B: Backend<'ctx>>(
     }
 }
 
+/// Integers twice the size of a pointer are passed in two integer registers, like the C conventions of
+/// 64-bit targets pass `__int128`, since not every backend can pass them on their own.
+pub fn get_scalar_pass_mode<'ctx, B: Backend<'ctx>>(mcx: &ModuleCtx<'_, 'ctx, B>, scalar: &Scalar) -> PassMode<'ctx, B> {
+    match scalar.value {
+        Primitive::Int(Integer::I128, _) if Integer::ptr_sized(&mcx.target) == Integer::I64 => {
+            let half = super::ret::int_reg_ty(mcx, 8);
+
+            PassMode::Cast(half, Some(half))
+        }
+        _ => PassMode::ByVal(mcx.scalar_ty(scalar)),
+    }
+}
+
+/// Returns how a value is passed to or returned from a function with the calling convention `conv`.
+pub fn get_pass_mode_for<'ctx, B: Backend<'ctx>>(mcx: &ModuleCtx<'_, 'ctx, B>, layout: &TyLayout, conv: ir::CallConv) -> PassMode<'ctx, B> {
+    match super::AggregateRules::for_conv(conv, &mcx.target) {
+        super::AggregateRules::Default => match &layout.abi {
+            Abi::Scalar(scalar) => get_scalar_pass_mode(mcx, scalar),
+            _ => get_pass_mode(mcx, layout),
+        },
+        super::AggregateRules::SysV => super::get_sysv_pass_mode(mcx, layout),
+        super::AggregateRules::Win64 => super::get_win64_pass_mode(mcx, layout),
+        super::AggregateRules::Aapcs => super::get_aapcs_pass_mode(mcx, layout),
+        super::AggregateRules::Wasm => super::get_wasm_pass_mode(mcx, layout),
+    }
+}
+
 #[macro_export]
 macro_rules! value_for_arg {
     ($fx:ident, $arg:ident, $by_ref:expr) => {
@@ -117,6 +148,7 @@ macro_rules! value_for_arg {
                 $crate::abi::EmptySinglePair::Pair(a, b)
             }
             $crate::abi::PassMode::ByRef { size: _ } => $by_ref,
+            $crate::abi::PassMode::Cast(..) => unreachable!(),
         }
     };
 }
@@ -124,10 +156,11 @@ macro_rules! value_for_arg {
 pub fn value_for_ret<'ctx, B: Backend<'ctx>>(
     fx: &mut FunctionCtx<'_, 'ctx, '_, B>,
     ret: ir::Local,
+    mode: PassMode<'ctx, B>,
 ) -> EmptySinglePair<<B::Value as Value<'ctx>>::Raw> {
     let place = fx.locals[&ret].clone();
 
-    match get_pass_mode(fx.mcx, place.layout()) {
+    match mode {
         PassMode::NoPass => EmptySinglePair::Empty,
         PassMode::ByVal(_) => EmptySinglePair::Single(place.to_value(fx).load_scalar(fx)),
         PassMode::ByValPair(_, _) => {
@@ -136,5 +169,6 @@ pub fn value_for_ret<'ctx, B: Backend<'ctx>>(
             EmptySinglePair::Pair(a, b)
         }
         PassMode::ByRef { size: _ } => EmptySinglePair::Empty,
+        PassMode::Cast(..) => unreachable!(),
     }
 }
@@ -1,5 +1,36 @@
 use super::*;
-use ir::layout::TyLayout;
+use ir::layout::{Abi, Integer, Primitive, Scalar, TyLayout};
+
+/// The registers the lowlang conventions return values in, by register class.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetRegs {
+    pub int: u64,
+    pub float: u64,
+}
+
+impl RetRegs {
+    /// Fluix uses the return registers of the C convention of the target, which on Windows are a single integer
+    /// and float register and two of each everywhere else. Fastcall always has two of each.
+    pub fn for_conv(conv: ir::CallConv, target: &target_lexicon::Triple) -> Self {
+        let windows = target.default_calling_convention() == Ok(target_lexicon::CallingConvention::WindowsFastcall);
+
+        match conv {
+            ir::CallConv::Fluix if windows => RetRegs { int: 1, float: 1 },
+            _ => RetRegs { int: 2, float: 2 },
+        }
+    }
+
+    /// Takes the registers `needed` if they are still free.
+    fn take(&mut self, needed: RetRegs) -> bool {
+        if needed.int <= self.int && needed.float <= self.float {
+            self.int -= needed.int;
+            self.float -= needed.float;
+            true
+        } else {
+            false
+        }
+    }
+}
 
 pub fn can_return_to_ssa_var<'ctx>(
     fx: &FunctionCtx<'_, 'ctx, '_, impl Backend<'ctx>>,
@@ -7,6 +38,81 @@ pub fn can_return_to_ssa_var<'ctx>(
 ) -> bool {
     match get_pass_mode(fx, dest_layout) {
         PassMode::NoPass | PassMode::ByVal(_) | PassMode::ByValPair(_, _) => true,
-        PassMode::ByRef { size: _ } => false,
+        PassMode::Cast(..) | PassMode::ByRef { size: _ } => false,
+    }
+}
+
+/// Returns how each of the return values of a function with the calling convention `conv` is returned.
+///
+/// Under the lowlang conventions the return values are packed into the registers of `RetRegs` in order:
+/// scalars and scalar pairs are returned as they are, floats in float registers and everything else in integer
+/// registers, and other aggregates that fit into the integer registers are loaded into them. A return value
+/// that does not fit into the registers that are left is written to memory instead, through a pointer that is
+/// passed before the parameters. Callers and callees both pick the modes here, so they always agree.
+/// Foreign conventions return every value according to its own pass mode.
+pub fn get_ret_modes<'ctx, B: Backend<'ctx>>(mcx: &ModuleCtx<'_, 'ctx, B>, rets: &[TyLayout], conv: ir::CallConv) -> Vec<PassMode<'ctx, B>> {
+    if AggregateRules::for_conv(conv, &mcx.target) != AggregateRules::Default {
+        return rets.iter().map(|layout| get_pass_mode_for(mcx, layout, conv)).collect();
     }
+
+    let ptr_size = Integer::ptr_sized(&mcx.target).size().bytes();
+    let regs = RetRegs::for_conv(conv, &mcx.target);
+    let regs_for = |scalar: &Scalar| match scalar.value {
+        Primitive::F32 | Primitive::F64 => RetRegs { int: 0, float: 1 },
+        _ => RetRegs {
+            int: (scalar.value.size(&mcx.target).bytes() + ptr_size - 1) / ptr_size,
+            float: 0,
+        },
+    };
+
+    let mut free = regs;
+
+    rets.iter()
+        .map(|layout| {
+            let (mode, needed) = match (get_pass_mode_for(mcx, layout, conv), &layout.abi) {
+                (PassMode::ByVal(ty), Abi::Scalar(a)) => (PassMode::ByVal(ty), regs_for(a)),
+                (PassMode::Cast(a_ty, b_ty), Abi::Scalar(a)) => (PassMode::Cast(a_ty, b_ty), regs_for(a)),
+                (PassMode::ByValPair(a_ty, b_ty), Abi::ScalarPair(a, b)) => {
+                    let (a, b) = (regs_for(a), regs_for(b));
+
+                    (PassMode::ByValPair(a_ty, b_ty), RetRegs {
+                        int: a.int + b.int,
+                        float: a.float + b.float,
+                    })
+                }
+                (PassMode::ByRef { size: Some(size) }, _) if size.bytes() <= regs.int.min(2) * ptr_size => {
+                    let first = int_reg_ty(mcx, size.bytes().min(ptr_size));
+                    let second = if size.bytes() > ptr_size {
+                        Some(int_reg_ty(mcx, size.bytes() - ptr_size))
+                    } else {
+                        None
+                    };
+
+                    (PassMode::Cast(first, second), RetRegs {
+                        int: 1 + second.is_some() as u64,
+                        float: 0,
+                    })
+                }
+                (mode, _) => (mode, RetRegs::default()),
+            };
+
+            if free.take(needed) {
+                mode
+            } else {
+                PassMode::ByRef { size: Some(layout.size) }
+            }
+        })
+        .collect()
+}
+
+/// Returns the smallest integer register type that can hold `bytes` bytes, up to 8.
+pub(crate) fn int_reg_ty<'ctx, B: Backend<'ctx>>(mcx: &ModuleCtx<'_, 'ctx, B>, bytes: u64) -> <B::Type as Type<'ctx>>::Raw {
+    let int = match bytes {
+        1 => Integer::I8,
+        2 => Integer::I16,
+        3..=4 => Integer::I32,
+        _ => Integer::I64,
+    };
+
+    mcx.scalar_ty(&Scalar::unit(Primitive::Int(int, false), &mcx.target))
 }
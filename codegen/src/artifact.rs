@@ -0,0 +1,53 @@
+/// The machine code of a single function, compiled without the rest of its module,
+/// so that backends can be tested and functions cached one at a time.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FuncArtifact {
+    pub name: String,
+    pub code: Vec<u8>,
+    /// The places in `code` that have to be patched with the address of another symbol, ordered by offset.
+    pub relocs: Vec<Reloc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Reloc {
+    /// The offset of the patched bytes from the start of the function.
+    pub offset: u32,
+    /// The kind of relocation, as named by the backend.
+    pub kind: String,
+    pub symbol: String,
+    pub addend: i64,
+}
+
+impl std::fmt::Display for FuncArtifact {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{}: {} bytes", self.name, self.code.len())?;
+
+        for (i, line) in self.code.chunks(16).enumerate() {
+            write!(f, "    {:04x}:", i * 16)?;
+
+            for byte in line {
+                write!(f, " {:02x}", byte)?;
+            }
+
+            writeln!(f)?;
+        }
+
+        for reloc in &self.relocs {
+            writeln!(f, "    {}", reloc)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Reloc {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:04x}: {} {}", self.offset, self.kind, self.symbol)?;
+
+        match self.addend {
+            0 => Ok(()),
+            a if a < 0 => write!(f, " - {}", -a),
+            a => write!(f, " + {}", a),
+        }
+    }
+}
@@ -0,0 +1,181 @@
+//! Reusing the machine code of functions that have not changed since an earlier compilation.
+
+use crate::artifact::{FuncArtifact, Reloc};
+use ir::serialize::{Decode, Encode};
+use ir::visitor::Visitor;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+pub const MAGIC: [u8; 4] = *b"LOWC";
+
+/// Compiled functions keyed on a hash of everything their code depends on: the body, the declaration of the
+/// function and of everything it refers to, the backend's configuration and the version of the compiler.
+/// Only functions that refer to nothing but declarations of the module are kept, since anonymous constants
+/// and the helpers a backend generates are only defined while a function is translated.
+///
+/// Entries are kept in memory, and in a directory as well if one is given, so that they are reused by later
+/// processes. Entries in the directory are never removed.
+#[derive(Debug, Default)]
+pub struct FuncCache {
+    dir: Option<PathBuf>,
+    entries: HashMap<u64, FuncArtifact>,
+    hits: usize,
+    misses: usize,
+}
+
+impl FuncCache {
+    pub fn new() -> Self {
+        FuncCache::default()
+    }
+
+    pub fn with_dir(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+
+        std::fs::create_dir_all(&dir)?;
+
+        Ok(FuncCache {
+            dir: Some(dir),
+            ..FuncCache::default()
+        })
+    }
+
+    /// The number of functions that were found in the cache.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// The number of functions that had to be compiled.
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    /// Hashes what the code of `body` depends on. `config` describes the backend's settings, including its
+    /// own version, as a newer backend may generate different code for the same body.
+    pub fn key(module: &ir::Module, body: &ir::Body, config: &str) -> u64 {
+        let mut refs = Refs(vec![body.decl]);
+        let mut bytes = Vec::new();
+
+        refs.visit_body(body);
+        refs.0.sort();
+        refs.0.dedup();
+
+        // the body refers to declarations by id, so their ids are part of the key as well
+        ir::serialize::VERSION.encode(&mut bytes).unwrap();
+        env!("CARGO_PKG_VERSION").to_string().encode(&mut bytes).unwrap();
+        config.to_string().encode(&mut bytes).unwrap();
+        body.locals.encode(&mut bytes).unwrap();
+        body.blocks.encode(&mut bytes).unwrap();
+
+        for decl in refs.0 {
+            module.decls[decl].encode(&mut bytes).unwrap();
+        }
+
+        ir::serialize::stable_hash(&bytes)
+    }
+
+    /// Looks up the code for `key`, counting a hit or a miss.
+    pub fn get(&mut self, key: u64) -> Option<FuncArtifact> {
+        let found = match self.entries.get(&key) {
+            Some(artifact) => Some(artifact.clone()),
+            None => self.read(key),
+        };
+
+        match found {
+            Some(artifact) => {
+                self.hits += 1;
+                self.entries.insert(key, artifact.clone());
+                Some(artifact)
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Keeps `artifact` under `key` if it only refers to declarations of `module`.
+    /// Failing to write it to the directory is not an error, the function is compiled again next time.
+    pub fn insert(&mut self, module: &ir::Module, key: u64, artifact: FuncArtifact) {
+        if !artifact.relocs.iter().all(|r| module.decls.iter().any(|d| d.name == r.symbol)) {
+            return;
+        }
+
+        if let Some(path) = self.path(key) {
+            let mut bytes = Vec::new();
+
+            if write_artifact(&artifact, &mut bytes).is_ok() {
+                let _ = std::fs::write(path, bytes);
+            }
+        }
+
+        self.entries.insert(key, artifact);
+    }
+
+    fn read(&self, key: u64) -> Option<FuncArtifact> {
+        let bytes = std::fs::read(self.path(key)?).ok()?;
+
+        // an entry written by another version of the compiler is compiled again
+        read_artifact(&mut bytes.as_slice()).ok()
+    }
+
+    fn path(&self, key: u64) -> Option<PathBuf> {
+        self.dir.as_ref().map(|dir| dir.join(format!("{:016x}.lowc", key)))
+    }
+}
+
+struct Refs(Vec<ir::DeclId>);
+
+impl Visitor for Refs {
+    fn visit_const(&mut self, c: &ir::Const) {
+        match c {
+            ir::Const::Addr(decl) => self.0.push(*decl),
+            ir::Const::Variant(_, fields, _) => fields.iter().for_each(|c| self.visit_const(c)),
+            _ => {}
+        }
+
+        self.super_const(c);
+    }
+}
+
+fn write_artifact(artifact: &FuncArtifact, w: &mut impl Write) -> io::Result<()> {
+    w.write_all(&MAGIC)?;
+    ir::serialize::VERSION.encode(w)?;
+    artifact.name.encode(w)?;
+    artifact.code.encode(w)?;
+    artifact.relocs.len().encode(w)?;
+
+    for reloc in &artifact.relocs {
+        reloc.offset.encode(w)?;
+        reloc.kind.encode(w)?;
+        reloc.symbol.encode(w)?;
+        reloc.addend.encode(w)?;
+    }
+
+    Ok(())
+}
+
+fn read_artifact(r: &mut impl Read) -> io::Result<FuncArtifact> {
+    let mut magic = [0; 4];
+
+    r.read_exact(&mut magic)?;
+
+    if magic != MAGIC || u32::decode(r)? != ir::serialize::VERSION {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a cache entry of this version"));
+    }
+
+    let name = String::decode(r)?;
+    let code = Vec::<u8>::decode(r)?;
+    let mut relocs = Vec::new();
+
+    for _ in 0..usize::decode(r)? {
+        relocs.push(Reloc {
+            offset: u32::decode(r)?,
+            kind: String::decode(r)?,
+            symbol: String::decode(r)?,
+            addend: i64::decode(r)?,
+        });
+    }
+
+    Ok(FuncArtifact { name, code, relocs })
+}
@@ -0,0 +1,231 @@
+use ir::layout::layout_of;
+use std::fmt::Write;
+
+/// Generates a C header declaring every exported function of `module` that follows the C calling convention,
+/// including the wrappers of functions marked `@extern_c_export`, and every exported global.
+///
+/// Tuples and unions are declared as structs and unions with fields named `_0`, `_1`, ... except for zero-sized fields,
+/// enums without payloads as the unsigned integer of their tag, and other aggregates as structs of bytes
+/// with the same size and alignment. Every struct and union is followed by static assertions that its size,
+/// alignment and field offsets match `ir::layout`, so a C compiler that would lay it out differently rejects
+/// the header instead of miscompiling calls.
+pub fn c_header(module: &ir::Module, target: &target_lexicon::Triple, guard: &str) -> String {
+    let mut gen = HeaderGen {
+        target,
+        types: Vec::new(),
+        defs: String::new(),
+    };
+
+    let mut decls = String::new();
+
+    for decl in module.decls.iter().filter(|d| d.linkage == ir::Linkage::Export) {
+        match &decl.ty.kind {
+            ir::Type::Func(sig) if sig.call_conv != ir::CallConv::C => continue,
+            _ if !representable(&decl.ty) => {
+                writeln!(decls, "/* '{}' has a type that can not be declared in C */", decl.name).unwrap();
+                continue;
+            }
+            _ => {}
+        }
+
+        let sig = match &decl.ty.kind {
+            ir::Type::Func(sig) => sig,
+            _ => {
+                writeln!(decls, "extern {} {};", gen.c_type(&decl.ty), decl.name).unwrap();
+                continue;
+            }
+        };
+
+        let ret = match sig.rets.as_slice() {
+            [] => String::from("void"),
+            [ret] => gen.c_type(ret),
+            _ => {
+                writeln!(decls, "/* '{}' returns multiple values and can not be declared in C */", decl.name).unwrap();
+                continue;
+            }
+        };
+
+        let mut params = sig.params.iter().map(|p| gen.c_type(p)).collect::<Vec<_>>();
+
+        if sig.variadic {
+            params.push(String::from("..."));
+        } else if params.is_empty() {
+            params.push(String::from("void"));
+        }
+
+        writeln!(decls, "{} {}({});", ret, decl.name, params.join(", ")).unwrap();
+    }
+
+    let mut out = String::new();
+
+    writeln!(out, "#ifndef {}", guard).unwrap();
+    writeln!(out, "#define {}", guard).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "#include <stddef.h>").unwrap();
+    writeln!(out, "#include <stdint.h>").unwrap();
+    writeln!(out).unwrap();
+
+    if !gen.defs.is_empty() {
+        writeln!(out, "{}", gen.defs).unwrap();
+    }
+
+    writeln!(out, "{}", decls).unwrap();
+    writeln!(out, "#endif").unwrap();
+
+    out
+}
+
+struct HeaderGen<'a> {
+    target: &'a target_lexicon::Triple,
+    types: Vec<(ir::Type, String)>,
+    defs: String,
+}
+
+impl HeaderGen<'_> {
+    fn c_type(&mut self, ty: &ir::Ty) -> String {
+        match &ty.kind {
+            ir::Type::U8 => String::from("uint8_t"),
+            ir::Type::U16 => String::from("uint16_t"),
+            ir::Type::U32 => String::from("uint32_t"),
+            ir::Type::U64 => String::from("uint64_t"),
+            ir::Type::U128 => String::from("unsigned __int128"),
+            ir::Type::I8 => String::from("int8_t"),
+            ir::Type::I16 => String::from("int16_t"),
+            ir::Type::I32 => String::from("int32_t"),
+            ir::Type::I64 => String::from("int64_t"),
+            ir::Type::I128 => String::from("__int128"),
+            ir::Type::F32 => String::from("float"),
+            ir::Type::F64 => String::from("double"),
+            ir::Type::Bool => String::from("_Bool"),
+            ir::Type::Ptr(to) => match &to.kind {
+                ir::Type::Tuple(tys) if tys.is_empty() => String::from("void *"),
+                ir::Type::Func(_) | ir::Type::Opaque(_) | ir::Type::Recurse(_) => String::from("void *"),
+                ir::Type::Type(_) | ir::Type::Vwt(_) => String::from("void *"),
+                _ => format!("{} *", self.c_type(to)),
+            },
+            ir::Type::Func(_) => String::from("void *"),
+            ir::Type::Newtype(name, inner) => self.typedef(ty, name, inner),
+            // an enum without payloads is passed as its tag, which a struct would not be
+            ir::Type::Tagged(_) => match layout_of(ty, self.target).abi {
+                ir::layout::Abi::Scalar(ir::layout::Scalar {
+                    value: ir::layout::Primitive::Int(int, _),
+                    ..
+                }) => format!("uint{}_t", int.size().bits()),
+                _ => self.aggregate(ty),
+            },
+            _ => self.aggregate(ty),
+        }
+    }
+
+    /// Returns the name of the typedef for the newtype `ty`, defining it first if needed. Newtypes whose name
+    /// is taken or is not an identifier in C are declared as the type they wrap.
+    fn typedef(&mut self, ty: &ir::Ty, name: &str, inner: &ir::Ty) -> String {
+        if let Some((_, name)) = self.types.iter().find(|(t, _)| *t == ty.kind) {
+            return name.clone();
+        }
+
+        let inner = self.c_type(inner);
+        let valid = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') && !name.starts_with(|c: char| c.is_ascii_digit());
+        let name = if valid && !self.types.iter().any(|(_, n)| n == name) {
+            writeln!(self.defs, "typedef {} {};", inner, name).unwrap();
+            name.to_string()
+        } else {
+            inner
+        };
+
+        self.types.push((ty.kind.clone(), name.clone()));
+        name
+    }
+
+    /// Returns the name of the struct or union for `ty`, defining it first if needed.
+    fn aggregate(&mut self, ty: &ir::Ty) -> String {
+        if let Some((_, name)) = self.types.iter().find(|(t, _)| *t == ty.kind) {
+            return name.clone();
+        }
+
+        let mut def = String::new();
+        let name = match &ty.kind {
+            ir::Type::Tuple(tys) | ir::Type::Union(tys) => {
+                // the fields are defined first, so they come before this type in the header.
+                // C has no zero-sized types, so those fields are left out and the others keep their index
+                let target = self.target;
+                let fields = tys
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, ty)| !layout_of(ty, target).is_zst())
+                    .map(|(i, ty)| (i, self.c_type(ty)))
+                    .collect::<Vec<_>>();
+                let kind = if let ir::Type::Tuple(_) = ty.kind { "struct" } else { "union" };
+                let name = format!("{} lowlang_{}", kind, self.types.len());
+
+                writeln!(def, "{} {{", name).unwrap();
+
+                for (i, field) in &fields {
+                    writeln!(def, "    {} _{};", field, i).unwrap();
+                }
+
+                writeln!(def, "}};").unwrap();
+                name
+            }
+            _ => {
+                let layout = layout_of(ty, self.target);
+                let name = format!("struct lowlang_{}", self.types.len());
+
+                writeln!(def, "{} {{", name).unwrap();
+                writeln!(def, "    _Alignas({}) uint8_t bytes[{}];", layout.align.bytes(), layout.size.bytes()).unwrap();
+                writeln!(def, "}};").unwrap();
+                name
+            }
+        };
+
+        self.assert_layout(&mut def, ty, &name);
+        self.types.push((ty.kind.clone(), name.clone()));
+        self.defs.push_str(&def);
+        name
+    }
+
+    fn assert_layout(&self, def: &mut String, ty: &ir::Ty, name: &str) {
+        let layout = layout_of(ty, self.target);
+
+        // lowlang does not count the padding after the last field in the size, C does
+        writeln!(
+            def,
+            "_Static_assert(sizeof({0}) == {1}, \"size of {0}\");",
+            name,
+            layout.stride.bytes()
+        )
+        .unwrap();
+
+        writeln!(
+            def,
+            "_Static_assert(_Alignof({0}) == {1}, \"alignment of {0}\");",
+            name,
+            layout.align.bytes()
+        )
+        .unwrap();
+
+        if let ir::Type::Tuple(tys) = &ty.kind {
+            for i in (0..tys.len()).filter(|&i| !layout_of(&tys[i], self.target).is_zst()) {
+                writeln!(
+                    def,
+                    "_Static_assert(offsetof({0}, _{1}) == {2}, \"offset of {0}._{1}\");",
+                    name,
+                    i,
+                    layout.fields.offset(i).bytes()
+                )
+                .unwrap();
+            }
+        }
+    }
+}
+
+/// Whether C can name `ty`, which it can not for types that are only known once the module is monomorphized.
+fn representable(ty: &ir::Ty) -> bool {
+    match &ty.kind {
+        ir::Type::Type(_) | ir::Type::Vwt(_) | ir::Type::Opaque(_) => false,
+        ir::Type::Tuple(tys) | ir::Type::Union(tys) | ir::Type::Tagged(tys) => tys.iter().all(representable),
+        ir::Type::Box(to) | ir::Type::Discr(to) | ir::Type::Vector(to, _) | ir::Type::Newtype(_, to) => representable(to),
+        ir::Type::Func(sig) => sig.params.iter().chain(&sig.rets).all(representable),
+        _ => true,
+    }
+}
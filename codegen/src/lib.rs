@@ -1,9 +1,20 @@
+#![feature(generic_associated_types)]
+
 pub mod abi;
 pub mod analyze;
+pub mod artifact;
+pub mod cache;
+pub mod header;
+pub mod link;
 pub mod obj_file;
+pub mod session;
+pub mod support;
 
 use ir::layout::{Scalar, TyLayout};
+use ir::progress::{CancellationToken, Cancelled, NoProgress, Progress};
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
 pub trait Backend<'ctx>:
     Sized
@@ -14,7 +25,9 @@ pub trait Backend<'ctx>:
 {
     type Module;
     type Context: 'ctx;
-    type Builder: 'ctx;
+    /// The storage a function is built in, which is reused for every function of a module.
+    type FuncContext: 'ctx;
+    type Builder<'bcx>;
     type Func: Copy;
     type Static: Copy;
     type Block: Copy;
@@ -22,11 +35,33 @@ pub trait Backend<'ctx>:
     type Value: Value<'ctx, Backend = Self> + Clone;
     type Type: Type<'ctx, Backend = Self>;
 
-    fn create_module(&mut self, target: &target_lexicon::Triple) -> Self::Module;
+    fn create_module(&mut self, target: &target_lexicon::Triple) -> Result<Self::Module, CodegenError>;
     fn create_context(&mut self, module: &mut Self::Module) -> Self::Context;
-    fn create_builder(&mut self, ctx: &mut Self::Context) -> Self::Builder;
+    fn create_func_context(&mut self) -> Self::FuncContext;
+    fn create_builder<'bcx>(&mut self, ctx: &'bcx mut Self::FuncContext) -> Self::Builder<'bcx>;
+
+    fn finish(mcx: ModuleCtx<'_, 'ctx, Self>) -> Result<obj_file::ObjectFile, CodegenError>;
+
+    /// Describes the target and settings the backend generates code for, so that cached code is only reused
+    /// by a backend that would generate the same.
+    fn code_config(mcx: &ModuleCtx<'_, 'ctx, Self>) -> String;
+
+    /// Checks that the values of a function with the signature `sig` can be passed the way its calling convention
+    /// expects, before the function is declared or called.
+    fn check_signature(mcx: &ModuleCtx<'_, 'ctx, Self>, sig: &ir::Signature) -> Result<(), String> {
+        abi::check_signature(sig, &mcx.target)
+    }
+
+    /// Whether this backend can lower `feature` for `target`.
+    fn supports_feature(&self, _feature: &support::Feature, _target: &target_lexicon::Triple) -> bool {
+        true
+    }
 
-    fn finish(mcx: ModuleCtx<'_, 'ctx, Self>) -> obj_file::ObjectFile;
+    /// Lists the features `module` uses and the ones this backend can not lower,
+    /// so they can be reported before code generation would fail on the first of them.
+    fn supports(&self, module: &ir::Module, target: &target_lexicon::Triple) -> support::SupportReport {
+        support::SupportReport::new(module, |feature| self.supports_feature(feature, target))
+    }
 }
 
 pub trait DeclMethods<'ctx> {
@@ -47,7 +82,15 @@ pub trait DeclMethods<'ctx> {
         id: <Self::Backend as Backend<'ctx>>::Static,
         body: &ir::Body,
     ) {
-        let vals = eval::evaluate(mcx.ir, body, &mcx.target);
+        let mut ctx = eval::EvalCtx::new(mcx.ir, body, &mcx.target);
+
+        if let Err(e) = ctx.eval() {
+            let name = &mcx.ir.decls[body.decl].name;
+
+            panic!("could not evaluate the initializer of '{}': {}\n{}", name, e, eval::format_traceback(ctx.traceback()));
+        }
+
+        let vals = ctx.finish();
         let ty = ir::const_type(mcx.ir, &vals[0]);
         let layout = ir::layout::layout_of(&ty, &mcx.target);
 
@@ -59,6 +102,20 @@ pub trait DeclMethods<'ctx> {
         id: <Self::Backend as Backend<'ctx>>::Func,
     );
 
+    /// Compiles the function built in `fx` to machine code like `define_func`, but returns the code
+    /// instead of adding it to the module, which leaves the function declared but not defined.
+    fn emit_func(
+        fx: &mut FunctionCtx<'_, 'ctx, '_, Self::Backend>,
+        id: <Self::Backend as Backend<'ctx>>::Func,
+    ) -> artifact::FuncArtifact;
+
+    /// Defines the function `id` with code returned by `emit_func` for the same function, possibly by an earlier compilation.
+    fn define_artifact(
+        mcx: &mut ModuleCtx<'_, 'ctx, Self::Backend>,
+        id: <Self::Backend as Backend<'ctx>>::Func,
+        artifact: &artifact::FuncArtifact,
+    );
+
     fn func_prologue(fx: &mut FunctionCtx<'_, 'ctx, '_, Self::Backend>);
 }
 
@@ -213,114 +270,133 @@ pub trait Type<'ctx>: Sized {
     fn scalar_ty(scalar: &Scalar, mcx: &ModuleCtx<'_, 'ctx, Self::Backend>) -> Self::Raw;
 }
 
+/// Options that control how a module is generated.
+#[derive(Debug, Clone, Default)]
+pub struct CodegenOptions {
+    /// A directory to write the backend's IR of every function to, one file per function.
+    /// The IR is also logged at the debug level, regardless of this option.
+    pub dump_ir: Option<PathBuf>,
+    /// Prefixes the symbols of anonymous constants, so that objects generated in one process can be told apart.
+    pub module_name: Option<String>,
+    /// Declares and defines symbols sorted by name instead of in the order of the module,
+    /// so that reordering the declarations of a module does not change the object file.
+    pub sort_symbols: bool,
+    /// The number of threads that compile functions to machine code. With more than one, functions are
+    /// only translated while the module is built and are compiled together when it is finished, so the
+    /// times reported for every function leave out their compilation. Zero and one compile every function right away.
+    pub threads: usize,
+    /// Reuses the code of functions that have not changed since they were put in the cache and adds the
+    /// functions that had to be compiled. Functions are compiled one at a time when a cache is used.
+    pub cache: Option<Arc<Mutex<cache::FuncCache>>>,
+    /// Replaces every NaN a float operation produces with the canonical NaN, so that float results are the same
+    /// bits on every host and in the interpreter with `EvalOptions::canonical_nans`. Float operations are never
+    /// contracted into fused multiply-adds, with or without this.
+    pub canonical_nans: bool,
+}
+
+#[derive(Debug)]
+pub enum CodegenError {
+    Cancelled,
+    /// The body of `func` cannot be compiled, because it has no blocks or because `block` refers to a block it does not have.
+    InvalidBody { func: String, block: Option<ir::Block>, message: String },
+    /// The signature of `func` can not be lowered by the backend for the target.
+    Unsupported { func: String, message: String },
+    /// The module uses something the backend can not lower, the first of what `Backend::supports` reports.
+    UnsupportedFeature(support::FeatureUse),
+    /// The backend does not generate code for the target, with the reason why.
+    UnsupportedTarget(String),
+    /// The backend failed to compile `func` or to put its code in the object.
+    Backend { func: String, message: String },
+}
+
+impl From<Cancelled> for CodegenError {
+    fn from(_: Cancelled) -> Self {
+        CodegenError::Cancelled
+    }
+}
+
+impl std::fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CodegenError::Cancelled => Cancelled.fmt(f),
+            CodegenError::InvalidBody { func, block: None, message } => write!(f, "invalid body of {}: {}", func, message),
+            CodegenError::InvalidBody {
+                func,
+                block: Some(block),
+                message,
+            } => write!(f, "invalid body of {} in %{}: {}", func, block.index(), message),
+            CodegenError::Unsupported { func, message } => write!(f, "unsupported signature of {}: {}", func, message),
+            CodegenError::UnsupportedFeature(feature) => write!(f, "unsupported {}", feature),
+            CodegenError::UnsupportedTarget(message) => message.fmt(f),
+            CodegenError::Backend { func, message } => write!(f, "could not compile {}: {}", func, message),
+        }
+    }
+}
+
+impl std::error::Error for CodegenError {}
+
 pub struct ModuleCtx<'ir, 'ctx, B: Backend<'ctx>> {
     pub backend: B,
     pub target: target_lexicon::Triple,
+    pub options: CodegenOptions,
     pub module: B::Module,
     pub ctx: B::Context,
     pub ir: &'ir ir::Module,
+    /// The pool of anonymous constants emitted so far and the type they were laid out as,
+    /// shared by functions and global initializers so that a constant is stored once.
+    pub consts: HashMap<ir::Const, (ir::Ty, B::Static)>,
 }
 
 pub struct FunctionCtx<'ir, 'ctx, 'mcx, B: Backend<'ctx>> {
     pub mcx: &'mcx mut ModuleCtx<'ir, 'ctx, B>,
-    pub bcx: B::Builder,
+    pub bcx: B::Builder<'mcx>,
     pub body: &'ir ir::Body,
     pub blocks: HashMap<ir::Block, B::Block>,
     pub locals: HashMap<ir::Local, B::Place>,
 }
 
 impl<'ir, 'ctx, B: Backend<'ctx>> ModuleCtx<'ir, 'ctx, B> {
-    pub fn new(ir: &'ir ir::Module, target: target_lexicon::Triple, mut backend: B) -> Self {
-        let mut module = backend.create_module(&target);
+    pub fn new(ir: &'ir ir::Module, target: target_lexicon::Triple, backend: B) -> Result<Self, CodegenError> {
+        ModuleCtx::with_options(ir, target, backend, CodegenOptions::default())
+    }
+
+    /// Creates the backend's module for `target`, failing if the backend does not generate code for it.
+    pub fn with_options(ir: &'ir ir::Module, target: target_lexicon::Triple, mut backend: B, options: CodegenOptions) -> Result<Self, CodegenError> {
+        let mut module = backend.create_module(&target)?;
         let ctx = backend.create_context(&mut module);
 
-        ModuleCtx {
+        Ok(ModuleCtx {
             backend,
             target,
+            options,
             module,
             ctx,
             ir,
-        }
+            consts: HashMap::new(),
+        })
     }
 
-    pub fn build(mut self) -> obj_file::ObjectFile {
-        let mut func_ids = HashMap::new();
-        let mut static_ids = HashMap::new();
-        let ir = self.ir;
-
-        for decl in &ir.decls {
-            if let ir::Type::Func(_) = &decl.ty.kind {
-                func_ids.insert(decl.id, B::declare_func(&mut self, decl));
-            } else {
-                static_ids.insert(decl.id, B::declare_static(&mut self, decl));
-            }
-        }
-
-        for body in &ir.bodies {
-            let decl = &ir.decls[body.decl];
-
-            if let ir::Type::Func(_) = &decl.ty.kind {
-                let func_id = func_ids.remove(&decl.id).unwrap();
-                let builder = B::create_builder(&mut self.backend, &mut self.ctx);
-                let mut fx = FunctionCtx::new(&mut self, builder, body);
-
-                B::func_prologue(&mut fx);
-
-                for block in &body.blocks {
-                    let block_id = fx.blocks[&block.id];
-
-                    B::switch_to_block(&mut fx, block_id);
-
-                    for stmt in &block.stmts {
-                        match stmt {
-                            ir::Stmt::Init(local) => {
-                                let place = fx.locals[local].clone();
-
-                                B::trans_init(&mut fx, place)
-                            }
-                            ir::Stmt::Drop(local) => {
-                                let place = fx.locals[local].clone();
-
-                                B::trans_drop(&mut fx, place)
-                            }
-                            ir::Stmt::Assign(place, rvalue) => {
-                                let place = B::trans_place(&mut fx, place);
-
-                                B::trans_rvalue(&mut fx, place, rvalue);
-                            }
-                            ir::Stmt::SetDiscr(place, val) => {
-                                let place = B::trans_place(&mut fx, place);
-
-                                B::trans_set_discr(&mut fx, place, *val);
-                            }
-                            ir::Stmt::Call(rets, func, args) => {
-                                let rets = rets
-                                    .iter()
-                                    .map(|r| B::trans_place(&mut fx, r))
-                                    .collect::<Vec<_>>();
+    pub fn build(self) -> obj_file::ObjectFile {
+        self.build_with(&mut NoProgress, &CancellationToken::new()).unwrap_or_else(|e| panic!("{}", e))
+    }
 
-                                let args = args
-                                    .iter()
-                                    .map(|a| B::trans_op(&mut fx, a, None))
-                                    .collect::<Vec<_>>();
+    /// Builds the module, reporting each function to `progress`.
+    /// Stops between functions once `cancel` is cancelled.
+    pub fn build_with(self, progress: &mut dyn Progress, cancel: &CancellationToken) -> Result<obj_file::ObjectFile, CodegenError> {
+        let mut session = session::Session::new(self)?;
 
-                                B::trans_call(&mut fx, rets, func, args);
-                            }
-                        }
-                    }
+        session.run(progress, cancel)?;
 
-                    B::trans_term(&mut fx, &block.term);
-                }
+        session.finish()
+    }
 
-                B::define_func(&mut fx, func_id);
-            } else {
-                let static_id = static_ids.remove(&decl.id).unwrap();
+    /// Defines every body like `build_with`, but returns the module context instead of finishing the object.
+    pub fn define_with(self, progress: &mut dyn Progress, cancel: &CancellationToken) -> Result<Self, CodegenError> {
+        let mut session = session::Session::new(self)?;
 
-                B::define_static(&mut self, static_id, body);
-            }
-        }
+        session.run(progress, cancel)?;
 
-        B::finish(self)
+        Ok(session.into_module_ctx())
     }
 
     pub fn ir_type(&self, layout: &TyLayout) -> Option<<B::Type as Type<'ctx>>::Raw> {
@@ -342,7 +418,7 @@ impl<'ir, 'ctx, B: Backend<'ctx>> ModuleCtx<'ir, 'ctx, B> {
 impl<'ir, 'ctx, 'mcx, B: Backend<'ctx>> FunctionCtx<'ir, 'ctx, 'mcx, B> {
     pub fn new(
         mcx: &'mcx mut ModuleCtx<'ir, 'ctx, B>,
-        bcx: B::Builder,
+        bcx: B::Builder<'mcx>,
         body: &'ir ir::Body,
     ) -> Self {
         FunctionCtx {
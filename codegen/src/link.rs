@@ -0,0 +1,301 @@
+use crate::obj_file::ObjectFile;
+use ir::capability::{self, Capabilities};
+use object::{BinaryFormat, Object, ObjectSection};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub struct LinkOptions {
+    pub output: PathBuf,
+    pub kind: OutputKind,
+    /// The compiler driver used to link. Defaults to `$CC`, or `cc` when that is not set.
+    pub linker: PathBuf,
+    /// Link with lld instead of the driver's default linker.
+    pub lld: bool,
+    /// Directories to search for libraries, passed as `-L`.
+    pub lib_paths: Vec<PathBuf>,
+    /// Libraries to link against, passed as `-l`.
+    pub libs: Vec<String>,
+    /// Other objects, static and shared libraries to link with, like the runtime library.
+    pub objects: Vec<PathBuf>,
+    /// Check that every symbol the object imports is provided before running the linker.
+    /// Libraries in `libs` are only searched for in `lib_paths`, symbols from any other library must be allowed explicitly.
+    pub check_symbols: bool,
+    /// Symbols that may stay undefined when checking, because they are provided by a library that is always linked.
+    pub allow_undefined: Vec<String>,
+    /// Extra arguments passed to the linker as is.
+    pub args: Vec<String>,
+    /// What the linked program may rely on besides the runtime library, whose version is read from the
+    /// objects and libraries instead. Objects that require more are rejected when checking symbols.
+    pub provides: Capabilities,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputKind {
+    Executable,
+    SharedLibrary,
+}
+
+#[derive(Debug)]
+pub enum LinkError {
+    /// The linker could not be started.
+    Spawn(PathBuf, std::io::Error),
+    /// The linker ran but did not succeed.
+    Failed(std::process::ExitStatus, String),
+    /// An object or library could not be read while checking symbols.
+    Read(PathBuf, String),
+    /// These symbols are imported but not provided by any object or library.
+    Undefined(Vec<String>),
+    /// An object requires something that is not provided, like a recent enough runtime library.
+    Requires(String),
+}
+
+impl Default for LinkOptions {
+    fn default() -> Self {
+        LinkOptions {
+            output: PathBuf::from("a.out"),
+            kind: OutputKind::Executable,
+            linker: std::env::var_os("CC").map(PathBuf::from).unwrap_or_else(|| PathBuf::from("cc")),
+            lld: false,
+            lib_paths: Vec::new(),
+            libs: Vec::new(),
+            objects: Vec::new(),
+            check_symbols: true,
+            // provided by the C library, which the driver always links
+            allow_undefined: ["memcpy", "memmove", "memset", "memcmp", "strcmp", "malloc", "realloc", "free"].iter().map(|s| s.to_string()).collect(),
+            args: Vec::new(),
+            provides: Capabilities {
+                runtime: 0,
+                ..Capabilities::all()
+            },
+        }
+    }
+}
+
+impl ObjectFile {
+    /// Links this object into an executable or shared library.
+    pub fn link(&self, options: &LinkOptions) -> Result<(), LinkError> {
+        if options.check_symbols {
+            // a missing runtime shows up as undefined symbols as well, which is less clear
+            self.check_capabilities(options)?;
+
+            let missing = self.missing_symbols(options)?;
+
+            if !missing.is_empty() {
+                return Err(LinkError::Undefined(missing));
+            }
+        }
+
+        let mut cmd = Command::new(&options.linker);
+
+        cmd.arg(self.path()).args(&options.objects).arg("-o").arg(&options.output);
+
+        if options.kind == OutputKind::SharedLibrary {
+            cmd.arg("-shared");
+        }
+
+        if options.lld {
+            cmd.arg("-fuse-ld=lld");
+        }
+
+        for path in &options.lib_paths {
+            cmd.arg("-L").arg(path);
+        }
+
+        for lib in &options.libs {
+            cmd.arg(format!("-l{}", lib));
+        }
+
+        cmd.args(&options.args);
+
+        let output = cmd.output().map_err(|e| LinkError::Spawn(options.linker.clone(), e))?;
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(LinkError::Failed(output.status, String::from_utf8_lossy(&output.stderr).into_owned()))
+        }
+    }
+
+    /// Returns the symbols this object imports that are not provided by `options.objects`,
+    /// the libraries in `options.libs` or `options.allow_undefined`, sorted by name.
+    pub fn missing_symbols(&self, options: &LinkOptions) -> Result<Vec<String>, LinkError> {
+        let mut undefined = Vec::new();
+        let mut provided = options.allow_undefined.iter().cloned().collect::<HashSet<_>>();
+
+        read_symbols(self.path(), &mut |name, defined| {
+            if !defined {
+                undefined.push(name);
+            }
+        })?;
+
+        for path in options.objects.iter().cloned().chain(options.libs.iter().filter_map(|lib| find_lib(lib, &options.lib_paths))) {
+            read_symbols(&path, &mut |name, defined| {
+                if defined {
+                    provided.insert(name);
+                }
+            })?;
+        }
+
+        let mut missing = undefined.into_iter().filter(|name| !provided.contains(name)).collect::<Vec<_>>();
+
+        missing.sort();
+        missing.dedup();
+
+        Ok(missing)
+    }
+
+    /// Checks that this object and the other objects in `options` only require what `options.provides`
+    /// provides, and a runtime library at least as recent as the one they were compiled for.
+    pub fn check_capabilities(&self, options: &LinkOptions) -> Result<(), LinkError> {
+        let mut provided = options.provides;
+        let libs = options.libs.iter().filter_map(|lib| find_lib(lib, &options.lib_paths)).collect::<Vec<_>>();
+
+        for path in options.objects.iter().chain(&libs) {
+            read_symbols(path, &mut |name, defined| match capability::runtime_version_of(&name) {
+                Some(version) if defined => provided.runtime = provided.runtime.max(version),
+                _ => {}
+            })?;
+        }
+
+        if let Some(required) = read_capabilities(self.path())? {
+            required.check("the object", &provided).map_err(LinkError::Requires)?;
+        }
+
+        for path in &options.objects {
+            if let Some(required) = read_capabilities(path)? {
+                required.check(&path.display().to_string(), &provided).map_err(LinkError::Requires)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the capabilities lowlang objects record, combining those of the members of a static library.
+/// Returns `None` for objects and libraries that were not compiled from a lowlang module.
+fn read_capabilities(path: &Path) -> Result<Option<Capabilities>, LinkError> {
+    let bytes = std::fs::read(path).map_err(|e| LinkError::Read(path.to_path_buf(), e.to_string()))?;
+    let invalid = || LinkError::Read(path.to_path_buf(), String::from("invalid capabilities"));
+    let mut caps = None::<Capabilities>;
+    let members = match archive_members(&bytes) {
+        Some(members) => members,
+        None => vec![&bytes[..]],
+    };
+
+    for member in members {
+        let file = match object::File::parse(member) {
+            Ok(file) => file,
+            Err(_) => continue,
+        };
+
+        if let Some(section) = file.section_by_name(capability::SECTION) {
+            let required = section.data().ok().and_then(Capabilities::from_bytes).ok_or_else(invalid)?;
+
+            caps = Some(caps.map_or(required, |caps| caps.union(required)));
+        }
+    }
+
+    Ok(caps)
+}
+
+fn find_lib(name: &str, lib_paths: &[PathBuf]) -> Option<PathBuf> {
+    let files = [format!("lib{}.so", name), format!("lib{}.dylib", name), format!("lib{}.a", name), format!("{}.lib", name)];
+
+    lib_paths.iter().flat_map(|dir| files.iter().map(move |file| dir.join(file))).find(|path| path.is_file())
+}
+
+/// Calls `f` with the name of every global symbol in an object, shared library or static library
+/// and whether it is defined there. Names are given without the leading underscore of Mach-O.
+fn read_symbols(path: &Path, f: &mut dyn FnMut(String, bool)) -> Result<(), LinkError> {
+    let bytes = std::fs::read(path).map_err(|e| LinkError::Read(path.to_path_buf(), e.to_string()))?;
+
+    if let Some(members) = archive_members(&bytes) {
+        for member in members {
+            // archives may contain other files than objects, which the linker ignores as well
+            if let Ok(file) = object::File::parse(member) {
+                object_symbols(&file, f);
+            }
+        }
+
+        return Ok(());
+    }
+
+    let file = object::File::parse(&bytes).map_err(|e| LinkError::Read(path.to_path_buf(), e.to_string()))?;
+
+    object_symbols(&file, f);
+    Ok(())
+}
+
+fn object_symbols(file: &object::File, f: &mut dyn FnMut(String, bool)) {
+    let mut symbols = file.symbols().collect::<Vec<_>>();
+
+    if symbols.is_empty() {
+        // stripped shared libraries only have dynamic symbols
+        symbols = file.dynamic_symbols().collect();
+    }
+
+    for (_, symbol) in symbols {
+        let name = match symbol.name() {
+            Some(name) if !name.is_empty() && (symbol.is_global() || symbol.is_undefined()) => name,
+            _ => continue,
+        };
+
+        let name = match file.format() {
+            BinaryFormat::MachO => name.strip_prefix('_').unwrap_or(name),
+            _ => name,
+        };
+
+        f(name.to_string(), !symbol.is_undefined());
+    }
+}
+
+/// Splits a static library in the common `ar` format into its members.
+fn archive_members(bytes: &[u8]) -> Option<Vec<&[u8]>> {
+    let mut rest = bytes.strip_prefix(b"!<arch>\n")?;
+    let mut members = Vec::new();
+
+    while rest.len() >= 60 {
+        let header = &rest[..60];
+        let name = std::str::from_utf8(&header[..16]).ok()?.trim_end();
+        let size = std::str::from_utf8(&header[48..58]).ok()?.trim().parse::<usize>().ok()?;
+        let data = rest.get(60..60 + size)?;
+
+        match name {
+            // the symbol table and the table of long names
+            "/" | "//" | "/SYM64/" => {}
+            _ if name.starts_with("__.SYMDEF") => {}
+            _ => match name.strip_prefix("#1/") {
+                // bsd archives store long names at the start of the data
+                Some(len) => members.push(data.get(len.parse::<usize>().ok()?..)?),
+                None => members.push(data),
+            },
+        }
+
+        rest = rest.get(60 + size + size % 2..).unwrap_or(&[]);
+    }
+
+    Some(members)
+}
+
+impl std::fmt::Display for LinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LinkError::Spawn(linker, e) => write!(f, "could not run linker {}: {}", linker.display(), e),
+            LinkError::Failed(status, stderr) => write!(f, "linking failed with {}:\n{}", status, stderr),
+            LinkError::Read(path, e) => write!(f, "could not read symbols of {}: {}", path.display(), e),
+            LinkError::Undefined(symbols) => {
+                write!(f, "{} undefined symbol(s), which must be provided by an object or library:", symbols.len())?;
+
+                for symbol in symbols {
+                    write!(f, "\n    {}", symbol)?;
+                }
+
+                Ok(())
+            }
+            LinkError::Requires(msg) => msg.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
@@ -0,0 +1,325 @@
+use crate::*;
+use ir::progress::{CancellationToken, Progress};
+
+/// A compilation of a module that can be interrupted between functions.
+/// When cancelled, the functions compiled so far are kept and calling
+/// [`Session::run`] again resumes with the next one.
+pub struct Session<'ir, 'ctx, B: Backend<'ctx>> {
+    mcx: ModuleCtx<'ir, 'ctx, B>,
+    func_ctx: B::FuncContext,
+    func_ids: HashMap<ir::DeclId, B::Func>,
+    static_ids: HashMap<ir::DeclId, B::Static>,
+    /// The bodies in the order they are compiled.
+    order: Vec<&'ir ir::Body>,
+    /// The number of bodies that have been compiled.
+    done: usize,
+}
+
+impl<'ir, 'ctx, B: Backend<'ctx>> Session<'ir, 'ctx, B> {
+    /// Declares every function and static of the module, failing if the module uses a feature or a function
+    /// has a signature the backend can not lower.
+    pub fn new(mut mcx: ModuleCtx<'ir, 'ctx, B>) -> Result<Self, CodegenError> {
+        let mut func_ids = HashMap::new();
+        let mut static_ids = HashMap::new();
+        let ir = mcx.ir;
+        let mut decls = ir.decls.iter().collect::<Vec<_>>();
+        let mut order = ir.bodies.iter().collect::<Vec<_>>();
+
+        if let Some(feature) = mcx.backend.supports(ir, &mcx.target).unsupported().next() {
+            return Err(CodegenError::UnsupportedFeature(feature.clone()));
+        }
+
+        if mcx.options.sort_symbols {
+            decls.sort_by(|a, b| a.name.cmp(&b.name));
+            order.sort_by(|a, b| ir.decls[a.decl].name.cmp(&ir.decls[b.decl].name));
+        }
+
+        for decl in decls {
+            if let ir::Type::Func(sig) = &decl.ty.kind {
+                B::check_signature(&mcx, sig).map_err(|message| CodegenError::Unsupported {
+                    func: decl.name.clone(),
+                    message,
+                })?;
+
+                func_ids.insert(decl.id, B::declare_func(&mut mcx, decl));
+            } else {
+                static_ids.insert(decl.id, B::declare_static(&mut mcx, decl));
+            }
+        }
+
+        let func_ctx = mcx.backend.create_func_context();
+
+        Ok(Session {
+            mcx,
+            func_ctx,
+            func_ids,
+            static_ids,
+            order,
+            done: 0,
+        })
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done == self.order.len()
+    }
+
+    /// The declarations whose bodies have already been compiled.
+    pub fn compiled(&self) -> impl Iterator<Item = ir::DeclId> + 'ir {
+        self.order[..self.done].iter().map(|b| b.decl).collect::<Vec<_>>().into_iter()
+    }
+
+    /// Compiles the remaining bodies, reporting each function to `progress`.
+    /// Stops between functions once `cancel` is cancelled, or at the first function that cannot be compiled.
+    pub fn run(&mut self, progress: &mut dyn Progress, cancel: &CancellationToken) -> Result<(), CodegenError> {
+        let ir = self.mcx.ir;
+
+        while let Some(&body) = self.order.get(self.done) {
+            cancel.check()?;
+
+            let decl = &ir.decls[body.decl];
+
+            if let ir::Type::Func(_) = &decl.ty.kind {
+                check_body(&self.mcx, decl, body)?;
+
+                let start = std::time::Instant::now();
+
+                let func_id = self.func_ids.remove(&decl.id).unwrap();
+
+                progress.on_function_start(decl);
+
+                match self.mcx.options.cache.clone() {
+                    Some(cache) => {
+                        let key = cache::FuncCache::key(ir, body, &B::code_config(&self.mcx));
+                        let cached = cache.lock().unwrap().get(key);
+                        let artifact = match cached {
+                            Some(artifact) => artifact,
+                            None => {
+                                let artifact = self.compile_func(body, func_id, B::emit_func);
+
+                                cache.lock().unwrap().insert(ir, key, artifact.clone());
+                                artifact
+                            }
+                        };
+
+                        B::define_artifact(&mut self.mcx, func_id, &artifact);
+                    }
+                    None => self.compile_func(body, func_id, B::define_func),
+                }
+
+                progress.on_function_finish(decl, start.elapsed());
+            } else {
+                let static_id = self.static_ids.remove(&decl.id).unwrap();
+
+                B::define_static(&mut self.mcx, static_id, body);
+            }
+
+            self.done += 1;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<obj_file::ObjectFile, CodegenError> {
+        assert!(self.is_done(), "session finished before all bodies were compiled");
+
+        B::finish(self.mcx)
+    }
+
+    /// Returns the module context with every body defined in it, for backends that use the module as is
+    /// instead of finishing it into an object, like a jit.
+    pub fn into_module_ctx(self) -> ModuleCtx<'ir, 'ctx, B> {
+        assert!(self.is_done(), "session finished before all bodies were compiled");
+
+        self.mcx
+    }
+
+    /// Compiles the function `decl` on its own and returns its machine code, without defining it in the object.
+    /// This works whether or not the session has compiled the function already, and does not affect the session.
+    pub fn compile_function(&mut self, decl: ir::DeclId) -> Result<artifact::FuncArtifact, CodegenError> {
+        let ir = self.mcx.ir;
+        let decl = &ir.decls[decl];
+        let body = ir.bodies.iter().find(|b| b.decl == decl.id);
+        let error = |message: &str| CodegenError::InvalidBody {
+            func: decl.name.clone(),
+            block: None,
+            message: String::from(message),
+        };
+
+        let body = match (&decl.ty.kind, body) {
+            (ir::Type::Func(_), Some(body)) => body,
+            (ir::Type::Func(_), None) => return Err(error("the function has no body")),
+            _ => return Err(error("only functions can be compiled on their own")),
+        };
+
+        check_body(&self.mcx, decl, body)?;
+
+        // `run` takes the ids of the functions it compiles, declaring again returns the same one
+        let func_id = B::declare_func(&mut self.mcx, decl);
+
+        Ok(self.compile_func(body, func_id, B::emit_func))
+    }
+
+    /// Translates `body` and hands the finished function to `finish`.
+    fn compile_func<R>(
+        &mut self,
+        body: &'ir ir::Body,
+        func_id: B::Func,
+        finish: impl FnOnce(&mut FunctionCtx<'ir, 'ctx, '_, B>, B::Func) -> R,
+    ) -> R {
+        let builder = B::create_builder(&mut self.mcx.backend, &mut self.func_ctx);
+        let mut fx = FunctionCtx::new(&mut self.mcx, builder, body);
+
+        B::func_prologue(&mut fx);
+
+        // compiled code aborts instead of unwinding, so blocks only reachable from cleanups are left out
+        let reachable = body.reachable();
+        // cold blocks come last, which places them after the others in backends that lay out blocks in the order
+        // they are built, the entry block stays first
+        let (cold, warm): (Vec<_>, Vec<_>) = body
+            .blocks
+            .iter()
+            .filter(|b| reachable.contains(&b.id))
+            .partition(|b| b.hint == ir::BranchHint::Cold && b.id.index() != 0);
+
+        for block in warm.into_iter().chain(cold) {
+            let block_id = fx.blocks[&block.id];
+
+            B::switch_to_block(&mut fx, block_id);
+
+            for stmt in &block.stmts {
+                match stmt {
+                    ir::Stmt::Init(local) => {
+                        let place = fx.locals[local].clone();
+
+                        B::trans_init(&mut fx, place)
+                    }
+                    ir::Stmt::Drop(local) => {
+                        let place = fx.locals[local].clone();
+
+                        B::trans_drop(&mut fx, place)
+                    }
+                    ir::Stmt::Assign(place, rvalue) => {
+                        let place = B::trans_place(&mut fx, place);
+
+                        B::trans_rvalue(&mut fx, place, rvalue);
+                    }
+                    ir::Stmt::SetDiscr(place, val) => {
+                        let place = B::trans_place(&mut fx, place);
+
+                        B::trans_set_discr(&mut fx, place, *val);
+                    }
+                    // source locations are only used for diagnostics
+                    ir::Stmt::Loc(_) => {}
+                    ir::Stmt::Call(rets, func, args, _) => {
+                        let rets = rets
+                            .iter()
+                            .map(|r| B::trans_place(&mut fx, r))
+                            .collect::<Vec<_>>();
+
+                        let args = args
+                            .iter()
+                            .map(|a| B::trans_op(&mut fx, a, None))
+                            .collect::<Vec<_>>();
+
+                        B::trans_call(&mut fx, rets, func, args);
+                    }
+                }
+            }
+
+            B::trans_term(&mut fx, &block.term);
+        }
+
+        finish(&mut fx, func_id)
+    }
+}
+
+/// Checks that every block a function jumps or unwinds to exists and that branches and selects are conditioned
+/// on bools, which the backend relies on. Newtypes are erased by the backend, so this is also where values are
+/// checked to only be used as a newtype, or the other way around, through a cast. Functions called through
+/// a pointer are not declared, so their signatures are checked here.
+fn check_body<'ctx, B: Backend<'ctx>>(mcx: &ModuleCtx<'_, 'ctx, B>, decl: &ir::Decl, body: &ir::Body) -> Result<(), CodegenError> {
+    let ir = mcx.ir;
+    let error = |block, message: String| CodegenError::InvalidBody {
+        func: decl.name.clone(),
+        block,
+        message,
+    };
+
+    if body.blocks.is_empty() {
+        return Err(error(None, String::from("the body has no blocks")));
+    }
+
+    for block in &body.blocks {
+        let cleanups = block.stmts.iter().filter_map(|stmt| match stmt {
+            ir::Stmt::Call(_, _, _, cleanup) => *cleanup,
+            _ => None,
+        });
+
+        for target in block.successors().into_iter().chain(cleanups) {
+            if target.index() >= body.blocks.len() {
+                return Err(error(Some(block.id), format!("%{} does not exist", target.index())));
+            }
+        }
+
+        let conds = block.stmts.iter().filter_map(|stmt| match stmt {
+            ir::Stmt::Assign(_, ir::RValue::Intrinsic(name, ops)) if name == "select" => Some(("select", ops.first())),
+            _ => None,
+        });
+
+        let conds = conds.chain(match &block.term {
+            ir::Term::BrIf(op, ..) => Some(("branch", Some(op))),
+            _ => None,
+        });
+
+        for (what, cond) in conds {
+            let ty = cond.map(|op| ir::operand_type(ir, body, op));
+
+            match ty {
+                Some(ty) if ty.without_newtypes().kind == ir::Type::Bool => {}
+                Some(ty) => return Err(error(Some(block.id), format!("the condition of a {} is a {}, not a bool", what, ir::display_ty(&ty)))),
+                None => return Err(error(Some(block.id), format!("a {} has no condition", what))),
+            }
+        }
+
+        for stmt in &block.stmts {
+            let uses = match stmt {
+                ir::Stmt::Assign(place, ir::RValue::Use(op)) => vec![(ir::place_type(body, place), ir::operand_type(ir, body, op))],
+                ir::Stmt::Call(rets, func, args, _) => {
+                    let sig = ir::operand_type(ir, body, func).signature();
+
+                    if let ir::Operand::Place(_) = func {
+                        B::check_signature(mcx, &sig).map_err(|message| CodegenError::Unsupported {
+                            func: decl.name.clone(),
+                            message: format!("a call in %{}: {}", block.id.index(), message),
+                        })?;
+                    }
+                    let args = sig.params.into_iter().zip(args).map(|(param, arg)| (param, ir::operand_type(ir, body, arg)));
+                    let rets = rets.iter().zip(sig.rets).map(|(place, ret)| (ir::place_type(body, place), ret));
+
+                    args.chain(rets).collect()
+                }
+                _ => Vec::new(),
+            };
+
+            for (to, from) in uses {
+                if mixes_newtypes(&to, &from) {
+                    return Err(error(
+                        Some(block.id),
+                        format!("a {} is used as a {} without a cast", ir::display_ty(&from), ir::display_ty(&to)),
+                    ));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether a value of type `from` is used as a `to` where either is a newtype and the other is a different type.
+/// Generic types can stand for newtypes.
+fn mixes_newtypes(to: &ir::Ty, from: &ir::Ty) -> bool {
+    let is_newtype = |ty: &ir::Ty| matches!(ty.kind, ir::Type::Newtype(_, _));
+    let is_generic = |ty: &ir::Ty| matches!(ty.kind, ir::Type::Opaque(_));
+
+    (is_newtype(to) || is_newtype(from)) && !is_generic(to) && !is_generic(from) && to.kind != from.kind
+}
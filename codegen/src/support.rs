@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+
+/// Something a module uses that a backend has to be able to lower.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// A kind of statement, rvalue or terminator, named as it is written in the text format.
+    Instruction(&'static str),
+    Intrinsic(String),
+    /// The calling convention of a declared function.
+    CallConv(ir::CallConv),
+    /// A variadic function with the given calling convention.
+    Variadic(ir::CallConv),
+    /// A tuple constant with fields used as an operand, which has to be built at runtime.
+    TupleConst,
+    /// A box anywhere in the type of a function, parameter or local.
+    Box,
+    /// A 128-bit integer anywhere in the type of a function, parameter or local.
+    Int128,
+}
+
+/// How often a module uses a feature and where it is first used.
+#[derive(Debug, Clone)]
+pub struct FeatureUse {
+    pub feature: Feature,
+    pub supported: bool,
+    pub count: usize,
+    pub func: String,
+    /// The block of the first use, or `None` if the feature is used by the declaration of `func`.
+    pub block: Option<ir::Block>,
+}
+
+/// The features a module uses, with the ones a backend can not lower,
+/// so that they can be reported together before any code is generated.
+#[derive(Debug, Clone, Default)]
+pub struct SupportReport {
+    /// Every feature used by the module, in the order of their first use.
+    pub features: Vec<FeatureUse>,
+}
+
+impl SupportReport {
+    /// Collects the features of the functions of `module`, asking `supports` about each of them once.
+    /// The bodies of statics are evaluated at compile time, so they are not included.
+    pub fn new(module: &ir::Module, mut supports: impl FnMut(&Feature) -> bool) -> Self {
+        let mut scan = Scan {
+            report: SupportReport::default(),
+            indices: HashMap::new(),
+            supports: &mut supports,
+        };
+
+        for decl in &module.decls {
+            if let ir::Type::Func(sig) = &decl.ty.kind {
+                scan.add(Feature::CallConv(sig.call_conv), &decl.name, None);
+
+                if sig.variadic {
+                    scan.add(Feature::Variadic(sig.call_conv), &decl.name, None);
+                }
+
+                for feature in type_features(&decl.ty) {
+                    scan.add(feature, &decl.name, None);
+                }
+            }
+        }
+
+        for body in &module.bodies {
+            let decl = &module.decls[body.decl];
+
+            if !matches!(decl.ty.kind, ir::Type::Func(_)) {
+                continue;
+            }
+
+            for local in &body.locals {
+                for feature in type_features(&local.ty) {
+                    scan.add(feature, &decl.name, None);
+                }
+            }
+
+            for block in &body.blocks {
+                for stmt in &block.stmts {
+                    for feature in stmt_features(stmt) {
+                        scan.add(feature, &decl.name, Some(block.id));
+                    }
+                }
+
+                for feature in term_features(&block.term) {
+                    scan.add(feature, &decl.name, Some(block.id));
+                }
+            }
+        }
+
+        scan.report
+    }
+
+    pub fn is_supported(&self) -> bool {
+        self.features.iter().all(|f| f.supported)
+    }
+
+    pub fn unsupported(&self) -> impl Iterator<Item = &FeatureUse> {
+        self.features.iter().filter(|f| !f.supported)
+    }
+
+    pub fn count(&self, feature: &Feature) -> usize {
+        self.features.iter().find(|f| f.feature == *feature).map_or(0, |f| f.count)
+    }
+}
+
+struct Scan<'a> {
+    report: SupportReport,
+    indices: HashMap<Feature, usize>,
+    supports: &'a mut dyn FnMut(&Feature) -> bool,
+}
+
+impl Scan<'_> {
+    fn add(&mut self, feature: Feature, func: &str, block: Option<ir::Block>) {
+        if let Some(&i) = self.indices.get(&feature) {
+            self.report.features[i].count += 1;
+            return;
+        }
+
+        self.indices.insert(feature.clone(), self.report.features.len());
+        self.report.features.push(FeatureUse {
+            supported: (self.supports)(&feature),
+            feature,
+            count: 1,
+            func: func.to_string(),
+            block,
+        });
+    }
+}
+
+fn stmt_features(stmt: &ir::Stmt) -> Vec<Feature> {
+    let mut features = Vec::new();
+
+    match stmt {
+        ir::Stmt::Init(_) => features.push(Feature::Instruction("init")),
+        ir::Stmt::Drop(_) => features.push(Feature::Instruction("drop")),
+        ir::Stmt::SetDiscr(..) => features.push(Feature::Instruction("set_discr")),
+        ir::Stmt::Loc(_) => {}
+        ir::Stmt::Call(_, func, args, _) => {
+            features.push(Feature::Instruction("call"));
+            features.extend(op_features(func));
+            features.extend(args.iter().flat_map(op_features));
+        }
+        ir::Stmt::Assign(_, rvalue) => match rvalue {
+            ir::RValue::Use(op) => {
+                features.push(Feature::Instruction("use"));
+                features.extend(op_features(op));
+            }
+            ir::RValue::AddrOf(_) => features.push(Feature::Instruction("addrof")),
+            ir::RValue::Lea { base, index, .. } => {
+                features.push(Feature::Instruction("lea"));
+                features.extend(op_features(base));
+                features.extend(index.iter().flat_map(op_features));
+            }
+            ir::RValue::UnOp(op, val) => {
+                features.push(Feature::Instruction(match op {
+                    ir::UnOp::Neg => "neg",
+                    ir::UnOp::Not => "not",
+                    ir::UnOp::BitNot => "bitnot",
+                }));
+                features.extend(op_features(val));
+            }
+            ir::RValue::GetDiscr(_) => features.push(Feature::Instruction("get_discr")),
+            ir::RValue::NullOp(ir::NullOp::SizeOf, _) => features.push(Feature::Instruction("size_of")),
+            ir::RValue::NullOp(ir::NullOp::AlignOf, _) => features.push(Feature::Instruction("align_of")),
+            ir::RValue::Cast(..) => features.push(Feature::Instruction("cast")),
+            ir::RValue::Intrinsic(name, args) => {
+                features.push(Feature::Intrinsic(name.clone()));
+                features.extend(args.iter().flat_map(op_features));
+            }
+        },
+    }
+
+    features
+}
+
+fn term_features(term: &ir::Term) -> Vec<Feature> {
+    match term {
+        ir::Term::Abort => vec![Feature::Instruction("abort")],
+        ir::Term::Return => vec![Feature::Instruction("return")],
+        ir::Term::Resume => vec![Feature::Instruction("resume")],
+        ir::Term::Jump(_) => vec![Feature::Instruction("jump")],
+        ir::Term::Switch(op, ..) => std::iter::once(Feature::Instruction("switch")).chain(op_features(op)).collect(),
+        ir::Term::BrIf(op, ..) => std::iter::once(Feature::Instruction("br_if")).chain(op_features(op)).collect(),
+    }
+}
+
+/// The features needed to hold values of `ty`, each at most once.
+fn type_features(ty: &ir::Ty) -> Vec<Feature> {
+    fn walk(ty: &ir::Ty, features: &mut Vec<Feature>) {
+        let found = match &ty.kind {
+            ir::Type::U128 | ir::Type::I128 => Some(Feature::Int128),
+            ir::Type::Box(_) => Some(Feature::Box),
+            _ => None,
+        };
+
+        if let Some(feature) = found {
+            if !features.contains(&feature) {
+                features.push(feature);
+            }
+        }
+
+        match &ty.kind {
+            ir::Type::Ptr(ty) | ir::Type::Box(ty) | ir::Type::Vector(ty, _) | ir::Type::Newtype(_, ty) => walk(ty, features),
+            ir::Type::Tuple(tys) | ir::Type::Union(tys) | ir::Type::Tagged(tys) => tys.iter().for_each(|ty| walk(ty, features)),
+            ir::Type::Func(sig) => sig.params.iter().chain(&sig.rets).for_each(|ty| walk(ty, features)),
+            _ => {}
+        }
+    }
+
+    let mut features = Vec::new();
+
+    walk(ty, &mut features);
+    features
+}
+
+fn op_features(op: &ir::Operand) -> Option<Feature> {
+    match op {
+        ir::Operand::Const(ir::Const::Tuple(vals)) if !vals.is_empty() => Some(Feature::TupleConst),
+        _ => None,
+    }
+}
+
+impl std::fmt::Display for Feature {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Feature::Instruction(name) => write!(f, "instruction '{}'", name),
+            Feature::Intrinsic(name) => write!(f, "intrinsic #{}", name),
+            Feature::CallConv(conv) => write!(f, "calling convention {}", conv.name()),
+            Feature::Variadic(conv) => write!(f, "variadic functions with calling convention {}", conv.name()),
+            Feature::TupleConst => write!(f, "tuple constants as operands"),
+            Feature::Box => write!(f, "boxes"),
+            Feature::Int128 => write!(f, "128-bit integers"),
+        }
+    }
+}
+
+impl std::fmt::Display for FeatureUse {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let uses = if self.count == 1 { "use" } else { "uses" };
+
+        match self.block {
+            Some(block) => write!(f, "{} ({} {}, first in {} in %{})", self.feature, self.count, uses, self.func, block.index()),
+            None => write!(f, "{} ({} {}, first by {})", self.feature, self.count, uses, self.func),
+        }
+    }
+}
+
+impl std::fmt::Display for SupportReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for feature in self.unsupported() {
+            writeln!(f, "unsupported {}", feature)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+//! The functions that manage boxes, generated into every module that uses them so that
+//! objects and jitted code do not need the runtime library to be linked in.
+//!
+//! A box points to a header of three words: the value, the strong count and the weak count,
+//! the same layout as `BoxInner` in the runtime. The value is null once it has been freed.
+
+use crate::*;
+use clif::{InstBuilder, Module};
+
+/// Calls the box function `name`, generating it on first use.
+pub(crate) fn call<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>, name: &'static str, args: &[clif::Value]) -> Option<clif::Value> {
+    let func = match fx.mcx.box_fns.get(name) {
+        | Some(func) => *func,
+        | None => {
+            let func = define(fx.mcx, name);
+
+            fx.mcx.box_fns.insert(name, func);
+            func
+        },
+    };
+
+    let func = fx.mcx.module.declare_func_in_func(func, &mut fx.bcx.func);
+    let inst = fx.bcx.ins().call(func, args);
+
+    fx.bcx.inst_results(inst).first().copied()
+}
+
+fn define<'ctx>(mcx: &mut ModuleCtx<'_, 'ctx, ClifBackend<'ctx>>, name: &str) -> clif::FuncId {
+    let ptr_type = mcx.module.target_config().pointer_type();
+    let word = ptr_type.bytes() as i32;
+    let flags = clif::MemFlags::trusted();
+    let mut ctx = clif::Context::new();
+    let mut builder = clif::FunctionBuilderContext::new();
+
+    ctx.func.signature = signature(mcx, matches!(name, "box_alloc" | "box_addr_checked"));
+
+    let func = mcx
+        .module
+        .declare_function(&format!("__lowlang_{}", name), clif::Linkage::Local, &ctx.func.signature)
+        .unwrap();
+
+    let malloc = import(mcx, &mut ctx.func, "malloc", true);
+    let free = import(mcx, &mut ctx.func, "free", false);
+    let mut bcx = clif::FunctionBuilder::new(&mut ctx.func, &mut builder);
+    let entry = bcx.create_block();
+
+    bcx.append_block_params_for_function_params(entry);
+    bcx.switch_to_block(entry);
+
+    let arg = bcx.block_params(entry)[0];
+
+    match name {
+        | "box_alloc" => {
+            // the value is never null, because a null value marks a freed box
+            let one = bcx.ins().iconst(ptr_type, 1);
+            let zero = bcx.ins().iconst(ptr_type, 0);
+            let is_empty = bcx.ins().icmp_imm(clif::IntCC::Equal, arg, 0);
+            let size = bcx.ins().select(is_empty, one, arg);
+            let inst = bcx.ins().call(malloc, &[size]);
+            let val = bcx.inst_results(inst)[0];
+            let size = bcx.ins().iconst(ptr_type, i64::from(word) * 3);
+            let inst = bcx.ins().call(malloc, &[size]);
+            let ptr = bcx.inst_results(inst)[0];
+
+            bcx.ins().trapz(val, clif::TrapCode::HeapOutOfBounds);
+            bcx.ins().trapz(ptr, clif::TrapCode::HeapOutOfBounds);
+            bcx.ins().store(flags, val, ptr, 0);
+            bcx.ins().store(flags, one, ptr, word);
+            bcx.ins().store(flags, zero, ptr, word * 2);
+            bcx.ins().return_(&[ptr]);
+        },
+        | "box_copy" => {
+            let strong_count = bcx.ins().load(ptr_type, flags, arg, word);
+            let strong_count = bcx.ins().iadd_imm(strong_count, 1);
+
+            bcx.ins().store(flags, strong_count, arg, word);
+            bcx.ins().return_(&[]);
+        },
+        | "box_free" => {
+            let free_val = bcx.create_block();
+            let free_header = bcx.create_block();
+            let exit = bcx.create_block();
+            let strong_count = bcx.ins().load(ptr_type, flags, arg, word);
+            let strong_count = bcx.ins().iadd_imm(strong_count, -1);
+
+            bcx.ins().store(flags, strong_count, arg, word);
+            bcx.ins().brnz(strong_count, exit, &[]);
+            bcx.ins().jump(free_val, &[]);
+
+            bcx.switch_to_block(free_val);
+
+            let val = bcx.ins().load(ptr_type, flags, arg, 0);
+            let null = bcx.ins().iconst(ptr_type, 0);
+
+            bcx.ins().call(free, &[val]);
+            bcx.ins().store(flags, null, arg, 0);
+
+            // weak references still need the header to see that the value is gone
+            let weak_count = bcx.ins().load(ptr_type, flags, arg, word * 2);
+
+            bcx.ins().brnz(weak_count, exit, &[]);
+            bcx.ins().jump(free_header, &[]);
+
+            bcx.switch_to_block(free_header);
+            bcx.ins().call(free, &[arg]);
+            bcx.ins().jump(exit, &[]);
+
+            bcx.switch_to_block(exit);
+            bcx.ins().return_(&[]);
+        },
+        | "box_addr_checked" => {
+            let val = bcx.ins().load(ptr_type, flags, arg, 0);
+
+            // a use after free
+            bcx.ins().trapz(val, clif::TrapCode::User(0));
+            bcx.ins().return_(&[val]);
+        },
+        | _ => unreachable!("unknown box function {}", name),
+    }
+
+    bcx.seal_all_blocks();
+    bcx.finalize();
+
+    mcx.module
+        .define_function(func, &mut ctx, &mut clif::codegen::binemit::NullTrapSink {})
+        .unwrap();
+
+    func
+}
+
+/// A signature taking one word, returning one word if `ret` is set.
+fn signature<'ctx>(mcx: &ModuleCtx<'_, 'ctx, ClifBackend<'ctx>>, ret: bool) -> clif::Signature {
+    let ptr_type = mcx.module.target_config().pointer_type();
+    let mut sig = mcx.module.make_signature();
+
+    sig.params.push(clif::AbiParam::new(ptr_type));
+
+    if ret {
+        sig.returns.push(clif::AbiParam::new(ptr_type));
+    }
+
+    sig
+}
+
+fn import<'ctx>(mcx: &mut ModuleCtx<'_, 'ctx, ClifBackend<'ctx>>, func: &mut clif::ir::Function, name: &str, ret: bool) -> clif::ir::FuncRef {
+    let sig = signature(mcx, ret);
+    let id = mcx.module.declare_function(name, clif::Linkage::Import, &sig).unwrap();
+
+    mcx.module.declare_func_in_func(id, func)
+}
@@ -1,5 +1,21 @@
 use super::*;
 use clif::Module;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// The contents of an anonymous constant. Identical constants share their data.
+#[derive(PartialEq, Eq, Hash)]
+pub(crate) struct ConstData {
+    bytes: Vec<u8>,
+    relocs: Vec<(u32, Reloc)>,
+}
+
+/// An address written into a constant at an offset.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Reloc {
+    Func(clif::FuncId),
+    Data(clif::DataId),
+}
 
 impl<'ctx> ConstMethods<'ctx> for ClifBackend<'ctx> {
     type Backend = Self;
@@ -10,23 +26,18 @@ impl<'ctx> ConstMethods<'ctx> for ClifBackend<'ctx> {
         layout: ir::layout::TyLayout,
         data_id: Option<clif::DataId>,
     ) -> clif::DataId {
-        let data_id = data_id.unwrap_or_else(|| {
-            let id = mcx
-                .module
-                .declare_data(
-                    &format!("__const_{}", mcx.backend.anon_count),
-                    clif::Linkage::Local,
-                    false,
-                    false,
-                )
-                .unwrap();
-
-            mcx.backend.anon_count += 1;
-            id
-        });
+        if data_id.is_none() {
+            if let Some((ty, id)) = mcx.consts.get(c) {
+                if *ty == layout.ty {
+                    return *id;
+                }
+            }
+        }
 
+        let ty = layout.ty.clone();
         let mut dcx = clif::DataContext::new();
         let mut bytes = Vec::with_capacity(layout.size.bytes() as usize);
+        let mut relocs = Vec::new();
 
         fn rec<'ctx>(
             mcx: &mut ModuleCtx<'_, 'ctx, ClifBackend<'ctx>>,
@@ -34,28 +45,39 @@ impl<'ctx> ConstMethods<'ctx> for ClifBackend<'ctx> {
             c: &ir::Const,
             layout: ir::layout::TyLayout,
             bytes: &mut Vec<u8>,
+            relocs: &mut Vec<(u32, Reloc)>,
         ) {
             match c {
                 ir::Const::Undefined(_) => {
                     bytes.resize(bytes.len() + layout.size.bytes() as usize, 0)
                 }
                 ir::Const::Scalar(s, _) => {
-                    bytes.extend(&s.to_ne_bytes()[..layout.size.bytes() as usize])
+                    let start = bytes.len();
+
+                    bytes.resize(start + layout.size.bytes() as usize, 0);
+                    ir::layout::write_scalar(*s, &mut bytes[start..], &mcx.target);
                 }
+                ir::Const::Bytes(b) => bytes.extend(&b[..]),
                 ir::Const::Addr(id) => {
                     if let Some((id, _)) = mcx.func_ids.get(id) {
                         let func = mcx.module.declare_func_in_data(*id, dcx);
 
+                        relocs.push((bytes.len() as u32, Reloc::Func(*id)));
                         dcx.write_function_addr(bytes.len() as u32, func);
                         bytes.extend(vec![0; layout.size.bytes() as usize]);
                     } else {
-                        unimplemented!();
+                        let global = mcx.module.declare_data_in_data(mcx.data_ids[id], dcx);
+
+                        relocs.push((bytes.len() as u32, Reloc::Data(mcx.data_ids[id])));
+                        dcx.write_data_addr(bytes.len() as u32, global, 0);
+                        bytes.extend(vec![0; layout.size.bytes() as usize]);
                     }
                 }
                 ir::Const::Ptr(to) => {
                     let id = ClifBackend::alloc_const(mcx, to, layout.pointee(&mcx.target), None);
                     let global = mcx.module.declare_data_in_data(id, dcx);
 
+                    relocs.push((bytes.len() as u32, Reloc::Data(id)));
                     dcx.write_data_addr(bytes.len() as u32, global, 0);
                     bytes.extend(vec![0; layout.size.bytes() as usize]);
                 }
@@ -70,7 +92,7 @@ impl<'ctx> ConstMethods<'ctx> for ClifBackend<'ctx> {
                             let field = layout.field(j, &mcx.target);
 
                             i += field.size.bytes() as usize;
-                            rec(mcx, dcx, c, field, bytes);
+                            rec(mcx, dcx, c, field, bytes, relocs);
                         }
                     }
                     _ => unimplemented!(),
@@ -86,7 +108,7 @@ impl<'ctx> ConstMethods<'ctx> for ClifBackend<'ctx> {
                                     let variant = layout.variant(*idx);
                                     let mut i = tag_layout.size.bytes();
 
-                                    rec(mcx, dcx, &ir::Const::Scalar(*idx as u128, tag_layout.ty.clone()), tag_layout, bytes);
+                                    rec(mcx, dcx, &ir::Const::Scalar(*idx as u128, tag_layout.ty.clone()), tag_layout, bytes, relocs);
 
                                     for (j, (c, offset)) in cs.iter().zip(offsets.iter().skip(1)).enumerate() {
                                         bytes.extend(vec![0; (offset.bytes() - i) as usize]);
@@ -95,10 +117,42 @@ impl<'ctx> ConstMethods<'ctx> for ClifBackend<'ctx> {
                                         let field = variant.field(j, &mcx.target);
 
                                         i += field.size.bytes();
-                                        rec(mcx, dcx, c, field, bytes);
+                                        rec(mcx, dcx, c, field, bytes, relocs);
+                                    }
+                                },
+                                ir::layout::TagEncoding::Niche {
+                                    dataful_variant,
+                                    niche_variants,
+                                    niche_start,
+                                } => {
+                                    let start = bytes.len();
+
+                                    if idx == dataful_variant {
+                                        // the payload is stored as is, its niche holds no other variant
+                                        let variant = layout.variant(*idx);
+
+                                        if let ir::layout::FieldsShape::Arbitrary { offsets } = &variant.fields {
+                                            for (j, (c, offset)) in cs.iter().zip(offsets).enumerate() {
+                                                bytes.resize(start + offset.bytes() as usize, 0);
+
+                                                let field = variant.field(j, &mcx.target);
+
+                                                rec(mcx, dcx, c, field, bytes, relocs);
+                                            }
+                                        }
+
+                                        bytes.resize(start + layout.size.bytes() as usize, 0);
+                                    } else {
+                                        let tag_layout = layout.field(*tag_field, &mcx.target);
+                                        let tag_offset = start + offsets[*tag_field].bytes() as usize;
+                                        let tag_size = tag_layout.size.bytes() as usize;
+                                        let niche_value = (*idx - *niche_variants.start()) as u128;
+                                        let niche_value = niche_value.wrapping_add(*niche_start);
+
+                                        bytes.resize(start + layout.size.bytes() as usize, 0);
+                                        ir::layout::write_scalar(niche_value, &mut bytes[tag_offset..tag_offset + tag_size], &mcx.target);
                                     }
                                 },
-                                ir::layout::TagEncoding::Niche { .. } => unreachable!(),
                             }
                         }
                     },
@@ -111,11 +165,61 @@ impl<'ctx> ConstMethods<'ctx> for ClifBackend<'ctx> {
             }
         }
 
-        rec(mcx, &mut dcx, c, layout, &mut bytes);
-
+        rec(mcx, &mut dcx, c, layout, &mut bytes, &mut relocs);
         bytes.resize(bytes.capacity(), 0);
+
+        let data_id = match data_id {
+            Some(id) => id,
+            None => {
+                let data = ConstData { bytes, relocs };
+
+                if let Some(id) = mcx.backend.anon_consts.get(&data) {
+                    let id = *id;
+
+                    mcx.consts.insert(c.clone(), (ty, id));
+                    return id;
+                }
+
+                let id = declare_anon(mcx, &data);
+
+                bytes = data.bytes.clone();
+                mcx.backend.anon_consts.insert(data, id);
+                mcx.consts.insert(c.clone(), (ty, id));
+                id
+            }
+        };
+
+        if let Some(section) = mcx.sections.get(&data_id) {
+            // the segment is only used by Mach-O
+            dcx.set_segment_section("__DATA", section);
+        }
+
         dcx.define(bytes.into());
         mcx.module.define_data(data_id, &dcx).unwrap();
         data_id
     }
 }
+
+/// Declares the data of an anonymous constant under a name derived from its contents,
+/// prefixed with the name of the module if it has one.
+fn declare_anon<'ctx>(mcx: &mut ModuleCtx<'_, 'ctx, ClifBackend<'ctx>>, data: &ConstData) -> clif::DataId {
+    let mut hasher = DefaultHasher::new();
+
+    data.hash(&mut hasher);
+
+    let base = match &mcx.options.module_name {
+        Some(module) => format!("__const.{}.{:016x}", module, hasher.finish()),
+        None => format!("__const.{:016x}", hasher.finish()),
+    };
+
+    let mut name = base.clone();
+    let mut n = 1;
+
+    // a different constant with the same hash
+    while mcx.module.get_name(&name).is_some() {
+        name = format!("{}.{}", base, n);
+        n += 1;
+    }
+
+    mcx.module.declare_data(&name, clif::Linkage::Local, false, false).unwrap()
+}
@@ -5,48 +5,50 @@ impl<'ctx> DeclMethods<'ctx> for ClifBackend<'ctx> {
     type Backend = Self;
 
     fn declare_static(mcx: &mut ModuleCtx<'_, 'ctx, ClifBackend<'ctx>>, decl: &ir::Decl) -> clif::DataId {
-        let linkage = match decl.linkage {
-            | ir::Linkage::Import => clif::Linkage::Import,
-            | ir::Linkage::Export => clif::Linkage::Export,
-            | ir::Linkage::Hidden => clif::Linkage::Hidden,
-            | ir::Linkage::Local => clif::Linkage::Local,
-        };
-
+        let linkage = linkage(mcx, decl);
         let data = mcx.module.declare_data(&decl.name, linkage, true, false).unwrap();
 
         mcx.data_ids.insert(decl.id, data);
 
+        if let Some(section) = &decl.attrs.section {
+            mcx.sections.insert(data, section.clone());
+        }
+
         data
     }
 
     fn declare_func(mcx: &mut ModuleCtx<'_, 'ctx, ClifBackend<'ctx>>, decl: &ir::Decl) -> clif::FuncId {
-        let linkage = match decl.linkage {
-            | ir::Linkage::Import => clif::Linkage::Import,
-            | ir::Linkage::Export => clif::Linkage::Export,
-            | ir::Linkage::Hidden => clif::Linkage::Hidden,
-            | ir::Linkage::Local => clif::Linkage::Local,
-        };
-
-        let sig = mk_signature(mcx, &decl.ty.signature());
+        let linkage = linkage(mcx, decl);
+        let sig = mk_signature(mcx, &decl.ty.signature()).unwrap_or_else(|e| panic!("{}: {}", decl.name, e));
         let func = mcx.module.declare_function(&decl.name, linkage, &sig).unwrap();
 
         mcx.func_ids.insert(decl.id, (func, sig));
 
+        // placed functions are added to the finished object, a jit defines them like any other
+        if (decl.attrs.cold || decl.attrs.align.is_some()) && matches!(mcx.module, ClifModule::Object(_)) {
+            mcx.placements.insert(func, (decl.attrs.cold, decl.attrs.align));
+        }
+
         func
     }
 
     fn define_func(fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>, func: clif::FuncId) {
-        fx.bcx.seal_all_blocks();
-        fx.bcx.finalize();
-        fx.ctx.compute_cfg();
-        fx.ctx.compute_domtree();
+        if fx.placements.contains_key(&func) {
+            let artifact = Self::emit_func(fx, func);
+
+            fx.mcx.placed.push((func, artifact));
+            return;
+        }
 
-        // println!("{}", fx.module.declarations().get_function_decl(func).name);
-        // println!("{}", fx.bcx.func);
+        finish_func(fx);
 
-        fx.mcx.ctx.eliminate_unreachable_code(fx.mcx.module.isa()).unwrap();
+        if fx.options.threads > 1 {
+            let translated = std::mem::replace(&mut fx.mcx.ctx.func, clif::ir::Function::new());
 
-        fx.mcx.ctx.dce(fx.mcx.module.isa()).unwrap();
+            fx.pending.push((func, translated));
+            fx.ctx.clear();
+            return;
+        }
 
         fx.mcx
             .module
@@ -56,6 +58,38 @@ impl<'ctx> DeclMethods<'ctx> for ClifBackend<'ctx> {
         fx.ctx.clear();
     }
 
+    fn emit_func(fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>, func: clif::FuncId) -> artifact::FuncArtifact {
+        use clif::codegen::binemit::{NullStackMapSink, NullTrapSink};
+
+        finish_func(fx);
+
+        let mut code = Vec::new();
+        let mut relocs = RelocCollector(Vec::new());
+        let mcx = &mut *fx.mcx;
+
+        mcx.ctx
+            .compile_and_emit(mcx.module.isa(), &mut code, &mut relocs, &mut NullTrapSink {}, &mut NullStackMapSink {})
+            .unwrap();
+
+        mcx.ctx.clear();
+
+        artifact::FuncArtifact {
+            name: mcx.module.declarations().get_function_decl(func).name.clone(),
+            code,
+            relocs: relocs.into_artifact(&mcx.module),
+        }
+    }
+
+    fn define_artifact(mcx: &mut ModuleCtx<'_, 'ctx, ClifBackend<'ctx>>, func: clif::FuncId, artifact: &artifact::FuncArtifact) {
+        if mcx.placements.contains_key(&func) {
+            mcx.placed.push((func, artifact.clone()));
+            return;
+        }
+
+        mcx.module.define_function_bytes(func, &artifact.code).unwrap();
+        mcx.backend.relocs.push((func, artifact.relocs.clone()));
+    }
+
     fn func_prologue(fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>) {
         use clif::InstBuilder;
         let sig = fx.func_ids[&fx.body.decl].1.clone();
@@ -70,33 +104,42 @@ impl<'ctx> DeclMethods<'ctx> for ClifBackend<'ctx> {
 
         let ssa_map = analyze::analyze(fx);
         let ptr_ty = fx.module.target_config().pointer_type();
+        let conv = fx.ir.decls[fx.body.decl].ty.signature().call_conv;
 
-        for ret in fx.body.rets() {
-            let layout = ir::layout::layout_of(&ret.ty, &fx.target);
+        let rets = fx.body.rets().map(|ret| (ret.id, ir::layout::layout_of(&ret.ty, &fx.target))).collect::<Vec<_>>();
+        let ret_layouts = rets.iter().map(|(_, layout)| layout.clone()).collect::<Vec<_>>();
 
-            match abi::get_pass_mode(fx.mcx, &layout) {
+        for ((id, layout), mode) in rets.into_iter().zip(abi::get_ret_modes(fx.mcx, &ret_layouts, conv)) {
+            match mode {
                 | abi::PassMode::NoPass => {
-                    fx.locals.insert(ret.id, place::Place::no_place(layout));
+                    fx.locals.insert(id, place::Place::no_place(layout));
                 },
                 | abi::PassMode::ByVal(_) | abi::PassMode::ByValPair(_, _) => {
-                    let ssa = ssa_map[&ret.id] == analyze::SsaKind::Ssa;
+                    let ssa = ssa_map[&id] == analyze::SsaKind::Ssa;
+
+                    local_place(fx, id, layout, ssa);
+                },
+                | abi::PassMode::Cast(_, _) => {
+                    let place = cast_place(fx, layout);
 
-                    local_place(fx, ret.id, layout, ssa);
+                    fx.locals.insert(id, place);
                 },
                 | abi::PassMode::ByRef { size: _ } => {
                     let val = fx.bcx.append_block_param(start_block, ptr_ty);
 
-                    fx.locals.insert(ret.id, place::Place::new_ref(ptr::Pointer::addr(val), layout));
+                    fx.locals.insert(id, place::Place::new_ref(ptr::Pointer::addr(val), layout));
                 },
             }
         }
 
+        // block parameters can only be added before the first instruction
+        let mut casts = Vec::new();
         let vals = fx
             .body
             .args()
             .filter_map(|arg| {
                 let layout = ir::layout::layout_of(&arg.ty, &fx.target);
-                let value = match abi::get_pass_mode(fx.mcx, &layout) {
+                let value = match abi::get_pass_mode_for(fx.mcx, &layout, conv) {
                     | abi::PassMode::NoPass => return None,
                     | abi::PassMode::ByVal(ty) => {
                         let param = fx.bcx.append_block_param(start_block, ty);
@@ -109,6 +152,17 @@ impl<'ctx> DeclMethods<'ctx> for ClifBackend<'ctx> {
 
                         value::Value::new_val_pair(a, b, layout.clone())
                     },
+                    | abi::PassMode::Cast(a, b) => {
+                        let regs = std::iter::once(a)
+                            .chain(b)
+                            .map(|ty| fx.bcx.append_block_param(start_block, ty))
+                            .collect::<Vec<_>>();
+                        let place = cast_place(fx, layout.clone());
+                        let value = value::Value::new_ref(place.as_ptr(), layout.clone());
+
+                        casts.push((place, regs));
+                        value
+                    },
                     | abi::PassMode::ByRef { size: Some(_) } => {
                         let param = fx.bcx.append_block_param(start_block, ptr_ty);
 
@@ -127,6 +181,10 @@ impl<'ctx> DeclMethods<'ctx> for ClifBackend<'ctx> {
             })
             .collect::<Vec<_>>();
 
+        for (place, regs) in casts {
+            cast_from_regs(fx, &place, &regs);
+        }
+
         for (arg, value) in fx.body.args().zip(vals) {
             let ssa = ssa_map[&arg.id] == analyze::SsaKind::Ssa;
             let place = if ssa {
@@ -177,3 +235,185 @@ impl<'ctx> DeclMethods<'ctx> for ClifBackend<'ctx> {
         }
     }
 }
+
+fn linkage<'ctx>(mcx: &ModuleCtx<'_, 'ctx, ClifBackend<'ctx>>, decl: &ir::Decl) -> clif::Linkage {
+    match decl.linkage {
+        | ir::Linkage::Import => clif::Linkage::Import,
+        | ir::Linkage::Export => match decl.attrs.visibility {
+            | ir::Visibility::Default if mcx.module.isa().flags().is_pic() => clif::Linkage::Preemptible,
+            // protected symbols are marked as such when the object is finished
+            | ir::Visibility::Default | ir::Visibility::Protected => clif::Linkage::Export,
+            | ir::Visibility::Hidden => clif::Linkage::Hidden,
+        },
+        | ir::Linkage::Hidden => clif::Linkage::Hidden,
+        | ir::Linkage::Local => clif::Linkage::Local,
+    }
+}
+
+/// Cranelift can not express every visibility, so the symbols are fixed up in the object.
+/// Preemptible symbols are defined as weak by cranelift, while an exported symbol should be global,
+/// and ELF objects get the protected visibility for protected declarations.
+pub(crate) fn fix_visibility(module: &ir::Module, backend: &ClifBackend, product: &mut cranelift_object::ObjectProduct) {
+    use object::SymbolFlags;
+
+    let elf = product.object.format() == object::BinaryFormat::Elf;
+
+    for decl in module.decls.iter().filter(|d| d.linkage == ir::Linkage::Export) {
+        let symbol = if let Some((func, _)) = backend.func_ids.get(&decl.id) {
+            product.functions[*func]
+        } else if let Some(data) = backend.data_ids.get(&decl.id) {
+            product.data_objects[*data]
+        } else {
+            None
+        };
+
+        let symbol = match symbol {
+            | Some((symbol, true)) => product.object.symbol_mut(symbol),
+            | _ => continue,
+        };
+
+        symbol.weak = false;
+
+        if elf && decl.attrs.visibility == ir::Visibility::Protected {
+            let kind = match symbol.kind {
+                | object::SymbolKind::Text => object::elf::STT_FUNC,
+                | _ => object::elf::STT_OBJECT,
+            };
+
+            symbol.flags = SymbolFlags::Elf {
+                st_info: (object::elf::STB_GLOBAL << 4) + kind,
+                st_other: object::elf::STV_PROTECTED,
+            };
+        }
+    }
+}
+
+/// Defines the functions with a placement in the finished object, which cranelift can not do itself: every one
+/// at its alignment, and the cold ones in a text section of their own so they do not sit between the functions
+/// that run. Returns their relocations for `parallel::add_relocs`.
+pub(crate) fn define_placed(
+    backend: &ClifBackend,
+    placed: Vec<(clif::FuncId, artifact::FuncArtifact)>,
+    product: &mut cranelift_object::ObjectProduct,
+) -> Vec<(clif::FuncId, Vec<artifact::Reloc>)> {
+    let mut cold_section = None;
+    let mut relocs = Vec::new();
+
+    for (func, artifact) in placed {
+        let (cold, align) = backend.placements[&func];
+        let section = if cold {
+            *cold_section.get_or_insert_with(|| {
+                let name = match product.object.format() {
+                    | object::BinaryFormat::MachO => "__text_cold",
+                    | object::BinaryFormat::Coff => ".text$unlikely",
+                    | _ => ".text.unlikely",
+                };
+
+                // the segment is only used by Mach-O
+                product.object.add_section(b"__TEXT".to_vec(), name.as_bytes().to_vec(), object::SectionKind::Text)
+            })
+        } else {
+            product.object.section_id(object::write::StandardSection::Text)
+        };
+
+        let symbol = product.function_symbol(func);
+
+        product.object.add_symbol_data(symbol, section, &artifact.code, align.unwrap_or(1));
+        product.functions[func] = Some((symbol, true));
+        relocs.push((func, artifact.relocs));
+    }
+
+    relocs
+}
+
+/// Records what the module requires in a section that is not loaded, which the linker and the jit check.
+pub(crate) fn add_capabilities(module: &ir::Module, product: &mut cranelift_object::ObjectProduct) {
+    let caps = ir::capability::Capabilities::of(module);
+    // the segment is only used by Mach-O
+    let section = product.object.add_section(
+        b"__DATA".to_vec(),
+        ir::capability::SECTION.as_bytes().to_vec(),
+        object::SectionKind::Other,
+    );
+
+    product.object.append_section_data(section, &caps.to_bytes(), 4);
+}
+
+/// Seals the function being built, moves it into the module's context and removes the code it does not need.
+fn finish_func<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>) {
+    fx.bcx.seal_all_blocks();
+    fx.bcx.finalize();
+    fx.mcx.ctx.func = std::mem::replace(fx.bcx.func, clif::ir::Function::new());
+    fx.ctx.compute_cfg();
+    fx.ctx.compute_domtree();
+
+    dump_func(fx);
+
+    fx.mcx.ctx.eliminate_unreachable_code(fx.mcx.module.isa()).unwrap();
+
+    fx.mcx.ctx.dce(fx.mcx.module.isa()).unwrap();
+}
+
+/// Keeps the relocations against other symbols. Blocks, constants and jump tables are placed
+/// in the function's own code by the backends this crate supports, so those are resolved already.
+pub(crate) struct RelocCollector(pub(crate) Vec<(u32, clif::codegen::binemit::Reloc, clif::ir::ExternalName, i64)>);
+
+impl RelocCollector {
+    /// Names the relocations by symbol, sorted by offset. Kinds are named by their `Debug` output,
+    /// which unlike their `Display` output includes the architecture.
+    pub(crate) fn into_artifact(self, module: &ClifModule) -> Vec<artifact::Reloc> {
+        let decls = module.declarations();
+        let mut relocs = self
+            .0
+            .into_iter()
+            .map(|(offset, kind, name, addend)| artifact::Reloc {
+                offset,
+                kind: format!("{:?}", kind),
+                symbol: match name {
+                    | clif::ir::ExternalName::LibCall(call) => clif::default_libcall_names()(call),
+                    | name if decls.is_function(&name) => decls.get_function_decl(decls.get_function_id(&name)).name.clone(),
+                    | name => decls.get_data_decl(decls.get_data_id(&name)).name.clone(),
+                },
+                addend,
+            })
+            .collect::<Vec<_>>();
+
+        relocs.sort_by_key(|r| r.offset);
+        relocs
+    }
+}
+
+impl clif::codegen::binemit::RelocSink for RelocCollector {
+    fn reloc_block(&mut self, _: u32, _: clif::codegen::binemit::Reloc, _: u32) {}
+
+    fn reloc_external(
+        &mut self,
+        offset: u32,
+        _: clif::ir::SourceLoc,
+        kind: clif::codegen::binemit::Reloc,
+        name: &clif::ir::ExternalName,
+        addend: i64,
+    ) {
+        self.0.push((offset, kind, name.clone(), addend));
+    }
+
+    fn reloc_constant(&mut self, _: u32, _: clif::codegen::binemit::Reloc, _: u32) {}
+
+    fn reloc_jt(&mut self, _: u32, _: clif::codegen::binemit::Reloc, _: clif::ir::JumpTable) {}
+}
+
+/// Logs the generated function and writes it to `CodegenOptions::dump_ir` if that is set.
+fn dump_func<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>) {
+    let name = &fx.ir.decls[fx.body.decl].name;
+    let clif = fx.mcx.ctx.func.display(fx.mcx.module.isa()).to_string();
+
+    log::debug!(target: "codegen_cranelift::clif", "{}:\n{}", name, clif);
+
+    if let Some(dir) = &fx.mcx.options.dump_ir {
+        let path = dir.join(format!("{}.clif", name));
+
+        std::fs::create_dir_all(dir)
+            .and_then(|_| std::fs::write(&path, clif))
+            .unwrap_or_else(|e| panic!("could not dump {}: {}", path.display(), e));
+    }
+}
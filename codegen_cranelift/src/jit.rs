@@ -0,0 +1,173 @@
+use crate::target::TargetSpec;
+use crate::{ClifBackend, ClifModule};
+use cranelift_module::Module;
+use ir::progress::{CancellationToken, NoProgress};
+use std::collections::HashMap;
+
+/// Compiles modules in memory with cranelift's jit and runs them in the current process.
+pub struct JitBackend {
+    symbols: HashMap<String, *const u8>,
+    options: codegen::CodegenOptions,
+}
+
+/// A module that has been loaded into executable memory, which is freed again when this is dropped.
+pub struct JitModule {
+    product: cranelift_simplejit::SimpleJITProduct,
+    symbols: HashMap<String, *const u8>,
+}
+
+#[derive(Debug)]
+pub enum JitError {
+    UnsupportedHost,
+    Codegen(codegen::CodegenError),
+    UndefinedSymbol(String),
+    /// The module needs something the process does not provide, like a recent enough runtime library.
+    Requires(String),
+}
+
+impl JitBackend {
+    pub fn new() -> Self {
+        JitBackend {
+            symbols: HashMap::new(),
+            options: codegen::CodegenOptions::default(),
+        }
+    }
+
+    /// Compiles modules with `options`, like `canonical_nans` to get the same float results as the interpreter.
+    /// The jit compiles one function at a time and does not use a cache, so `threads` and `cache` are ignored.
+    pub fn with_options(mut self, options: codegen::CodegenOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Makes `ptr` available to jitted code under `name`, e.g. to call back into the host.
+    /// Symbols that are not registered are looked up in the current process.
+    pub fn register_symbol(&mut self, name: impl Into<String>, ptr: *const u8) {
+        self.symbols.insert(name.into(), ptr);
+    }
+
+    pub fn load(&self, module: &ir::Module) -> Result<JitModule, JitError> {
+        // by default the code is not position independent and calls libcalls through absolute addresses,
+        // which is what the jit expects
+        let spec = TargetSpec::host();
+        let isa = spec.isa().map_err(|_| JitError::UnsupportedHost)?;
+
+        self.check_capabilities(module)?;
+
+        // functions defined from bytes lose their relocations in a jit, which is how threads and the cache define them
+        let options = codegen::CodegenOptions {
+            threads: 1,
+            cache: None,
+            ..self.options.clone()
+        };
+
+        let backend = ClifBackend::jit(isa, self.symbols.clone());
+        let mcx = codegen::ModuleCtx::with_options(module, spec.triple, backend, options).map_err(JitError::Codegen)?;
+        let mcx = mcx.define_with(&mut NoProgress, &CancellationToken::new()).map_err(JitError::Codegen)?;
+        let jit = match mcx.module {
+            | ClifModule::Jit(jit) => jit,
+            | ClifModule::Object(_) => unreachable!(),
+        };
+
+        // cranelift panics on symbols it can not resolve
+        for (_, decl) in jit.declarations().get_functions() {
+            if decl.linkage == cranelift_module::Linkage::Import && self.lookup(&decl.name).is_none() {
+                return Err(JitError::UndefinedSymbol(decl.name.clone()));
+            }
+        }
+
+        for (_, decl) in jit.declarations().get_data_objects() {
+            if decl.linkage == cranelift_module::Linkage::Import && self.lookup(&decl.name).is_none() {
+                return Err(JitError::UndefinedSymbol(decl.name.clone()));
+            }
+        }
+
+        let product = jit.finish();
+        let mut symbols = HashMap::new();
+
+        for body in &module.bodies {
+            let decl = &module.decls[body.decl];
+            let addr = if let Some((func, _)) = mcx.backend.func_ids.get(&decl.id) {
+                product.lookup_func(*func)
+            } else if let Some(data) = mcx.backend.data_ids.get(&decl.id) {
+                product.lookup_data(*data).0
+            } else {
+                continue;
+            };
+
+            symbols.insert(decl.name.clone(), addr);
+        }
+
+        Ok(JitModule { product, symbols })
+    }
+
+    /// Finds a symbol that the module does not define among the registered symbols and those of the current process.
+    fn lookup(&self, name: &str) -> Option<*const u8> {
+        if let Some(addr) = self.symbols.get(name) {
+            return Some(*addr);
+        }
+
+        let cname = std::ffi::CString::new(name).ok()?;
+        let addr = unsafe { libc::dlsym(libc::RTLD_DEFAULT, cname.as_ptr()) };
+
+        if addr.is_null() { None } else { Some(addr as *const u8) }
+    }
+
+    /// Fails if the module requires a newer runtime library than the current process provides.
+    /// Every other capability is provided, as missing helper functions are looked up like any other symbol.
+    fn check_capabilities(&self, module: &ir::Module) -> Result<(), JitError> {
+        use ir::capability::{self, Capabilities};
+
+        let required = Capabilities::of(module);
+        let runtime = (1..=required.runtime).rev().find(|v| self.lookup(&capability::runtime_symbol(*v)).is_some());
+        let provided = Capabilities {
+            flags: Capabilities::all().flags,
+            runtime: runtime.unwrap_or(0),
+        };
+
+        required.check("the module", &provided).map_err(JitError::Requires)
+    }
+}
+
+impl JitModule {
+    /// Returns the address of the function or global with the given name, which is valid until this is dropped.
+    pub fn get(&self, name: &str) -> Option<*const u8> {
+        self.symbols.get(name).copied()
+    }
+
+    /// Calls the `main` function of the module, which must have the signature `() -> (i32)`.
+    pub fn run_main(&self) -> Option<i32> {
+        let main = self.get("main")?;
+        let main: extern "C" fn() -> i32 = unsafe { std::mem::transmute(main) };
+
+        Some(main())
+    }
+}
+
+impl Drop for JitModule {
+    fn drop(&mut self) {
+        unsafe {
+            self.product.free_memory();
+        }
+    }
+}
+
+/// Compiles and runs the `main` function of `module` in the current process.
+pub fn run_main(module: &ir::Module) -> i32 {
+    let jit = JitBackend::new().load(module).unwrap();
+
+    jit.run_main().expect("module has no main function")
+}
+
+impl std::fmt::Display for JitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            | JitError::UnsupportedHost => write!(f, "cranelift can not generate code for this host"),
+            | JitError::Codegen(e) => e.fmt(f),
+            | JitError::UndefinedSymbol(name) => write!(f, "undefined symbol '{}'", name),
+            | JitError::Requires(msg) => msg.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for JitError {}
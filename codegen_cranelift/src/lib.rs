@@ -1,16 +1,24 @@
 #![feature(decl_macro)]
+#![feature(generic_associated_types)]
+#![recursion_limit = "256"]
 
+mod boxes;
 mod const_;
 mod decl;
+pub mod jit;
+mod module;
+mod parallel;
 pub mod place;
 pub mod ptr;
+pub mod target;
 mod trans;
 pub mod value;
 
 use codegen::*;
-use cranelift_object::ObjectModule;
+pub use module::ClifModule;
 use std::collections::HashMap;
 use std::marker::PhantomData;
+use target::{TargetError, TargetSpec};
 
 mod clif {
     pub use cranelift::codegen::ir;
@@ -23,31 +31,73 @@ mod clif {
 }
 
 pub struct ClifBackend<'ctx> {
-    func_ctx: Option<&'static mut clif::FunctionBuilderContext>,
     func_ids: HashMap<ir::DeclId, (clif::FuncId, clif::Signature)>,
     data_ids: HashMap<ir::DeclId, clif::DataId>,
     ssa_vars: u32,
-    anon_count: usize,
+    anon_consts: HashMap<const_::ConstData, clif::DataId>,
+    /// The box functions generated so far.
+    box_fns: HashMap<&'static str, clif::FuncId>,
+    /// The sections of globals that are not placed in the default data section.
+    sections: HashMap<clif::DataId, String>,
+    /// Whether the functions marked `@cold` or `@align` are cold and their alignment, see `decl::define_placed`.
+    placements: HashMap<clif::FuncId, (bool, Option<u64>)>,
+    /// The code of the functions with a placement, which is added once the module is finished.
+    placed: Vec<(clif::FuncId, artifact::FuncArtifact)>,
+    isa: Option<Box<dyn cranelift::codegen::isa::TargetIsa>>,
+    /// The functions that are compiled when the module is finished, see `parallel`.
+    pending: Vec<(clif::FuncId, clif::ir::Function)>,
+    /// The relocations of the functions defined from machine code, which are added once the module is finished.
+    relocs: Vec<(clif::FuncId, Vec<artifact::Reloc>)>,
+    /// The symbols of the host to resolve imports with when the module is loaded into memory instead of
+    /// becoming an object, see `jit`.
+    jit: Option<HashMap<String, *const u8>>,
     _marker: PhantomData<&'ctx cranelift::codegen::Context>,
 }
 
 impl<'ctx> ClifBackend<'ctx> {
     pub fn new() -> Self {
         ClifBackend {
-            func_ctx: None,
             func_ids: HashMap::new(),
             data_ids: HashMap::new(),
             ssa_vars: 0,
-            anon_count: 0,
+            anon_consts: HashMap::new(),
+            box_fns: HashMap::new(),
+            sections: HashMap::new(),
+            placements: HashMap::new(),
+            placed: Vec::new(),
+            isa: None,
+            pending: Vec::new(),
+            relocs: Vec::new(),
+            jit: None,
             _marker: PhantomData,
         }
     }
+
+    /// Creates a backend that generates code for `spec` instead of using the default settings.
+    /// The module context must be created with the same triple.
+    pub fn with_target(spec: &TargetSpec) -> Result<Self, TargetError> {
+        let mut backend = ClifBackend::new();
+
+        backend.isa = Some(spec.isa()?);
+        Ok(backend)
+    }
+
+    /// Creates a backend that loads the module into memory for `jit`, resolving imports with `symbols`
+    /// before looking in the current process. `isa` must describe the host.
+    pub(crate) fn jit(isa: Box<dyn cranelift::codegen::isa::TargetIsa>, symbols: HashMap<String, *const u8>) -> Self {
+        let mut backend = ClifBackend::new();
+
+        backend.isa = Some(isa);
+        backend.jit = Some(symbols);
+        backend
+    }
 }
 
 impl<'ctx> Backend<'ctx> for ClifBackend<'ctx> {
-    type Module = ObjectModule;
+    type Module = ClifModule;
     type Context = clif::Context;
-    type Builder = clif::FunctionBuilder<'ctx>;
+    type FuncContext = FuncContext;
+    type Builder<'bcx> = clif::FunctionBuilder<'bcx>;
     type Func = clif::FuncId;
     type Static = clif::DataId;
     type Block = clif::Block;
@@ -55,39 +105,87 @@ impl<'ctx> Backend<'ctx> for ClifBackend<'ctx> {
     type Value = value::Value<'ctx>;
     type Type = ClifType;
 
-    fn create_module(&mut self, target: &target_lexicon::Triple) -> Self::Module {
-        let flags_builder = clif::settings::builder();
-        let flags = clif::settings::Flags::new(flags_builder);
-        let isa = clif::isa::lookup(target.clone()).unwrap().finish(flags);
+    fn create_module(&mut self, target: &target_lexicon::Triple) -> Result<Self::Module, CodegenError> {
+        let isa = match self.isa.take() {
+            Some(isa) => isa,
+            None => TargetSpec::new(target.clone()).isa().map_err(|e| CodegenError::UnsupportedTarget(e.to_string()))?,
+        };
+
+        if let Some(symbols) = self.jit.take() {
+            let mut builder = cranelift_simplejit::SimpleJITBuilder::with_isa(isa, clif::default_libcall_names());
+
+            builder.symbols(symbols);
+
+            return Ok(ClifModule::Jit(cranelift_simplejit::SimpleJITModule::new(builder)));
+        }
 
         let builder =
             cranelift_object::ObjectBuilder::new(isa, "test", clif::default_libcall_names())
                 .unwrap();
 
-        cranelift_object::ObjectModule::new(builder)
+        Ok(ClifModule::Object(cranelift_object::ObjectModule::new(builder)))
     }
 
     fn create_context(&mut self, module: &mut Self::Module) -> Self::Context {
         <Self::Module as clif::Module>::make_context(module)
     }
 
-    fn create_builder(&mut self, ctx: &mut Self::Context) -> Self::Builder {
-        self.func_ctx = Some(Box::leak(Box::new(clif::FunctionBuilderContext::new())));
-
-        let func: *mut _ = &mut ctx.func;
-        let func_ctx: *mut _ = *self.func_ctx.as_mut().unwrap();
+    fn create_func_context(&mut self) -> Self::FuncContext {
+        FuncContext {
+            func: clif::ir::Function::new(),
+            builder: clif::FunctionBuilderContext::new(),
+        }
+    }
 
-        // SAFETY: unsafe blocks are used to correct the lifetimes
-        clif::FunctionBuilder::new(unsafe { &mut *func }, unsafe { &mut *func_ctx })
+    fn create_builder<'bcx>(&mut self, ctx: &'bcx mut Self::FuncContext) -> Self::Builder<'bcx> {
+        clif::FunctionBuilder::new(&mut ctx.func, &mut ctx.builder)
     }
 
-    fn finish(mcx: ModuleCtx<'_, 'ctx, Self>) -> obj_file::ObjectFile {
+    fn finish(mut mcx: ModuleCtx<'_, 'ctx, Self>) -> Result<obj_file::ObjectFile, CodegenError> {
         let mut obj_file = obj_file::ObjectFile::new();
-        let product = mcx.module.finish();
-        let bytes = product.emit().unwrap();
+        let pending = std::mem::take(&mut mcx.backend.pending);
+        let placed = std::mem::take(&mut mcx.backend.placed);
+        let mut relocs = std::mem::take(&mut mcx.backend.relocs);
+
+        relocs.extend(parallel::define(&mut mcx.module, pending, mcx.options.threads)?);
+
+        let mut product = match mcx.module {
+            | ClifModule::Object(module) => module.finish(),
+            | ClifModule::Jit(_) => unreachable!("a jit module is finalized by jit::JitBackend"),
+        };
+
+        relocs.extend(decl::define_placed(&mcx.backend, placed, &mut product));
+        parallel::add_relocs(&mut product, relocs)?;
+
+        decl::fix_visibility(mcx.ir, &mcx.backend, &mut product);
+        decl::add_capabilities(mcx.ir, &mut product);
+
+        let bytes = product.emit().map_err(|e| CodegenError::UnsupportedTarget(format!("could not write the object: {}", e)))?;
 
         obj_file.write(&bytes);
-        obj_file
+        Ok(obj_file)
+    }
+
+    fn code_config(mcx: &ModuleCtx<'_, 'ctx, Self>) -> String {
+        let isa = clif::Module::isa(&mcx.module);
+
+        format!("{}\n{}\ncanonical_nans = {}\ncodegen_cranelift {}", isa.triple(), isa, mcx.options.canonical_nans, env!("CARGO_PKG_VERSION"))
+    }
+
+    fn check_signature(mcx: &ModuleCtx<'_, 'ctx, Self>, sig: &ir::Signature) -> Result<(), String> {
+        abi::check_signature(sig, &mcx.target)?;
+        mk_signature(mcx, sig).map(|_| ())
+    }
+
+    fn supports_feature(&self, feature: &support::Feature, triple: &target_lexicon::Triple) -> bool {
+        match feature {
+            | support::Feature::Instruction(_) => true,
+            | support::Feature::Intrinsic(name) => trans::INTRINSICS.contains(&name.as_str()),
+            | support::Feature::CallConv(conv) => target::call_conv(*conv, triple).is_some(),
+            | support::Feature::Variadic(conv) => *conv != ir::CallConv::Fluix,
+            | support::Feature::TupleConst => false,
+            | support::Feature::Box | support::Feature::Int128 => true,
+        }
     }
 }
 
@@ -98,14 +196,11 @@ impl<'ctx> ClifBackend<'ctx> {
     }
 }
 
-impl<'ctx> Drop for ClifBackend<'ctx> {
-    fn drop(&mut self) {
-        unsafe {
-            if let Some(func_ctx) = self.func_ctx.take() {
-                Box::from_raw(func_ctx);
-            }
-        }
-    }
+/// The function being built and the state of its builder.
+/// The function is moved into the module's `clif::Context` once it is finished.
+pub struct FuncContext {
+    func: clif::ir::Function,
+    builder: clif::FunctionBuilderContext,
 }
 
 pub struct ClifType;
@@ -157,26 +252,46 @@ impl<'ctx> Type<'ctx> for ClifType {
     }
 }
 
-pub fn mk_signature<'ctx>(
-    mcx: &ModuleCtx<'_, 'ctx, ClifBackend<'ctx>>,
-    sig: &ir::Signature,
-) -> clif::Signature {
-    let mut out = <ObjectModule as clif::Module>::make_signature(&mcx.module);
-    let ptr_ty = <ObjectModule as clif::Module>::target_config(&mcx.module).pointer_type();
+/// Lowers `sig` to a cranelift signature, failing for calling conventions that are not supported on the target.
+/// Signatures are checked with `check_signature` before they are declared or called, so the callers expect this to succeed.
+pub fn mk_signature<'ctx>(mcx: &ModuleCtx<'_, 'ctx, ClifBackend<'ctx>>, sig: &ir::Signature) -> Result<clif::Signature, String> {
+    let mut out = clif::Module::make_signature(&mcx.module);
+    let ptr_ty = clif::Module::target_config(&mcx.module).pointer_type();
+    let conv = sig.call_conv;
+    let rules = abi::AggregateRules::for_conv(conv, &mcx.target);
 
-    for ret in &sig.rets {
-        let layout = ir::layout::layout_of(ret, &mcx.target);
+    if sig.variadic && conv == ir::CallConv::Fluix {
+        return Err(String::from("variadic functions must use a foreign calling convention"));
+    }
+
+    out.call_conv = match target::call_conv(conv, &mcx.target) {
+        | Some(call_conv) => call_conv,
+        | None => return Err(format!("calling convention {} is not supported on {}", conv.name(), mcx.target)),
+    };
+
+    let ret_layouts = sig.rets.iter().map(|ret| ir::layout::layout_of(ret, &mcx.target)).collect::<Vec<_>>();
 
-        match abi::get_pass_mode(mcx, &layout) {
+    for (layout, mode) in ret_layouts.iter().zip(abi::get_ret_modes(mcx, &ret_layouts, conv)) {
+        match mode {
             abi::PassMode::NoPass => {}
             abi::PassMode::ByVal(ty) => {
-                out.returns.push(clif::AbiParam::new(ty));
+                out.returns.push(abi_param(ty, layout, rules));
             }
             abi::PassMode::ByValPair(a, b) => {
                 out.returns.push(clif::AbiParam::new(a));
                 out.returns.push(clif::AbiParam::new(b));
             }
+            abi::PassMode::Cast(a, b) => {
+                out.returns.push(clif::AbiParam::new(a));
+                out.returns.extend(b.map(clif::AbiParam::new));
+            }
+            abi::PassMode::ByRef { size: _ } if rules.returns_sret_ptr() => {
+                // the callee returns the struct return pointer as well
+                out.params.push(clif::AbiParam::special(ptr_ty, clif::ir::ArgumentPurpose::StructReturn));
+                out.returns.push(clif::AbiParam::new(ptr_ty));
+            }
             abi::PassMode::ByRef { size: _ } => {
+                // aapcs expects the pointer in x8, so `check_signature` rejects returning in memory with it
                 out.params.push(clif::AbiParam::new(ptr_ty));
             }
         }
@@ -185,20 +300,87 @@ pub fn mk_signature<'ctx>(
     for param in &sig.params {
         let layout = ir::layout::layout_of(param, &mcx.target);
 
-        match abi::get_pass_mode(mcx, &layout) {
+        match abi::get_pass_mode_for(mcx, &layout, conv) {
             abi::PassMode::NoPass => {}
             abi::PassMode::ByVal(ty) => {
-                out.params.push(clif::AbiParam::new(ty));
+                out.params.push(abi_param(ty, &layout, rules));
             }
             abi::PassMode::ByValPair(a, b) => {
                 out.params.push(clif::AbiParam::new(a));
                 out.params.push(clif::AbiParam::new(b));
             }
+            abi::PassMode::Cast(a, b) => {
+                out.params.push(clif::AbiParam::new(a));
+                out.params.extend(b.map(clif::AbiParam::new));
+            }
+            abi::PassMode::ByRef { size: Some(size) } if rules == abi::AggregateRules::SysV => {
+                // copied to the stack by the caller
+                let size = size.align_to(ir::layout::Align::from_bytes(8)).bytes() as u32;
+
+                out.params.push(clif::AbiParam::special(ptr_ty, clif::ir::ArgumentPurpose::StructArgument(size)));
+            }
             abi::PassMode::ByRef { size: _ } => {
                 out.params.push(clif::AbiParam::new(ptr_ty));
             }
         }
     }
 
-    out
+    Ok(out)
+}
+
+/// Foreign conventions expect integers smaller than 32 bits to be extended by the caller.
+fn abi_param(ty: clif::Type, layout: &ir::layout::TyLayout, rules: abi::AggregateRules) -> clif::AbiParam {
+    let param = clif::AbiParam::new(ty);
+
+    match &layout.abi {
+        ir::layout::Abi::Scalar(ir::layout::Scalar {
+            value: ir::layout::Primitive::Int(int, signed),
+            ..
+        }) if rules != abi::AggregateRules::Default && int.size().bits() < 32 => {
+            if *signed {
+                param.sext()
+            } else {
+                param.uext()
+            }
+        }
+        _ => param,
+    }
+}
+
+/// Creates a place for a value passed as `PassMode::Cast`, large enough to load whole registers from.
+pub(crate) fn cast_place<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>, layout: ir::layout::TyLayout) -> place::Place<'ctx> {
+    let slot = fx.bcx.create_stack_slot(clif::StackSlotData {
+        kind: clif::StackSlotKind::ExplicitSlot,
+        size: layout.size.align_to(ir::layout::Align::from_bytes(8)).bytes() as u32,
+        offset: None,
+    });
+
+    place::Place::new_ref(ptr::Pointer::stack(slot), layout)
+}
+
+/// Loads the registers of a value passed as `PassMode::Cast` from a place created by `cast_place`.
+pub(crate) fn cast_to_regs<'ctx>(
+    fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>,
+    place: &place::Place<'ctx>,
+    a: clif::Type,
+    b: Option<clif::Type>,
+) -> abi::EmptySinglePair<clif::Value> {
+    let ptr = place.as_ptr();
+    let first = ptr.load(fx, a, clif::MemFlags::new());
+
+    match b {
+        Some(b) => abi::EmptySinglePair::Pair(first, ptr.offset_i64(fx, a.bytes() as i64).load(fx, b, clif::MemFlags::new())),
+        None => abi::EmptySinglePair::Single(first),
+    }
+}
+
+/// Stores the registers of a value passed as `PassMode::Cast` to a place created by `cast_place`.
+pub(crate) fn cast_from_regs<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>, place: &place::Place<'ctx>, regs: &[clif::Value]) {
+    let ptr = place.as_ptr();
+    let mut offset = 0;
+
+    for reg in regs {
+        ptr.offset_i64(fx, offset).store(fx, *reg, clif::MemFlags::new());
+        offset += fx.bcx.func.dfg.value_type(*reg).bytes() as i64;
+    }
 }
@@ -0,0 +1,69 @@
+use crate::clif;
+use cranelift::codegen::binemit::TrapSink;
+use cranelift::codegen::isa::TargetIsa;
+use cranelift_module::{ModuleCompiledFunction, ModuleDeclarations, ModuleResult};
+use cranelift_object::ObjectModule;
+use cranelift_simplejit::SimpleJITModule;
+
+/// The cranelift module the functions and globals are defined in, which either becomes an object file
+/// or loads them into the memory of the current process, see `jit`.
+pub enum ClifModule {
+    Object(ObjectModule),
+    Jit(SimpleJITModule),
+}
+
+impl clif::Module for ClifModule {
+    fn isa(&self) -> &dyn TargetIsa {
+        match self {
+            | ClifModule::Object(module) => module.isa(),
+            | ClifModule::Jit(module) => module.isa(),
+        }
+    }
+
+    fn declarations(&self) -> &ModuleDeclarations {
+        match self {
+            | ClifModule::Object(module) => module.declarations(),
+            | ClifModule::Jit(module) => module.declarations(),
+        }
+    }
+
+    fn declare_function(&mut self, name: &str, linkage: clif::Linkage, signature: &clif::Signature) -> ModuleResult<clif::FuncId> {
+        match self {
+            | ClifModule::Object(module) => module.declare_function(name, linkage, signature),
+            | ClifModule::Jit(module) => module.declare_function(name, linkage, signature),
+        }
+    }
+
+    fn declare_data(&mut self, name: &str, linkage: clif::Linkage, writable: bool, tls: bool) -> ModuleResult<clif::DataId> {
+        match self {
+            | ClifModule::Object(module) => module.declare_data(name, linkage, writable, tls),
+            | ClifModule::Jit(module) => module.declare_data(name, linkage, writable, tls),
+        }
+    }
+
+    fn define_function<TS: TrapSink>(
+        &mut self,
+        func: clif::FuncId,
+        ctx: &mut clif::Context,
+        trap_sink: &mut TS,
+    ) -> ModuleResult<ModuleCompiledFunction> {
+        match self {
+            | ClifModule::Object(module) => module.define_function(func, ctx, trap_sink),
+            | ClifModule::Jit(module) => module.define_function(func, ctx, trap_sink),
+        }
+    }
+
+    fn define_function_bytes(&mut self, func: clif::FuncId, bytes: &[u8]) -> ModuleResult<ModuleCompiledFunction> {
+        match self {
+            | ClifModule::Object(module) => module.define_function_bytes(func, bytes),
+            | ClifModule::Jit(module) => module.define_function_bytes(func, bytes),
+        }
+    }
+
+    fn define_data(&mut self, data: clif::DataId, data_ctx: &clif::DataContext) -> ModuleResult<()> {
+        match self {
+            | ClifModule::Object(module) => module.define_data(data, data_ctx),
+            | ClifModule::Jit(module) => module.define_data(data, data_ctx),
+        }
+    }
+}
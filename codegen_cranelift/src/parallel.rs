@@ -0,0 +1,155 @@
+//! Compiling functions to machine code on several threads.
+//!
+//! Translating a body declares the constants and functions it uses in the module, so bodies are still
+//! translated one at a time. With `CodegenOptions::threads` set, the translated functions are kept until the
+//! module is finished and compiled together, which is where most of the time goes. Cranelift can only define
+//! a function from bytes without its relocations, so those are added to the object once it is finished,
+//! together with the relocations of functions defined from cached code.
+
+use crate::decl::RelocCollector;
+use crate::*;
+use clif::codegen::binemit::{NullStackMapSink, NullTrapSink};
+use clif::Module;
+use object::write::{Relocation, Symbol, SymbolId, SymbolSection};
+use object::{BinaryFormat, RelocationEncoding, RelocationKind, SymbolFlags, SymbolKind, SymbolScope};
+
+/// Compiles `funcs` on up to `threads` threads and defines them in `module` in the order they are given,
+/// so the object does not depend on the number of threads. Returns the relocations for `add_relocs`.
+pub(crate) fn define(
+    module: &mut ClifModule,
+    funcs: Vec<(clif::FuncId, clif::ir::Function)>,
+    threads: usize,
+) -> Result<Vec<(clif::FuncId, Vec<artifact::Reloc>)>, CodegenError> {
+    if funcs.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let size = (funcs.len() + threads - 1) / threads;
+    let mut chunks = Vec::new();
+    let mut funcs = funcs;
+
+    while funcs.len() > size {
+        let rest = funcs.split_off(size);
+
+        chunks.push(std::mem::replace(&mut funcs, rest));
+    }
+
+    chunks.push(funcs);
+
+    let isa = module.isa();
+    let compiled = std::thread::scope(|s| {
+        let handles = chunks
+            .into_iter()
+            .map(|chunk| s.spawn(move || chunk.into_iter().map(|(id, func)| compile(isa, id, func)).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+
+        handles.into_iter().flat_map(|h| h.join().unwrap()).collect::<Vec<_>>()
+    });
+
+    compiled
+        .into_iter()
+        .map(|compiled| {
+            let (id, code, relocs) = compiled.map_err(|(id, message)| error(module, id, message))?;
+
+            module.define_function_bytes(id, &code).map_err(|e| error(module, id, e.to_string()))?;
+
+            Ok((id, relocs.into_artifact(module)))
+        })
+        .collect()
+}
+
+fn error(module: &ClifModule, id: clif::FuncId, message: String) -> CodegenError {
+    CodegenError::Backend {
+        func: module.declarations().get_function_decl(id).name.clone(),
+        message,
+    }
+}
+
+/// Compiles `func`, the error is only given its name once the threads are joined.
+fn compile(
+    isa: &dyn clif::codegen::isa::TargetIsa,
+    id: clif::FuncId,
+    func: clif::ir::Function,
+) -> Result<(clif::FuncId, Vec<u8>, RelocCollector), (clif::FuncId, String)> {
+    let mut ctx = clif::Context::for_function(func);
+    let mut code = Vec::new();
+    let mut relocs = RelocCollector(Vec::new());
+
+    ctx.compile_and_emit(isa, &mut code, &mut relocs, &mut NullTrapSink {}, &mut NullStackMapSink {})
+        .map_err(|e| (id, e.to_string()))?;
+
+    Ok((id, code, relocs))
+}
+
+/// Adds the relocations of the functions defined from bytes to the finished object,
+/// the way the object module does for the functions it compiles itself.
+pub(crate) fn add_relocs(
+    product: &mut cranelift_object::ObjectProduct,
+    relocs: Vec<(clif::FuncId, Vec<artifact::Reloc>)>,
+) -> Result<(), CodegenError> {
+    let format = product.object.format();
+
+    for (func, relocs) in relocs {
+        let symbol = product.object.symbol(product.function_symbol(func));
+        let name = String::from_utf8_lossy(&symbol.name).into_owned();
+        let (section, base) = match symbol.section {
+            | SymbolSection::Section(section) => (section, symbol.value),
+            | _ => unreachable!("function {} was not defined", func),
+        };
+
+        for reloc in relocs {
+            let (kind, encoding, size) = match (reloc.kind.as_str(), format) {
+                | ("Abs4", _) => (RelocationKind::Absolute, RelocationEncoding::Generic, 32),
+                | ("Abs8", _) => (RelocationKind::Absolute, RelocationEncoding::Generic, 64),
+                | ("X86PCRel4", _) => (RelocationKind::Relative, RelocationEncoding::Generic, 32),
+                | ("X86CallPCRel4", _) => (RelocationKind::Relative, RelocationEncoding::X86Branch, 32),
+                | ("X86CallPLTRel4", _) => (RelocationKind::PltRelative, RelocationEncoding::X86Branch, 32),
+                | ("X86GOTPCRel4", _) => (RelocationKind::GotRelative, RelocationEncoding::Generic, 32),
+                // the object crate only writes aarch64 relocations for elf
+                | ("Arm64Call", BinaryFormat::Elf) => (RelocationKind::Elf(object::elf::R_AARCH64_CALL26), RelocationEncoding::Generic, 32),
+                | (kind, format) => {
+                    return Err(CodegenError::Backend {
+                        func: name,
+                        message: format!("relocation {} is not supported in {:?} objects", kind, format),
+                    });
+                },
+            };
+
+            let symbol = symbol_named(product, &reloc.symbol);
+
+            product
+                .object
+                .add_relocation(section, Relocation {
+                    offset: base + u64::from(reloc.offset),
+                    size,
+                    kind,
+                    encoding,
+                    symbol,
+                    addend: reloc.addend,
+                })
+                .map_err(|e| CodegenError::Backend {
+                    func: name.clone(),
+                    message: e.to_string(),
+                })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Every symbol the module declared is in the object, only the libcalls a function uses have to be added.
+fn symbol_named(product: &mut cranelift_object::ObjectProduct, name: &str) -> SymbolId {
+    match product.object.symbol_id(name.as_bytes()) {
+        | Some(symbol) => symbol,
+        | None => product.object.add_symbol(Symbol {
+            name: name.as_bytes().to_vec(),
+            value: 0,
+            size: 0,
+            kind: SymbolKind::Text,
+            scope: SymbolScope::Unknown,
+            weak: false,
+            section: SymbolSection::Undefined,
+            flags: SymbolFlags::None,
+        }),
+    }
+}
@@ -154,15 +154,10 @@ impl<'ctx> codegen::Place<'ctx> for Place<'ctx> {
         let is_box = matches!(self.layout.ty.kind, ir::Type::Box(_));
 
         if is_box {
-            use crate::clif::Module;
             let val = self.to_value(fx).load_scalar(fx);
-            let ptr = Pointer::addr(val);
-            let ptr_type = fx.module.target_config().pointer_type();
+            let ptr = crate::boxes::call(fx, "box_addr_checked", &[val]).unwrap();
 
-            Self::new_ref(
-                Pointer::addr(ptr.load(fx, ptr_type, crate::clif::MemFlags::trusted())),
-                pointee,
-            )
+            Self::new_ref(Pointer::addr(ptr), pointee)
         } else {
             Self::new_ref(Pointer::addr(self.to_value(fx).load_scalar(fx)), pointee)
         }
@@ -329,8 +324,10 @@ impl<'ctx> codegen::Place<'ctx> for Place<'ctx> {
                 let to_addr = to_ptr.get_addr(fx);
                 let src_layout = from.layout;
                 let size = dst_layout.size.bytes();
-                let src_align = src_layout.align.bytes() as u8;
-                let dst_align = dst_layout.align.bytes() as u8;
+                // the size of a type is not rounded up to its alignment, so copy in smaller units if needed
+                let max_align = 1 << size.trailing_zeros().min(7);
+                let src_align = src_layout.align.bytes().min(max_align) as u8;
+                let dst_align = dst_layout.align.bytes().min(max_align) as u8;
 
                 fx.bcx.emit_small_memory_copy(
                     fx.module.target_config(),
@@ -161,6 +161,21 @@ impl Pointer {
         ty: cir::Type,
         flags: cir::MemFlags,
     ) -> cir::Value {
+        // the legacy x86 backend has no encodings for 128-bit loads and stores, so they access both halves
+        if ty == cir::types::I128 {
+            let lo = self.load(fx, cir::types::I64, flags);
+            let hi = self.offset_i64(fx, 8).load(fx, cir::types::I64, flags);
+
+            return fx.bcx.ins().iconcat(lo, hi);
+        }
+
+        // neither are there encodings for vector stack loads and stores, so they go through the address of the slot
+        if ty.is_vector() {
+            let addr = self.get_addr(fx);
+
+            return fx.bcx.ins().load(ty, flags, addr, 0);
+        }
+
         match self.kind {
             PointerKind::Addr(addr) => fx.bcx.ins().load(ty, flags, addr, self.offset),
             PointerKind::Stack(ss) => fx.bcx.ins().stack_load(ty, ss, self.offset),
@@ -174,6 +189,21 @@ impl Pointer {
         value: cir::Value,
         flags: cir::MemFlags,
     ) {
+        if fx.bcx.func.dfg.value_type(value) == cir::types::I128 {
+            let (lo, hi) = fx.bcx.ins().isplit(value);
+
+            self.store(fx, lo, flags);
+            self.offset_i64(fx, 8).store(fx, hi, flags);
+            return;
+        }
+
+        if fx.bcx.func.dfg.value_type(value).is_vector() {
+            let addr = self.get_addr(fx);
+
+            fx.bcx.ins().store(flags, value, addr, 0);
+            return;
+        }
+
         match self.kind {
             PointerKind::Addr(addr) => {
                 fx.bcx.ins().store(flags, value, addr, self.offset);
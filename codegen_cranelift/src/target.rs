@@ -0,0 +1,110 @@
+use crate::clif;
+use clif::settings::Configurable;
+use cranelift::codegen::isa::TargetIsa;
+
+/// Describes the machine to generate code for.
+#[derive(Debug, Clone)]
+pub struct TargetSpec {
+    pub triple: target_lexicon::Triple,
+    /// A cpu preset such as `haswell`. The baseline of the architecture is used when this is `None`.
+    pub cpu: Option<String>,
+    /// Isa features like `has_avx2` to enable, or to disable when prefixed with `-`.
+    pub features: Vec<String>,
+    pub reloc_model: RelocModel,
+    /// Whether to generate position independent code.
+    pub pic: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocModel {
+    /// Everything is linked into one image, so libcalls can be called directly.
+    Static,
+    /// Libcalls may live in a shared library and are called through the usual relocations.
+    Dynamic,
+}
+
+#[derive(Debug)]
+pub enum TargetError {
+    UnsupportedTriple(target_lexicon::Triple),
+    UnknownCpu(String),
+    UnknownFeature(String),
+}
+
+impl TargetSpec {
+    pub fn new(triple: target_lexicon::Triple) -> Self {
+        TargetSpec {
+            triple,
+            cpu: None,
+            features: Vec::new(),
+            reloc_model: RelocModel::Dynamic,
+            pic: false,
+        }
+    }
+
+    pub fn host() -> Self {
+        TargetSpec::new(target_lexicon::Triple::host())
+    }
+
+    pub fn isa(&self) -> Result<Box<dyn TargetIsa>, TargetError> {
+        let mut flags = clif::settings::builder();
+        let colocated = self.reloc_model == RelocModel::Static;
+
+        flags.set("is_pic", if self.pic { "true" } else { "false" }).unwrap();
+        flags.set("use_colocated_libcalls", if colocated { "true" } else { "false" }).unwrap();
+        // vector intrinsics are lowered to SIMD instructions, which are only encoded if this is set
+        flags.set("enable_simd", "true").unwrap();
+
+        let mut isa = clif::isa::lookup(self.triple.clone()).map_err(|_| TargetError::UnsupportedTriple(self.triple.clone()))?;
+
+        if let Some(cpu) = &self.cpu {
+            isa.enable(cpu).map_err(|_| TargetError::UnknownCpu(cpu.clone()))?;
+        }
+
+        for feature in &self.features {
+            let (name, value) = match feature.strip_prefix('-') {
+                | Some(name) => (name, "false"),
+                | None => (feature.trim_start_matches('+'), "true"),
+            };
+
+            isa.set(name, value).map_err(|_| TargetError::UnknownFeature(feature.clone()))?;
+        }
+
+        Ok(isa.finish(clif::settings::Flags::new(flags)))
+    }
+}
+
+/// Maps a calling convention of the ir to the one cranelift uses for it on `triple`,
+/// or returns `None` if the convention does not exist on that target.
+pub fn call_conv(conv: ir::CallConv, triple: &target_lexicon::Triple) -> Option<clif::isa::CallConv> {
+    use target_lexicon::Architecture;
+
+    let default = match triple.default_calling_convention() {
+        | Ok(target_lexicon::CallingConvention::WindowsFastcall) => clif::isa::CallConv::WindowsFastcall,
+        | _ => clif::isa::CallConv::SystemV,
+    };
+
+    match conv {
+        | ir::CallConv::Fluix | ir::CallConv::C => Some(default),
+        | ir::CallConv::Fastcall => Some(clif::isa::CallConv::Fast),
+        | ir::CallConv::Win64 => match triple.architecture {
+            | Architecture::X86_64 => Some(clif::isa::CallConv::WindowsFastcall),
+            | _ => None,
+        },
+        | ir::CallConv::Aapcs => match triple.architecture {
+            | Architecture::Aarch64(_) | Architecture::Arm(_) => Some(clif::isa::CallConv::SystemV),
+            | _ => None,
+        },
+    }
+}
+
+impl std::fmt::Display for TargetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            | TargetError::UnsupportedTriple(triple) => write!(f, "unsupported target '{}'", triple),
+            | TargetError::UnknownCpu(cpu) => write!(f, "unknown cpu '{}'", cpu),
+            | TargetError::UnknownFeature(feature) => write!(f, "unknown target feature '{}'", feature),
+        }
+    }
+}
+
+impl std::error::Error for TargetError {}
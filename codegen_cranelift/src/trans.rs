@@ -3,20 +3,20 @@ use clif::{InstBuilder, Module};
 
 macro_rules! intrinsic {
     (
-        $fx:ident, $name:ident, $args:expr, $place:ident, [
+        $fx:ident, $name:ident, $ops:ident, $args:expr, $place:ident, [
             $(($($tokens:tt)+)),*$(,)?
         ]
     ) => {
-        intrinsic!(@munch $fx, $name, $args, $place, [$(($($tokens)*),)*], []);
+        intrinsic!(@munch $fx, $name, $ops, $args, $place, [$(($($tokens)*),)*], []);
     };
-    (@munch $fx:ident, $name:ident, $args:expr, $place:ident, [], [$($p:pat => $val:expr,)*]) => {
+    (@munch $fx:ident, $name:ident, $ops:ident, $args:expr, $place:ident, [], [$($p:pat => $val:expr,)*]) => {
         match $name.as_str() {
             $($p => $val,)*
             _ => panic!("unknown intrinsic {}", $name),
         }
     };
-    (@munch $fx:ident, $name:ident, $args:expr, $place:ident, [(simple $n:literal ($($params:ident),*) => $func:ident), $($rest:tt)*], [$($out:tt)*]) => {
-        intrinsic!(@munch $fx, $name, $args, $place, [$($rest)*], [$($out)*
+    (@munch $fx:ident, $name:ident, $ops:ident, $args:expr, $place:ident, [(simple $n:literal ($($params:ident),*) => $func:ident), $($rest:tt)*], [$($out:tt)*]) => {
+        intrinsic!(@munch $fx, $name, $ops, $args, $place, [$($rest)*], [$($out)*
             $n => {
                 if let [$($params),*] = $args {
                     let val = $fx.bcx.ins().$func($($params),*);
@@ -29,8 +29,36 @@ macro_rules! intrinsic {
             },
         ]);
     };
-    (@munch $fx:ident, $name:ident, $args:expr, $place:ident, [(complex $n:literal ($($params:ident),*) => $func:expr), $($rest:tt)*], [$($out:tt)*]) => {
-        intrinsic!(@munch $fx, $name, $args, $place, [$($rest)*], [$($out)*
+    (@munch $fx:ident, $name:ident, $ops:ident, $args:expr, $place:ident, [(checked $n:literal ($($params:ident),*) => $op:ident, $signed:literal), $($rest:tt)*], [$($out:tt)*]) => {
+        intrinsic!(@munch $fx, $name, $ops, $args, $place, [$($rest)*], [$($out)*
+            $n => {
+                if let [$($params),*] = $args {
+                    let (val, overflow) = checked_arith($fx, stringify!($op), $signed, $($params),*);
+                    let layout = $place.layout.field(0, &$fx.target);
+
+                    $place.clone().field($fx, 0).store($fx, value::Value::new_val(val, layout));
+
+                    let layout = $place.layout.field(1, &$fx.target);
+
+                    $place.field($fx, 1).store($fx, value::Value::new_val(overflow, layout));
+                } else {
+                    panic!("incorrect number of arguments for intrinsic {}", $n);
+                }
+            },
+        ]);
+    };
+    (@munch $fx:ident, $name:ident, $ops:ident, $args:expr, $place:ident, [(vector $n:literal => $op:ident), $($rest:tt)*], [$($out:tt)*]) => {
+        intrinsic!(@munch $fx, $name, $ops, $args, $place, [$($rest)*], [$($out)*
+            $n => trans_vector($fx, stringify!($op), &$args, $place),
+        ]);
+    };
+    (@munch $fx:ident, $name:ident, $ops:ident, $args:expr, $place:ident, [(atomic $n:literal => $op:ident, $ty:ident), $($rest:tt)*], [$($out:tt)*]) => {
+        intrinsic!(@munch $fx, $name, $ops, $args, $place, [$($rest)*], [$($out)*
+            $n => trans_atomic($fx, stringify!($op), clif::types::$ty, $ops, &$args, $place),
+        ]);
+    };
+    (@munch $fx:ident, $name:ident, $ops:ident, $args:expr, $place:ident, [(complex $n:literal ($($params:ident),*) => $func:expr), $($rest:tt)*], [$($out:tt)*]) => {
+        intrinsic!(@munch $fx, $name, $ops, $args, $place, [$($rest)*], [$($out)*
             $n => {
                 if let [$($params),*] = $args {
                     let val = $func;
@@ -44,6 +72,31 @@ macro_rules! intrinsic {
     };
 }
 
+/// The intrinsics `trans_rvalue` can lower, which must be kept in sync with the list given to `intrinsic!`.
+pub(crate) const INTRINSICS: &[&str] = &[
+    "add_i8", "sub_i8", "mul_i8", "div_i8", "rem_i8", "add_i16", "sub_i16", "mul_i16", "div_i16", "rem_i16", "add_i32",
+    "sub_i32", "mul_i32", "div_i32", "rem_i32", "add_i64", "sub_i64", "mul_i64", "div_i64", "rem_i64", "add_i128",
+    "sub_i128", "mul_i128", "div_i128", "rem_i128", "add_u8", "sub_u8", "mul_u8", "div_u8", "rem_u8", "add_u16",
+    "sub_u16", "mul_u16", "div_u16", "rem_u16", "add_u32", "sub_u32", "mul_u32", "div_u32", "rem_u32", "add_u64",
+    "sub_u64", "mul_u64", "div_u64", "rem_u64", "add_u128", "sub_u128", "mul_u128", "div_u128", "rem_u128", "shl_i8",
+    "shl_i16", "shl_i32", "shl_i64", "shl_i128", "shl_u8", "shl_u16", "shl_u32", "shl_u64", "shl_u128",
+    "add_i32_checked", "add_i64_checked", "add_u32_checked", "add_u64_checked", "sub_i32_checked", "sub_i64_checked",
+    "sub_u32_checked", "sub_u64_checked", "mul_i32_checked", "mul_i64_checked", "mul_u32_checked", "mul_u64_checked",
+    "atomic_load_i8", "atomic_load_i16", "atomic_load_i32", "atomic_load_i64", "atomic_load_u8", "atomic_load_u16",
+    "atomic_load_u32", "atomic_load_u64", "atomic_store_i8", "atomic_store_i16", "atomic_store_i32", "atomic_store_i64",
+    "atomic_store_u8", "atomic_store_u16", "atomic_store_u32", "atomic_store_u64", "atomic_cmpxchg_i8",
+    "atomic_cmpxchg_i16", "atomic_cmpxchg_i32", "atomic_cmpxchg_i64", "atomic_cmpxchg_u8", "atomic_cmpxchg_u16",
+    "atomic_cmpxchg_u32", "atomic_cmpxchg_u64", "atomic_fetch_add_i8", "atomic_fetch_add_i16", "atomic_fetch_add_i32",
+    "atomic_fetch_add_i64", "atomic_fetch_add_u8", "atomic_fetch_add_u16", "atomic_fetch_add_u32",
+    "atomic_fetch_add_u64", "lt_i32", "le_i32", "gt_i32", "ge_i32", "eq_i32", "ne_i32", "memcpy", "memset", "memcmp",
+    "f64_to_str", "i64_to_str", "u64_to_str", "str_to_f64", "str_to_i64", "str_to_u64", "bigint_from_str",
+    "bigint_to_str", "bigint_add", "bigint_mul", "bigint_cmp", "bigint_free", "dyn_cast", "resource", "ptr_offset",
+    "stack_alloc", "stack_free", "pool_create", "pool_alloc", "pool_free", "pool_destroy", "box_alloc", "box_free",
+    "box_copy", "splat", "extractlane", "insertlane", "vadd_i8x16", "vadd_i16x8", "vadd_i32x4", "vadd_i64x2",
+    "vadd_f32x4", "vadd_f64x2", "vsub_i8x16", "vsub_i16x8", "vsub_i32x4", "vsub_i64x2", "vsub_f32x4", "vsub_f64x2",
+    "vmul_i16x8", "vmul_i32x4", "vmul_f32x4", "vmul_f64x2", "vdiv_f32x4", "vdiv_f64x2", "select",
+];
+
 impl<'ctx> TransMethods<'ctx> for ClifBackend<'ctx> {
     type Backend = Self;
 
@@ -116,6 +169,12 @@ impl<'ctx> TransMethods<'ctx> for ClifBackend<'ctx> {
                     into.store(fx, val.clone());
                     val
                 },
+                | ir::Const::Bytes(_) => {
+                    let val = Self::trans_const(fx, c, None);
+
+                    into.store(fx, val.clone());
+                    val
+                },
                 | _ => unimplemented!(),
             }
         } else {
@@ -145,6 +204,10 @@ impl<'ctx> TransMethods<'ctx> for ClifBackend<'ctx> {
                         value::Value::new_val(global, layout)
                     }
                 },
+                // payloadless enums are laid out as their tag
+                | ir::Const::Variant(idx, cs, _) if cs.is_empty() && fx.ir_type(&layout).is_some() => {
+                    value::Value::new_const(*idx as u128, fx, layout)
+                },
                 | ir::Const::Variant(idx, cs, ty) => {
                     let layout = ir::layout::layout_of(ty, &fx.target);
                     let place = place::Place::new_stack(fx, layout);
@@ -169,6 +232,14 @@ impl<'ctx> TransMethods<'ctx> for ClifBackend<'ctx> {
 
                     value::Value::new_val(global, layout)
                 },
+                | ir::Const::Bytes(_) => {
+                    let data = Self::alloc_const(fx.mcx, c, layout.clone(), None);
+                    let ptr_ty = fx.module.target_config().pointer_type();
+                    let global = fx.mcx.module.declare_data_in_func(data, &mut fx.bcx.func);
+                    let global = fx.bcx.ins().global_value(ptr_ty, global);
+
+                    value::Value::new_ref(ptr::Pointer::addr(global), layout)
+                },
                 | _ => unimplemented!(),
             }
         }
@@ -229,6 +300,77 @@ impl<'ctx> TransMethods<'ctx> for ClifBackend<'ctx> {
 
                 val.write_place_ref(fx, place);
             },
+            | ir::RValue::Lea { base, index, scale, offset } => {
+                let ptr_ty = fx.module.target_config().pointer_type();
+                let mut addr = Self::trans_op(fx, base, None).load_scalar(fx);
+
+                if let Some(index) = index {
+                    let index = Self::trans_op(fx, index, None);
+                    let signed = index.layout.abi.is_signed();
+                    let mut index = index.load_scalar(fx);
+                    let index_ty = fx.bcx.func.dfg.value_type(index);
+
+                    if index_ty.bits() < ptr_ty.bits() {
+                        index = if signed { fx.bcx.ins().sextend(ptr_ty, index) } else { fx.bcx.ins().uextend(ptr_ty, index) };
+                    } else if index_ty.bits() > ptr_ty.bits() {
+                        index = fx.bcx.ins().ireduce(ptr_ty, index);
+                    }
+
+                    if *scale != 1 {
+                        index = fx.bcx.ins().imul_imm(index, *scale as i64);
+                    }
+
+                    addr = fx.bcx.ins().iadd(addr, index);
+                }
+
+                if *offset != 0 {
+                    addr = fx.bcx.ins().iadd_imm(addr, *offset);
+                }
+
+                let val = value::Value::new_val(addr, place.layout.clone());
+
+                place.store(fx, val);
+            },
+            | ir::RValue::UnOp(op, val) => {
+                let val = Self::trans_op(fx, val, None).load_scalar(fx);
+                let ty = fx.bcx.func.dfg.value_type(val);
+                let val = match op {
+                    | ir::UnOp::Neg if ty.is_float() => {
+                        let val = fx.bcx.ins().fneg(val);
+                        let prim = if ty == clif::types::F32 { ir::layout::Primitive::F32 } else { ir::layout::Primitive::F64 };
+
+                        // negating flips the sign of a NaN as well
+                        value::canonicalize_nan(fx, val, prim)
+                    },
+                    | ir::UnOp::Neg => fx.bcx.ins().ineg(val),
+                    | ir::UnOp::Not => {
+                        let val = fx.bcx.ins().icmp_imm(clif::IntCC::Equal, val, 0);
+
+                        fx.bcx.ins().bint(ty, val)
+                    },
+                    // a bool only has one bit to flip
+                    | ir::UnOp::BitNot if place.layout.ty.kind == ir::Type::Bool => fx.bcx.ins().bxor_imm(val, 1),
+                    | ir::UnOp::BitNot => fx.bcx.ins().bnot(val),
+                };
+
+                let val = value::Value::new_val(val, place.layout.clone());
+
+                place.store(fx, val);
+            },
+            | ir::RValue::NullOp(op, ty) => {
+                let layout = ir::layout::layout_of(ty, &fx.target);
+
+                assert!(!layout.is_unsized(), "{:?} of an unsized type", op);
+
+                let val = match op {
+                    | ir::NullOp::SizeOf => layout.size.bytes(),
+                    | ir::NullOp::AlignOf => layout.align.bytes(),
+                };
+
+                let val = value::Value::new_const(val as u128, fx, place.layout.clone());
+
+                place.store(fx, val);
+            },
             | ir::RValue::Cast(val, to) => {
                 let layout = ir::layout::layout_of(to, &fx.target);
                 let val = Self::trans_place(fx, val).to_value(fx);
@@ -268,7 +410,24 @@ impl<'ctx> TransMethods<'ctx> for ClifBackend<'ctx> {
                     | ir::layout::TagEncoding::Direct => {
                         place.store(fx, tag);
                     },
-                    | ir::layout::TagEncoding::Niche { .. } => unimplemented!(),
+                    | ir::layout::TagEncoding::Niche {
+                        dataful_variant,
+                        niche_variants,
+                        niche_start,
+                    } => {
+                        let tag = tag.load_scalar(fx);
+                        let discr_ty = fx.ir_type(&place.layout).unwrap();
+                        let relative = fx.bcx.ins().iadd_imm(tag, (niche_start as i64).wrapping_neg());
+                        let relative_max = (*niche_variants.end() - *niche_variants.start()) as i64;
+                        let is_niche = fx.bcx.ins().icmp_imm(clif::IntCC::UnsignedLessThanOrEqual, relative, relative_max);
+                        let relative = fit_int(fx, relative, discr_ty);
+                        let niche_discr = fx.bcx.ins().iadd_imm(relative, *niche_variants.start() as i64);
+                        let dataful = fx.bcx.ins().iconst(discr_ty, dataful_variant as i64);
+                        let discr = fx.bcx.ins().select(is_niche, niche_discr, dataful);
+                        let discr = value::Value::new_val(discr, place.layout.clone());
+
+                        place.store(fx, discr);
+                    },
                 }
             },
             | ir::RValue::Intrinsic(name, args) => {
@@ -285,7 +444,7 @@ impl<'ctx> TransMethods<'ctx> for ClifBackend<'ctx> {
                     .flatten()
                     .collect::<Vec<_>>();
 
-                intrinsic!(fx, name, args2[..], place, [
+                intrinsic!(fx, name, args, args2[..], place, [
                     (simple "add_i8"(a, b) => iadd),
                     (simple "sub_i8"(a, b) => isub),
                     (simple "mul_i8"(a, b) => imul),
@@ -309,8 +468,8 @@ impl<'ctx> TransMethods<'ctx> for ClifBackend<'ctx> {
                     (simple "add_i128"(a, b) => iadd),
                     (simple "sub_i128"(a, b) => isub),
                     (simple "mul_i128"(a, b) => imul),
-                    (simple "div_i128"(a, b) => sdiv),
-                    (simple "rem_i128"(a, b) => srem),
+                    (complex "div_i128"(a, b) => int128_libcall(fx, "__divti3", a, b, place.layout.clone())),
+                    (complex "rem_i128"(a, b) => int128_libcall(fx, "__modti3", a, b, place.layout.clone())),
                     (simple "add_u8"(a, b) => iadd),
                     (simple "sub_u8"(a, b) => isub),
                     (simple "mul_u8"(a, b) => imul),
@@ -334,8 +493,62 @@ impl<'ctx> TransMethods<'ctx> for ClifBackend<'ctx> {
                     (simple "add_u128"(a, b) => iadd),
                     (simple "sub_u128"(a, b) => isub),
                     (simple "mul_u128"(a, b) => imul),
-                    (simple "div_u128"(a, b) => udiv),
-                    (simple "rem_u128"(a, b) => urem),
+                    (complex "div_u128"(a, b) => int128_libcall(fx, "__udivti3", a, b, place.layout.clone())),
+                    (complex "rem_u128"(a, b) => int128_libcall(fx, "__umodti3", a, b, place.layout.clone())),
+                    (simple "shl_i8"(a, b) => ishl),
+                    (simple "shl_i16"(a, b) => ishl),
+                    (simple "shl_i32"(a, b) => ishl),
+                    (simple "shl_i64"(a, b) => ishl),
+                    (complex "shl_i128"(a, b) => value::Value::new_val(shl_i128(fx, a, b), place.layout.clone())),
+                    (simple "shl_u8"(a, b) => ishl),
+                    (simple "shl_u16"(a, b) => ishl),
+                    (simple "shl_u32"(a, b) => ishl),
+                    (simple "shl_u64"(a, b) => ishl),
+                    (complex "shl_u128"(a, b) => value::Value::new_val(shl_i128(fx, a, b), place.layout.clone())),
+                    (checked "add_i32_checked"(a, b) => add, true),
+                    (checked "add_i64_checked"(a, b) => add, true),
+                    (checked "add_u32_checked"(a, b) => add, false),
+                    (checked "add_u64_checked"(a, b) => add, false),
+                    (checked "sub_i32_checked"(a, b) => sub, true),
+                    (checked "sub_i64_checked"(a, b) => sub, true),
+                    (checked "sub_u32_checked"(a, b) => sub, false),
+                    (checked "sub_u64_checked"(a, b) => sub, false),
+                    (checked "mul_i32_checked"(a, b) => mul, true),
+                    (checked "mul_i64_checked"(a, b) => mul, true),
+                    (checked "mul_u32_checked"(a, b) => mul, false),
+                    (checked "mul_u64_checked"(a, b) => mul, false),
+                    (atomic "atomic_load_i8" => load, I8),
+                    (atomic "atomic_load_i16" => load, I16),
+                    (atomic "atomic_load_i32" => load, I32),
+                    (atomic "atomic_load_i64" => load, I64),
+                    (atomic "atomic_load_u8" => load, I8),
+                    (atomic "atomic_load_u16" => load, I16),
+                    (atomic "atomic_load_u32" => load, I32),
+                    (atomic "atomic_load_u64" => load, I64),
+                    (atomic "atomic_store_i8" => store, I8),
+                    (atomic "atomic_store_i16" => store, I16),
+                    (atomic "atomic_store_i32" => store, I32),
+                    (atomic "atomic_store_i64" => store, I64),
+                    (atomic "atomic_store_u8" => store, I8),
+                    (atomic "atomic_store_u16" => store, I16),
+                    (atomic "atomic_store_u32" => store, I32),
+                    (atomic "atomic_store_u64" => store, I64),
+                    (atomic "atomic_cmpxchg_i8" => cmpxchg, I8),
+                    (atomic "atomic_cmpxchg_i16" => cmpxchg, I16),
+                    (atomic "atomic_cmpxchg_i32" => cmpxchg, I32),
+                    (atomic "atomic_cmpxchg_i64" => cmpxchg, I64),
+                    (atomic "atomic_cmpxchg_u8" => cmpxchg, I8),
+                    (atomic "atomic_cmpxchg_u16" => cmpxchg, I16),
+                    (atomic "atomic_cmpxchg_u32" => cmpxchg, I32),
+                    (atomic "atomic_cmpxchg_u64" => cmpxchg, I64),
+                    (atomic "atomic_fetch_add_i8" => fetch_add, I8),
+                    (atomic "atomic_fetch_add_i16" => fetch_add, I16),
+                    (atomic "atomic_fetch_add_i32" => fetch_add, I32),
+                    (atomic "atomic_fetch_add_i64" => fetch_add, I64),
+                    (atomic "atomic_fetch_add_u8" => fetch_add, I8),
+                    (atomic "atomic_fetch_add_u16" => fetch_add, I16),
+                    (atomic "atomic_fetch_add_u32" => fetch_add, I32),
+                    (atomic "atomic_fetch_add_u64" => fetch_add, I64),
                     (complex "lt_i32"(a, b) => {
                         let val = fx.bcx.ins().icmp(clif::IntCC::SignedLessThan, a, b);
                         let val = fx.bcx.ins().bint(clif::types::I8, val);
@@ -367,9 +580,170 @@ impl<'ctx> TransMethods<'ctx> for ClifBackend<'ctx> {
                         value::Value::new_val(val, place.layout.clone())
                     }),
                     (complex "memcpy"(dst, src, n) => {
-                        fx.bcx.call_memcpy(fx.mcx.module.target_config(), dst, src, n);
+                        let config = fx.mcx.module.target_config();
+
+                        let dst_align = pointee_align(fx, &args[0]);
+                        let src_align = pointee_align(fx, &args[1]);
+
+                        match const_size(&args[2]) {
+                            | Some(size) if size % dst_align.min(src_align) as u64 == 0 => {
+                                fx.bcx.emit_small_memory_copy(config, dst, src, size, dst_align, src_align, true);
+                            },
+                            | _ => fx.bcx.call_memcpy(config, dst, src, n),
+                        }
+
+                        value::Value::new_unit()
+                    }),
+                    (complex "memset"(dst, c, n) => {
+                        let config = fx.mcx.module.target_config();
+
+                        let align = pointee_align(fx, &args[0]);
+
+                        // emit_small_memset only splats zero bytes correctly
+                        match (const_size(&args[1]), const_size(&args[2])) {
+                            | (Some(0), Some(size)) if size % align as u64 == 0 => {
+                                fx.bcx.emit_small_memset(config, dst, 0, size, align);
+                            },
+                            | _ => {
+                                let c = match fx.bcx.func.dfg.value_type(c) {
+                                    | clif::types::I8 => c,
+                                    | _ => fx.bcx.ins().ireduce(clif::types::I8, c),
+                                };
+
+                                fx.bcx.call_memset(config, dst, c, n);
+                            },
+                        }
+
                         value::Value::new_unit()
                     }),
+                    (complex "memcmp"(a, b, n) => {
+                        let mut memcmp = fx.module.make_signature();
+                        let ptr_type = fx.module.target_config().pointer_type();
+
+                        memcmp.returns.push(clif::AbiParam::new(clif::types::I32));
+                        memcmp.params.push(clif::AbiParam::new(ptr_type));
+                        memcmp.params.push(clif::AbiParam::new(ptr_type));
+                        memcmp.params.push(clif::AbiParam::new(ptr_type));
+
+                        let memcmp = fx.mcx.module.declare_function("memcmp", clif::Linkage::Import, &memcmp).unwrap();
+                        let memcmp = fx.mcx.module.declare_func_in_func(memcmp, &mut fx.bcx.func);
+                        let inst = fx.bcx.ins().call(memcmp, &[a, b, n]);
+                        let val = fx.bcx.inst_results(inst)[0];
+
+                        // libc only promises the sign, the interpreter returns -1, 0 or 1
+                        let gt = fx.bcx.ins().icmp_imm(clif::IntCC::SignedGreaterThan, val, 0);
+                        let gt = fx.bcx.ins().bint(clif::types::I32, gt);
+                        let lt = fx.bcx.ins().icmp_imm(clif::IntCC::SignedLessThan, val, 0);
+                        let lt = fx.bcx.ins().bint(clif::types::I32, lt);
+                        let val = fx.bcx.ins().isub(gt, lt);
+
+                        value::Value::new_val(val, place.layout.clone())
+                    }),
+                    (complex "f64_to_str"(val, buf, len) => {
+                        let val = call_runtime(fx, "f64_to_str", &[val, buf, len], Some(clif::types::I64)).unwrap();
+                        value::Value::new_val(val, place.layout.clone())
+                    }),
+                    (complex "i64_to_str"(val, buf, len) => {
+                        let val = call_runtime(fx, "i64_to_str", &[val, buf, len], Some(clif::types::I64)).unwrap();
+                        value::Value::new_val(val, place.layout.clone())
+                    }),
+                    (complex "u64_to_str"(val, buf, len) => {
+                        let val = call_runtime(fx, "u64_to_str", &[val, buf, len], Some(clif::types::I64)).unwrap();
+                        value::Value::new_val(val, place.layout.clone())
+                    }),
+                    (complex "str_to_f64"(buf, len, out) => {
+                        let val = call_runtime(fx, "str_to_f64", &[buf, len, out], Some(clif::types::I64)).unwrap();
+                        value::Value::new_val(val, place.layout.clone())
+                    }),
+                    (complex "str_to_i64"(buf, len, out) => {
+                        let val = call_runtime(fx, "str_to_i64", &[buf, len, out], Some(clif::types::I64)).unwrap();
+                        value::Value::new_val(val, place.layout.clone())
+                    }),
+                    (complex "str_to_u64"(buf, len, out) => {
+                        let val = call_runtime(fx, "str_to_u64", &[buf, len, out], Some(clif::types::I64)).unwrap();
+                        value::Value::new_val(val, place.layout.clone())
+                    }),
+                    (complex "bigint_from_str"(buf, len) => {
+                        let ptr_type = fx.module.target_config().pointer_type();
+                        let val = call_runtime(fx, "bigint_from_str", &[buf, len], Some(ptr_type)).unwrap();
+
+                        value::Value::new_val(val, place.layout.clone())
+                    }),
+                    (complex "bigint_to_str"(a, buf, len) => {
+                        let val = call_runtime(fx, "bigint_to_str", &[a, buf, len], Some(clif::types::I64)).unwrap();
+                        value::Value::new_val(val, place.layout.clone())
+                    }),
+                    (complex "bigint_add"(a, b) => {
+                        let ptr_type = fx.module.target_config().pointer_type();
+                        let val = call_runtime(fx, "bigint_add", &[a, b], Some(ptr_type)).unwrap();
+
+                        value::Value::new_val(val, place.layout.clone())
+                    }),
+                    (complex "bigint_mul"(a, b) => {
+                        let ptr_type = fx.module.target_config().pointer_type();
+                        let val = call_runtime(fx, "bigint_mul", &[a, b], Some(ptr_type)).unwrap();
+
+                        value::Value::new_val(val, place.layout.clone())
+                    }),
+                    (complex "bigint_cmp"(a, b) => {
+                        let val = call_runtime(fx, "bigint_cmp", &[a, b], Some(clif::types::I32)).unwrap();
+                        value::Value::new_val(val, place.layout.clone())
+                    }),
+                    (complex "bigint_free"(a) => {
+                        call_runtime(fx, "bigint_free", &[a], None);
+                        value::Value::new_unit()
+                    }),
+                    (complex "dyn_cast"(a, b) => {
+                        let ptr_type = fx.module.target_config().pointer_type();
+                        let (id, name) = (ptr_type.bytes() as i32 * 4, ptr_type.bytes() as i32 * 5);
+                        let cmp_names = fx.bcx.create_block();
+                        let done = fx.bcx.create_block();
+                        let val = fx.bcx.append_block_param(done, clif::types::I8);
+                        let id_a = fx.bcx.ins().load(ptr_type, clif::MemFlags::trusted(), a, id);
+                        let id_b = fx.bcx.ins().load(ptr_type, clif::MemFlags::trusted(), b, id);
+                        let same_id = fx.bcx.ins().icmp(clif::IntCC::Equal, id_a, id_b);
+                        let false_ = fx.bcx.ins().iconst(clif::types::I8, 0);
+
+                        // ids are truncated hashes, so the names are compared as well when they are equal
+                        fx.bcx.ins().brz(same_id, done, &[false_]);
+                        fx.bcx.ins().jump(cmp_names, &[]);
+                        fx.bcx.switch_to_block(cmp_names);
+
+                        let mut strcmp = fx.module.make_signature();
+
+                        strcmp.returns.push(clif::AbiParam::new(clif::types::I32));
+                        strcmp.params.push(clif::AbiParam::new(ptr_type));
+                        strcmp.params.push(clif::AbiParam::new(ptr_type));
+
+                        let strcmp = fx.mcx.module.declare_function("strcmp", clif::Linkage::Import, &strcmp).unwrap();
+                        let strcmp = fx.mcx.module.declare_func_in_func(strcmp, &mut fx.bcx.func);
+                        let name_a = fx.bcx.ins().load(ptr_type, clif::MemFlags::trusted(), a, name);
+                        let name_b = fx.bcx.ins().load(ptr_type, clif::MemFlags::trusted(), b, name);
+                        let inst = fx.bcx.ins().call(strcmp, &[name_a, name_b]);
+                        let cmp = fx.bcx.inst_results(inst)[0];
+                        let same_name = fx.bcx.ins().icmp_imm(clif::IntCC::Equal, cmp, 0);
+                        let same_name = fx.bcx.ins().bint(clif::types::I8, same_name);
+
+                        fx.bcx.ins().jump(done, &[same_name]);
+                        fx.bcx.switch_to_block(done);
+
+                        value::Value::new_val(val, place.layout.clone())
+                    }),
+                    (complex "resource"(ptr) => {
+                        let ptr_type = fx.module.target_config().pointer_type();
+                        let res_ty = ir::operand_type(fx.ir, fx.body, &args[0]);
+                        let size = ir::layout::layout_of(&res_ty, &fx.target).pointee(&fx.target).size.bytes();
+                        let len = fx.bcx.ins().iconst(ptr_type, size as i64);
+                        let res = place::Place::new_stack(fx, place.layout.clone());
+                        let layout = place.layout.field(0, &fx.target);
+
+                        res.clone().field(fx, 0).store(fx, value::Value::new_val(ptr, layout));
+
+                        let layout = place.layout.field(1, &fx.target);
+
+                        res.clone().field(fx, 1).store(fx, value::Value::new_val(len, layout));
+                        res.to_value(fx)
+                    }),
                     (complex "ptr_offset"(ptr, offset) => {
                         let ptr_ty = ir::operand_type(fx.ir, fx.body, &args[0]);
                         let pointee = ir::layout::layout_of(&ptr_ty, &fx.target).pointee(&fx.target);
@@ -403,69 +777,81 @@ impl<'ctx> TransMethods<'ctx> for ClifBackend<'ctx> {
                         fx.bcx.ins().call(free, &[ptr]);
                         value::Value::new_unit()
                     }),
-                    (complex "box_alloc"(n) => {
-                        let mut malloc = fx.module.make_signature();
+                    (complex "pool_create"(size, align, count) => {
                         let ptr_type = fx.module.target_config().pointer_type();
+                        let val = call_runtime(fx, "pool_create", &[size, align, count], Some(ptr_type)).unwrap();
 
-                        malloc.returns.push(clif::AbiParam::new(ptr_type));
-                        malloc.params.push(clif::AbiParam::new(ptr_type));
-
-                        let malloc = fx.mcx.module.declare_function("malloc", clif::Linkage::Import, &malloc).unwrap();
-                        let malloc = fx.mcx.module.declare_func_in_func(malloc, &mut fx.bcx.func);
-                        let inst = fx.bcx.ins().call(malloc, &[n]);
-                        let val = fx.bcx.inst_results(inst)[0];
-                        let n = fx.bcx.ins().iconst(ptr_type, ptr_type.bytes() as i64 * 3);
-                        let inst = fx.bcx.ins().call(malloc, &[n]);
-                        let ptr = fx.bcx.inst_results(inst)[0];
-                        let one = fx.bcx.ins().iconst(ptr_type, 1);
-                        let zero = fx.bcx.ins().iconst(ptr_type, 0);
+                        value::Value::new_val(val, place.layout.clone())
+                    }),
+                    (complex "pool_alloc"(pool) => {
+                        let ptr_type = fx.module.target_config().pointer_type();
+                        let val = call_runtime(fx, "pool_alloc", &[pool], Some(ptr_type)).unwrap();
 
-                        fx.bcx.ins().store(clif::MemFlags::trusted(), val, ptr, 0);
-                        fx.bcx.ins().store(clif::MemFlags::trusted(), one, ptr, ptr_type.bytes() as i32);
-                        fx.bcx.ins().store(clif::MemFlags::trusted(), zero, ptr, ptr_type.bytes() as i32);
+                        value::Value::new_val(val, place.layout.clone())
+                    }),
+                    (complex "pool_free"(pool, ptr) => {
+                        call_runtime(fx, "pool_free", &[pool, ptr], None);
+                        value::Value::new_unit()
+                    }),
+                    (complex "pool_destroy"(pool) => {
+                        call_runtime(fx, "pool_destroy", &[pool], None);
+                        value::Value::new_unit()
+                    }),
+                    (complex "box_alloc"(n) => {
+                        let ptr = boxes::call(fx, "box_alloc", &[n]).unwrap();
 
                         value::Value::new_val(ptr, place.layout.clone())
                     }),
                     (complex "box_free"(ptr) => {
-                        let mut free = fx.module.make_signature();
-                        let ptr_type = fx.module.target_config().pointer_type();
-
-                        free.params.push(clif::AbiParam::new(ptr_type));
-
-                        let free = fx.mcx.module.declare_function("free", clif::Linkage::Import, &free).unwrap();
-                        let free = fx.mcx.module.declare_func_in_func(free, &mut fx.bcx.func);
-                        let strong_count = fx.bcx.ins().load(ptr_type, clif::MemFlags::trusted(), ptr, ptr_type.bytes() as i32);
-                        let strong_count = fx.bcx.ins().irsub_imm(strong_count, 1);
-                        let if_zero = fx.bcx.create_block();
-                        let if_else = fx.bcx.create_block();
-                        let exit = fx.bcx.create_block();
-
-                        fx.bcx.ins().brz(strong_count, if_zero, &[]);
-                        fx.bcx.ins().jump(if_else, &[]);
-
-                        fx.bcx.switch_to_block(if_zero);
-
-                        let val = fx.bcx.ins().load(ptr_type, clif::MemFlags::trusted(), ptr, 0);
-
-                        fx.bcx.ins().call(free, &[val]);
-                        fx.bcx.ins().call(free, &[ptr]);
-                        fx.bcx.ins().jump(exit, &[]);
-
-                        fx.bcx.switch_to_block(if_else);
-                        fx.bcx.ins().store(clif::MemFlags::trusted(), strong_count, ptr, ptr_type.bytes() as i32);
-                        fx.bcx.ins().jump(exit, &[]);
-
-                        fx.bcx.switch_to_block(exit);
+                        boxes::call(fx, "box_free", &[ptr]);
                         value::Value::new_unit()
                     }),
                     (complex "box_copy"(ptr) => {
-                        let ptr_type = fx.module.target_config().pointer_type();
-                        let strong_count = fx.bcx.ins().load(ptr_type, clif::MemFlags::trusted(), ptr, ptr_type.bytes() as i32);
-                        let strong_count = fx.bcx.ins().iadd_imm(strong_count, 1);
-
-                        fx.bcx.ins().store(clif::MemFlags::trusted(), strong_count, ptr, ptr_type.bytes() as i32);
+                        boxes::call(fx, "box_copy", &[ptr]);
                         value::Value::new_unit()
                     }),
+                    (complex "splat"(x) => trans_splat(fx, x, place.layout.clone())),
+                    // vectors live in memory, so single lanes are accessed there like the fields of an array,
+                    // which unlike the lane instructions does not need SSE4.1 on x86
+                    (complex "extractlane"(vec, _lane) => {
+                        let lane = const_size(&args[1]).expect("extractlane requires a constant lane") as usize;
+                        let vec_ty = ir::operand_type(fx.ir, fx.body, &args[0]);
+                        let vec = place::Place::new_ref(ptr::Pointer::addr(vec), ir::layout::layout_of(&vec_ty, &fx.target));
+
+                        vec.field(fx, lane).to_value(fx)
+                    }),
+                    (complex "insertlane"(vec, x, _lane) => {
+                        let lane = const_size(&args[2]).expect("insertlane requires a constant lane") as usize;
+                        let res = place::Place::new_stack(fx, place.layout.clone());
+                        let layout = place.layout.field(lane, &fx.target);
+
+                        res.clone().store(fx, value::Value::new_ref(ptr::Pointer::addr(vec), place.layout.clone()));
+                        res.clone().field(fx, lane).store(fx, value::Value::new_val(x, layout));
+                        res.to_value(fx)
+                    }),
+                    (vector "vadd_i8x16" => iadd),
+                    (vector "vadd_i16x8" => iadd),
+                    (vector "vadd_i32x4" => iadd),
+                    (vector "vadd_i64x2" => iadd),
+                    (vector "vadd_f32x4" => fadd),
+                    (vector "vadd_f64x2" => fadd),
+                    (vector "vsub_i8x16" => isub),
+                    (vector "vsub_i16x8" => isub),
+                    (vector "vsub_i32x4" => isub),
+                    (vector "vsub_i64x2" => isub),
+                    (vector "vsub_f32x4" => fsub),
+                    (vector "vsub_f64x2" => fsub),
+                    (vector "vmul_i16x8" => imul),
+                    (vector "vmul_i32x4" => imul),
+                    (vector "vmul_f32x4" => fmul),
+                    (vector "vmul_f64x2" => fmul),
+                    (vector "vdiv_f32x4" => fdiv),
+                    (vector "vdiv_f64x2" => fdiv),
+                    (complex "select"(c, a, b) => {
+                        let c = fx.bcx.ins().icmp_imm(clif::IntCC::NotEqual, c, 0);
+                        let val = fx.bcx.ins().select(c, a, b);
+                        value::Value::new_val(val, place.layout.clone())
+                    }),
                 ]);
             },
         }
@@ -476,8 +862,32 @@ impl<'ctx> TransMethods<'ctx> for ClifBackend<'ctx> {
             | ir::Term::Abort => {
                 fx.bcx.ins().trap(clif::TrapCode::User(0));
             },
+            // compiled code aborts instead of unwinding, so cleanup blocks are never entered
+            | ir::Term::Resume => {
+                fx.bcx.ins().trap(clif::TrapCode::User(0));
+            },
             | ir::Term::Return => {
-                let rets = fx.body.rets().map(|r| abi::value_for_ret(fx, r.id)).flatten().collect::<Vec<_>>();
+                let conv = fx.ir.decls[fx.body.decl].ty.signature().call_conv;
+                let rules = abi::AggregateRules::for_conv(conv, &fx.target);
+                let rets = fx.body.rets().map(|r| r.id).collect::<Vec<_>>();
+                let ret_layouts = rets.iter().map(|id| fx.locals[id].layout().clone()).collect::<Vec<_>>();
+                let ret_modes = abi::get_ret_modes(fx.mcx, &ret_layouts, conv);
+                let rets = rets
+                    .into_iter()
+                    .zip(ret_modes)
+                    .map(|(id, mode)| {
+                        let place = fx.locals[&id].clone();
+
+                        match mode {
+                            | abi::PassMode::Cast(a, b) => cast_to_regs(fx, &place, a, b),
+                            | abi::PassMode::ByRef { size: _ } if rules.returns_sret_ptr() => {
+                                abi::EmptySinglePair::Single(place.as_ptr().get_addr(fx))
+                            },
+                            | _ => abi::value_for_ret(fx, id, mode),
+                        }
+                    })
+                    .flatten()
+                    .collect::<Vec<_>>();
 
                 fx.bcx.ins().return_(&rets);
             },
@@ -485,27 +895,116 @@ impl<'ctx> TransMethods<'ctx> for ClifBackend<'ctx> {
                 fx.bcx.ins().jump(fx.blocks[to], &[]);
             },
             | ir::Term::Switch(op, vals, blocks) => {
-                let mut switch = clif::Switch::new();
                 let otherwise = fx.blocks[blocks.last().unwrap()];
                 let val = Self::trans_op(fx, op, None);
+                let size = val.layout.size;
                 let val = val.load_scalar(fx);
+                let mut seen = std::collections::HashSet::new();
+
+                // only the first of several equal cases can be taken
+                let cases = vals
+                    .iter()
+                    .map(|v| size.truncate(*v))
+                    .zip(blocks)
+                    .filter(|(v, _)| seen.insert(*v))
+                    .collect::<Vec<_>>();
+
+                if size.bits() > 64 {
+                    // clif::Switch truncates its entries to 64 bits, so compare both halves instead
+                    let (lo, hi) = fx.bcx.ins().isplit(val);
+
+                    for (case, block) in cases {
+                        let next = fx.bcx.create_block();
+                        let lo_eq = fx.bcx.ins().icmp_imm(clif::IntCC::Equal, lo, case as u64 as i64);
+                        let hi_eq = fx.bcx.ins().icmp_imm(clif::IntCC::Equal, hi, (case >> 64) as u64 as i64);
+                        let eq = fx.bcx.ins().band(lo_eq, hi_eq);
 
-                for (val, block) in vals.iter().zip(blocks) {
-                    switch.set_entry(*val, fx.blocks[block]);
+                        fx.bcx.ins().brnz(eq, fx.blocks[block], &[]);
+                        fx.bcx.ins().jump(next, &[]);
+                        fx.bcx.switch_to_block(next);
+                    }
+
+                    fx.bcx.ins().jump(otherwise, &[]);
+                } else {
+                    let mut switch = clif::Switch::new();
+
+                    for (case, block) in cases {
+                        switch.set_entry(case, fx.blocks[block]);
+                    }
+
+                    switch.emit(&mut fx.bcx, val, otherwise)
                 }
+            },
+            | ir::Term::BrIf(op, then, else_) => {
+                let val = Self::trans_op(fx, op, None);
+                let val = val.load_scalar(fx);
+                let then_hint = fx.body.blocks[*then].hint;
+                let else_hint = fx.body.blocks[*else_].hint;
 
-                switch.emit(&mut fx.bcx, val, otherwise)
+                // the block that is jumped to unconditionally is the one the code falls through to when it is next
+                if then_hint == ir::BranchHint::Likely || (else_hint == ir::BranchHint::Cold && then_hint != ir::BranchHint::Cold) {
+                    fx.bcx.ins().brz(val, fx.blocks[else_], &[]);
+                    fx.bcx.ins().jump(fx.blocks[then], &[]);
+                } else {
+                    fx.bcx.ins().brnz(val, fx.blocks[then], &[]);
+                    fx.bcx.ins().jump(fx.blocks[else_], &[]);
+                }
             },
         }
     }
 
     fn trans_call(fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>, rets: Vec<place::Place<'ctx>>, func: &ir::Operand, args: Vec<value::Value<'ctx>>) {
-        let ret_modes = rets.into_iter().map(|r| (abi::get_pass_mode(fx.mcx, r.layout()), r)).collect::<Vec<_>>();
+        let func_ty = ir::operand_type(fx.ir, fx.body, func);
+        let mut sig = func_ty.signature();
+        let conv = sig.call_conv;
+        let rules = abi::AggregateRules::for_conv(conv, &fx.target);
+        let variadic = sig.variadic;
+
+        // the extra arguments are promoted and then passed as if the callee declared them
+        let args = if variadic {
+            let fixed = sig.params.len();
+            let args = args
+                .into_iter()
+                .enumerate()
+                .map(|(i, a)| if i < fixed { a } else { promote_vararg(fx, a) })
+                .collect::<Vec<_>>();
+
+            sig.params.extend(args[fixed..].iter().map(|a| a.layout.ty.clone()));
+            sig.variadic = false;
+            args
+        } else {
+            args
+        };
+
+        let ret_layouts = rets.iter().map(|r| r.layout().clone()).collect::<Vec<_>>();
+        let ret_modes = abi::get_ret_modes(fx.mcx, &ret_layouts, conv);
+
+        // the destination may be a variable, as only the pass mode of its own type is known when picking those,
+        // so these are returned to a stack slot first
+        let ret_modes = ret_modes
+            .into_iter()
+            .zip(rets)
+            .map(|(m, p)| match m {
+                | abi::PassMode::ByRef { size: _ }
+                    if rules != abi::AggregateRules::Default || !matches!(abi::get_pass_mode(fx.mcx, p.layout()), abi::PassMode::ByRef { .. }) =>
+                {
+                    let tmp = place::Place::new_stack(fx, p.layout.clone());
+
+                    (m, p, Some(tmp))
+                },
+                | abi::PassMode::Cast(_, _) => {
+                    let tmp = cast_place(fx, p.layout.clone());
+
+                    (m, p, Some(tmp))
+                },
+                | _ => (m, p, None),
+            })
+            .collect::<Vec<_>>();
 
         let ret_ptrs = ret_modes
             .iter()
-            .filter_map(|(m, p)| match m {
-                | abi::PassMode::ByRef { size: _ } => Some(p.as_ptr().get_addr(fx)),
+            .filter_map(|(m, p, tmp)| match m {
+                | abi::PassMode::ByRef { size: _ } => Some(tmp.as_ref().unwrap_or(p).as_ptr().get_addr(fx)),
                 | _ => None,
             })
             .collect::<Vec<_>>();
@@ -514,38 +1013,77 @@ impl<'ctx> TransMethods<'ctx> for ClifBackend<'ctx> {
             .into_iter()
             .chain(
                 args.into_iter()
-                    .map(|a| {
-                        value_for_arg!(fx, a, match a.on_stack(fx) {
+                    .map(|a| match abi::get_pass_mode_for(fx.mcx, a.layout(), conv) {
+                        | abi::PassMode::Cast(ty_a, ty_b) => {
+                            let tmp = cast_place(fx, a.layout.clone());
+
+                            tmp.clone().store(fx, a);
+                            cast_to_regs(fx, &tmp, ty_a, ty_b)
+                        },
+                        | abi::PassMode::ByRef { size: Some(_) } if rules != abi::AggregateRules::Default => {
+                            // the callee may copy from it in whole eightbytes
+                            let tmp = cast_place(fx, a.layout.clone());
+
+                            tmp.clone().store(fx, a);
+                            abi::EmptySinglePair::Single(tmp.as_ptr().get_addr(fx))
+                        },
+                        | _ => value_for_arg!(fx, a, match a.on_stack(fx) {
                             | (ptr, None) => abi::EmptySinglePair::Single(ptr.get_addr(fx)),
                             | (ptr, Some(meta)) => {
                                 abi::EmptySinglePair::Pair(ptr.get_addr(fx), meta)
                             },
-                        })
+                        }),
                     })
                     .flatten(),
             )
             .collect::<Vec<_>>();
 
-        let inst = if let ir::Operand::Const(ir::Const::Addr(id)) = func {
-            let func = fx.func_ids[id].0;
-            let func = fx.mcx.module.declare_func_in_func(func, &mut fx.bcx.func);
+        let inst = match func {
+            | ir::Operand::Const(ir::Const::Addr(id)) if !variadic => {
+                let func = fx.func_ids[id].0;
+                let func = fx.mcx.module.declare_func_in_func(func, &mut fx.bcx.func);
 
-            fx.bcx.ins().call(func, &args)
-        } else {
-            let func_ty = ir::operand_type(fx.ir, fx.body, func);
-            let sig = crate::mk_signature(fx.mcx, &func_ty.signature());
-            let sig = fx.bcx.import_signature(sig);
-            let func = Self::trans_op(fx, func, None).load_scalar(fx);
+                fx.bcx.ins().call(func, &args)
+            },
+            | _ => {
+                // a variadic callee is declared without its extra parameters, so it is called through a pointer
+                let mut sig = crate::mk_signature(fx.mcx, &sig).unwrap_or_else(|e| panic!("{}", e));
+                let mut args = args;
+
+                if variadic && rules == abi::AggregateRules::SysV && fx.target.architecture == target_lexicon::Architecture::X86_64 {
+                    // the callee reads the number of vector registers used by the arguments from %al,
+                    // an upper bound is enough as it only decides whether to save them
+                    let vector_regs = sig.params.iter().filter(|p| p.value_type.is_float() || p.value_type.is_vector()).count().min(8);
+                    let al = fx.module.isa().register_info().parse_regunit("rax").unwrap();
+
+                    sig.params.push(clif::AbiParam::special_reg(clif::types::I8, clif::ir::ArgumentPurpose::Normal, al));
+                    args.push(fx.bcx.ins().iconst(clif::types::I8, vector_regs as i64));
+                }
+
+                let sig = fx.bcx.import_signature(sig);
+                let func = Self::trans_op(fx, func, None).load_scalar(fx);
 
-            fx.bcx.ins().call_indirect(sig, func, &args)
+                fx.bcx.ins().call_indirect(sig, func, &args)
+            },
         };
 
         let mut res = fx.bcx.inst_results(inst).iter().copied().collect::<Vec<_>>().into_iter();
 
-        for (ret_mode, place) in ret_modes {
+        for (ret_mode, place, tmp) in ret_modes {
             match ret_mode {
                 | abi::PassMode::NoPass => {},
-                | abi::PassMode::ByRef { .. } => {},
+                | abi::PassMode::ByRef { .. } => {
+                    if let Some(tmp) = tmp {
+                        if rules.returns_sret_ptr() {
+                            // skip the returned struct return pointer
+                            res.next().unwrap();
+                        }
+
+                        let val = tmp.to_value(fx);
+
+                        place.store(fx, val);
+                    }
+                },
                 | abi::PassMode::ByVal(_) => {
                     let ret_val = res.next().unwrap();
                     let ret_val = value::Value::new_val(ret_val, place.layout.clone());
@@ -559,7 +1097,403 @@ impl<'ctx> TransMethods<'ctx> for ClifBackend<'ctx> {
 
                     place.store(fx, ret_val);
                 },
+                | abi::PassMode::Cast(_, b) => {
+                    let tmp = tmp.unwrap();
+                    let regs = res.by_ref().take(1 + b.is_some() as usize).collect::<Vec<_>>();
+
+                    cast_from_regs(fx, &tmp, &regs);
+
+                    let val = tmp.to_value(fx);
+
+                    place.store(fx, val);
+                },
             }
         }
     }
 }
+
+/// Applies the default argument promotions of C to an argument that is passed in place of `...`.
+fn promote_vararg<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>, arg: value::Value<'ctx>) -> value::Value<'ctx> {
+    use ir::layout::{Abi, Primitive, Scalar};
+
+    match arg.layout.abi {
+        | Abi::Scalar(Scalar { value: Primitive::F32, .. }) => {
+            let val = arg.load_scalar(fx);
+            let val = fx.bcx.ins().fpromote(clif::types::F64, val);
+
+            value::Value::new_val(val, ir::layout::layout_of(&ir::Ty::new(ir::Type::F64), &fx.target))
+        },
+        | Abi::Scalar(Scalar {
+            value: Primitive::Int(int, signed),
+            ..
+        }) if int.size().bits() < 32 => {
+            let val = arg.load_scalar(fx);
+            let (val, ty) = if signed {
+                (fx.bcx.ins().sextend(clif::types::I32, val), ir::Type::I32)
+            } else {
+                (fx.bcx.ins().uextend(clif::types::I32, val), ir::Type::U32)
+            };
+
+            value::Value::new_val(val, ir::layout::layout_of(&ir::Ty::new(ty), &fx.target))
+        },
+        | _ => arg,
+    }
+}
+
+fn const_size(op: &ir::Operand) -> Option<u64> {
+    match op {
+        | ir::Operand::Const(ir::Const::Scalar(val, _)) => Some(*val as u64),
+        | _ => None,
+    }
+}
+
+/// Lowers the lane-wise arithmetic of the vector intrinsics, whose operands are the addresses of the vectors.
+fn trans_vector<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>, op: &str, args: &[clif::Value], place: place::Place<'ctx>) {
+    let (ty, lane) = vector_type(fx, &place.layout);
+    let flags = clif::MemFlags::new();
+    let (a, b) = match args {
+        | [a, b] => (*a, *b),
+        | _ => panic!("incorrect number of arguments for intrinsic v{}", op),
+    };
+
+    // the legacy x86 backend can only multiply 32-bit lanes with SSE4.1, which not every x86_64 cpu has,
+    // so they are multiplied one at a time there
+    if op == "imul" && ty.lane_bits() == 32 && fx.module.isa().get_mach_backend().is_none() {
+        let res = place::Place::new_stack(fx, place.layout.clone());
+
+        for i in 0..ty.lane_count() as i32 {
+            let x = fx.bcx.ins().load(ty.lane_type(), flags, a, i * 4);
+            let y = fx.bcx.ins().load(ty.lane_type(), flags, b, i * 4);
+            let val = fx.bcx.ins().imul(x, y);
+
+            res.as_ptr().offset_i64(fx, i64::from(i) * 4).store(fx, val, flags);
+        }
+
+        let val = res.to_value(fx);
+
+        return place.store(fx, val);
+    }
+
+    let a = fx.bcx.ins().load(ty, flags, a, 0);
+    let b = fx.bcx.ins().load(ty, flags, b, 0);
+    let val = match op {
+        | "iadd" => fx.bcx.ins().iadd(a, b),
+        | "isub" => fx.bcx.ins().isub(a, b),
+        | "imul" => fx.bcx.ins().imul(a, b),
+        | "fadd" => fx.bcx.ins().fadd(a, b),
+        | "fsub" => fx.bcx.ins().fsub(a, b),
+        | "fmul" => fx.bcx.ins().fmul(a, b),
+        | "fdiv" => fx.bcx.ins().fdiv(a, b),
+        | _ => unreachable!(),
+    };
+
+    let val = value::canonicalize_nan(fx, val, lane);
+    let val = vector_value(fx, val, place.layout.clone());
+
+    place.store(fx, val);
+}
+
+/// Lowers `splat`, which the legacy x86 backend can only do for some lane types with the instruction sets
+/// of x86_64 and not at all for floats, so there every lane is written on its own.
+fn trans_splat<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>, x: clif::Value, layout: ir::layout::TyLayout) -> value::Value<'ctx> {
+    let (ty, _) = vector_type(fx, &layout);
+
+    if fx.module.isa().get_mach_backend().is_some() {
+        let val = fx.bcx.ins().splat(ty, x);
+
+        return vector_value(fx, val, layout);
+    }
+
+    let res = place::Place::new_stack(fx, layout);
+    let size = i64::from(ty.lane_bits() / 8);
+
+    for i in 0..i64::from(ty.lane_count()) {
+        res.as_ptr().offset_i64(fx, i * size).store(fx, x, clif::MemFlags::new());
+    }
+
+    res.to_value(fx)
+}
+
+/// The cranelift type of the vector type of `layout` and the primitive of its lanes.
+fn vector_type<'ctx>(fx: &FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>, layout: &ir::layout::TyLayout) -> (clif::Type, ir::layout::Primitive) {
+    let lane = layout.element(&fx.target);
+    let prim = match &lane.abi {
+        | ir::layout::Abi::Scalar(scalar) => scalar.value,
+        | _ => unreachable!(),
+    };
+
+    let lanes = layout.fields.count();
+    let ty = fx.ir_type(&lane).unwrap().by(lanes as u16);
+
+    (ty.unwrap_or_else(|| panic!("vectors of {} are not supported", ir::display_ty(&layout.ty))), prim)
+}
+
+/// Vectors are aggregates, so the result of a vector intrinsic is stored to a stack slot before it is used.
+fn vector_value<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>, val: clif::Value, layout: ir::layout::TyLayout) -> value::Value<'ctx> {
+    let res = place::Place::new_stack(fx, layout);
+
+    res.as_ptr().store(fx, val, clif::MemFlags::new());
+    res.to_value(fx)
+}
+
+/// Calls a function from the runtime library, whose signature follows from the argument types.
+fn call_runtime<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>, name: &str, args: &[clif::Value], ret: Option<clif::Type>) -> Option<clif::Value> {
+    let mut sig = fx.module.make_signature();
+
+    for arg in args {
+        sig.params.push(clif::AbiParam::new(fx.bcx.func.dfg.value_type(*arg)));
+    }
+
+    sig.returns.extend(ret.map(clif::AbiParam::new));
+
+    let func = fx.mcx.module.declare_function(name, clif::Linkage::Import, &sig).unwrap();
+    let func = fx.mcx.module.declare_func_in_func(func, &mut fx.bcx.func);
+    let inst = fx.bcx.ins().call(func, args);
+
+    fx.bcx.inst_results(inst).first().copied()
+}
+
+/// The alignment cranelift may assume for what `ptr` points to. Cranelift takes alignments as a `u8`,
+/// so larger ones are lowered to 128, which any multiple of them is a multiple of as well.
+fn pointee_align<'ctx>(fx: &FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>, ptr: &ir::Operand) -> u8 {
+    let ptr_ty = ir::operand_type(fx.ir, fx.body, ptr);
+    let align = ir::layout::layout_of(&ptr_ty, &fx.target).pointee(&fx.target).align.bytes();
+
+    align.clamp(1, 128) as u8
+}
+
+fn trans_atomic<'ctx>(
+    fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>,
+    op: &str,
+    ty: clif::Type,
+    ops: &[ir::Operand],
+    args: &[clif::Value],
+    place: place::Place<'ctx>,
+) {
+    let ordering = ops.last().and_then(ir::AtomicOrdering::from_operand);
+    let ordering = ordering.unwrap_or_else(|| panic!("atomic_{} requires a constant ordering", op));
+
+    match op {
+        | "load" | "store" if !ordering.is_valid_for(op == "store") => panic!("invalid ordering {:?} for atomic_{}", ordering, op),
+        | _ => {},
+    }
+
+    let args = args.iter().enumerate().map(|(i, v)| if i == 0 { *v } else { fit_int(fx, *v, ty) }).collect::<Vec<_>>();
+
+    // the legacy x86 backend has no encodings for atomic instructions,
+    // so fall back to the __atomic_* functions from libatomic/compiler-rt there
+    let (old, success) = if fx.module.isa().get_mach_backend().is_some() {
+        // cranelift's atomics are sequentially consistent, which satisfies every ordering
+        let flags = clif::MemFlags::trusted();
+
+        match (op, &args[..]) {
+            | ("load", [ptr, _]) => (Some(fx.bcx.ins().atomic_load(ty, flags, *ptr)), None),
+            | ("store", [ptr, val, _]) => {
+                fx.bcx.ins().atomic_store(flags, *val, *ptr);
+                (None, None)
+            },
+            | ("cmpxchg", [ptr, expected, new, _]) => {
+                let old = fx.bcx.ins().atomic_cas(flags, *ptr, *expected, *new);
+                let success = fx.bcx.ins().icmp(clif::IntCC::Equal, old, *expected);
+
+                (Some(old), Some(fx.bcx.ins().bint(clif::types::I8, success)))
+            },
+            | ("fetch_add", [ptr, val, _]) => (Some(fx.bcx.ins().atomic_rmw(ty, flags, clif::ir::AtomicRmwOp::Add, *ptr, *val)), None),
+            | _ => panic!("incorrect number of arguments for intrinsic atomic_{}", op),
+        }
+    } else {
+        use clif::types::{I32, I8};
+        let ptr_type = fx.module.target_config().pointer_type();
+        let order = |fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>, ordering: ir::AtomicOrdering| {
+            let order = match ordering {
+                | ir::AtomicOrdering::Relaxed => 0,
+                | ir::AtomicOrdering::Acquire => 2,
+                | ir::AtomicOrdering::Release => 3,
+                | ir::AtomicOrdering::AcqRel => 4,
+                | ir::AtomicOrdering::SeqCst => 5,
+            };
+
+            fx.bcx.ins().iconst(I32, order)
+        };
+
+        let name = format!("__atomic_{}_{}", if op == "cmpxchg" { "compare_exchange" } else { op }, ty.bytes());
+        let int = clif::AbiParam::new(ty).uext();
+        let (params, ret, call_args) = match (op, &args[..]) {
+            | ("load", [ptr, _]) => (vec![ptr_type, I32], Some(ty), vec![*ptr, order(fx, ordering)]),
+            | ("store", [ptr, val, _]) => (vec![ptr_type, ty, I32], None, vec![*ptr, *val, order(fx, ordering)]),
+            | ("fetch_add", [ptr, val, _]) => (vec![ptr_type, ty, I32], Some(ty), vec![*ptr, *val, order(fx, ordering)]),
+            | ("cmpxchg", [ptr, expected, new, _]) => {
+                let slot = fx
+                    .bcx
+                    .create_stack_slot(clif::StackSlotData::new(clif::StackSlotKind::ExplicitSlot, ty.bytes()));
+
+                fx.bcx.ins().stack_store(*expected, slot, 0);
+
+                let expected = fx.bcx.ins().stack_addr(ptr_type, slot, 0);
+                let weak = fx.bcx.ins().iconst(I8, 0);
+                let failure = match ordering {
+                    | ir::AtomicOrdering::Release => ir::AtomicOrdering::Relaxed,
+                    | ir::AtomicOrdering::AcqRel => ir::AtomicOrdering::Acquire,
+                    | ordering => ordering,
+                };
+
+                let success = order(fx, ordering);
+                let failure = order(fx, failure);
+                let call_args = vec![*ptr, expected, *new, weak, success, failure];
+
+                (vec![ptr_type, ptr_type, ty, I8, I32, I32], Some(I8), call_args)
+            },
+            | _ => panic!("incorrect number of arguments for intrinsic atomic_{}", op),
+        };
+
+        let mut sig = fx.module.make_signature();
+
+        sig.params.extend(params.into_iter().map(|t| if t == ty { int } else { clif::AbiParam::new(t) }));
+        sig.returns.extend(ret.map(|t| clif::AbiParam::new(t).uext()));
+
+        let func = fx.mcx.module.declare_function(&name, clif::Linkage::Import, &sig).unwrap();
+        let func = fx.mcx.module.declare_func_in_func(func, &mut fx.bcx.func);
+        let inst = fx.bcx.ins().call(func, &call_args);
+        let res = fx.bcx.inst_results(inst).first().copied();
+
+        if op == "cmpxchg" {
+            let expected = call_args[1];
+            let old = fx.bcx.ins().load(ty, clif::MemFlags::trusted(), expected, 0);
+
+            (Some(old), res)
+        } else {
+            (res, None)
+        }
+    };
+
+    match (old, success) {
+        | (Some(old), Some(success)) => {
+            let layout = place.layout.field(0, &fx.target);
+
+            place.clone().field(fx, 0).store(fx, value::Value::new_val(old, layout));
+
+            let layout = place.layout.field(1, &fx.target);
+
+            place.field(fx, 1).store(fx, value::Value::new_val(success, layout));
+        },
+        | (Some(old), None) => {
+            let old = value::Value::new_val(old, place.layout.clone());
+
+            place.store(fx, old);
+        },
+        | _ => {},
+    }
+}
+
+/// Small integer arguments may arrive widened by the calling convention.
+fn fit_int<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>, val: clif::Value, ty: clif::Type) -> clif::Value {
+    let from = fx.bcx.func.dfg.value_type(val);
+
+    if from.bits() > ty.bits() {
+        fx.bcx.ins().ireduce(ty, val)
+    } else if from.bits() < ty.bits() {
+        fx.bcx.ins().uextend(ty, val)
+    } else {
+        val
+    }
+}
+
+/// The legacy x86 backend cannot divide 128-bit integers, so division goes through the helpers of libgcc or compiler-rt.
+/// Nor can it pass them to calls, but they are passed and returned in two registers, just like their halves are.
+fn int128_libcall<'ctx>(
+    fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>,
+    name: &str,
+    a: clif::Value,
+    b: clif::Value,
+    layout: ir::layout::TyLayout,
+) -> value::Value<'ctx> {
+    let half = clif::AbiParam::new(clif::types::I64);
+    let mut sig = fx.module.make_signature();
+
+    sig.params.extend(std::iter::repeat(half).take(4));
+    sig.returns.extend(std::iter::repeat(half).take(2));
+
+    let func = fx.mcx.module.declare_function(name, clif::Linkage::Import, &sig).unwrap();
+    let func = fx.mcx.module.declare_func_in_func(func, &mut fx.bcx.func);
+    let (a_lo, a_hi) = fx.bcx.ins().isplit(a);
+    let (b_lo, b_hi) = fx.bcx.ins().isplit(b);
+    let inst = fx.bcx.ins().call(func, &[a_lo, a_hi, b_lo, b_hi]);
+    let (lo, hi) = (fx.bcx.inst_results(inst)[0], fx.bcx.inst_results(inst)[1]);
+    let val = fx.bcx.ins().iconcat(lo, hi);
+
+    value::Value::new_val(val, layout)
+}
+
+/// Shifts a 128-bit integer left by its halves, which the legacy x86 backend cannot do on its own.
+/// Like the interpreter, the amount is taken modulo 128.
+fn shl_i128<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>, a: clif::Value, b: clif::Value) -> clif::Value {
+    use clif::types::I64;
+
+    let (lo, hi) = fx.bcx.ins().isplit(a);
+    let amount = fit_int(fx, b, I64);
+    let amount = fx.bcx.ins().band_imm(amount, 127);
+    let zero = fx.bcx.ins().iconst(I64, 0);
+
+    // shifts by less than 64
+    let small_lo = fx.bcx.ins().ishl(lo, amount);
+    let small_hi = fx.bcx.ins().ishl(hi, amount);
+    // `lo >> (64 - amount)` in two steps, since shifting by 64 is shifting by 0
+    let carry = fx.bcx.ins().ushr_imm(lo, 1);
+    let rest = fx.bcx.ins().irsub_imm(amount, 63);
+    let carry = fx.bcx.ins().ushr(carry, rest);
+    let small_hi = fx.bcx.ins().bor(small_hi, carry);
+    // shifts by 64 or more move the low half into the high half, shifted by the rest of the amount
+    let is_large = fx.bcx.ins().icmp_imm(clif::IntCC::UnsignedGreaterThanOrEqual, amount, 64);
+    let lo = fx.bcx.ins().select(is_large, zero, small_lo);
+    let hi = fx.bcx.ins().select(is_large, small_lo, small_hi);
+
+    fx.bcx.ins().iconcat(lo, hi)
+}
+
+fn checked_arith<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>, op: &str, signed: bool, a: clif::Value, b: clif::Value) -> (clif::Value, clif::Value) {
+    let ty = fx.bcx.func.dfg.value_type(a);
+    let (val, overflow) = match (op, signed) {
+        | ("add", true) => {
+            let val = fx.bcx.ins().iadd(a, b);
+            let x = fx.bcx.ins().bxor(val, a);
+            let y = fx.bcx.ins().bxor(val, b);
+            let sign = fx.bcx.ins().band(x, y);
+
+            (val, fx.bcx.ins().icmp_imm(clif::IntCC::SignedLessThan, sign, 0))
+        },
+        | ("sub", true) => {
+            let val = fx.bcx.ins().isub(a, b);
+            let x = fx.bcx.ins().bxor(a, b);
+            let y = fx.bcx.ins().bxor(a, val);
+            let sign = fx.bcx.ins().band(x, y);
+
+            (val, fx.bcx.ins().icmp_imm(clif::IntCC::SignedLessThan, sign, 0))
+        },
+        | ("mul", true) => {
+            let val = fx.bcx.ins().imul(a, b);
+            let hi = fx.bcx.ins().smulhi(a, b);
+            let sign = fx.bcx.ins().sshr_imm(val, ty.bits() as i64 - 1);
+
+            (val, fx.bcx.ins().icmp(clif::IntCC::NotEqual, hi, sign))
+        },
+        | ("add", false) => {
+            let val = fx.bcx.ins().iadd(a, b);
+
+            (val, fx.bcx.ins().icmp(clif::IntCC::UnsignedLessThan, val, a))
+        },
+        | ("sub", false) => {
+            let val = fx.bcx.ins().isub(a, b);
+
+            (val, fx.bcx.ins().icmp(clif::IntCC::UnsignedLessThan, a, b))
+        },
+        | ("mul", false) => {
+            let val = fx.bcx.ins().imul(a, b);
+            let hi = fx.bcx.ins().umulhi(a, b);
+
+            (val, fx.bcx.ins().icmp_imm(clif::IntCC::NotEqual, hi, 0))
+        },
+        | _ => unreachable!(),
+    };
+
+    (val, fx.bcx.ins().bint(clif::types::I8, overflow))
+}
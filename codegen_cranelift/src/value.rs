@@ -64,6 +64,13 @@ impl<'ctx> Value<'ctx> {
             Abi::Scalar(scalar) => match scalar.value {
                 Primitive::F32 => fx.bcx.ins().f32const(f64::from_bits(val as u64) as f32),
                 Primitive::F64 => fx.bcx.ins().f64const(val as u64),
+                // iconst only takes a 64 bit immediate
+                _ if ty == cir::types::I128 => {
+                    let lo = fx.bcx.ins().iconst(cir::types::I64, val as i64);
+                    let hi = fx.bcx.ins().iconst(cir::types::I64, (val >> 64) as i64);
+
+                    fx.bcx.ins().iconcat(lo, hi)
+                }
                 _ => fx.bcx.ins().iconst(ty, val as i64),
             },
             _ => unimplemented!(),
@@ -142,16 +149,40 @@ impl<'ctx> codegen::Value<'ctx> for Value<'ctx> {
         }
     }
 
+    /// Converts between scalars of different primitives, see `cast_scalar`, and any other scalar to a bool
+    /// by comparing it with zero. Any other cast reinterprets the value with the new layout.
     fn cast(
         self,
-        _fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>,
+        fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>,
         layout: TyLayout,
     ) -> Self {
-        Value {
-            kind: self.kind,
-            layout,
-            _marker: PhantomData,
+        if let (ir::Type::Bool, Abi::Scalar(from)) = (&layout.ty.kind, &self.layout.abi) {
+            if self.layout.ty.kind != ir::Type::Bool {
+                let from = from.value;
+                let val = self.load_scalar(fx);
+                let val = is_nonzero(fx, val, from);
+                let val = fx.bcx.ins().bint(cir::types::I8, val);
+
+                return Value::new_val(val, layout);
+            }
         }
+
+        let (from, to) = match (&self.layout.abi, &layout.abi) {
+            | (Abi::Scalar(a), Abi::Scalar(b)) if a.value != b.value => (a.value, b.value),
+            | _ => {
+                return Value {
+                    kind: self.kind,
+                    layout,
+                    _marker: PhantomData,
+                }
+            },
+        };
+
+        let ty = fx.ir_type(&layout).unwrap();
+        let val = self.load_scalar(fx);
+        let val = cast_scalar(fx, val, from, to, ty);
+
+        Value::new_val(val, layout)
     }
 
     fn field(self, fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>, idx: usize) -> Self {
@@ -191,10 +222,7 @@ impl<'ctx> codegen::Value<'ctx> for Value<'ctx> {
         let ptr = self.load_scalar(fx);
 
         if is_box {
-            use crate::clif::Module;
-            let ptr_type = fx.module.target_config().pointer_type();
-            let ptr = Pointer::addr(ptr);
-            let ptr = ptr.load(fx, ptr_type, crate::clif::MemFlags::trusted());
+            let ptr = crate::boxes::call(fx, "box_addr_checked", &[ptr]).unwrap();
 
             Value::new_ref(Pointer::addr(ptr), pointee)
         } else {
@@ -232,3 +260,109 @@ fn gen_field<'ctx>(
         simple(fx)
     }
 }
+
+/// Integers and pointers are sign extended if the source is signed, zero extended otherwise and truncated
+/// to the target size. Floats are converted to integers with saturation, with NaN becoming zero.
+fn cast_scalar<'ctx>(
+    fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>,
+    val: cir::Value,
+    from: Primitive,
+    to: Primitive,
+    ty: cir::Type,
+) -> cir::Value {
+    let from_ty = fx.bcx.func.dfg.value_type(val);
+    let signed = |p| matches!(p, Primitive::Int(_, true));
+
+    match (from, to) {
+        | (Primitive::F32, Primitive::F64) => {
+            let val = fx.bcx.ins().fpromote(ty, val);
+
+            canonicalize_nan(fx, val, to)
+        },
+        | (Primitive::F64, Primitive::F32) => {
+            let val = fx.bcx.ins().fdemote(ty, val);
+
+            canonicalize_nan(fx, val, to)
+        },
+        | (Primitive::F32, _) | (Primitive::F64, _) if ty.bits() >= 32 => {
+            if signed(to) {
+                fx.bcx.ins().fcvt_to_sint_sat(ty, val)
+            } else {
+                fx.bcx.ins().fcvt_to_uint_sat(ty, val)
+            }
+        },
+        | (Primitive::F32, _) | (Primitive::F64, _) => {
+            // there is no saturating conversion to small integers, so clamp a 32 bit result
+            let bits = ty.bits();
+            let (min, max) = if signed(to) { (-(1i64 << (bits - 1)), (1i64 << (bits - 1)) - 1) } else { (0, (1i64 << bits) - 1) };
+            let wide = fx.bcx.ins().fcvt_to_sint_sat(cir::types::I32, val);
+            let min = fx.bcx.ins().iconst(cir::types::I32, min);
+            let max = fx.bcx.ins().iconst(cir::types::I32, max);
+            let below = fx.bcx.ins().icmp(cir::condcodes::IntCC::SignedLessThan, wide, min);
+            let wide = fx.bcx.ins().select(below, min, wide);
+            let above = fx.bcx.ins().icmp(cir::condcodes::IntCC::SignedGreaterThan, wide, max);
+            let wide = fx.bcx.ins().select(above, max, wide);
+
+            fx.bcx.ins().ireduce(ty, wide)
+        },
+        | (_, Primitive::F32) | (_, Primitive::F64) => {
+            if signed(from) {
+                fx.bcx.ins().fcvt_from_sint(ty, val)
+            } else {
+                fx.bcx.ins().fcvt_from_uint(ty, val)
+            }
+        },
+        | _ if from_ty.bits() < ty.bits() && signed(from) => fx.bcx.ins().sextend(ty, val),
+        | _ if from_ty.bits() < ty.bits() => fx.bcx.ins().uextend(ty, val),
+        | _ if from_ty.bits() > ty.bits() => fx.bcx.ins().ireduce(ty, val),
+        | _ => val,
+    }
+}
+
+/// Compares `val` with zero as a b1, with NaN counting as non-zero like it does in C.
+fn is_nonzero<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>, val: cir::Value, prim: Primitive) -> cir::Value {
+    match prim {
+        | Primitive::F32 => {
+            let zero = fx.bcx.ins().f32const(0.0);
+
+            fx.bcx.ins().fcmp(cir::condcodes::FloatCC::NotEqual, val, zero)
+        },
+        | Primitive::F64 => {
+            let zero = fx.bcx.ins().f64const(0.0);
+
+            fx.bcx.ins().fcmp(cir::condcodes::FloatCC::NotEqual, val, zero)
+        },
+        | _ => fx.bcx.ins().icmp_imm(cir::condcodes::IntCC::NotEqual, val, 0),
+    }
+}
+
+/// Replaces `val`, a float of type `prim` or a vector of them, with the canonical NaN if it is a NaN and
+/// `CodegenOptions::canonical_nans` is set, lane by lane for vectors.
+/// Conversions between floats keep the payload of a NaN on some hosts and not on others.
+pub fn canonicalize_nan<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, ClifBackend<'ctx>>, val: cir::Value, prim: Primitive) -> cir::Value {
+    let nan = match prim.canonical_nan() {
+        | Some(nan) if fx.options.canonical_nans => nan,
+        | _ => return val,
+    };
+
+    let ty = fx.bcx.func.dfg.value_type(val);
+    let is_nan = fx.bcx.ins().fcmp(cir::condcodes::FloatCC::Unordered, val, val);
+
+    if ty.is_vector() {
+        let size = ty.lane_bits() as usize / 8;
+        let lanes = (0..ty.lane_count()).flat_map(|_| nan.to_le_bytes()[..size].to_vec()).collect::<Vec<_>>();
+        let nan = fx.bcx.func.dfg.constants.insert(lanes.into());
+        let nan = fx.bcx.ins().vconst(ty, nan);
+        // a vector comparison sets all bits of the lanes it holds for
+        let is_nan = fx.bcx.ins().raw_bitcast(ty, is_nan);
+
+        return fx.bcx.ins().bitselect(is_nan, nan, val);
+    }
+
+    let nan = match prim {
+        | Primitive::F32 => fx.bcx.ins().f32const(cir::immediates::Ieee32::with_bits(nan as u32)),
+        | _ => fx.bcx.ins().f64const(cir::immediates::Ieee64::with_bits(nan as u64)),
+    };
+
+    fx.bcx.ins().select(is_nan, nan, val)
+}
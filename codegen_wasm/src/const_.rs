@@ -0,0 +1,211 @@
+use super::*;
+use codegen::artifact::Reloc;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// The contents of an anonymous constant. Identical constants share their data.
+#[derive(PartialEq, Eq, Hash)]
+pub(crate) struct ConstData {
+    bytes: Vec<u8>,
+    relocs: Vec<Reloc>,
+}
+
+impl<'ctx> ConstMethods<'ctx> for WasmBackend<'ctx> {
+    type Backend = Self;
+
+    fn alloc_const(
+        mcx: &mut ModuleCtx<'_, 'ctx, WasmBackend<'ctx>>,
+        c: &ir::Const,
+        layout: ir::layout::TyLayout,
+        data_id: Option<usize>,
+    ) -> usize {
+        if data_id.is_none() {
+            if let Some((ty, id)) = mcx.consts.get(c) {
+                if *ty == layout.ty {
+                    return *id;
+                }
+            }
+        }
+
+        let ty = layout.ty.clone();
+        let align = layout.align.bytes() as u32;
+        let mut bytes = Vec::with_capacity(layout.size.bytes() as usize);
+        let mut relocs = Vec::new();
+
+        fn reloc(bytes: &mut Vec<u8>, relocs: &mut Vec<Reloc>, kind: &str, symbol: &str, layout: &ir::layout::TyLayout) {
+            relocs.push(Reloc {
+                offset: bytes.len() as u32,
+                kind: kind.to_string(),
+                symbol: symbol.to_string(),
+                addend: 0,
+            });
+
+            bytes.extend(vec![0; layout.size.bytes() as usize]);
+        }
+
+        fn rec<'ctx>(
+            mcx: &mut ModuleCtx<'_, 'ctx, WasmBackend<'ctx>>,
+            c: &ir::Const,
+            layout: ir::layout::TyLayout,
+            bytes: &mut Vec<u8>,
+            relocs: &mut Vec<Reloc>,
+        ) {
+            match c {
+                ir::Const::Undefined(_) => bytes.resize(bytes.len() + layout.size.bytes() as usize, 0),
+                ir::Const::Scalar(s, _) => {
+                    let start = bytes.len();
+
+                    bytes.resize(start + layout.size.bytes() as usize, 0);
+                    ir::layout::write_scalar(*s, &mut bytes[start..], &mcx.target);
+                }
+                ir::Const::Bytes(b) => bytes.extend(&b[..]),
+                ir::Const::Addr(id) => {
+                    if let Some((func, _)) = mcx.func_ids.get(id) {
+                        let name = mcx.module.func_name(*func).to_string();
+
+                        reloc(bytes, relocs, encode::reloc::TABLE_INDEX_I32, &name, &layout);
+                    } else {
+                        let name = mcx.module.data_name(mcx.data_ids[id]).to_string();
+
+                        reloc(bytes, relocs, encode::reloc::MEMORY_ADDR_I32, &name, &layout);
+                    }
+                }
+                ir::Const::Ptr(to) => {
+                    let id = WasmBackend::alloc_const(mcx, to, layout.pointee(&mcx.target), None);
+                    let name = mcx.module.data_name(id).to_string();
+
+                    reloc(bytes, relocs, encode::reloc::MEMORY_ADDR_I32, &name, &layout);
+                }
+                ir::Const::Tuple(cs) => match &layout.fields {
+                    ir::layout::FieldsShape::Arbitrary { offsets } => {
+                        let mut i = 0;
+
+                        for (j, (c, offset)) in cs.iter().zip(offsets).enumerate() {
+                            bytes.extend(vec![0; offset.bytes() as usize - i]);
+                            i = offset.bytes() as usize;
+
+                            let field = layout.field(j, &mcx.target);
+
+                            i += field.size.bytes() as usize;
+                            rec(mcx, c, field, bytes, relocs);
+                        }
+                    }
+                    _ => unimplemented!(),
+                },
+                ir::Const::Variant(idx, cs, _) => match &layout.variants {
+                    ir::layout::Variants::Multiple { tag_encoding, tag_field, .. } => {
+                        if let ir::layout::FieldsShape::Arbitrary { offsets } = &layout.fields {
+                            match tag_encoding {
+                                ir::layout::TagEncoding::Direct => {
+                                    assert_eq!(*tag_field, 0);
+
+                                    let tag_layout = layout.field(0, &mcx.target);
+                                    let variant = layout.variant(*idx);
+                                    let mut i = tag_layout.size.bytes();
+
+                                    rec(mcx, &ir::Const::Scalar(*idx as u128, tag_layout.ty.clone()), tag_layout, bytes, relocs);
+
+                                    for (j, (c, offset)) in cs.iter().zip(offsets.iter().skip(1)).enumerate() {
+                                        bytes.extend(vec![0; (offset.bytes() - i) as usize]);
+                                        i = offset.bytes();
+
+                                        let field = variant.field(j, &mcx.target);
+
+                                        i += field.size.bytes();
+                                        rec(mcx, c, field, bytes, relocs);
+                                    }
+                                }
+                                ir::layout::TagEncoding::Niche {
+                                    dataful_variant,
+                                    niche_variants,
+                                    niche_start,
+                                } => {
+                                    let start = bytes.len();
+
+                                    if idx == dataful_variant {
+                                        // the payload is stored as is, its niche holds no other variant
+                                        let variant = layout.variant(*idx);
+
+                                        if let ir::layout::FieldsShape::Arbitrary { offsets } = &variant.fields {
+                                            for (j, (c, offset)) in cs.iter().zip(offsets).enumerate() {
+                                                bytes.resize(start + offset.bytes() as usize, 0);
+
+                                                let field = variant.field(j, &mcx.target);
+
+                                                rec(mcx, c, field, bytes, relocs);
+                                            }
+                                        }
+
+                                        bytes.resize(start + layout.size.bytes() as usize, 0);
+                                    } else {
+                                        let tag_layout = layout.field(*tag_field, &mcx.target);
+                                        let tag_offset = start + offsets[*tag_field].bytes() as usize;
+                                        let tag_size = tag_layout.size.bytes() as usize;
+                                        let niche_value = (*idx - *niche_variants.start()) as u128;
+                                        let niche_value = niche_value.wrapping_add(*niche_start);
+
+                                        bytes.resize(start + layout.size.bytes() as usize, 0);
+                                        ir::layout::write_scalar(niche_value, &mut bytes[tag_offset..tag_offset + tag_size], &mcx.target);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    _ => unreachable!(),
+                },
+            }
+        }
+
+        rec(mcx, c, layout, &mut bytes, &mut relocs);
+        bytes.resize(bytes.capacity(), 0);
+
+        let data_id = match data_id {
+            Some(id) => id,
+            None => {
+                let data = ConstData { bytes, relocs };
+
+                if let Some(id) = mcx.backend.anon_consts.get(&data) {
+                    let id = *id;
+
+                    mcx.consts.insert(c.clone(), (ty, id));
+                    return id;
+                }
+
+                let id = declare_anon(mcx, &data);
+
+                bytes = data.bytes.clone();
+                relocs = data.relocs.clone();
+                mcx.backend.anon_consts.insert(data, id);
+                mcx.consts.insert(c.clone(), (ty, id));
+                id
+            }
+        };
+
+        mcx.module.define_data(data_id, bytes, align, relocs);
+        data_id
+    }
+}
+
+/// Declares the data of an anonymous constant under a name derived from its contents,
+/// prefixed with the name of the module if it has one.
+fn declare_anon<'ctx>(mcx: &mut ModuleCtx<'_, 'ctx, WasmBackend<'ctx>>, data: &ConstData) -> usize {
+    let mut hasher = DefaultHasher::new();
+
+    data.hash(&mut hasher);
+
+    let base = match &mcx.options.module_name {
+        Some(module) => format!("__const.{}.{:016x}", module, hasher.finish()),
+        None => format!("__const.{:016x}", hasher.finish()),
+    };
+
+    let mut name = base.clone();
+    let mut n = 1;
+
+    // a different constant with the same hash
+    while mcx.module.get_name(&name).is_some() {
+        name = format!("{}.{}", base, n);
+        n += 1;
+    }
+
+    mcx.module.declare_data(&name, ir::Linkage::Local)
+}
@@ -0,0 +1,172 @@
+use crate::*;
+
+impl<'ctx> DeclMethods<'ctx> for WasmBackend<'ctx> {
+    type Backend = Self;
+
+    fn declare_static(mcx: &mut ModuleCtx<'_, 'ctx, WasmBackend<'ctx>>, decl: &ir::Decl) -> usize {
+        let data = mcx.module.declare_data(&decl.name, decl.linkage);
+
+        mcx.data_ids.insert(decl.id, data);
+        data
+    }
+
+    fn declare_func(mcx: &mut ModuleCtx<'_, 'ctx, WasmBackend<'ctx>>, decl: &ir::Decl) -> usize {
+        let ty = mk_functype(mcx, &decl.ty.signature());
+        let func = mcx.module.declare_function(&decl.name, decl.linkage, &ty);
+
+        mcx.func_ids.insert(decl.id, (func, ty));
+
+        func
+    }
+
+    fn define_func(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, func: usize) {
+        let artifact = Self::emit_func(fx, func);
+
+        Self::define_artifact(fx.mcx, func, &artifact);
+    }
+
+    fn emit_func(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, func: usize) -> artifact::FuncArtifact {
+        let code = fx.bcx.finish();
+        let artifact = artifact::FuncArtifact {
+            name: fx.module.func_name(func).to_string(),
+            code: code.bytes,
+            relocs: code.relocs,
+        };
+
+        dump_func(fx, &artifact);
+        artifact
+    }
+
+    fn define_artifact(mcx: &mut ModuleCtx<'_, 'ctx, WasmBackend<'ctx>>, func: usize, artifact: &artifact::FuncArtifact) {
+        mcx.module.define_function(func, artifact.code.clone(), artifact.relocs.clone());
+    }
+
+    fn func_prologue(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>) {
+        let ty = fx.func_ids[&fx.body.decl].1.clone();
+
+        fx.bcx.reset(ty.params, fx.body.blocks.len());
+
+        for (i, block) in fx.body.blocks.iter().enumerate() {
+            fx.blocks.insert(block.id, i);
+        }
+
+        let ssa_map = analyze::analyze(fx);
+        let conv = fx.ir.decls[fx.body.decl].ty.signature().call_conv;
+        // the parameters are the first locals
+        let mut param = 0..;
+
+        let rets = fx.body.rets().map(|ret| (ret.id, ir::layout::layout_of(&ret.ty, &fx.target))).collect::<Vec<_>>();
+        let ret_layouts = rets.iter().map(|(_, layout)| layout.clone()).collect::<Vec<_>>();
+
+        for ((id, layout), mode) in rets.into_iter().zip(abi::get_ret_modes(fx.mcx, &ret_layouts, conv)) {
+            match mode {
+                abi::PassMode::NoPass => {
+                    fx.locals.insert(id, place::Place::no_place(layout));
+                }
+                abi::PassMode::ByVal(_) | abi::PassMode::ByValPair(_, _) => {
+                    let ssa = ssa_map[&id] == analyze::SsaKind::Ssa;
+
+                    local_place(fx, id, layout, ssa);
+                }
+                abi::PassMode::Cast(_, _) => {
+                    let place = cast_place(fx, layout);
+
+                    fx.locals.insert(id, place);
+                }
+                abi::PassMode::ByRef { size: _ } => {
+                    let ptr = ptr::Pointer::addr(param.next().unwrap());
+
+                    fx.locals.insert(id, place::Place::new_ref(ptr, layout));
+                }
+            }
+        }
+
+        let args = fx.body.args().cloned().collect::<Vec<_>>();
+
+        for arg in args {
+            let layout = ir::layout::layout_of(&arg.ty, &fx.target);
+            let value = match abi::get_pass_mode_for(fx.mcx, &layout, conv) {
+                abi::PassMode::NoPass => {
+                    fx.locals.insert(arg.id, place::Place::no_place(layout));
+                    continue;
+                }
+                abi::PassMode::ByVal(_) => value::Value::new_val(param.next().unwrap(), layout),
+                abi::PassMode::ByValPair(_, _) => {
+                    let a = param.next().unwrap();
+                    let b = param.next().unwrap();
+
+                    value::Value::new_val_pair(a, b, layout)
+                }
+                abi::PassMode::Cast(_, b) => {
+                    let regs = (0..1 + b.is_some() as usize).map(|_| param.next().unwrap()).collect::<Vec<_>>();
+                    let place = cast_place(fx, layout.clone());
+
+                    cast_from_regs(fx, &place, &regs);
+                    value::Value::new_ref(place.as_ptr(), layout)
+                }
+                abi::PassMode::ByRef { size: _ } => value::Value::new_ref(ptr::Pointer::addr(param.next().unwrap()), layout),
+            };
+
+            let ssa = ssa_map[&arg.id] == analyze::SsaKind::Ssa;
+            let place = if ssa {
+                let place = if let ir::layout::Abi::ScalarPair(_, _) = value.layout.abi {
+                    place::Place::new_var_pair(fx, value.layout.clone())
+                } else {
+                    place::Place::new_var(fx, value.layout.clone())
+                };
+
+                place.clone().store(fx, value);
+                place
+            } else {
+                place::Place::new_ref(value.clone().on_stack(fx), value.layout)
+            };
+
+            fx.locals.insert(arg.id, place);
+        }
+
+        for local in &fx.body.locals {
+            if let ir::LocalKind::Var | ir::LocalKind::Tmp = local.kind {
+                let ssa = ssa_map[&local.id] == analyze::SsaKind::Ssa;
+                let layout = ir::layout::layout_of(&local.ty, &fx.target);
+
+                local_place(fx, local.id, layout, ssa);
+            }
+        }
+
+        fn local_place<'ctx>(
+            fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>,
+            local: ir::Local,
+            layout: ir::layout::TyLayout,
+            ssa: bool,
+        ) -> place::Place<'ctx> {
+            let place = if ssa {
+                if let ir::layout::Abi::ScalarPair(_, _) = layout.abi {
+                    place::Place::new_var_pair(fx, layout)
+                } else {
+                    place::Place::new_var(fx, layout)
+                }
+            } else {
+                place::Place::new_stack(fx, layout)
+            };
+
+            fx.locals.insert(local, place.clone());
+            place
+        }
+    }
+}
+
+/// Logs the generated function and writes it to `CodegenOptions::dump_ir` if that is set.
+fn dump_func<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, artifact: &artifact::FuncArtifact) {
+    let name = &fx.ir.decls[fx.body.decl].name;
+    let code = artifact.to_string();
+
+    log::debug!(target: "codegen_wasm::code", "{}:\n{}", name, code);
+
+    if let Some(dir) = &fx.mcx.options.dump_ir {
+        let path = dir.join(format!("{}.wasm.txt", name));
+
+        std::fs::create_dir_all(dir)
+            .and_then(|_| std::fs::write(&path, code))
+            .unwrap_or_else(|e| panic!("could not dump {}: {}", path.display(), e));
+    }
+}
@@ -0,0 +1,427 @@
+use codegen::artifact::Reloc;
+use std::fmt;
+use std::str::FromStr;
+
+/// The opcodes this backend emits, named after the instructions of the text format.
+#[allow(dead_code)]
+pub mod op {
+    pub const UNREACHABLE: u8 = 0x00;
+    pub const BLOCK: u8 = 0x02;
+    pub const LOOP: u8 = 0x03;
+    pub const IF: u8 = 0x04;
+    pub const ELSE: u8 = 0x05;
+    pub const END: u8 = 0x0B;
+    pub const BR: u8 = 0x0C;
+    pub const BR_IF: u8 = 0x0D;
+    pub const BR_TABLE: u8 = 0x0E;
+    pub const RETURN: u8 = 0x0F;
+    pub const CALL: u8 = 0x10;
+    pub const CALL_INDIRECT: u8 = 0x11;
+    pub const DROP: u8 = 0x1A;
+    pub const SELECT: u8 = 0x1B;
+    pub const LOCAL_GET: u8 = 0x20;
+    pub const LOCAL_SET: u8 = 0x21;
+    pub const LOCAL_TEE: u8 = 0x22;
+    pub const GLOBAL_GET: u8 = 0x23;
+    pub const GLOBAL_SET: u8 = 0x24;
+    pub const I32_LOAD: u8 = 0x28;
+    pub const I64_LOAD: u8 = 0x29;
+    pub const F32_LOAD: u8 = 0x2A;
+    pub const F64_LOAD: u8 = 0x2B;
+    pub const I32_LOAD8_S: u8 = 0x2C;
+    pub const I32_LOAD8_U: u8 = 0x2D;
+    pub const I32_LOAD16_S: u8 = 0x2E;
+    pub const I32_LOAD16_U: u8 = 0x2F;
+    pub const I32_STORE: u8 = 0x36;
+    pub const I64_STORE: u8 = 0x37;
+    pub const F32_STORE: u8 = 0x38;
+    pub const F64_STORE: u8 = 0x39;
+    pub const I32_STORE8: u8 = 0x3A;
+    pub const I32_STORE16: u8 = 0x3B;
+    pub const I32_CONST: u8 = 0x41;
+    pub const I64_CONST: u8 = 0x42;
+    pub const F32_CONST: u8 = 0x43;
+    pub const F64_CONST: u8 = 0x44;
+    pub const I32_EQZ: u8 = 0x45;
+    pub const I32_EQ: u8 = 0x46;
+    pub const I32_NE: u8 = 0x47;
+    pub const I32_LT_S: u8 = 0x48;
+    pub const I32_LT_U: u8 = 0x49;
+    pub const I32_GT_S: u8 = 0x4A;
+    pub const I32_GT_U: u8 = 0x4B;
+    pub const I32_LE_S: u8 = 0x4C;
+    pub const I32_LE_U: u8 = 0x4D;
+    pub const I32_GE_S: u8 = 0x4E;
+    pub const I32_GE_U: u8 = 0x4F;
+    pub const I64_EQZ: u8 = 0x50;
+    pub const I64_EQ: u8 = 0x51;
+    pub const I64_NE: u8 = 0x52;
+    pub const I64_LT_S: u8 = 0x53;
+    pub const I64_LT_U: u8 = 0x54;
+    pub const I64_GT_U: u8 = 0x56;
+    pub const I64_LE_U: u8 = 0x58;
+    pub const F32_NE: u8 = 0x5C;
+    pub const F64_NE: u8 = 0x62;
+    pub const I32_ADD: u8 = 0x6A;
+    pub const I32_SUB: u8 = 0x6B;
+    pub const I32_MUL: u8 = 0x6C;
+    pub const I32_DIV_S: u8 = 0x6D;
+    pub const I32_DIV_U: u8 = 0x6E;
+    pub const I32_REM_S: u8 = 0x6F;
+    pub const I32_REM_U: u8 = 0x70;
+    pub const I32_AND: u8 = 0x71;
+    pub const I32_OR: u8 = 0x72;
+    pub const I32_XOR: u8 = 0x73;
+    pub const I32_SHL: u8 = 0x74;
+    pub const I32_SHR_S: u8 = 0x75;
+    pub const I32_SHR_U: u8 = 0x76;
+    pub const I64_ADD: u8 = 0x7C;
+    pub const I64_SUB: u8 = 0x7D;
+    pub const I64_MUL: u8 = 0x7E;
+    pub const I64_DIV_S: u8 = 0x7F;
+    pub const I64_DIV_U: u8 = 0x80;
+    pub const I64_REM_S: u8 = 0x81;
+    pub const I64_REM_U: u8 = 0x82;
+    pub const I64_AND: u8 = 0x83;
+    pub const I64_OR: u8 = 0x84;
+    pub const I64_XOR: u8 = 0x85;
+    pub const I64_SHL: u8 = 0x86;
+    pub const I64_SHR_S: u8 = 0x87;
+    pub const I64_SHR_U: u8 = 0x88;
+    pub const F32_NEG: u8 = 0x8C;
+    pub const F64_NEG: u8 = 0x9A;
+    pub const I32_WRAP_I64: u8 = 0xA7;
+    pub const I64_EXTEND_I32_S: u8 = 0xAC;
+    pub const I64_EXTEND_I32_U: u8 = 0xAD;
+    pub const F32_CONVERT_I32_S: u8 = 0xB2;
+    pub const F32_CONVERT_I32_U: u8 = 0xB3;
+    pub const F32_CONVERT_I64_S: u8 = 0xB4;
+    pub const F32_CONVERT_I64_U: u8 = 0xB5;
+    pub const F32_DEMOTE_F64: u8 = 0xB6;
+    pub const F64_CONVERT_I32_S: u8 = 0xB7;
+    pub const F64_CONVERT_I32_U: u8 = 0xB8;
+    pub const F64_CONVERT_I64_S: u8 = 0xB9;
+    pub const F64_CONVERT_I64_U: u8 = 0xBA;
+    pub const F64_PROMOTE_F32: u8 = 0xBB;
+    pub const I32_REINTERPRET_F32: u8 = 0xBC;
+    pub const I64_REINTERPRET_F64: u8 = 0xBD;
+    pub const F32_REINTERPRET_I32: u8 = 0xBE;
+    pub const F64_REINTERPRET_I64: u8 = 0xBF;
+    pub const I32_EXTEND8_S: u8 = 0xC0;
+    pub const I32_EXTEND16_S: u8 = 0xC1;
+    /// The prefix of the saturating conversions and the bulk memory instructions.
+    pub const PREFIX_FC: u8 = 0xFC;
+    pub const I32_TRUNC_SAT_F32_S: u32 = 0;
+    pub const I32_TRUNC_SAT_F32_U: u32 = 1;
+    pub const I32_TRUNC_SAT_F64_S: u32 = 2;
+    pub const I32_TRUNC_SAT_F64_U: u32 = 3;
+    pub const I64_TRUNC_SAT_F32_S: u32 = 4;
+    pub const I64_TRUNC_SAT_F32_U: u32 = 5;
+    pub const I64_TRUNC_SAT_F64_S: u32 = 6;
+    pub const I64_TRUNC_SAT_F64_U: u32 = 7;
+    pub const MEMORY_COPY: u32 = 10;
+    pub const MEMORY_FILL: u32 = 11;
+}
+
+/// The relocations of function bodies, named like those of wasm object files. Their values are written
+/// as LEBs padded to 5 bytes, so that they can be patched without moving the code after them.
+pub mod reloc {
+    pub const FUNCTION_INDEX_LEB: &str = "R_WASM_FUNCTION_INDEX_LEB";
+    pub const TABLE_INDEX_SLEB: &str = "R_WASM_TABLE_INDEX_SLEB";
+    pub const MEMORY_ADDR_SLEB: &str = "R_WASM_MEMORY_ADDR_SLEB";
+    pub const GLOBAL_INDEX_LEB: &str = "R_WASM_GLOBAL_INDEX_LEB";
+    /// The symbol of a type index is the signature, as written by `FuncType`'s `Display`.
+    pub const TYPE_INDEX_LEB: &str = "R_WASM_TYPE_INDEX_LEB";
+    /// Relocations in data, which are written as 4 byte little endian integers.
+    pub const MEMORY_ADDR_I32: &str = "R_WASM_MEMORY_ADDR_I32";
+    pub const TABLE_INDEX_I32: &str = "R_WASM_TABLE_INDEX_I32";
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValType {
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl ValType {
+    pub fn code(self) -> u8 {
+        match self {
+            ValType::I32 => 0x7F,
+            ValType::I64 => 0x7E,
+            ValType::F32 => 0x7D,
+            ValType::F64 => 0x7C,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ValType::I32 => "i32",
+            ValType::I64 => "i64",
+            ValType::F32 => "f32",
+            ValType::F64 => "f64",
+        }
+    }
+
+    pub fn bytes(self) -> u32 {
+        match self {
+            ValType::I32 | ValType::F32 => 4,
+            ValType::I64 | ValType::F64 => 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct FuncType {
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+}
+
+impl fmt::Display for FuncType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let list = |tys: &[ValType]| tys.iter().map(|t| t.name()).collect::<Vec<_>>().join(", ");
+
+        write!(f, "({}) -> ({})", list(&self.params), list(&self.results))
+    }
+}
+
+impl FromStr for FuncType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let list = |s: &str| {
+            let s = s.trim().strip_prefix('(').and_then(|s| s.strip_suffix(')')).ok_or_else(|| format!("invalid signature '{}'", s))?;
+
+            s.split(", ")
+                .filter(|t| !t.is_empty())
+                .map(|t| match t {
+                    "i32" => Ok(ValType::I32),
+                    "i64" => Ok(ValType::I64),
+                    "f32" => Ok(ValType::F32),
+                    "f64" => Ok(ValType::F64),
+                    _ => Err(format!("unknown value type '{}'", t)),
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        match s.split_once(" -> ") {
+            Some((params, results)) => Ok(FuncType {
+                params: list(params)?,
+                results: list(results)?,
+            }),
+            None => Err(format!("invalid signature '{}'", s)),
+        }
+    }
+}
+
+/// A piece of a function body and the relocations in it, with offsets from its start.
+#[derive(Debug, Clone, Default)]
+pub struct Code {
+    pub bytes: Vec<u8>,
+    pub relocs: Vec<Reloc>,
+    /// The number of blocks, loops and ifs that are open, which branches out of the piece have to count.
+    pub depth: u32,
+}
+
+impl Code {
+    pub fn op(&mut self, op: u8) -> &mut Self {
+        self.bytes.push(op);
+        self
+    }
+
+    pub fn op_fc(&mut self, op: u32) -> &mut Self {
+        self.bytes.push(op::PREFIX_FC);
+        uleb(&mut self.bytes, op as u64);
+        self
+    }
+
+    pub fn local_get(&mut self, local: u32) -> &mut Self {
+        self.op(op::LOCAL_GET);
+        uleb(&mut self.bytes, local as u64);
+        self
+    }
+
+    pub fn local_set(&mut self, local: u32) -> &mut Self {
+        self.op(op::LOCAL_SET);
+        uleb(&mut self.bytes, local as u64);
+        self
+    }
+
+    pub fn local_tee(&mut self, local: u32) -> &mut Self {
+        self.op(op::LOCAL_TEE);
+        uleb(&mut self.bytes, local as u64);
+        self
+    }
+
+    pub fn i32_const(&mut self, val: i32) -> &mut Self {
+        self.op(op::I32_CONST);
+        sleb(&mut self.bytes, val as i64);
+        self
+    }
+
+    pub fn i64_const(&mut self, val: i64) -> &mut Self {
+        self.op(op::I64_CONST);
+        sleb(&mut self.bytes, val);
+        self
+    }
+
+    pub fn f32_const(&mut self, bits: u32) -> &mut Self {
+        self.op(op::F32_CONST);
+        self.bytes.extend(&bits.to_le_bytes());
+        self
+    }
+
+    pub fn f64_const(&mut self, bits: u64) -> &mut Self {
+        self.op(op::F64_CONST);
+        self.bytes.extend(&bits.to_le_bytes());
+        self
+    }
+
+    /// A load or store at `offset` from the address on the stack. The alignment is only a hint,
+    /// so every access claims to be unaligned.
+    pub fn mem(&mut self, op: u8, offset: u32) -> &mut Self {
+        self.op(op);
+        uleb(&mut self.bytes, 0);
+        uleb(&mut self.bytes, offset as u64);
+        self
+    }
+
+    pub fn block(&mut self, op: u8) -> &mut Self {
+        self.depth += 1;
+        self.op(op);
+        // the empty block type
+        self.op(0x40)
+    }
+
+    pub fn end(&mut self) -> &mut Self {
+        self.depth -= 1;
+        self.op(op::END)
+    }
+
+    pub fn br(&mut self, depth: u32) -> &mut Self {
+        self.op(op::BR);
+        uleb(&mut self.bytes, depth as u64);
+        self
+    }
+
+    pub fn br_table(&mut self, depths: impl ExactSizeIterator<Item = u32>, default: u32) -> &mut Self {
+        self.op(op::BR_TABLE);
+        uleb(&mut self.bytes, depths.len() as u64);
+
+        for depth in depths {
+            uleb(&mut self.bytes, depth as u64);
+        }
+
+        uleb(&mut self.bytes, default as u64);
+        self
+    }
+
+    pub fn call(&mut self, func: &str) -> &mut Self {
+        self.op(op::CALL);
+        self.reloc(reloc::FUNCTION_INDEX_LEB, func, 0)
+    }
+
+    pub fn call_indirect(&mut self, ty: &FuncType) -> &mut Self {
+        self.op(op::CALL_INDIRECT);
+        self.reloc(reloc::TYPE_INDEX_LEB, &ty.to_string(), 0);
+        // the table
+        self.op(0x00)
+    }
+
+    /// Pushes the address of the data `symbol` plus `addend`.
+    pub fn memory_addr(&mut self, symbol: &str, addend: i64) -> &mut Self {
+        self.op(op::I32_CONST);
+        self.reloc(reloc::MEMORY_ADDR_SLEB, symbol, addend)
+    }
+
+    /// Pushes the index of the function `symbol` in the table, which is what a function pointer holds.
+    pub fn table_index(&mut self, symbol: &str) -> &mut Self {
+        self.op(op::I32_CONST);
+        self.reloc(reloc::TABLE_INDEX_SLEB, symbol, 0)
+    }
+
+    pub fn global(&mut self, op: u8, symbol: &str) -> &mut Self {
+        self.op(op);
+        self.reloc(reloc::GLOBAL_INDEX_LEB, symbol, 0)
+    }
+
+    fn reloc(&mut self, kind: &str, symbol: &str, addend: i64) -> &mut Self {
+        self.relocs.push(Reloc {
+            offset: self.bytes.len() as u32,
+            kind: kind.to_string(),
+            symbol: symbol.to_string(),
+            addend,
+        });
+
+        self.bytes.extend(&[0x80, 0x80, 0x80, 0x80, 0x00]);
+        self
+    }
+
+    /// Appends `other`, moving its relocations along.
+    pub fn append(&mut self, other: &Code) {
+        let start = self.bytes.len() as u32;
+
+        self.bytes.extend(&other.bytes);
+        self.relocs.extend(other.relocs.iter().map(|r| Reloc {
+            offset: r.offset + start,
+            ..r.clone()
+        }));
+    }
+}
+
+pub fn uleb(out: &mut Vec<u8>, mut val: u64) {
+    loop {
+        let byte = (val & 0x7F) as u8;
+
+        val >>= 7;
+
+        if val == 0 {
+            out.push(byte);
+            return;
+        }
+
+        out.push(byte | 0x80);
+    }
+}
+
+pub fn sleb(out: &mut Vec<u8>, mut val: i64) {
+    loop {
+        let byte = (val & 0x7F) as u8;
+
+        val >>= 7;
+
+        if (val == 0 && byte & 0x40 == 0) || (val == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+
+        out.push(byte | 0x80);
+    }
+}
+
+/// Writes `val` over the 5 bytes reserved by a relocation, as an unsigned or signed LEB.
+pub fn patch_leb(out: &mut [u8], val: i64, signed: bool) {
+    assert!(
+        if signed { i32::MIN as i64 <= val && val <= i32::MAX as i64 } else { 0 <= val && val <= u32::MAX as i64 },
+        "relocation value {} out of range",
+        val
+    );
+
+    for (i, byte) in out[..5].iter_mut().enumerate() {
+        let bits = ((val >> (i * 7)) & 0x7F) as u8;
+
+        *byte = if i < 4 { bits | 0x80 } else { bits };
+    }
+}
+
+pub fn name(out: &mut Vec<u8>, name: &str) {
+    uleb(out, name.len() as u64);
+    out.extend(name.as_bytes());
+}
+
+pub fn section(out: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    out.push(id);
+    uleb(out, contents.len() as u64);
+    out.extend(contents);
+}
@@ -0,0 +1,377 @@
+#![feature(generic_associated_types)]
+
+mod const_;
+mod decl;
+pub mod encode;
+pub mod module;
+pub mod place;
+pub mod ptr;
+mod trans;
+pub mod value;
+
+use codegen::*;
+use encode::{Code, FuncType, ValType};
+use module::WasmModule;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+/// Generates a WebAssembly module for `wasm32` targets instead of an object file.
+///
+/// Scalars that are not in memory are kept in wasm locals, everything else lives in a frame on a stack in
+/// linear memory. Function pointers are indices into the table, and the blocks of a function are
+/// dispatched by a loop, so that any control flow graph can be expressed with the structured control flow of wasm.
+pub struct WasmBackend<'ctx> {
+    func_ids: HashMap<ir::DeclId, (usize, FuncType)>,
+    data_ids: HashMap<ir::DeclId, usize>,
+    anon_consts: HashMap<const_::ConstData, usize>,
+    _marker: PhantomData<&'ctx ()>,
+}
+
+impl<'ctx> WasmBackend<'ctx> {
+    pub fn new() -> Self {
+        WasmBackend {
+            func_ids: HashMap::new(),
+            data_ids: HashMap::new(),
+            anon_consts: HashMap::new(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'ctx> Backend<'ctx> for WasmBackend<'ctx> {
+    type Module = WasmModule;
+    type Context = ();
+    type FuncContext = FuncContext;
+    type Builder<'bcx> = &'bcx mut FuncContext;
+    type Func = usize;
+    type Static = usize;
+    type Block = usize;
+    type Place = place::Place<'ctx>;
+    type Value = value::Value<'ctx>;
+    type Type = WasmType;
+
+    fn create_module(&mut self, target: &target_lexicon::Triple) -> Result<Self::Module, CodegenError> {
+        if target.architecture != target_lexicon::Architecture::Wasm32 {
+            return Err(CodegenError::UnsupportedTarget(format!("the wasm backend only generates code for wasm32, not {}", target)));
+        }
+
+        Ok(WasmModule::new())
+    }
+
+    fn create_context(&mut self, _module: &mut Self::Module) -> Self::Context {
+    }
+
+    fn create_func_context(&mut self) -> Self::FuncContext {
+        FuncContext::default()
+    }
+
+    fn create_builder<'bcx>(&mut self, ctx: &'bcx mut Self::FuncContext) -> Self::Builder<'bcx> {
+        ctx
+    }
+
+    fn finish(mcx: ModuleCtx<'_, 'ctx, Self>) -> Result<obj_file::ObjectFile, CodegenError> {
+        let mut obj_file = obj_file::ObjectFile::new();
+        let caps = ir::capability::Capabilities::of(mcx.ir);
+        let bytes = mcx.module.finish(&[(ir::capability::SECTION, caps.to_bytes().to_vec())]);
+
+        obj_file.write(&bytes);
+        Ok(obj_file)
+    }
+
+    fn code_config(mcx: &ModuleCtx<'_, 'ctx, Self>) -> String {
+        format!("{}\nwasm\ncanonical_nans = {}\ncodegen_wasm {}", mcx.target, mcx.options.canonical_nans, env!("CARGO_PKG_VERSION"))
+    }
+
+    fn supports_feature(&self, feature: &support::Feature, _triple: &target_lexicon::Triple) -> bool {
+        match feature {
+            support::Feature::Instruction(_) => true,
+            support::Feature::Intrinsic(name) => trans::INTRINSICS.contains(&name.as_str()),
+            // wasm has no calling conventions of its own, the foreign ones are those of other architectures
+            support::Feature::CallConv(conv) => matches!(conv, ir::CallConv::Fluix | ir::CallConv::C | ir::CallConv::Fastcall),
+            // clang passes the extra arguments in memory, which is not implemented
+            support::Feature::Variadic(_) => false,
+            support::Feature::TupleConst => false,
+            support::Feature::Box | support::Feature::Int128 => false,
+        }
+    }
+}
+
+/// The function being built, as pieces of code for the entry and every block,
+/// which are put together with the locals once the size of the frame is known.
+#[derive(Default)]
+pub struct FuncContext {
+    params: Vec<ValType>,
+    locals: Vec<ValType>,
+    entry: Code,
+    blocks: Vec<Code>,
+    /// The block being built, or `None` while building the entry.
+    current: Option<usize>,
+    frame_size: u32,
+    /// The local holding the address of the frame.
+    fp: u32,
+    /// The local holding the stack pointer of the caller, which is restored on return.
+    saved_sp: u32,
+    /// The local holding the index of the block to dispatch to.
+    label: u32,
+}
+
+impl FuncContext {
+    /// Starts a new function with the parameters `params` and `blocks` blocks.
+    pub fn reset(&mut self, params: Vec<ValType>, blocks: usize) {
+        *self = FuncContext {
+            params,
+            blocks: vec![Code::default(); blocks],
+            ..FuncContext::default()
+        };
+
+        self.fp = self.new_local(ValType::I32);
+        self.saved_sp = self.new_local(ValType::I32);
+        self.label = self.new_local(ValType::I32);
+    }
+
+    pub fn new_local(&mut self, ty: ValType) -> u32 {
+        self.locals.push(ty);
+        (self.params.len() + self.locals.len() - 1) as u32
+    }
+
+    pub fn local_type(&self, local: u32) -> ValType {
+        let local = local as usize;
+
+        if local < self.params.len() {
+            self.params[local]
+        } else {
+            self.locals[local - self.params.len()]
+        }
+    }
+
+    pub fn fp(&self) -> u32 {
+        self.fp
+    }
+
+    /// The code of the block being built.
+    pub fn code(&mut self) -> &mut Code {
+        match self.current {
+            Some(block) => &mut self.blocks[block],
+            None => &mut self.entry,
+        }
+    }
+
+    pub fn switch_to_block(&mut self, block: usize) {
+        self.current = Some(block);
+    }
+
+    /// Reserves `size` bytes in the frame, returning their offset from the frame pointer.
+    /// The frame is aligned to 16 bytes, so larger alignments can not be guaranteed.
+    pub fn alloc_stack(&mut self, size: u64, align: u64) -> u32 {
+        assert!(align <= 16, "the wasm backend can not align stack slots to {} bytes", align);
+
+        let align = align.max(1) as u32;
+        let offset = (self.frame_size + align - 1) / align * align;
+
+        self.frame_size = offset + size as u32;
+        offset
+    }
+
+    /// Jumps from the block being built to `block`.
+    pub fn jump(&mut self, block: usize) {
+        let depth = self.loop_depth();
+        let label = self.label;
+
+        self.code().i32_const(block as i32).local_set(label).br(depth);
+    }
+
+    /// Jumps to the block whose index is on the stack.
+    pub fn jump_dynamic(&mut self) {
+        let depth = self.loop_depth();
+        let label = self.label;
+
+        self.code().local_set(label).br(depth);
+    }
+
+    /// The depth of the dispatch loop from the block being built.
+    fn loop_depth(&mut self) -> u32 {
+        let block = self.current.expect("jumped from the entry of a function");
+        let n = self.blocks.len() as u32;
+        let depth = self.code().depth;
+
+        n - 1 - block as u32 + depth
+    }
+
+    /// Restores the stack pointer of the caller, which has to be done before returning.
+    pub fn pop_frame(&mut self) {
+        let saved_sp = self.saved_sp;
+
+        self.code().local_get(saved_sp).global(encode::op::GLOBAL_SET, module::STACK_POINTER);
+    }
+
+    /// Puts the function together, as it is encoded in the code section.
+    ///
+    /// The entry sets up the frame and is followed by a loop that dispatches to the block in `label`,
+    /// the block at index `i` being reached by branching to the `i`th of as many nested blocks.
+    pub fn finish(&mut self) -> Code {
+        use encode::op;
+
+        let mut out = Code::default();
+        let mut runs = Vec::<(u32, ValType)>::new();
+
+        for ty in &self.locals {
+            match runs.last_mut() {
+                Some((n, last)) if last == ty => *n += 1,
+                _ => runs.push((1, *ty)),
+            }
+        }
+
+        encode::uleb(&mut out.bytes, runs.len() as u64);
+
+        for (n, ty) in runs {
+            encode::uleb(&mut out.bytes, n as u64);
+            out.bytes.push(ty.code());
+        }
+
+        let frame_size = (self.frame_size + 15) / 16 * 16;
+
+        out.global(op::GLOBAL_GET, module::STACK_POINTER)
+            .local_tee(self.saved_sp)
+            .i32_const(frame_size as i32)
+            .op(op::I32_SUB)
+            .local_tee(self.fp)
+            .global(op::GLOBAL_SET, module::STACK_POINTER);
+
+        out.append(&self.entry);
+        out.i32_const(0).local_set(self.label);
+        out.block(op::LOOP);
+
+        for _ in &self.blocks {
+            out.block(op::BLOCK);
+        }
+
+        let n = self.blocks.len() as u32;
+
+        out.local_get(self.label).br_table(0..n, n - 1);
+
+        for block in &self.blocks {
+            out.end();
+
+            if block.bytes.is_empty() {
+                // blocks that are never reached are not translated
+                out.op(op::UNREACHABLE);
+            } else {
+                out.append(block);
+            }
+        }
+
+        out.end();
+        out.op(op::UNREACHABLE);
+        out.op(op::END);
+        out
+    }
+}
+
+pub struct WasmType;
+
+impl<'ctx> Type<'ctx> for WasmType {
+    type Backend = WasmBackend<'ctx>;
+    type Raw = ValType;
+
+    fn ir_type(layout: &ir::layout::TyLayout, mcx: &ModuleCtx<'_, 'ctx, WasmBackend<'ctx>>) -> Option<Self::Raw> {
+        if let ir::layout::Abi::Scalar(s) = &layout.abi {
+            Some(Self::scalar_ty(s, mcx))
+        } else {
+            None
+        }
+    }
+
+    fn ir_pair_type(layout: &ir::layout::TyLayout, mcx: &ModuleCtx<'_, 'ctx, WasmBackend<'ctx>>) -> Option<(Self::Raw, Self::Raw)> {
+        if let ir::layout::Abi::ScalarPair(a, b) = &layout.abi {
+            Some((Self::scalar_ty(a, mcx), Self::scalar_ty(b, mcx)))
+        } else {
+            None
+        }
+    }
+
+    /// Integers smaller than 32 bits are kept in i32s, sign extended if they are signed and zero extended otherwise.
+    fn scalar_ty(scalar: &ir::layout::Scalar, _mcx: &ModuleCtx<'_, 'ctx, WasmBackend<'ctx>>) -> Self::Raw {
+        use ir::layout::{Integer, Primitive};
+
+        match &scalar.value {
+            Primitive::Int(int, _) => match int {
+                Integer::I8 | Integer::I16 | Integer::I32 => ValType::I32,
+                Integer::I64 => ValType::I64,
+                Integer::I128 => unreachable!("128-bit integers are reported as unsupported by supports_feature"),
+            },
+            Primitive::F32 => ValType::F32,
+            Primitive::F64 => ValType::F64,
+            Primitive::Pointer => ValType::I32,
+        }
+    }
+}
+
+pub fn mk_functype<'ctx>(mcx: &ModuleCtx<'_, 'ctx, WasmBackend<'ctx>>, sig: &ir::Signature) -> FuncType {
+    let mut out = FuncType::default();
+    let conv = sig.call_conv;
+
+    assert!(!sig.variadic, "the wasm backend does not support variadic functions");
+
+    let ret_layouts = sig.rets.iter().map(|ret| ir::layout::layout_of(ret, &mcx.target)).collect::<Vec<_>>();
+
+    for mode in abi::get_ret_modes(mcx, &ret_layouts, conv) {
+        match mode {
+            abi::PassMode::NoPass => {}
+            abi::PassMode::ByVal(ty) => out.results.push(ty),
+            abi::PassMode::ByValPair(a, b) => out.results.extend(&[a, b]),
+            abi::PassMode::Cast(a, b) => out.results.extend(std::iter::once(a).chain(b)),
+            abi::PassMode::ByRef { size: _ } => out.params.push(ValType::I32),
+        }
+    }
+
+    for param in &sig.params {
+        let layout = ir::layout::layout_of(param, &mcx.target);
+
+        match abi::get_pass_mode_for(mcx, &layout, conv) {
+            abi::PassMode::NoPass => {}
+            abi::PassMode::ByVal(ty) => out.params.push(ty),
+            abi::PassMode::ByValPair(a, b) => out.params.extend(&[a, b]),
+            abi::PassMode::Cast(a, b) => out.params.extend(std::iter::once(a).chain(b)),
+            abi::PassMode::ByRef { size: _ } => out.params.push(ValType::I32),
+        }
+    }
+
+    out
+}
+
+/// Creates a place for a value passed as `PassMode::Cast`, large enough to load whole registers from.
+pub(crate) fn cast_place<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, layout: ir::layout::TyLayout) -> place::Place<'ctx> {
+    let size = layout.size.align_to(ir::layout::Align::from_bytes(8)).bytes();
+    let offset = fx.bcx.alloc_stack(size, layout.align.bytes().max(8));
+
+    place::Place::new_ref(ptr::Pointer::stack(fx, offset), layout)
+}
+
+/// Loads the registers of a value passed as `PassMode::Cast` from a place created by `cast_place`.
+pub(crate) fn cast_to_regs<'ctx>(
+    fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>,
+    place: &place::Place<'ctx>,
+    a: ValType,
+    b: Option<ValType>,
+) -> abi::EmptySinglePair<u32> {
+    let ptr = place.as_ptr();
+    let first = ptr.load_ty(fx, a);
+
+    match b {
+        Some(b) => abi::EmptySinglePair::Pair(first, ptr.offset(a.bytes()).load_ty(fx, b)),
+        None => abi::EmptySinglePair::Single(first),
+    }
+}
+
+/// Stores the registers of a value passed as `PassMode::Cast` to a place created by `cast_place`.
+pub(crate) fn cast_from_regs<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, place: &place::Place<'ctx>, regs: &[u32]) {
+    let ptr = place.as_ptr();
+    let mut offset = 0;
+
+    for reg in regs {
+        let ty = fx.bcx.local_type(*reg);
+
+        ptr.offset(offset).store_ty(fx, *reg);
+        offset += ty.bytes();
+    }
+}
@@ -0,0 +1,414 @@
+use crate::encode::{self, op, reloc, FuncType, ValType};
+use codegen::artifact::Reloc;
+use std::collections::HashMap;
+
+/// The functions and data of a WebAssembly module, which are only given indices and addresses once it is finished.
+/// Until then code refers to them by name through relocations, so that the code of a function does not depend on
+/// the other functions of its module.
+#[derive(Default)]
+pub struct WasmModule {
+    funcs: Vec<Func>,
+    data: Vec<Data>,
+    names: HashMap<String, Symbol>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Symbol {
+    Func(usize),
+    Data(usize),
+}
+
+struct Func {
+    name: String,
+    linkage: ir::Linkage,
+    ty: FuncType,
+    body: Option<(Vec<u8>, Vec<Reloc>)>,
+}
+
+struct Data {
+    name: String,
+    linkage: ir::Linkage,
+    contents: Option<DataContents>,
+}
+
+struct DataContents {
+    bytes: Vec<u8>,
+    align: u32,
+    relocs: Vec<Reloc>,
+}
+
+/// The global holding the top of the stack in linear memory, which grows down.
+pub const STACK_POINTER: &str = "__stack_pointer";
+
+/// The addresses below this are left unused, so that no data is placed at the null pointer
+/// or the small addresses dangling pointers get.
+const DATA_START: u32 = 1024;
+
+/// The size of the stack, placed after the data.
+const STACK_SIZE: u32 = 1 << 20;
+
+const PAGE_SIZE: u32 = 1 << 16;
+
+impl WasmModule {
+    pub fn new() -> Self {
+        WasmModule::default()
+    }
+
+    /// Declares the function `name`, or returns the one declared before under that name.
+    pub fn declare_function(&mut self, name: &str, linkage: ir::Linkage, ty: &FuncType) -> usize {
+        match self.names.get(name) {
+            Some(Symbol::Func(id)) => return *id,
+            Some(Symbol::Data(_)) => panic!("'{}' is declared as data", name),
+            None => {}
+        }
+
+        self.funcs.push(Func {
+            name: name.to_string(),
+            linkage,
+            ty: ty.clone(),
+            body: None,
+        });
+
+        self.names.insert(name.to_string(), Symbol::Func(self.funcs.len() - 1));
+        self.funcs.len() - 1
+    }
+
+    /// Declares the data `name`, or returns the data declared before under that name.
+    pub fn declare_data(&mut self, name: &str, linkage: ir::Linkage) -> usize {
+        match self.names.get(name) {
+            Some(Symbol::Data(id)) => return *id,
+            Some(Symbol::Func(_)) => panic!("'{}' is declared as a function", name),
+            None => {}
+        }
+
+        self.data.push(Data {
+            name: name.to_string(),
+            linkage,
+            contents: None,
+        });
+
+        self.names.insert(name.to_string(), Symbol::Data(self.data.len() - 1));
+        self.data.len() - 1
+    }
+
+    pub fn get_name(&self, name: &str) -> Option<Symbol> {
+        self.names.get(name).copied()
+    }
+
+    pub fn func_name(&self, func: usize) -> &str {
+        &self.funcs[func].name
+    }
+
+    pub fn func_type(&self, func: usize) -> &FuncType {
+        &self.funcs[func].ty
+    }
+
+    pub fn data_name(&self, data: usize) -> &str {
+        &self.data[data].name
+    }
+
+    pub fn is_imported_data(&self, data: usize) -> bool {
+        self.data[data].linkage == ir::Linkage::Import
+    }
+
+    /// Defines a function with a body encoded as in the code section, without the size in front of it.
+    pub fn define_function(&mut self, func: usize, body: Vec<u8>, relocs: Vec<Reloc>) {
+        let func = &mut self.funcs[func];
+
+        assert!(func.body.is_none(), "'{}' is defined twice", func.name);
+        func.body = Some((body, relocs));
+    }
+
+    pub fn define_data(&mut self, data: usize, bytes: Vec<u8>, align: u32, relocs: Vec<Reloc>) {
+        let data = &mut self.data[data];
+
+        assert!(data.contents.is_none(), "'{}' is defined twice", data.name);
+        data.contents = Some(DataContents { bytes, align, relocs });
+    }
+
+    /// Lays out the module and encodes it, adding `custom` as custom sections.
+    ///
+    /// Imported functions are imported from the module `env`, as are imported data, which become immutable
+    /// globals holding their address. Every function is put in the table, at its index plus one so that
+    /// the null pointer is no function. The data starts at `DATA_START` and is followed by the stack.
+    pub fn finish(&self, custom: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        // imported functions come first in the index space
+        let mut order = (0..self.funcs.len()).collect::<Vec<_>>();
+
+        order.sort_by_key(|&f| self.funcs[f].linkage != ir::Linkage::Import);
+
+        let func_index = order.iter().enumerate().map(|(i, &f)| (f, i as u32)).collect::<HashMap<_, _>>();
+
+        for &f in &order {
+            let func = &self.funcs[f];
+
+            assert!(
+                func.linkage == ir::Linkage::Import || func.body.is_some(),
+                "'{}' was declared but not defined",
+                func.name
+            );
+        }
+
+        let mut types = Vec::<FuncType>::new();
+        let mut type_index = |ty: &FuncType| match types.iter().position(|t| t == ty) {
+            Some(i) => i as u32,
+            None => {
+                types.push(ty.clone());
+                types.len() as u32 - 1
+            }
+        };
+
+        let func_types = order.iter().map(|&f| type_index(&self.funcs[f].ty)).collect::<Vec<_>>();
+
+        // imported globals come first in the index space as well
+        let imported_data = (0..self.data.len()).filter(|&d| self.is_imported_data(d)).collect::<Vec<_>>();
+        let mut global_index = imported_data.iter().enumerate().map(|(i, &d)| (self.data[d].name.as_str(), i as u32)).collect::<HashMap<_, _>>();
+        let stack_pointer = imported_data.len() as u32;
+
+        global_index.insert(STACK_POINTER, stack_pointer);
+
+        let mut addrs = HashMap::new();
+        let mut end = DATA_START;
+
+        for (d, data) in self.data.iter().enumerate() {
+            if let Some(contents) = &data.contents {
+                end = align_to(end, contents.align);
+                addrs.insert(d, end);
+                end += contents.bytes.len() as u32;
+            } else if data.linkage != ir::Linkage::Import {
+                panic!("'{}' was declared but not defined", data.name);
+            }
+        }
+
+        let stack_top = align_to(end, 16) + STACK_SIZE;
+        let pages = (stack_top + PAGE_SIZE - 1) / PAGE_SIZE;
+        let addr_of = |symbol: &str, addend: i64| match self.names.get(symbol) {
+            Some(Symbol::Data(d)) if addrs.contains_key(d) => addrs[d] as i64 + addend,
+            Some(Symbol::Data(_)) => panic!("the address of the imported '{}' is not known until the module is instantiated", symbol),
+            _ => panic!("'{}' is not data", symbol),
+        };
+
+        let table_index = |symbol: &str| match self.names.get(symbol) {
+            Some(Symbol::Func(f)) => func_index[f] as i64 + 1,
+            _ => panic!("'{}' is not a function", symbol),
+        };
+
+        let mut out = b"\0asm".to_vec();
+
+        out.extend(&1u32.to_le_bytes());
+
+        // the code is encoded first, as calls through pointers can add types
+        let mut code = Vec::new();
+
+        encode::uleb(&mut code, (order.len() - imported_funcs(self, &order)) as u64);
+
+        for &f in &order {
+            let (body, relocs) = match &self.funcs[f].body {
+                Some(body) if self.funcs[f].linkage != ir::Linkage::Import => body,
+                _ => continue,
+            };
+
+            let mut body = body.clone();
+
+            for r in relocs {
+                let at = &mut body[r.offset as usize..];
+
+                match r.kind.as_str() {
+                    reloc::FUNCTION_INDEX_LEB => match self.names.get(&r.symbol) {
+                        Some(Symbol::Func(g)) => encode::patch_leb(at, func_index[g] as i64, false),
+                        _ => panic!("'{}' is not a function", r.symbol),
+                    },
+                    reloc::TABLE_INDEX_SLEB => encode::patch_leb(at, table_index(&r.symbol), true),
+                    reloc::MEMORY_ADDR_SLEB => encode::patch_leb(at, addr_of(&r.symbol, r.addend), true),
+                    reloc::GLOBAL_INDEX_LEB => encode::patch_leb(at, global_index[r.symbol.as_str()] as i64, false),
+                    reloc::TYPE_INDEX_LEB => {
+                        let ty = r.symbol.parse::<FuncType>().unwrap();
+
+                        encode::patch_leb(at, type_index(&ty) as i64, false)
+                    }
+                    kind => panic!("unknown relocation {}", kind),
+                }
+            }
+
+            encode::uleb(&mut code, body.len() as u64);
+            code.extend(body);
+        }
+
+        let mut section = Vec::new();
+
+        encode::uleb(&mut section, types.len() as u64);
+
+        for ty in &types {
+            section.push(0x60);
+            encode::uleb(&mut section, ty.params.len() as u64);
+            section.extend(ty.params.iter().map(|t| t.code()));
+            encode::uleb(&mut section, ty.results.len() as u64);
+            section.extend(ty.results.iter().map(|t| t.code()));
+        }
+
+        encode::section(&mut out, 1, &section);
+
+        let mut section = Vec::new();
+        let imports = imported_funcs(self, &order);
+
+        encode::uleb(&mut section, (imports + imported_data.len()) as u64);
+
+        for (&f, ty) in order.iter().zip(&func_types).take(imports) {
+            encode::name(&mut section, "env");
+            encode::name(&mut section, &self.funcs[f].name);
+            section.push(0x00);
+            encode::uleb(&mut section, *ty as u64);
+        }
+
+        for &d in &imported_data {
+            encode::name(&mut section, "env");
+            encode::name(&mut section, &self.data[d].name);
+            section.push(0x03);
+            section.push(ValType::I32.code());
+            section.push(0x00);
+        }
+
+        encode::section(&mut out, 2, &section);
+
+        let mut section = Vec::new();
+
+        encode::uleb(&mut section, func_types[imports..].len() as u64);
+
+        for ty in &func_types[imports..] {
+            encode::uleb(&mut section, *ty as u64);
+        }
+
+        encode::section(&mut out, 3, &section);
+
+        let mut section = Vec::new();
+
+        section.push(1);
+        section.push(0x70);
+        section.push(0x00);
+        encode::uleb(&mut section, order.len() as u64 + 1);
+        encode::section(&mut out, 4, &section);
+
+        let mut section = Vec::new();
+
+        section.push(1);
+        section.push(0x00);
+        encode::uleb(&mut section, pages as u64);
+        encode::section(&mut out, 5, &section);
+
+        // the stack pointer, then the addresses of the exported data
+        let exported_data = self.exports().filter_map(|s| match s {
+            Symbol::Data(d) => Some(d),
+            Symbol::Func(_) => None,
+        });
+
+        let exported_data = exported_data.collect::<Vec<_>>();
+        let mut section = Vec::new();
+
+        encode::uleb(&mut section, 1 + exported_data.len() as u64);
+        section.extend(&[ValType::I32.code(), 0x01, op::I32_CONST]);
+        encode::sleb(&mut section, stack_top as i32 as i64);
+        section.push(op::END);
+
+        for &d in &exported_data {
+            section.extend(&[ValType::I32.code(), 0x00, op::I32_CONST]);
+            encode::sleb(&mut section, addrs[&d] as i32 as i64);
+            section.push(op::END);
+        }
+
+        encode::section(&mut out, 6, &section);
+
+        let mut section = Vec::new();
+        let exports = self.exports().collect::<Vec<_>>();
+
+        encode::uleb(&mut section, exports.len() as u64 + 2);
+        encode::name(&mut section, "memory");
+        section.extend(&[0x02, 0x00]);
+        encode::name(&mut section, "__indirect_function_table");
+        section.extend(&[0x01, 0x00]);
+
+        for export in exports {
+            match export {
+                Symbol::Func(f) => {
+                    encode::name(&mut section, &self.funcs[f].name);
+                    section.push(0x00);
+                    encode::uleb(&mut section, func_index[&f] as u64);
+                }
+                Symbol::Data(d) => {
+                    let global = stack_pointer as usize + 1 + exported_data.iter().position(|&e| e == d).unwrap();
+
+                    encode::name(&mut section, &self.data[d].name);
+                    section.push(0x03);
+                    encode::uleb(&mut section, global as u64);
+                }
+            }
+        }
+
+        encode::section(&mut out, 7, &section);
+
+        let mut section = Vec::new();
+
+        section.extend(&[1, 0x00, op::I32_CONST, 1, op::END]);
+        encode::uleb(&mut section, order.len() as u64);
+
+        for i in 0..order.len() {
+            encode::uleb(&mut section, i as u64);
+        }
+
+        encode::section(&mut out, 9, &section);
+        encode::section(&mut out, 10, &code);
+
+        let mut section = Vec::new();
+        let defined = (0..self.data.len()).filter(|d| addrs.contains_key(d)).collect::<Vec<_>>();
+
+        encode::uleb(&mut section, defined.len() as u64);
+
+        for d in defined {
+            let contents = self.data[d].contents.as_ref().unwrap();
+            let mut bytes = contents.bytes.clone();
+
+            for r in &contents.relocs {
+                let val = match r.kind.as_str() {
+                    reloc::MEMORY_ADDR_I32 => addr_of(&r.symbol, r.addend),
+                    reloc::TABLE_INDEX_I32 => table_index(&r.symbol),
+                    kind => panic!("unknown relocation {}", kind),
+                };
+
+                bytes[r.offset as usize..r.offset as usize + 4].copy_from_slice(&(val as u32).to_le_bytes());
+            }
+
+            section.extend(&[0x00, op::I32_CONST]);
+            encode::sleb(&mut section, addrs[&d] as i32 as i64);
+            section.push(op::END);
+            encode::uleb(&mut section, bytes.len() as u64);
+            section.extend(bytes);
+        }
+
+        encode::section(&mut out, 11, &section);
+
+        for (name, contents) in custom {
+            let mut section = Vec::new();
+
+            encode::name(&mut section, name);
+            section.extend(contents);
+            encode::section(&mut out, 0, &section);
+        }
+
+        out
+    }
+
+    /// The exported functions and data, in the order they were declared.
+    fn exports(&self) -> impl Iterator<Item = Symbol> + '_ {
+        let funcs = self.funcs.iter().enumerate().filter(|(_, f)| f.linkage == ir::Linkage::Export).map(|(i, _)| Symbol::Func(i));
+        let data = self.data.iter().enumerate().filter(|(_, d)| d.linkage == ir::Linkage::Export).map(|(i, _)| Symbol::Data(i));
+
+        funcs.chain(data)
+    }
+}
+
+fn imported_funcs(module: &WasmModule, order: &[usize]) -> usize {
+    order.iter().filter(|&&f| module.funcs[f].linkage == ir::Linkage::Import).count()
+}
+
+fn align_to(val: u32, align: u32) -> u32 {
+    (val + align - 1) / align * align
+}
@@ -0,0 +1,324 @@
+use crate::encode::{op, ValType};
+use crate::ptr::Pointer;
+use crate::value::{Value, ValueKind};
+use crate::{FunctionCtx, WasmBackend};
+use codegen::Value as _;
+use ir::layout::{Abi, Primitive, Scalar, TyLayout};
+use std::marker::PhantomData;
+
+#[derive(Debug, Clone)]
+pub struct Place<'ctx> {
+    pub kind: PlaceKind,
+    pub layout: TyLayout,
+    _marker: PhantomData<&'ctx ()>,
+}
+
+/// A place in one or two locals or in memory.
+#[derive(Debug, Clone, Copy)]
+pub enum PlaceKind {
+    Var(u32),
+    VarPair(u32, u32),
+    Addr(Pointer),
+}
+
+impl<'ctx> Place<'ctx> {
+    pub fn new_var(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, layout: TyLayout) -> Self {
+        let ty = fx.ir_type(&layout).unwrap();
+        let var = fx.bcx.new_local(ty);
+
+        Place {
+            kind: PlaceKind::Var(var),
+            layout,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn new_var_pair(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, layout: TyLayout) -> Self {
+        let (ty1, ty2) = fx.ir_pair_type(&layout).unwrap();
+        let var1 = fx.bcx.new_local(ty1);
+        let var2 = fx.bcx.new_local(ty2);
+
+        Place {
+            kind: PlaceKind::VarPair(var1, var2),
+            layout,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn no_place(layout: TyLayout) -> Self {
+        Place {
+            kind: PlaceKind::Addr(Pointer::dangling(layout.align)),
+            layout,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn new_stack(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, layout: TyLayout) -> Self {
+        if layout.is_zst() {
+            return Place::no_place(layout);
+        }
+
+        let offset = fx.bcx.alloc_stack(layout.size.bytes(), layout.align.bytes());
+
+        Place {
+            kind: PlaceKind::Addr(Pointer::stack(fx, offset)),
+            layout,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn new_ref(ptr: Pointer, layout: TyLayout) -> Self {
+        Place {
+            kind: PlaceKind::Addr(ptr),
+            layout,
+            _marker: PhantomData,
+        }
+    }
+
+    #[track_caller]
+    pub fn as_ptr(&self) -> Pointer {
+        match self.kind {
+            PlaceKind::Addr(ptr) => ptr,
+            PlaceKind::Var(_) | PlaceKind::VarPair(_, _) => unreachable!(),
+        }
+    }
+}
+
+impl<'ctx> codegen::Place<'ctx> for Place<'ctx> {
+    type Backend = WasmBackend<'ctx>;
+
+    fn layout(&self) -> &TyLayout {
+        &self.layout
+    }
+
+    fn to_value(self, _fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>) -> Value<'ctx> {
+        match self.kind {
+            PlaceKind::Var(var) => Value::new_val(var, self.layout),
+            PlaceKind::VarPair(var1, var2) => Value::new_val_pair(var1, var2, self.layout),
+            PlaceKind::Addr(ptr) => Value::new_ref(ptr, self.layout),
+        }
+    }
+
+    fn deref(self, fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>) -> Self {
+        let pointee = self.layout.pointee(&fx.target);
+
+        if let ir::Type::Box(_) = self.layout.ty.kind {
+            unreachable!("boxes are reported as unsupported by supports_feature");
+        }
+
+        Self::new_ref(Pointer::addr(self.to_value(fx).load_scalar(fx)), pointee)
+    }
+
+    fn index(self, fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, idx: Value<'ctx>) -> Self {
+        let layout = self.layout.element(&fx.target);
+        let idx = idx.load_scalar(fx);
+        let offset = fx.bcx.new_local(ValType::I32);
+        let wide = fx.bcx.local_type(idx) == ValType::I64;
+        let code = fx.bcx.code().local_get(idx);
+
+        // addresses are 32 bits, so larger indices are wrapped
+        if wide {
+            code.op(op::I32_WRAP_I64);
+        }
+
+        fx.bcx
+            .code()
+            .i32_const(layout.stride.bytes() as i32)
+            .op(op::I32_MUL)
+            .local_set(offset);
+
+        let ptr = self.as_ptr().offset_value(fx, offset);
+
+        Place {
+            kind: PlaceKind::Addr(ptr),
+            layout,
+            _marker: PhantomData,
+        }
+    }
+
+    fn field(self, fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, idx: usize) -> Self {
+        if let ir::Type::Box(_) = self.layout.ty.kind {
+            return self.deref(fx).field(fx, idx);
+        }
+
+        let layout = self.layout.field(idx, &fx.target);
+
+        match self.kind {
+            PlaceKind::Var(_) => {
+                if idx == 0 {
+                    return self;
+                } else {
+                    unreachable!();
+                }
+            }
+            PlaceKind::VarPair(var1, var2) => match idx {
+                0 => {
+                    return Place {
+                        kind: PlaceKind::Var(var1),
+                        layout,
+                        _marker: PhantomData,
+                    }
+                }
+                1 => {
+                    return Place {
+                        kind: PlaceKind::Var(var2),
+                        layout,
+                        _marker: PhantomData,
+                    }
+                }
+                _ => unreachable!(),
+            },
+            PlaceKind::Addr(_) => {}
+        }
+
+        let offset = self.layout.fields.offset(idx);
+        let ptr = self.as_ptr().offset(offset.bytes() as u32);
+
+        Place {
+            kind: PlaceKind::Addr(ptr),
+            layout,
+            _marker: PhantomData,
+        }
+    }
+
+    fn store(self, fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, from: Value<'ctx>) {
+        /// Sets `var` to `data`, reinterpreting floats as integers and the other way around.
+        fn transmute_value<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, var: u32, data: u32) {
+            let src_ty = fx.bcx.local_type(data);
+            let dst_ty = fx.bcx.local_type(var);
+            let code = fx.bcx.code().local_get(data);
+
+            match (src_ty, dst_ty) {
+                (_, _) if src_ty == dst_ty => code,
+                (ValType::I32, ValType::F32) => code.op(op::F32_REINTERPRET_I32),
+                (ValType::F32, ValType::I32) => code.op(op::I32_REINTERPRET_F32),
+                (ValType::I64, ValType::F64) => code.op(op::F64_REINTERPRET_I64),
+                (ValType::F64, ValType::I64) => code.op(op::I64_REINTERPRET_F64),
+                _ => unreachable!("{} != {}", src_ty.name(), dst_ty.name()),
+            }
+            .local_set(var);
+        }
+
+        let dst_layout = self.layout.clone();
+        let to_ptr = match self.kind {
+            PlaceKind::Var(var) => {
+                let src = scalar_prim(&from.layout);
+                let data = Value {
+                    kind: from.kind,
+                    layout: dst_layout,
+                    _marker: PhantomData,
+                }
+                .load_scalar(fx);
+
+                transmute_value(fx, var, data);
+
+                // small integers are kept extended by their own sign
+                if let (Some(src), Some(dst)) = (src, scalar_prim(&self.layout)) {
+                    if src != dst {
+                        let val = crate::value::normalize(fx, var, dst);
+
+                        if val != var {
+                            fx.bcx.code().local_get(val).local_set(var);
+                        }
+                    }
+                }
+
+                return;
+            }
+            PlaceKind::VarPair(var1, var2) => {
+                let (data1, data2) = Value {
+                    kind: from.kind,
+                    layout: dst_layout,
+                    _marker: PhantomData,
+                }
+                .load_scalar_pair(fx);
+
+                transmute_value(fx, var1, data1);
+                transmute_value(fx, var2, data2);
+
+                return;
+            }
+            PlaceKind::Addr(ptr) => {
+                if dst_layout.is_zst() {
+                    return;
+                }
+
+                ptr
+            }
+        };
+
+        match from.layout.abi.clone() {
+            Abi::Scalar(_) => {
+                let prim = scalar_prim(&dst_layout).or_else(|| scalar_prim(&from.layout)).unwrap();
+                let val = from.load_scalar(fx);
+
+                to_ptr.store(fx, val, prim);
+
+                return;
+            }
+            Abi::ScalarPair(a, b) => {
+                let (value, meta) = from.load_scalar_pair(fx);
+                let b_offset = scalar_pair_calculate_b_offset(&fx.target, &a, &b);
+
+                to_ptr.store(fx, value, a.value);
+                to_ptr.offset(b_offset).store(fx, meta, b.value);
+
+                return;
+            }
+            _ => {}
+        }
+
+        match from.kind {
+            ValueKind::Val(val) => {
+                let prim = scalar_prim(&dst_layout).unwrap_or(Primitive::Int(ir::layout::Integer::I64, false));
+
+                to_ptr.store(fx, val, prim);
+            }
+            ValueKind::ValPair(_, _) => unreachable!(),
+            ValueKind::Ref(from_ptr) => {
+                to_ptr.push_addr(fx);
+                from_ptr.push_addr(fx);
+                fx.bcx
+                    .code()
+                    .i32_const(dst_layout.size.bytes() as i32)
+                    .op_fc(op::MEMORY_COPY)
+                    // the source and destination memory
+                    .op(0x00)
+                    .op(0x00);
+            }
+        }
+    }
+
+    fn write_place_ref(self, fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, dest: Self) {
+        let ptr = Value::new_val(self.as_ptr().get_addr(fx), dest.layout.clone());
+
+        dest.store(fx, ptr);
+    }
+
+    fn downcast_variant(self, fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, variant: usize) -> Self {
+        if let ir::Type::Box(_) = self.layout.ty.kind {
+            return self.deref(fx).downcast_variant(fx, variant);
+        }
+
+        let layout = self.layout.variant(variant);
+
+        Place {
+            kind: self.kind,
+            layout,
+            _marker: PhantomData,
+        }
+    }
+}
+
+fn scalar_prim(layout: &TyLayout) -> Option<Primitive> {
+    match &layout.abi {
+        Abi::Scalar(scalar) => Some(scalar.value),
+        _ => None,
+    }
+}
+
+pub(crate) fn scalar_pair_calculate_b_offset(triple: &target_lexicon::Triple, a_scalar: &Scalar, b_scalar: &Scalar) -> u32 {
+    let b_offset = a_scalar.value.size(triple).align_to(b_scalar.value.align(triple));
+
+    b_offset.bytes() as u32
+}
@@ -0,0 +1,140 @@
+use crate::encode::{op, ValType};
+use crate::*;
+use ir::layout::{Align, Integer, Primitive};
+
+/// An address in linear memory, as a local holding a base address and a constant offset from it.
+/// Without a base the offset is the address itself.
+#[derive(Debug, Clone, Copy)]
+pub struct Pointer {
+    pub base: Option<u32>,
+    pub offset: u32,
+}
+
+impl Pointer {
+    pub fn addr(addr: u32) -> Self {
+        Pointer { base: Some(addr), offset: 0 }
+    }
+
+    /// The address `offset` bytes into the frame of the function being built.
+    pub fn stack<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, offset: u32) -> Self {
+        Pointer {
+            base: Some(fx.bcx.fp()),
+            offset,
+        }
+    }
+
+    pub fn dangling(align: Align) -> Self {
+        Pointer {
+            base: None,
+            offset: align.bytes() as u32,
+        }
+    }
+
+    /// Returns a local holding the address.
+    pub fn get_addr<'ctx>(self, fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>) -> u32 {
+        if let (Some(base), 0) = (self.base, self.offset) {
+            return base;
+        }
+
+        let addr = fx.bcx.new_local(ValType::I32);
+
+        self.push_addr(fx);
+        fx.bcx.code().local_set(addr);
+        addr
+    }
+
+    /// Pushes the address onto the stack.
+    pub fn push_addr<'ctx>(self, fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>) {
+        match self.base {
+            Some(base) if self.offset == 0 => {
+                fx.bcx.code().local_get(base);
+            }
+            Some(base) => {
+                fx.bcx.code().local_get(base).i32_const(self.offset as i32).op(op::I32_ADD);
+            }
+            None => {
+                fx.bcx.code().i32_const(self.offset as i32);
+            }
+        }
+    }
+
+    pub fn offset(self, offset: u32) -> Self {
+        Pointer {
+            base: self.base,
+            offset: self.offset.checked_add(offset).expect("too large offset"),
+        }
+    }
+
+    /// Offsets the pointer by the i32 in the local `offset`.
+    pub fn offset_value<'ctx>(self, fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, offset: u32) -> Self {
+        let addr = fx.bcx.new_local(ValType::I32);
+
+        self.push_addr(fx);
+        fx.bcx.code().local_get(offset).op(op::I32_ADD).local_set(addr);
+        Pointer::addr(addr)
+    }
+
+    /// Pushes the base address of a load or store, whose offset is then `self.offset`.
+    fn push_base<'ctx>(self, fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>) -> u32 {
+        match self.base {
+            Some(base) => fx.bcx.code().local_get(base),
+            None => fx.bcx.code().i32_const(0),
+        };
+
+        self.offset
+    }
+
+    /// Loads a scalar into a new local, extending integers smaller than 32 bits according to their sign.
+    pub fn load<'ctx>(self, fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, prim: Primitive) -> u32 {
+        let (ty, op) = match prim {
+            Primitive::Int(Integer::I8, true) => (ValType::I32, op::I32_LOAD8_S),
+            Primitive::Int(Integer::I8, false) => (ValType::I32, op::I32_LOAD8_U),
+            Primitive::Int(Integer::I16, true) => (ValType::I32, op::I32_LOAD16_S),
+            Primitive::Int(Integer::I16, false) => (ValType::I32, op::I32_LOAD16_U),
+            Primitive::Int(Integer::I32, _) | Primitive::Pointer => (ValType::I32, op::I32_LOAD),
+            Primitive::Int(Integer::I64, _) => (ValType::I64, op::I64_LOAD),
+            Primitive::Int(Integer::I128, _) => unreachable!("128-bit integers are reported as unsupported by supports_feature"),
+            Primitive::F32 => (ValType::F32, op::F32_LOAD),
+            Primitive::F64 => (ValType::F64, op::F64_LOAD),
+        };
+
+        let val = fx.bcx.new_local(ty);
+        let offset = self.push_base(fx);
+
+        fx.bcx.code().mem(op, offset).local_set(val);
+        val
+    }
+
+    /// Loads a whole value of type `ty` into a new local.
+    pub fn load_ty<'ctx>(self, fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, ty: ValType) -> u32 {
+        let prim = match ty {
+            ValType::I32 => Primitive::Int(Integer::I32, false),
+            ValType::I64 => Primitive::Int(Integer::I64, false),
+            ValType::F32 => Primitive::F32,
+            ValType::F64 => Primitive::F64,
+        };
+
+        self.load(fx, prim)
+    }
+
+    /// Stores the scalar in the local `val`, truncating it to the size of `prim`.
+    pub fn store<'ctx>(self, fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, val: u32, prim: Primitive) {
+        let op = match (prim, fx.bcx.local_type(val)) {
+            (Primitive::Int(Integer::I8, _), ValType::I32) => op::I32_STORE8,
+            (Primitive::Int(Integer::I16, _), ValType::I32) => op::I32_STORE16,
+            (_, ValType::I32) => op::I32_STORE,
+            (_, ValType::I64) => op::I64_STORE,
+            (_, ValType::F32) => op::F32_STORE,
+            (_, ValType::F64) => op::F64_STORE,
+        };
+
+        let offset = self.push_base(fx);
+
+        fx.bcx.code().local_get(val).mem(op, offset);
+    }
+
+    /// Stores the whole local `val`.
+    pub fn store_ty<'ctx>(self, fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, val: u32) {
+        self.store(fx, val, Primitive::Int(Integer::I64, false));
+    }
+}
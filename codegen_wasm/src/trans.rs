@@ -0,0 +1,1084 @@
+use crate::encode::{op, FuncType, ValType};
+use crate::*;
+use ir::layout::{Integer, Primitive};
+
+/// The intrinsics `trans_rvalue` can lower, which must be kept in sync with `trans_intrinsic`.
+/// 128-bit integers, boxes and vectors are not supported.
+pub(crate) const INTRINSICS: &[&str] = &[
+    "add_i8", "sub_i8", "mul_i8", "div_i8", "rem_i8", "add_i16", "sub_i16", "mul_i16", "div_i16", "rem_i16", "add_i32",
+    "sub_i32", "mul_i32", "div_i32", "rem_i32", "add_i64", "sub_i64", "mul_i64", "div_i64", "rem_i64", "add_u8",
+    "sub_u8", "mul_u8", "div_u8", "rem_u8", "add_u16", "sub_u16", "mul_u16", "div_u16", "rem_u16", "add_u32", "sub_u32",
+    "mul_u32", "div_u32", "rem_u32", "add_u64", "sub_u64", "mul_u64", "div_u64", "rem_u64", "shl_i8", "shl_i16",
+    "shl_i32", "shl_i64", "shl_u8", "shl_u16", "shl_u32", "shl_u64", "add_i32_checked", "add_i64_checked",
+    "add_u32_checked", "add_u64_checked", "sub_i32_checked", "sub_i64_checked", "sub_u32_checked", "sub_u64_checked",
+    "mul_i32_checked", "mul_i64_checked", "mul_u32_checked", "mul_u64_checked", "atomic_load_i8", "atomic_load_i16",
+    "atomic_load_i32", "atomic_load_i64", "atomic_load_u8", "atomic_load_u16", "atomic_load_u32", "atomic_load_u64",
+    "atomic_store_i8", "atomic_store_i16", "atomic_store_i32", "atomic_store_i64", "atomic_store_u8",
+    "atomic_store_u16", "atomic_store_u32", "atomic_store_u64", "atomic_cmpxchg_i8", "atomic_cmpxchg_i16",
+    "atomic_cmpxchg_i32", "atomic_cmpxchg_i64", "atomic_cmpxchg_u8", "atomic_cmpxchg_u16", "atomic_cmpxchg_u32",
+    "atomic_cmpxchg_u64", "atomic_fetch_add_i8", "atomic_fetch_add_i16", "atomic_fetch_add_i32",
+    "atomic_fetch_add_i64", "atomic_fetch_add_u8", "atomic_fetch_add_u16", "atomic_fetch_add_u32",
+    "atomic_fetch_add_u64", "lt_i32", "le_i32", "gt_i32", "ge_i32", "eq_i32", "ne_i32", "memcpy", "memset", "memcmp",
+    "f64_to_str", "i64_to_str", "u64_to_str", "str_to_f64", "str_to_i64", "str_to_u64", "bigint_from_str",
+    "bigint_to_str", "bigint_add", "bigint_mul", "bigint_cmp", "bigint_free", "dyn_cast", "resource", "ptr_offset",
+    "stack_alloc", "stack_free", "pool_create", "pool_alloc", "pool_free", "pool_destroy", "select",
+];
+
+impl<'ctx> TransMethods<'ctx> for WasmBackend<'ctx> {
+    type Backend = Self;
+
+    fn switch_to_block(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, block: usize) {
+        fx.bcx.switch_to_block(block);
+    }
+
+    fn trans_init(_fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, _place: place::Place<'ctx>) {
+    }
+
+    fn trans_drop(_fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, _place: place::Place<'ctx>) {
+    }
+
+    fn trans_place(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, place: &ir::Place) -> place::Place<'ctx> {
+        let mut res = fx.locals[&place.local].clone();
+
+        for elem in &place.elems {
+            match elem {
+                ir::PlaceElem::Deref => res = res.deref(fx),
+                ir::PlaceElem::Field(idx) => res = res.field(fx, *idx),
+                ir::PlaceElem::Index(idx) => {
+                    let idx = Self::trans_op(fx, idx, None);
+
+                    res = res.index(fx, idx);
+                }
+                ir::PlaceElem::Downcast(idx) => {
+                    res = res.downcast_variant(fx, *idx);
+                }
+            }
+        }
+
+        res
+    }
+
+    fn trans_const(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, c: &ir::Const, into: Option<place::Place<'ctx>>) -> value::Value<'ctx> {
+        let ty = ir::const_type(fx.ir, c);
+        let layout = ir::layout::layout_of(&ty, &fx.target);
+
+        if let Some(into) = into {
+            match c {
+                ir::Const::Undefined(_) => into.to_value(fx),
+                ir::Const::Tuple(vals) if vals.is_empty() => value::Value::new_unit(),
+                ir::Const::Variant(idx, cs, _) => {
+                    let as_variant = into.clone().downcast_variant(fx, *idx);
+
+                    for (i, c) in cs.iter().enumerate() {
+                        let place = as_variant.clone().field(fx, i);
+
+                        Self::trans_const(fx, c, Some(place));
+                    }
+
+                    Self::trans_set_discr(fx, into.clone(), *idx as u128);
+
+                    into.to_value(fx)
+                }
+                ir::Const::Scalar(_, _) | ir::Const::Ptr(_) | ir::Const::Bytes(_) | ir::Const::Addr(_) => {
+                    let val = Self::trans_const(fx, c, None);
+
+                    into.store(fx, val.clone());
+                    val
+                }
+                _ => unimplemented!(),
+            }
+        } else {
+            match c {
+                ir::Const::Undefined(_) => place::Place::new_stack(fx, layout).to_value(fx),
+                ir::Const::Scalar(s, _) => value::Value::new_const(*s, fx, layout),
+                ir::Const::Tuple(vals) if vals.is_empty() => value::Value::new_unit(),
+                ir::Const::Addr(decl) => {
+                    let val = fx.bcx.new_local(ValType::I32);
+
+                    if let Some((func, _)) = fx.func_ids.get(decl) {
+                        let name = fx.module.func_name(*func).to_string();
+
+                        fx.bcx.code().table_index(&name);
+                    } else {
+                        let data = fx.data_ids[decl];
+                        let name = fx.module.data_name(data).to_string();
+
+                        // the address of imported data is given to the module in a global
+                        if fx.module.is_imported_data(data) {
+                            fx.bcx.code().global(op::GLOBAL_GET, &name);
+                        } else {
+                            fx.bcx.code().memory_addr(&name, 0);
+                        }
+                    }
+
+                    fx.bcx.code().local_set(val);
+                    value::Value::new_val(val, layout)
+                }
+                // payloadless enums are laid out as their tag
+                ir::Const::Variant(idx, cs, _) if cs.is_empty() && fx.ir_type(&layout).is_some() => {
+                    value::Value::new_const(*idx as u128, fx, layout)
+                }
+                ir::Const::Variant(idx, cs, ty) => {
+                    let layout = ir::layout::layout_of(ty, &fx.target);
+                    let place = place::Place::new_stack(fx, layout);
+                    let as_variant = place.clone().downcast_variant(fx, *idx);
+
+                    for (i, c) in cs.iter().enumerate() {
+                        let place = as_variant.clone().field(fx, i);
+
+                        Self::trans_const(fx, c, Some(place));
+                    }
+
+                    Self::trans_set_discr(fx, place.clone(), *idx as u128);
+
+                    place.to_value(fx)
+                }
+                ir::Const::Ptr(to) => {
+                    let to_layout = layout.pointee(&fx.target);
+                    let data = Self::alloc_const(fx.mcx, to, to_layout, None);
+                    let addr = data_addr(fx, data);
+
+                    value::Value::new_val(addr, layout)
+                }
+                ir::Const::Bytes(_) => {
+                    let data = Self::alloc_const(fx.mcx, c, layout.clone(), None);
+                    let addr = data_addr(fx, data);
+
+                    value::Value::new_ref(ptr::Pointer::addr(addr), layout)
+                }
+                _ => unimplemented!(),
+            }
+        }
+    }
+
+    fn trans_set_discr(fx: &mut FunctionCtx<'_, 'ctx, '_, Self::Backend>, place: place::Place<'ctx>, val: u128) {
+        if let ir::Type::Box(_) = place.layout.ty.kind {
+            let place = place.deref(fx);
+
+            return Self::trans_set_discr(fx, place, val);
+        }
+
+        match place.layout.variants.clone() {
+            ir::layout::Variants::Single { index } => {
+                assert_eq!(index, val as usize);
+            }
+            ir::layout::Variants::Multiple {
+                tag: _,
+                tag_field,
+                tag_encoding: ir::layout::TagEncoding::Direct,
+                variants: _,
+            } => {
+                let ptr = place.field(fx, tag_field);
+                let discr = value::Value::new_const(val, fx, ptr.layout.clone());
+
+                ptr.store(fx, discr);
+            }
+            ir::layout::Variants::Multiple {
+                tag: _,
+                tag_field,
+                tag_encoding:
+                    ir::layout::TagEncoding::Niche {
+                        dataful_variant,
+                        niche_variants,
+                        niche_start,
+                    },
+                variants: _,
+            } => {
+                if val != dataful_variant as u128 {
+                    let niche = place.field(fx, tag_field);
+                    let niche_value = val - *niche_variants.start() as u128;
+                    let niche_value = niche_value.wrapping_add(niche_start);
+                    let niche_val = value::Value::new_const(niche_value, fx, niche.layout.clone());
+
+                    niche.store(fx, niche_val);
+                }
+            }
+        }
+    }
+
+    fn trans_rvalue(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, place: place::Place<'ctx>, rvalue: &ir::RValue) {
+        match rvalue {
+            ir::RValue::Use(op) => {
+                Self::trans_op(fx, op, Some(place));
+            }
+            ir::RValue::AddrOf(val) => {
+                let val = Self::trans_place(fx, val);
+
+                val.write_place_ref(fx, place);
+            }
+            ir::RValue::Lea { base, index, scale, offset } => {
+                let base = Self::trans_op(fx, base, None).load_scalar(fx);
+                let addr = fx.bcx.new_local(ValType::I32);
+
+                fx.bcx.code().local_get(base);
+
+                if let Some(index) = index {
+                    // addresses are 32 bits, so only the low half of a larger index counts
+                    let index = Self::trans_op(fx, index, None).load_scalar(fx);
+                    let index = fit_int(fx, index, ValType::I32);
+
+                    fx.bcx.code().local_get(index);
+
+                    if *scale != 1 {
+                        fx.bcx.code().i32_const(*scale as i32).op(op::I32_MUL);
+                    }
+
+                    fx.bcx.code().op(op::I32_ADD);
+                }
+
+                if *offset != 0 {
+                    fx.bcx.code().i32_const(*offset as i32).op(op::I32_ADD);
+                }
+
+                fx.bcx.code().local_set(addr);
+
+                let val = value::Value::new_val(addr, place.layout.clone());
+
+                place.store(fx, val);
+            }
+            ir::RValue::UnOp(op, val) => {
+                let val = Self::trans_op(fx, val, None).load_scalar(fx);
+                let ty = fx.bcx.local_type(val);
+                let res = fx.bcx.new_local(ty);
+                let prim = match &place.layout.abi {
+                    ir::layout::Abi::Scalar(scalar) => scalar.value,
+                    _ => unreachable!(),
+                };
+
+                let code = fx.bcx.code();
+
+                match (op, ty) {
+                    (ir::UnOp::Neg, ValType::F32) => code.local_get(val).op(op::F32_NEG),
+                    (ir::UnOp::Neg, ValType::F64) => code.local_get(val).op(op::F64_NEG),
+                    (ir::UnOp::Neg, ValType::I32) => code.i32_const(0).local_get(val).op(op::I32_SUB),
+                    (ir::UnOp::Neg, ValType::I64) => code.i64_const(0).local_get(val).op(op::I64_SUB),
+                    (ir::UnOp::Not, ValType::I32) => code.local_get(val).op(op::I32_EQZ),
+                    (ir::UnOp::Not, ValType::I64) => code.local_get(val).op(op::I64_EQZ).op(op::I64_EXTEND_I32_U),
+                    // a bool only has one bit to flip
+                    (ir::UnOp::BitNot, _) if place.layout.ty.kind == ir::Type::Bool => code.local_get(val).i32_const(1).op(op::I32_XOR),
+                    (ir::UnOp::BitNot, ValType::I32) => code.local_get(val).i32_const(-1).op(op::I32_XOR),
+                    (ir::UnOp::BitNot, ValType::I64) => code.local_get(val).i64_const(-1).op(op::I64_XOR),
+                    (_, _) => unreachable!(),
+                }
+                .local_set(res);
+
+                // negating flips the sign of a NaN as well
+                let res = value::canonicalize_nan(fx, res, prim);
+                let res = value::normalize(fx, res, prim);
+                let val = value::Value::new_val(res, place.layout.clone());
+
+                place.store(fx, val);
+            }
+            ir::RValue::NullOp(op, ty) => {
+                let layout = ir::layout::layout_of(ty, &fx.target);
+
+                assert!(!layout.is_unsized(), "{:?} of an unsized type", op);
+
+                let val = match op {
+                    ir::NullOp::SizeOf => layout.size.bytes(),
+                    ir::NullOp::AlignOf => layout.align.bytes(),
+                };
+
+                let val = value::Value::new_const(val as u128, fx, place.layout.clone());
+
+                place.store(fx, val);
+            }
+            ir::RValue::Cast(val, to) => {
+                let layout = ir::layout::layout_of(to, &fx.target);
+                let val = Self::trans_place(fx, val).to_value(fx);
+                let val = val.cast(fx, layout);
+
+                place.store(fx, val);
+            }
+            ir::RValue::GetDiscr(val) => {
+                let val = Self::trans_place(fx, val).to_value(fx);
+
+                if let ir::Type::Box(_) = &val.layout.ty.kind {
+                    unreachable!("boxes are reported as unsupported by supports_feature");
+                }
+
+                if let ir::layout::Abi::Uninhabited = val.layout.abi {
+                    unreachable!();
+                }
+
+                let (tag_scalar, tag_field, tag_encoding) = match &val.layout.variants {
+                    ir::layout::Variants::Single { index } => {
+                        let val = value::Value::new_const(*index as u128, fx, place.layout.clone());
+
+                        place.store(fx, val);
+                        return;
+                    }
+                    ir::layout::Variants::Multiple {
+                        tag,
+                        tag_field,
+                        tag_encoding,
+                        variants: _,
+                    } => (tag.clone(), *tag_field, tag_encoding.clone()),
+                };
+
+                let tag = val.field(fx, tag_field);
+
+                match tag_encoding {
+                    ir::layout::TagEncoding::Direct => {
+                        place.store(fx, tag);
+                    }
+                    ir::layout::TagEncoding::Niche {
+                        dataful_variant,
+                        niche_variants,
+                        niche_start,
+                    } => {
+                        let tag = tag.load_scalar(fx);
+                        let tag_ty = fx.bcx.local_type(tag);
+                        let discr_ty = fx.ir_type(&place.layout).unwrap();
+                        let relative = fx.bcx.new_local(tag_ty);
+                        let is_niche = fx.bcx.new_local(ValType::I32);
+                        let relative_max = (*niche_variants.end() - *niche_variants.start()) as i64;
+                        let bits = tag_scalar.value.size(&fx.target).bits();
+
+                        if tag_ty == ValType::I64 {
+                            fx.bcx
+                                .code()
+                                .local_get(tag)
+                                .i64_const((niche_start as i64).wrapping_neg())
+                                .op(op::I64_ADD)
+                                .local_tee(relative)
+                                .i64_const(relative_max)
+                                .op(op::I64_LE_U)
+                                .local_set(is_niche);
+                        } else {
+                            let code = fx.bcx.code().local_get(tag).i32_const((niche_start as i32).wrapping_neg()).op(op::I32_ADD);
+
+                            // the tag is kept extended, but the difference is taken in its own size
+                            if bits < 32 {
+                                code.i32_const(((1u32 << bits) - 1) as i32).op(op::I32_AND);
+                            }
+
+                            code.local_tee(relative).i32_const(relative_max as i32).op(op::I32_LE_U).local_set(is_niche);
+                        }
+
+                        let relative = fit_int(fx, relative, discr_ty);
+                        let discr = fx.bcx.new_local(discr_ty);
+                        let code = fx.bcx.code();
+
+                        if discr_ty == ValType::I64 {
+                            code.local_get(relative)
+                                .i64_const(*niche_variants.start() as i64)
+                                .op(op::I64_ADD)
+                                .i64_const(dataful_variant as i64);
+                        } else {
+                            code.local_get(relative)
+                                .i32_const(*niche_variants.start() as i32)
+                                .op(op::I32_ADD)
+                                .i32_const(dataful_variant as i32);
+                        }
+
+                        code.local_get(is_niche).op(op::SELECT).local_set(discr);
+
+                        let discr = value::Value::new_val(discr, place.layout.clone());
+
+                        place.store(fx, discr);
+                    }
+                }
+            }
+            ir::RValue::Intrinsic(name, args) => {
+                let args2 = args
+                    .iter()
+                    .map(|a| {
+                        let a = Self::trans_op(fx, a, None);
+
+                        value_for_arg!(fx, a, {
+                            let ptr = a.on_stack(fx);
+
+                            abi::EmptySinglePair::Single(ptr.get_addr(fx))
+                        })
+                    })
+                    .flatten()
+                    .collect::<Vec<_>>();
+
+                trans_intrinsic(fx, name, args, &args2, place);
+            }
+        }
+    }
+
+    fn trans_term(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, term: &ir::Term) {
+        match term {
+            ir::Term::Abort => {
+                fx.bcx.code().op(op::UNREACHABLE);
+            }
+            // compiled code aborts instead of unwinding, so cleanup blocks are never entered
+            ir::Term::Resume => {
+                fx.bcx.code().op(op::UNREACHABLE);
+            }
+            ir::Term::Return => {
+                let conv = fx.ir.decls[fx.body.decl].ty.signature().call_conv;
+                let rets = fx.body.rets().map(|r| r.id).collect::<Vec<_>>();
+                let ret_layouts = rets.iter().map(|id| fx.locals[id].layout().clone()).collect::<Vec<_>>();
+                let ret_modes = abi::get_ret_modes(fx.mcx, &ret_layouts, conv);
+                let rets = rets
+                    .into_iter()
+                    .zip(ret_modes)
+                    .map(|(id, mode)| {
+                        let place = fx.locals[&id].clone();
+
+                        match mode {
+                            abi::PassMode::Cast(a, b) => cast_to_regs(fx, &place, a, b),
+                            _ => abi::value_for_ret(fx, id, mode),
+                        }
+                    })
+                    .flatten()
+                    .collect::<Vec<_>>();
+
+                fx.bcx.pop_frame();
+
+                for ret in rets {
+                    fx.bcx.code().local_get(ret);
+                }
+
+                fx.bcx.code().op(op::RETURN);
+            }
+            ir::Term::Jump(to) => {
+                let to = fx.blocks[to];
+
+                fx.bcx.jump(to);
+            }
+            ir::Term::Switch(op, vals, blocks) => {
+                let otherwise = fx.blocks[blocks.last().unwrap()];
+                let val = Self::trans_op(fx, op, None);
+                let size = val.layout.size;
+                let signed = val.layout.abi.is_signed();
+                let val = val.load_scalar(fx);
+                let ty = fx.bcx.local_type(val);
+                let mut seen = std::collections::HashSet::new();
+
+                assert!(size.bits() <= 64, "128-bit integers are reported as unsupported by supports_feature");
+
+                // only the first of several equal cases can be taken
+                let cases = vals
+                    .iter()
+                    .map(|v| size.truncate(*v))
+                    .zip(blocks)
+                    .filter(|(v, _)| seen.insert(*v))
+                    .collect::<Vec<_>>();
+
+                for (case, block) in cases {
+                    let block = fx.blocks[block];
+                    let code = fx.bcx.code().local_get(val);
+
+                    // small integers are kept extended, so the case is extended the same way
+                    if ty == ValType::I64 {
+                        code.i64_const(case as i64).op(op::I64_EQ);
+                    } else if signed {
+                        let shift = 32 - size.bits() as u32;
+
+                        code.i32_const(((case as u32) << shift) as i32 >> shift).op(op::I32_EQ);
+                    } else {
+                        code.i32_const(case as u32 as i32).op(op::I32_EQ);
+                    }
+
+                    code.block(op::IF);
+                    fx.bcx.jump(block);
+                    fx.bcx.code().end();
+                }
+
+                fx.bcx.jump(otherwise);
+            }
+            ir::Term::BrIf(op, then, else_) => {
+                let val = Self::trans_op(fx, op, None);
+                let val = val.load_scalar(fx);
+                let (then, else_) = (fx.blocks[then], fx.blocks[else_]);
+
+                fx.bcx
+                    .code()
+                    .i32_const(then as i32)
+                    .i32_const(else_ as i32)
+                    .local_get(val)
+                    .op(op::SELECT);
+
+                fx.bcx.jump_dynamic();
+            }
+        }
+    }
+
+    fn trans_call(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, rets: Vec<place::Place<'ctx>>, func: &ir::Operand, args: Vec<value::Value<'ctx>>) {
+        let func_ty = ir::operand_type(fx.ir, fx.body, func);
+        let sig = func_ty.signature();
+        let conv = sig.call_conv;
+        let rules = abi::AggregateRules::for_conv(conv, &fx.target);
+        let ret_layouts = rets.iter().map(|r| r.layout().clone()).collect::<Vec<_>>();
+        let ret_modes = abi::get_ret_modes(fx.mcx, &ret_layouts, conv);
+
+        // the destination may be a variable, as only the pass mode of its own type is known when picking those,
+        // so these are returned to a slot in the frame first
+        let ret_modes = ret_modes
+            .into_iter()
+            .zip(rets)
+            .map(|(m, p)| match m {
+                abi::PassMode::ByRef { size: _ }
+                    if rules != abi::AggregateRules::Default || !matches!(abi::get_pass_mode(fx.mcx, p.layout()), abi::PassMode::ByRef { .. }) =>
+                {
+                    let tmp = place::Place::new_stack(fx, p.layout.clone());
+
+                    (m, p, Some(tmp))
+                }
+                abi::PassMode::Cast(_, _) => {
+                    let tmp = cast_place(fx, p.layout.clone());
+
+                    (m, p, Some(tmp))
+                }
+                _ => (m, p, None),
+            })
+            .collect::<Vec<_>>();
+
+        let ret_ptrs = ret_modes
+            .iter()
+            .filter_map(|(m, p, tmp)| match m {
+                abi::PassMode::ByRef { size: _ } => Some(tmp.as_ref().unwrap_or(p).as_ptr().get_addr(fx)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let args = ret_ptrs
+            .into_iter()
+            .chain(
+                args.into_iter()
+                    .map(|a| match abi::get_pass_mode_for(fx.mcx, a.layout(), conv) {
+                        abi::PassMode::Cast(ty_a, ty_b) => {
+                            let tmp = cast_place(fx, a.layout.clone());
+
+                            tmp.clone().store(fx, a);
+                            cast_to_regs(fx, &tmp, ty_a, ty_b)
+                        }
+                        abi::PassMode::ByRef { size: Some(_) } if rules != abi::AggregateRules::Default => {
+                            // the callee owns the copy it is given
+                            let tmp = place::Place::new_stack(fx, a.layout.clone());
+
+                            tmp.clone().store(fx, a);
+                            abi::EmptySinglePair::Single(tmp.as_ptr().get_addr(fx))
+                        }
+                        _ => value_for_arg!(fx, a, {
+                            let ptr = a.on_stack(fx);
+
+                            abi::EmptySinglePair::Single(ptr.get_addr(fx))
+                        }),
+                    })
+                    .flatten(),
+            )
+            .collect::<Vec<_>>();
+
+        let ty = mk_functype(fx.mcx, &sig);
+
+        match func {
+            ir::Operand::Const(ir::Const::Addr(id)) => {
+                let name = fx.module.func_name(fx.func_ids[id].0).to_string();
+
+                for arg in args {
+                    fx.bcx.code().local_get(arg);
+                }
+
+                fx.bcx.code().call(&name);
+            }
+            _ => {
+                let func = Self::trans_op(fx, func, None).load_scalar(fx);
+
+                for arg in args {
+                    fx.bcx.code().local_get(arg);
+                }
+
+                fx.bcx.code().local_get(func).call_indirect(&ty);
+            }
+        }
+
+        let mut res = ty.results.iter().map(|t| fx.bcx.new_local(*t)).collect::<Vec<_>>();
+
+        for val in res.iter().rev() {
+            fx.bcx.code().local_set(*val);
+        }
+
+        let mut res = res.drain(..);
+
+        for (ret_mode, place, tmp) in ret_modes {
+            match ret_mode {
+                abi::PassMode::NoPass => {}
+                abi::PassMode::ByRef { .. } => {
+                    if let Some(tmp) = tmp {
+                        let val = tmp.to_value(fx);
+
+                        place.store(fx, val);
+                    }
+                }
+                abi::PassMode::ByVal(_) => {
+                    let ret_val = res.next().unwrap();
+                    let ret_val = value::Value::new_val(ret_val, place.layout.clone());
+
+                    place.store(fx, ret_val);
+                }
+                abi::PassMode::ByValPair(_, _) => {
+                    let val1 = res.next().unwrap();
+                    let val2 = res.next().unwrap();
+                    let ret_val = value::Value::new_val_pair(val1, val2, place.layout.clone());
+
+                    place.store(fx, ret_val);
+                }
+                abi::PassMode::Cast(_, b) => {
+                    let tmp = tmp.unwrap();
+                    let regs = res.by_ref().take(1 + b.is_some() as usize).collect::<Vec<_>>();
+
+                    cast_from_regs(fx, &tmp, &regs);
+
+                    let val = tmp.to_value(fx);
+
+                    place.store(fx, val);
+                }
+            }
+        }
+    }
+}
+
+/// Pushes the address of `data` into a new local.
+fn data_addr<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, data: usize) -> u32 {
+    let name = fx.module.data_name(data).to_string();
+    let addr = fx.bcx.new_local(ValType::I32);
+
+    fx.bcx.code().memory_addr(&name, 0).local_set(addr);
+    addr
+}
+
+/// Splits the name of an integer intrinsic like `add_u16` or `mul_i64_checked` into the operation and its type.
+fn int_intrinsic(name: &str) -> Option<(&str, Primitive, bool)> {
+    let (name, checked) = match name.strip_suffix("_checked") {
+        Some(name) => (name, true),
+        None => (name, false),
+    };
+
+    let (op, ty) = name.split_once('_')?;
+    let int = match &ty[1..] {
+        "8" => Integer::I8,
+        "16" => Integer::I16,
+        "32" => Integer::I32,
+        "64" => Integer::I64,
+        _ => return None,
+    };
+
+    let signed = match &ty[..1] {
+        "i" => true,
+        "u" => false,
+        _ => return None,
+    };
+
+    match op {
+        "add" | "sub" | "mul" | "div" | "rem" | "shl" => Some((op, Primitive::Int(int, signed), checked)),
+        _ => None,
+    }
+}
+
+fn int_opcode(op: &str, signed: bool, ty: ValType) -> u8 {
+    let wide = ty == ValType::I64;
+
+    match (op, signed) {
+        ("add", _) => if wide { op::I64_ADD } else { op::I32_ADD },
+        ("sub", _) => if wide { op::I64_SUB } else { op::I32_SUB },
+        ("mul", _) => if wide { op::I64_MUL } else { op::I32_MUL },
+        ("div", true) => if wide { op::I64_DIV_S } else { op::I32_DIV_S },
+        ("div", false) => if wide { op::I64_DIV_U } else { op::I32_DIV_U },
+        ("rem", true) => if wide { op::I64_REM_S } else { op::I32_REM_S },
+        ("rem", false) => if wide { op::I64_REM_U } else { op::I32_REM_U },
+        ("shl", _) => if wide { op::I64_SHL } else { op::I32_SHL },
+        _ => unreachable!(),
+    }
+}
+
+/// Lowers the intrinsic `name`, whose operands are `ops` and which are passed as the locals in `args`.
+fn trans_intrinsic<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, name: &str, ops: &[ir::Operand], args: &[u32], place: place::Place<'ctx>) {
+    let wrong_args = || -> ! { panic!("incorrect number of arguments for intrinsic {}", name) };
+
+    if let Some((op, prim, checked)) = int_intrinsic(name) {
+        let (a, b) = match args {
+            [a, b] => (*a, *b),
+            _ => wrong_args(),
+        };
+
+        let signed = matches!(prim, Primitive::Int(_, true));
+
+        if checked {
+            let (val, overflow) = checked_arith(fx, op, signed, a, b);
+            let layout = place.layout.field(0, &fx.target);
+
+            place.clone().field(fx, 0).store(fx, value::Value::new_val(val, layout));
+
+            let layout = place.layout.field(1, &fx.target);
+
+            place.field(fx, 1).store(fx, value::Value::new_val(overflow, layout));
+            return;
+        }
+
+        let ty = fx.bcx.local_type(a);
+        let res = fx.bcx.new_local(ty);
+        let b = fit_int(fx, b, ty);
+        let bits = prim.size(&fx.target).bits() as i32;
+        let code = fx.bcx.code().local_get(a).local_get(b);
+
+        // the amount is taken modulo the size of the integer, like it is for the wider ones
+        if op == "shl" && bits < 32 {
+            code.i32_const(bits - 1).op(op::I32_AND);
+        }
+
+        code.op(int_opcode(op, signed, ty)).local_set(res);
+
+        let res = value::normalize(fx, res, prim);
+        let res = value::Value::new_val(res, place.layout.clone());
+
+        return place.store(fx, res);
+    }
+
+    if let Some(rest) = name.strip_prefix("atomic_") {
+        return trans_atomic(fx, rest, ops, args, place);
+    }
+
+    let val = match (name, args) {
+        ("lt_i32", [a, b]) => compare(fx, op::I32_LT_S, *a, *b),
+        ("le_i32", [a, b]) => compare(fx, op::I32_LE_S, *a, *b),
+        ("gt_i32", [a, b]) => compare(fx, op::I32_GT_S, *a, *b),
+        ("ge_i32", [a, b]) => compare(fx, op::I32_GE_S, *a, *b),
+        ("eq_i32", [a, b]) => compare(fx, op::I32_EQ, *a, *b),
+        ("ne_i32", [a, b]) => compare(fx, op::I32_NE, *a, *b),
+        ("select", [c, a, b]) => {
+            let res = fx.bcx.new_local(fx.bcx.local_type(*a));
+
+            fx.bcx.code().local_get(*a).local_get(*b).local_get(*c).op(op::SELECT).local_set(res);
+            Some(res)
+        }
+        ("memcpy", [dst, src, n]) => {
+            let n = fit_int(fx, *n, ValType::I32);
+
+            // the source and destination memory
+            fx.bcx.code().local_get(*dst).local_get(*src).local_get(n).op_fc(op::MEMORY_COPY).op(0x00).op(0x00);
+            None
+        }
+        ("memset", [dst, c, n]) => {
+            let c = fit_int(fx, *c, ValType::I32);
+            let n = fit_int(fx, *n, ValType::I32);
+
+            fx.bcx.code().local_get(*dst).local_get(c).local_get(n).op_fc(op::MEMORY_FILL).op(0x00);
+            None
+        }
+        ("memcmp", [_, _, _]) => {
+            let args = [args[0], args[1], fit_int(fx, args[2], ValType::I32)];
+            let cmp = call_import(fx, "memcmp", &args, Some(ValType::I32)).unwrap();
+            let res = fx.bcx.new_local(ValType::I32);
+
+            // libc only promises the sign, the interpreter returns -1, 0 or 1
+            fx.bcx.code().local_get(cmp).i32_const(0).op(op::I32_GT_S).local_get(cmp).i32_const(0).op(op::I32_LT_S).op(op::I32_SUB).local_set(res);
+            Some(res)
+        }
+        ("f64_to_str", [_, _, _])
+        | ("i64_to_str", [_, _, _])
+        | ("u64_to_str", [_, _, _])
+        | ("str_to_f64", [_, _, _])
+        | ("str_to_i64", [_, _, _])
+        | ("str_to_u64", [_, _, _])
+        | ("bigint_to_str", [_, _, _]) => call_import(fx, name, args, Some(ValType::I64)),
+        ("bigint_from_str", [_, _]) | ("bigint_add", [_, _]) | ("bigint_mul", [_, _]) => call_import(fx, name, args, Some(ValType::I32)),
+        ("bigint_cmp", [_, _]) => call_import(fx, name, args, Some(ValType::I32)),
+        ("pool_create", [_, _, _]) | ("pool_alloc", [_]) => call_import(fx, name, args, Some(ValType::I32)),
+        ("bigint_free", [_]) | ("pool_free", [_, _]) | ("pool_destroy", [_]) => call_import(fx, name, args, None),
+        ("stack_alloc", [n]) => {
+            let n = fit_int(fx, *n, ValType::I32);
+
+            call_import(fx, "malloc", &[n], Some(ValType::I32))
+        }
+        ("stack_free", [_]) => call_import(fx, "free", args, None),
+        ("dyn_cast", [a, b]) => {
+            // the id of a type follows four other pointers in its type info, its name follows the id
+            let (id, name) = (4 * 4, 5 * 4);
+            let res = fx.bcx.new_local(ValType::I32);
+            let (name_a, name_b) = (fx.bcx.new_local(ValType::I32), fx.bcx.new_local(ValType::I32));
+
+            fx.bcx.code().i32_const(0).local_set(res);
+            fx.bcx.code().local_get(*a).mem(op::I32_LOAD, id).local_get(*b).mem(op::I32_LOAD, id).op(op::I32_EQ).block(op::IF);
+            fx.bcx.code().local_get(*a).mem(op::I32_LOAD, name).local_set(name_a);
+            fx.bcx.code().local_get(*b).mem(op::I32_LOAD, name).local_set(name_b);
+
+            let cmp = call_import(fx, "strcmp", &[name_a, name_b], Some(ValType::I32)).unwrap();
+
+            fx.bcx.code().local_get(cmp).op(op::I32_EQZ).local_set(res).end();
+            Some(res)
+        }
+        ("resource", [ptr]) => {
+            let res_ty = ir::operand_type(fx.ir, fx.body, &ops[0]);
+            let size = ir::layout::layout_of(&res_ty, &fx.target).pointee(&fx.target).size.bytes();
+            let res = place::Place::new_stack(fx, place.layout.clone());
+            let layout = place.layout.field(0, &fx.target);
+
+            res.clone().field(fx, 0).store(fx, value::Value::new_val(*ptr, layout));
+
+            let layout = place.layout.field(1, &fx.target);
+            let len = value::Value::new_const(size as u128, fx, layout);
+
+            res.clone().field(fx, 1).store(fx, len);
+
+            let val = res.to_value(fx);
+
+            return place.store(fx, val);
+        }
+        ("ptr_offset", [ptr, offset]) => {
+            let ptr_ty = ir::operand_type(fx.ir, fx.body, &ops[0]);
+            let pointee = ir::layout::layout_of(&ptr_ty, &fx.target).pointee(&fx.target);
+            let offset = fit_int(fx, *offset, ValType::I32);
+            let res = fx.bcx.new_local(ValType::I32);
+
+            fx.bcx
+                .code()
+                .local_get(*ptr)
+                .local_get(offset)
+                .i32_const(pointee.size.bytes() as i32)
+                .op(op::I32_MUL)
+                .op(op::I32_ADD)
+                .local_set(res);
+
+            Some(res)
+        }
+        _ if INTRINSICS.contains(&name) => wrong_args(),
+        _ => panic!("unknown intrinsic {}", name),
+    };
+
+    if let Some(val) = val {
+        let val = value::Value::new_val(val, place.layout.clone());
+
+        place.store(fx, val);
+    }
+}
+
+/// Compares two i32s, giving a bool.
+fn compare<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, op: u8, a: u32, b: u32) -> Option<u32> {
+    let res = fx.bcx.new_local(ValType::I32);
+
+    fx.bcx.code().local_get(a).local_get(b).op(op).local_set(res);
+    Some(res)
+}
+
+/// Calls a function the module imports, like those of the runtime library, whose signature follows from the argument types.
+fn call_import<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, name: &str, args: &[u32], ret: Option<ValType>) -> Option<u32> {
+    let ty = FuncType {
+        params: args.iter().map(|a| fx.bcx.local_type(*a)).collect(),
+        results: ret.into_iter().collect(),
+    };
+
+    fx.mcx.module.declare_function(name, ir::Linkage::Import, &ty);
+
+    for arg in args {
+        fx.bcx.code().local_get(*arg);
+    }
+
+    fx.bcx.code().call(name);
+
+    ret.map(|ty| {
+        let res = fx.bcx.new_local(ty);
+
+        fx.bcx.code().local_set(res);
+        res
+    })
+}
+
+/// Modules run on a single thread, so atomic operations are ordinary loads and stores.
+fn trans_atomic<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, name: &str, ops: &[ir::Operand], args: &[u32], place: place::Place<'ctx>) {
+    let (op, ty) = name.rsplit_once('_').unwrap();
+    let prim = match int_intrinsic(&format!("add_{}", ty)) {
+        Some((_, prim, _)) => prim,
+        None => panic!("unknown intrinsic atomic_{}", name),
+    };
+
+    let ordering = ops.last().and_then(ir::AtomicOrdering::from_operand);
+    let ordering = ordering.unwrap_or_else(|| panic!("atomic_{} requires a constant ordering", op));
+
+    match op {
+        "load" | "store" if !ordering.is_valid_for(op == "store") => panic!("invalid ordering {:?} for atomic_{}", ordering, op),
+        _ => {}
+    }
+
+    let ty = fx.scalar_ty(&ir::layout::Scalar::unit(prim, &fx.target));
+    let args = args.iter().enumerate().map(|(i, v)| if i == 0 { *v } else { fit_int(fx, *v, ty) }).collect::<Vec<_>>();
+
+    let (old, success) = match (op, &args[..]) {
+        ("load", [ptr, _]) => (Some(ptr::Pointer::addr(*ptr).load(fx, prim)), None),
+        ("store", [ptr, val, _]) => {
+            ptr::Pointer::addr(*ptr).store(fx, *val, prim);
+            (None, None)
+        }
+        ("cmpxchg", [ptr, expected, new, _]) => {
+            let ptr = ptr::Pointer::addr(*ptr);
+            let old = ptr.load(fx, prim);
+            let expected = value::normalize(fx, *expected, prim);
+            let success = fx.bcx.new_local(ValType::I32);
+            let eq = if ty == ValType::I64 { op::I64_EQ } else { op::I32_EQ };
+
+            fx.bcx.code().local_get(old).local_get(expected).op(eq).local_tee(success).block(op::IF);
+            ptr.store(fx, *new, prim);
+            fx.bcx.code().end();
+
+            (Some(old), Some(success))
+        }
+        ("fetch_add", [ptr, val, _]) => {
+            let ptr = ptr::Pointer::addr(*ptr);
+            let old = ptr.load(fx, prim);
+            let new = fx.bcx.new_local(ty);
+            let add = int_opcode("add", false, ty);
+
+            fx.bcx.code().local_get(old).local_get(*val).op(add).local_set(new);
+            ptr.store(fx, new, prim);
+
+            (Some(old), None)
+        }
+        _ => panic!("incorrect number of arguments for intrinsic atomic_{}", name),
+    };
+
+    match (old, success) {
+        (Some(old), Some(success)) => {
+            let layout = place.layout.field(0, &fx.target);
+
+            place.clone().field(fx, 0).store(fx, value::Value::new_val(old, layout));
+
+            let layout = place.layout.field(1, &fx.target);
+
+            place.field(fx, 1).store(fx, value::Value::new_val(success, layout));
+        }
+        (Some(old), None) => {
+            let old = value::Value::new_val(old, place.layout.clone());
+
+            place.store(fx, old);
+        }
+        _ => {}
+    }
+}
+
+/// Small integer arguments may arrive widened by the calling convention, and integers wider than a pointer
+/// are truncated where a pointer sized one is needed.
+fn fit_int<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, val: u32, ty: ValType) -> u32 {
+    let op = match (fx.bcx.local_type(val), ty) {
+        (ValType::I64, ValType::I32) => op::I32_WRAP_I64,
+        (ValType::I32, ValType::I64) => op::I64_EXTEND_I32_U,
+        _ => return val,
+    };
+
+    let res = fx.bcx.new_local(ty);
+
+    fx.bcx.code().local_get(val).op(op).local_set(res);
+    res
+}
+
+/// Returns the result of `op` and whether it overflowed. 32-bit operations are done in 64 bits, where they
+/// cannot overflow, and 64-bit ones check their result.
+fn checked_arith<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, op: &str, signed: bool, a: u32, b: u32) -> (u32, u32) {
+    let ty = fx.bcx.local_type(a);
+    let overflow = fx.bcx.new_local(ValType::I32);
+    let b = fit_int(fx, b, ty);
+
+    if ty == ValType::I32 {
+        let extend = if signed { op::I64_EXTEND_I32_S } else { op::I64_EXTEND_I32_U };
+        let wide = fx.bcx.new_local(ValType::I64);
+        let val = fx.bcx.new_local(ValType::I32);
+
+        fx.bcx
+            .code()
+            .local_get(a)
+            .op(extend)
+            .local_get(b)
+            .op(extend)
+            .op(int_opcode(op, signed, ValType::I64))
+            .local_tee(wide)
+            .op(op::I32_WRAP_I64)
+            .local_tee(val)
+            .op(extend)
+            .local_get(wide)
+            .op(op::I64_NE)
+            .local_set(overflow);
+
+        return (val, overflow);
+    }
+
+    let val = fx.bcx.new_local(ValType::I64);
+
+    fx.bcx.code().local_get(a).local_get(b).op(int_opcode(op, signed, ty)).local_set(val);
+
+    let code = fx.bcx.code();
+
+    match (op, signed) {
+        ("add", true) => {
+            code.local_get(val)
+                .local_get(a)
+                .op(op::I64_XOR)
+                .local_get(val)
+                .local_get(b)
+                .op(op::I64_XOR)
+                .op(op::I64_AND)
+                .i64_const(0)
+                .op(op::I64_LT_S);
+        }
+        ("sub", true) => {
+            code.local_get(a)
+                .local_get(b)
+                .op(op::I64_XOR)
+                .local_get(a)
+                .local_get(val)
+                .op(op::I64_XOR)
+                .op(op::I64_AND)
+                .i64_const(0)
+                .op(op::I64_LT_S);
+        }
+        ("add", false) => {
+            code.local_get(val).local_get(a).op(op::I64_LT_U);
+        }
+        ("sub", false) => {
+            code.local_get(a).local_get(b).op(op::I64_LT_U);
+        }
+        // the product overflowed if dividing it by one factor does not give the other, which is checked
+        // without dividing by zero or dividing the smallest integer by -1
+        ("mul", true) => {
+            code.local_get(val)
+                .i64_const(1)
+                .local_get(a)
+                .local_get(a)
+                .i64_const(1)
+                .op(op::I64_ADD)
+                .i64_const(1)
+                .op(op::I64_LE_U)
+                .op(op::SELECT)
+                .op(op::I64_DIV_S)
+                .local_get(b)
+                .op(op::I64_NE)
+                .local_get(a)
+                .i64_const(1)
+                .op(op::I64_ADD)
+                .i64_const(1)
+                .op(op::I64_GT_U)
+                .op(op::I32_AND)
+                .local_get(a)
+                .i64_const(-1)
+                .op(op::I64_EQ)
+                .local_get(b)
+                .i64_const(i64::MIN)
+                .op(op::I64_EQ)
+                .op(op::I32_AND)
+                .op(op::I32_OR);
+        }
+        ("mul", false) => {
+            code.local_get(val)
+                .i64_const(1)
+                .local_get(a)
+                .local_get(a)
+                .op(op::I64_EQZ)
+                .op(op::SELECT)
+                .op(op::I64_DIV_U)
+                .local_get(b)
+                .op(op::I64_NE)
+                .local_get(a)
+                .op(op::I64_EQZ)
+                .op(op::I32_EQZ)
+                .op(op::I32_AND);
+        }
+        _ => unreachable!(),
+    }
+
+    code.local_set(overflow);
+
+    (val, overflow)
+}
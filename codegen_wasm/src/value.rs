@@ -0,0 +1,329 @@
+use crate::encode::{op, ValType};
+use crate::place::Place;
+use crate::ptr::Pointer;
+use crate::{FunctionCtx, WasmBackend};
+use ir::layout::{Abi, Integer, Primitive, TyLayout};
+use std::marker::PhantomData;
+
+#[derive(Debug, Clone)]
+pub struct Value<'ctx> {
+    pub kind: ValueKind,
+    pub layout: TyLayout,
+    pub(crate) _marker: PhantomData<&'ctx ()>,
+}
+
+/// A value in memory or in one or two locals.
+#[derive(Debug, Clone, Copy)]
+pub enum ValueKind {
+    Ref(Pointer),
+    Val(u32),
+    ValPair(u32, u32),
+}
+
+impl<'ctx> Value<'ctx> {
+    pub fn new_ref(ptr: Pointer, layout: TyLayout) -> Self {
+        Value {
+            kind: ValueKind::Ref(ptr),
+            layout,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn new_val(val: u32, layout: TyLayout) -> Self {
+        Value {
+            kind: ValueKind::Val(val),
+            layout,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn new_val_pair(val1: u32, val2: u32, layout: TyLayout) -> Self {
+        Value {
+            kind: ValueKind::ValPair(val1, val2),
+            layout,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Floats are given as their bits, like the interpreter stores them.
+    pub fn new_const(val: u128, fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, layout: TyLayout) -> Self {
+        let ty = fx.ir_type(&layout).unwrap();
+        let local = fx.bcx.new_local(ty);
+        let code = fx.bcx.code();
+
+        match ty {
+            ValType::I32 => code.i32_const(val as i32),
+            ValType::I64 => code.i64_const(val as i64),
+            ValType::F32 => code.f32_const(val as u32),
+            ValType::F64 => code.f64_const(val as u64),
+        };
+
+        code.local_set(local);
+
+        // small integers are kept extended
+        let val = match &layout.abi {
+            Abi::Scalar(scalar) => normalize(fx, local, scalar.value),
+            _ => unimplemented!(),
+        };
+
+        Value::new_val(val, layout)
+    }
+
+    pub fn new_unit() -> Self {
+        Value::new_ref(Pointer::dangling(ir::layout::Align::from_bytes(1)), TyLayout::unit())
+    }
+
+    /// Returns the address of the value, storing it in the frame first if it is not in memory.
+    pub fn on_stack(self, fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>) -> Pointer {
+        match self.kind {
+            ValueKind::Ref(ptr) => ptr,
+            ValueKind::Val(_) | ValueKind::ValPair(_, _) => {
+                use codegen::Place as _;
+                let place = Place::new_stack(fx, self.layout.clone());
+
+                place.clone().store(fx, self);
+                place.as_ptr()
+            }
+        }
+    }
+}
+
+impl<'ctx> codegen::Value<'ctx> for Value<'ctx> {
+    type Backend = WasmBackend<'ctx>;
+    type Raw = u32;
+
+    fn layout(&self) -> &TyLayout {
+        &self.layout
+    }
+
+    #[track_caller]
+    fn load_scalar(self, fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>) -> Self::Raw {
+        match self.kind {
+            ValueKind::Ref(ptr) => match &self.layout.abi {
+                Abi::Scalar(scalar) => ptr.load(fx, scalar.value),
+                _ => unreachable!(),
+            },
+            ValueKind::Val(val) => val,
+            ValueKind::ValPair(_, _) => unreachable!(),
+        }
+    }
+
+    fn load_scalar_pair(self, fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>) -> (Self::Raw, Self::Raw) {
+        match self.kind {
+            ValueKind::Ref(ptr) => {
+                let (a, b) = match &self.layout.abi {
+                    Abi::ScalarPair(a, b) => (a, b),
+                    _ => unreachable!(),
+                };
+
+                let b_offset = crate::place::scalar_pair_calculate_b_offset(&fx.target, a, b);
+                let val1 = ptr.load(fx, a.value);
+                let val2 = ptr.offset(b_offset).load(fx, b.value);
+
+                (val1, val2)
+            }
+            ValueKind::Val(_) => unreachable!(),
+            ValueKind::ValPair(a, b) => (a, b),
+        }
+    }
+
+    /// Converts between scalars of different primitives, see `cast_scalar`, and any other scalar to a bool
+    /// by comparing it with zero. Any other cast reinterprets the value with the new layout.
+    fn cast(self, fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, layout: TyLayout) -> Self {
+        if let (ir::Type::Bool, Abi::Scalar(from)) = (&layout.ty.kind, &self.layout.abi) {
+            if self.layout.ty.kind != ir::Type::Bool {
+                let from = from.value;
+                let val = self.load_scalar(fx);
+                let val = is_nonzero(fx, val, from);
+
+                return Value::new_val(val, layout);
+            }
+        }
+
+        let (from, to) = match (&self.layout.abi, &layout.abi) {
+            (Abi::Scalar(a), Abi::Scalar(b)) if a.value != b.value => (a.value, b.value),
+            _ => {
+                return Value {
+                    kind: self.kind,
+                    layout,
+                    _marker: PhantomData,
+                }
+            }
+        };
+
+        let val = self.load_scalar(fx);
+        let val = cast_scalar(fx, val, from, to);
+
+        Value::new_val(val, layout)
+    }
+
+    fn field(self, fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, idx: usize) -> Self {
+        match self.kind {
+            ValueKind::Val(_) => {
+                if idx == 0 {
+                    self
+                } else {
+                    unreachable!();
+                }
+            }
+            ValueKind::Ref(ptr) => {
+                let offset = self.layout.fields.offset(idx);
+                let field_layout = self.layout.field(idx, &fx.target);
+
+                Value::new_ref(ptr.offset(offset.bytes() as u32), field_layout)
+            }
+            ValueKind::ValPair(a, b) => {
+                let field_layout = self.layout.field(idx, &fx.target);
+
+                if idx == 0 {
+                    Value::new_val(a, field_layout)
+                } else {
+                    Value::new_val(b, field_layout)
+                }
+            }
+        }
+    }
+
+    fn deref(self, fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>) -> Self {
+        let pointee = self.layout.pointee(&fx.target);
+
+        if let ir::Type::Box(_) = self.layout.ty.kind {
+            unreachable!("boxes are reported as unsupported by supports_feature");
+        }
+
+        let ptr = self.load_scalar(fx);
+
+        Value::new_val(ptr, pointee)
+    }
+}
+
+/// Extends an integer smaller than 32 bits in the local `val` according to its sign, as they are kept,
+/// returning the local holding the result. Other scalars are returned as they are.
+pub(crate) fn normalize<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, val: u32, prim: Primitive) -> u32 {
+    let code = fx.bcx.code();
+
+    match prim {
+        Primitive::Int(Integer::I8, true) => code.local_get(val).op(op::I32_EXTEND8_S),
+        Primitive::Int(Integer::I16, true) => code.local_get(val).op(op::I32_EXTEND16_S),
+        Primitive::Int(Integer::I8, false) => code.local_get(val).i32_const(0xFF).op(op::I32_AND),
+        Primitive::Int(Integer::I16, false) => code.local_get(val).i32_const(0xFFFF).op(op::I32_AND),
+        _ => return val,
+    };
+
+    let res = fx.bcx.new_local(ValType::I32);
+
+    fx.bcx.code().local_set(res);
+    res
+}
+
+/// Integers and pointers are sign extended if the source is signed, zero extended otherwise and truncated
+/// to the target size. Floats are converted to integers with saturation, with NaN becoming zero.
+pub(crate) fn cast_scalar<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, val: u32, from: Primitive, to: Primitive) -> u32 {
+    use ValType::*;
+
+    let signed = |p| matches!(p, Primitive::Int(_, true));
+    let from_ty = fx.bcx.local_type(val);
+    let ty = fx.scalar_ty(&ir::layout::Scalar::unit(to, &fx.target));
+    let res = fx.bcx.new_local(ty);
+
+    match (from, to) {
+        (Primitive::F32, Primitive::F64) | (Primitive::F64, Primitive::F32) => {
+            let op = if to == Primitive::F64 { op::F64_PROMOTE_F32 } else { op::F32_DEMOTE_F64 };
+
+            fx.bcx.code().local_get(val).op(op).local_set(res);
+
+            return canonicalize_nan(fx, res, to);
+        }
+        (Primitive::F32, _) | (Primitive::F64, _) if to.size(&fx.target).bits() >= 32 => {
+            let op = match (from_ty, ty, signed(to)) {
+                (F32, I32, true) => op::I32_TRUNC_SAT_F32_S,
+                (F32, I32, false) => op::I32_TRUNC_SAT_F32_U,
+                (F64, I32, true) => op::I32_TRUNC_SAT_F64_S,
+                (F64, I32, false) => op::I32_TRUNC_SAT_F64_U,
+                (F32, I64, true) => op::I64_TRUNC_SAT_F32_S,
+                (F32, I64, false) => op::I64_TRUNC_SAT_F32_U,
+                (F64, I64, true) => op::I64_TRUNC_SAT_F64_S,
+                (_, _, _) => op::I64_TRUNC_SAT_F64_U,
+            };
+
+            fx.bcx.code().local_get(val).op_fc(op).local_set(res);
+        }
+        (Primitive::F32, _) | (Primitive::F64, _) => {
+            // there is no saturating conversion to small integers, so clamp a 32 bit result
+            let bits = to.size(&fx.target).bits();
+            let (min, max) = if signed(to) { (-(1i32 << (bits - 1)), (1i32 << (bits - 1)) - 1) } else { (0, (1i32 << bits) - 1) };
+            let op = if from_ty == F32 { op::I32_TRUNC_SAT_F32_S } else { op::I32_TRUNC_SAT_F64_S };
+
+            fx.bcx.code().local_get(val).op_fc(op).local_set(res);
+            fx.bcx.code().i32_const(min).local_get(res).local_get(res).i32_const(min).op(op::I32_LT_S).op(op::SELECT).local_set(res);
+            fx.bcx.code().i32_const(max).local_get(res).local_get(res).i32_const(max).op(op::I32_GT_S).op(op::SELECT).local_set(res);
+        }
+        (_, Primitive::F32) | (_, Primitive::F64) => {
+            let op = match (from_ty, ty, signed(from)) {
+                (I32, F32, true) => op::F32_CONVERT_I32_S,
+                (I32, F32, false) => op::F32_CONVERT_I32_U,
+                (I64, F32, true) => op::F32_CONVERT_I64_S,
+                (I64, F32, false) => op::F32_CONVERT_I64_U,
+                (I32, F64, true) => op::F64_CONVERT_I32_S,
+                (I32, F64, false) => op::F64_CONVERT_I32_U,
+                (I64, F64, true) => op::F64_CONVERT_I64_S,
+                (_, _, _) => op::F64_CONVERT_I64_U,
+            };
+
+            fx.bcx.code().local_get(val).op(op).local_set(res);
+        }
+        _ => {
+            let code = fx.bcx.code().local_get(val);
+
+            match (from_ty, ty) {
+                (I32, I64) if signed(from) => code.op(op::I64_EXTEND_I32_S),
+                (I32, I64) => code.op(op::I64_EXTEND_I32_U),
+                (I64, I32) => code.op(op::I32_WRAP_I64),
+                _ => code,
+            }
+            .local_set(res);
+
+            return normalize(fx, res, to);
+        }
+    }
+
+    res
+}
+
+/// Compares `val` with zero as a bool, with NaN counting as non-zero like it does in C.
+fn is_nonzero<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, val: u32, prim: Primitive) -> u32 {
+    let res = fx.bcx.new_local(ValType::I32);
+    let ty = fx.bcx.local_type(val);
+    let code = fx.bcx.code().local_get(val);
+
+    match prim {
+        Primitive::F32 => code.f32_const(0).op(op::F32_NE),
+        Primitive::F64 => code.f64_const(0).op(op::F64_NE),
+        _ if ty == ValType::I64 => code.op(op::I64_EQZ).op(op::I32_EQZ),
+        _ => code.op(op::I32_EQZ).op(op::I32_EQZ),
+    }
+    .local_set(res);
+
+    res
+}
+
+/// Replaces the float `val` of type `prim` with the canonical NaN if it is a NaN and
+/// `CodegenOptions::canonical_nans` is set, returning the local holding the result.
+pub fn canonicalize_nan<'ctx>(fx: &mut FunctionCtx<'_, 'ctx, '_, WasmBackend<'ctx>>, val: u32, prim: Primitive) -> u32 {
+    let nan = match prim.canonical_nan() {
+        Some(nan) if fx.options.canonical_nans => nan,
+        _ => return val,
+    };
+
+    let res = fx.bcx.new_local(fx.bcx.local_type(val));
+    let code = fx.bcx.code();
+
+    if prim == Primitive::F32 {
+        code.f32_const(nan as u32).local_get(val).local_get(val).local_get(val).op(op::F32_NE);
+    } else {
+        code.f64_const(nan as u64).local_get(val).local_get(val).local_get(val).op(op::F64_NE);
+    }
+
+    code.op(op::SELECT).local_set(res);
+    res
+}
@@ -0,0 +1,155 @@
+use std::cmp::Ordering;
+
+/// An integer of arbitrary size, matching `BigInt` in the runtime library. The magnitude is stored least
+/// significant limb first and its most significant limb is never zero, so zero is never negative.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BigInt {
+    neg: bool,
+    mag: Vec<u32>,
+}
+
+impl BigInt {
+    fn new(neg: bool, mut mag: Vec<u32>) -> Self {
+        while mag.last() == Some(&0) {
+            mag.pop();
+        }
+
+        BigInt { neg: neg && !mag.is_empty(), mag }
+    }
+
+    /// Parses a decimal integer with an optional minus sign.
+    pub(crate) fn parse(s: &[u8]) -> Option<Self> {
+        let (neg, digits) = match s.split_first() {
+            Some((b'-', rest)) => (true, rest),
+            _ => (false, s),
+        };
+
+        if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+
+        let mut mag = Vec::new();
+
+        for d in digits {
+            let mut carry = u64::from(d - b'0');
+
+            for limb in &mut mag {
+                let val = u64::from(*limb) * 10 + carry;
+
+                *limb = val as u32;
+                carry = val >> 32;
+            }
+
+            if carry > 0 {
+                mag.push(carry as u32);
+            }
+        }
+
+        Some(BigInt::new(neg, mag))
+    }
+
+    pub(crate) fn add(&self, other: &Self) -> Self {
+        if self.neg == other.neg {
+            BigInt::new(self.neg, add_mag(&self.mag, &other.mag))
+        } else if cmp_mag(&self.mag, &other.mag) == Ordering::Less {
+            BigInt::new(other.neg, sub_mag(&other.mag, &self.mag))
+        } else {
+            BigInt::new(self.neg, sub_mag(&self.mag, &other.mag))
+        }
+    }
+
+    pub(crate) fn mul(&self, other: &Self) -> Self {
+        let mut mag = vec![0u32; self.mag.len() + other.mag.len()];
+
+        for (i, a) in self.mag.iter().enumerate() {
+            let mut carry = 0;
+
+            for (j, b) in other.mag.iter().enumerate() {
+                let prod = u64::from(*a) * u64::from(*b) + u64::from(mag[i + j]) + carry;
+
+                mag[i + j] = prod as u32;
+                carry = prod >> 32;
+            }
+
+            mag[i + other.mag.len()] = carry as u32;
+        }
+
+        BigInt::new(self.neg != other.neg, mag)
+    }
+
+    pub(crate) fn cmp(&self, other: &Self) -> Ordering {
+        match (self.neg, other.neg) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.mag, &other.mag),
+            (true, true) => cmp_mag(&other.mag, &self.mag),
+        }
+    }
+}
+
+impl std::fmt::Display for BigInt {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut rem = self.mag.clone();
+        let mut digits = Vec::new();
+
+        while !rem.is_empty() {
+            let mut digit = 0;
+
+            for limb in rem.iter_mut().rev() {
+                let val = (digit << 32) | u64::from(*limb);
+
+                *limb = (val / 10) as u32;
+                digit = val % 10;
+            }
+
+            while rem.last() == Some(&0) {
+                rem.pop();
+            }
+
+            digits.push(b'0' + digit as u8);
+        }
+
+        if digits.is_empty() {
+            digits.push(b'0');
+        }
+
+        if self.neg {
+            digits.push(b'-');
+        }
+
+        digits.reverse();
+        f.write_str(std::str::from_utf8(&digits).unwrap())
+    }
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut carry = 0;
+
+    (0..a.len().max(b.len()) + 1)
+        .map(|i| {
+            let sum = carry + u64::from(*a.get(i).unwrap_or(&0)) + u64::from(*b.get(i).unwrap_or(&0));
+
+            carry = sum >> 32;
+            sum as u32
+        })
+        .collect()
+}
+
+/// Subtracts `b` from `a`, which must not be smaller.
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut borrow = 0;
+
+    a.iter()
+        .enumerate()
+        .map(|(i, a)| {
+            let diff = i64::from(*a) - i64::from(*b.get(i).unwrap_or(&0)) - borrow;
+
+            borrow = (diff < 0) as i64;
+            diff as u32
+        })
+        .collect()
+}
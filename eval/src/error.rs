@@ -0,0 +1,68 @@
+use ir::{Block, Local, Location, SourceLoc};
+
+/// Why a body could not be evaluated.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// An access of `offset` bytes or fields into a value that only has `size`.
+    OutOfBounds { offset: u128, size: u128 },
+    /// A call to a function or a reference to a global that neither the module nor the externs provide.
+    UnknownSymbol(String),
+    UndefinedBlock(Block),
+    UndefinedLocal(Local),
+    /// A use of a local after it was dropped and before it was initialized again.
+    DeadLocal(Local),
+    /// The stack grew to `depth` frames or `bytes` bytes, beyond the limits in `EvalOptions`.
+    StackOverflow { depth: usize, bytes: u64 },
+    /// An operation was applied to a value of the wrong kind, like dereferencing something that is not a pointer.
+    Invalid(String),
+    /// The operation is valid but can not be evaluated at compile time.
+    Unsupported(String),
+    /// The `ExecutionMeter` refused to run the next instruction, for the reason it gave.
+    Exhausted(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::OutOfBounds { offset, size } => write!(f, "out of bounds access: {} into a value of size {}", offset, size),
+            Error::UnknownSymbol(name) => write!(f, "unknown symbol {}", name),
+            Error::UndefinedBlock(block) => write!(f, "jump to undefined block {}", block),
+            Error::UndefinedLocal(local) => write!(f, "use of undefined local _{}", local.index()),
+            Error::DeadLocal(local) => write!(f, "use of dropped local _{}", local.index()),
+            Error::StackOverflow { depth, bytes } => write!(f, "stack overflow: {} frames using {} bytes", depth, bytes),
+            Error::Invalid(what) => write!(f, "invalid operation: {}", what),
+            Error::Unsupported(what) => write!(f, "{} can not be evaluated", what),
+            Error::Exhausted(why) => write!(f, "evaluation stopped: {}", why),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A frame on the stack of the interpreter when it failed, see `EvalCtx::traceback`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    /// The name of the function the frame is running.
+    pub func: String,
+    /// The statement the frame is at, or its terminator if the index is past the statements of the block.
+    /// Frames below the innermost one are at the call they are waiting for.
+    pub loc: Location,
+    pub source: Option<SourceLoc>,
+}
+
+impl std::fmt::Display for TraceFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "at {} %{}:{}", self.func, self.loc.block.index(), self.loc.stmt)?;
+
+        if let Some(source) = &self.source {
+            write!(f, " ({}:{}:{})", source.file, source.line, source.col)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Formats a traceback with one frame per line, innermost frame first, each line indented by two spaces.
+pub fn format_traceback(trace: &[TraceFrame]) -> String {
+    trace.iter().map(|frame| format!("  {}", frame)).collect::<Vec<_>>().join("\n")
+}
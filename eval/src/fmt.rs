@@ -0,0 +1,133 @@
+/// Formats in decimal notation when the exponent is in -5..17 and in scientific notation otherwise,
+/// matching `f64_to_str` in the runtime library.
+pub(crate) fn format_f64(val: f64) -> String {
+    if val.is_nan() {
+        return String::from("NaN");
+    }
+
+    let mut out = String::new();
+
+    if val.is_sign_negative() {
+        out.push('-');
+    }
+
+    if val.is_infinite() {
+        out.push_str("inf");
+        return out;
+    } else if val == 0.0 {
+        out.push_str("0.0");
+        return out;
+    }
+
+    // `{:e}` prints the shortest digits that round trip
+    let sci = format!("{:e}", val.abs());
+    let (mantissa, exp) = sci.split_at(sci.find('e').unwrap());
+    let digits = mantissa.replace('.', "");
+    let exp = exp[1..].parse::<i32>().unwrap();
+
+    if exp < -5 || exp >= 17 {
+        out.push_str(mantissa);
+        out.push('e');
+        out.push_str(&exp.to_string());
+    } else if exp < 0 {
+        out.push_str("0.");
+        out.extend(std::iter::repeat('0').take((-exp - 1) as usize));
+        out.push_str(&digits);
+    } else {
+        let int = exp as usize + 1;
+
+        out.push_str(&format!("{:0<width$}", &digits[..int.min(digits.len())], width = int));
+        out.push('.');
+        out.push_str(if digits.len() > int { &digits[int..] } else { "0" });
+    }
+
+    out
+}
+
+/// Parses `[+-]digits[.digits][(e|E)[+-]digits]`, `inf` or `NaN` from the start of `s`.
+/// Digits beyond the 19th significant one are ignored, just like the runtime does.
+pub(crate) fn parse_f64(s: &[u8]) -> Option<(f64, usize)> {
+    let neg = s.first() == Some(&b'-');
+    let mut i = (neg || s.first() == Some(&b'+')) as usize;
+    let sign = if neg { -1.0 } else { 1.0 };
+
+    if s[i..].starts_with(b"inf") {
+        return Some((sign * f64::INFINITY, i + 3));
+    } else if s[i..].starts_with(b"NaN") {
+        return Some((f64::NAN, i + 3));
+    }
+
+    let mut digits = String::new();
+    let mut exp = 0i64;
+    let mut any = false;
+    let mut frac = false;
+
+    loop {
+        match s.get(i) {
+            Some(c @ b'0'..=b'9') => {
+                if digits.len() < 19 {
+                    if !digits.is_empty() || *c != b'0' {
+                        digits.push(*c as char);
+                    }
+
+                    exp -= frac as i64;
+                } else if !frac {
+                    exp += 1;
+                }
+
+                any = true;
+            }
+            Some(b'.') if !frac => frac = true,
+            _ => break,
+        }
+
+        i += 1;
+    }
+
+    if !any {
+        return None;
+    }
+
+    if let Some(b'e') | Some(b'E') = s.get(i) {
+        let neg = s.get(i + 1) == Some(&b'-');
+        let start = i + 1 + (neg || s.get(i + 1) == Some(&b'+')) as usize;
+        let mut e = 0i64;
+        let mut j = start;
+
+        while let Some(c @ b'0'..=b'9') = s.get(j) {
+            e = (e * 10 + (c - b'0') as i64).min(100_000);
+            j += 1;
+        }
+
+        if j > start {
+            exp += if neg { -e } else { e };
+            i = j;
+        }
+    }
+
+    if digits.is_empty() {
+        return Some((sign * 0.0, i));
+    }
+
+    Some((sign * format!("{}e{}", digits, exp).parse::<f64>().unwrap(), i))
+}
+
+/// Parses an optionally signed integer from the start of `s`, returning `None` if it overflows.
+pub(crate) fn parse_int(s: &[u8], signed: bool) -> Option<(i128, usize)> {
+    let neg = signed && s.first() == Some(&b'-');
+    let start = (neg || s.first() == Some(&b'+')) as usize;
+    let len = s[start..].iter().take_while(|c| c.is_ascii_digit()).count();
+
+    if len == 0 {
+        return None;
+    }
+
+    let text = std::str::from_utf8(&s[..start + len]).unwrap();
+    let val = if signed {
+        text.parse::<i64>().ok()? as i128
+    } else {
+        text.parse::<u64>().ok()? as i128
+    };
+
+    Some((val, start + len))
+}
@@ -0,0 +1,135 @@
+use crate::memory::bytes_of;
+use crate::stubs::{Externs, NoExterns};
+use crate::Error;
+use ir::*;
+use std::collections::HashMap;
+
+/// A host function callable from evaluated code. Arguments are passed as 64-bit words, pointers are
+/// addresses into `Memory`. Returning `None` is only allowed for functions without a return value.
+pub type HostFn = dyn FnMut(&[u64], &mut Memory) -> Option<u64>;
+
+/// Implements imported functions with host closures, registered by the name of the import.
+/// Imports without a host function are passed on to the fallback, which is `NoExterns` by default.
+pub struct HostFns {
+    fns: HashMap<String, Box<HostFn>>,
+    fallback: Box<dyn Externs>,
+}
+
+/// The memory pointer arguments of a host function point into. It holds a copy of the pointees,
+/// so writes are not visible to the evaluated program.
+#[derive(Debug)]
+pub struct Memory {
+    bytes: Vec<u8>,
+}
+
+impl HostFns {
+    pub fn new() -> Self {
+        HostFns {
+            fns: HashMap::new(),
+            fallback: Box::new(NoExterns),
+        }
+    }
+
+    pub fn with_fallback(mut self, fallback: impl Externs + 'static) -> Self {
+        self.fallback = Box::new(fallback);
+        self
+    }
+
+    /// Makes calls to the import `name` call `f`, replacing a previously registered function.
+    pub fn register(&mut self, name: impl Into<String>, f: impl FnMut(&[u64], &mut Memory) -> Option<u64> + 'static) -> &mut Self {
+        self.fns.insert(name.into(), Box::new(f));
+        self
+    }
+}
+
+impl Default for HostFns {
+    fn default() -> Self {
+        HostFns::new()
+    }
+}
+
+impl Externs for HostFns {
+    fn call(&mut self, module: &Module, decl: &Decl, args: &[Const], target: &target_lexicon::Triple) -> Result<Vec<Const>, Error> {
+        let f = match self.fns.get_mut(&decl.name) {
+            Some(f) => f,
+            None => return self.fallback.call(module, decl, args, target),
+        };
+
+        let mut mem = Memory::new();
+        let args = args.iter().map(|arg| mem.pass(module, arg, target)).collect::<Result<Vec<_>, _>>()?;
+        let ret = f(&args, &mut mem);
+
+        match (decl.ty.signature().rets.pop(), ret) {
+            (None, _) => Ok(Vec::new()),
+            (Some(ty), Some(val)) => {
+                let val = layout::layout_of(&ty, target).size.truncate(val as u128);
+
+                Ok(vec![Const::Scalar(val, ty)])
+            }
+            (Some(_), None) => Err(Error::Invalid(format!("host function {} did not return a value", decl.name))),
+        }
+    }
+}
+
+impl Memory {
+    /// Address zero is never handed out so that it can be used as null.
+    const BASE: u64 = 8;
+
+    fn new() -> Self {
+        Memory {
+            bytes: vec![0; Memory::BASE as usize],
+        }
+    }
+
+    /// The bytes from `addr` to `addr + len`, or `None` if they are out of bounds.
+    pub fn read(&self, addr: u64, len: u64) -> Option<&[u8]> {
+        let end = addr.checked_add(len)?;
+
+        if addr < Memory::BASE {
+            return None;
+        }
+
+        self.bytes.get(addr as usize..end as usize)
+    }
+
+    /// The bytes from `addr` up to the first zero byte.
+    pub fn read_c_str(&self, addr: u64) -> Option<&[u8]> {
+        if addr < Memory::BASE {
+            return None;
+        }
+
+        let bytes = self.bytes.get(addr as usize..)?;
+
+        bytes.iter().position(|b| *b == 0).map(|end| &bytes[..end])
+    }
+
+    pub fn write(&mut self, addr: u64, bytes: &[u8]) -> Option<()> {
+        let end = addr.checked_add(bytes.len() as u64)?;
+
+        if addr < Memory::BASE {
+            return None;
+        }
+
+        self.bytes.get_mut(addr as usize..end as usize)?.copy_from_slice(bytes);
+        Some(())
+    }
+
+    /// Converts an argument to the word passed to a host function, copying pointees into memory.
+    fn pass(&mut self, module: &Module, arg: &Const, target: &target_lexicon::Triple) -> Result<u64, Error> {
+        match arg {
+            Const::Scalar(s, _) => Ok(*s as u64),
+            Const::Undefined(_) => Ok(0),
+            Const::Ptr(to) => {
+                let bytes = bytes_of(to, &const_type(module, to), target)?;
+                let addr = self.bytes.len() as u64;
+
+                self.bytes.extend(bytes);
+                // a string without a terminator ends with its pointee instead of running into the next one
+                self.bytes.push(0);
+                Ok(addr)
+            }
+            Const::Addr(_) => Err(Error::Unsupported(String::from("passing functions to host functions"))),
+            _ => Err(Error::Unsupported(format!("passing {:?} to host functions", arg))),
+        }
+    }
+}
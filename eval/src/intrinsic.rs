@@ -0,0 +1,540 @@
+use crate::bigint::BigInt;
+use crate::fmt;
+use crate::memory::{bytes_of, from_bytes};
+use crate::{check_bounds, init_undefined, Error, EvalCtx};
+use ir::*;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+
+macro_rules! checked {
+    ($op:ident, $a:expr, $b:expr, $ty:ty, $uty:ty) => {{
+        let (val, overflow) = ($a as $ty).$op($b as $ty);
+
+        (val as $uty as u128, overflow)
+    }};
+}
+
+impl<'ir> EvalCtx<'ir> {
+    pub(crate) fn eval_intrinsic(&mut self, place: &Place, name: &str, ops: &'ir [Operand]) -> Result<Const, Error> {
+        let args = ops.iter().map(|a| self.eval_op(a)).collect::<Result<Vec<_>, _>>()?;
+
+        if let Some(op) = name.strip_prefix("atomic_") {
+            return self.eval_atomic(op, ops, &args);
+        }
+
+        match (name, &args[..]) {
+            ("memcpy", [_, _, Const::Scalar(n, _)]) => {
+                let (dst, ty, mut bytes) = self.read_memory(&ops[0], *n)?;
+                let (_, _, src) = self.read_memory(&ops[1], *n)?;
+
+                bytes[..*n as usize].copy_from_slice(&src[..*n as usize]);
+                self.store(&dst, from_bytes(&ty, &bytes, self.target)?)?;
+
+                return Ok(Const::Tuple(Vec::new()));
+            }
+            ("memset", [_, Const::Scalar(c, _), Const::Scalar(n, _)]) => {
+                let (dst, ty, mut bytes) = self.read_memory(&ops[0], *n)?;
+
+                bytes[..*n as usize].iter_mut().for_each(|b| *b = *c as u8);
+                self.store(&dst, from_bytes(&ty, &bytes, self.target)?)?;
+
+                return Ok(Const::Tuple(Vec::new()));
+            }
+            ("memcmp", [_, _, Const::Scalar(n, _)]) => {
+                let (_, _, a) = self.read_memory(&ops[0], *n)?;
+                let (_, _, b) = self.read_memory(&ops[1], *n)?;
+                let res = match a[..*n as usize].cmp(&b[..*n as usize]) {
+                    Ordering::Less => -1i32,
+                    Ordering::Equal => 0,
+                    Ordering::Greater => 1,
+                };
+
+                return Ok(Const::Scalar(res as u32 as u128, Ty::new(Type::I32)));
+            }
+            ("f64_to_str", [Const::Scalar(val, _), _, Const::Scalar(len, _)]) => {
+                return self.write_str(&ops[1], *len, fmt::format_f64(f64::from_bits(*val as u64)));
+            }
+            ("i64_to_str", [Const::Scalar(val, _), _, Const::Scalar(len, _)]) => {
+                return self.write_str(&ops[1], *len, (*val as u64 as i64).to_string());
+            }
+            ("u64_to_str", [Const::Scalar(val, _), _, Const::Scalar(len, _)]) => {
+                return self.write_str(&ops[1], *len, (*val as u64).to_string());
+            }
+            ("str_to_f64", [_, Const::Scalar(len, _), _]) => {
+                let (_, _, bytes) = self.read_memory(&ops[0], *len)?;
+                let parsed = fmt::parse_f64(&bytes[..*len as usize]).map(|(val, n)| (val.to_bits() as u128, n));
+
+                return self.store_parsed(&ops[2], Type::F64, parsed);
+            }
+            ("str_to_i64", [_, Const::Scalar(len, _), _]) | ("str_to_u64", [_, Const::Scalar(len, _), _]) => {
+                let signed = name == "str_to_i64";
+                let (_, _, bytes) = self.read_memory(&ops[0], *len)?;
+                let parsed = fmt::parse_int(&bytes[..*len as usize], signed).map(|(val, n)| (val as u64 as u128, n));
+
+                return self.store_parsed(&ops[2], if signed { Type::I64 } else { Type::U64 }, parsed);
+            }
+            ("dyn_cast", [a, b]) => {
+                let a = self.type_id(&ops[0], a)?;
+                let b = self.type_id(&ops[1], b)?;
+
+                return Ok(Const::Scalar((a == b) as u128, Ty::new(Type::Bool)));
+            }
+            ("select", [Const::Scalar(c, ty), a, b]) if ty.without_newtypes().kind == Type::Bool => {
+                return Ok(if *c != 0 { a.clone() } else { b.clone() });
+            }
+            ("select", [c, _, _]) => return Err(Error::Invalid(format!("select on {:?}, which is not a bool", c))),
+            ("resource", [ptr]) => {
+                let ty = operand_type(self.module, self.frame().body, &ops[0]);
+                let size = layout::layout_of(&ty, self.target).pointee(self.target).size.bytes();
+
+                return Ok(Const::Tuple(vec![ptr.clone(), Const::Scalar(size as u128, layout::ptr_sized_int(self.target))]));
+            }
+            ("pool_create", [Const::Scalar(_, _), Const::Scalar(_, _), Const::Scalar(count, _)]) => {
+                let mut pools = self.pools.borrow_mut();
+
+                pools.push(Some(Pool { capacity: *count, live: 0 }));
+
+                return Ok(Const::Scalar(pools.len() as u128, layout::ptr_sized_int(self.target)));
+            }
+            ("pool_alloc", [handle]) => {
+                let mut pools = self.pools.borrow_mut();
+                let pool = pool_mut(&mut pools, handle)?;
+                let ty = place_type(self.frame().body, place);
+
+                if pool.live == pool.capacity {
+                    return Ok(Const::Scalar(0, ty));
+                }
+
+                pool.live += 1;
+
+                // every object is a separate value, so the pool does not need to keep them
+                return Ok(match ty.kind {
+                    Type::Ptr(to) => Const::Ptr(Box::new(init_undefined(*to, self.target))),
+                    _ => Const::Ptr(Box::new(Const::Undefined(Ty::new(Type::U8)))),
+                });
+            }
+            ("pool_free", [handle, _]) => {
+                let mut pools = self.pools.borrow_mut();
+                let pool = pool_mut(&mut pools, handle)?;
+
+                if pool.live == 0 {
+                    return Err(Error::Invalid(String::from("pool_free of an object that is not in use")));
+                }
+
+                pool.live -= 1;
+
+                return Ok(Const::Tuple(Vec::new()));
+            }
+            ("pool_destroy", [handle]) => {
+                let mut pools = self.pools.borrow_mut();
+
+                pool_mut(&mut pools, handle)?;
+                pools[handle_index(handle, "pool")?] = None;
+
+                return Ok(Const::Tuple(Vec::new()));
+            }
+            ("bigint_from_str", [_, Const::Scalar(len, _)]) => {
+                let (_, _, bytes) = self.read_memory(&ops[0], *len)?;
+
+                return Ok(match BigInt::parse(&bytes[..*len as usize]) {
+                    Some(int) => self.new_bigint(int),
+                    None => Const::Scalar(0, layout::ptr_sized_int(self.target)),
+                });
+            }
+            ("bigint_to_str", [a, _, Const::Scalar(len, _)]) => {
+                let s = self.bigint(a)?.to_string();
+
+                return self.write_str(&ops[1], *len, s);
+            }
+            ("bigint_add", [a, b]) => {
+                let int = self.bigint(a)?.add(&self.bigint(b)?);
+
+                return Ok(self.new_bigint(int));
+            }
+            ("bigint_mul", [a, b]) => {
+                let int = self.bigint(a)?.mul(&self.bigint(b)?);
+
+                return Ok(self.new_bigint(int));
+            }
+            ("bigint_cmp", [a, b]) => {
+                let ord = self.bigint(a)?.cmp(&self.bigint(b)?);
+
+                return Ok(Const::Scalar(ord as i32 as u32 as u128, Ty::new(Type::I32)));
+            }
+            ("bigint_free", [a]) => {
+                self.bigint(a)?;
+                self.bigints.borrow_mut()[handle_index(a, "integer")?] = None;
+
+                return Ok(Const::Tuple(Vec::new()));
+            }
+            _ => {}
+        }
+
+        if let Some(val) = self.eval_vector(place, name, ops, &args)? {
+            return Ok(val);
+        }
+
+        if let Some(name) = name.strip_suffix("_checked") {
+            if let [Const::Scalar(a, ty), Const::Scalar(b, _)] = &args[..] {
+                let (val, overflow) = checked_arith(name, *a, *b)?;
+
+                return Ok(Const::Tuple(vec![Const::Scalar(val, ty.clone()), Const::Scalar(overflow as u128, Ty::new(Type::Bool))]));
+            }
+        }
+
+        if let [Const::Scalar(a, ty), Const::Scalar(b, _)] = &args[..] {
+            match int_arith(name, *a, *b)? {
+                Some(Arith::Int(val)) => return Ok(Const::Scalar(val, ty.clone())),
+                Some(Arith::Bool(val)) => return Ok(Const::Scalar(val as u128, place_type(self.frame().body, place))),
+                None => {}
+            }
+        }
+
+        Err(Error::Unsupported(format!("intrinsic {}", name)))
+    }
+
+    /// Evaluates the vector intrinsics one lane at a time, like `splat` or `vadd_i32x4`.
+    /// Returns `None` if `name` is not one of them.
+    fn eval_vector(&self, place: &Place, name: &str, ops: &[Operand], args: &[Const]) -> Result<Option<Const>, Error> {
+        match (name, args) {
+            ("splat", [Const::Scalar(val, _)]) => {
+                let ty = place_type(self.frame().body, place);
+
+                return match &ty.kind {
+                    Type::Vector(elem, lanes) => Ok(Some(Const::Tuple(vec![Const::Scalar(*val, (**elem).clone()); *lanes as usize]))),
+                    _ => Err(Error::Invalid(format!("splat to {}", display_ty(&ty)))),
+                };
+            }
+            ("extractlane", [vec, _]) => {
+                let mut lanes = vector_lanes(vec)?;
+                let lane = lane_index(&ops[1], lanes.len())?;
+
+                return Ok(Some(lanes.swap_remove(lane)));
+            }
+            ("insertlane", [vec, val, _]) => {
+                let mut lanes = vector_lanes(vec)?;
+                let lane = lane_index(&ops[2], lanes.len())?;
+
+                lanes[lane] = val.clone();
+
+                return Ok(Some(Const::Tuple(lanes)));
+            }
+            _ => {}
+        }
+
+        let (op, shape) = match name.strip_prefix('v').and_then(|name| name.split_once('_')) {
+            Some(split) => split,
+            None => return Ok(None),
+        };
+
+        let (kind, bits, count) = match parse_shape(shape) {
+            Some(shape) => shape,
+            None => return Ok(None),
+        };
+
+        let (a, b) = match args {
+            [a, b] => (vector_lanes(a)?, vector_lanes(b)?),
+            _ => return Ok(None),
+        };
+
+        if a.len() != count || b.len() != count {
+            return Err(Error::Invalid(format!("{} of vectors with {} and {} lanes", name, a.len(), b.len())));
+        }
+
+        a.iter()
+            .zip(&b)
+            .map(|(a, b)| {
+                let (a, b, ty) = match (a, b) {
+                    (Const::Scalar(a, ty), Const::Scalar(b, _)) => (*a, *b, ty),
+                    _ => return Err(Error::Invalid(format!("{} of {:?} and {:?}", name, a, b))),
+                };
+
+                let val = if kind == 'f' {
+                    float_arith(op, bits, a, b).map(|val| self.canonicalize_nan(val, ty))
+                } else {
+                    match int_arith(&format!("{}_i{}", op, bits), a, b)? {
+                        Some(Arith::Int(val)) => Some(val),
+                        _ => None,
+                    }
+                };
+
+                match val {
+                    Some(val) => Ok(Some(Const::Scalar(val, ty.clone()))),
+                    None => Ok(None),
+                }
+            })
+            .collect::<Result<Option<Vec<_>>, _>>()
+            .map(|lanes| lanes.map(Const::Tuple))
+    }
+
+    fn bigint(&self, handle: &Const) -> Result<BigInt, Error> {
+        match self.bigints.borrow().get(handle_index(handle, "integer")?) {
+            Some(Some(int)) => Ok(int.clone()),
+            Some(None) => Err(Error::Invalid(String::from("use of a freed integer"))),
+            None => Err(Error::Invalid(format!("integer {:?}", handle))),
+        }
+    }
+
+    fn new_bigint(&self, int: BigInt) -> Const {
+        let mut bigints = self.bigints.borrow_mut();
+
+        bigints.push(Some(int));
+        Const::Scalar(bigints.len() as u128, layout::ptr_sized_int(self.target))
+    }
+
+    /// Reads the id of the type info that `op`, evaluated to `val`, points to.
+    /// The id and the name of the type info `val` points to, which together tell types apart.
+    fn type_id(&self, op: &Operand, val: &Const) -> Result<Const, Error> {
+        let info = match val {
+            Const::Addr(decl) => self.global(*decl)?,
+            _ => self.load(&pointee(Some(op))?)?,
+        };
+
+        match info {
+            Const::Tuple(mut fields) if fields.len() == 6 => Ok(Const::Tuple(fields.split_off(4))),
+            info => Err(Error::Invalid(format!("type info {:?}", info))),
+        }
+    }
+
+    /// Statements are never interrupted, not even by `sched::Scheduler`, so every ordering behaves as sequentially consistent.
+    fn eval_atomic(&mut self, op: &str, ops: &'ir [Operand], args: &[Const]) -> Result<Const, Error> {
+        let (op, _) = op.split_at(op.rfind('_').ok_or_else(|| Error::Unsupported(format!("intrinsic atomic_{}", op)))?);
+        let ordering = ops.last().and_then(AtomicOrdering::from_operand);
+        let ordering = ordering.ok_or_else(|| Error::Invalid(format!("atomic_{} requires a constant ordering", op)))?;
+
+        if (op == "load" || op == "store") && !ordering.is_valid_for(op == "store") {
+            return Err(Error::Invalid(format!("ordering {:?} for atomic_{}", ordering, op)));
+        }
+
+        let place = pointee(ops.first())?;
+        let old = self.load(&place)?;
+
+        match (op, &old, args) {
+            ("load", _, [_, _]) => Ok(old),
+            ("store", _, [_, val, _]) => {
+                self.store(&place, val.clone())?;
+
+                Ok(Const::Tuple(Vec::new()))
+            }
+            ("cmpxchg", Const::Scalar(o, ty), [_, Const::Scalar(expected, _), new, _]) => {
+                let success = o == expected;
+
+                if success {
+                    self.store(&place, new.clone())?;
+                }
+
+                Ok(Const::Tuple(vec![Const::Scalar(*o, ty.clone()), Const::Scalar(success as u128, Ty::new(Type::Bool))]))
+            }
+            ("fetch_add", Const::Scalar(o, ty), [_, Const::Scalar(val, _), _]) => {
+                let bits = layout::layout_of(ty, self.target).size.bits();
+                let mask = if bits >= 128 { u128::MAX } else { (1 << bits) - 1 };
+
+                self.store(&place, Const::Scalar(o.wrapping_add(*val) & mask, ty.clone()))?;
+                Ok(old)
+            }
+            _ => Err(Error::Invalid(format!("atomic_{} on {:?}", op, old))),
+        }
+    }
+
+    /// Returns the place a pointer operand points to, along with the bytes stored there.
+    /// At least `n` bytes must be readable.
+    fn read_memory(&self, op: &Operand, n: u128) -> Result<(Place, Ty, Vec<u8>), Error> {
+        let place = pointee(Some(op))?;
+        let ty = place_type(self.frame().body, &place);
+        let bytes = bytes_of(&self.load(&place)?, &ty, self.target)?;
+
+        if n > bytes.len() as u128 {
+            return Err(Error::OutOfBounds {
+                offset: n,
+                size: bytes.len() as u128,
+            });
+        }
+
+        Ok((place, ty, bytes))
+    }
+
+    /// Writes `s` through a pointer operand if it fits in `len` bytes and returns its length.
+    fn write_str(&mut self, op: &Operand, len: u128, s: String) -> Result<Const, Error> {
+        if s.len() as u128 <= len {
+            let (dst, ty, mut bytes) = self.read_memory(op, s.len() as u128)?;
+
+            bytes[..s.len()].copy_from_slice(s.as_bytes());
+            self.store(&dst, from_bytes(&ty, &bytes, self.target)?)?;
+        }
+
+        Ok(Const::Scalar(s.len() as u128, Ty::new(Type::U64)))
+    }
+
+    /// Stores a parsed value through a pointer operand and returns the number of bytes consumed.
+    fn store_parsed(&mut self, op: &Operand, ty: Type, parsed: Option<(u128, usize)>) -> Result<Const, Error> {
+        match parsed {
+            Some((val, n)) => {
+                self.store(&pointee(Some(op))?, Const::Scalar(val, Ty::new(ty)))?;
+
+                Ok(Const::Scalar(n as u128, Ty::new(Type::U64)))
+            }
+            None => Ok(Const::Scalar(0, Ty::new(Type::U64))),
+        }
+    }
+}
+
+/// The objects of a pool are ordinary values in the interpreter, so a pool only counts them.
+pub(crate) struct Pool {
+    capacity: u128,
+    live: u128,
+}
+
+/// The index of the object a handle refers to. Handles start at one, so null is never a valid handle.
+fn handle_index(handle: &Const, what: &str) -> Result<usize, Error> {
+    match handle {
+        Const::Scalar(h, _) if *h > 0 => Ok(*h as usize - 1),
+        _ => Err(Error::Invalid(format!("{} {:?}", what, handle))),
+    }
+}
+
+fn pool_mut<'a>(pools: &'a mut [Option<Pool>], handle: &Const) -> Result<&'a mut Pool, Error> {
+    match pools.get_mut(handle_index(handle, "pool")?) {
+        Some(Some(pool)) => Ok(pool),
+        Some(None) => Err(Error::Invalid(String::from("use of a destroyed pool"))),
+        None => Err(Error::Invalid(format!("pool {:?}", handle))),
+    }
+}
+
+fn checked_arith(name: &str, a: u128, b: u128) -> Result<(u128, bool), Error> {
+    Ok(match name {
+        "add_i32" => checked!(overflowing_add, a, b, i32, u32),
+        "sub_i32" => checked!(overflowing_sub, a, b, i32, u32),
+        "mul_i32" => checked!(overflowing_mul, a, b, i32, u32),
+        "add_i64" => checked!(overflowing_add, a, b, i64, u64),
+        "sub_i64" => checked!(overflowing_sub, a, b, i64, u64),
+        "mul_i64" => checked!(overflowing_mul, a, b, i64, u64),
+        "add_u32" => checked!(overflowing_add, a, b, u32, u32),
+        "sub_u32" => checked!(overflowing_sub, a, b, u32, u32),
+        "mul_u32" => checked!(overflowing_mul, a, b, u32, u32),
+        "add_u64" => checked!(overflowing_add, a, b, u64, u64),
+        "sub_u64" => checked!(overflowing_sub, a, b, u64, u64),
+        "mul_u64" => checked!(overflowing_mul, a, b, u64, u64),
+        _ => return Err(Error::Unsupported(format!("intrinsic {}_checked", name))),
+    })
+}
+
+enum Arith {
+    Int(u128),
+    Bool(bool),
+}
+
+/// Evaluates the wrapping arithmetic and comparison intrinsics, like `add_i8` or `lt_u64`.
+/// Returns `None` if `name` is not one of them.
+fn int_arith(name: &str, a: u128, b: u128) -> Result<Option<Arith>, Error> {
+    let (op, suffix) = match name.rfind('_') {
+        Some(idx) => (&name[..idx], &name[idx + 1..]),
+        None => return Ok(None),
+    };
+
+    let signed = match suffix.chars().next() {
+        Some('i') => true,
+        Some('u') => false,
+        _ => return Ok(None),
+    };
+
+    let bits = match suffix[1..].parse::<u32>() {
+        Ok(bits @ (8 | 16 | 32 | 64 | 128)) => bits,
+        _ => return Ok(None),
+    };
+
+    let mask = if bits == 128 { u128::MAX } else { (1 << bits) - 1 };
+    let (a, b) = (a & mask, b & mask);
+    let sext = |v: u128| ((v << (128 - bits)) as i128) >> (128 - bits);
+    let ord = if signed { sext(a).cmp(&sext(b)) } else { a.cmp(&b) };
+
+    if b == 0 && (op == "div" || op == "rem") {
+        return Err(Error::Invalid(format!("division by zero in {}", name)));
+    }
+
+    // compiled code traps on the one signed quotient that does not fit, the remainder is 0 there
+    if op == "div" && signed && sext(a) == i128::MIN >> (128 - bits) && sext(b) == -1 {
+        return Err(Error::Invalid(format!("signed overflow in {}", name)));
+    }
+
+    let val = match op {
+        "add" => Arith::Int(a.wrapping_add(b) & mask),
+        "sub" => Arith::Int(a.wrapping_sub(b) & mask),
+        "mul" => Arith::Int(a.wrapping_mul(b) & mask),
+        "div" if signed => Arith::Int(sext(a).wrapping_div(sext(b)) as u128 & mask),
+        "div" => Arith::Int(a / b),
+        "rem" if signed => Arith::Int(sext(a).wrapping_rem(sext(b)) as u128 & mask),
+        "rem" => Arith::Int(a % b),
+        // the shift amount is taken modulo the width, like the backend does
+        "shl" => Arith::Int((a << (b % u128::from(bits))) & mask),
+        "lt" => Arith::Bool(ord == Ordering::Less),
+        "le" => Arith::Bool(ord != Ordering::Greater),
+        "gt" => Arith::Bool(ord == Ordering::Greater),
+        "ge" => Arith::Bool(ord != Ordering::Less),
+        "eq" => Arith::Bool(ord == Ordering::Equal),
+        "ne" => Arith::Bool(ord != Ordering::Equal),
+        _ => return Ok(None),
+    };
+
+    Ok(Some(val))
+}
+
+/// Splits a vector shape like `i32x4` into the kind of its lanes, their width in bits and their number.
+fn parse_shape(shape: &str) -> Option<(char, u32, usize)> {
+    let kind = shape.chars().next().filter(|k| *k == 'i' || *k == 'f')?;
+    let (bits, count) = shape[1..].split_once('x')?;
+
+    Some((kind, bits.parse().ok()?, count.parse().ok()?))
+}
+
+/// Evaluates the float arithmetic of the vector intrinsics on a single lane.
+/// Returns `None` if `op` is not one of them.
+fn float_arith(op: &str, bits: u32, a: u128, b: u128) -> Option<u128> {
+    macro_rules! arith {
+        ($ty:ty, $bits:ty) => {{
+            let (a, b) = (<$ty>::from_bits(a as $bits), <$ty>::from_bits(b as $bits));
+            let val = match op {
+                "add" => a + b,
+                "sub" => a - b,
+                "mul" => a * b,
+                "div" => a / b,
+                _ => return None,
+            };
+
+            Some(val.to_bits() as u128)
+        }};
+    }
+
+    match bits {
+        32 => arith!(f32, u32),
+        64 => arith!(f64, u64),
+        _ => None,
+    }
+}
+
+fn vector_lanes(vec: &Const) -> Result<Vec<Const>, Error> {
+    match vec {
+        Const::Tuple(lanes) => Ok(lanes.clone()),
+        _ => Err(Error::Invalid(format!("vector {:?}", vec))),
+    }
+}
+
+/// The lane a vector intrinsic accesses, which like in compiled code must be a constant.
+fn lane_index(op: &Operand, lanes: usize) -> Result<usize, Error> {
+    match op {
+        Operand::Const(Const::Scalar(lane, _)) => {
+            let lane = usize::try_from(*lane).unwrap_or(usize::MAX);
+
+            check_bounds(lane, lanes)?;
+            Ok(lane)
+        }
+        _ => Err(Error::Invalid(String::from("vector lanes must be accessed with a constant index"))),
+    }
+}
+
+fn pointee(op: Option<&Operand>) -> Result<Place, Error> {
+    match op {
+        Some(Operand::Place(place)) => Ok(place.clone().deref()),
+        Some(Operand::Const(_)) => Err(Error::Unsupported(String::from("memory access through a constant pointer"))),
+        None => Err(Error::Invalid(String::from("memory access without a pointer"))),
+    }
+}
@@ -1,21 +1,110 @@
+mod bigint;
+mod error;
+mod fmt;
+pub mod host;
+mod intrinsic;
+pub mod log;
+mod memory;
+pub mod meter;
+pub mod sched;
+pub mod stubs;
+
+pub use error::{format_traceback, Error, TraceFrame};
 use index_vec::IndexVec;
 use ir::*;
+use log::{AccessKind, AccessLog};
+use meter::ExecutionMeter;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::rc::Rc;
+use stubs::{Externs, NoExterns};
+
+pub fn evaluate(module: &Module, body: &Body, target: &target_lexicon::Triple) -> Result<Vec<Const>, Error> {
+    evaluate_with(module, body, target, EvalOptions::default())
+}
+
+pub fn evaluate_with(module: &Module, body: &Body, target: &target_lexicon::Triple, options: EvalOptions) -> Result<Vec<Const>, Error> {
+    let mut eval_ctx = EvalCtx::new(module, body, target).with_options(options);
 
-pub fn evaluate(module: &Module, body: &Body, target: &target_lexicon::Triple) -> Vec<Const> {
-    let mut eval_ctx = EvalCtx::new(module, body, target);
+    eval_ctx.eval()?;
+
+    Ok(eval_ctx.finish())
+}
+
+/// Limits on the stack of the interpreter and how it computes. Exceeding either limit stops evaluation with `Error::StackOverflow`.
+#[derive(Debug, Clone, Copy)]
+pub struct EvalOptions {
+    /// The combined size of the locals of all frames on the stack, as they are laid out on the target.
+    pub max_stack_bytes: u64,
+    /// The number of frames on the stack, including the body that is evaluated.
+    pub max_call_depth: usize,
+    /// Replaces every NaN a float operation produces with the canonical NaN, like compiled code does with
+    /// `CodegenOptions::canonical_nans`, so that both produce the same bits on every host.
+    pub canonical_nans: bool,
+}
 
-    eval_ctx.eval();
-    eval_ctx.finish()
+impl Default for EvalOptions {
+    fn default() -> Self {
+        EvalOptions {
+            max_stack_bytes: 8 * 1024 * 1024,
+            max_call_depth: 4096,
+            canonical_nans: false,
+        }
+    }
 }
 
+/// Evaluates a body and the functions it calls.
 #[allow(dead_code)]
 pub struct EvalCtx<'ir> {
     module: &'ir Module,
-    body: &'ir Body,
     target: &'ir target_lexicon::Triple,
-    locals: IndexVec<Local, Const>,
-    current_block: Block,
+    frames: Vec<Frame<'ir>>,
+    /// Frames that returned, kept so that later calls can reuse their memory.
+    free: Vec<Frame<'ir>>,
+    frame_sizes: HashMap<BodyId, u64>,
     status: EvalStatus,
+    externs: Rc<RefCell<dyn Externs>>,
+    /// The current values of the globals of the module, shared with the contexts evaluating their initializers.
+    /// A global is initialized the first time it is used.
+    globals: Rc<RefCell<HashMap<DeclId, Const>>>,
+    /// The object pools created by `pool_create`, indexed by their handle minus one. Destroyed pools are `None`.
+    pools: Rc<RefCell<Vec<Option<intrinsic::Pool>>>>,
+    /// The integers created by the `bigint_` intrinsics, indexed by their handle minus one. Freed integers are `None`.
+    bigints: Rc<RefCell<Vec<Option<bigint::BigInt>>>>,
+    access_log: Option<Rc<RefCell<dyn AccessLog>>>,
+    profile: Option<Rc<RefCell<profile::ProfileData>>>,
+    meter: Option<Rc<RefCell<dyn ExecutionMeter>>>,
+    options: EvalOptions,
+    traceback: Vec<TraceFrame>,
+}
+
+/// A call that has not returned yet. Calls are evaluated by pushing a frame instead of recursing,
+/// so the depth of the evaluated program does not depend on the stack of the host.
+struct Frame<'ir> {
+    body: &'ir Body,
+    locals: IndexVec<Local, Const>,
+    block: Block,
+    /// The index of the next statement in `block`.
+    stmt: usize,
+    /// Where the caller stores the return values.
+    rets: &'ir [Place],
+    /// The end of this frame on the stack. It starts at the limit of its caller, which is where the
+    /// stack ends again when it returns.
+    limit: u64,
+    storage: IndexVec<Local, Storage>,
+    /// The failure this frame is running a cleanup block for.
+    unwinding: Option<Error>,
+}
+
+/// Whether a local holds a value according to its `init` and `drop` statements.
+/// Locals that have none are always accessible, not every body is run through the lifetime analysis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Storage {
+    Unmarked,
+    Live,
+    Dead,
 }
 
 pub enum EvalStatus {
@@ -26,104 +115,579 @@ pub enum EvalStatus {
 
 impl<'ir> EvalCtx<'ir> {
     pub fn new(module: &'ir Module, body: &'ir Body, target: &'ir target_lexicon::Triple) -> Self {
+        let size = frame_size(body, target);
+        let frame = Frame::new(body, &[], 0, size);
+
         EvalCtx {
             module,
-            body,
             target,
-            locals: body.locals.iter().map(|l| Const::Undefined(l.ty.clone())).collect(),
-            current_block: body.blocks.first().unwrap().id,
+            frames: vec![frame],
+            free: Vec::new(),
+            frame_sizes: HashMap::new(),
             status: EvalStatus::Busy,
+            externs: Rc::new(RefCell::new(NoExterns)),
+            globals: Rc::new(RefCell::new(HashMap::new())),
+            pools: Rc::new(RefCell::new(Vec::new())),
+            bigints: Rc::new(RefCell::new(Vec::new())),
+            access_log: None,
+            profile: None,
+            meter: None,
+            options: EvalOptions::default(),
+            traceback: Vec::new(),
+        }
+    }
+
+    pub fn with_args(mut self, args: Vec<Const>) -> Self {
+        self.frames[0].set_args(args);
+        self
+    }
+
+    /// Uses `externs` to evaluate calls to imported functions.
+    pub fn with_externs(mut self, externs: Rc<RefCell<dyn Externs>>) -> Self {
+        self.externs = externs;
+        self
+    }
+
+    pub fn with_options(mut self, options: EvalOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Reports every read and write of memory to `log`. This includes the initializers of globals.
+    pub fn with_access_log(mut self, log: Rc<RefCell<dyn AccessLog>>) -> Self {
+        self.access_log = Some(log);
+        self
+    }
+
+    /// Counts every call and every block entered in `profile`, starting with the call of the body that is evaluated.
+    pub fn with_profile(mut self, profile: Rc<RefCell<profile::ProfileData>>) -> Self {
+        let body = self.frame().body;
+
+        profile.borrow_mut().record_call(&self.module.decls[body.decl].name, body.blocks.len());
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Charges `meter` for every statement and terminator, including those of the initializers of globals.
+    pub fn with_meter(mut self, meter: Rc<RefCell<dyn ExecutionMeter>>) -> Self {
+        self.meter = Some(meter);
+        self
+    }
+
+    pub fn finish(mut self) -> Vec<Const> {
+        let frame = self.frames.swap_remove(0);
+
+        frame.body.rets().map(|r| frame.locals[r.id].clone()).collect()
+    }
+
+    /// Evaluates the body until it returns or an abort anywhere on the stack ends it. An abort is not an error,
+    /// it leaves the return values undefined.
+    ///
+    /// An error unwinds the stack: every frame that is left runs the cleanup block of the call it is in, if that
+    /// call has one, until the cleanup resumes unwinding. The error is returned once it reaches the first frame.
+    pub fn eval(&mut self) -> Result<(), Error> {
+        self.check_stack()?;
+
+        while !self.is_finished() {
+            self.step()?;
+        }
+
+        Ok(())
+    }
+
+    /// Evaluates the next statement or terminator, failing like `eval` if it fails and is not caught by a cleanup block.
+    pub fn step(&mut self) -> Result<(), Error> {
+        let depth = self.frames.len();
+        let frame = self.frame();
+        let block = frame.body.blocks.get(frame.block).ok_or(Error::UndefinedBlock(frame.block))?;
+
+        let inst = match block.stmts.get(frame.stmt) {
+            Some(stmt) => meter::Inst::Stmt(stmt),
+            None => meter::Inst::Term(&block.term),
+        };
+
+        let at = frame.stmt;
+
+        // a refused instruction has no effect, not even on the profile, so evaluation can resume with it
+        if let Some(meter) = self.meter.as_ref().filter(|_| !matches!(inst, meter::Inst::Stmt(Stmt::Loc(_)))) {
+            if let Err(why) = meter.borrow_mut().charge(meter::InstKind::of(inst), inst) {
+                self.traceback = self.backtrace(depth, at);
+                return Err(Error::Exhausted(why));
+            }
+        }
+
+        if let (Some(profile), 0) = (&self.profile, frame.stmt) {
+            profile.borrow_mut().record_block(&self.module.decls[frame.body.decl].name, block.id);
+        }
+
+        let (result, call) = match inst {
+            meter::Inst::Stmt(stmt) => {
+                self.frame_mut().stmt += 1;
+                (self.eval_stmt(stmt), matches!(stmt, Stmt::Call(..)))
+            }
+            meter::Inst::Term(term) => {
+                if let Term::Abort = term {
+                    self.traceback = self.backtrace(depth, at);
+                }
+
+                (self.eval_term(term), false)
+            }
+        };
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                // resuming keeps the stack of the failure that is resumed
+                if !matches!(inst, meter::Inst::Term(Term::Resume)) {
+                    self.traceback = self.backtrace(depth, at);
+                }
+
+                self.unwind(e, depth, call)
+            }
+        }
+    }
+
+    /// The stack when the last failure or abort happened, innermost frame first, with the source locations of the
+    /// frames that have them. This includes failures caught by a cleanup block, so after `eval` or `step` fails or
+    /// an abort anywhere on the stack ends evaluation it is the stack of that failure or abort.
+    /// Empty if nothing failed or aborted yet. `format_traceback` formats it for people.
+    pub fn traceback(&self) -> &[TraceFrame] {
+        &self.traceback
+    }
+
+    /// The first `depth` frames of the stack, of which the innermost is at statement `stmt`.
+    fn backtrace(&self, depth: usize, stmt: usize) -> Vec<TraceFrame> {
+        self.frames[..depth]
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(i, frame)| {
+                // the callers are stopped right after their call
+                let stmt = if i == 0 { stmt } else { frame.stmt.saturating_sub(1) };
+                let loc = Location {
+                    body: frame.body.id,
+                    block: frame.block,
+                    stmt,
+                };
+
+                TraceFrame {
+                    func: self.module.decls[frame.body.decl].name.clone(),
+                    loc,
+                    source: frame.body.source_loc(loc).cloned(),
+                }
+            })
+            .collect()
+    }
+
+    /// Whether the body returned or aborted.
+    pub fn is_finished(&self) -> bool {
+        !matches!(self.status, EvalStatus::Busy)
+    }
+
+    /// Whether the body or any function it called aborted, in which case `traceback` is the stack at the abort.
+    pub fn aborted(&self) -> bool {
+        matches!(self.status, EvalStatus::Error)
+    }
+
+    /// The globals that have been used so far and their current values, ordered by their declaration.
+    pub fn globals(&self) -> Vec<(DeclId, Const)> {
+        let mut globals = self.globals.borrow().iter().map(|(decl, val)| (*decl, val.clone())).collect::<Vec<_>>();
+
+        globals.sort_by_key(|(decl, _)| *decl);
+        globals
+    }
+
+    /// Shares the globals, pools and integers of `other`, so that both contexts evaluate threads of one program.
+    /// Pointers are values in the interpreter, so the globals are the only memory the threads can share.
+    pub fn with_shared_memory(mut self, other: &EvalCtx<'ir>) -> Self {
+        self.globals = other.globals.clone();
+        self.pools = other.pools.clone();
+        self.bigints = other.bigints.clone();
+        self
+    }
+
+    /// Unwinds from the frame at `depth`, which failed with `err` in a call if `call` is set, to the first
+    /// cleanup block. Fails with `err` if there is none, or if a frame fails while it is already unwinding.
+    fn unwind(&mut self, err: Error, depth: usize, mut call: bool) -> Result<(), Error> {
+        // frames pushed by the failed statement did not start running
+        while self.frames.len() > depth {
+            let frame = self.frames.pop().unwrap();
+
+            self.free.push(frame);
+        }
+
+        loop {
+            if self.frame().unwinding.is_some() {
+                return Err(err);
+            }
+
+            if let Some(cleanup) = self.frame().cleanup().filter(|_| call) {
+                let frame = self.frame_mut();
+
+                frame.unwinding = Some(err);
+                frame.jump(cleanup);
+                return Ok(());
+            }
+
+            if self.frames.len() == 1 {
+                return Err(err);
+            }
+
+            let frame = self.frames.pop().unwrap();
+
+            self.free.push(frame);
+            call = true;
         }
     }
 
-    pub fn finish(self) -> Vec<Const> {
-        self.body.rets().map(|r| self.locals[r.id].clone()).collect()
+    fn frame(&self) -> &Frame<'ir> {
+        self.frames.last().unwrap()
+    }
+
+    fn frame_mut(&mut self) -> &mut Frame<'ir> {
+        self.frames.last_mut().unwrap()
     }
 
-    pub fn eval(&mut self) {
-        while let EvalStatus::Busy = self.status {
-            let block = &self.body.blocks[self.current_block];
+    pub(crate) fn check_stack(&self) -> Result<(), Error> {
+        let bytes = self.frame().limit;
+
+        if self.frames.len() > self.options.max_call_depth || bytes > self.options.max_stack_bytes {
+            Err(Error::StackOverflow {
+                depth: self.frames.len(),
+                bytes,
+            })
+        } else {
+            Ok(())
+        }
+    }
 
-            for stmt in &block.stmts {
-                self.eval_stmt(stmt);
+    fn push_frame(&mut self, body: &'ir Body, args: Vec<Const>, rets: &'ir [Place]) -> Result<(), Error> {
+        let target = self.target;
+        let base = self.frame().limit;
+        let size = *self.frame_sizes.entry(body.id).or_insert_with(|| frame_size(body, target));
+        let mut frame = match self.free.pop() {
+            Some(mut frame) => {
+                frame.reset(body, rets, base, size);
+                frame
             }
+            None => Frame::new(body, rets, base, size),
+        };
+
+        frame.set_args(args);
+
+        if let Some(profile) = &self.profile {
+            profile.borrow_mut().record_call(&self.module.decls[body.decl].name, body.blocks.len());
+        }
+
+        self.frames.push(frame);
+        self.check_stack()
+    }
+
+    /// Leaves the current frame, storing its return values in the caller.
+    /// Leaving the last frame finishes evaluation with `status`.
+    fn pop_frame(&mut self, status: EvalStatus) -> Result<(), Error> {
+        if self.frames.len() == 1 {
+            self.status = status;
+            return Ok(());
+        }
+
+        // the whole frame is released, the caller's frame ends where it starts
+        let mut frame = self.frames.pop().unwrap();
 
-            self.eval_term(&block.term);
+        for (ret, r) in frame.rets.iter().zip(frame.body.rets()) {
+            let val = std::mem::replace(&mut frame.locals[r.id], Const::Undefined(r.ty.clone()));
+
+            self.store(ret, val)?;
         }
+
+        self.free.push(frame);
+
+        Ok(())
+    }
+
+    /// Creates the context for evaluating a global from this one. It shares the externs, the globals,
+    /// the pools, the integers, the access log and the meter, and the stack used by this context counts towards its limits.
+    fn nested(&self, body: &'ir Body) -> Self {
+        let options = EvalOptions {
+            max_stack_bytes: self.options.max_stack_bytes.saturating_sub(self.frame().limit),
+            max_call_depth: self.options.max_call_depth.saturating_sub(self.frames.len()),
+            ..self.options
+        };
+        let mut ctx = EvalCtx::new(self.module, body, self.target).with_externs(self.externs.clone()).with_options(options);
+
+        ctx.globals = self.globals.clone();
+        ctx.pools = self.pools.clone();
+        ctx.bigints = self.bigints.clone();
+        ctx.access_log = self.access_log.clone();
+        ctx.meter = self.meter.clone();
+        ctx
     }
 
-    fn eval_stmt(&mut self, stmt: &'ir Stmt) {
+    fn eval_stmt(&mut self, stmt: &'ir Stmt) -> Result<(), Error> {
         match stmt {
-            Stmt::Init(_) => {}
-            Stmt::Drop(_) => {}
+            Stmt::Init(local) => {
+                let frame = self.frame_mut();
+                let body = frame.body;
+                let ty = &body.locals.get(*local).ok_or(Error::UndefinedLocal(*local))?.ty;
+
+                if frame.storage[*local] == Storage::Live {
+                    return Err(Error::Invalid(format!("init of _{}, which is already live", local.index())));
+                }
+
+                // boxes are allocated when they are initialized
+                frame.locals[*local] = match &ty.kind {
+                    Type::Box(to) => Const::Ptr(Box::new(Const::Undefined((**to).clone()))),
+                    _ => Const::Undefined(ty.clone()),
+                };
+
+                frame.storage[*local] = Storage::Live;
+            }
+            Stmt::Drop(local) => {
+                let frame = self.frame_mut();
+                let body = frame.body;
+                let ty = &body.locals.get(*local).ok_or(Error::UndefinedLocal(*local))?.ty;
+
+                if frame.storage[*local] != Storage::Live {
+                    return Err(Error::Invalid(format!("drop of _{}, which is not live", local.index())));
+                }
+
+                frame.locals[*local] = Const::Undefined(ty.clone());
+                frame.storage[*local] = Storage::Dead;
+            }
             Stmt::Assign(place, rvalue) => {
-                let val = self.eval_rvalue(rvalue);
+                let val = self.eval_rvalue(place, rvalue)?;
+
+                self.store(place, val)?;
+            }
+            Stmt::SetDiscr(place, val) => {
+                let idx = *val as usize;
+
+                if let Const::Variant(i, _, _) = self.read(place)? {
+                    if i == idx {
+                        return Ok(());
+                    }
+                }
+
+                let ty = place_type(self.frame().body, place);
+
+                self.store(place, init_variant(ty, idx, self.target))?;
+            }
+            Stmt::Loc(_) => {}
+            Stmt::Call(rets, func, args, _) => {
+                let decl = match self.eval_op(func)? {
+                    Const::Addr(decl) => self.module.decls.get(decl).ok_or_else(|| Error::UnknownSymbol(format!("{:?}", decl)))?,
+                    _ => return Err(Error::Unsupported(String::from("indirect calls"))),
+                };
+
+                let args = args.iter().map(|a| self.eval_op(a)).collect::<Result<Vec<_>, _>>()?;
+
+                if let Some(body) = self.module.bodies.iter().find(|b| b.decl == decl.id) {
+                    return self.push_frame(body, args, rets);
+                }
 
-                self.store(place, val);
+                // externs only see a copy of the globals passed to them
+                let args = args
+                    .into_iter()
+                    .map(|arg| match arg {
+                        Const::Addr(decl) if !self.is_func(decl) => Ok(Const::Ptr(Box::new(self.global(decl)?))),
+                        arg => Ok(arg),
+                    })
+                    .collect::<Result<Vec<_>, Error>>()?;
+
+                let vals = self.externs.borrow_mut().call(self.module, decl, &args, self.target)?;
+
+                for (ret, val) in rets.iter().zip(vals) {
+                    self.store(ret, val)?;
+                }
             }
-            Stmt::SetDiscr(..) => unimplemented!(),
-            Stmt::Call(..) => unimplemented!(),
         }
+
+        Ok(())
     }
 
-    fn eval_term(&mut self, term: &'ir Term) {
+    fn eval_term(&mut self, term: &'ir Term) -> Result<(), Error> {
         match term {
             Term::Abort => {
-                for r in self.body.rets() {
-                    self.locals[r.id] = Const::Undefined(r.ty.clone());
+                // an abort ends evaluation at any depth, like the trap compiled code aborts with
+                while self.frames.len() > 1 {
+                    let frame = self.frames.pop().unwrap();
+
+                    self.free.push(frame);
+                }
+
+                let frame = self.frame_mut();
+
+                for r in frame.body.rets() {
+                    frame.locals[r.id] = Const::Undefined(r.ty.clone());
                 }
 
                 self.status = EvalStatus::Error;
+                return Ok(());
             }
             Term::Return => {
-                self.status = EvalStatus::Done;
+                if self.frame().unwinding.is_some() {
+                    return Err(Error::Invalid(String::from("return from a cleanup block")));
+                }
+
+                return self.pop_frame(EvalStatus::Done);
+            }
+            Term::Resume => {
+                let err = self
+                    .frame_mut()
+                    .unwinding
+                    .take()
+                    .ok_or_else(|| Error::Invalid(String::from("resume outside of a cleanup block")))?;
+
+                return self.unwind(err, self.frames.len(), false);
             }
             Term::Jump(block) => {
-                self.current_block = *block;
+                self.frame_mut().jump(*block);
             }
             Term::Switch(op, vals, blocks) => {
-                let op = self.eval_op(op);
+                let op = self.eval_op(op)?;
 
-                if let Const::Scalar(s, _) = op {
-                    let mut block = *blocks.last().unwrap();
+                if let Const::Scalar(s, ty) = op {
+                    let size = layout::layout_of(&ty, self.target).size;
+                    let case = vals.iter().position(|val| size.truncate(*val) == size.truncate(s));
+                    let otherwise = blocks.last().ok_or_else(|| Error::Invalid(String::from("switch without blocks")))?;
 
-                    for (val, b) in vals.iter().zip(blocks) {
-                        if *val == s {
-                            block = *b;
-                        }
-                    }
-
-                    self.current_block = block;
+                    self.frame_mut().jump(case.and_then(|i| blocks.get(i)).unwrap_or(otherwise).clone());
                 } else {
-                    unreachable!();
+                    return Err(Error::Invalid(format!("switch on {:?}", op)));
                 }
             }
+            Term::BrIf(op, then, else_) => match self.eval_op(op)? {
+                Const::Scalar(s, ty) if ty.without_newtypes().kind == Type::Bool => self.frame_mut().jump(if s != 0 { *then } else { *else_ }),
+                Const::Scalar(_, ty) => return Err(Error::Invalid(format!("branch on a {}, which is not a bool", display_ty(&ty)))),
+                op => return Err(Error::Invalid(format!("branch on {:?}", op))),
+            },
         }
+
+        Ok(())
     }
 
-    fn eval_rvalue(&mut self, rvalue: &'ir RValue) -> Const {
+    /// Evaluates the value assigned to `place`, which gives the type of results that have none of their own.
+    fn eval_rvalue(&mut self, place: &Place, rvalue: &'ir RValue) -> Result<Const, Error> {
         match rvalue {
             RValue::Use(op) => self.eval_op(op),
             RValue::AddrOf(place) => {
-                let val = self.load(place);
+                // taking an address does not access memory, even though the interpreter copies the value
+                let val = self.read(place)?;
+
+                Ok(Const::Ptr(Box::new(val)))
+            }
+            RValue::Lea { .. } => Err(Error::Unsupported(String::from("lea"))),
+            RValue::UnOp(op, val) => match self.eval_op(val)? {
+                Const::Scalar(s, ty) => {
+                    let bits = layout::layout_of(&ty, self.target).size.bits();
+                    let mask = if bits >= 128 { u128::MAX } else { (1 << bits) - 1 };
+                    let s = match op {
+                        UnOp::Neg => match ty.without_newtypes().kind {
+                            Type::F32 | Type::F64 => s ^ (1 << (bits - 1)),
+                            _ => s.wrapping_neg() & mask,
+                        },
+                        UnOp::Not => (s & mask == 0) as u128,
+                        // a bool only has one bit to flip
+                        UnOp::BitNot if ty.without_newtypes().kind == Type::Bool => s ^ 1,
+                        UnOp::BitNot => !s & mask,
+                    };
+
+                    Ok(Const::Scalar(self.canonicalize_nan(s, &ty), ty))
+                }
+                val => Err(Error::Invalid(format!("{:?} of {:?}", op, val))),
+            },
+            RValue::GetDiscr(place) => match self.load(place)? {
+                Const::Variant(idx, _, ty) => Ok(Const::Scalar(idx as u128, Ty::new(Type::Discr(Box::new(ty))))),
+                val => Err(Error::Invalid(format!("discriminant of {:?}", val))),
+            },
+            RValue::Cast(place, ty) => match self.load(place)? {
+                // only conversions between floats can produce a NaN, casts between the same types reinterpret it
+                Const::Scalar(s, from) => match (&from.without_newtypes().kind, &ty.without_newtypes().kind) {
+                    (Type::F32, Type::F64) | (Type::F64, Type::F32) => {
+                        let s = cast_scalar(s, &from, ty, self.target);
+
+                        Ok(Const::Scalar(self.canonicalize_nan(s, ty), ty.clone()))
+                    }
+                    _ => Ok(Const::Scalar(cast_scalar(s, &from, ty, self.target), ty.clone())),
+                },
+                // other values keep their representation, like a box cast to a pointer
+                val => Ok(val),
+            },
+            RValue::Intrinsic(name, args) => self.eval_intrinsic(place, name, args),
+            RValue::NullOp(op, ty) => {
+                let layout = layout::layout_of(ty, self.target);
+                let (name, val) = match op {
+                    NullOp::SizeOf => ("size", layout.size.bytes()),
+                    NullOp::AlignOf => ("alignment", layout.align.bytes()),
+                };
+
+                // generic types only have a size at runtime
+                if layout.is_unsized() {
+                    return Err(Error::Unsupported(format!("the {} of the unsized type {}", name, display_ty(ty))));
+                }
 
-                Const::Ptr(Box::new(val))
+                Ok(Const::Scalar(val as u128, place_type(self.frame().body, place)))
             }
-            RValue::GetDiscr(..) => unimplemented!(),
-            RValue::Cast(place, _) => self.load(place),
-            RValue::Intrinsic(..) => unimplemented!(),
         }
     }
 
-    fn eval_op(&mut self, op: &'ir Operand) -> Const {
+    fn eval_op(&mut self, op: &'ir Operand) -> Result<Const, Error> {
         match op {
             Operand::Place(p) => self.load(p),
-            Operand::Const(c) => c.clone(),
+            Operand::Const(c) => Ok(c.clone()),
+        }
+    }
+
+    fn is_func(&self, decl: DeclId) -> bool {
+        matches!(self.module.decls.get(decl), Some(Decl { ty: Ty { kind: Type::Func(_), .. }, .. }))
+    }
+
+    /// The current value of a global, initializing it if this is its first use.
+    fn global(&self, decl: DeclId) -> Result<Const, Error> {
+        self.init_global(decl)?;
+
+        Ok(self.globals.borrow()[&decl].clone())
+    }
+
+    fn init_global(&self, decl: DeclId) -> Result<(), Error> {
+        if self.globals.borrow().contains_key(&decl) {
+            return Ok(());
         }
+
+        let name = match self.module.decls.get(decl) {
+            Some(Decl { ty: Ty { kind: Type::Func(_), .. }, name, .. }) => return Err(Error::Invalid(format!("dereference of function {}", name))),
+            Some(d) => &d.name,
+            None => return Err(Error::UnknownSymbol(format!("{:?}", decl))),
+        };
+
+        let body = self.module.bodies.iter().find(|b| b.decl == decl).ok_or_else(|| Error::UnknownSymbol(name.clone()))?;
+        let mut ctx = self.nested(body);
+
+        ctx.eval()?;
+
+        if ctx.aborted() {
+            return Err(Error::Invalid(format!("the initializer of global {} aborted", name)));
+        }
+
+        let val = ctx.finish().pop().ok_or_else(|| Error::Invalid(format!("global {} without a value", name)))?;
+
+        // evaluating the initializer may have initialized this global already
+        self.globals.borrow_mut().entry(decl).or_insert(val);
+
+        Ok(())
     }
 
-    fn load(&self, place: &'ir Place) -> Const {
-        let mut val = self.locals[place.local].clone();
+    fn load(&self, place: &Place) -> Result<Const, Error> {
+        let place = self.resolve_indices(place)?;
+
+        self.log_access(AccessKind::Read, &place)?;
+        self.read(&place)
+    }
+
+    /// Loads the value at `place` without logging the access. Only the indices in `place` are loaded with `load`.
+    fn read(&self, place: &Place) -> Result<Const, Error> {
+        let place = self.resolve_indices(place)?;
+        let mut val = self.frame().local(place.local)?.clone();
 
         for elem in &place.elems {
             match elem {
@@ -131,69 +695,332 @@ impl<'ir> EvalCtx<'ir> {
                     Const::Ptr(to) => {
                         val = *to;
                     }
-                    _ => unreachable!(),
+                    Const::Addr(decl) => {
+                        val = self.global(decl)?;
+                    }
+                    _ => return Err(Error::Invalid(format!("dereference of {:?}", val))),
                 },
                 PlaceElem::Field(idx) => match val {
-                    Const::Tuple(mut cs) => {
+                    Const::Tuple(mut cs) | Const::Variant(_, mut cs, _) => {
+                        check_bounds(*idx, cs.len())?;
                         val = cs.swap_remove(*idx);
                     }
-                    _ => unreachable!(),
+                    Const::Bytes(b) => {
+                        check_bounds(*idx, b.len())?;
+                        val = Const::Scalar(b[*idx] as u128, Ty::new(Type::U8));
+                    }
+                    _ => return Err(Error::Invalid(format!("field {} of {:?}", idx, val))),
+                },
+                PlaceElem::Index(_) => unreachable!(),
+                PlaceElem::Downcast(v) => match val {
+                    Const::Variant(idx, ..) if idx == *v => {}
+                    _ => return Err(Error::Invalid(format!("downcast of {:?} to variant {}", val, v))),
                 },
-                PlaceElem::Index(_idx) => unimplemented!(),
-                PlaceElem::Downcast(_) => unimplemented!(),
             }
         }
 
-        val
+        Ok(val)
+    }
+
+    fn store(&mut self, place: &Place, val: Const) -> Result<(), Error> {
+        let place = self.resolve_indices(place)?.into_owned();
+
+        self.log_access(AccessKind::Write, &place)?;
+
+        let target = self.target;
+        let root = self.frame_mut().local_mut(place.local)?;
+        let mut rest = store_in(root, &place.elems, val, target)?;
+
+        while let Some((decl, elems, val)) = rest {
+            self.init_global(decl)?;
+            rest = store_in(self.globals.borrow_mut().get_mut(&decl).unwrap(), elems, val, target)?;
+        }
+
+        Ok(())
     }
 
-    fn store(&mut self, place: &'ir Place, val: Const) {
-        let mut ptr = &mut self.locals[place.local];
+    /// Replaces the indices in `place` with the fields they select. Arrays are tuples of which every field
+    /// has the same type, so an index selects a field and is bounds checked like one.
+    fn resolve_indices<'a>(&self, place: &'a Place) -> Result<Cow<'a, Place>, Error> {
+        if !place.elems.iter().any(|e| matches!(e, PlaceElem::Index(_))) {
+            return Ok(Cow::Borrowed(place));
+        }
+
+        let mut elems = Vec::with_capacity(place.elems.len());
 
         for elem in &place.elems {
-            match elem {
-                PlaceElem::Deref => match ptr {
-                    Const::Ptr(to) => {
-                        ptr = &mut **to;
+            let elem = match elem {
+                PlaceElem::Index(op) => {
+                    let idx = match op {
+                        Operand::Place(p) => self.load(p)?,
+                        Operand::Const(c) => c.clone(),
+                    };
+
+                    match idx {
+                        Const::Scalar(idx, _) => PlaceElem::Field(usize::try_from(idx).unwrap_or(usize::MAX)),
+                        idx => return Err(Error::Invalid(format!("index {:?}", idx))),
                     }
-                    _ => unreachable!(),
-                },
-                PlaceElem::Field(idx) => match ptr {
-                    Const::Tuple(cs) => {
-                        ptr = &mut cs[*idx];
+                }
+                elem => elem.clone(),
+            };
+
+            elems.push(elem);
+        }
+
+        Ok(Cow::Owned(Place { local: place.local, elems }))
+    }
+}
+
+/// Stores `val` in the part of `ptr` that `elems` point to. If they dereference a pointer to a global,
+/// the store continues in that global and the global, the remaining elems and the value are returned instead.
+fn store_in<'a>(
+    mut ptr: &mut Const,
+    elems: &'a [PlaceElem],
+    val: Const,
+    target: &target_lexicon::Triple,
+) -> Result<Option<(DeclId, &'a [PlaceElem], Const)>, Error> {
+    for (i, elem) in elems.iter().enumerate() {
+        match elem {
+            PlaceElem::Deref => match ptr {
+                Const::Ptr(to) => {
+                    ptr = &mut **to;
+                }
+                Const::Addr(decl) => return Ok(Some((*decl, &elems[i + 1..], val))),
+                _ => return Err(Error::Invalid(format!("dereference of {:?}", ptr))),
+            },
+            PlaceElem::Field(idx) => {
+                match ptr {
+                    Const::Bytes(b) => {
+                        *ptr = Const::Tuple(b.iter().map(|b| Const::Scalar(*b as u128, Ty::new(Type::U8))).collect());
                     }
                     Const::Undefined(ty) => {
-                        *ptr = init_undefined(ty.clone(), self.target);
+                        *ptr = init_undefined(ty.clone(), target);
+                    }
+                    Const::Tuple(_) | Const::Variant(..) => {}
+                    _ => return Err(Error::Invalid(format!("field {} of {:?}", idx, ptr))),
+                }
 
-                        if let Const::Tuple(cs) = ptr {
-                            ptr = &mut cs[*idx];
-                        }
+                ptr = match ptr {
+                    Const::Tuple(cs) | Const::Variant(_, cs, _) => {
+                        check_bounds(*idx, cs.len())?;
+                        &mut cs[*idx]
                     }
-                    _ => unreachable!(),
-                },
-                PlaceElem::Index(_idx) => unimplemented!(),
-                PlaceElem::Downcast(_) => unimplemented!(),
+                    _ => return Err(Error::Invalid(format!("field {} of {:?}", idx, ptr))),
+                };
+            }
+            PlaceElem::Index(_) => unreachable!(),
+            PlaceElem::Downcast(v) => match ptr {
+                Const::Variant(idx, ..) if idx == v => {}
+                Const::Variant(_, _, ty) | Const::Undefined(ty) => {
+                    *ptr = init_variant(ty.clone(), *v, target);
+                }
+                _ => return Err(Error::Invalid(format!("downcast of {:?} to variant {}", ptr, v))),
+            },
+        }
+    }
+
+    match (ptr, val) {
+        // a whole variant is stored as a tuple of its fields
+        (Const::Variant(_, fields, _), Const::Tuple(cs)) if matches!(elems.last(), Some(PlaceElem::Downcast(_))) => {
+            *fields = cs;
+        }
+        (ptr, val) => *ptr = val,
+    }
+
+    Ok(None)
+}
+
+impl<'ir> Frame<'ir> {
+    fn new(body: &'ir Body, rets: &'ir [Place], base: u64, size: u64) -> Self {
+        let mut frame = Frame {
+            body,
+            locals: IndexVec::new(),
+            block: Block::new(0),
+            stmt: 0,
+            rets,
+            limit: base,
+            storage: IndexVec::new(),
+            unwinding: None,
+        };
+
+        frame.reset(body, rets, base, size);
+        frame
+    }
+
+    /// Prepares the frame for a call to `body`, keeping the memory of its locals.
+    fn reset(&mut self, body: &'ir Body, rets: &'ir [Place], base: u64, size: u64) {
+        self.body = body;
+        self.locals.clear();
+        self.locals.extend(body.locals.iter().map(|l| Const::Undefined(l.ty.clone())));
+        self.block = body.blocks.first().map(|b| b.id).unwrap_or_else(|| Block::new(0));
+        self.stmt = 0;
+        self.rets = rets;
+        self.limit = base + size;
+        self.storage.clear();
+        self.storage.extend(body.locals.iter().map(|_| Storage::Unmarked));
+        self.unwinding = None;
+    }
+
+    fn set_args(&mut self, args: Vec<Const>) {
+        let params = self.body.args().map(|a| a.id).collect::<Vec<_>>();
+
+        for (param, arg) in params.into_iter().zip(args) {
+            self.locals[param] = arg;
+        }
+    }
+
+    fn jump(&mut self, block: Block) {
+        self.block = block;
+        self.stmt = 0;
+    }
+
+    /// The cleanup block of the call the frame is stopped after, which is the call that made the frame above it.
+    fn cleanup(&self) -> Option<Block> {
+        let stmt = self.stmt.checked_sub(1)?;
+
+        match self.body.blocks.get(self.block)?.stmts.get(stmt)? {
+            Stmt::Call(_, _, _, cleanup) => *cleanup,
+            _ => None,
+        }
+    }
+
+    fn local(&self, local: Local) -> Result<&Const, Error> {
+        match self.storage.get(local) {
+            Some(Storage::Dead) => Err(Error::DeadLocal(local)),
+            _ => self.locals.get(local).ok_or(Error::UndefinedLocal(local)),
+        }
+    }
+
+    fn local_mut(&mut self, local: Local) -> Result<&mut Const, Error> {
+        match self.storage.get(local) {
+            Some(Storage::Dead) => Err(Error::DeadLocal(local)),
+            _ => self.locals.get_mut(local).ok_or(Error::UndefinedLocal(local)),
+        }
+    }
+}
+
+impl<'ir> EvalCtx<'ir> {
+    /// Replaces `s`, a scalar of type `ty`, with the canonical NaN if it is a NaN and `EvalOptions::canonical_nans` is set.
+    fn canonicalize_nan(&self, s: u128, ty: &Ty) -> u128 {
+        let nan = match ty.without_newtypes().kind {
+            Type::F32 if f32::from_bits(s as u32).is_nan() => layout::Primitive::F32.canonical_nan(),
+            Type::F64 if f64::from_bits(s as u64).is_nan() => layout::Primitive::F64.canonical_nan(),
+            _ => None,
+        };
+
+        match nan {
+            Some(nan) if self.options.canonical_nans => nan,
+            _ => s,
+        }
+    }
+}
+
+/// Converts a scalar the same way compiled code does: integers and pointers are sign extended if the source
+/// is signed, zero extended otherwise and truncated to the target size. Floats are converted to integers
+/// with saturation, with NaN becoming zero. Scalars of the same primitive are reinterpreted.
+fn cast_scalar(s: u128, from: &Ty, to: &Ty, target: &target_lexicon::Triple) -> u128 {
+    use layout::{Abi, Primitive};
+
+    if to.without_newtypes().kind == Type::Bool && from.without_newtypes().kind != Type::Bool {
+        return !is_zero(s, from, target) as u128;
+    }
+
+    let from = layout::layout_of(from, target);
+    let to = layout::layout_of(to, target);
+    let (a, b) = match (&from.abi, &to.abi) {
+        (Abi::Scalar(a), Abi::Scalar(b)) if a.value != b.value => (a.value, b.value),
+        _ => return s,
+    };
+
+    let s = from.size.truncate(s);
+    let signed = |p| matches!(p, Primitive::Int(_, true));
+    let bits = to.size.bits();
+    let sign_extended = {
+        let shift = 128 - from.size.bits();
+
+        ((s << shift) as i128 >> shift) as u128
+    };
+
+    match (a, b) {
+        (Primitive::F32, Primitive::F64) => (f32::from_bits(s as u32) as f64).to_bits() as u128,
+        (Primitive::F64, Primitive::F32) => (f64::from_bits(s as u64) as f32).to_bits() as u128,
+        (Primitive::F32, _) | (Primitive::F64, _) => {
+            let f = if a == Primitive::F32 { f32::from_bits(s as u32) as f64 } else { f64::from_bits(s as u64) };
+
+            // `as` saturates and turns NaN into zero, the target range only needs to be clamped further
+            if signed(b) {
+                let max = (u128::MAX >> (129 - bits)) as i128;
+
+                to.size.truncate((f as i128).max(-max - 1).min(max) as u128)
+            } else {
+                (f as u128).min(u128::MAX >> (128 - bits))
             }
         }
+        (_, Primitive::F32) if signed(a) => (sign_extended as i128 as f32).to_bits() as u128,
+        (_, Primitive::F32) => (s as f32).to_bits() as u128,
+        (_, Primitive::F64) if signed(a) => (sign_extended as i128 as f64).to_bits() as u128,
+        (_, Primitive::F64) => (s as f64).to_bits() as u128,
+        _ if signed(a) => to.size.truncate(sign_extended),
+        _ => to.size.truncate(s),
+    }
+}
+
+/// Whether `s` is zero as a `ty`, which for floats includes negative zero but not NaN.
+fn is_zero(s: u128, ty: &Ty, target: &target_lexicon::Triple) -> bool {
+    match ty.without_newtypes().kind {
+        Type::F32 => f32::from_bits(s as u32) == 0.0,
+        Type::F64 => f64::from_bits(s as u64) == 0.0,
+        _ => layout::layout_of(ty, target).size.truncate(s) == 0,
+    }
+}
+
+/// The combined size of the locals of `body` as they are laid out on the target.
+fn frame_size(body: &Body, target: &target_lexicon::Triple) -> u64 {
+    body.locals.iter().map(|l| layout::layout_of(&l.ty, target).size.bytes()).sum()
+}
 
-        *ptr = val;
+fn check_bounds(idx: usize, len: usize) -> Result<(), Error> {
+    if idx < len {
+        Ok(())
+    } else {
+        Err(Error::OutOfBounds {
+            offset: idx as u128,
+            size: len as u128,
+        })
     }
 }
 
+fn init_variant(ty: Ty, idx: usize, target: &target_lexicon::Triple) -> Const {
+    let fields = match &ty.access().kind {
+        Type::Tagged(tys) => match tys.get(idx).map(|t| init_undefined(t.access(), target)) {
+            Some(Const::Tuple(cs)) => cs,
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    };
+
+    Const::Variant(idx, fields, ty)
+}
+
 fn init_undefined(ty: Ty, target: &target_lexicon::Triple) -> Const {
     match ty.kind {
         Type::Tuple(tys) => Const::Tuple(tys.into_iter().map(|t| Const::Undefined(t)).collect()),
+        Type::Vector(elem, lanes) => Const::Tuple(vec![Const::Undefined(*elem); lanes as usize]),
         Type::Type(t) => Const::Tuple(vec![
             Const::Undefined(layout::ptr_sized_int(target)),
             Const::Undefined(layout::ptr_sized_int(target)),
             Const::Undefined(layout::ptr_sized_int(target)),
             Const::Undefined(ir::Ty::new(Type::Ptr(Box::new(ir::Ty::new(Type::Vwt(t)))))),
+            Const::Undefined(layout::ptr_sized_int(target)),
+            Const::Undefined(ir::Ty::new(Type::Ptr(Box::new(ir::Ty::new(Type::U8))))),
         ]),
         Type::Vwt(t) => Const::Tuple(vec![
             Const::Undefined(layout::copy_fn_type(&t)),
             Const::Undefined(layout::copy_fn_type(&t)),
             Const::Undefined(layout::drop_fn_type(&t)),
         ]),
+        // a newtype is represented like the type it wraps
+        Type::Newtype(_, inner) => init_undefined(*inner, target),
         _ => Const::Undefined(ty),
     }
 }
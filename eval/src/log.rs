@@ -0,0 +1,144 @@
+use crate::{check_bounds, Error, EvalCtx};
+use ir::layout::{layout_of, TyLayout};
+use ir::*;
+use std::cell::RefCell;
+
+/// Receives every read and write of memory during evaluation, including the reads of the pointers
+/// that are dereferenced on the way.
+pub trait AccessLog {
+    fn access(&mut self, access: Access);
+}
+
+impl AccessLog for Vec<Access> {
+    fn access(&mut self, access: Access) {
+        self.push(access);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Access {
+    pub kind: AccessKind,
+    pub owner: Owner,
+    /// The first accessed byte, relative to the start of the owner.
+    pub offset: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// The memory an access falls in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Owner {
+    /// A local of the function `func`.
+    Local { func: String, local: Local },
+    Global(String),
+    /// Memory behind a pointer that does not point to a global, like a box or the address of a local.
+    /// The interpreter copies these, so it does not know which local or allocation they belong to.
+    Pointee,
+}
+
+impl std::fmt::Display for Access {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let kind = match self.kind {
+            AccessKind::Read => "read",
+            AccessKind::Write => "write",
+        };
+
+        match &self.owner {
+            Owner::Local { func, local } => write!(f, "{} {}::_{}", kind, func, local.index())?,
+            Owner::Global(name) => write!(f, "{} @{}", kind, name)?,
+            Owner::Pointee => write!(f, "{} *", kind)?,
+        }
+
+        write!(f, "[{}..{}]", self.offset, self.offset + self.size)
+    }
+}
+
+impl<'ir> EvalCtx<'ir> {
+    /// Logs an access of `place`, resolving it to the bytes it covers using the layouts of the types on the way.
+    pub(crate) fn log_access(&self, kind: AccessKind, place: &Place) -> Result<(), Error> {
+        let log = match &self.access_log {
+            Some(log) => log,
+            None => return Ok(()),
+        };
+
+        let body = self.frame().body;
+        let mut owner = Owner::Local {
+            func: self.module.decls[body.decl].name.clone(),
+            local: place.local,
+        };
+
+        let local = body.locals.get(place.local).ok_or(Error::UndefinedLocal(place.local))?;
+        let mut layout = layout_of(&local.ty, self.target);
+        let mut offset = 0;
+        let mut ptr = Place::new(place.local);
+
+        for elem in &place.elems {
+            if let Type::Box(_) = layout.ty.kind {
+                if !matches!(elem, PlaceElem::Deref) {
+                    let (o, l) = self.log_deref(log, &ptr, owner, offset, &layout)?;
+
+                    owner = o;
+                    layout = l;
+                    offset = 0;
+                    ptr = ptr.deref();
+                }
+            }
+
+            match elem {
+                PlaceElem::Deref => {
+                    let (o, l) = self.log_deref(log, &ptr, owner, offset, &layout)?;
+
+                    owner = o;
+                    layout = l;
+                    offset = 0;
+                }
+                PlaceElem::Field(idx) => {
+                    check_bounds(*idx, layout.fields.count())?;
+                    offset += layout.fields.offset(*idx).bytes();
+                    layout = layout.field(*idx, self.target);
+                }
+                // indices are replaced with fields before places are accessed
+                PlaceElem::Index(_) => unreachable!(),
+                PlaceElem::Downcast(v) => {
+                    layout = layout.variant(*v);
+                }
+            }
+
+            ptr.elems.push(elem.clone());
+        }
+
+        log.borrow_mut().access(Access {
+            kind,
+            owner,
+            offset,
+            size: layout.size.bytes(),
+        });
+
+        Ok(())
+    }
+
+    /// Logs the read of the pointer at `ptr` and returns what it points to.
+    fn log_deref(&self, log: &RefCell<dyn AccessLog>, ptr: &Place, owner: Owner, offset: u64, layout: &TyLayout) -> Result<(Owner, TyLayout), Error> {
+        log.borrow_mut().access(Access {
+            kind: AccessKind::Read,
+            owner,
+            offset,
+            size: layout.size.bytes(),
+        });
+
+        match (self.read(ptr)?, &layout.ty.kind) {
+            (Const::Addr(decl), _) => {
+                let decl = &self.module.decls[decl];
+
+                Ok((Owner::Global(decl.name.clone()), layout_of(&decl.ty, self.target)))
+            }
+            (_, Type::Ptr(_)) | (_, Type::Box(_)) => Ok((Owner::Pointee, layout.pointee(self.target))),
+            (val, _) => Err(Error::Invalid(format!("dereference of {:?}", val))),
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use crate::Error;
+use ir::layout::{self, Abi, TyLayout};
+use ir::*;
+
+/// The byte representation of a value as it would be laid out in memory on `target`.
+/// Undefined bytes read as zero.
+pub(crate) fn bytes_of(val: &Const, ty: &Ty, target: &target_lexicon::Triple) -> Result<Vec<u8>, Error> {
+    let layout = layout::layout_of(ty, target);
+    let mut bytes = vec![0; layout.size.bytes() as usize];
+
+    write_bytes(val, &layout, &mut bytes, target)?;
+    Ok(bytes)
+}
+
+/// Reconstructs a value of type `ty` from its byte representation.
+pub(crate) fn from_bytes(ty: &Ty, bytes: &[u8], target: &target_lexicon::Triple) -> Result<Const, Error> {
+    let layout = layout::layout_of(ty, target);
+
+    read_bytes(&layout, bytes, target)
+}
+
+fn write_bytes(val: &Const, layout: &TyLayout, bytes: &mut [u8], target: &target_lexicon::Triple) -> Result<(), Error> {
+    match val {
+        Const::Undefined(_) => {}
+        Const::Scalar(s, _) => {
+            let size = layout.size.bytes() as usize;
+
+            layout::write_scalar(*s, slice_mut(bytes, 0, size)?, target);
+        }
+        Const::Tuple(cs) => {
+            for (i, c) in cs.iter().enumerate() {
+                let offset = layout.fields.offset(i).bytes() as usize;
+                let field = layout.field(i, target);
+                let len = bytes.len();
+
+                write_bytes(c, &field, slice_mut(bytes, offset, len)?, target)?;
+            }
+        }
+        Const::Bytes(b) => slice_mut(bytes, 0, b.len())?.copy_from_slice(b),
+        // a payloadless enum is stored as just its tag
+        Const::Variant(idx, cs, _) if cs.is_empty() && matches!(layout.abi, Abi::Scalar(_)) => {
+            let size = layout.size.bytes() as usize;
+
+            layout::write_scalar(*idx as u128, slice_mut(bytes, 0, size)?, target);
+        }
+        _ => return Err(Error::Unsupported(format!("byte representation of {:?}", val))),
+    }
+
+    Ok(())
+}
+
+fn read_bytes(layout: &TyLayout, bytes: &[u8], target: &target_lexicon::Triple) -> Result<Const, Error> {
+    match (&layout.abi, &layout.ty.kind) {
+        (Abi::Scalar(_), Type::Tagged(_)) => {
+            let size = layout.size.bytes() as usize;
+            let idx = layout::read_scalar(slice(bytes, 0, size)?, target);
+
+            Ok(Const::Variant(idx as usize, Vec::new(), layout.ty.clone()))
+        }
+        (Abi::Scalar(_), _) => {
+            let size = layout.size.bytes() as usize;
+
+            Ok(Const::Scalar(layout::read_scalar(slice(bytes, 0, size)?, target), layout.ty.clone()))
+        }
+        (_, Type::Tuple(tys)) => Ok(Const::Tuple(
+            (0..tys.len())
+                .map(|i| {
+                    let offset = layout.fields.offset(i).bytes() as usize;
+
+                    read_bytes(&layout.field(i, target), slice(bytes, offset, bytes.len())?, target)
+                })
+                .collect::<Result<_, _>>()?,
+        )),
+        (_, Type::Vector(_, lanes)) => Ok(Const::Tuple(
+            (0..*lanes as usize)
+                .map(|i| {
+                    let offset = layout.fields.offset(i).bytes() as usize;
+
+                    read_bytes(&layout.field(i, target), slice(bytes, offset, bytes.len())?, target)
+                })
+                .collect::<Result<_, _>>()?,
+        )),
+        _ => Err(Error::Unsupported(format!("value of type {} from bytes", display_ty(&layout.ty)))),
+    }
+}
+
+/// `bytes[start..end]`, or an error if that is out of bounds.
+pub(crate) fn slice(bytes: &[u8], start: usize, end: usize) -> Result<&[u8], Error> {
+    bytes.get(start..end).ok_or(Error::OutOfBounds {
+        offset: start.max(end) as u128,
+        size: bytes.len() as u128,
+    })
+}
+
+fn slice_mut(bytes: &mut [u8], start: usize, end: usize) -> Result<&mut [u8], Error> {
+    let size = bytes.len() as u128;
+
+    bytes.get_mut(start..end).ok_or(Error::OutOfBounds {
+        offset: start.max(end) as u128,
+        size,
+    })
+}
@@ -0,0 +1,118 @@
+use ir::*;
+
+/// Charged for every statement and terminator except source locations before it is evaluated, so that embedders running untrusted code
+/// can price instructions by their kind instead of counting steps.
+///
+/// Refusing an instruction stops evaluation with `Error::Exhausted` before the instruction has any effect,
+/// cleanup blocks do not run. The context can be resumed with `EvalCtx::step` or `EvalCtx::eval` once the
+/// meter accepts it again, which charges for the instruction again.
+pub trait ExecutionMeter {
+    /// Charges for `inst`, failing with a description of the limit that was reached if it must not run.
+    fn charge(&mut self, kind: InstKind, inst: Inst) -> Result<(), String>;
+}
+
+/// An instruction about to be evaluated.
+#[derive(Debug, Clone, Copy)]
+pub enum Inst<'ir> {
+    Stmt(&'ir Stmt),
+    Term(&'ir Term),
+}
+
+/// What an instruction does, from the point of view of what it costs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InstKind {
+    Init,
+    Drop,
+    /// An assignment between locals that does not go through a pointer.
+    Assign,
+    /// An assignment that reads through a pointer.
+    Load,
+    /// An assignment that writes through a pointer, whether or not it reads through one as well.
+    Store,
+    SetDiscr,
+    Intrinsic,
+    Call,
+    Jump,
+    Switch,
+    Return,
+    Abort,
+    Resume,
+    /// A source location, which does nothing and is never charged.
+    Loc,
+}
+
+impl InstKind {
+    pub fn of(inst: Inst) -> Self {
+        match inst {
+            Inst::Stmt(stmt) => match stmt {
+                Stmt::Init(_) => InstKind::Init,
+                Stmt::Drop(_) => InstKind::Drop,
+                Stmt::Assign(place, _) if through_ptr(place) => InstKind::Store,
+                Stmt::Assign(_, RValue::Intrinsic(..)) => InstKind::Intrinsic,
+                Stmt::Assign(_, rvalue) if reads_through_ptr(rvalue) => InstKind::Load,
+                Stmt::Assign(..) => InstKind::Assign,
+                Stmt::SetDiscr(place, _) if through_ptr(place) => InstKind::Store,
+                Stmt::SetDiscr(..) => InstKind::SetDiscr,
+                Stmt::Call(..) => InstKind::Call,
+                Stmt::Loc(_) => InstKind::Loc,
+            },
+            Inst::Term(term) => match term {
+                Term::Abort => InstKind::Abort,
+                Term::Return => InstKind::Return,
+                Term::Resume => InstKind::Resume,
+                Term::Jump(_) => InstKind::Jump,
+                Term::Switch(..) | Term::BrIf(..) => InstKind::Switch,
+            },
+        }
+    }
+}
+
+fn through_ptr(place: &Place) -> bool {
+    place.elems.contains(&PlaceElem::Deref)
+}
+
+fn reads_through_ptr(rvalue: &RValue) -> bool {
+    let op = |op: &Operand| matches!(op, Operand::Place(place) if through_ptr(place));
+
+    match rvalue {
+        RValue::Use(o) | RValue::UnOp(_, o) => op(o),
+        RValue::GetDiscr(place) | RValue::Cast(place, _) => through_ptr(place),
+        RValue::Lea { base, index, .. } => op(base) || index.as_ref().map_or(false, op),
+        RValue::Intrinsic(_, ops) => ops.iter().any(op),
+        // taking an address does not read what it points to
+        RValue::AddrOf(_) | RValue::NullOp(..) => false,
+    }
+}
+
+/// The flat model: every instruction costs one unit of fuel.
+#[derive(Debug, Clone, Copy)]
+pub struct Fuel {
+    remaining: u64,
+}
+
+impl Fuel {
+    pub fn new(amount: u64) -> Self {
+        Fuel { remaining: amount }
+    }
+
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
+
+    /// Adds `amount` to the remaining fuel, to resume a context that ran out.
+    pub fn refill(&mut self, amount: u64) {
+        self.remaining = self.remaining.saturating_add(amount);
+    }
+}
+
+impl ExecutionMeter for Fuel {
+    fn charge(&mut self, _: InstKind, _: Inst) -> Result<(), String> {
+        match self.remaining.checked_sub(1) {
+            Some(remaining) => {
+                self.remaining = remaining;
+                Ok(())
+            }
+            None => Err(String::from("out of fuel")),
+        }
+    }
+}
@@ -0,0 +1,179 @@
+//! Deterministic interleaving of threads, for testing programs that communicate through globals and atomics.
+
+use crate::{Error, EvalCtx};
+use ir::rand::SplitMix64;
+use ir::Const;
+use std::ops::Range;
+
+/// Runs several contexts as threads of one program, switching between them after every statement.
+/// The next thread is picked by a generator seeded with `seed`, so an interleaving that fails can be
+/// replayed by using the same seed again.
+///
+/// A statement is never interrupted, which makes every atomic intrinsic atomic, and every thread sees
+/// every write as soon as it is made. Interleavings are explored, weaker orderings than sequential
+/// consistency are not.
+pub struct Scheduler<'ir> {
+    threads: Vec<Thread<'ir>>,
+    rng: SplitMix64,
+    steps: u64,
+}
+
+/// What the threads returned, `None` for a thread that did not finish within the steps it was given.
+pub type Results = Vec<Option<Result<Vec<Const>, Error>>>;
+
+/// The distinct outcomes of running the same threads under many schedules.
+#[derive(Debug, Clone)]
+pub struct Exploration {
+    pub outcomes: Vec<Outcome>,
+}
+
+/// The state a run ended in. Only the globals and whether the threads finished are compared between runs,
+/// what a thread returns may depend on the schedule even in a correct program, like the value of a counter
+/// that other threads increment.
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub globals: Vec<(ir::DeclId, Const)>,
+    /// Whether every thread finished, and the error of every thread that failed.
+    pub threads: Vec<Option<Result<(), Error>>>,
+    /// What the threads returned in the first run that ended in this state.
+    pub results: Results,
+    /// The first seed that led to this state, which replays it.
+    pub seed: u64,
+    /// The number of seeds that led to this state.
+    pub count: usize,
+}
+
+struct Thread<'ir> {
+    ctx: EvalCtx<'ir>,
+    result: Option<Result<(), Error>>,
+}
+
+impl<'ir> Scheduler<'ir> {
+    pub fn new(seed: u64) -> Self {
+        Scheduler {
+            threads: Vec::new(),
+            rng: SplitMix64::new(seed),
+            steps: 0,
+        }
+    }
+
+    /// Adds a thread and returns its index. Use `EvalCtx::with_shared_memory` to let threads communicate.
+    pub fn spawn(&mut self, ctx: EvalCtx<'ir>) -> usize {
+        let result = ctx.check_stack().err().map(Err);
+
+        self.threads.push(Thread { ctx, result });
+        self.threads.len() - 1
+    }
+
+    /// Takes up to `max_steps` steps, returning whether every thread has finished.
+    /// A thread spinning on a value another thread never writes keeps the program from finishing.
+    pub fn run(&mut self, max_steps: u64) -> bool {
+        for _ in 0..max_steps {
+            let running = self.threads.iter().filter(|t| t.result.is_none()).count();
+
+            if running == 0 {
+                return true;
+            }
+
+            let pick = self.rng.below(running as u64) as usize;
+            let thread = self.threads.iter_mut().filter(|t| t.result.is_none()).nth(pick).unwrap();
+
+            self.steps += 1;
+
+            match thread.ctx.step() {
+                Ok(()) if thread.ctx.is_finished() => thread.result = Some(Ok(())),
+                Ok(()) => {}
+                Err(e) => thread.result = Some(Err(e)),
+            }
+        }
+
+        self.threads.iter().all(|t| t.result.is_some())
+    }
+
+    /// The number of steps taken by all threads together.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// The current values of the globals the threads share, as seen by the first thread.
+    pub fn globals(&self) -> Vec<(ir::DeclId, Const)> {
+        self.threads.first().map(|t| t.ctx.globals()).unwrap_or_default()
+    }
+
+    /// Returns the return values of every thread in the order they were spawned,
+    /// or `None` for the threads that have not finished.
+    pub fn finish(self) -> Results {
+        self.threads
+            .into_iter()
+            .map(|t| match t.result {
+                Some(Ok(())) => Some(Ok(t.ctx.finish())),
+                Some(Err(e)) => Some(Err(e)),
+                None => None,
+            })
+            .collect()
+    }
+
+}
+
+/// Runs the threads returned by `spawn` once for every seed in `seeds`, giving them `max_steps` steps every time.
+/// `spawn` has to create the threads from scratch, sharing memory only with each other, so every run starts from
+/// the same state. More than one outcome means the program depends on the schedule, which usually means a race.
+pub fn explore<'ir>(seeds: Range<u64>, max_steps: u64, mut spawn: impl FnMut() -> Vec<EvalCtx<'ir>>) -> Exploration {
+    let mut outcomes = Vec::<Outcome>::new();
+
+    for seed in seeds {
+        let mut scheduler = Scheduler::new(seed);
+
+        for ctx in spawn() {
+            scheduler.spawn(ctx);
+        }
+
+        scheduler.run(max_steps);
+
+        let globals = scheduler.globals();
+        let results = scheduler.finish();
+        let threads = results.iter().map(|r| r.as_ref().map(|r| r.as_ref().map(|_| ()).map_err(Clone::clone))).collect::<Vec<_>>();
+
+        match outcomes.iter_mut().find(|o| o.globals == globals && o.threads == threads) {
+            Some(outcome) => outcome.count += 1,
+            None => outcomes.push(Outcome {
+                globals,
+                threads,
+                results,
+                seed,
+                count: 1,
+            }),
+        }
+    }
+
+    Exploration { outcomes }
+}
+
+impl Exploration {
+    /// Whether every schedule led to the same results.
+    pub fn is_deterministic(&self) -> bool {
+        self.outcomes.len() <= 1
+    }
+}
+
+impl std::fmt::Display for Exploration {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for outcome in &self.outcomes {
+            writeln!(f, "{} schedules, first with seed {}:", outcome.count, outcome.seed)?;
+
+            for (decl, val) in &outcome.globals {
+                writeln!(f, "    global {:?} = {:?}", decl, val)?;
+            }
+
+            for (i, result) in outcome.threads.iter().enumerate() {
+                match result {
+                    Some(Ok(())) => {}
+                    Some(Err(e)) => writeln!(f, "    thread {} failed: {}", i, e)?,
+                    None => writeln!(f, "    thread {} did not finish", i)?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+use crate::memory::{bytes_of, slice};
+use crate::Error;
+use std::convert::TryFrom;
+use ir::*;
+
+/// Provides the functions a module imports.
+pub trait Externs {
+    /// Evaluates a call to the imported function `decl`, returning `Error::UnknownSymbol` if it is unknown.
+    fn call(&mut self, module: &Module, decl: &Decl, args: &[Const], target: &target_lexicon::Triple) -> Result<Vec<Const>, Error>;
+}
+
+pub struct NoExterns;
+
+impl Externs for NoExterns {
+    fn call(&mut self, _: &Module, decl: &Decl, _: &[Const], _: &target_lexicon::Triple) -> Result<Vec<Const>, Error> {
+        Err(Error::UnknownSymbol(decl.name.clone()))
+    }
+}
+
+pub const CLOCK_STEP: u64 = 1000;
+pub const RAND_SEED: u64 = 42;
+
+/// Deterministic stand-ins for `write`, `clock` and `rand`. The runtime crate's
+/// `stubs` feature implements the same behaviour for compiled code, so a program
+/// produces identical output under both.
+///
+/// - `write(fd, buf, len)` appends `len` bytes to `output` and returns `len`, or fails with
+///   `Error::OutOfBounds` if `buf` has fewer than `len` bytes.
+/// - `clock()` starts at zero and advances by `CLOCK_STEP` on every call.
+/// - `rand()` is a 64-bit LCG seeded with `RAND_SEED`, returning the upper 31 bits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stubs {
+    pub output: Vec<u8>,
+    clock: u64,
+    rand: u64,
+}
+
+impl Stubs {
+    pub fn new() -> Self {
+        Stubs {
+            output: Vec::new(),
+            clock: 0,
+            rand: RAND_SEED,
+        }
+    }
+}
+
+impl Default for Stubs {
+    fn default() -> Self {
+        Stubs::new()
+    }
+}
+
+impl Externs for Stubs {
+    fn call(&mut self, module: &Module, decl: &Decl, args: &[Const], target: &target_lexicon::Triple) -> Result<Vec<Const>, Error> {
+        let val = match (decl.name.as_str(), args) {
+            ("write", [_, Const::Ptr(buf), Const::Scalar(len, _)]) => {
+                let bytes = bytes_of(buf, &const_type(module, buf), target)?;
+                // a length that does not fit a usize would be truncated to one that does
+                let end = usize::try_from(*len).map_err(|_| Error::OutOfBounds {
+                    offset: *len,
+                    size: bytes.len() as u128,
+                })?;
+
+                self.output.extend(slice(&bytes, 0, end)?);
+                *len
+            }
+            ("clock", []) => {
+                let now = self.clock;
+
+                self.clock += CLOCK_STEP;
+                now as u128
+            }
+            ("rand", []) => {
+                self.rand = self.rand.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                (self.rand >> 33) as u128
+            }
+            _ => return Err(Error::UnknownSymbol(decl.name.clone())),
+        };
+
+        let ty = decl.ty.signature().rets.remove(0);
+
+        Ok(vec![Const::Scalar(val, ty)])
+    }
+}
@@ -1,5 +1,10 @@
 use crate::*;
 
+/// Appends statements to the blocks of a body.
+///
+/// Bodies are not in SSA form: locals are mutable and keep their value across blocks, so a frontend
+/// maps each of its variables to a local from `create_var` and assigns to it wherever the variable changes.
+/// There are no block parameters to thread values through.
 pub struct Builder<'ir> {
     body: &'ir mut Body,
     current_block: Block,
@@ -30,12 +35,17 @@ impl<'ir> Builder<'ir> {
                 id: block,
                 stmts: Vec::new(),
                 term: Term::Abort,
+                hint: BranchHint::Normal,
             },
         );
 
         block
     }
 
+    pub fn set_hint(&mut self, block: Block, hint: BranchHint) {
+        self.body.blocks[block].hint = hint;
+    }
+
     pub fn create_ret(&mut self, ty: Ty) -> Local {
         let local = self.body.locals.next_idx();
 
@@ -129,6 +139,10 @@ impl<'ir> Builder<'ir> {
         self.block().term = Term::Return;
     }
 
+    pub fn resume(&mut self) {
+        self.block().term = Term::Resume;
+    }
+
     pub fn jump(&mut self, to: Block) {
         self.block().term = Term::Jump(to);
     }
@@ -137,6 +151,32 @@ impl<'ir> Builder<'ir> {
         self.block().term = Term::Switch(op, vals, blocks);
     }
 
+    pub fn br_if(&mut self, cond: Operand, then: Block, else_: Block) {
+        self.block().term = Term::BrIf(cond, then, else_);
+    }
+
+    pub fn init(&mut self, local: Local) {
+        self.block().stmts.push(Stmt::Init(local));
+    }
+
+    pub fn drop(&mut self, local: Local) {
+        self.block().stmts.push(Stmt::Drop(local));
+    }
+
+    /// Marks the statements built after this in the current block as coming from `loc`.
+    /// Nothing is added if they already are.
+    pub fn set_loc(&mut self, loc: SourceLoc) {
+        let block = self.block();
+        let current = block.stmts.iter().rev().find_map(|stmt| match stmt {
+            Stmt::Loc(loc) => Some(loc),
+            _ => None,
+        });
+
+        if current != Some(&loc) {
+            block.stmts.push(Stmt::Loc(loc));
+        }
+    }
+
     pub fn use_op(&mut self, place: Place, op: Operand) {
         self.block()
             .stmts
@@ -149,6 +189,117 @@ impl<'ir> Builder<'ir> {
             .push(Stmt::Assign(place, RValue::AddrOf(of)));
     }
 
+    pub fn cast(&mut self, place: Place, of: Place, ty: Ty) {
+        self.block()
+            .stmts
+            .push(Stmt::Assign(place, RValue::Cast(of, ty)));
+    }
+
+    pub fn lea(&mut self, place: Place, base: Operand, index: Option<Operand>, scale: u64, offset: i64) {
+        self.block().stmts.push(Stmt::Assign(place, RValue::Lea {
+            base,
+            index,
+            scale,
+            offset,
+        }));
+    }
+
+    /// Creates a temporary holding a pointer to field `index` of `var`.
+    /// `var` is either the struct itself or a pointer to it.
+    pub fn struct_field_addr(&mut self, var: Place, index: usize) -> Place {
+        let ty = place_type(self.body, &var);
+        let base = match ty.access().kind {
+            Type::Ptr(_) => var.deref(),
+            _ => var,
+        };
+
+        let field = base.clone().field(index);
+        let field_ty = place_type(self.body, &field);
+        let tmp = Place::new(self.create_tmp(Ty::new(Type::Ptr(Box::new(field_ty)))));
+
+        self.addrof(tmp.clone(), field);
+        tmp
+    }
+
+    /// Creates a temporary of tuple type `ty` with its fields initialized to `vars`.
+    pub fn struct_init(&mut self, ty: Ty, vars: Vec<Operand>) -> Place {
+        match &ty.access().kind {
+            Type::Tuple(tys) => assert_eq!(tys.len(), vars.len(), "wrong number of fields"),
+            _ => panic!("struct_init on a non-tuple type"),
+        }
+
+        let tmp = Place::new(self.create_tmp(ty));
+
+        for (i, op) in vars.into_iter().enumerate() {
+            self.use_op(tmp.clone().field(i), op);
+        }
+
+        tmp
+    }
+
+    /// Creates a temporary of enum type `ty` holding variant `idx` with its fields initialized to `args`.
+    pub fn variant_init(&mut self, ty: Ty, idx: usize, args: Vec<Operand>) -> Place {
+        match &ty.access().kind {
+            Type::Tagged(tys) => match &tys[idx].access().kind {
+                Type::Tuple(fields) => assert_eq!(fields.len(), args.len(), "wrong number of fields"),
+                _ => assert!(args.is_empty(), "variant has no fields"),
+            },
+            _ => panic!("variant_init on a non-enum type"),
+        }
+
+        let tmp = Place::new(self.create_tmp(ty));
+
+        for (i, op) in args.into_iter().enumerate() {
+            self.use_op(tmp.clone().downcast(idx).field(i), op);
+        }
+
+        self.set_discr(tmp.clone(), idx as u128);
+        tmp
+    }
+
+    /// Creates a temporary holding the index of the active variant of `var`,
+    /// which can be used as the operand of a switch.
+    pub fn get_tag(&mut self, var: Place) -> Place {
+        let ty = place_type(self.body, &var);
+        let tmp = Place::new(self.create_tmp(Ty::new(Type::Discr(Box::new(ty)))));
+
+        self.get_discr(tmp.clone(), var);
+        tmp
+    }
+
+    /// Creates a temporary holding a pointer to field `field` of variant `idx` of `var`.
+    /// `var` is either the enum itself or a pointer to it.
+    pub fn variant_field_addr(&mut self, var: Place, idx: usize, field: usize) -> Place {
+        let ty = place_type(self.body, &var);
+        let base = match ty.access().kind {
+            Type::Ptr(_) | Type::Box(_) => var.deref(),
+            _ => var,
+        };
+
+        let field = base.downcast(idx).field(field);
+        let field_ty = place_type(self.body, &field);
+        let tmp = Place::new(self.create_tmp(Ty::new(Type::Ptr(Box::new(field_ty)))));
+
+        self.addrof(tmp.clone(), field);
+        tmp
+    }
+
+    pub fn neg(&mut self, place: Place, op: Operand) {
+        self.block().stmts.push(Stmt::Assign(place, RValue::UnOp(UnOp::Neg, op)));
+    }
+
+    pub fn not(&mut self, place: Place, op: Operand) {
+        self.block().stmts.push(Stmt::Assign(place, RValue::UnOp(UnOp::Not, op)));
+    }
+
+    pub fn bitnot(&mut self, place: Place, op: Operand) {
+        self.block().stmts.push(Stmt::Assign(place, RValue::UnOp(UnOp::BitNot, op)));
+    }
+
+    pub fn nullary(&mut self, place: Place, op: NullOp, ty: Ty) {
+        self.block().stmts.push(Stmt::Assign(place, RValue::NullOp(op, ty)));
+    }
+
     pub fn intrinsic(&mut self, place: Place, name: impl Into<String>, args: Vec<Operand>) {
         self.block()
             .stmts
@@ -156,7 +307,12 @@ impl<'ir> Builder<'ir> {
     }
 
     pub fn call(&mut self, rets: Vec<Place>, func: Operand, args: Vec<Operand>) {
-        self.block().stmts.push(Stmt::Call(rets, func, args));
+        self.block().stmts.push(Stmt::Call(rets, func, args, None));
+    }
+
+    /// A call that continues at `cleanup` if the callee fails.
+    pub fn call_with_cleanup(&mut self, rets: Vec<Place>, func: Operand, args: Vec<Operand>, cleanup: Block) {
+        self.block().stmts.push(Stmt::Call(rets, func, args, Some(cleanup)));
     }
 
     pub fn set_discr(&mut self, place: Place, val: u128) {
@@ -0,0 +1,875 @@
+use crate::*;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use target_lexicon::{Architecture, OperatingSystem, PointerWidth, Triple};
+
+/// Adds an import for every function and `extern` global declared in a C header.
+///
+/// The header must already be preprocessed, e.g. with `cc -E`. Declarations whose name is already declared in
+/// `module` are skipped, as are `static` and `inline` functions. Structs and unions are translated to tuples
+/// and unions with the same fields, pointers to incomplete types to pointers to `()`.
+pub fn declare_header(module: &mut Module, src: &str, target: &Triple) -> Result<(), String> {
+    for (name, ty) in parse_header(src, target)? {
+        if module.decls.iter().any(|d| d.name == name) {
+            continue;
+        }
+
+        let id = module.decls.next_idx();
+
+        module.decls.insert(id, Decl {
+            id,
+            linkage: Linkage::Import,
+            name,
+            ty,
+            attrs: Attrs {
+                c_abi: true,
+                ..Attrs::default()
+            },
+        });
+    }
+
+    Ok(())
+}
+
+/// Returns the name and type of every function and `extern` global declared in a preprocessed C header.
+pub fn parse_header(src: &str, target: &Triple) -> Result<Vec<(String, Ty)>, String> {
+    let mut parser = HeaderParser {
+        tokens: lex(src)?,
+        i: 0,
+        target,
+        typedefs: HashMap::new(),
+        consts: HashMap::new(),
+        records: HashMap::new(),
+        unsupported: HashSet::new(),
+        skip: false,
+        decls: Vec::new(),
+    };
+
+    parser.parse()?;
+
+    Ok(parser.decls)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Ident(String),
+    Number(u64),
+    Str,
+    Punct(&'static str),
+}
+
+/// The number of types an array may consist of, counting the type of every element and the types they contain.
+const MAX_ARRAY_TYPES: u64 = 1 << 20;
+
+/// How a declarator derives its type from the base type.
+enum Derive {
+    Ptr,
+    Array(Option<u64>),
+    Func(Vec<Ty>, bool),
+}
+
+struct Specifiers {
+    ty: Option<Ty>,
+    is_typedef: bool,
+    is_static: bool,
+    is_inline: bool,
+    is_extern: bool,
+    call_conv: CallConv,
+}
+
+struct HeaderParser<'a> {
+    tokens: Vec<Tok>,
+    i: usize,
+    target: &'a Triple,
+    typedefs: HashMap<String, Ty>,
+    /// The values of enum constants.
+    consts: HashMap<String, i64>,
+    /// The fields of structs and unions, keyed by `struct name` or `union name`.
+    records: HashMap<String, Ty>,
+    /// Typedefs and records that can not be represented, like `long double` and bit fields.
+    unsupported: HashSet<String>,
+    /// Whether the declaration being parsed uses an unsupported type and should be skipped.
+    skip: bool,
+    decls: Vec<(String, Ty)>,
+}
+
+impl HeaderParser<'_> {
+    fn parse(&mut self) -> Result<(), String> {
+        while self.i < self.tokens.len() {
+            if self.peek_ident("extern") && self.tokens.get(self.i + 1) == Some(&Tok::Str) {
+                // extern "C" { ... } in headers that can be included from C++
+                self.i += 2;
+                self.eat("{");
+            } else if self.eat("}") || self.eat(";") {
+            } else {
+                self.parse_decl()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse_decl(&mut self) -> Result<(), String> {
+        self.skip = false;
+
+        let specs = self.parse_specifiers()?;
+        let skip_base = self.skip;
+        let base = match specs.ty.clone() {
+            Some(ty) => ty,
+            None => return Err(format!("Expected a type at token {}", self.i)),
+        };
+
+        if self.eat(";") {
+            return Ok(());
+        }
+
+        loop {
+            self.skip = skip_base;
+
+            let (name, derives) = self.parse_declarator()?;
+            let is_func = matches!(derives.last(), Some(Derive::Func(..)));
+            let ty = self.derive(base.clone(), derives, specs.call_conv)?;
+            let name = name.ok_or_else(|| format!("Expected a name at token {}", self.i))?;
+
+            self.skip_attributes();
+
+            if is_func && self.peek("{") {
+                // a definition, which is only possible for static or inline functions in a header
+                self.skip_balanced("{", "}");
+                return Ok(());
+            } else if specs.is_typedef {
+                if self.skip {
+                    self.unsupported.insert(name.clone());
+                }
+
+                self.typedefs.insert(name, ty);
+            } else if self.skip {
+            } else if is_func && !specs.is_static && !specs.is_inline {
+                self.decls.push((name, ty));
+            } else if !is_func && specs.is_extern {
+                self.decls.push((name, ty));
+            }
+
+            if self.eat("=") {
+                self.skip_initializer();
+            }
+
+            if !self.eat(",") {
+                break;
+            }
+        }
+
+        self.expect(";")
+    }
+
+    fn parse_specifiers(&mut self) -> Result<Specifiers, String> {
+        let mut specs = Specifiers {
+            ty: None,
+            is_typedef: false,
+            is_static: false,
+            is_inline: false,
+            is_extern: false,
+            call_conv: CallConv::C,
+        };
+
+        let mut signed = None;
+        let mut longs = 0;
+        let mut short = false;
+        let mut base = None;
+
+        loop {
+            let id = match self.tokens.get(self.i) {
+                Some(Tok::Ident(id)) => id.clone(),
+                _ => break,
+            };
+
+            match id.as_str() {
+                "typedef" => specs.is_typedef = true,
+                "static" => specs.is_static = true,
+                "extern" => specs.is_extern = true,
+                "inline" | "__inline" | "__inline__" => specs.is_inline = true,
+                "const" | "volatile" | "restrict" | "__restrict" | "__restrict__" | "__const" | "register" | "auto" | "_Noreturn" | "__extension__" |
+                "__cdecl" | "__stdcall" => {}
+                "__attribute__" | "__declspec" => {
+                    if self.parse_attribute() {
+                        specs.call_conv = CallConv::Win64;
+                    }
+
+                    continue;
+                }
+                "signed" | "__signed__" => signed = Some(true),
+                "unsigned" => signed = Some(false),
+                "short" => short = true,
+                "long" => longs += 1,
+                "void" | "char" | "int" | "float" | "double" | "_Bool" | "bool" => base = Some(id),
+                "struct" | "union" => {
+                    self.i += 1;
+                    specs.ty = Some(self.parse_record(&id)?);
+                    continue;
+                }
+                "enum" => {
+                    self.i += 1;
+                    self.skip_attributes();
+                    self.eat_ident();
+
+                    if self.eat("{") {
+                        self.parse_enum()?;
+                    }
+
+                    specs.ty = Some(Ty::new(Type::I32));
+                    continue;
+                }
+                _ if specs.ty.is_none() && base.is_none() && signed.is_none() && longs == 0 && !short => {
+                    specs.ty = Some(self.typedef_type(&id));
+                }
+                _ => break,
+            }
+
+            self.i += 1;
+        }
+
+        if specs.ty.is_none() && (base.is_some() || signed.is_some() || longs > 0 || short) {
+            specs.ty = Some(self.builtin_type(base.as_deref(), signed, longs, short)?);
+        }
+
+        Ok(specs)
+    }
+
+    fn builtin_type(&mut self, base: Option<&str>, signed: Option<bool>, longs: usize, short: bool) -> Result<Ty, String> {
+        let int = |bits: u32, signed: bool| {
+            Ty::new(match (bits, signed) {
+                (8, true) => Type::I8,
+                (8, false) => Type::U8,
+                (16, true) => Type::I16,
+                (16, false) => Type::U16,
+                (32, true) => Type::I32,
+                (32, false) => Type::U32,
+                (_, true) => Type::I64,
+                (_, false) => Type::U64,
+            })
+        };
+
+        let ty = match base {
+            Some("void") => Ty::new(Type::Tuple(Vec::new())),
+            Some("_Bool") | Some("bool") => Ty::new(Type::Bool),
+            Some("float") => Ty::new(Type::F32),
+            Some("double") if longs == 0 => Ty::new(Type::F64),
+            Some("double") => {
+                self.skip = true;
+                Ty::new(Type::F64)
+            }
+            Some("char") => int(8, signed.unwrap_or_else(|| self.char_is_signed())),
+            _ if short => int(16, signed.unwrap_or(true)),
+            _ if longs == 1 => int(self.long_bits(), signed.unwrap_or(true)),
+            _ if longs > 1 => int(64, signed.unwrap_or(true)),
+            _ => int(32, signed.unwrap_or(true)),
+        };
+
+        Ok(ty)
+    }
+
+    fn typedef_type(&mut self, name: &str) -> Ty {
+        if self.unsupported.contains(name) {
+            self.skip = true;
+        }
+
+        if let Some(ty) = self.typedefs.get(name) {
+            return ty.clone();
+        }
+
+        let ptr = self.pointer_bits();
+
+        Ty::new(match name {
+            "int8_t" => Type::I8,
+            "int16_t" => Type::I16,
+            "int32_t" => Type::I32,
+            "int64_t" => Type::I64,
+            "uint8_t" => Type::U8,
+            "uint16_t" => Type::U16,
+            "uint32_t" => Type::U32,
+            "uint64_t" => Type::U64,
+            "__int128" | "__int128_t" => Type::I128,
+            "__uint128_t" => Type::U128,
+            "size_t" | "uintptr_t" if ptr == 32 => Type::U32,
+            "size_t" | "uintptr_t" => Type::U64,
+            "ssize_t" | "intptr_t" | "ptrdiff_t" if ptr == 32 => Type::I32,
+            "ssize_t" | "intptr_t" | "ptrdiff_t" => Type::I64,
+            "wchar_t" if self.target.operating_system == OperatingSystem::Windows => Type::U16,
+            "wchar_t" => Type::I32,
+            // an opaque type like FILE, which can only be used behind a pointer
+            _ => Type::Tuple(Vec::new()),
+        })
+    }
+
+    fn parse_record(&mut self, kind: &str) -> Result<Ty, String> {
+        self.skip_attributes();
+
+        let key = self.eat_ident().map(|name| format!("{} {}", kind, name));
+
+        if !self.eat("{") {
+            // a reference to a record that may be defined later, which is fine as long as it is behind a pointer
+            let key = key.ok_or_else(|| format!("Expected a {} name at token {}", kind, self.i))?;
+
+            if self.unsupported.contains(&key) {
+                self.skip = true;
+            }
+
+            return Ok(self.records.get(&key).cloned().unwrap_or_else(|| Ty::new(Type::Tuple(Vec::new()))));
+        }
+
+        let skip_outer = std::mem::replace(&mut self.skip, false);
+
+        let mut fields = Vec::new();
+
+        while !self.eat("}") {
+            let specs = self.parse_specifiers()?;
+            let base = specs.ty.ok_or_else(|| format!("Expected a field type at token {}", self.i))?;
+
+            loop {
+                let (_, derives) = self.parse_declarator()?;
+
+                if self.eat(":") {
+                    self.parse_const(0)?;
+                    self.skip = true;
+                }
+
+                fields.push(self.derive(base.clone(), derives, CallConv::C)?);
+
+                if !self.eat(",") {
+                    break;
+                }
+            }
+
+            self.expect(";")?;
+        }
+
+        self.skip_attributes();
+
+        let ty = Ty::new(if kind == "union" { Type::Union(fields) } else { Type::Tuple(fields) });
+
+        if let Some(key) = key {
+            if self.skip {
+                self.unsupported.insert(key.clone());
+            }
+
+            self.records.insert(key, ty.clone());
+        }
+
+        self.skip |= skip_outer;
+
+        Ok(ty)
+    }
+
+    fn parse_enum(&mut self) -> Result<(), String> {
+        let mut value = 0;
+
+        while !self.eat("}") {
+            let name = self.eat_ident().ok_or_else(|| format!("Expected an enum constant at token {}", self.i))?;
+
+            self.skip_attributes();
+
+            if self.eat("=") {
+                value = self.parse_const(0)?;
+            }
+
+            self.consts.insert(name, value);
+            value += 1;
+
+            if !self.eat(",") {
+                self.expect("}")?;
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Evaluates a constant expression like the ones used for array lengths and enum values.
+    fn parse_const(&mut self, min_prec: u8) -> Result<i64, String> {
+        let mut lhs = self.parse_const_unary()?;
+
+        loop {
+            let (op, prec) = match self.tokens.get(self.i) {
+                Some(Tok::Punct(op @ "*")) | Some(Tok::Punct(op @ "/")) | Some(Tok::Punct(op @ "%")) => (*op, 5),
+                Some(Tok::Punct(op @ "+")) | Some(Tok::Punct(op @ "-")) => (*op, 4),
+                Some(Tok::Punct(op @ "<<")) | Some(Tok::Punct(op @ ">>")) => (*op, 3),
+                Some(Tok::Punct(op @ "&")) => (*op, 2),
+                Some(Tok::Punct(op @ "^")) => (*op, 1),
+                Some(Tok::Punct(op @ "|")) => (*op, 0),
+                _ => break,
+            };
+
+            if prec < min_prec {
+                break;
+            }
+
+            self.i += 1;
+
+            let rhs = self.parse_const(prec + 1)?;
+
+            // shifts by a negative amount or the width of the type or more are undefined in C
+            let shift = u32::try_from(rhs).ok();
+
+            lhs = match op {
+                "*" => lhs.wrapping_mul(rhs),
+                "/" | "%" if rhs == 0 => return Err(format!("Division by zero at token {}", self.i)),
+                "/" => lhs.checked_div(rhs).ok_or_else(|| format!("Division overflows at token {}", self.i))?,
+                "%" => lhs.checked_rem(rhs).ok_or_else(|| format!("Division overflows at token {}", self.i))?,
+                "+" => lhs.wrapping_add(rhs),
+                "-" => lhs.wrapping_sub(rhs),
+                "<<" => shift.and_then(|s| lhs.checked_shl(s)).ok_or_else(|| format!("Invalid shift amount {} at token {}", rhs, self.i))?,
+                ">>" => shift.and_then(|s| lhs.checked_shr(s)).ok_or_else(|| format!("Invalid shift amount {} at token {}", rhs, self.i))?,
+                "&" => lhs & rhs,
+                "^" => lhs ^ rhs,
+                _ => lhs | rhs,
+            };
+        }
+
+        Ok(lhs)
+    }
+
+    fn parse_const_unary(&mut self) -> Result<i64, String> {
+        if self.eat("-") {
+            Ok(self.parse_const_unary()?.wrapping_neg())
+        } else if self.eat("~") {
+            Ok(!self.parse_const_unary()?)
+        } else if self.eat("+") {
+            self.parse_const_unary()
+        } else if self.peek_ident("sizeof") || self.peek_ident("_Alignof") || self.peek_ident("__alignof__") {
+            let is_size = self.peek_ident("sizeof");
+
+            self.i += 1;
+            self.expect("(")?;
+
+            let ty = self.parse_type_name()?;
+            let layout = crate::layout::layout_of(&ty, self.target);
+
+            self.expect(")")?;
+
+            Ok(if is_size { layout.size.bytes() } else { layout.align.bytes() } as i64)
+        } else if self.eat("(") {
+            if self.starts_type() {
+                // a cast, which does not change the values used in headers
+                self.parse_type_name()?;
+                self.expect(")")?;
+                self.parse_const_unary()
+            } else {
+                let value = self.parse_const(0)?;
+
+                self.expect(")")?;
+                Ok(value)
+            }
+        } else {
+            match self.tokens.get(self.i).cloned() {
+                Some(Tok::Number(n)) => {
+                    self.i += 1;
+                    Ok(n as i64)
+                }
+                Some(Tok::Ident(id)) if self.consts.contains_key(&id) => {
+                    self.i += 1;
+                    Ok(self.consts[&id])
+                }
+                tok => Err(format!("Expected a constant at token {}, found {:?}", self.i, tok)),
+            }
+        }
+    }
+
+    fn parse_type_name(&mut self) -> Result<Ty, String> {
+        let specs = self.parse_specifiers()?;
+        let base = specs.ty.ok_or_else(|| format!("Expected a type at token {}", self.i))?;
+        let (_, derives) = self.parse_declarator()?;
+
+        self.derive(base, derives, CallConv::C)
+    }
+
+    fn starts_type(&self) -> bool {
+        match self.tokens.get(self.i) {
+            Some(Tok::Ident(id)) => !self.consts.contains_key(id) && id != "sizeof" && id != "_Alignof" && id != "__alignof__",
+            _ => false,
+        }
+    }
+
+    /// Parses a possibly abstract declarator, returning the derivations from the base type in the order they apply.
+    fn parse_declarator(&mut self) -> Result<(Option<String>, Vec<Derive>), String> {
+        let mut derives = Vec::new();
+
+        loop {
+            self.skip_attributes();
+
+            if self.eat("*") {
+                derives.push(Derive::Ptr);
+            } else if self.eat_qualifier() {
+            } else {
+                break;
+            }
+        }
+
+        let (name, inner) = if self.peek("(") && matches!(self.tokens.get(self.i + 1), Some(Tok::Punct("*")) | Some(Tok::Punct("("))) {
+            self.i += 1;
+
+            let inner = self.parse_declarator()?;
+
+            self.expect(")")?;
+            inner
+        } else {
+            (self.eat_ident(), Vec::new())
+        };
+
+        let mut suffixes = Vec::new();
+
+        loop {
+            if self.eat("[") {
+                let len = if self.peek("]") {
+                    None
+                } else {
+                    let len = self.parse_const(0)?;
+
+                    Some(u64::try_from(len).map_err(|_| format!("Negative array length {} at token {}", len, self.i))?)
+                };
+
+                self.expect("]")?;
+                suffixes.push(Derive::Array(len));
+            } else if self.eat("(") {
+                let (params, variadic) = self.parse_params()?;
+
+                suffixes.push(Derive::Func(params, variadic));
+            } else {
+                break;
+            }
+        }
+
+        derives.extend(suffixes.into_iter().rev());
+        derives.extend(inner);
+
+        Ok((name, derives))
+    }
+
+    fn parse_params(&mut self) -> Result<(Vec<Ty>, bool), String> {
+        let mut params = Vec::new();
+
+        if self.peek_ident("void") && self.tokens.get(self.i + 1) == Some(&Tok::Punct(")")) {
+            self.i += 2;
+            return Ok((params, false));
+        }
+
+        while !self.eat(")") {
+            if self.eat("...") {
+                self.expect(")")?;
+                return Ok((params, true));
+            }
+
+            let specs = self.parse_specifiers()?;
+            let base = specs.ty.ok_or_else(|| format!("Expected a parameter type at token {}", self.i))?;
+            let (_, mut derives) = self.parse_declarator()?;
+
+            // arrays and functions are passed as pointers
+            if let Some(Derive::Array(_)) = derives.last() {
+                *derives.last_mut().unwrap() = Derive::Ptr;
+            }
+
+            params.push(self.derive(base, derives, CallConv::C)?);
+
+            if !self.eat(",") {
+                self.expect(")")?;
+                break;
+            }
+        }
+
+        Ok((params, false))
+    }
+
+    fn derive(&self, mut ty: Ty, derives: Vec<Derive>, call_conv: CallConv) -> Result<Ty, String> {
+        for derive in derives {
+            ty = match derive {
+                // function types are already pointers
+                Derive::Ptr if matches!(ty.kind, Type::Func(_)) => ty,
+                Derive::Ptr => Ty::new(Type::Ptr(Box::new(ty))),
+                Derive::Array(Some(len)) => {
+                    // arrays are tuples with a field for every element, so a large array would use up all memory
+                    match len.checked_mul(count_types(&ty)) {
+                        Some(count) if count <= MAX_ARRAY_TYPES => Ty::new(Type::Tuple(vec![ty; len as usize])),
+                        _ => return Err(format!("Array of {} elements is too large", len)),
+                    }
+                }
+                Derive::Array(None) => Ty::new(Type::Ptr(Box::new(ty))),
+                Derive::Func(params, variadic) => {
+                    let rets = match &ty.kind {
+                        Type::Tuple(tys) if tys.is_empty() => Vec::new(),
+                        _ => vec![ty],
+                    };
+
+                    Ty::new(Type::Func(Signature {
+                        params,
+                        rets,
+                        call_conv,
+                        variadic,
+                    }))
+                }
+            };
+        }
+
+        Ok(ty)
+    }
+
+    /// Skips an `__attribute__((...))` or `__declspec(...)`, returning whether it selects the Windows x64 convention.
+    fn parse_attribute(&mut self) -> bool {
+        let start = self.i;
+
+        self.i += 1;
+        self.skip_balanced("(", ")");
+
+        self.tokens[start..self.i]
+            .iter()
+            .any(|t| matches!(t, Tok::Ident(id) if id == "ms_abi" || id == "__ms_abi__"))
+    }
+
+    fn skip_attributes(&mut self) {
+        while self.peek_ident("__attribute__") || self.peek_ident("__declspec") || self.peek_ident("__asm__") || self.peek_ident("__asm") {
+            self.parse_attribute();
+        }
+    }
+
+    fn skip_initializer(&mut self) {
+        let mut depth = 0;
+
+        while let Some(tok) = self.tokens.get(self.i) {
+            match tok {
+                Tok::Punct("(") | Tok::Punct("{") | Tok::Punct("[") => depth += 1,
+                Tok::Punct(")") | Tok::Punct("}") | Tok::Punct("]") => depth -= 1,
+                Tok::Punct(",") | Tok::Punct(";") if depth == 0 => break,
+                _ => {}
+            }
+
+            self.i += 1;
+        }
+    }
+
+    fn skip_balanced(&mut self, open: &str, close: &str) {
+        let mut depth = 0;
+
+        while let Some(tok) = self.tokens.get(self.i) {
+            self.i += 1;
+
+            match tok {
+                Tok::Punct(p) if *p == open => depth += 1,
+                Tok::Punct(p) if *p == close => {
+                    depth -= 1;
+
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn eat_qualifier(&mut self) -> bool {
+        let is_qualifier = match self.tokens.get(self.i) {
+            Some(Tok::Ident(id)) => matches!(
+                id.as_str(),
+                "const" | "volatile" | "restrict" | "__restrict" | "__restrict__" | "__const" | "_Nullable" | "_Nonnull"
+            ),
+            _ => false,
+        };
+
+        self.i += is_qualifier as usize;
+        is_qualifier
+    }
+
+    fn eat_ident(&mut self) -> Option<String> {
+        match self.tokens.get(self.i) {
+            Some(Tok::Ident(id)) => {
+                self.i += 1;
+                Some(id.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn peek_ident(&self, name: &str) -> bool {
+        matches!(self.tokens.get(self.i), Some(Tok::Ident(id)) if id == name)
+    }
+
+    fn peek(&self, punct: &str) -> bool {
+        matches!(self.tokens.get(self.i), Some(Tok::Punct(p)) if *p == punct)
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        let found = self.peek(punct);
+
+        self.i += found as usize;
+        found
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), String> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            Err(format!("Expected '{}' at token {}, found {:?}", punct, self.i, self.tokens.get(self.i)))
+        }
+    }
+
+    fn pointer_bits(&self) -> u32 {
+        match self.target.pointer_width() {
+            Ok(PointerWidth::U16) => 16,
+            Ok(PointerWidth::U32) => 32,
+            _ => 64,
+        }
+    }
+
+    fn long_bits(&self) -> u32 {
+        if self.target.operating_system == OperatingSystem::Windows {
+            32
+        } else {
+            self.pointer_bits()
+        }
+    }
+
+    fn char_is_signed(&self) -> bool {
+        match self.target.architecture {
+            Architecture::Aarch64(_) | Architecture::Arm(_) => matches!(
+                self.target.operating_system,
+                OperatingSystem::Darwin | OperatingSystem::Ios | OperatingSystem::MacOSX { .. } | OperatingSystem::Windows
+            ),
+            _ => true,
+        }
+    }
+}
+
+/// Counts `ty` and the types it is made of.
+fn count_types(ty: &Ty) -> u64 {
+    let inner = match &ty.kind {
+        Type::Ptr(to) | Type::Box(to) | Type::Discr(to) | Type::Vector(to, _) | Type::Newtype(_, to) => count_types(to),
+        Type::Tuple(tys) | Type::Union(tys) | Type::Tagged(tys) => tys.iter().map(count_types).sum(),
+        Type::Func(sig) => sig.params.iter().chain(&sig.rets).map(count_types).sum(),
+        _ => 0,
+    };
+
+    1 + inner
+}
+
+const PUNCTS: &[&str] = &[
+    "...", "<<", ">>", "(", ")", "{", "}", "[", "]", "*", ",", ";", ":", "=", "+", "-", "/", "%", "&", "|", "^", "~", "!", "<", ">", ".", "?",
+];
+
+fn lex(src: &str) -> Result<Vec<Tok>, String> {
+    let mut tokens = Vec::new();
+    let src = src.as_bytes();
+    let mut i = 0;
+
+    while i < src.len() {
+        let c = src[i];
+
+        if c == b'#' && (i == 0 || src[..i].iter().rev().take_while(|c| **c != b'\n').all(|c| c.is_ascii_whitespace())) {
+            // preprocessor directives and line markers
+            while i < src.len() && src[i] != b'\n' {
+                i += 1 + (src[i] == b'\\') as usize;
+            }
+        } else if c.is_ascii_whitespace() {
+            i += 1;
+        } else if src[i..].starts_with(b"//") {
+            while i < src.len() && src[i] != b'\n' {
+                i += 1;
+            }
+        } else if src[i..].starts_with(b"/*") {
+            let end = src[i + 2..].windows(2).position(|w| w == b"*/").ok_or("Unterminated comment")?;
+
+            i += end + 4;
+        } else if c == b'"' || c == b'\'' {
+            i += 1;
+
+            while i < src.len() && src[i] != c {
+                i += 1 + (src[i] == b'\\') as usize;
+            }
+
+            i += 1;
+
+            if c == b'"' {
+                tokens.push(Tok::Str);
+            } else {
+                tokens.push(Tok::Number(0));
+            }
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            let start = i;
+
+            while i < src.len() && (src[i].is_ascii_alphanumeric() || src[i] == b'_') {
+                i += 1;
+            }
+
+            tokens.push(Tok::Ident(String::from_utf8_lossy(&src[start..i]).into_owned()));
+        } else if c.is_ascii_digit() {
+            let start = i;
+
+            while i < src.len() && (src[i].is_ascii_alphanumeric() || src[i] == b'.') {
+                i += 1;
+            }
+
+            let text = String::from_utf8_lossy(&src[start..i]);
+            let digits = text.trim_end_matches(|c| c == 'u' || c == 'U' || c == 'l' || c == 'L');
+            let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+                u64::from_str_radix(hex, 16)
+            } else {
+                digits.parse()
+            };
+
+            tokens.push(Tok::Number(value.unwrap_or(0)));
+        } else if let Some(p) = PUNCTS.iter().find(|p| src[i..].starts_with(p.as_bytes())) {
+            tokens.push(Tok::Punct(p));
+            i += p.len();
+        } else {
+            return Err(format!("Unknown character '{}'", c as char));
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(src: &str) -> Result<Vec<(String, Ty)>, String> {
+        parse_header(src, &"x86_64-unknown-linux-gnu".parse().unwrap())
+    }
+
+    /// Parses `int a[<len>];` and returns the length of the array.
+    fn array_len(len: &str) -> Result<usize, String> {
+        let decls = parse(&format!("extern int a[{}];", len))?;
+
+        match &decls[0].1.kind {
+            Type::Tuple(tys) => Ok(tys.len()),
+            ty => panic!("expected an array, found {:?}", ty),
+        }
+    }
+
+    #[test]
+    fn constants() {
+        assert_eq!(array_len("1 << 4"), Ok(16));
+        assert_eq!(array_len("(64 >> 2) / 3 % 4"), Ok(1));
+        assert_eq!(array_len("2 + 3 * 4 - (8 | 1) & 7"), Ok(5));
+    }
+
+    #[test]
+    fn invalid_shifts() {
+        assert!(array_len("1 << 64").is_err());
+        assert!(array_len("1 << -1").is_err());
+        assert!(array_len("16 >> 100").is_err());
+    }
+
+    #[test]
+    fn division_overflow() {
+        assert!(array_len("1 / 0").is_err());
+        assert!(array_len("1 % 0").is_err());
+        assert!(array_len("(-9223372036854775807 - 1) / -1").is_err());
+        assert!(array_len("(-9223372036854775807 - 1) % -1").is_err());
+    }
+
+    #[test]
+    fn array_lengths() {
+        assert!(array_len("-1").is_err());
+        assert!(array_len("1 << 40").is_err());
+        assert!(parse("extern int a[1024][1024][1024];").is_err());
+        assert_eq!(array_len("4096"), Ok(4096));
+    }
+}
@@ -0,0 +1,193 @@
+//! What a module needs from the environment it runs or is linked in. Serialized modules and objects record
+//! the capabilities of the module they were built from, so that an artifact that needs more than it is given
+//! is rejected when it is loaded or linked instead of failing once the missing feature is used.
+
+use crate::serialize::{Decode, Encode};
+use crate::visitor::Visitor;
+use crate::*;
+use std::io::{self, Read, Write};
+
+/// The version of the runtime library compiled code calls into. Versions are backwards compatible,
+/// every version of the runtime defines `runtime_symbol` of itself and of every earlier version.
+pub const RUNTIME_VERSION: u32 = 1;
+
+/// The section objects store their capabilities in, encoded by `Capabilities::to_bytes`.
+pub const SECTION: &str = "lowlang_caps";
+
+/// The runtime functions modules import by name, besides the ones intrinsics are lowered to.
+const RUNTIME_IMPORTS: &[&str] = &["type_info_register", "copy_trivial", "move_trivial", "copy_move_nop", "drop_nop"];
+
+const RUNTIME_INTRINSICS: &[&str] = &[
+    "f64_to_str", "i64_to_str", "u64_to_str", "str_to_f64", "str_to_i64", "str_to_u64", "bigint_from_str",
+    "bigint_to_str", "bigint_add", "bigint_mul", "bigint_cmp", "bigint_free", "pool_create", "pool_alloc",
+    "pool_free", "pool_destroy",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Capabilities {
+    pub flags: u32,
+    /// The version of the runtime library that is required, or 0 if the module does not use it.
+    pub runtime: u32,
+}
+
+impl Capabilities {
+    /// Atomic intrinsics, which compiled code calls the `__atomic_*` functions of libatomic for.
+    pub const ATOMICS: u32 = 1;
+    /// 128-bit integers, whose division and multiplication need the helpers of libgcc or compiler-rt.
+    pub const INT128: u32 = 1 << 1;
+    /// Vector types, which compiled code uses the SIMD instructions of the target for.
+    pub const SIMD: u32 = 1 << 2;
+
+    const NAMES: [(u32, &'static str); 3] = [
+        (Capabilities::ATOMICS, "atomics"),
+        (Capabilities::INT128, "128-bit integers"),
+        (Capabilities::SIMD, "SIMD vectors"),
+    ];
+
+    /// Everything this version of the compiler can require.
+    pub fn all() -> Self {
+        Capabilities {
+            flags: Capabilities::ATOMICS | Capabilities::INT128 | Capabilities::SIMD,
+            runtime: RUNTIME_VERSION,
+        }
+    }
+
+    /// The capabilities `module` needs, which only grow as passes run.
+    pub fn of(module: &Module) -> Self {
+        let mut scan = Scan(Capabilities::default());
+
+        scan.visit_module(module);
+
+        let imports_runtime = module
+            .decls
+            .iter()
+            .any(|d| d.linkage == Linkage::Import && RUNTIME_IMPORTS.contains(&d.name.as_str()));
+
+        if imports_runtime {
+            scan.0.runtime = RUNTIME_VERSION;
+        }
+
+        scan.0
+    }
+
+    pub fn has(&self, flag: u32) -> bool {
+        self.flags & flag == flag
+    }
+
+    /// Combines the requirements of two artifacts that are used together.
+    pub fn union(self, other: Capabilities) -> Self {
+        Capabilities {
+            flags: self.flags | other.flags,
+            runtime: self.runtime.max(other.runtime),
+        }
+    }
+
+    /// Describes everything `self` requires that `provided` does not provide.
+    pub fn missing(&self, provided: &Capabilities) -> Vec<String> {
+        let mut missing = Capabilities::NAMES
+            .iter()
+            .filter(|(flag, _)| self.has(*flag) && !provided.has(*flag))
+            .map(|(_, name)| name.to_string())
+            .collect::<Vec<_>>();
+
+        let unknown = self.flags & !Capabilities::all().flags & !provided.flags;
+
+        if unknown != 0 {
+            missing.push(format!("unknown capabilities {:#x}", unknown));
+        }
+
+        if self.runtime > provided.runtime {
+            missing.push(format!("runtime version {}", self.runtime));
+        }
+
+        missing
+    }
+
+    /// Fails with "`what` requires ..." if `provided` lacks anything `self` requires.
+    pub fn check(&self, what: &str, provided: &Capabilities) -> Result<(), String> {
+        match self.missing(provided).as_slice() {
+            [] => Ok(()),
+            missing => Err(format!("{} requires {}", what, missing.join(", "))),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let mut bytes = [0; 8];
+
+        bytes[..4].copy_from_slice(&self.flags.to_le_bytes());
+        bytes[4..].copy_from_slice(&self.runtime.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != 8 {
+            return None;
+        }
+
+        let mut flags = [0; 4];
+        let mut runtime = [0; 4];
+
+        flags.copy_from_slice(&bytes[..4]);
+        runtime.copy_from_slice(&bytes[4..]);
+
+        Some(Capabilities {
+            flags: u32::from_le_bytes(flags),
+            runtime: u32::from_le_bytes(runtime),
+        })
+    }
+}
+
+/// The symbol the runtime library defines to show it provides `version`.
+pub fn runtime_symbol(version: u32) -> String {
+    format!("lowlang_runtime_v{}", version)
+}
+
+/// The version of the runtime `symbol` shows is provided, if it is one of the symbols of `runtime_symbol`.
+pub fn runtime_version_of(symbol: &str) -> Option<u32> {
+    symbol.strip_prefix("lowlang_runtime_v")?.parse().ok()
+}
+
+struct Scan(Capabilities);
+
+impl Visitor for Scan {
+    fn visit_rvalue(&mut self, rvalue: &RValue) {
+        if let RValue::Intrinsic(name, _) = rvalue {
+            if name.starts_with("atomic_") {
+                self.0.flags |= Capabilities::ATOMICS;
+            } else if RUNTIME_INTRINSICS.contains(&name.as_str()) {
+                self.0.runtime = RUNTIME_VERSION;
+            }
+        }
+
+        self.super_rvalue(rvalue);
+    }
+
+    fn visit_type(&mut self, ty: &Ty) {
+        match &ty.kind {
+            Type::I128 | Type::U128 => self.0.flags |= Capabilities::INT128,
+            Type::Vector(elem, _) => {
+                self.0.flags |= Capabilities::SIMD;
+                self.visit_type(elem);
+            }
+            _ => {}
+        }
+
+        self.super_type(ty);
+    }
+}
+
+impl Encode for Capabilities {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.flags.encode(w)?;
+        self.runtime.encode(w)
+    }
+}
+
+impl Decode for Capabilities {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        Ok(Capabilities {
+            flags: Decode::decode(r)?,
+            runtime: Decode::decode(r)?,
+        })
+    }
+}
@@ -0,0 +1,266 @@
+//! The control flow graph of a body: predecessors, dominators and traversal orders of its blocks.
+//!
+//! Unlike `Body::reachable`, the graph includes the edges from calls to their cleanup blocks.
+
+use crate::*;
+use index_vec::IndexVec;
+
+/// The blocks a call can unwind to or the terminator can jump to.
+pub fn edges(block: &BlockData) -> Vec<Block> {
+    let cleanups = block.stmts.iter().filter_map(|stmt| match stmt {
+        Stmt::Call(_, _, _, cleanup) => *cleanup,
+        _ => None,
+    });
+
+    let mut edges = block.successors();
+
+    for cleanup in cleanups {
+        if !edges.contains(&cleanup) {
+            edges.push(cleanup);
+        }
+    }
+
+    edges
+}
+
+#[derive(Debug, Clone)]
+pub struct Predecessors {
+    preds: IndexVec<Block, Vec<Block>>,
+}
+
+impl Predecessors {
+    pub fn new(body: &Body) -> Self {
+        let mut preds = IndexVec::from_vec(vec![Vec::new(); body.blocks.len()]);
+
+        for block in &body.blocks {
+            for succ in edges(block) {
+                if !preds[succ].contains(&block.id) {
+                    preds[succ].push(block.id);
+                }
+            }
+        }
+
+        Predecessors { preds }
+    }
+
+    /// The blocks with an edge to `block`, in the order of the blocks.
+    pub fn of(&self, block: Block) -> &[Block] {
+        &self.preds[block]
+    }
+}
+
+/// Visits the blocks reachable from the entry block, every block after all of its successors
+/// except for those reached through a back edge.
+pub struct PostOrder<'ir> {
+    body: &'ir Body,
+    visited: IndexVec<Block, bool>,
+    /// The blocks being visited, with the successors that have not been visited yet in reverse.
+    stack: Vec<(Block, Vec<Block>)>,
+}
+
+impl<'ir> PostOrder<'ir> {
+    pub fn new(body: &'ir Body) -> Self {
+        let mut visited = IndexVec::from_vec(vec![false; body.blocks.len()]);
+        let mut stack = Vec::new();
+
+        if !body.blocks.is_empty() {
+            let entry = Block::new(0);
+
+            visited[entry] = true;
+            stack.push((entry, reversed_edges(&body.blocks[entry])));
+        }
+
+        PostOrder { body, visited, stack }
+    }
+}
+
+impl Iterator for PostOrder<'_> {
+    type Item = Block;
+
+    fn next(&mut self) -> Option<Block> {
+        loop {
+            let (block, succs) = self.stack.last_mut()?;
+
+            match succs.pop() {
+                Some(succ) if !self.visited[succ] => {
+                    self.visited[succ] = true;
+                    self.stack.push((succ, reversed_edges(&self.body.blocks[succ])));
+                }
+                Some(_) => {}
+                None => {
+                    let block = *block;
+
+                    self.stack.pop();
+                    return Some(block);
+                }
+            }
+        }
+    }
+}
+
+fn reversed_edges(block: &BlockData) -> Vec<Block> {
+    let mut edges = edges(block);
+
+    edges.reverse();
+    edges
+}
+
+/// Visits the blocks reachable from the entry block, every block before its successors
+/// except for those reached through a back edge.
+pub struct ReversePostOrder {
+    blocks: std::iter::Rev<std::vec::IntoIter<Block>>,
+}
+
+impl ReversePostOrder {
+    pub fn new(body: &Body) -> Self {
+        ReversePostOrder {
+            blocks: PostOrder::new(body).collect::<Vec<_>>().into_iter().rev(),
+        }
+    }
+}
+
+impl Iterator for ReversePostOrder {
+    type Item = Block;
+
+    fn next(&mut self) -> Option<Block> {
+        self.blocks.next()
+    }
+}
+
+/// The immediate dominators of the blocks reachable from the entry block, computed with
+/// "A Simple, Fast Dominance Algorithm" by Cooper, Harvey and Kennedy.
+#[derive(Debug, Clone)]
+pub struct DominatorTree {
+    idoms: IndexVec<Block, Option<Block>>,
+    /// The index of every reachable block in the reverse post order.
+    rpo_index: IndexVec<Block, Option<usize>>,
+}
+
+impl DominatorTree {
+    pub fn new(body: &Body, preds: &Predecessors) -> Self {
+        let rpo = ReversePostOrder::new(body).collect::<Vec<_>>();
+        let mut rpo_index = IndexVec::from_vec(vec![None; body.blocks.len()]);
+        let mut idoms = IndexVec::from_vec(vec![None; body.blocks.len()]);
+
+        for (i, block) in rpo.iter().enumerate() {
+            rpo_index[*block] = Some(i);
+        }
+
+        if let Some(entry) = rpo.first() {
+            idoms[*entry] = Some(*entry);
+        }
+
+        let mut changed = true;
+
+        while changed {
+            changed = false;
+
+            for block in rpo.iter().skip(1) {
+                let mut new_idom = None;
+
+                for pred in preds.of(*block) {
+                    if idoms[*pred].is_none() {
+                        continue;
+                    }
+
+                    new_idom = Some(match new_idom {
+                        None => *pred,
+                        Some(idom) => intersect(&idoms, &rpo_index, *pred, idom),
+                    });
+                }
+
+                if new_idom.is_some() && idoms[*block] != new_idom {
+                    idoms[*block] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        DominatorTree { idoms, rpo_index }
+    }
+
+    /// The immediate dominator of `block`, or `None` for the entry block and unreachable blocks.
+    pub fn idom(&self, block: Block) -> Option<Block> {
+        self.idoms[block].filter(|idom| *idom != block)
+    }
+
+    pub fn is_reachable(&self, block: Block) -> bool {
+        self.rpo_index[block].is_some()
+    }
+
+    /// Whether every path from the entry block to `b` goes through `a`. Every block dominates itself,
+    /// unreachable blocks are only dominated by themselves.
+    pub fn dominates(&self, a: Block, mut b: Block) -> bool {
+        loop {
+            if a == b {
+                return true;
+            }
+
+            match self.idom(b) {
+                Some(idom) => b = idom,
+                None => return false,
+            }
+        }
+    }
+}
+
+fn intersect(idoms: &IndexVec<Block, Option<Block>>, rpo_index: &IndexVec<Block, Option<usize>>, mut a: Block, mut b: Block) -> Block {
+    while a != b {
+        while rpo_index[a] > rpo_index[b] {
+            a = idoms[a].unwrap();
+        }
+
+        while rpo_index[b] > rpo_index[a] {
+            b = idoms[b].unwrap();
+        }
+    }
+
+    a
+}
+
+/// Computes the graphs of a body on first use, so passes can share them. A pass that changes the
+/// blocks or terminators of the body must call `invalidate` afterwards.
+#[derive(Debug, Clone, Default)]
+pub struct CfgCache {
+    preds: Option<Predecessors>,
+    rpo: Option<Vec<Block>>,
+    doms: Option<DominatorTree>,
+}
+
+impl CfgCache {
+    pub fn new() -> Self {
+        CfgCache::default()
+    }
+
+    pub fn predecessors(&mut self, body: &Body) -> &Predecessors {
+        self.preds.get_or_insert_with(|| Predecessors::new(body))
+    }
+
+    pub fn reverse_post_order(&mut self, body: &Body) -> &[Block] {
+        self.rpo.get_or_insert_with(|| ReversePostOrder::new(body).collect())
+    }
+
+    pub fn dominators(&mut self, body: &Body) -> &DominatorTree {
+        if self.doms.is_none() {
+            let doms = DominatorTree::new(body, self.predecessors(body));
+
+            self.doms = Some(doms);
+        }
+
+        self.doms.as_ref().unwrap()
+    }
+
+    pub fn invalidate(&mut self) {
+        *self = CfgCache::default();
+    }
+}
+
+impl Body {
+    pub fn post_order(&self) -> PostOrder<'_> {
+        PostOrder::new(self)
+    }
+
+    pub fn reverse_post_order(&self) -> ReversePostOrder {
+        ReversePostOrder::new(self)
+    }
+}
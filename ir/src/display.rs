@@ -70,44 +70,6 @@ impl Display for Module {
             Ok(())
         }
 
-        fn fmt_const(c: &Const, f: &mut Formatter, this: &Module) -> Result {
-            match c {
-                Const::Undefined(ty) => write!(f, "\x1B[0;32mundefined\x1B[0m :: {}", ty),
-                Const::Scalar(s, ty) => write!(f, "\x1B[0;32m{}\x1B[0m :: {}", s, ty),
-                Const::Addr(decl) => write!(f, "\x1B[0;34m@{}\x1B[0m", this.decls[*decl].name),
-                Const::Ptr(to) => {
-                    write!(f, "&")?;
-                    fmt_const(to, f, this)
-                }
-                Const::Tuple(cs) => {
-                    write!(f, "(")?;
-
-                    for (i, c) in cs.iter().enumerate() {
-                        if i != 0 {
-                            write!(f, ", ")?;
-                        }
-
-                        fmt_const(c, f, this)?;
-                    }
-
-                    write!(f, ")")
-                }
-                Const::Variant(i, cs, ty) => {
-                    write!(f, "{}(", i)?;
-
-                    for (i, c) in cs.iter().enumerate() {
-                        if i != 0 {
-                            write!(f, ", ")?;
-                        }
-
-                        fmt_const(c, f, this)?;
-                    }
-
-                    write!(f, ") :: {}", ty)
-                }
-            }
-        }
-
         fn fmt_op(op: &Operand, f: &mut Formatter, this: &Module) -> Result {
             match op {
                 Operand::Place(place) => fmt_place(place, f, this),
@@ -122,10 +84,41 @@ impl Display for Module {
                     write!(f, "\x1B[0;31maddrof\x1B[0m ")?;
                     fmt_place(place, f, self)
                 }
+                RValue::Lea { base, index, scale, offset } => {
+                    write!(f, "\x1B[0;31mlea\x1B[0m ")?;
+                    fmt_op(base, f, self)?;
+
+                    if let Some(index) = index {
+                        write!(f, " + ")?;
+                        fmt_op(index, f, self)?;
+                        write!(f, " * \x1B[0;32m{}\x1B[0m", scale)?;
+                    }
+
+                    if *offset < 0 {
+                        write!(f, " - \x1B[0;32m{}\x1B[0m", offset.unsigned_abs())
+                    } else if *offset > 0 {
+                        write!(f, " + \x1B[0;32m{}\x1B[0m", offset)
+                    } else {
+                        Ok(())
+                    }
+                }
+                RValue::UnOp(op, val) => {
+                    match op {
+                        UnOp::Neg => write!(f, "\x1B[0;31mneg\x1B[0m ")?,
+                        UnOp::Not => write!(f, "\x1B[0;31mnot\x1B[0m ")?,
+                        UnOp::BitNot => write!(f, "\x1B[0;31mbitnot\x1B[0m ")?,
+                    }
+
+                    fmt_op(val, f, self)
+                }
                 RValue::GetDiscr(place) => {
                     write!(f, "\x1B[0;31mget_discr\x1B[0m ")?;
                     fmt_place(place, f, self)
                 }
+                RValue::NullOp(op, ty) => match op {
+                    NullOp::SizeOf => write!(f, "\x1B[0;31msize_of\x1B[0m {}", ty),
+                    NullOp::AlignOf => write!(f, "\x1B[0;31malign_of\x1B[0m {}", ty),
+                },
                 RValue::Cast(place, ty) => {
                     write!(f, "\x1B[0;31mcast\x1B[0m ")?;
                     fmt_place(place, f, self)?;
@@ -156,12 +149,17 @@ impl Display for Module {
                     write!(f, " = ")?;
                     fmt_rvalue(rvalue, f)
                 }
+                Stmt::Loc(loc) => {
+                    let escaped = loc.file.bytes().flat_map(std::ascii::escape_default).map(char::from).collect::<String>();
+
+                    write!(f, "\x1B[0;31mloc\x1B[0m b\"{}\" {}:{}", escaped, loc.line, loc.col)
+                }
                 Stmt::SetDiscr(place, val) => {
                     write!(f, "\x1B[0;31mset_discr\x1B[0m ")?;
                     fmt_place(place, f, self)?;
                     write!(f, ", \x1B[0;32m{}\x1B[0m", val)
                 }
-                Stmt::Call(rets, func, args) => {
+                Stmt::Call(rets, func, args, cleanup) => {
                     write!(f, "\x1B[0;31mcall\x1B[0m ")?;
                     fmt_op(func, f, self)?;
                     write!(f, "(")?;
@@ -188,6 +186,10 @@ impl Display for Module {
                         }
                     }
 
+                    if let Some(cleanup) = cleanup {
+                        write!(f, " \x1B[0;31munwind\x1B[0m {}", cleanup)?;
+                    }
+
                     Ok(())
                 }
             }
@@ -197,6 +199,7 @@ impl Display for Module {
             match term {
                 Term::Abort => write!(f, "\x1B[0;31mabort\x1B[0m"),
                 Term::Return => write!(f, "\x1B[0;31mreturn\x1B[0m"),
+                Term::Resume => write!(f, "\x1B[0;31mresume\x1B[0m"),
                 Term::Jump(to) => write!(f, "\x1B[0;31mjump\x1B[0m {}", to),
                 Term::Switch(op, vals, blocks) => {
                     write!(f, "\x1B[0;31mswitch\x1B[0m ")?;
@@ -213,11 +216,20 @@ impl Display for Module {
                         blocks[blocks.len() - 1]
                     )
                 }
+                Term::BrIf(op, then, else_) => {
+                    write!(f, "\x1B[0;31mbr_if\x1B[0m ")?;
+                    fmt_op(op, f, self)?;
+                    write!(f, ", {}, {}", then, else_)
+                }
             }
         };
 
         let fmt_block = |block: &BlockData, f: &mut Formatter| -> Result {
-            write!(f, "{}:", block.id)?;
+            match block.hint {
+                BranchHint::Normal => write!(f, "{}:", block.id)?,
+                BranchHint::Likely => write!(f, "{} \x1B[0;35m@likely\x1B[0m:", block.id)?,
+                BranchHint::Cold => write!(f, "{} \x1B[0;35m@cold\x1B[0m:", block.id)?,
+            }
 
             for stmt in &block.stmts {
                 write!(f, "\n    ")?;
@@ -309,6 +321,42 @@ impl Display for Attrs {
             writeln!(f, "\x1B[0;35m@c_abi")?;
         }
 
+        if self.noalias {
+            writeln!(f, "\x1B[0;35m@noalias")?;
+        }
+
+        if self.extern_c_export {
+            writeln!(f, "\x1B[0;35m@extern_c_export")?;
+        }
+
+        match self.visibility {
+            Visibility::Default => {}
+            Visibility::Hidden => writeln!(f, "\x1B[0;35m@hidden")?,
+            Visibility::Protected => writeln!(f, "\x1B[0;35m@protected")?,
+        }
+
+        if let Some(section) = &self.section {
+            let escaped = section.bytes().flat_map(std::ascii::escape_default).map(char::from).collect::<String>();
+
+            writeln!(f, "\x1B[0;35m@section\x1B[0m(\x1B[0;32mb\"{}\"\x1B[0m)", escaped)?;
+        }
+
+        if self.mono {
+            writeln!(f, "\x1B[0;35m@mono")?;
+        }
+
+        if self.hot {
+            writeln!(f, "\x1B[0;35m@hot")?;
+        }
+
+        if self.cold {
+            writeln!(f, "\x1B[0;35m@cold")?;
+        }
+
+        if let Some(align) = self.align {
+            writeln!(f, "\x1B[0;35m@align\x1B[0m(\x1B[0;32m{}\x1B[0m)", align)?;
+        }
+
         Ok(())
     }
 }
@@ -351,70 +399,160 @@ impl Display for Ty {
 impl Display for Type {
     fn fmt(&self, f: &mut Formatter) -> Result {
         match self {
-            Type::U8 => write!(f, "\x1B[0;33mu8\x1B[0m"),
-            Type::U16 => write!(f, "\x1B[0;33mu16\x1B[0m"),
-            Type::U32 => write!(f, "\x1B[0;33mu32\x1B[0m"),
-            Type::U64 => write!(f, "\x1B[0;33mu64\x1B[0m"),
-            Type::U128 => write!(f, "\x1B[0;33mu128\x1B[0m"),
-            Type::I8 => write!(f, "\x1B[0;33mi8\x1B[0m"),
-            Type::I16 => write!(f, "\x1B[0;33mi16\x1B[0m"),
-            Type::I32 => write!(f, "\x1B[0;33mi32\x1B[0m"),
-            Type::I64 => write!(f, "\x1B[0;33mi64\x1B[0m"),
-            Type::I128 => write!(f, "\x1B[0;33mi128\x1B[0m"),
-            Type::F32 => write!(f, "\x1B[0;33mf32\x1B[0m"),
-            Type::F64 => write!(f, "\x1B[0;33mf64\x1B[0m"),
-            Type::Type(t) => write!(f, "\x1B[0;33mtype {}\x1B[0m", t),
-            Type::Vwt(t) => write!(f, "\x1B[0;33mvwt {}\x1B[0m", t),
-            Type::Ptr(to) => write!(f, "*{}", to),
-            Type::Box(to) => write!(f, "&{}", to),
-            Type::Tuple(tys) => write!(
-                f,
-                "({})",
-                tys.iter()
-                    .map(|t| t.to_string())
-                    .collect::<Vec<_>>()
-                    .join(", ")
-            ),
-            Type::Union(tys) => write!(
-                f,
-                "({})",
-                tys.iter()
-                    .map(|t| t.to_string())
-                    .collect::<Vec<_>>()
-                    .join(" | ")
-            ),
-            Type::Tagged(tys) => write!(
-                f,
-                "({})",
-                tys.iter()
-                    .map(|t| t.to_string())
-                    .collect::<Vec<_>>()
-                    .join(" / ")
-            ),
-            Type::Opaque(name) => write!(f, "\x1B[0;33m{}\x1B[0m", name),
+            Type::U8 => keyword(f, "u8"),
+            Type::U16 => keyword(f, "u16"),
+            Type::U32 => keyword(f, "u32"),
+            Type::U64 => keyword(f, "u64"),
+            Type::U128 => keyword(f, "u128"),
+            Type::I8 => keyword(f, "i8"),
+            Type::I16 => keyword(f, "i16"),
+            Type::I32 => keyword(f, "i32"),
+            Type::I64 => keyword(f, "i64"),
+            Type::I128 => keyword(f, "i128"),
+            Type::F32 => keyword(f, "f32"),
+            Type::F64 => keyword(f, "f64"),
+            Type::Bool => keyword(f, "bool"),
+            Type::Type(t) => keyword(f, &format!("type {}", t)),
+            Type::Vwt(t) => keyword(f, &format!("vwt {}", t)),
+            Type::Ptr(to) => {
+                f.write_str("*")?;
+                to.fmt(f)
+            }
+            Type::Box(to) => {
+                f.write_str("&")?;
+                to.fmt(f)
+            }
+            Type::Tuple(tys) => list(f, tys, ", "),
+            Type::Union(tys) => list(f, tys, " | "),
+            Type::Tagged(tys) => list(f, tys, " / "),
+            Type::Opaque(name) => keyword(f, name),
             Type::Func(sig) => sig.fmt(f),
-            Type::Discr(ty) => write!(f, "\x1B[0;33mdiscr\x1B[0m {}", ty),
-            Type::Recurse(i) => write!(f, "\x1B[0;33m\\{}\x1B[0m", i),
+            Type::Discr(ty) => {
+                keyword(f, "discr")?;
+                f.write_str(" ")?;
+                ty.fmt(f)
+            }
+            Type::Vector(elem, lanes) => {
+                keyword(f, &format!("vector {}", lanes))?;
+                f.write_str(" ")?;
+                elem.fmt(f)
+            }
+            Type::Newtype(name, inner) => {
+                keyword(f, &format!("newtype {}", name))?;
+                f.write_str(" ")?;
+                inner.fmt(f)
+            }
+            Type::Recurse(i) => keyword(f, &format!("\\{}", i)),
         }
     }
 }
 
 impl Display for Signature {
     fn fmt(&self, f: &mut Formatter) -> Result {
-        let params = self
-            .params
-            .iter()
-            .map(|t| t.to_string())
-            .collect::<Vec<_>>()
-            .join(", ");
-
-        let rets = self
-            .rets
-            .iter()
-            .map(|t| t.to_string())
-            .collect::<Vec<_>>()
-            .join(", ");
-
-        write!(f, "({}) -> ({})", params, rets)
+        if self.call_conv != CallConv::Fluix {
+            keyword(f, &format!("extern {}", self.call_conv.name()))?;
+            f.write_str(" ")?;
+        }
+
+        f.write_str("(")?;
+
+        for (i, param) in self.params.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+
+            param.fmt(f)?;
+        }
+
+        if self.variadic {
+            f.write_str(if self.params.is_empty() { "..." } else { ", ..." })?;
+        }
+
+        f.write_str(") -> ")?;
+        list(f, &self.rets, ", ")
     }
 }
+
+fn fmt_const(c: &Const, f: &mut Formatter, this: &Module) -> Result {
+    match c {
+        Const::Undefined(ty) => write!(f, "\x1B[0;32mundefined\x1B[0m :: {}", ty),
+        Const::Scalar(s, ty) => write!(f, "\x1B[0;32m{}\x1B[0m :: {}", s, ty),
+        Const::Addr(decl) => write!(f, "\x1B[0;34m@{}\x1B[0m", this.decls[*decl].name),
+        Const::Ptr(to) => {
+            write!(f, "&")?;
+            fmt_const(to, f, this)
+        }
+        Const::Tuple(cs) => {
+            write!(f, "(")?;
+
+            for (i, c) in cs.iter().enumerate() {
+                if i != 0 {
+                    write!(f, ", ")?;
+                }
+
+                fmt_const(c, f, this)?;
+            }
+
+            write!(f, ")")
+        }
+        Const::Variant(i, cs, ty) => {
+            write!(f, "{}(", i)?;
+
+            for (i, c) in cs.iter().enumerate() {
+                if i != 0 {
+                    write!(f, ", ")?;
+                }
+
+                fmt_const(c, f, this)?;
+            }
+
+            write!(f, ") :: {}", ty)
+        }
+        Const::Bytes(bytes) => {
+            let escaped = bytes.iter().flat_map(|b| std::ascii::escape_default(*b)).map(char::from).collect::<String>();
+
+            write!(f, "\x1B[0;32mb\"{}\"\x1B[0m", escaped)
+        }
+    }
+}
+
+/// Renders a constant in the syntax of the text format, naming the declarations it refers to from `module`.
+pub fn display_const(c: &Const, module: &Module) -> String {
+    struct DisplayConst<'a>(&'a Const, &'a Module);
+
+    impl Display for DisplayConst<'_> {
+        fn fmt(&self, f: &mut Formatter) -> Result {
+            fmt_const(self.0, f, self.1)
+        }
+    }
+
+    DisplayConst(c, module).to_string()
+}
+
+/// Renders a type in the syntax of the text format without colors, for symbol names and messages.
+pub fn display_ty(ty: &Ty) -> String {
+    format!("{:#}", ty)
+}
+
+/// Writes a type keyword, colored unless the alternate flag is set.
+fn keyword(f: &mut Formatter, name: &str) -> Result {
+    if f.alternate() {
+        f.write_str(name)
+    } else {
+        write!(f, "\x1B[0;33m{}\x1B[0m", name)
+    }
+}
+
+fn list(f: &mut Formatter, tys: &[Ty], sep: &str) -> Result {
+    f.write_str("(")?;
+
+    for (i, ty) in tys.iter().enumerate() {
+        if i > 0 {
+            f.write_str(sep)?;
+        }
+
+        ty.fmt(f)?;
+    }
+
+    f.write_str(")")
+}
@@ -1,6 +1,7 @@
 use crate::*;
 use index_vec::IndexSlice;
 use linked_hash_set::LinkedHashSet;
+use std::collections::HashSet;
 
 pub struct Diamonds<'ir> {
     blocks: &'ir IndexSlice<Block, [BlockData]>,
@@ -20,6 +21,20 @@ impl Body {
             block: Some(Block::new(0)),
         }
     }
+
+    /// The blocks reachable from the entry block without unwinding into a cleanup block.
+    pub fn reachable(&self) -> HashSet<Block> {
+        let mut seen = HashSet::new();
+        let mut worklist = vec![Block::new(0)];
+
+        while let Some(block) = worklist.pop() {
+            if seen.insert(block) {
+                worklist.extend(self.blocks[block].successors());
+            }
+        }
+
+        seen
+    }
 }
 
 impl BlockData {
@@ -27,6 +42,7 @@ impl BlockData {
         match &self.term {
             Term::Jump(id) => vec![*id],
             Term::Switch(_, _, blocks) => blocks.clone(),
+            Term::BrIf(_, then, else_) => vec![*then, *else_],
             _ => Vec::new(),
         }
     }
@@ -0,0 +1,138 @@
+//! Module interfaces: the exported declarations of a module with their types and layouts, which other
+//! modules import to check their calls against without loading the IR of the whole module.
+
+use crate::serialize::{invalid, Decode, Encode};
+use crate::*;
+use std::io::{self, Read, Write};
+use target_lexicon::Triple;
+
+pub const MAGIC: [u8; 4] = *b"LOWF";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interface {
+    /// A hash of the exports, which changes whenever modules importing the interface need to be checked again.
+    pub hash: u64,
+    pub exports: Vec<Export>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Export {
+    pub name: String,
+    pub ty: Ty,
+    pub c_abi: bool,
+    /// The size and alignment of the type on the target the interface was emitted for.
+    pub size: u64,
+    pub align: u64,
+}
+
+impl Interface {
+    pub fn new(module: &Module, target: &Triple) -> Self {
+        let exports = module
+            .decls
+            .iter()
+            .filter(|d| d.linkage == Linkage::Export)
+            .map(|d| {
+                let layout = layout::layout_of(&d.ty, target);
+
+                Export {
+                    name: d.name.clone(),
+                    ty: d.ty.clone(),
+                    c_abi: d.attrs.c_abi,
+                    size: layout.size.bytes(),
+                    align: layout.align.bytes(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        Interface {
+            hash: serialize::stable_hash(&exports),
+            exports,
+        }
+    }
+
+    pub fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        w.write_all(&MAGIC)?;
+        serialize::VERSION.encode(w)?;
+        self.hash.encode(w)?;
+        self.exports.encode(w)
+    }
+
+    pub fn read_from(r: &mut impl Read) -> io::Result<Interface> {
+        let mut magic = [0; 4];
+
+        r.read_exact(&mut magic)?;
+
+        if magic != MAGIC {
+            return Err(invalid("not a lowlang interface"));
+        }
+
+        let version = u32::decode(r)?;
+
+        if version != serialize::VERSION {
+            return Err(invalid(format!("unsupported interface version {}, expected {}", version, serialize::VERSION)));
+        }
+
+        let hash = u64::decode(r)?;
+        let exports = Vec::<Export>::decode(r)?;
+
+        if hash != serialize::stable_hash(&exports) {
+            return Err(invalid("the hash of the interface does not match its exports"));
+        }
+
+        Ok(Interface { hash, exports })
+    }
+}
+
+impl Module {
+    /// Declares the exports of `interface` as imports, checking the imports that are already declared against them.
+    pub fn import_interface(&mut self, interface: &Interface) -> Result<(), String> {
+        for export in &interface.exports {
+            match self.decls.iter().find(|d| d.name == export.name) {
+                Some(decl) if decl.linkage != Linkage::Import => {
+                    return Err(format!("'{}' is defined in the module and exported by an interface", export.name));
+                }
+                Some(decl) if decl.ty != export.ty => {
+                    return Err(format!(
+                        "'{}' is imported as {}, but the interface exports it as {}",
+                        export.name,
+                        display_ty(&decl.ty),
+                        display_ty(&export.ty)
+                    ));
+                }
+                Some(decl) if decl.attrs.c_abi != export.c_abi => {
+                    return Err(format!("'{}' is imported with a different calling convention than it is exported with", export.name));
+                }
+                Some(_) => {}
+                None => {
+                    let decl = self.declare_global(export.name.clone(), Linkage::Import, export.ty.clone());
+
+                    self.decls[decl].attrs.c_abi = export.c_abi;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Encode for Export {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.name.encode(w)?;
+        self.ty.encode(w)?;
+        self.c_abi.encode(w)?;
+        self.size.encode(w)?;
+        self.align.encode(w)
+    }
+}
+
+impl Decode for Export {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        Ok(Export {
+            name: Decode::decode(r)?,
+            ty: Decode::decode(r)?,
+            c_abi: Decode::decode(r)?,
+            size: Decode::decode(r)?,
+            align: Decode::decode(r)?,
+        })
+    }
+}
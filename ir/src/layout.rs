@@ -3,6 +3,7 @@ use std::convert::{TryFrom, TryInto};
 use std::ops::{Add, Mul, RangeInclusive};
 use target_lexicon::{PointerWidth, Triple};
 
+/// Computes the layout of `ty`, which must pass `check_recursion`. The parser checks every type it reads.
 pub fn layout_of(ty: &Ty, target: &Triple) -> TyLayout {
     let scalar_unit = |value: Primitive| {
         let bits = value.size(target).bits();
@@ -31,7 +32,7 @@ pub fn layout_of(ty: &Ty, target: &Triple) -> TyLayout {
     };
 
     let mut layout = match &ty.access().kind {
-        Type::Recurse(..) => unreachable!(),
+        Type::Recurse(depth) => panic!("recursive type refers to \\{}, which is not one of its parents", depth),
         Type::U8 => scalar(Primitive::Int(Integer::I8, false)),
         Type::U16 => scalar(Primitive::Int(Integer::I16, false)),
         Type::U32 => scalar(Primitive::Int(Integer::I32, false)),
@@ -44,6 +45,12 @@ pub fn layout_of(ty: &Ty, target: &Triple) -> TyLayout {
         Type::I128 => scalar(Primitive::Int(Integer::I128, true)),
         Type::F32 => scalar(Primitive::F32),
         Type::F64 => scalar(Primitive::F64),
+        Type::Bool => {
+            let mut scalar = scalar_unit(Primitive::Int(Integer::I8, false));
+
+            scalar.valid_range = 0..=1;
+            Layout::scalar(scalar, target)
+        }
         Type::Ptr(_) => scalar(Primitive::Pointer),
         Type::Box(_) => {
             let mut scalar = scalar_unit(Primitive::Pointer);
@@ -58,6 +65,9 @@ pub fn layout_of(ty: &Ty, target: &Triple) -> TyLayout {
             Layout::scalar(ptr, target)
         }
         Type::Tuple(tys) => struct_layout(tys.iter().map(|t| layout_of(t, target)).collect(), target),
+        Type::Vector(elem, lanes) => vector_layout(&layout_of(elem, target), *lanes),
+        // the layout is that of the wrapped type, so codegen never sees the newtype
+        Type::Newtype(_, inner) => return layout_of(inner, target),
         Type::Opaque(_) => Layout {
             size: Size::ZERO,
             align: Align::from_bytes(1),
@@ -68,12 +78,12 @@ pub fn layout_of(ty: &Ty, target: &Triple) -> TyLayout {
             largest_niche: None,
         },
         Type::Type(_) => Layout {
-            size: ptr_size * 4,
+            size: ptr_size * 6,
             align: Align::from_bytes(ptr_size.bytes()),
-            stride: ptr_size * 4,
+            stride: ptr_size * 6,
             abi: Abi::Aggregate { sized: true },
             fields: FieldsShape::Arbitrary {
-                offsets: vec![Size::ZERO, ptr_size, ptr_size * 2, ptr_size * 3],
+                offsets: vec![Size::ZERO, ptr_size, ptr_size * 2, ptr_size * 3, ptr_size * 4, ptr_size * 5],
             },
             variants: Variants::Single { index: 0 },
             largest_niche: None,
@@ -132,6 +142,71 @@ pub fn layout_of(ty: &Ty, target: &Triple) -> TyLayout {
     TyLayout { ty: ty.clone(), layout }
 }
 
+/// Checks that every recursive reference in `ty` refers to one of its parents and that `ty` does not contain
+/// itself without an indirection, so that `layout_of` terminates.
+pub fn check_recursion(ty: &Ty) -> Result<(), String> {
+    // `parents` is the number of types `ty` is nested in, which is how far up a recursive reference can refer to
+    fn unbound(ty: &Ty, parents: usize) -> Option<usize> {
+        match &ty.kind {
+            Type::Recurse(depth) if *depth > parents => Some(*depth),
+            Type::Ptr(to) | Type::Box(to) | Type::Discr(to) | Type::Newtype(_, to) => unbound(to, parents + 1),
+            Type::Tuple(tys) | Type::Union(tys) | Type::Tagged(tys) => tys.iter().find_map(|t| unbound(t, parents + 1)),
+            Type::Func(sig) => sig.params.iter().chain(&sig.rets).find_map(|t| unbound(t, parents + 1)),
+            _ => None,
+        }
+    }
+
+    if let Some(depth) = unbound(ty, 0) {
+        Err(format!("recursive type {} refers to \\{}, which is not one of its parents", crate::display_ty(ty), depth))
+    } else if has_infinite_size(ty) {
+        Err(format!("recursive type {} has an infinite size", crate::display_ty(ty)))
+    } else {
+        Ok(())
+    }
+}
+
+/// Whether `ty` contains itself without a pointer, box or function in between. Recursion through
+/// an indirection is fine, as the layout of a pointee is only computed when it is accessed.
+pub fn has_infinite_size(ty: &Ty) -> bool {
+    // `inline` holds, for every parent on the way, whether it is reached from here without an indirection
+    fn walk(ty: &Ty, inline: &mut Vec<bool>) -> bool {
+        match &ty.kind {
+            Type::Recurse(depth) => *depth <= inline.len() && inline[inline.len() - depth..].iter().all(|d| *d),
+            Type::Tuple(tys) | Type::Union(tys) | Type::Tagged(tys) => walk_inner(tys.iter(), inline, true),
+            Type::Discr(to) => {
+                // the layout of a discriminant is that of the type it is taken of, behind any boxes
+                let mut to = &**to;
+                let len = inline.len();
+
+                while let Type::Box(inner) = &to.kind {
+                    inline.push(true);
+                    to = inner;
+                }
+
+                let infinite = walk_inner(std::iter::once(to), inline, true);
+
+                inline.truncate(len);
+                infinite
+            }
+            Type::Newtype(_, inner) => walk_inner(std::iter::once(&**inner), inline, true),
+            Type::Ptr(to) | Type::Box(to) => walk_inner(std::iter::once(&**to), inline, false),
+            Type::Func(sig) => walk_inner(sig.params.iter().chain(&sig.rets), inline, false),
+            _ => false,
+        }
+    }
+
+    fn walk_inner<'a>(mut tys: impl Iterator<Item = &'a Ty>, inline: &mut Vec<bool>, direct: bool) -> bool {
+        inline.push(direct);
+
+        let infinite = tys.any(|t| walk(t, inline));
+
+        inline.pop();
+        infinite
+    }
+
+    walk(ty, &mut Vec::new())
+}
+
 fn struct_layout(fields: Vec<TyLayout>, target: &Triple) -> Layout {
     let mut align = Align::from_bytes(1);
     let mut offsets = vec![Size::ZERO; fields.len()];
@@ -165,6 +240,33 @@ fn struct_layout(fields: Vec<TyLayout>, target: &Triple) -> Layout {
     }
 }
 
+/// Vectors are laid out like an array of their lanes, aligned to their full size so they can be loaded
+/// into a SIMD register at once. They are aggregates, so they are passed like any other aggregate of their size.
+fn vector_layout(elem: &TyLayout, lanes: u32) -> Layout {
+    match &elem.abi {
+        Abi::Scalar(Scalar { value: Primitive::Pointer, .. }) => panic!("vector lanes must be integers or floats, not pointers"),
+        Abi::Scalar(_) => {}
+        _ => panic!("vector lanes must be integers or floats, not {}", crate::display_ty(&elem.ty)),
+    }
+
+    assert!(lanes.is_power_of_two(), "vectors must have a power of two lanes, not {}", lanes);
+
+    let size = elem.size * u64::from(lanes);
+
+    Layout {
+        size,
+        align: Align::from_bytes(size.bytes()),
+        stride: size,
+        abi: Abi::Aggregate { sized: true },
+        fields: FieldsShape::Array {
+            stride: elem.size,
+            count: u64::from(lanes),
+        },
+        variants: Variants::Single { index: 0 },
+        largest_niche: None,
+    }
+}
+
 fn enum_layout(mut variants: Vec<Layout>, target: &Triple) -> Layout {
     if variants.is_empty() {
         Layout {
@@ -178,12 +280,35 @@ fn enum_layout(mut variants: Vec<Layout>, target: &Triple) -> Layout {
         }
     } else if variants.len() == 1 {
         variants.remove(0)
-    } else {
-        let largest_niche = variants
+    } else if variants.iter().all(|v| v.is_zst() && v.align.bytes() == 1) {
+        // without any payload the enum is just its tag, so it can be passed and kept in a register
+        let last = variants.len() as u128 - 1;
+        let int = [Integer::I8, Integer::I16, Integer::I32, Integer::I64]
             .iter()
-            .filter_map(|v| v.largest_niche.clone())
-            .max_by_key(|niche| niche.available(target));
+            .copied()
+            .find(|int| int.size().truncate(last) == last)
+            .unwrap_or(Integer::I128);
 
+        let tag = Scalar {
+            value: Primitive::Int(int, false),
+            valid_range: 0..=last,
+        };
+
+        for (i, variant) in variants.iter_mut().enumerate() {
+            variant.variants = Variants::Single { index: i };
+        }
+
+        Layout {
+            fields: FieldsShape::Arbitrary { offsets: vec![Size::ZERO] },
+            variants: Variants::Multiple {
+                tag: tag.clone(),
+                tag_encoding: TagEncoding::Direct,
+                variants,
+                tag_field: 0,
+            },
+            ..Layout::scalar(tag, target)
+        }
+    } else {
         for (i, variant) in variants.iter_mut().enumerate() {
             variant.variants = Variants::Single { index: i };
         }
@@ -228,13 +353,33 @@ fn enum_layout(mut variants: Vec<Layout>, target: &Triple) -> Layout {
             })
         };
 
-        let (fields, variants) = if let Some(niche) = largest_niche {
-            if niche.available(target) >= variants.len() as u128 {
-                // @TODO: implement niches
-                no_niche(variants)
-            } else {
-                no_niche(variants)
+        // if only one variant holds data, the others can be encoded in its niche
+        let niche = {
+            let mut sized = variants.iter().enumerate().filter(|(_, v)| v.size != Size::ZERO);
+
+            match (sized.next(), sized.next()) {
+                (Some((i, v)), None) => v.largest_niche.clone().and_then(|niche| {
+                    let start = (0..variants.len()).find(|&j| j != i).unwrap();
+                    let end = (0..variants.len()).rev().find(|&j| j != i).unwrap();
+                    let (niche_start, tag) = niche.reserve(target, (end - start + 1) as u128)?;
+
+                    Some((i, start..=end, niche_start, tag, niche.offset))
+                }),
+                _ => None,
             }
+        };
+
+        let (fields, variants) = if let Some((dataful_variant, niche_variants, niche_start, tag, offset)) = niche {
+            (FieldsShape::Arbitrary { offsets: vec![offset] }, Variants::Multiple {
+                tag,
+                tag_encoding: TagEncoding::Niche {
+                    dataful_variant,
+                    niche_variants,
+                    niche_start,
+                },
+                variants,
+                tag_field: 0,
+            })
         } else {
             no_niche(variants)
         };
@@ -365,6 +510,31 @@ pub fn ptr_sized_int(target: &Triple) -> Ty {
     }
 }
 
+/// Encodes `val` into `bytes` in the byte order of `target`, keeping only as many bytes as `bytes` is long.
+pub fn write_scalar(val: u128, bytes: &mut [u8], target: &Triple) {
+    let size = bytes.len();
+
+    if let Ok(target_lexicon::Endianness::Big) = target.endianness() {
+        bytes.copy_from_slice(&val.to_be_bytes()[16 - size..]);
+    } else {
+        bytes.copy_from_slice(&val.to_le_bytes()[..size]);
+    }
+}
+
+/// Decodes a scalar that was encoded by `write_scalar`.
+pub fn read_scalar(bytes: &[u8], target: &Triple) -> u128 {
+    let size = bytes.len();
+    let mut buf = [0; 16];
+
+    if let Ok(target_lexicon::Endianness::Big) = target.endianness() {
+        buf[16 - size..].copy_from_slice(bytes);
+        u128::from_be_bytes(buf)
+    } else {
+        buf[..size].copy_from_slice(bytes);
+        u128::from_le_bytes(buf)
+    }
+}
+
 pub fn copy_fn_type(t: &String) -> Ty {
     Ty::new(Type::Func(crate::Signature {
         params: vec![
@@ -373,6 +543,8 @@ pub fn copy_fn_type(t: &String) -> Ty {
             Ty::new(Type::Ptr(Box::new(Ty::new(Type::Type(t.clone()))))),
         ],
         rets: Vec::new(),
+        call_conv: crate::CallConv::Fluix,
+        variadic: false,
     }))
 }
 
@@ -383,6 +555,8 @@ pub fn drop_fn_type(t: &String) -> Ty {
             Ty::new(Type::Ptr(Box::new(Ty::new(Type::Type(t.clone()))))),
         ],
         rets: Vec::new(),
+        call_conv: crate::CallConv::Fluix,
+        variadic: false,
     }))
 }
 
@@ -414,8 +588,13 @@ impl TyLayout {
         }
     }
 
-    pub fn element(&self, _target: &Triple) -> Self {
-        unimplemented!();
+    /// The layout of the elements of an array, which is a tuple of which every field has the same type, or of the lanes of a vector.
+    pub fn element(&self, target: &Triple) -> Self {
+        match &self.ty.access().kind {
+            Type::Tuple(tys) if !tys.is_empty() => layout_of(&tys[0], target),
+            Type::Vector(elem, _) => layout_of(elem, target),
+            _ => unreachable!(),
+        }
     }
 
     pub fn field(&self, field: usize, target: &Triple) -> Self {
@@ -434,6 +613,8 @@ impl TyLayout {
             Type::I128 |
             Type::F32 |
             Type::F64 |
+            Type::Bool |
+            Type::Newtype(_, _) |
             Type::Ptr(_) |
             Type::Box(_) |
             Type::Func(_) |
@@ -445,6 +626,8 @@ impl TyLayout {
                 1 => ptr_sized_int(target),
                 2 => ptr_sized_int(target),
                 3 => Ty::new(Type::Ptr(Box::new(Ty::new(Type::Vwt(t.clone()))))),
+                4 => ptr_sized_int(target),
+                5 => Ty::new(Type::Ptr(Box::new(Ty::new(Type::U8)))),
                 _ => unreachable!(),
             },
             Type::Vwt(t) => match field {
@@ -455,6 +638,7 @@ impl TyLayout {
             },
             Type::Tuple(tys) => tys[field].clone(),
             Type::Union(tys) => tys[field].clone(),
+            Type::Vector(elem, _) => (**elem).clone(),
             Type::Tagged(tys) => match self.variants {
                 Variants::Single { index } => layout_of(&tys[index], target).field(field, target).ty.clone(),
                 Variants::Multiple { ref tag, .. } => {
@@ -662,6 +846,15 @@ impl Size {
         self.bytes() * 8
     }
 
+    /// Truncates `val` to the lowest `self.bits()` bits.
+    pub fn truncate(self, val: u128) -> u128 {
+        if self.bits() >= 128 {
+            val
+        } else {
+            val & ((1 << self.bits()) - 1)
+        }
+    }
+
     pub fn align_to(self, align: Align) -> Self {
         let mask = align.bytes() - 1;
 
@@ -771,7 +964,17 @@ impl Primitive {
             Primitive::Int(Integer::I128, _) => Ty::new(Type::I128),
             Primitive::F32 => Ty::new(Type::F32),
             Primitive::F64 => Ty::new(Type::F64),
-            Primitive::Pointer => unreachable!(),
+            Primitive::Pointer => Ty::new(Type::Ptr(Box::new(Ty::tuple(Vec::new())))),
+        }
+    }
+
+    /// The bits of the NaN that NaN results are replaced with when they are canonicalized,
+    /// the quiet NaN with a positive sign and no payload. `None` if this is not a float.
+    pub fn canonical_nan(&self) -> Option<u128> {
+        match self {
+            Primitive::F32 => Some(0x7fc0_0000),
+            Primitive::F64 => Some(0x7ff8_0000_0000_0000),
+            _ => None,
         }
     }
 }
@@ -800,3 +1003,55 @@ impl Integer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ty(kind: Type) -> Ty {
+        Ty::new(kind)
+    }
+
+    fn target() -> Triple {
+        "x86_64-unknown-linux-gnu".parse().unwrap()
+    }
+
+    #[test]
+    fn recursion_through_indirection() {
+        // a list node holding a box of the next node, and one holding a pointer to it
+        for next in vec![Type::Box(Box::new(ty(Type::Recurse(2)))), Type::Ptr(Box::new(ty(Type::Recurse(2))))] {
+            let node = ty(Type::Tuple(vec![ty(Type::I32), ty(next)]));
+
+            assert_eq!(check_recursion(&node), Ok(()));
+            assert_eq!(layout_of(&node, &target()).size.bytes(), 16);
+        }
+    }
+
+    #[test]
+    fn direct_recursion() {
+        let tuple = ty(Type::Tuple(vec![ty(Type::I32), ty(Type::Recurse(1))]));
+        let newtype = ty(Type::Union(vec![ty(Type::I32), ty(Type::Newtype(String::from("n"), Box::new(ty(Type::Recurse(2)))))]));
+        let nested = ty(Type::Tuple(vec![ty(Type::Ptr(Box::new(ty(Type::Tuple(vec![ty(Type::Recurse(1))])))))]));
+
+        assert!(check_recursion(&tuple).unwrap_err().contains("infinite size"));
+        assert!(check_recursion(&newtype).unwrap_err().contains("infinite size"));
+        // the inner tuple contains itself, even though it is behind a pointer
+        assert!(check_recursion(&nested).unwrap_err().contains("infinite size"));
+    }
+
+    #[test]
+    fn unbound_recursion() {
+        let tuple = ty(Type::Tuple(vec![ty(Type::Ptr(Box::new(ty(Type::Recurse(3)))))]));
+
+        assert!(check_recursion(&tuple).unwrap_err().contains("not one of its parents"));
+    }
+
+    #[test]
+    fn parsed_types_are_checked() {
+        let parse = |ty: &str| crate::parser::parse(&format!("local @f :: () -> ()\nfn @f {{\n    var _0 :: {}\n%0:\n    return\n}}\n", ty));
+
+        assert!(parse("(i32, *\\2)").is_ok());
+        assert!(parse("(i32, \\1)").is_err());
+        assert!(parse("(i32, *\\3)").is_err());
+    }
+}
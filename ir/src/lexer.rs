@@ -13,18 +13,25 @@ pub enum Token {
     Dot,
     Star,
     Pipe,
+    Plus,
+    Minus,
     Colon,
     Arrow,
     DblColon,
     Scalar(u128),
+    Bytes(Vec<u8>),
     Identifier(String),
     Decl(String),
     Intrinsic(String),
     Local(String),
     Block(usize),
+    /// A reference to a parent of a recursive type, `\\1` for the closest one.
+    Recurse(usize),
+    /// Never produced by `lex`, but what the parser finds past the last token.
+    Eof,
 }
 
-pub fn lex(src: &str) -> Vec<Token> {
+pub fn lex(src: &str) -> Result<Vec<Token>, String> {
     let mut tokens = Vec::new();
     let mut it = src.chars().peekable();
 
@@ -74,6 +81,10 @@ pub fn lex(src: &str) -> Vec<Token> {
                 it.next();
                 tokens.push(Token::Pipe);
             }
+            '+' => {
+                it.next();
+                tokens.push(Token::Plus);
+            }
             ':' => {
                 it.next();
 
@@ -91,13 +102,13 @@ pub fn lex(src: &str) -> Vec<Token> {
                     it.next();
                     tokens.push(Token::Arrow);
                 } else {
-                    panic!("'-' must be followed by '>'");
+                    tokens.push(Token::Minus);
                 }
             }
             '0'..='9' => {
                 it.next();
 
-                let s = lex_scalar(c, &mut it);
+                let s = lex_scalar(c, &mut it)?;
 
                 tokens.push(Token::Scalar(s));
             }
@@ -111,10 +122,17 @@ pub fn lex(src: &str) -> Vec<Token> {
             '%' => {
                 it.next();
 
-                let s = lex_scalar('0', &mut it);
+                let s = lex_scalar('0', &mut it)?;
 
                 tokens.push(Token::Block(s as usize));
             }
+            '\\' => {
+                it.next();
+
+                let s = lex_scalar('0', &mut it)?;
+
+                tokens.push(Token::Recurse(s as usize));
+            }
             '@' => {
                 it.next();
 
@@ -132,34 +150,72 @@ pub fn lex(src: &str) -> Vec<Token> {
             c if c.is_alphabetic() => {
                 let i = lex_ident(&mut it);
 
-                tokens.push(Token::Identifier(i));
+                if i == "b" && it.peek() == Some(&'"') {
+                    it.next();
+                    tokens.push(Token::Bytes(lex_bytes(&mut it)?));
+                } else {
+                    tokens.push(Token::Identifier(i));
+                }
             }
             c if c.is_whitespace() => {
                 it.next();
             }
             ';' => {
-                while !matches!(it.peek(), Some('\n')) {
+                while !matches!(it.peek(), Some('\n') | None) {
                     it.next();
                 }
             }
-            _ => panic!("Unknown character {:?}", c),
+            _ => return Err(format!("Unknown character {:?}", c)),
         }
     }
 
-    tokens
+    Ok(tokens)
 }
 
-fn lex_scalar(c: char, it: &mut Peekable<impl Iterator<Item = char>>) -> u128 {
+fn lex_scalar(c: char, it: &mut Peekable<impl Iterator<Item = char>>) -> Result<u128, String> {
     let mut res = (c as u32 - '0' as u32) as u128;
 
     while let Some(&c @ '0'..='9') = it.peek() {
         let digit = (c as u32 - '0' as u32) as u128;
 
-        res = res * 10 + digit;
+        res = res.checked_mul(10).and_then(|r| r.checked_add(digit)).ok_or("Number does not fit in 128 bits")?;
         it.next();
     }
 
-    res
+    Ok(res)
+}
+
+fn lex_bytes(it: &mut Peekable<impl Iterator<Item = char>>) -> Result<Vec<u8>, String> {
+    let mut res = Vec::new();
+
+    while let Some(c) = it.next() {
+        match c {
+            '"' => return Ok(res),
+            '\\' => match it.next() {
+                Some('n') => res.push(b'\n'),
+                Some('r') => res.push(b'\r'),
+                Some('t') => res.push(b'\t'),
+                Some('x') => {
+                    let hex = it.by_ref().take(2).collect::<String>();
+
+                    if hex.len() != 2 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                        return Err(format!("Invalid byte escape \\x{}", hex));
+                    }
+
+                    res.push(u8::from_str_radix(&hex, 16).unwrap());
+                }
+                Some(c) => res.push(c as u8),
+                None => break,
+            },
+            c => {
+                let mut buf = [0; 4];
+
+                res.extend(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+
+    Err(String::from("Unterminated byte string"))
 }
 
 fn lex_ident(it: &mut Peekable<impl Iterator<Item = char>>) -> String {
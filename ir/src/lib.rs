@@ -1,14 +1,24 @@
 #![feature(iterator_fold_self)]
 
 pub mod builder;
+#[cfg(feature = "c-header")]
+pub mod c_header;
+pub mod capability;
+pub mod cfg;
 mod display;
 pub mod graph;
+pub mod interface;
 pub mod layout;
 pub(crate) mod lexer;
 pub mod parser;
+pub mod profile;
+pub mod progress;
+pub mod rand;
+pub mod serialize;
 pub mod visitor;
 
 pub use builder::Builder;
+pub use display::{display_const, display_ty};
 use index_vec::IndexVec;
 use std::collections::HashMap;
 
@@ -44,14 +54,49 @@ pub enum Linkage {
     Hidden,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Attrs {
     pub c_abi: bool,
+    pub noalias: bool,
+    /// Export a wrapper following the C calling convention alongside the function.
+    pub extern_c_export: bool,
+    /// The visibility of the symbol of an exported declaration in a shared library.
+    pub visibility: Visibility,
+    /// The section of the object file a global is placed in, instead of the default data section.
+    pub section: Option<String>,
+    /// Specialize a generic function for the types of every call instead of passing it their type info.
+    pub mono: bool,
+    /// The function runs often according to a profile, so its blocks are laid out along its hot path.
+    pub hot: bool,
+    /// The function rarely runs, so it is placed apart from the other functions to keep them close together.
+    pub cold: bool,
+    /// The alignment of the start of the function in bytes, instead of the default of the backend.
+    pub align: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visibility {
+    /// Visible to other images, which may also preempt the definition when compiling position independent code.
+    Default,
+    /// Only visible to the objects linked into the same image, like `Linkage::Hidden`.
+    Hidden,
+    /// Visible to other images, but references from within the image always resolve to this definition.
+    Protected,
 }
 
 impl Default for Attrs {
     fn default() -> Self {
-        Attrs { c_abi: false }
+        Attrs {
+            c_abi: false,
+            noalias: false,
+            extern_c_export: false,
+            visibility: Visibility::Default,
+            section: None,
+            mono: false,
+            hot: false,
+            cold: false,
+            align: None,
+        }
     }
 }
 
@@ -119,6 +164,18 @@ pub struct BlockData {
     pub id: Block,
     pub stmts: Vec<Stmt>,
     pub term: Term,
+    /// How likely the block is to run when it is branched to.
+    pub hint: BranchHint,
+}
+
+/// A hint for the layout of a block, given by frontends or derived from a profile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BranchHint {
+    Normal,
+    /// Branches to the block are usually taken, so it is placed where the code falls through to.
+    Likely,
+    /// The block rarely runs, so it is placed after the other blocks of the function.
+    Cold,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -127,24 +184,79 @@ pub enum Stmt {
     Drop(Local),
     Assign(Place, RValue),
     SetDiscr(Place, u128),
-    Call(Vec<Place>, Operand, Vec<Operand>),
+    /// Calls a function, storing its return values in the places. If the call fails, the frame unwinds to the
+    /// cleanup block when there is one, and to the caller otherwise.
+    Call(Vec<Place>, Operand, Vec<Operand>, Option<Block>),
+    /// Marks the statements after it in the block, and its terminator, as coming from a location in the source
+    /// of the frontend, until the next `Loc`. It has no effect on what the body does.
+    Loc(SourceLoc),
+}
+
+/// A location in the source code a frontend compiled to the module, used to point diagnostics at that code.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SourceLoc {
+    pub file: String,
+    /// The line, starting at 1.
+    pub line: u32,
+    /// The column, starting at 1.
+    pub col: u32,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Term {
     Abort,
     Return,
+    /// Ends a cleanup block, continuing to unwind into the caller.
+    Resume,
     Jump(Block),
+    /// Jumps to the block of the first case equal to the operand, or to the last block if there is none.
+    /// Cases are compared as bit patterns truncated to the size of the operand, so a signed case
+    /// can be written either in that size or sign-extended to 128 bits.
     Switch(Operand, Vec<u128>, Vec<Block>),
+    /// Jumps to the first block if the operand, which must be a bool, is true and to the second block otherwise.
+    BrIf(Operand, Block, Block),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum RValue {
     Use(Operand),
     AddrOf(Place),
+    Lea {
+        base: Operand,
+        index: Option<Operand>,
+        scale: u64,
+        offset: i64,
+    },
+    UnOp(UnOp, Operand),
     GetDiscr(Place),
     Cast(Place, Ty),
     Intrinsic(String, Vec<Operand>),
+    /// A property of a type, computed from its layout. The result has the type of the place it is assigned to.
+    NullOp(NullOp, Ty),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnOp {
+    Neg,
+    Not,
+    BitNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NullOp {
+    SizeOf,
+    AlignOf,
+}
+
+/// The memory ordering of an atomic intrinsic, passed to it as a `u8` constant
+/// in its last operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AtomicOrdering {
+    Relaxed,
+    Acquire,
+    Release,
+    AcqRel,
+    SeqCst,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -167,7 +279,7 @@ pub enum PlaceElem {
     Downcast(usize),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Const {
     Undefined(Ty),
     Scalar(u128, Ty),
@@ -175,21 +287,22 @@ pub enum Const {
     Tuple(Vec<Const>),
     Ptr(Box<Const>),
     Variant(usize, Vec<Const>, Ty),
+    Bytes(Box<[u8]>),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Ty {
     pub info: TyInfo,
     pub kind: Type,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct TyInfo {
     pub abi: Option<layout::Abi>,
     pub valid_range: Option<std::ops::RangeInclusive<u128>>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Type {
     U8,
     U16,
@@ -203,6 +316,8 @@ pub enum Type {
     I128,
     F32,
     F64,
+    /// A boolean stored in a byte that is either 0 or 1, which branches and selects take as their condition.
+    Bool,
     Type(String),
     Vwt(String),
     Opaque(String),
@@ -213,16 +328,72 @@ pub enum Type {
     Tagged(Vec<Ty>),
     Func(Signature),
     Discr(Box<Ty>),
+    /// A SIMD vector of a number of lanes of an integer or float type.
+    Vector(Box<Ty>, u32),
+    /// A named type with the same layout as the type it wraps, which is only distinct from it
+    /// until codegen, where it is erased.
+    Newtype(String, Box<Ty>),
     Recurse(usize),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Signature {
     pub params: Vec<Ty>,
     pub rets: Vec<Ty>,
+    pub call_conv: CallConv,
+    /// Whether any number of arguments may be passed after `params`, which is only allowed for foreign conventions.
+    pub variadic: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CallConv {
+    /// The convention used for calls between lowlang functions.
+    Fluix,
+    /// The C calling convention of the target, for calls to and from foreign code.
+    C,
+    /// A convention optimized for speed that is not ABI stable, only to be used between lowlang functions.
+    Fastcall,
+    /// The Windows x64 convention, regardless of the operating system of the target.
+    Win64,
+    /// The procedure call standard of the arm architecture.
+    Aapcs,
+}
+
+impl CallConv {
+    pub fn name(self) -> &'static str {
+        match self {
+            CallConv::Fluix => "fluix",
+            CallConv::C => "C",
+            CallConv::Fastcall => "fastcall",
+            CallConv::Win64 => "win64",
+            CallConv::Aapcs => "aapcs",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "fluix" => Some(CallConv::Fluix),
+            "C" => Some(CallConv::C),
+            "fastcall" => Some(CallConv::Fastcall),
+            "win64" => Some(CallConv::Win64),
+            "aapcs" => Some(CallConv::Aapcs),
+            _ => None,
+        }
+    }
 }
 
 impl Body {
+    /// The source location of the statement at `loc`, or of the terminator if `loc.stmt` is past the statements,
+    /// which is given by the last `Stmt::Loc` before it in its block.
+    pub fn source_loc(&self, loc: Location) -> Option<&SourceLoc> {
+        let stmts = &self.blocks.get(loc.block)?.stmts;
+
+        stmts[..loc.stmt.min(stmts.len())].iter().rev().find_map(|stmt| match stmt {
+            Stmt::Loc(loc) => Some(loc),
+            _ => None,
+        })
+    }
+
     pub fn new(id: BodyId, decl: DeclId) -> Self {
         Body {
             id,
@@ -255,6 +426,58 @@ impl Body {
     }
 }
 
+impl Module {
+    pub fn declare_global(&mut self, name: impl Into<String>, linkage: Linkage, ty: Ty) -> DeclId {
+        let id = self.decls.next_idx();
+
+        self.decls.push(Decl {
+            id,
+            linkage,
+            name: name.into(),
+            ty,
+            attrs: Attrs::default(),
+        });
+
+        id
+    }
+
+    /// Embeds `bytes` in the object under the exported symbol `name`, placed in `section` if one is given.
+    /// Functions read its address and length with the `resource` intrinsic.
+    pub fn add_resource(&mut self, name: impl Into<String>, bytes: impl Into<Box<[u8]>>, section: Option<&str>) -> DeclId {
+        let bytes = bytes.into();
+        let ty = const_type(self, &Const::Bytes(bytes.clone()));
+        let decl = self.declare_global(name, Linkage::Export, ty);
+
+        self.decls[decl].attrs.section = section.map(String::from);
+        self.define_global(decl, bytes);
+        decl
+    }
+
+    /// Defines the global `decl` to be initialized with `bytes`.
+    pub fn define_global(&mut self, decl: DeclId, bytes: impl Into<Box<[u8]>>) -> BodyId {
+        let id = self.bodies.next_idx();
+        let ret = Local::new(0);
+        let mut locals = IndexVec::new();
+        let mut blocks = IndexVec::new();
+
+        locals.push(LocalData {
+            id: ret,
+            kind: LocalKind::Ret,
+            ty: self.decls[decl].ty.clone(),
+        });
+
+        blocks.push(BlockData {
+            id: Block::new(0),
+            stmts: vec![Stmt::Assign(Place::new(ret), RValue::Use(Operand::Const(Const::Bytes(bytes.into()))))],
+            term: Term::Return,
+            hint: BranchHint::Normal,
+        });
+
+        self.bodies.push(Body { id, decl, locals, blocks });
+        id
+    }
+}
+
 impl Ty {
     pub fn new(kind: Type) -> Self {
         Ty {
@@ -263,6 +486,26 @@ impl Ty {
         }
     }
 
+    pub fn tuple(fields: Vec<Ty>) -> Self {
+        Ty::new(Type::Tuple(fields))
+    }
+
+    pub fn vector(elem: Ty, lanes: u32) -> Self {
+        Ty::new(Type::Vector(Box::new(elem), lanes))
+    }
+
+    pub fn newtype(name: impl Into<String>, inner: Ty) -> Self {
+        Ty::new(Type::Newtype(name.into(), Box::new(inner)))
+    }
+
+    /// The type wrapped by any number of newtypes.
+    pub fn without_newtypes(&self) -> &Ty {
+        match &self.kind {
+            Type::Newtype(_, inner) => inner.without_newtypes(),
+            _ => self,
+        }
+    }
+
     pub fn with_valid_range(mut self, range: std::ops::RangeInclusive<u128>) -> Self {
         self.info.valid_range = Some(range);
         self
@@ -311,6 +554,34 @@ impl Place {
     }
 }
 
+impl AtomicOrdering {
+    pub fn from_operand(op: &Operand) -> Option<Self> {
+        match op {
+            Operand::Const(Const::Scalar(0, _)) => Some(AtomicOrdering::Relaxed),
+            Operand::Const(Const::Scalar(1, _)) => Some(AtomicOrdering::Acquire),
+            Operand::Const(Const::Scalar(2, _)) => Some(AtomicOrdering::Release),
+            Operand::Const(Const::Scalar(3, _)) => Some(AtomicOrdering::AcqRel),
+            Operand::Const(Const::Scalar(4, _)) => Some(AtomicOrdering::SeqCst),
+            _ => None,
+        }
+    }
+
+    pub fn operand(self) -> Operand {
+        Operand::Const(Const::Scalar(self as u128, Ty::new(Type::U8)))
+    }
+
+    /// Whether this ordering is allowed on an atomic load (`store == false`)
+    /// or an atomic store (`store == true`). Read-modify-write operations accept any ordering.
+    pub fn is_valid_for(self, store: bool) -> bool {
+        match self {
+            AtomicOrdering::Acquire => !store,
+            AtomicOrdering::Release => store,
+            AtomicOrdering::AcqRel => false,
+            AtomicOrdering::Relaxed | AtomicOrdering::SeqCst => true,
+        }
+    }
+}
+
 pub fn operand_type(module: &Module, body: &Body, op: &Operand) -> Ty {
     match op {
         Operand::Place(place) => place_type(body, place),
@@ -329,24 +600,49 @@ pub fn place_type(body: &Body, place: &Place) -> Ty {
             PlaceElem::Deref => match ty.access().kind {
                 Type::Ptr(to) => ty = *to,
                 Type::Box(to) => ty = *to,
+                Type::Newtype(_, inner) => {
+                    ty = *inner;
+                    continue;
+                }
                 _ => unreachable!(),
             },
             PlaceElem::Field(f) => match ty.access().kind {
                 Type::Tuple(mut tys) => ty = tys.swap_remove(*f),
                 Type::Union(mut tys) => ty = tys.swap_remove(*f),
+                Type::Vector(elem, _) => ty = *elem,
+                Type::Box(to) => {
+                    ty = *to;
+                    continue;
+                }
+                Type::Newtype(_, inner) => {
+                    ty = *inner;
+                    continue;
+                }
+                _ => unreachable!(),
+            },
+            PlaceElem::Index(_) => match ty.access().kind {
+                Type::Tuple(mut tys) if !tys.is_empty() => ty = tys.swap_remove(0),
+                Type::Vector(elem, _) => ty = *elem,
                 Type::Box(to) => {
                     ty = *to;
                     continue;
                 }
+                Type::Newtype(_, inner) => {
+                    ty = *inner;
+                    continue;
+                }
                 _ => unreachable!(),
             },
-            PlaceElem::Index(_) => unimplemented!(),
             PlaceElem::Downcast(v) => match ty.access().kind {
                 Type::Tagged(mut tys) => ty = tys.swap_remove(*v),
                 Type::Box(to) => {
                     ty = *to;
                     continue;
                 }
+                Type::Newtype(_, inner) => {
+                    ty = *inner;
+                    continue;
+                }
                 _ => unreachable!(),
             },
         }
@@ -375,6 +671,7 @@ pub fn const_type(module: &Module, c: &Const) -> Ty {
             cs.iter().map(|c| const_type(module, c)).collect(),
         )),
         Const::Variant(_, _, ty) => ty.clone(),
+        Const::Bytes(bytes) => Ty::new(Type::Tuple(vec![Ty::new(Type::U8); bytes.len()])),
     }
 }
 
@@ -382,6 +679,7 @@ impl Type {
     pub fn signature(&self) -> Signature {
         match self {
             Type::Func(sig) => sig.clone(),
+            Type::Newtype(_, inner) => inner.signature(),
             _ => unreachable!(),
         }
     }
@@ -425,6 +723,10 @@ impl Ty {
                 info: self.info.clone(),
                 kind: Type::Discr(Box::new(to.replace(i + 1, with))),
             },
+            Type::Newtype(name, inner) => Ty {
+                info: self.info.clone(),
+                kind: Type::Newtype(name.clone(), Box::new(inner.replace(i + 1, with))),
+            },
             Type::Func(sig) => Ty {
                 info: self.info.clone(),
                 kind: Type::Func(Signature {
@@ -438,6 +740,8 @@ impl Ty {
                         .iter()
                         .map(|t| t.replace(i + 1, with.clone()))
                         .collect(),
+                    call_conv: sig.call_conv,
+                    variadic: sig.variadic,
                 }),
             },
             _ => self.clone(),
@@ -1,9 +1,10 @@
 use crate::lexer::{lex, Token};
 use crate::*;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 
 pub fn parse(src: &str) -> Result<Module, String> {
-    let tokens = lex(src);
+    let tokens = lex(src)?;
     let mut decls = IndexVec::new();
     let mut impls = IndexVec::new();
     let mut bodies = IndexVec::new();
@@ -26,8 +27,9 @@ pub fn parse(src: &str) -> Result<Module, String> {
 
 macro_rules! expect {
     ($tokens:ident, $i:expr, $p:pat $(if $guard:expr)?) => {
-        match &$tokens[$i] {
+        match token($tokens, $i) {
             $p $(if $guard)? => $i + 1,
+            Token::Eof => return Err(String::from("Unexpected end of input")),
             tok => return Err(format!("Unexpected token {:?} at {}", tok, $i)),
         }
     }
@@ -35,24 +37,34 @@ macro_rules! expect {
 
 macro_rules! peek {
     ($tokens:ident, $i:expr, $p:pat $(if $guard:expr)?) => {
-        match &$tokens[$i] {
+        match token($tokens, $i) {
             $p $(if $guard)? => true,
             _ => false,
         }
     }
 }
 
+/// The token at `i`, or `Token::Eof` past the last one, so that running out of tokens is an error like any other.
+fn token(tokens: &[Token], i: usize) -> &Token {
+    static EOF: Token = Token::Eof;
+
+    tokens.get(i).unwrap_or(&EOF)
+}
+
 fn peek_decl(tokens: &[Token], i: usize) -> bool {
-    match &tokens[i] {
+    match token(tokens, i) {
         Token::Identifier(id) => match id.as_str() {
             "export" | "import" | "local" | "hidden" => true,
             _ => false,
         },
+        // attributes are only allowed in front of declarations
+        Token::Decl(_) => true,
         _ => false,
     }
 }
 
 fn parse_decl(tokens: &[Token], i: usize, decls: &mut IndexVec<DeclId, Decl>) -> Result<usize, String> {
+    let (attrs, i) = parse_attrs(tokens, i)?;
     let (linkage, i) = parse_linkage(tokens, i)?;
     let (name, i) = parse_declid(tokens, i)?;
     let i = expect!(tokens, i, Token::DblColon);
@@ -64,14 +76,52 @@ fn parse_decl(tokens: &[Token], i: usize, decls: &mut IndexVec<DeclId, Decl>) ->
         linkage,
         name,
         ty,
-        attrs: Attrs::default(),
+        attrs,
     });
 
     Ok(i)
 }
 
+fn parse_attrs(tokens: &[Token], mut i: usize) -> Result<(Attrs, usize), String> {
+    let mut attrs = Attrs::default();
+
+    while let Token::Decl(name) = token(tokens, i) {
+        match name.as_str() {
+            "c_abi" => attrs.c_abi = true,
+            "noalias" => attrs.noalias = true,
+            "extern_c_export" => attrs.extern_c_export = true,
+            "hidden" => attrs.visibility = Visibility::Hidden,
+            "protected" => attrs.visibility = Visibility::Protected,
+            "mono" => attrs.mono = true,
+            "hot" => attrs.hot = true,
+            "cold" => attrs.cold = true,
+            "align" => match &tokens[i + 1..] {
+                [Token::LParen, Token::Scalar(align), Token::RParen, ..] if align.is_power_of_two() => {
+                    attrs.align = Some(*align as u64);
+                    i += 3;
+                }
+                _ => return Err(format!("Expected an alignment that is a power of two at {}", i + 1)),
+            },
+            "section" => match &tokens[i + 1..] {
+                [Token::LParen, Token::Bytes(section), Token::RParen, ..] => {
+                    let section = String::from_utf8(section.clone()).map_err(|_| format!("Section name is not UTF-8 at {}", i))?;
+
+                    attrs.section = Some(section);
+                    i += 3;
+                }
+                _ => return Err(format!("Expected a section name at {}", i + 1)),
+            },
+            _ => return Err(format!("Unknown attribute '{}' at {}", name, i)),
+        }
+
+        i += 1;
+    }
+
+    Ok((attrs, i))
+}
+
 fn parse_linkage(tokens: &[Token], i: usize) -> Result<(Linkage, usize), String> {
-    match &tokens[i] {
+    match token(tokens, i) {
         Token::Identifier(id) => match id.as_str() {
             "export" => Ok((Linkage::Export, i + 1)),
             "import" => Ok((Linkage::Import, i + 1)),
@@ -84,7 +134,7 @@ fn parse_linkage(tokens: &[Token], i: usize) -> Result<(Linkage, usize), String>
 }
 
 fn peek_impl(tokens: &[Token], i: usize) -> bool {
-    match &tokens[i] {
+    match token(tokens, i) {
         Token::Identifier(id) => match id.as_str() {
             "impl" => true,
             _ => false,
@@ -98,7 +148,7 @@ fn parse_impl(tokens: &[Token], i: usize, decls: &IndexVec<DeclId, Decl>, impls:
     let mut i = expect!(tokens, i, Token::LBrace);
     let mut entries = Vec::new();
 
-    while i < tokens.len() && !matches!(tokens[i], Token::RBrace) {
+    while i < tokens.len() && !matches!(*token(tokens, i), Token::RBrace) {
         let (entry, next_i) = parse_impl_entry(tokens, i, decls, impls)?;
 
         entries.push(entry);
@@ -114,7 +164,7 @@ fn parse_impl(tokens: &[Token], i: usize, decls: &IndexVec<DeclId, Decl>, impls:
 }
 
 fn parse_impl_entry(tokens: &[Token], i: usize, decls: &IndexVec<DeclId, Decl>, impls: &IndexVec<ImplId, Impl>) -> Result<(ImplEntry, usize), String> {
-    match &tokens[i] {
+    match token(tokens, i) {
         Token::Identifier(id) => match id.as_str() {
             "base" => {
                 let (name, i) = parse_ident(tokens, i + 1)?;
@@ -145,7 +195,7 @@ fn parse_impl_entry(tokens: &[Token], i: usize, decls: &IndexVec<DeclId, Decl>,
 }
 
 fn peek_body(tokens: &[Token], i: usize) -> bool {
-    match &tokens[i] {
+    match token(tokens, i) {
         Token::Identifier(id) => match id.as_str() {
             "fn" => true,
             _ => false,
@@ -185,7 +235,7 @@ fn parse_body(tokens: &[Token], i: usize, decls: &IndexVec<DeclId, Decl>, bodies
 }
 
 fn peek_local(tokens: &[Token], i: usize) -> bool {
-    match &tokens[i] {
+    match token(tokens, i) {
         Token::Identifier(id) => match id.as_str() {
             "ret" | "arg" | "var" | "tmp" => true,
             _ => false,
@@ -208,7 +258,7 @@ fn parse_local(tokens: &[Token], i: usize, locals: &mut IndexVec<Local, LocalDat
 }
 
 fn parse_local_kind(tokens: &[Token], i: usize) -> Result<(LocalKind, usize), String> {
-    match &tokens[i] {
+    match token(tokens, i) {
         Token::Identifier(id) => match id.as_str() {
             "ret" => Ok((LocalKind::Ret, i + 1)),
             "arg" => Ok((LocalKind::Arg, i + 1)),
@@ -221,14 +271,14 @@ fn parse_local_kind(tokens: &[Token], i: usize) -> Result<(LocalKind, usize), St
 }
 
 fn parse_local_name(tokens: &[Token], i: usize) -> Result<(String, usize), String> {
-    match &tokens[i] {
+    match token(tokens, i) {
         Token::Local(id) => Ok((id.clone(), i + 1)),
         _ => Err(format!("Expected a local id at {}", i)),
     }
 }
 
 fn peek_block(tokens: &[Token], i: usize) -> bool {
-    matches!(tokens[i], Token::Block(_))
+    matches!(*token(tokens, i), Token::Block(_))
 }
 
 fn parse_block(
@@ -239,11 +289,17 @@ fn parse_block(
     blocks: &mut IndexVec<Block, BlockData>,
 ) -> Result<usize, String> {
     let (id, i) = parse_block_id(tokens, i)?;
+    let (hint, i) = match token(tokens, i) {
+        Token::Decl(name) if name == "likely" => (BranchHint::Likely, i + 1),
+        Token::Decl(name) if name == "cold" => (BranchHint::Cold, i + 1),
+        _ => (BranchHint::Normal, i),
+    };
+
     let mut i = expect!(tokens, i, Token::Colon);
     let mut stmts = Vec::new();
     let mut term = Term::Abort;
 
-    while i < tokens.len() && !peek_block(tokens, i) && !matches!(tokens[i], Token::RBrace) {
+    while i < tokens.len() && !peek_block(tokens, i) && !matches!(*token(tokens, i), Token::RBrace) {
         if peek_term(tokens, i) {
             let (t, next_i) = parse_term(tokens, i, decls, locals)?;
 
@@ -258,22 +314,22 @@ fn parse_block(
         }
     }
 
-    blocks.insert(id, BlockData { id, stmts, term });
+    blocks.insert(id, BlockData { id, stmts, term, hint });
 
     Ok(i)
 }
 
 fn parse_block_id(tokens: &[Token], i: usize) -> Result<(Block, usize), String> {
-    match tokens[i] {
+    match *token(tokens, i) {
         Token::Block(b) => Ok((Block::new(b), i + 1)),
         _ => Err(format!("Expected a block id at {}", i)),
     }
 }
 
 fn peek_term(tokens: &[Token], i: usize) -> bool {
-    match &tokens[i] {
+    match token(tokens, i) {
         Token::Identifier(id) => match id.as_str() {
-            "abort" | "return" | "jump" | "switch" => true,
+            "abort" | "return" | "resume" | "jump" | "switch" | "br_if" => true,
             _ => false,
         },
         _ => false,
@@ -286,13 +342,13 @@ fn parse_stmt(tokens: &[Token], i: usize, decls: &IndexVec<DeclId, Decl>, locals
         let mut i = expect!(tokens, i, Token::LParen);
         let mut args = Vec::new();
 
-        while i < tokens.len() && !matches!(tokens[i], Token::RParen) {
+        while i < tokens.len() && !matches!(*token(tokens, i), Token::RParen) {
             let (op, next_i) = parse_operand(tokens, i, decls, locals)?;
 
             i = next_i;
             args.push(op);
 
-            if !matches!(tokens[i], Token::RParen) {
+            if !matches!(*token(tokens, i), Token::RParen) {
                 i = expect!(tokens, i, Token::Comma);
             }
         }
@@ -300,13 +356,13 @@ fn parse_stmt(tokens: &[Token], i: usize, decls: &IndexVec<DeclId, Decl>, locals
         let mut i = expect!(tokens, i, Token::RParen);
         let mut rets = Vec::new();
 
-        if let Token::Arrow = tokens[i] {
+        if let Token::Arrow = *token(tokens, i) {
             let (p, next_i) = parse_place(tokens, i + 1, decls, locals)?;
 
             rets.push(p);
             i = next_i;
 
-            while i < tokens.len() && matches!(tokens[i], Token::Comma) {
+            while i < tokens.len() && matches!(*token(tokens, i), Token::Comma) {
                 let (p, next_i) = parse_place(tokens, i + 1, decls, locals)?;
 
                 rets.push(p);
@@ -314,7 +370,33 @@ fn parse_stmt(tokens: &[Token], i: usize, decls: &IndexVec<DeclId, Decl>, locals
             }
         }
 
-        Ok((Stmt::Call(rets, func, args), i))
+        let (cleanup, i) = if peek!(tokens, i, Token::Identifier(id) if id == "unwind") {
+            let (block, i) = parse_block_id(tokens, i + 1)?;
+
+            (Some(block), i)
+        } else {
+            (None, i)
+        };
+
+        Ok((Stmt::Call(rets, func, args, cleanup), i))
+    } else if peek!(tokens, i, Token::Identifier(id) if id == "loc") {
+        let file = match token(tokens, i + 1) {
+            Token::Bytes(file) => String::from_utf8(file.clone()).map_err(|_| format!("Expected a file name in UTF-8 at {}", i + 1))?,
+            _ => return Err(format!("Expected a file name at {}", i + 1)),
+        };
+
+        match tokens[i + 2..] {
+            [Token::Scalar(line), Token::Colon, Token::Scalar(col), ..] => {
+                let loc = SourceLoc {
+                    file,
+                    line: line as u32,
+                    col: col as u32,
+                };
+
+                Ok((Stmt::Loc(loc), i + 5))
+            }
+            _ => Err(format!("Expected a line and column at {}", i + 2)),
+        }
     } else {
         let (place, i) = parse_place(tokens, i, decls, locals)?;
         let i = expect!(tokens, i, Token::Equals);
@@ -325,25 +407,41 @@ fn parse_stmt(tokens: &[Token], i: usize, decls: &IndexVec<DeclId, Decl>, locals
 }
 
 fn parse_term(tokens: &[Token], i: usize, decls: &IndexVec<DeclId, Decl>, locals: &HashMap<String, Local>) -> Result<(Term, usize), String> {
-    match &tokens[i] {
+    match token(tokens, i) {
         Token::Identifier(id) => match id.as_str() {
             "abort" => Ok((Term::Abort, i + 1)),
             "return" => Ok((Term::Return, i + 1)),
+            "resume" => Ok((Term::Resume, i + 1)),
             "jump" => {
                 let (block, i) = parse_block_id(tokens, i + 1)?;
 
                 Ok((Term::Jump(block), i))
             }
             "switch" => {
-                let (op, i) = parse_operand(tokens, i + 1, decls, locals)?;
+                // stop the operand before the case list, which would otherwise be parsed as an index
+                let cases = (i..tokens.len())
+                    .take_while(|&j| !peek!(tokens, j, Token::Identifier(id) if id == "otherwise"))
+                    .filter(|&j| matches!(*token(tokens, j), Token::LBracket))
+                    .last()
+                    .unwrap_or(tokens.len());
+
+                let (op, i) = parse_operand(&tokens[..cases], i + 1, decls, locals)?;
                 let mut i = expect!(tokens, i, Token::LBracket);
                 let mut vals = Vec::new();
                 let mut blocks = Vec::new();
 
                 while i < tokens.len() && !peek!(tokens, i, Token::Identifier(id) if id == "otherwise") {
-                    let (val, next_i) = parse_scalar(tokens, i)?;
-                    let next_i = expect!(tokens, next_i, Token::Comma);
-                    let (block, next_i) = parse_block_id(tokens, next_i)?;
+                    let (val, next_i) = match token(tokens, i) {
+                        Token::Minus => parse_scalar(tokens, i + 1).map(|(val, i)| (val.wrapping_neg(), i))?,
+                        _ => parse_scalar(tokens, i)?,
+                    };
+
+                    let next_i = expect!(tokens, next_i, Token::Colon);
+                    let (block, mut next_i) = parse_block_id(tokens, next_i)?;
+
+                    if peek!(tokens, next_i, Token::Comma) {
+                        next_i += 1;
+                    }
 
                     i = next_i;
                     vals.push(val);
@@ -352,11 +450,21 @@ fn parse_term(tokens: &[Token], i: usize, decls: &IndexVec<DeclId, Decl>, locals
 
                 let i = expect!(tokens, i, Token::Identifier(id) if id == "otherwise");
                 let (block, i) = parse_block_id(tokens, i)?;
+                let i = expect!(tokens, i, Token::RBracket);
 
                 blocks.push(block);
 
                 Ok((Term::Switch(op, vals, blocks), i))
             }
+            "br_if" => {
+                let (op, i) = parse_operand(tokens, i + 1, decls, locals)?;
+                let i = expect!(tokens, i, Token::Comma);
+                let (then, i) = parse_block_id(tokens, i)?;
+                let i = expect!(tokens, i, Token::Comma);
+                let (else_, i) = parse_block_id(tokens, i)?;
+
+                Ok((Term::BrIf(op, then, else_), i))
+            }
             _ => unreachable!(),
         },
         _ => unreachable!(),
@@ -368,17 +476,88 @@ fn parse_rvalue(tokens: &[Token], i: usize, decls: &IndexVec<DeclId, Decl>, loca
         let (place, i) = parse_place(tokens, i + 1, decls, locals)?;
 
         Ok((RValue::AddrOf(place), i))
-    } else if let Token::Intrinsic(name) = &tokens[i] {
+    } else if peek!(tokens, i, Token::Identifier(id) if id == "lea") {
+        let (base, mut i) = parse_operand(tokens, i + 1, decls, locals)?;
+        let mut index = None;
+        let mut scale = 1;
+        let mut offset = None;
+
+        while i < tokens.len() && matches!(*token(tokens, i), Token::Plus | Token::Minus) {
+            let neg = matches!(*token(tokens, i), Token::Minus);
+
+            if i + 1 >= tokens.len() {
+                return Err(format!("Expected an offset or an index at {}", i + 1));
+            }
+
+            if let (Some(Token::Scalar(s)), false) = (tokens.get(i + 1), i + 2 < tokens.len() && peek!(tokens, i + 2, Token::DblColon)) {
+                if offset.is_some() {
+                    return Err(format!("Duplicate offset at {}", i + 1));
+                }
+
+                let s = i128::try_from(*s).ok().map(|s| if neg { -s } else { s });
+                let s = s.and_then(|s| i64::try_from(s).ok()).ok_or_else(|| format!("Offset out of range at {}", i + 1))?;
+
+                offset = Some(s);
+                i += 2;
+            } else if !neg && index.is_none() {
+                let (idx, next_i) = parse_operand(tokens, i + 1, decls, locals)?;
+                let next_i = expect!(tokens, next_i, Token::Star);
+                let (s, next_i) = parse_scalar(tokens, next_i)?;
+
+                index = Some(idx);
+                scale = u64::try_from(s).map_err(|_| format!("Scale out of range at {}", next_i - 1))?;
+                i = next_i;
+            } else {
+                return Err(format!("Expected an offset at {}", i + 1));
+            }
+        }
+
+        Ok((
+            RValue::Lea {
+                base,
+                index,
+                scale,
+                offset: offset.unwrap_or(0),
+            },
+            i,
+        ))
+    } else if peek!(tokens, i, Token::Identifier(id) if id == "neg" || id == "not" || id == "bitnot") {
+        let op = match token(tokens, i) {
+            Token::Identifier(id) if id == "neg" => UnOp::Neg,
+            Token::Identifier(id) if id == "not" => UnOp::Not,
+            _ => UnOp::BitNot,
+        };
+
+        let (val, i) = parse_operand(tokens, i + 1, decls, locals)?;
+
+        Ok((RValue::UnOp(op, val), i))
+    } else if peek!(tokens, i, Token::Identifier(id) if id == "size_of" || id == "align_of") {
+        let op = match token(tokens, i) {
+            Token::Identifier(id) if id == "size_of" => NullOp::SizeOf,
+            _ => NullOp::AlignOf,
+        };
+
+        let (ty, i) = parse_type(tokens, i + 1)?;
+
+        Ok((RValue::NullOp(op, ty), i))
+    } else if peek!(tokens, i, Token::Identifier(id) if id == "cast") {
+        // converting to and from newtypes is the most common cast written by hand
+        let (place, i) = parse_place(tokens, i + 1, decls, locals)?;
+        let i = expect!(tokens, i, Token::Comma);
+        let (ty, i) = parse_type(tokens, i)?;
+
+        Ok((RValue::Cast(place, ty), i))
+    } else if let Token::Intrinsic(name) = token(tokens, i) {
         let mut i = expect!(tokens, i + 1, Token::LParen);
         let mut args = Vec::new();
 
-        while i < tokens.len() && !matches!(tokens[i], Token::RParen) {
+        while i < tokens.len() && !matches!(*token(tokens, i), Token::RParen) {
             let (arg, next_i) = parse_operand(tokens, i, decls, locals)?;
 
             args.push(arg);
             i = next_i;
 
-            if !matches!(tokens[i], Token::RParen) {
+            if !matches!(*token(tokens, i), Token::RParen) {
                 i = expect!(tokens, i, Token::Comma);
             }
         }
@@ -406,7 +585,7 @@ fn parse_operand(tokens: &[Token], i: usize, decls: &IndexVec<DeclId, Decl>, loc
 }
 
 fn peek_place(tokens: &[Token], i: usize) -> bool {
-    match tokens[i] {
+    match *token(tokens, i) {
         Token::LParen if i + 1 < tokens.len() => peek_place(tokens, i + 1),
         Token::Star => true,
         Token::Local(_) => true,
@@ -422,29 +601,29 @@ fn parse_place(tokens: &[Token], i: usize, decls: &IndexVec<DeclId, Decl>, local
         decls: &IndexVec<DeclId, Decl>,
         locals: &HashMap<String, Local>,
     ) -> Result<(Local, usize), String> {
-        let (local, mut i) = if let Token::LParen = tokens[i] {
+        let (local, mut i) = if let Token::LParen = *token(tokens, i) {
             let (local, i) = rec(tokens, i + 1, elems, decls, locals)?;
             let i = expect!(tokens, i, Token::RParen);
 
             (local, i)
-        } else if let Token::Star = tokens[i] {
+        } else if let Token::Star = *token(tokens, i) {
             elems.push(PlaceElem::Deref);
             rec(tokens, i + 1, elems, decls, locals)?
         } else {
             let (name, i) = parse_local_name(tokens, i)?;
-            let local = locals[&name];
+            let local = *locals.get(&name).ok_or_else(|| format!("Unknown local _{} at {}", name, i - 1))?;
 
             (local, i)
         };
 
-        while i < tokens.len() && matches!(tokens[i], Token::LBracket | Token::Dot) {
-            if let Token::LBracket = tokens[i] {
+        while i < tokens.len() && matches!(*token(tokens, i), Token::LBracket | Token::Dot) {
+            if let Token::LBracket = *token(tokens, i) {
                 let (op, next_i) = parse_operand(tokens, i + 1, decls, locals)?;
 
                 elems.push(PlaceElem::Index(op));
                 i = expect!(tokens, next_i, Token::RBracket);
-            } else if let Token::Dot = tokens[i] {
-                if let Token::Scalar(s) = tokens[i + 1] {
+            } else if let Token::Dot = *token(tokens, i) {
+                if let Token::Scalar(s) = *token(tokens, i + 1) {
                     elems.push(PlaceElem::Field(s as usize));
                     i += 2;
                 } else {
@@ -463,18 +642,18 @@ fn parse_place(tokens: &[Token], i: usize, decls: &IndexVec<DeclId, Decl>, local
 }
 
 fn parse_const(tokens: &[Token], mut i: usize, decls: &IndexVec<DeclId, Decl>) -> Result<(Const, usize), String> {
-    if let Token::LParen = tokens[i] {
+    if let Token::LParen = *token(tokens, i) {
         let mut cs = Vec::new();
 
         i += 1;
 
-        while i < tokens.len() && !matches!(tokens[i], Token::RParen) {
+        while i < tokens.len() && !matches!(*token(tokens, i), Token::RParen) {
             let (c, next_i) = parse_const(tokens, i, decls)?;
 
             cs.push(c);
             i = next_i;
 
-            if !matches!(tokens[i], Token::RParen) {
+            if !matches!(*token(tokens, i), Token::RParen) {
                 i = expect!(tokens, i, Token::Comma);
             }
         }
@@ -482,12 +661,14 @@ fn parse_const(tokens: &[Token], mut i: usize, decls: &IndexVec<DeclId, Decl>) -
         let i = expect!(tokens, i, Token::RParen);
 
         Ok((Const::Tuple(cs), i))
-    } else if let Token::Scalar(s) = tokens[i] {
+    } else if let Token::Scalar(s) = *token(tokens, i) {
         let i = expect!(tokens, i + 1, Token::DblColon);
         let (ty, i) = parse_type(tokens, i)?;
 
         Ok((Const::Scalar(s, ty), i))
-    } else if let Token::Decl(name) = &tokens[i] {
+    } else if let Token::Bytes(bytes) = token(tokens, i) {
+        Ok((Const::Bytes(bytes.clone().into_boxed_slice()), i + 1))
+    } else if let Token::Decl(name) = token(tokens, i) {
         let decl = if let Some(decl) = decls.iter().find(|d| &d.name == name) {
             decl.id
         } else {
@@ -500,7 +681,16 @@ fn parse_const(tokens: &[Token], mut i: usize, decls: &IndexVec<DeclId, Decl>) -
     }
 }
 
-fn parse_type(tokens: &[Token], i: usize) -> Result<(Ty, usize), String> {
+/// Parses a type that is not part of another type, checking that it can be laid out if it is recursive.
+fn parse_type(tokens: &[Token], start: usize) -> Result<(Ty, usize), String> {
+    let (ty, i) = parse_inner_type(tokens, start)?;
+
+    crate::layout::check_recursion(&ty).map_err(|e| format!("Invalid type at {}: {}", start, e))?;
+
+    Ok((ty, i))
+}
+
+fn parse_inner_type(tokens: &[Token], i: usize) -> Result<(Ty, usize), String> {
     let (ty, i) = parse_type_union(tokens, i)?;
 
     Ok((Ty::new(ty), i))
@@ -512,7 +702,7 @@ fn parse_type_union(tokens: &[Token], i: usize) -> Result<(Type, usize), String>
     let tagged = false;
 
     while i < tokens.len() {
-        match tokens[i] {
+        match *token(tokens, i) {
             Token::Pipe if !tagged => {
                 let (next, next_i) = parse_type_func(tokens, i + 1)?;
 
@@ -533,41 +723,95 @@ fn parse_type_union(tokens: &[Token], i: usize) -> Result<(Type, usize), String>
 }
 
 fn parse_type_func(tokens: &[Token], i: usize) -> Result<(Type, usize), String> {
-    let (left, i) = parse_type_atom(tokens, i)?;
+    let (call_conv, i) = if peek!(tokens, i, Token::Identifier(id) if id == "extern") {
+        match tokens.get(i + 1) {
+            Some(Token::Identifier(id)) => match CallConv::from_name(id) {
+                Some(conv) => (conv, i + 2),
+                None => return Err(format!("Unknown calling convention '{}' at {}", id, i + 1)),
+            },
+            _ => return Err(format!("Expected a calling convention at {}", i + 1)),
+        }
+    } else {
+        (CallConv::Fluix, i)
+    };
+
+    let (left, variadic, i) = if let Token::LParen = *token(tokens, i) {
+        parse_params(tokens, i)?
+    } else {
+        let (left, i) = parse_type_atom(tokens, i)?;
+
+        (left, false, i)
+    };
+
+    if variadic && call_conv == CallConv::Fluix {
+        return Err(format!("Variadic functions must use a foreign calling convention at {}", i));
+    }
 
-    if let Token::Arrow = tokens[i] {
+    if let Token::Arrow = *token(tokens, i) {
         if let Type::Tuple(params) = left {
             let (right, i) = parse_type_atom(tokens, i + 1)?;
 
             if let Type::Tuple(rets) = right {
-                Ok((Type::Func(Signature { params, rets }), i))
+                Ok((Type::Func(Signature { params, rets, call_conv, variadic }), i))
             } else {
                 Err(format!("Right hand side of a function arrow must be a list of types at {}", i))
             }
         } else {
             Err(format!("Left hand side of a function arrow must be a list of types at {}", i))
         }
+    } else if call_conv != CallConv::Fluix || variadic {
+        Err(format!("Expected a function type at {}", i))
     } else {
         Ok((left, i))
     }
 }
 
+/// Parses a list of types that may end in `...` if it is the parameter list of a function.
+fn parse_params(tokens: &[Token], i: usize) -> Result<(Type, bool, usize), String> {
+    let mut tys = Vec::new();
+    let mut variadic = false;
+    let mut i = i + 1;
+
+    while !matches!(*token(tokens, i), Token::RParen) {
+        if peek!(tokens, i, Token::Dot) {
+            i = expect!(tokens, i, Token::Dot);
+            i = expect!(tokens, i, Token::Dot);
+            i = expect!(tokens, i, Token::Dot);
+            variadic = true;
+            break;
+        }
+
+        let (ty, next_i) = parse_inner_type(tokens, i)?;
+
+        tys.push(ty);
+        i = next_i;
+
+        if !matches!(*token(tokens, i), Token::RParen) {
+            i = expect!(tokens, i, Token::Comma);
+        }
+    }
+
+    let i = expect!(tokens, i, Token::RParen);
+
+    Ok((Type::Tuple(tys), variadic, i))
+}
+
 fn parse_type_atom(tokens: &[Token], i: usize) -> Result<(Type, usize), String> {
-    if let Token::Star = tokens[i] {
+    if let Token::Star = *token(tokens, i) {
         let (to, i) = parse_type_atom(tokens, i + 1)?;
 
         Ok((Type::Ptr(Box::new(Ty::new(to))), i))
-    } else if let Token::LParen = tokens[i] {
+    } else if let Token::LParen = *token(tokens, i) {
         let mut tys = Vec::new();
         let mut i = i + 1;
 
-        while !matches!(tokens[i], Token::RParen) {
-            let (ty, next_i) = parse_type(tokens, i)?;
+        while !matches!(*token(tokens, i), Token::RParen) {
+            let (ty, next_i) = parse_inner_type(tokens, i)?;
 
             tys.push(ty);
             i = next_i;
 
-            if !matches!(tokens[i], Token::RParen) {
+            if !matches!(*token(tokens, i), Token::RParen) {
                 i = expect!(tokens, i, Token::Comma);
             }
         }
@@ -575,15 +819,23 @@ fn parse_type_atom(tokens: &[Token], i: usize) -> Result<(Type, usize), String>
         let i = expect!(tokens, i, Token::RParen);
 
         Ok((Type::Tuple(tys), i))
-    } else if let Token::Identifier(ref id) = tokens[i] {
+    } else if let Token::Recurse(depth) = *token(tokens, i) {
+        Ok((Type::Recurse(depth), i + 1))
+    } else if let Token::Identifier(ref id) = *token(tokens, i) {
         match id.as_str() {
             "i8" => Ok((Type::I8, i + 1)),
             "i16" => Ok((Type::I16, i + 1)),
             "i32" => Ok((Type::I32, i + 1)),
             "i64" => Ok((Type::I64, i + 1)),
             "i128" => Ok((Type::I128, i + 1)),
+            "u8" => Ok((Type::U8, i + 1)),
+            "u16" => Ok((Type::U16, i + 1)),
+            "u32" => Ok((Type::U32, i + 1)),
+            "u64" => Ok((Type::U64, i + 1)),
+            "u128" => Ok((Type::U128, i + 1)),
             "f32" => Ok((Type::F32, i + 1)),
             "f64" => Ok((Type::F64, i + 1)),
+            "bool" => Ok((Type::Bool, i + 1)),
             "type" => {
                 let (name, i) = parse_ident(tokens, i + 1)?;
 
@@ -594,6 +846,19 @@ fn parse_type_atom(tokens: &[Token], i: usize) -> Result<(Type, usize), String>
 
                 Ok((Type::Vwt(name), i))
             }
+            "vector" => {
+                let (lanes, i) = parse_scalar(tokens, i + 1)?;
+                let lanes = u32::try_from(lanes).map_err(|_| format!("Too many vector lanes at {}", i - 1))?;
+                let (elem, i) = parse_type_atom(tokens, i)?;
+
+                Ok((Type::Vector(Box::new(Ty::new(elem)), lanes), i))
+            }
+            "newtype" => {
+                let (name, i) = parse_ident(tokens, i + 1)?;
+                let (inner, i) = parse_type_atom(tokens, i)?;
+
+                Ok((Type::Newtype(name, Box::new(Ty::new(inner))), i))
+            }
             _ => Ok((Type::Opaque(id.clone()), i + 1)),
         }
     } else {
@@ -602,22 +867,115 @@ fn parse_type_atom(tokens: &[Token], i: usize) -> Result<(Type, usize), String>
 }
 
 fn parse_declid(tokens: &[Token], i: usize) -> Result<(String, usize), String> {
-    match &tokens[i] {
+    match token(tokens, i) {
         Token::Decl(name) => Ok((name.clone(), i + 1)),
         _ => Err(format!("Expected a decl name at {}", i)),
     }
 }
 
 fn parse_ident(tokens: &[Token], i: usize) -> Result<(String, usize), String> {
-    match &tokens[i] {
+    match token(tokens, i) {
         Token::Identifier(name) => Ok((name.clone(), i + 1)),
         _ => Err(format!("Expected a name at {}", i)),
     }
 }
 
 fn parse_scalar(tokens: &[Token], i: usize) -> Result<(u128, usize), String> {
-    match tokens[i] {
+    match *token(tokens, i) {
         Token::Scalar(s) => Ok((s, i + 1)),
         _ => Err(format!("Expected a scalar at {}", i)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "export @main :: (i32) -> (i32)
+local @msg :: *u8
+
+fn @main {
+    ret _0 :: i32
+    arg _1 :: i32
+    tmp _2 :: (i32, *u8)
+%0:
+    _2.1 = @msg
+    switch _1 [0: %1, otherwise %2]
+%1:
+    _0 = #add_i32(_1, 1 :: i32)
+    return
+%2:
+    _0 = 0 :: i32
+    return
+}
+
+fn @msg {
+    ret _0 :: *u8
+%0:
+    _0 = b\"hi\\x00\"
+    return
+}
+";
+
+    #[test]
+    fn unknown_characters() {
+        assert_eq!(parse("local @f :: () -> () $"), Err(String::from("Unknown character '$'")));
+        assert_eq!(parse("local @f :: (i32) -> ()\n`"), Err(String::from("Unknown character '`'")));
+    }
+
+    #[test]
+    fn invalid_escapes() {
+        let parse_bytes = |bytes: &str| parse(&format!("local @s :: *u8\nfn @s {{\n    ret _0 :: *u8\n%0:\n    _0 = b\"{}\"\n    return\n}}\n", bytes));
+
+        assert!(parse_bytes("a\\x41\\n").is_ok());
+        assert_eq!(parse_bytes("\\xg1"), Err(String::from("Invalid byte escape \\xg1")));
+        assert_eq!(parse_bytes("\\x4"), Err(String::from("Invalid byte escape \\x4\"")));
+        assert_eq!(parse("local @s :: *u8\nfn @s {\n    ret _0 :: *u8\n%0:\n    _0 = b\"\\x"), Err(String::from("Invalid byte escape \\x")));
+        assert_eq!(parse("local @s :: *u8\nfn @s {\n    ret _0 :: *u8\n%0:\n    _0 = b\"abc"), Err(String::from("Unterminated byte string")));
+    }
+
+    #[test]
+    fn large_numbers() {
+        assert!(parse("local @f :: () -> ()\nfn @f {\n%0:\n    jump %340282366920938463463374607431768211456\n}\n").is_err());
+    }
+
+    #[test]
+    fn lea() {
+        let parse_lea = |lea: &str| {
+            let src = format!("local @f :: (*u8, u64) -> (*u8)\nfn @f {{\n    ret _0 :: *u8\n    arg _1 :: *u8\n    arg _2 :: u64\n%0:\n    _0 = {}\n    return\n}}\n", lea);
+
+            parse(&src).map(|module| match &module.bodies[BodyId::new(0)].blocks[Block::new(0)].stmts[0] {
+                Stmt::Assign(_, RValue::Lea { index, scale, offset, .. }) => (index.is_some(), *scale, *offset),
+                stmt => panic!("{:?}", stmt),
+            })
+        };
+
+        assert_eq!(parse_lea("lea _1 + _2 * 4 - 8"), Ok((true, 4, -8)));
+        assert_eq!(parse_lea("lea _1 - 9223372036854775808"), Ok((false, 1, i64::MIN)));
+        assert_eq!(parse_lea("lea _1 + 9223372036854775807"), Ok((false, 1, i64::MAX)));
+        assert!(parse_lea("lea _1 + 9223372036854775808").unwrap_err().starts_with("Offset out of range"));
+        assert!(parse_lea("lea _1 - 9223372036854775809").unwrap_err().starts_with("Offset out of range"));
+        assert!(parse_lea("lea _1 + _2 * 18446744073709551616").unwrap_err().starts_with("Scale out of range"));
+        assert!(parse_lea("lea _1 + 1 + 2").unwrap_err().starts_with("Duplicate offset"));
+    }
+
+    #[test]
+    fn unknown_locals() {
+        let err = parse("local @f :: () -> ()\nfn @f {\n    ret _0 :: i32\n%0:\n    _1 = 0 :: i32\n    return\n}\n").unwrap_err();
+
+        assert!(err.starts_with("Unknown local _1"), "{}", err);
+    }
+
+    #[test]
+    fn truncated_input() {
+        assert_eq!(parse(SOURCE).map(|_| ()), Ok(()));
+        assert_eq!(parse("local @f :: (i32) -> (i32"), Err(String::from("Unexpected end of input")));
+        assert!(parse("local @f ::").is_err());
+        assert!(parse("local @f :: *").is_err());
+
+        // every prefix either parses or is an error, never a panic
+        for (end, _) in SOURCE.char_indices() {
+            let _ = parse(&SOURCE[..end]);
+        }
+    }
+}
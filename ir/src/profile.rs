@@ -0,0 +1,128 @@
+//! Execution counts gathered by running a module in the interpreter, which later compilations of the same
+//! module use to tell its hot paths from its cold ones. Functions are identified by name and blocks by index,
+//! so a profile only applies to the module as it was before any pass ran.
+
+use crate::serialize::{invalid, Decode, Encode};
+use crate::*;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+
+pub const MAGIC: [u8; 4] = *b"LOWP";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProfileData {
+    pub funcs: BTreeMap<String, FuncProfile>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FuncProfile {
+    pub calls: u64,
+    /// How often every block was entered, by index.
+    pub blocks: Vec<u64>,
+}
+
+impl ProfileData {
+    pub fn new() -> Self {
+        ProfileData::default()
+    }
+
+    /// Counts a call of `func`, which has `blocks` blocks. Every block is counted from then on, including
+    /// those that never run, so that a function that has changed since the profile was gathered can be detected.
+    pub fn record_call(&mut self, func: &str, blocks: usize) {
+        let profile = self.func_mut(func);
+
+        profile.calls += 1;
+
+        if profile.blocks.len() < blocks {
+            profile.blocks.resize(blocks, 0);
+        }
+    }
+
+    pub fn record_block(&mut self, func: &str, block: Block) {
+        let blocks = &mut self.func_mut(func).blocks;
+
+        if blocks.len() <= block.index() {
+            blocks.resize(block.index() + 1, 0);
+        }
+
+        blocks[block.index()] += 1;
+    }
+
+    /// Adds the counts of `other`, so that several runs make up one profile.
+    pub fn merge(&mut self, other: &ProfileData) {
+        for (name, profile) in &other.funcs {
+            let into = self.func_mut(name);
+
+            into.calls += profile.calls;
+
+            if into.blocks.len() < profile.blocks.len() {
+                into.blocks.resize(profile.blocks.len(), 0);
+            }
+
+            for (into, count) in into.blocks.iter_mut().zip(&profile.blocks) {
+                *into += count;
+            }
+        }
+    }
+
+    /// The number of blocks entered in all functions together.
+    pub fn total(&self) -> u64 {
+        self.funcs.values().map(FuncProfile::total).sum()
+    }
+
+    fn func_mut(&mut self, func: &str) -> &mut FuncProfile {
+        if !self.funcs.contains_key(func) {
+            self.funcs.insert(func.to_string(), FuncProfile::default());
+        }
+
+        self.funcs.get_mut(func).unwrap()
+    }
+
+    pub fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        w.write_all(&MAGIC)?;
+        serialize::VERSION.encode(w)?;
+        self.funcs.len().encode(w)?;
+
+        for (name, profile) in &self.funcs {
+            name.encode(w)?;
+            profile.calls.encode(w)?;
+            profile.blocks.encode(w)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_from(r: &mut impl Read) -> io::Result<ProfileData> {
+        let mut magic = [0; 4];
+
+        r.read_exact(&mut magic)?;
+
+        if magic != MAGIC {
+            return Err(invalid("not a lowlang profile"));
+        }
+
+        let version = u32::decode(r)?;
+
+        if version != serialize::VERSION {
+            return Err(invalid(format!("unsupported profile version {}, expected {}", version, serialize::VERSION)));
+        }
+
+        let mut funcs = BTreeMap::new();
+
+        for _ in 0..usize::decode(r)? {
+            let name = String::decode(r)?;
+            let calls = u64::decode(r)?;
+            let blocks = Vec::<u64>::decode(r)?;
+
+            funcs.insert(name, FuncProfile { calls, blocks });
+        }
+
+        Ok(ProfileData { funcs })
+    }
+}
+
+impl FuncProfile {
+    pub fn total(&self) -> u64 {
+        self.blocks.iter().sum()
+    }
+}
@@ -0,0 +1,206 @@
+use crate::{Body, Decl, DeclId};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Receives events while a module is being optimized and compiled.
+/// All methods default to doing nothing.
+pub trait Progress {
+    fn on_function_start(&mut self, _decl: &Decl) {}
+
+    fn on_function_finish(&mut self, _decl: &Decl, _time: Duration) {}
+
+    fn on_pass_finish(&mut self, _pass: &str, _time: Duration) {}
+
+    /// Called before `on_pass_finish` for every statistic the pass reports.
+    fn on_pass_stat(&mut self, _pass: &str, _decl: &Decl, _stat: &Stat) {}
+
+    /// Whether to report the changes every pass makes with `on_pass_changes`.
+    /// Finding them needs a copy of the module from before the pass, so it is only made when this returns true.
+    fn wants_pass_changes(&self) -> bool {
+        false
+    }
+
+    /// Called before `on_pass_finish` with what the pass changed, if `wants_pass_changes` returns true.
+    fn on_pass_changes(&mut self, _pass: &str, _changes: &PassChanges) {}
+}
+
+/// How a pass changed the statements of a module. Statements are counted per function, so a pass moving
+/// a statement within a function counts neither, and one moving a statement to another function counts both.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PassChanges {
+    pub stmts_added: usize,
+    pub stmts_removed: usize,
+    /// The functions and globals whose body changed, was added or was removed.
+    pub bodies_changed: usize,
+}
+
+/// Collects the time and changes of every pass, printing them as a table.
+/// A pass that runs more than once is listed once, with the sum of its runs.
+#[derive(Debug, Clone, Default)]
+pub struct PassTimings {
+    pub passes: Vec<PassTiming>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PassTiming {
+    pub pass: String,
+    pub time: Duration,
+    pub changes: PassChanges,
+}
+
+/// A number a pass reports about one function, like the bytes it saved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stat {
+    pub decl: DeclId,
+    pub name: &'static str,
+    pub value: u64,
+}
+
+impl PassChanges {
+    /// Compares the bodies of a module from before a pass with the bodies after it.
+    pub fn new(before: &[Body], after: &[Body]) -> Self {
+        let mut changes = PassChanges::default();
+        let count = |body: &Body| body.blocks.iter().map(|b| b.stmts.len()).sum::<usize>();
+
+        for old in before {
+            match after.iter().find(|b| b.decl == old.decl) {
+                Some(new) if new == old => {}
+                Some(new) => {
+                    let (old, new) = (count(old), count(new));
+
+                    changes.stmts_added += new.saturating_sub(old);
+                    changes.stmts_removed += old.saturating_sub(new);
+                    changes.bodies_changed += 1;
+                }
+                None => {
+                    changes.stmts_removed += count(old);
+                    changes.bodies_changed += 1;
+                }
+            }
+        }
+
+        for new in after.iter().filter(|new| !before.iter().any(|b| b.decl == new.decl)) {
+            changes.stmts_added += count(new);
+            changes.bodies_changed += 1;
+        }
+
+        changes
+    }
+}
+
+impl PassTimings {
+    pub fn new() -> Self {
+        PassTimings::default()
+    }
+
+    pub fn total(&self) -> Duration {
+        self.passes.iter().map(|p| p.time).sum()
+    }
+
+    fn entry(&mut self, pass: &str) -> &mut PassTiming {
+        match self.passes.iter().position(|p| p.pass == pass) {
+            Some(i) => &mut self.passes[i],
+            None => {
+                self.passes.push(PassTiming {
+                    pass: pass.to_string(),
+                    time: Duration::default(),
+                    changes: PassChanges::default(),
+                });
+
+                self.passes.last_mut().unwrap()
+            }
+        }
+    }
+}
+
+impl Progress for PassTimings {
+    fn on_pass_finish(&mut self, pass: &str, time: Duration) {
+        self.entry(pass).time += time;
+    }
+
+    fn wants_pass_changes(&self) -> bool {
+        true
+    }
+
+    fn on_pass_changes(&mut self, pass: &str, changes: &PassChanges) {
+        let entry = &mut self.entry(pass).changes;
+
+        entry.stmts_added += changes.stmts_added;
+        entry.stmts_removed += changes.stmts_removed;
+        entry.bodies_changed += changes.bodies_changed;
+    }
+}
+
+impl std::fmt::Display for PassTimings {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let total = self.total();
+
+        writeln!(f, "{:<16} {:>12} {:>6} {:>8} {:>8} {:>8}", "pass", "time", "%", "added", "removed", "changed")?;
+
+        for p in &self.passes {
+            let share = if total.as_nanos() == 0 {
+                0.0
+            } else {
+                p.time.as_secs_f64() / total.as_secs_f64() * 100.0
+            };
+
+            writeln!(
+                f,
+                "{:<16} {:>10.3}ms {:>5.1}% {:>8} {:>8} {:>8}",
+                p.pass,
+                p.time.as_secs_f64() * 1000.0,
+                share,
+                p.changes.stmts_added,
+                p.changes.stmts_removed,
+                p.changes.bodies_changed
+            )?;
+        }
+
+        writeln!(f, "{:<16} {:>10.3}ms", "total", total.as_secs_f64() * 1000.0)
+    }
+}
+
+pub struct NoProgress;
+
+impl Progress for NoProgress {}
+
+/// A shared flag used to stop a compilation from another thread.
+/// Compilation checks it between passes and between functions.
+#[derive(Debug, Default, Clone)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "compilation was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
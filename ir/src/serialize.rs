@@ -0,0 +1,1127 @@
+use crate::layout::{Abi, Integer, Primitive, Scalar};
+use crate::*;
+use std::io::{self, Read, Write};
+use std::ops::RangeInclusive;
+
+pub const MAGIC: [u8; 4] = *b"LOWI";
+pub const VERSION: u32 = 18;
+
+pub trait Encode {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()>;
+}
+
+pub trait Decode: Sized {
+    fn decode(r: &mut dyn Read) -> io::Result<Self>;
+}
+
+impl Module {
+    pub fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        w.write_all(&MAGIC)?;
+        VERSION.encode(w)?;
+        capability::Capabilities::of(self).encode(w)?;
+        self.encode(w)
+    }
+
+    pub fn read_from(r: &mut impl Read) -> io::Result<Module> {
+        Module::read_for(r, &capability::Capabilities::all())
+    }
+
+    /// Reads a module like `read_from`, but fails before decoding it if it requires anything `provided` lacks.
+    pub fn read_for(r: &mut impl Read, provided: &capability::Capabilities) -> io::Result<Module> {
+        let mut magic = [0; 4];
+
+        r.read_exact(&mut magic)?;
+
+        if magic != MAGIC {
+            return Err(invalid("not a lowlang module"));
+        }
+
+        let version = u32::decode(r)?;
+
+        if version != VERSION {
+            return Err(invalid(format!("unsupported module version {}, expected {}", version, VERSION)));
+        }
+
+        capability::Capabilities::decode(r)?.check("the module", provided).map_err(invalid)?;
+
+        Module::decode(r)
+    }
+}
+
+/// FNV-1a over the encoding of `val`, which is the same for every build of the compiler.
+pub fn stable_hash(val: &impl Encode) -> u64 {
+    let mut bytes = Vec::new();
+
+    val.encode(&mut bytes).unwrap();
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| (hash ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3))
+}
+
+pub(crate) fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn invalid_tag<T>(tag: u8, what: &str) -> io::Result<T> {
+    Err(invalid(format!("invalid {} tag {}", what, tag)))
+}
+
+macro_rules! int_impls {
+    ($($ty:ty),*) => {
+        $(
+            impl Encode for $ty {
+                fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+                    w.write_all(&self.to_le_bytes())
+                }
+            }
+
+            impl Decode for $ty {
+                fn decode(r: &mut dyn Read) -> io::Result<Self> {
+                    let mut buf = [0; std::mem::size_of::<$ty>()];
+
+                    r.read_exact(&mut buf)?;
+
+                    Ok(<$ty>::from_le_bytes(buf))
+                }
+            }
+        )*
+    };
+}
+
+int_impls!(u8, u32, u64, u128, i64);
+
+macro_rules! index_impls {
+    ($($ty:ty),*) => {
+        $(
+            impl Encode for $ty {
+                fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+                    self.raw().encode(w)
+                }
+            }
+
+            impl Decode for $ty {
+                fn decode(r: &mut dyn Read) -> io::Result<Self> {
+                    u32::decode(r).map(<$ty>::from_raw)
+                }
+            }
+        )*
+    };
+}
+
+index_impls!(DeclId, ImplId, BodyId, Local, Block);
+
+impl Encode for usize {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        (*self as u64).encode(w)
+    }
+}
+
+impl Decode for usize {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        u64::decode(r).map(|v| v as usize)
+    }
+}
+
+impl Encode for bool {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        (*self as u8).encode(w)
+    }
+}
+
+impl Decode for bool {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        match u8::decode(r)? {
+            | 0 => Ok(false),
+            | 1 => Ok(true),
+            | tag => invalid_tag(tag, "bool"),
+        }
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.len().encode(w)?;
+        w.write_all(self.as_bytes())
+    }
+}
+
+impl Decode for String {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        let len = usize::decode(r)?;
+        let mut buf = Vec::new();
+
+        r.take(len as u64).read_to_end(&mut buf)?;
+
+        if buf.len() != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        String::from_utf8(buf).map_err(|e| invalid(e.to_string()))
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.len().encode(w)?;
+
+        for item in self {
+            item.encode(w)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        let len = usize::decode(r)?;
+        let mut vec = Vec::new();
+
+        for _ in 0..len {
+            vec.push(T::decode(r)?);
+        }
+
+        Ok(vec)
+    }
+}
+
+impl<I: index_vec::Idx, T: Encode> Encode for IndexVec<I, T> {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.raw.encode(w)
+    }
+}
+
+impl<I: index_vec::Idx, T: Decode> Decode for IndexVec<I, T> {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        Vec::decode(r).map(IndexVec::from_vec)
+    }
+}
+
+impl<T: Encode> Encode for Box<T> {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        (**self).encode(w)
+    }
+}
+
+impl<T: Decode> Decode for Box<T> {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        T::decode(r).map(Box::new)
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            | None => 0u8.encode(w),
+            | Some(val) => {
+                1u8.encode(w)?;
+                val.encode(w)
+            },
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        match u8::decode(r)? {
+            | 0 => Ok(None),
+            | 1 => T::decode(r).map(Some),
+            | tag => invalid_tag(tag, "option"),
+        }
+    }
+}
+
+impl Encode for RangeInclusive<u128> {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.start().encode(w)?;
+        self.end().encode(w)
+    }
+}
+
+impl Decode for RangeInclusive<u128> {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        Ok(u128::decode(r)?..=u128::decode(r)?)
+    }
+}
+
+impl Encode for Module {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.decls.encode(w)?;
+        self.impls.encode(w)?;
+        self.bodies.encode(w)
+    }
+}
+
+impl Decode for Module {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        Ok(Module {
+            decls: Decode::decode(r)?,
+            impls: Decode::decode(r)?,
+            bodies: Decode::decode(r)?,
+        })
+    }
+}
+
+impl Encode for Decl {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.id.encode(w)?;
+        self.linkage.encode(w)?;
+        self.name.encode(w)?;
+        self.ty.encode(w)?;
+        self.attrs.encode(w)
+    }
+}
+
+impl Decode for Decl {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        Ok(Decl {
+            id: Decode::decode(r)?,
+            linkage: Decode::decode(r)?,
+            name: Decode::decode(r)?,
+            ty: decode_type(r)?,
+            attrs: Decode::decode(r)?,
+        })
+    }
+}
+
+impl Encode for Linkage {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            | Linkage::Import => 0u8.encode(w),
+            | Linkage::Export => 1u8.encode(w),
+            | Linkage::Local => 2u8.encode(w),
+            | Linkage::Hidden => 3u8.encode(w),
+        }
+    }
+}
+
+impl Decode for Linkage {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        match u8::decode(r)? {
+            | 0 => Ok(Linkage::Import),
+            | 1 => Ok(Linkage::Export),
+            | 2 => Ok(Linkage::Local),
+            | 3 => Ok(Linkage::Hidden),
+            | tag => invalid_tag(tag, "linkage"),
+        }
+    }
+}
+
+impl Encode for Attrs {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.c_abi.encode(w)?;
+        self.noalias.encode(w)?;
+        self.extern_c_export.encode(w)?;
+        self.visibility.encode(w)?;
+        self.section.encode(w)?;
+        self.mono.encode(w)?;
+        self.hot.encode(w)?;
+        self.cold.encode(w)?;
+        self.align.encode(w)
+    }
+}
+
+impl Decode for Attrs {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        Ok(Attrs {
+            c_abi: Decode::decode(r)?,
+            noalias: Decode::decode(r)?,
+            extern_c_export: Decode::decode(r)?,
+            visibility: Decode::decode(r)?,
+            section: Decode::decode(r)?,
+            mono: Decode::decode(r)?,
+            hot: Decode::decode(r)?,
+            cold: Decode::decode(r)?,
+            align: Decode::decode(r)?,
+        })
+    }
+}
+
+impl Encode for Visibility {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            | Visibility::Default => 0u8.encode(w),
+            | Visibility::Hidden => 1u8.encode(w),
+            | Visibility::Protected => 2u8.encode(w),
+        }
+    }
+}
+
+impl Decode for Visibility {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        match u8::decode(r)? {
+            | 0 => Ok(Visibility::Default),
+            | 1 => Ok(Visibility::Hidden),
+            | 2 => Ok(Visibility::Protected),
+            | tag => invalid_tag(tag, "visibility"),
+        }
+    }
+}
+
+impl Encode for Impl {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.id.encode(w)?;
+        self.name.encode(w)?;
+        self.entries.encode(w)
+    }
+}
+
+impl Decode for Impl {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        Ok(Impl {
+            id: Decode::decode(r)?,
+            name: Decode::decode(r)?,
+            entries: Decode::decode(r)?,
+        })
+    }
+}
+
+impl Encode for ImplEntry {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            | ImplEntry::Base(id) => {
+                0u8.encode(w)?;
+                id.encode(w)
+            },
+            | ImplEntry::Func(name, decl) => {
+                1u8.encode(w)?;
+                name.encode(w)?;
+                decl.encode(w)
+            },
+        }
+    }
+}
+
+impl Decode for ImplEntry {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        match u8::decode(r)? {
+            | 0 => Ok(ImplEntry::Base(Decode::decode(r)?)),
+            | 1 => Ok(ImplEntry::Func(Decode::decode(r)?, Decode::decode(r)?)),
+            | tag => invalid_tag(tag, "impl entry"),
+        }
+    }
+}
+
+impl Encode for Body {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.decl.encode(w)?;
+        self.id.encode(w)?;
+        self.locals.encode(w)?;
+        self.blocks.encode(w)
+    }
+}
+
+impl Decode for Body {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        Ok(Body {
+            decl: Decode::decode(r)?,
+            id: Decode::decode(r)?,
+            locals: Decode::decode(r)?,
+            blocks: Decode::decode(r)?,
+        })
+    }
+}
+
+impl Encode for LocalData {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.id.encode(w)?;
+        self.ty.encode(w)?;
+        self.kind.encode(w)
+    }
+}
+
+impl Decode for LocalData {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        Ok(LocalData {
+            id: Decode::decode(r)?,
+            ty: decode_type(r)?,
+            kind: Decode::decode(r)?,
+        })
+    }
+}
+
+/// Decodes the type of a declaration or local, which like a parsed type has to be one that can be laid out.
+fn decode_type(r: &mut dyn Read) -> io::Result<Ty> {
+    let ty = Ty::decode(r)?;
+
+    crate::layout::check_recursion(&ty).map_err(invalid)?;
+
+    Ok(ty)
+}
+
+impl Encode for LocalKind {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            | LocalKind::Ret => 0u8.encode(w),
+            | LocalKind::Arg => 1u8.encode(w),
+            | LocalKind::Var => 2u8.encode(w),
+            | LocalKind::Tmp => 3u8.encode(w),
+        }
+    }
+}
+
+impl Decode for LocalKind {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        match u8::decode(r)? {
+            | 0 => Ok(LocalKind::Ret),
+            | 1 => Ok(LocalKind::Arg),
+            | 2 => Ok(LocalKind::Var),
+            | 3 => Ok(LocalKind::Tmp),
+            | tag => invalid_tag(tag, "local kind"),
+        }
+    }
+}
+
+impl Encode for BlockData {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.id.encode(w)?;
+        self.stmts.encode(w)?;
+        self.term.encode(w)?;
+        self.hint.encode(w)
+    }
+}
+
+impl Decode for BlockData {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        Ok(BlockData {
+            id: Decode::decode(r)?,
+            stmts: Decode::decode(r)?,
+            term: Decode::decode(r)?,
+            hint: Decode::decode(r)?,
+        })
+    }
+}
+
+impl Encode for BranchHint {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            | BranchHint::Normal => 0u8.encode(w),
+            | BranchHint::Likely => 1u8.encode(w),
+            | BranchHint::Cold => 2u8.encode(w),
+        }
+    }
+}
+
+impl Decode for BranchHint {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        match u8::decode(r)? {
+            | 0 => Ok(BranchHint::Normal),
+            | 1 => Ok(BranchHint::Likely),
+            | 2 => Ok(BranchHint::Cold),
+            | tag => invalid_tag(tag, "branch hint"),
+        }
+    }
+}
+
+impl Encode for Stmt {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            | Stmt::Init(local) => {
+                0u8.encode(w)?;
+                local.encode(w)
+            },
+            | Stmt::Drop(local) => {
+                1u8.encode(w)?;
+                local.encode(w)
+            },
+            | Stmt::Assign(place, rvalue) => {
+                2u8.encode(w)?;
+                place.encode(w)?;
+                rvalue.encode(w)
+            },
+            | Stmt::SetDiscr(place, discr) => {
+                3u8.encode(w)?;
+                place.encode(w)?;
+                discr.encode(w)
+            },
+            | Stmt::Call(rets, func, args, cleanup) => {
+                4u8.encode(w)?;
+                rets.encode(w)?;
+                func.encode(w)?;
+                args.encode(w)?;
+                cleanup.encode(w)
+            },
+            | Stmt::Loc(loc) => {
+                5u8.encode(w)?;
+                loc.file.encode(w)?;
+                loc.line.encode(w)?;
+                loc.col.encode(w)
+            },
+        }
+    }
+}
+
+impl Decode for Stmt {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        match u8::decode(r)? {
+            | 0 => Ok(Stmt::Init(Decode::decode(r)?)),
+            | 1 => Ok(Stmt::Drop(Decode::decode(r)?)),
+            | 2 => Ok(Stmt::Assign(Decode::decode(r)?, Decode::decode(r)?)),
+            | 3 => Ok(Stmt::SetDiscr(Decode::decode(r)?, Decode::decode(r)?)),
+            | 4 => Ok(Stmt::Call(Decode::decode(r)?, Decode::decode(r)?, Decode::decode(r)?, Decode::decode(r)?)),
+            | 5 => Ok(Stmt::Loc(SourceLoc {
+                file: Decode::decode(r)?,
+                line: Decode::decode(r)?,
+                col: Decode::decode(r)?,
+            })),
+            | tag => invalid_tag(tag, "statement"),
+        }
+    }
+}
+
+impl Encode for Term {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            | Term::Abort => 0u8.encode(w),
+            | Term::Return => 1u8.encode(w),
+            | Term::Jump(to) => {
+                2u8.encode(w)?;
+                to.encode(w)
+            },
+            | Term::Switch(op, vals, blocks) => {
+                3u8.encode(w)?;
+                op.encode(w)?;
+                vals.encode(w)?;
+                blocks.encode(w)
+            },
+            | Term::BrIf(op, then, else_) => {
+                4u8.encode(w)?;
+                op.encode(w)?;
+                then.encode(w)?;
+                else_.encode(w)
+            },
+            | Term::Resume => 5u8.encode(w),
+        }
+    }
+}
+
+impl Decode for Term {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        match u8::decode(r)? {
+            | 0 => Ok(Term::Abort),
+            | 1 => Ok(Term::Return),
+            | 2 => Ok(Term::Jump(Decode::decode(r)?)),
+            | 3 => Ok(Term::Switch(Decode::decode(r)?, Decode::decode(r)?, Decode::decode(r)?)),
+            | 4 => Ok(Term::BrIf(Decode::decode(r)?, Decode::decode(r)?, Decode::decode(r)?)),
+            | 5 => Ok(Term::Resume),
+            | tag => invalid_tag(tag, "terminator"),
+        }
+    }
+}
+
+impl Encode for RValue {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            | RValue::Use(op) => {
+                0u8.encode(w)?;
+                op.encode(w)
+            },
+            | RValue::AddrOf(place) => {
+                1u8.encode(w)?;
+                place.encode(w)
+            },
+            | RValue::Lea { base, index, scale, offset } => {
+                2u8.encode(w)?;
+                base.encode(w)?;
+                index.encode(w)?;
+                scale.encode(w)?;
+                offset.encode(w)
+            },
+            | RValue::GetDiscr(place) => {
+                3u8.encode(w)?;
+                place.encode(w)
+            },
+            | RValue::Cast(place, ty) => {
+                4u8.encode(w)?;
+                place.encode(w)?;
+                ty.encode(w)
+            },
+            | RValue::Intrinsic(name, args) => {
+                5u8.encode(w)?;
+                name.encode(w)?;
+                args.encode(w)
+            },
+            | RValue::UnOp(op, val) => {
+                6u8.encode(w)?;
+                op.encode(w)?;
+                val.encode(w)
+            },
+            | RValue::NullOp(op, ty) => {
+                7u8.encode(w)?;
+                op.encode(w)?;
+                ty.encode(w)
+            },
+        }
+    }
+}
+
+impl Decode for RValue {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        match u8::decode(r)? {
+            | 0 => Ok(RValue::Use(Decode::decode(r)?)),
+            | 1 => Ok(RValue::AddrOf(Decode::decode(r)?)),
+            | 2 => Ok(RValue::Lea {
+                base: Decode::decode(r)?,
+                index: Decode::decode(r)?,
+                scale: Decode::decode(r)?,
+                offset: Decode::decode(r)?,
+            }),
+            | 3 => Ok(RValue::GetDiscr(Decode::decode(r)?)),
+            | 4 => Ok(RValue::Cast(Decode::decode(r)?, Decode::decode(r)?)),
+            | 5 => Ok(RValue::Intrinsic(Decode::decode(r)?, Decode::decode(r)?)),
+            | 6 => Ok(RValue::UnOp(Decode::decode(r)?, Decode::decode(r)?)),
+            | 7 => Ok(RValue::NullOp(Decode::decode(r)?, Decode::decode(r)?)),
+            | tag => invalid_tag(tag, "rvalue"),
+        }
+    }
+}
+
+impl Encode for UnOp {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            | UnOp::Neg => 0u8.encode(w),
+            | UnOp::Not => 1u8.encode(w),
+            | UnOp::BitNot => 2u8.encode(w),
+        }
+    }
+}
+
+impl Decode for UnOp {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        match u8::decode(r)? {
+            | 0 => Ok(UnOp::Neg),
+            | 1 => Ok(UnOp::Not),
+            | 2 => Ok(UnOp::BitNot),
+            | tag => invalid_tag(tag, "unary operator"),
+        }
+    }
+}
+
+impl Encode for NullOp {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            | NullOp::SizeOf => 0u8.encode(w),
+            | NullOp::AlignOf => 1u8.encode(w),
+        }
+    }
+}
+
+impl Decode for NullOp {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        match u8::decode(r)? {
+            | 0 => Ok(NullOp::SizeOf),
+            | 1 => Ok(NullOp::AlignOf),
+            | tag => invalid_tag(tag, "nullary operator"),
+        }
+    }
+}
+
+impl Encode for Operand {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            | Operand::Place(place) => {
+                0u8.encode(w)?;
+                place.encode(w)
+            },
+            | Operand::Const(c) => {
+                1u8.encode(w)?;
+                c.encode(w)
+            },
+        }
+    }
+}
+
+impl Decode for Operand {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        match u8::decode(r)? {
+            | 0 => Ok(Operand::Place(Decode::decode(r)?)),
+            | 1 => Ok(Operand::Const(Decode::decode(r)?)),
+            | tag => invalid_tag(tag, "operand"),
+        }
+    }
+}
+
+impl Encode for Place {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.local.encode(w)?;
+        self.elems.encode(w)
+    }
+}
+
+impl Decode for Place {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        Ok(Place {
+            local: Decode::decode(r)?,
+            elems: Decode::decode(r)?,
+        })
+    }
+}
+
+impl Encode for PlaceElem {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            | PlaceElem::Deref => 0u8.encode(w),
+            | PlaceElem::Field(idx) => {
+                1u8.encode(w)?;
+                idx.encode(w)
+            },
+            | PlaceElem::Index(op) => {
+                2u8.encode(w)?;
+                op.encode(w)
+            },
+            | PlaceElem::Downcast(idx) => {
+                3u8.encode(w)?;
+                idx.encode(w)
+            },
+        }
+    }
+}
+
+impl Decode for PlaceElem {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        match u8::decode(r)? {
+            | 0 => Ok(PlaceElem::Deref),
+            | 1 => Ok(PlaceElem::Field(Decode::decode(r)?)),
+            | 2 => Ok(PlaceElem::Index(Decode::decode(r)?)),
+            | 3 => Ok(PlaceElem::Downcast(Decode::decode(r)?)),
+            | tag => invalid_tag(tag, "place element"),
+        }
+    }
+}
+
+impl Encode for Const {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            | Const::Undefined(ty) => {
+                0u8.encode(w)?;
+                ty.encode(w)
+            },
+            | Const::Scalar(val, ty) => {
+                1u8.encode(w)?;
+                val.encode(w)?;
+                ty.encode(w)
+            },
+            | Const::Addr(decl) => {
+                2u8.encode(w)?;
+                decl.encode(w)
+            },
+            | Const::Tuple(cs) => {
+                3u8.encode(w)?;
+                cs.encode(w)
+            },
+            | Const::Ptr(to) => {
+                4u8.encode(w)?;
+                to.encode(w)
+            },
+            | Const::Variant(idx, cs, ty) => {
+                5u8.encode(w)?;
+                idx.encode(w)?;
+                cs.encode(w)?;
+                ty.encode(w)
+            },
+            | Const::Bytes(bytes) => {
+                6u8.encode(w)?;
+                bytes.to_vec().encode(w)
+            },
+        }
+    }
+}
+
+impl Decode for Const {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        match u8::decode(r)? {
+            | 0 => Ok(Const::Undefined(Decode::decode(r)?)),
+            | 1 => Ok(Const::Scalar(Decode::decode(r)?, Decode::decode(r)?)),
+            | 2 => Ok(Const::Addr(Decode::decode(r)?)),
+            | 3 => Ok(Const::Tuple(Decode::decode(r)?)),
+            | 4 => Ok(Const::Ptr(Decode::decode(r)?)),
+            | 5 => Ok(Const::Variant(Decode::decode(r)?, Decode::decode(r)?, Decode::decode(r)?)),
+            | 6 => Ok(Const::Bytes(Vec::<u8>::decode(r)?.into_boxed_slice())),
+            | tag => invalid_tag(tag, "constant"),
+        }
+    }
+}
+
+impl Encode for Ty {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.info.encode(w)?;
+        self.kind.encode(w)
+    }
+}
+
+impl Decode for Ty {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        Ok(Ty {
+            info: Decode::decode(r)?,
+            kind: Decode::decode(r)?,
+        })
+    }
+}
+
+impl Encode for TyInfo {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.abi.encode(w)?;
+        self.valid_range.encode(w)
+    }
+}
+
+impl Decode for TyInfo {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        Ok(TyInfo {
+            abi: Decode::decode(r)?,
+            valid_range: Decode::decode(r)?,
+        })
+    }
+}
+
+impl Encode for Type {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            | Type::U8 => 0u8.encode(w),
+            | Type::U16 => 1u8.encode(w),
+            | Type::U32 => 2u8.encode(w),
+            | Type::U64 => 3u8.encode(w),
+            | Type::U128 => 4u8.encode(w),
+            | Type::I8 => 5u8.encode(w),
+            | Type::I16 => 6u8.encode(w),
+            | Type::I32 => 7u8.encode(w),
+            | Type::I64 => 8u8.encode(w),
+            | Type::I128 => 9u8.encode(w),
+            | Type::F32 => 10u8.encode(w),
+            | Type::F64 => 11u8.encode(w),
+            | Type::Type(name) => {
+                12u8.encode(w)?;
+                name.encode(w)
+            },
+            | Type::Vwt(name) => {
+                13u8.encode(w)?;
+                name.encode(w)
+            },
+            | Type::Opaque(name) => {
+                14u8.encode(w)?;
+                name.encode(w)
+            },
+            | Type::Ptr(to) => {
+                15u8.encode(w)?;
+                to.encode(w)
+            },
+            | Type::Box(to) => {
+                16u8.encode(w)?;
+                to.encode(w)
+            },
+            | Type::Tuple(tys) => {
+                17u8.encode(w)?;
+                tys.encode(w)
+            },
+            | Type::Union(tys) => {
+                18u8.encode(w)?;
+                tys.encode(w)
+            },
+            | Type::Tagged(tys) => {
+                19u8.encode(w)?;
+                tys.encode(w)
+            },
+            | Type::Func(sig) => {
+                20u8.encode(w)?;
+                sig.encode(w)
+            },
+            | Type::Discr(ty) => {
+                21u8.encode(w)?;
+                ty.encode(w)
+            },
+            | Type::Recurse(depth) => {
+                22u8.encode(w)?;
+                depth.encode(w)
+            },
+            | Type::Vector(elem, lanes) => {
+                23u8.encode(w)?;
+                elem.encode(w)?;
+                lanes.encode(w)
+            },
+            | Type::Bool => 24u8.encode(w),
+            | Type::Newtype(name, inner) => {
+                25u8.encode(w)?;
+                name.encode(w)?;
+                inner.encode(w)
+            },
+        }
+    }
+}
+
+impl Decode for Type {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        match u8::decode(r)? {
+            | 0 => Ok(Type::U8),
+            | 1 => Ok(Type::U16),
+            | 2 => Ok(Type::U32),
+            | 3 => Ok(Type::U64),
+            | 4 => Ok(Type::U128),
+            | 5 => Ok(Type::I8),
+            | 6 => Ok(Type::I16),
+            | 7 => Ok(Type::I32),
+            | 8 => Ok(Type::I64),
+            | 9 => Ok(Type::I128),
+            | 10 => Ok(Type::F32),
+            | 11 => Ok(Type::F64),
+            | 12 => Ok(Type::Type(Decode::decode(r)?)),
+            | 13 => Ok(Type::Vwt(Decode::decode(r)?)),
+            | 14 => Ok(Type::Opaque(Decode::decode(r)?)),
+            | 15 => Ok(Type::Ptr(Decode::decode(r)?)),
+            | 16 => Ok(Type::Box(Decode::decode(r)?)),
+            | 17 => Ok(Type::Tuple(Decode::decode(r)?)),
+            | 18 => Ok(Type::Union(Decode::decode(r)?)),
+            | 19 => Ok(Type::Tagged(Decode::decode(r)?)),
+            | 20 => Ok(Type::Func(Decode::decode(r)?)),
+            | 21 => Ok(Type::Discr(Decode::decode(r)?)),
+            | 22 => Ok(Type::Recurse(Decode::decode(r)?)),
+            | 23 => Ok(Type::Vector(Decode::decode(r)?, Decode::decode(r)?)),
+            | 24 => Ok(Type::Bool),
+            | 25 => Ok(Type::Newtype(Decode::decode(r)?, Decode::decode(r)?)),
+            | tag => invalid_tag(tag, "type"),
+        }
+    }
+}
+
+impl Encode for Signature {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.params.encode(w)?;
+        self.rets.encode(w)?;
+        self.call_conv.encode(w)?;
+        self.variadic.encode(w)
+    }
+}
+
+impl Decode for Signature {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        Ok(Signature {
+            params: Decode::decode(r)?,
+            rets: Decode::decode(r)?,
+            call_conv: Decode::decode(r)?,
+            variadic: Decode::decode(r)?,
+        })
+    }
+}
+
+impl Encode for CallConv {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            | CallConv::Fluix => 0u8.encode(w),
+            | CallConv::C => 1u8.encode(w),
+            | CallConv::Fastcall => 2u8.encode(w),
+            | CallConv::Win64 => 3u8.encode(w),
+            | CallConv::Aapcs => 4u8.encode(w),
+        }
+    }
+}
+
+impl Decode for CallConv {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        match u8::decode(r)? {
+            | 0 => Ok(CallConv::Fluix),
+            | 1 => Ok(CallConv::C),
+            | 2 => Ok(CallConv::Fastcall),
+            | 3 => Ok(CallConv::Win64),
+            | 4 => Ok(CallConv::Aapcs),
+            | tag => invalid_tag(tag, "calling convention"),
+        }
+    }
+}
+
+impl Encode for Abi {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            | Abi::Uninhabited => 0u8.encode(w),
+            | Abi::Scalar(s) => {
+                1u8.encode(w)?;
+                s.encode(w)
+            },
+            | Abi::ScalarPair(a, b) => {
+                2u8.encode(w)?;
+                a.encode(w)?;
+                b.encode(w)
+            },
+            | Abi::Aggregate { sized } => {
+                3u8.encode(w)?;
+                sized.encode(w)
+            },
+        }
+    }
+}
+
+impl Decode for Abi {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        match u8::decode(r)? {
+            | 0 => Ok(Abi::Uninhabited),
+            | 1 => Ok(Abi::Scalar(Decode::decode(r)?)),
+            | 2 => Ok(Abi::ScalarPair(Decode::decode(r)?, Decode::decode(r)?)),
+            | 3 => Ok(Abi::Aggregate { sized: Decode::decode(r)? }),
+            | tag => invalid_tag(tag, "abi"),
+        }
+    }
+}
+
+impl Encode for Scalar {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.value.encode(w)?;
+        self.valid_range.encode(w)
+    }
+}
+
+impl Decode for Scalar {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        Ok(Scalar {
+            value: Decode::decode(r)?,
+            valid_range: Decode::decode(r)?,
+        })
+    }
+}
+
+impl Encode for Primitive {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            | Primitive::Int(int, signed) => {
+                0u8.encode(w)?;
+                int.encode(w)?;
+                signed.encode(w)
+            },
+            | Primitive::F32 => 1u8.encode(w),
+            | Primitive::F64 => 2u8.encode(w),
+            | Primitive::Pointer => 3u8.encode(w),
+        }
+    }
+}
+
+impl Decode for Primitive {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        match u8::decode(r)? {
+            | 0 => Ok(Primitive::Int(Decode::decode(r)?, Decode::decode(r)?)),
+            | 1 => Ok(Primitive::F32),
+            | 2 => Ok(Primitive::F64),
+            | 3 => Ok(Primitive::Pointer),
+            | tag => invalid_tag(tag, "primitive"),
+        }
+    }
+}
+
+impl Encode for Integer {
+    fn encode(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            | Integer::I8 => 0u8.encode(w),
+            | Integer::I16 => 1u8.encode(w),
+            | Integer::I32 => 2u8.encode(w),
+            | Integer::I64 => 3u8.encode(w),
+            | Integer::I128 => 4u8.encode(w),
+        }
+    }
+}
+
+impl Decode for Integer {
+    fn decode(r: &mut dyn Read) -> io::Result<Self> {
+        match u8::decode(r)? {
+            | 0 => Ok(Integer::I8),
+            | 1 => Ok(Integer::I16),
+            | 2 => Ok(Integer::I32),
+            | 3 => Ok(Integer::I64),
+            | 4 => Ok(Integer::I128),
+            | tag => invalid_tag(tag, "integer"),
+        }
+    }
+}
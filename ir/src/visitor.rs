@@ -111,7 +111,7 @@ macro_rules! define_visitor {
             }
 
             fn super_block(&mut self, block: &$($mut)? BlockData, body: BodyId) {
-                let BlockData { stmts, term, id } = block;
+                let BlockData { stmts, term, id, .. } = block;
 
                 for (i, stmt) in stmts.into_iter().enumerate() {
                     self.visit_stmt(stmt, Location { body, block: *id, stmt: i });
@@ -129,7 +129,8 @@ macro_rules! define_visitor {
                         self.visit_rvalue(rvalue);
                     }
                     Stmt::SetDiscr(place, _) => self.visit_place(place),
-                    Stmt::Call(rets, func, args) => {
+                    Stmt::Loc(_) => {}
+                    Stmt::Call(rets, func, args, _) => {
                         for place in rets {
                             self.visit_place(place);
                         }
@@ -145,7 +146,7 @@ macro_rules! define_visitor {
 
             fn super_term(&mut self, term: &$($mut)? Term) {
                 match term {
-                    Term::Switch(op, _, _) => self.visit_op(op),
+                    Term::Switch(op, _, _) | Term::BrIf(op, _, _) => self.visit_op(op),
                     _ => {},
                 }
             }
@@ -154,11 +155,20 @@ macro_rules! define_visitor {
                 match rvalue {
                     RValue::Use(op) => self.visit_op(op),
                     RValue::AddrOf(place) => self.visit_place(place),
+                    RValue::Lea { base, index, .. } => {
+                        self.visit_op(base);
+
+                        if let Some(index) = index {
+                            self.visit_op(index);
+                        }
+                    }
+                    RValue::UnOp(_, op) => self.visit_op(op),
                     RValue::GetDiscr(place) => self.visit_place(place),
                     RValue::Cast(place, ty) => {
                         self.visit_place(place);
                         self.visit_type(ty);
                     }
+                    RValue::NullOp(_, ty) => self.visit_type(ty),
                     RValue::Intrinsic(_, args) => {
                         for op in args {
                             self.visit_op(op);
@@ -200,6 +210,7 @@ macro_rules! define_visitor {
             fn super_type(&mut self, ty: &$($mut)? Ty) {
                 match &$($mut)? ty.kind {
                     Type::Ptr(ty) => self.visit_type(ty),
+                    Type::Newtype(_, ty) => self.visit_type(ty),
                     Type::Tuple(tys) => {
                         for ty in tys {
                             self.visit_type(ty);
@@ -211,7 +222,7 @@ macro_rules! define_visitor {
                         }
                     },
                     Type::Func(sig) => {
-                        let Signature { params, rets } = sig;
+                        let Signature { params, rets, .. } = sig;
 
                         for ty in params {
                             self.visit_type(ty);
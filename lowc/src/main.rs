@@ -1,15 +1,445 @@
+mod message;
+mod repl;
+
+use eval::stubs::Stubs;
+use ir::progress::{CancellationToken, Progress};
+use message::{Message, MessageFormat, Reporter};
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+
+const USAGE: &str = "usage: lowc <command> <file> [options]
+
+commands:
+    run <file>                interpret the main function and exit with its status
+    build <file> [-o <out>]   compile to an object file, test.o by default or test.wasm with the wasm backend
+    check <file>              parse the file and run the mandatory passes
+    dump <file> [--after <pass>]
+                              print the module after the named pass, or as parsed
+    repl [<file>]             enter declarations and bodies and interpret calls to them one at a time,
+                              starting with those of <file>
+
+options:
+    --message-format=human|json
+    --backend=cranelift|wasm  (build, check) generate code for the host with cranelift, or for wasm32
+    --emit=obj,header,interface
+                              (build) the outputs to write
+    --interface=<file>        import the exports of an interface written by --emit=interface
+    --write-profile=<file>    (run) count the calls and blocks that run, adding to <file> if it exists
+    --use-profile=<file>      lay out and specialize the hot functions of a profile written by run,
+                              and mark the blocks that never ran as cold
+    --standalone              (build) define the witness tables of trivial types
+    --sort-symbols            (build) emit symbols sorted by name for reproducible objects
+    --dump-ir=<dir>           (build) write the generated IR of every function to <dir>
+    --codegen-threads=<n>     (build) compile functions to machine code on <n> threads
+    --cache-dir=<dir>         (build) reuse the machine code of unchanged functions compiled into <dir>
+    --validate                (build) run the exported C functions that take and return integers interpreted and compiled
+                              on generated arguments, failing if they return something else
+    --canonical-nans          replace the NaNs of float operations with one NaN, for the same bits on every host
+    --time-passes             print the time and changes of every pass when done";
+
 fn main() {
-    let mut args = std::env::args();
-    let arg = args.nth(1).unwrap();
-    let source = std::fs::read_to_string(arg).unwrap();
-    let mut module = ir::parser::parse(&source).unwrap();
-    let target = target_lexicon::Triple::host();
+    let mut args = std::env::args().skip(1);
+    let mut format = MessageFormat::Human;
+    let mut input = None;
+    let mut output = None;
+    let mut after = None;
+    let mut emit_obj = true;
+    let mut time_passes = false;
+    let mut emit_header = false;
+    let mut emit_interface = false;
+    let mut interfaces = Vec::new();
+    let mut write_profile = None;
+    let mut use_profile = None;
+    let mut validate = false;
+    let mut backend = CodegenBackend::Cranelift;
+    let mut options = codegen::CodegenOptions::default();
+    let mut analysis_options = analysis::Options::default();
+    let command = args.next().unwrap_or_else(|| fail(format, USAGE));
+
+    while let Some(arg) = args.next() {
+        if let Some(f) = arg.strip_prefix("--message-format=") {
+            format = MessageFormat::from_arg(f).unwrap_or_else(|| fail(format, &format!("unknown message format '{}'", f)));
+        } else if let Some(name) = arg.strip_prefix("--backend=") {
+            backend = match name {
+                "cranelift" => CodegenBackend::Cranelift,
+                "wasm" => CodegenBackend::Wasm,
+                _ => fail(format, &format!("unknown backend '{}'", name)),
+            };
+        } else if let Some(kinds) = arg.strip_prefix("--emit=") {
+            emit_obj = false;
+
+            for kind in kinds.split(',') {
+                match kind {
+                    "obj" => emit_obj = true,
+                    "header" => emit_header = true,
+                    "interface" => emit_interface = true,
+                    _ => fail(format, &format!("unknown output kind '{}'", kind)),
+                }
+            }
+        } else if let Some(file) = arg.strip_prefix("--interface=") {
+            interfaces.push(PathBuf::from(file));
+        } else if let Some(file) = arg.strip_prefix("--write-profile=") {
+            write_profile = Some(PathBuf::from(file));
+        } else if let Some(file) = arg.strip_prefix("--use-profile=") {
+            use_profile = Some(PathBuf::from(file));
+        } else if arg == "--standalone" {
+            analysis_options.witnesses = analysis::type_info::Witnesses::Define;
+        } else if arg == "--time-passes" {
+            time_passes = true;
+        } else if arg == "--canonical-nans" {
+            options.canonical_nans = true;
+        } else if arg == "--validate" {
+            validate = true;
+        } else if arg == "--sort-symbols" {
+            options.sort_symbols = true;
+        } else if let Some(dir) = arg.strip_prefix("--dump-ir=") {
+            options.dump_ir = Some(PathBuf::from(dir));
+        } else if let Some(n) = arg.strip_prefix("--codegen-threads=") {
+            options.threads = n.parse().unwrap_or_else(|_| fail(format, &format!("invalid number of threads '{}'", n)));
+        } else if let Some(dir) = arg.strip_prefix("--cache-dir=") {
+            let cache = codegen::cache::FuncCache::with_dir(dir).unwrap_or_else(|e| fail(format, &format!("could not create {}: {}", dir, e)));
+
+            options.cache = Some(std::sync::Arc::new(std::sync::Mutex::new(cache)));
+        } else if arg == "-o" {
+            output = Some(args.next().unwrap_or_else(|| fail(format, "expected a file name after -o")));
+        } else if arg == "--after" {
+            after = Some(args.next().unwrap_or_else(|| fail(format, "expected a pass name after --after")));
+        } else if arg.starts_with('-') {
+            fail(format, &format!("unknown option '{}'", arg));
+        } else {
+            input = Some(arg);
+        }
+    }
+
+    if command == "repl" {
+        return repl::run(input.as_deref(), &target_lexicon::Triple::host());
+    }
+
+    let input = input.unwrap_or_else(|| fail(format, "no input file"));
+    let source = std::fs::read_to_string(&input).unwrap_or_else(|e| fail(format, &format!("could not read {}: {}", input, e)));
+    let mut module = ir::parser::parse(&source).unwrap_or_else(|e| fail(format, &e));
+
+    for path in &interfaces {
+        let interface = std::fs::File::open(path)
+            .and_then(|f| ir::interface::Interface::read_from(&mut std::io::BufReader::new(f)))
+            .unwrap_or_else(|e| fail(format, &format!("could not read {}: {}", path.display(), e)));
+
+        module.import_interface(&interface).unwrap_or_else(|e| fail(format, &e));
+    }
+
+    if validate && backend != CodegenBackend::Cranelift {
+        fail(format, "--validate runs the compiled functions in memory, which needs the cranelift backend");
+    }
+
+    let target = match backend {
+        CodegenBackend::Cranelift => target_lexicon::Triple::host(),
+        CodegenBackend::Wasm => assemble::wasm_target(),
+    };
+    let cancel = CancellationToken::new();
+    let mut reporter = Reporter::new(format, time_passes);
+
+    let profile = use_profile.map(|path| read_profile(&path).unwrap_or_else(|e| fail(format, &format!("could not read {}: {}", path.display(), e))));
+    let profile = profile.as_ref();
+
+    match command.as_str() {
+        "run" => {
+            let counts = write_profile.as_ref().map(|_| Rc::new(RefCell::new(ir::profile::ProfileData::new())));
+            let eval_options = eval::EvalOptions {
+                canonical_nans: options.canonical_nans,
+                ..eval::EvalOptions::default()
+            };
+            let status = run(&module, &target, eval_options, counts.clone());
+
+            if let (Some(path), Some(counts)) = (&write_profile, counts) {
+                let mut merged = match read_profile(path) {
+                    Ok(merged) => merged,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => ir::profile::ProfileData::new(),
+                    Err(e) => fail(format, &format!("could not read {}: {}", path.display(), e)),
+                };
+                let mut bytes = Vec::new();
+
+                merged.merge(&counts.borrow());
+                merged.write_to(&mut bytes).unwrap();
+                std::fs::write(path, bytes).unwrap_or_else(|e| fail(format, &format!("could not write {}: {}", path.display(), e)));
+            }
 
-    analysis::mandatory(&mut module, &target);
+            std::process::exit(status.unwrap_or_else(|e| fail(format, &e)))
+        }
+        "check" => {
+            check_exports(format, &module);
+            apply_profile(&mut module, profile, &mut reporter, &cancel).unwrap();
+            analysis::mandatory_with(&mut module, &target, analysis_options, &mut reporter, &cancel).unwrap();
+            check_support(format, backend, &module, &target);
+            format.emit(Message::Remark {
+                message: &format!("{} has no errors", input),
+            });
+        }
+        "dump" => {
+            if let Some(pass) = after {
+                let mut stop = StopAfter {
+                    pass,
+                    found: false,
+                    cancel: cancel.clone(),
+                    inner: &mut reporter,
+                };
 
-    println!("{}", module);
+                // the passes check for cancellation before they start, so the module is left as the named pass left it
+                let _ = apply_profile(&mut module, profile, &mut stop, &cancel)
+                    .and_then(|_| analysis::mandatory_with(&mut module, &target, analysis_options, &mut stop, &cancel))
+                    .and_then(|_| analysis::optimize_with(&mut module, &target, &mut stop, &cancel));
 
-    let obj = assemble::assemble(&module, target);
+                if !stop.found {
+                    fail(format, &format!("unknown pass '{}'", stop.pass));
+                }
+            }
+
+            println!("{}", module);
+        }
+        "build" => {
+            // the interface describes the exports as written, before the passes add their own
+            let interface = ir::interface::Interface::new(&module, &target);
+            // the interpreter runs the module as written, so validation covers the passes too
+            let source = if validate { Some(module.clone()) } else { None };
+
+            check_exports(format, &module);
+            apply_profile(&mut module, profile, &mut reporter, &cancel).unwrap();
+            analysis::mandatory_with(&mut module, &target, analysis_options, &mut reporter, &cancel).unwrap();
+            analysis::optimize_with(&mut module, &target, &mut reporter, &cancel).unwrap();
+
+            for decl in &module.decls {
+                if decl.linkage == ir::Linkage::Import {
+                    format.emit(Message::Remark {
+                        message: &format!("'{}' is imported and must be provided at link time", decl.name),
+                    });
+                }
+            }
+
+            if let Some(source) = &source {
+                let jit_options = codegen::CodegenOptions {
+                    cache: None,
+                    dump_ir: None,
+                    ..options.clone()
+                };
+
+                match assemble::validate::validate(source, &module, jit_options, Default::default()) {
+                    Ok(checked) => format.emit(Message::Remark {
+                        message: &format!("{} functions return the same compiled as interpreted", checked),
+                    }),
+                    Err(e) => fail(format, &format!("translation validation failed: {}", e)),
+                }
+            }
+
+            let output = PathBuf::from(output.unwrap_or_else(|| String::from(backend.default_output())));
+            let stem = output.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+
+            if emit_header {
+                let header = output.with_extension("h");
+                let guard = format!("{}_H", stem.to_uppercase());
+
+                write(format, &header, assemble::c_header(&module, &target, &guard));
+                format.emit(Message::Artifact {
+                    kind: "header",
+                    path: &header.to_string_lossy(),
+                });
+            }
+
+            if emit_interface {
+                let path = output.with_extension("lowi");
+                let mut bytes = Vec::new();
+
+                interface.write_to(&mut bytes).unwrap();
+                std::fs::write(&path, bytes).unwrap_or_else(|e| fail(format, &format!("could not write {}: {}", path.display(), e)));
+                format.emit(Message::Artifact {
+                    kind: "interface",
+                    path: &path.to_string_lossy(),
+                });
+            }
+
+            if emit_obj {
+                check_support(format, backend, &module, &target);
+                options.module_name = Some(stem);
+
+                let cache = options.cache.clone();
+                let obj = match backend {
+                    CodegenBackend::Cranelift => assemble::assemble_with(&module, target, options, &mut reporter, &cancel),
+                    CodegenBackend::Wasm => assemble::assemble_wasm(&module, options, &mut reporter, &cancel),
+                };
+                let obj = obj.unwrap_or_else(|e| fail(format, &e.to_string()));
+
+                if let Some(cache) = cache {
+                    let cache = cache.lock().unwrap();
+
+                    format.emit(Message::Remark {
+                        message: &format!("{} functions reused from the cache, {} compiled", cache.hits(), cache.misses()),
+                    });
+                }
+
+                obj.copy(&output);
+                format.emit(Message::Artifact {
+                    kind: "object",
+                    path: &output.to_string_lossy(),
+                });
+            }
+        }
+        _ => fail(format, &format!("unknown command '{}'\n\n{}", command, USAGE)),
+    }
+
+    if let Some(timings) = reporter.timings {
+        eprint!("{}", timings);
+    }
+}
+
+/// The backend that generates the code of `build`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CodegenBackend {
+    Cranelift,
+    Wasm,
+}
+
+impl CodegenBackend {
+    fn default_output(self) -> &'static str {
+        match self {
+            CodegenBackend::Cranelift => "test.o",
+            CodegenBackend::Wasm => "test.wasm",
+        }
+    }
+}
+
+/// Evaluates `main` in the interpreter before any analysis has run, returning its exit status.
+/// Imports are provided by the deterministic stubs, of which the output of `write` is printed.
+/// The counts are added to `profile` if it is set, even if evaluation fails.
+/// A failure or abort is reported with the stack of the interpreter when it happened.
+fn run(
+    module: &ir::Module,
+    target: &target_lexicon::Triple,
+    options: eval::EvalOptions,
+    profile: Option<Rc<RefCell<ir::profile::ProfileData>>>,
+) -> Result<i32, String> {
+    let main = module.decls.iter().find(|d| d.name == "main").ok_or("no main function")?;
+    let body = module.bodies.iter().find(|b| b.decl == main.id).ok_or("main is not defined")?;
+    let args = main
+        .ty
+        .signature()
+        .params
+        .into_iter()
+        .map(|ty| match ty.kind {
+            ir::Type::Ptr(_) | ir::Type::Box(_) | ir::Type::Func(_) => ir::Const::Undefined(ty),
+            _ => ir::Const::Scalar(0, ty),
+        })
+        .collect();
+
+    let stubs = Rc::new(RefCell::new(Stubs::new()));
+    let mut ctx = eval::EvalCtx::new(module, body, target)
+        .with_args(args)
+        .with_externs(stubs.clone())
+        .with_options(options);
+
+    if let Some(profile) = profile {
+        ctx = ctx.with_profile(profile);
+    }
+
+    let result = ctx.eval();
+
+    print!("{}", String::from_utf8_lossy(&stubs.borrow().output));
+    result.map_err(|e| format!("{}\n{}", e, eval::format_traceback(ctx.traceback())))?;
+
+    if ctx.aborted() {
+        return Err(format!("main aborted\n{}", eval::format_traceback(ctx.traceback())));
+    }
+
+    match ctx.finish().as_slice() {
+        [] => Ok(0),
+        [ir::Const::Scalar(status, _)] => Ok(*status as i32),
+        rets => Err(format!("main returned {:?}", rets)),
+    }
+}
+
+/// Cancels the compilation once `pass` has finished.
+struct StopAfter<'a> {
+    pass: String,
+    found: bool,
+    cancel: CancellationToken,
+    inner: &'a mut Reporter,
+}
+
+impl Progress for StopAfter<'_> {
+    fn on_pass_stat(&mut self, pass: &str, decl: &ir::Decl, stat: &ir::progress::Stat) {
+        self.inner.on_pass_stat(pass, decl, stat);
+    }
+
+    fn wants_pass_changes(&self) -> bool {
+        self.inner.wants_pass_changes()
+    }
+
+    fn on_pass_changes(&mut self, pass: &str, changes: &ir::progress::PassChanges) {
+        self.inner.on_pass_changes(pass, changes);
+    }
+
+    fn on_pass_finish(&mut self, pass: &str, time: Duration) {
+        self.inner.on_pass_finish(pass, time);
+
+        if pass == self.pass {
+            self.found = true;
+            self.cancel.cancel();
+        }
+    }
+}
+
+fn apply_profile(
+    module: &mut ir::Module,
+    profile: Option<&ir::profile::ProfileData>,
+    progress: &mut dyn Progress,
+    cancel: &CancellationToken,
+) -> Result<(), ir::progress::Cancelled> {
+    match profile {
+        Some(profile) => analysis::apply_profile(module, profile, progress, cancel),
+        None => Ok(()),
+    }
+}
+
+fn read_profile(path: &Path) -> std::io::Result<ir::profile::ProfileData> {
+    ir::profile::ProfileData::read_from(&mut std::io::BufReader::new(std::fs::File::open(path)?))
+}
+
+fn write(format: MessageFormat, path: &Path, contents: String) {
+    std::fs::write(path, contents).unwrap_or_else(|e| fail(format, &format!("could not write {}: {}", path.display(), e)));
+}
+
+/// Reports every function of `module` marked `@extern_c_export` that can not be exported, failing if there is any.
+fn check_exports(format: MessageFormat, module: &ir::Module) {
+    let errors = analysis::export::check(module);
+
+    for message in &errors {
+        format.emit(Message::Diagnostic { level: "error", message });
+    }
+
+    if !errors.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+/// Reports everything in `module` the backend can not lower, failing if there is anything.
+fn check_support(format: MessageFormat, backend: CodegenBackend, module: &ir::Module, target: &target_lexicon::Triple) {
+    let report = match backend {
+        CodegenBackend::Cranelift => assemble::supports(module, target),
+        CodegenBackend::Wasm => assemble::supports_wasm(module),
+    };
+
+    for feature in report.unsupported() {
+        format.emit(Message::Diagnostic {
+            level: "error",
+            message: &format!("unsupported {}", feature),
+        });
+    }
+
+    if !report.is_supported() {
+        std::process::exit(1);
+    }
+}
 
-    obj.copy(&std::path::PathBuf::from("test.o"));
+fn fail(format: MessageFormat, message: &str) -> ! {
+    format.emit(Message::Diagnostic { level: "error", message });
+    std::process::exit(1);
 }
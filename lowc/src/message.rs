@@ -0,0 +1,161 @@
+use ir::progress::{PassChanges, PassTimings, Progress};
+use std::fmt::Write;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageFormat {
+    Human,
+    Json,
+}
+
+pub enum Message<'a> {
+    Diagnostic { level: &'a str, message: &'a str },
+    Artifact { kind: &'a str, path: &'a str },
+    PassTiming { pass: &'a str, time: Duration },
+    PassStat { pass: &'a str, function: &'a str, stat: &'a str, value: u64 },
+    PassChanges { pass: &'a str, changes: &'a PassChanges },
+    FunctionTiming { function: &'a str, time: Duration },
+    Remark { message: &'a str },
+}
+
+impl MessageFormat {
+    pub fn from_arg(arg: &str) -> Option<Self> {
+        match arg {
+            "human" => Some(MessageFormat::Human),
+            "json" => Some(MessageFormat::Json),
+            _ => None,
+        }
+    }
+
+    pub fn emit(self, msg: Message) {
+        match self {
+            MessageFormat::Human => match msg {
+                Message::Diagnostic { level, message } => eprintln!("{}: {}", level, message),
+                // remarks, timings, statistics and artifacts are only reported to tools
+                _ => {}
+            },
+            MessageFormat::Json => println!("{}", msg.to_json()),
+        }
+    }
+}
+
+impl Message<'_> {
+    /// One JSON object per message, tagged with a `reason` field.
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+
+        match self {
+            Message::Diagnostic { level, message } => {
+                write!(out, r#"{{"reason":"diagnostic","level":{},"message":{}}}"#, json_str(level), json_str(message)).unwrap();
+            }
+            Message::Artifact { kind, path } => {
+                write!(out, r#"{{"reason":"artifact","kind":{},"path":{}}}"#, json_str(kind), json_str(path)).unwrap();
+            }
+            Message::PassTiming { pass, time } => {
+                write!(out, r#"{{"reason":"timing","pass":{},"micros":{}}}"#, json_str(pass), time.as_micros()).unwrap();
+            }
+            Message::PassStat { pass, function, stat, value } => {
+                write!(
+                    out,
+                    r#"{{"reason":"stat","pass":{},"function":{},"stat":{},"value":{}}}"#,
+                    json_str(pass),
+                    json_str(function),
+                    json_str(stat),
+                    value
+                )
+                .unwrap();
+            }
+            Message::PassChanges { pass, changes } => {
+                write!(
+                    out,
+                    r#"{{"reason":"changes","pass":{},"added":{},"removed":{},"bodies":{}}}"#,
+                    json_str(pass),
+                    changes.stmts_added,
+                    changes.stmts_removed,
+                    changes.bodies_changed
+                )
+                .unwrap();
+            }
+            Message::FunctionTiming { function, time } => {
+                write!(out, r#"{{"reason":"timing","function":{},"micros":{}}}"#, json_str(function), time.as_micros()).unwrap();
+            }
+            Message::Remark { message } => {
+                write!(out, r#"{{"reason":"remark","message":{}}}"#, json_str(message)).unwrap();
+            }
+        }
+
+        out
+    }
+}
+
+/// Reports pass and function timings and pass statistics as messages,
+/// and collects the timings of the passes for `--time-passes`.
+pub struct Reporter {
+    pub format: MessageFormat,
+    pub timings: Option<PassTimings>,
+}
+
+impl Reporter {
+    pub fn new(format: MessageFormat, time_passes: bool) -> Self {
+        Reporter {
+            format,
+            timings: if time_passes { Some(PassTimings::new()) } else { None },
+        }
+    }
+}
+
+impl Progress for Reporter {
+    fn on_function_finish(&mut self, decl: &ir::Decl, time: Duration) {
+        self.format.emit(Message::FunctionTiming { function: &decl.name, time });
+    }
+
+    fn on_pass_finish(&mut self, pass: &str, time: Duration) {
+        if let Some(timings) = &mut self.timings {
+            timings.on_pass_finish(pass, time);
+        }
+
+        self.format.emit(Message::PassTiming { pass, time });
+    }
+
+    fn on_pass_stat(&mut self, pass: &str, decl: &ir::Decl, stat: &ir::progress::Stat) {
+        self.format.emit(Message::PassStat {
+            pass,
+            function: &decl.name,
+            stat: stat.name,
+            value: stat.value,
+        });
+    }
+
+    fn wants_pass_changes(&self) -> bool {
+        self.timings.is_some() || self.format == MessageFormat::Json
+    }
+
+    fn on_pass_changes(&mut self, pass: &str, changes: &PassChanges) {
+        if let Some(timings) = &mut self.timings {
+            timings.on_pass_changes(pass, changes);
+        }
+
+        self.format.emit(Message::PassChanges { pass, changes });
+    }
+}
+
+fn json_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+
+    out.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+    out
+}
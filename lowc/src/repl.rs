@@ -0,0 +1,284 @@
+use eval::stubs::Stubs;
+use std::cell::RefCell;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+const HELP: &str = "enter declarations and bodies in the text format to add them to the module, replacing those of the same name,
+or call @f(args) to interpret a call and print what it returns
+
+commands:
+    :dump           print the module
+    :load <file>    add the declarations and bodies of a file
+    :reset          remove all declarations and bodies
+    :help           print this message
+    :quit           leave the repl";
+
+/// The name of the function a call entered in the repl is wrapped in.
+const WRAPPER: &str = "__repl";
+
+/// Reads declarations, bodies and calls from stdin until it ends or `:quit` is entered, interpreting the calls
+/// as they are entered. The declarations and bodies of `input` are added first if it is set.
+pub fn run(input: Option<&str>, target: &target_lexicon::Triple) {
+    let mut session = Session::default();
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+
+    if let Some(path) = input {
+        session.load(path).unwrap_or_else(|e| eprintln!("error: {}", e));
+    }
+
+    loop {
+        let entry = match read_entry(&mut lines) {
+            Some(entry) => entry,
+            None => break,
+        };
+
+        let result = match entry.trim() {
+            "" => Ok(()),
+            ":quit" | ":q" => break,
+            ":help" => {
+                println!("{}", HELP);
+                Ok(())
+            }
+            ":dump" => {
+                println!("{}", session.module);
+                Ok(())
+            }
+            ":reset" => {
+                session = Session::default();
+                Ok(())
+            }
+            cmd if cmd.starts_with(":load ") => session.load(cmd[":load ".len()..].trim()),
+            cmd if cmd.starts_with(':') => Err(format!("unknown command '{}', see :help", cmd)),
+            call if call.starts_with("call ") => session.call(call, target),
+            _ => session.add(&entry),
+        };
+
+        if let Err(e) = result {
+            eprintln!("error: {}", e);
+        }
+    }
+}
+
+/// Reads the next entry, which continues over multiple lines while a body is open or the entry
+/// only has attributes so far. Returns `None` at the end of the input.
+fn read_entry(lines: &mut impl Iterator<Item = std::io::Result<String>>) -> Option<String> {
+    let mut entry = String::new();
+    let mut depth = 0;
+
+    loop {
+        print!("{}", if entry.is_empty() { "> " } else { ". " });
+        std::io::stdout().flush().ok()?;
+
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            // an unfinished entry at the end of the input is dropped
+            _ => return None,
+        };
+
+        depth += braces(&line);
+        entry.push_str(&line);
+        entry.push('\n');
+
+        if depth <= 0 && !line.trim_start().starts_with('@') {
+            return Some(entry);
+        }
+    }
+}
+
+/// The declarations and bodies entered so far. They are kept as text, so that one can be replaced
+/// by entering it again and the module is parsed from the text that is left.
+struct Session {
+    /// The declarations and impls, after which the bodies come, along with the name they are replaced by.
+    decls: Vec<(String, String)>,
+    bodies: Vec<(String, String)>,
+    module: ir::Module,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session {
+            decls: Vec::new(),
+            bodies: Vec::new(),
+            module: ir::Module {
+                decls: Default::default(),
+                impls: Default::default(),
+                bodies: Default::default(),
+            },
+        }
+    }
+}
+
+impl Session {
+    fn load(&mut self, path: &str) -> Result<(), String> {
+        let source = std::fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+
+        self.add(&source)
+    }
+
+    /// Adds the declarations and bodies in `source`, leaving the session as it was if the result does not parse.
+    fn add(&mut self, source: &str) -> Result<(), String> {
+        let mut decls = self.decls.clone();
+        let mut bodies = self.bodies.clone();
+
+        for item in split_items(source) {
+            let (name, is_body) = item_name(&item);
+            let list = if is_body { &mut bodies } else { &mut decls };
+
+            match list.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, text)) => *text = item,
+                None => list.push((name, item)),
+            }
+        }
+
+        let module = ir::parser::parse(&source_of(&decls, &bodies))?;
+
+        self.decls = decls;
+        self.bodies = bodies;
+        self.module = module;
+
+        Ok(())
+    }
+
+    /// Interprets `call`, a call statement without return places, by wrapping it in a function that
+    /// returns what the callee returns, and prints the return values.
+    fn call(&self, call: &str, target: &target_lexicon::Triple) -> Result<(), String> {
+        let name = call["call ".len()..]
+            .trim_start()
+            .strip_prefix('@')
+            .and_then(|rest| rest.split(|c: char| c == '(' || c.is_whitespace()).next())
+            .ok_or("expected a call like call @f(args)")?;
+
+        let decl = self
+            .module
+            .decls
+            .iter()
+            .find(|d| d.name == name)
+            .ok_or_else(|| format!("unknown function @{}", name))?;
+
+        let rets = match &decl.ty.kind {
+            ir::Type::Func(sig) => sig.rets.iter().map(ir::display_ty).collect::<Vec<_>>(),
+            _ => return Err(format!("@{} is not a function", name)),
+        };
+
+        let places = (0..rets.len()).map(|i| format!("_{}", i)).collect::<Vec<_>>();
+        let mut wrapper = format!("local @{} :: () -> ({})\n", WRAPPER, rets.join(", "));
+
+        wrapper.push_str(&format!("fn @{} {{\n", WRAPPER));
+
+        for (place, ty) in places.iter().zip(&rets) {
+            wrapper.push_str(&format!("    ret {} :: {}\n", place, ty));
+        }
+
+        if places.is_empty() {
+            wrapper.push_str(&format!("%0:\n    {}\n    return\n}}\n", call.trim()));
+        } else {
+            wrapper.push_str(&format!("%0:\n    {} -> {}\n    return\n}}\n", call.trim(), places.join(", ")));
+        }
+
+        let module = ir::parser::parse(&format!("{}{}", source_of(&self.decls, &self.bodies), wrapper))?;
+        let body = module.bodies.iter().find(|b| module.decls[b.decl].name == WRAPPER).unwrap();
+        let stubs = Rc::new(RefCell::new(Stubs::new()));
+        let mut ctx = eval::EvalCtx::new(&module, body, target).with_externs(stubs.clone());
+        let result = ctx.eval();
+
+        print!("{}", String::from_utf8_lossy(&stubs.borrow().output));
+
+        // the wrapper is not part of the program
+        let trace = ctx.traceback().iter().filter(|frame| frame.func != WRAPPER).cloned().collect::<Vec<_>>();
+
+        if let Err(e) = result {
+            return Err(if trace.is_empty() { e.to_string() } else { format!("{}\n{}", e, eval::format_traceback(&trace)) });
+        }
+
+        // an abort returns to the caller, leaving the return values undefined
+        let aborted = trace.first().map_or(false, |frame| {
+            let block = &module.bodies[frame.loc.body].blocks[frame.loc.block];
+
+            frame.loc.stmt == block.stmts.len() && block.term == ir::Term::Abort
+        });
+
+        if aborted {
+            eprintln!("note: aborted\n{}", eval::format_traceback(&trace));
+        }
+
+        let vals = ctx.finish();
+
+        if !vals.is_empty() {
+            let vals = vals.iter().map(|c| ir::display_const(c, &module)).collect::<Vec<_>>();
+
+            println!("{}", vals.join(", "));
+        }
+
+        Ok(())
+    }
+}
+
+fn source_of(decls: &[(String, String)], bodies: &[(String, String)]) -> String {
+    decls.iter().chain(bodies).map(|(_, text)| text.as_str()).collect()
+}
+
+/// Splits `source` into declarations, impls and bodies, each with the attributes in front of it.
+fn split_items(source: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut item = String::new();
+    let mut depth = 0;
+
+    for line in source.lines() {
+        if item.is_empty() && line.trim().is_empty() {
+            continue;
+        }
+
+        depth += braces(line);
+        item.push_str(line);
+        item.push('\n');
+
+        if depth <= 0 && !line.trim_start().starts_with('@') {
+            items.push(std::mem::take(&mut item));
+            depth = 0;
+        }
+    }
+
+    if !item.trim().is_empty() {
+        items.push(item);
+    }
+
+    items
+}
+
+/// Returns the name an item is replaced by, and whether it is a body. Declarations and bodies are named
+/// after what they declare or define, impls by their text, so they are never replaced.
+fn item_name(item: &str) -> (String, bool) {
+    let line = item.lines().find(|line| !line.trim_start().starts_with('@')).unwrap_or("");
+    let mut words = line.split_whitespace();
+    let keyword = words.next().unwrap_or("");
+    let name = words.next().and_then(|word| word.strip_prefix('@'));
+
+    match (keyword, name) {
+        ("fn", Some(name)) => (name.to_string(), true),
+        ("export" | "import" | "local" | "hidden", Some(name)) => (name.to_string(), false),
+        _ => (item.to_string(), false),
+    }
+}
+
+/// How much `line` changes the nesting of braces, ignoring those in byte strings and comments.
+fn braces(line: &str) -> i32 {
+    let mut depth = 0;
+    let mut chars = line.chars();
+    let mut string = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if string => {
+                chars.next();
+            }
+            '"' => string = !string,
+            ';' if !string => break,
+            '{' if !string => depth += 1,
+            '}' if !string => depth -= 1,
+            _ => {}
+        }
+    }
+
+    depth
+}
@@ -0,0 +1,240 @@
+//! Integers of arbitrary size. Every operation returns a new integer, which must be freed with `bigint_free`.
+
+use core::cmp::Ordering;
+use core::mem::size_of;
+use libc::{abort, c_void, free, malloc};
+
+/// The header of an integer, which is followed by its magnitude in `len` limbs, least significant first.
+/// The most significant limb is never zero, so zero has no limbs and is never negative.
+#[repr(C)]
+pub struct BigInt {
+    neg: bool,
+    len: usize,
+}
+
+/// The largest power of ten that fits in a limb.
+const CHUNK: u64 = 1_000_000_000;
+const CHUNK_DIGITS: usize = 9;
+
+unsafe fn alloc(len: usize, neg: bool) -> *mut BigInt {
+    let ptr = malloc(size_of::<BigInt>() + len * size_of::<u32>()) as *mut BigInt;
+
+    if ptr.is_null() {
+        abort();
+    }
+
+    *ptr = BigInt { neg, len };
+    limbs(ptr).iter_mut().for_each(|l| *l = 0);
+    ptr
+}
+
+unsafe fn limbs<'a>(int: *mut BigInt) -> &'a mut [u32] {
+    let ptr = (int as *mut u8).add(size_of::<BigInt>()) as *mut u32;
+
+    core::slice::from_raw_parts_mut(ptr, (*int).len)
+}
+
+unsafe fn normalize(int: *mut BigInt) -> *mut BigInt {
+    while (*int).len > 0 && limbs(int)[(*int).len - 1] == 0 {
+        (*int).len -= 1;
+    }
+
+    if (*int).len == 0 {
+        (*int).neg = false;
+    }
+
+    int
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+unsafe fn add_mag(a: &[u32], b: &[u32], neg: bool) -> *mut BigInt {
+    let res = alloc(a.len().max(b.len()) + 1, neg);
+    let mut carry = 0;
+
+    for (i, limb) in limbs(res).iter_mut().enumerate() {
+        let sum = carry + u64::from(*a.get(i).unwrap_or(&0)) + u64::from(*b.get(i).unwrap_or(&0));
+
+        *limb = sum as u32;
+        carry = sum >> 32;
+    }
+
+    normalize(res)
+}
+
+/// Subtracts `b` from `a`, which must not be smaller.
+unsafe fn sub_mag(a: &[u32], b: &[u32], neg: bool) -> *mut BigInt {
+    let res = alloc(a.len(), neg);
+    let mut borrow = 0;
+
+    for (i, limb) in limbs(res).iter_mut().enumerate() {
+        let diff = i64::from(a[i]) - i64::from(*b.get(i).unwrap_or(&0)) - borrow;
+
+        *limb = diff as u32;
+        borrow = (diff < 0) as i64;
+    }
+
+    normalize(res)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn bigint_add(a: *mut BigInt, b: *mut BigInt) -> *mut BigInt {
+    let (x, y) = (limbs(a), limbs(b));
+
+    if (*a).neg == (*b).neg {
+        add_mag(x, y, (*a).neg)
+    } else if cmp_mag(x, y) == Ordering::Less {
+        sub_mag(y, x, (*b).neg)
+    } else {
+        sub_mag(x, y, (*a).neg)
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn bigint_mul(a: *mut BigInt, b: *mut BigInt) -> *mut BigInt {
+    let (x, y) = (limbs(a), limbs(b));
+    let res = alloc(x.len() + y.len(), (*a).neg != (*b).neg);
+    let out = limbs(res);
+
+    for (i, a) in x.iter().enumerate() {
+        let mut carry = 0;
+
+        for (j, b) in y.iter().enumerate() {
+            let prod = u64::from(*a) * u64::from(*b) + u64::from(out[i + j]) + carry;
+
+            out[i + j] = prod as u32;
+            carry = prod >> 32;
+        }
+
+        out[i + y.len()] = carry as u32;
+    }
+
+    normalize(res)
+}
+
+/// Returns -1, 0 or 1 when `a` is less than, equal to or greater than `b`.
+#[no_mangle]
+pub unsafe extern "C" fn bigint_cmp(a: *mut BigInt, b: *mut BigInt) -> i32 {
+    let ord = match ((*a).neg, (*b).neg) {
+        (false, true) => Ordering::Greater,
+        (true, false) => Ordering::Less,
+        (false, false) => cmp_mag(limbs(a), limbs(b)),
+        (true, true) => cmp_mag(limbs(b), limbs(a)),
+    };
+
+    ord as i32
+}
+
+/// Parses the `len` bytes at `buf` as a decimal integer with an optional minus sign.
+/// Returns null if they are not an integer.
+#[no_mangle]
+pub unsafe extern "C" fn bigint_from_str(buf: *const u8, len: usize) -> *mut BigInt {
+    let s = core::slice::from_raw_parts(buf, len);
+    let (neg, digits) = match s.split_first() {
+        Some((b'-', rest)) => (true, rest),
+        _ => (false, s),
+    };
+
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return core::ptr::null_mut();
+    }
+
+    // every chunk of digits fits in a limb
+    let res = alloc(digits.len() / CHUNK_DIGITS + 1, neg);
+    let out = limbs(res);
+    let first = match digits.len() % CHUNK_DIGITS {
+        0 => CHUNK_DIGITS,
+        n => n,
+    };
+    let mut start = 0;
+    let mut end = first;
+
+    while start < digits.len() {
+        let chunk = &digits[start..end];
+        let mut carry = chunk.iter().fold(0, |acc, d| acc * 10 + u64::from(d - b'0'));
+        let scale = 10u64.pow(chunk.len() as u32);
+
+        for limb in out.iter_mut() {
+            let val = u64::from(*limb) * scale + carry;
+
+            *limb = val as u32;
+            carry = val >> 32;
+        }
+
+        start = end;
+        end += CHUNK_DIGITS;
+    }
+
+    normalize(res)
+}
+
+/// Writes `a` in decimal to `buf` if it fits in `len` bytes and returns its length.
+#[no_mangle]
+pub unsafe extern "C" fn bigint_to_str(a: *mut BigInt, buf: *mut u8, len: usize) -> usize {
+    let n = (*a).len;
+    let rem = malloc((n * size_of::<u32>()).max(1)) as *mut u32;
+    let digits = malloc(n * (CHUNK_DIGITS + 1) + 2) as *mut u8;
+
+    if rem.is_null() || digits.is_null() {
+        abort();
+    }
+
+    let rem = core::slice::from_raw_parts_mut(rem, n);
+    let mut count = 0;
+    let mut top = n;
+
+    rem.copy_from_slice(limbs(a));
+
+    // the digits are produced least significant first
+    while top > 0 {
+        let mut chunk = 0;
+
+        for limb in rem[..top].iter_mut().rev() {
+            let val = (chunk << 32) | u64::from(*limb);
+
+            *limb = (val / CHUNK) as u32;
+            chunk = val % CHUNK;
+        }
+
+        while top > 0 && rem[top - 1] == 0 {
+            top -= 1;
+        }
+
+        for _ in 0..CHUNK_DIGITS {
+            *digits.add(count) = b'0' + (chunk % 10) as u8;
+            chunk /= 10;
+            count += 1;
+
+            if top == 0 && chunk == 0 {
+                break;
+            }
+        }
+    }
+
+    if count == 0 {
+        *digits = b'0';
+        count = 1;
+    }
+
+    if (*a).neg {
+        *digits.add(count) = b'-';
+        count += 1;
+    }
+
+    if count <= len {
+        for i in 0..count {
+            *buf.add(i) = *digits.add(count - 1 - i);
+        }
+    }
+
+    free(rem.as_mut_ptr() as *mut c_void);
+    free(digits as *mut c_void);
+    count
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn bigint_free(a: *mut BigInt) {
+    free(a as *mut c_void);
+}
@@ -0,0 +1,592 @@
+use core::cmp::Ordering;
+use core::slice;
+
+const POW10: [f64; 23] = [
+    1e0, 1e1, 1e2, 1e3, 1e4, 1e5, 1e6, 1e7, 1e8, 1e9, 1e10, 1e11, 1e12, 1e13, 1e14, 1e15, 1e16, 1e17, 1e18, 1e19, 1e20, 1e21, 1e22,
+];
+
+const INF_BITS: u64 = 0x7ff0_0000_0000_0000;
+
+/// Writes the shortest string that parses back to `val` into `buf`.
+/// Returns the length of the string, which is only written if it fits in `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn f64_to_str(val: f64, buf: *mut u8, len: usize) -> usize {
+    let mut out = Writer::new();
+
+    format_f64(val.to_bits(), &mut out);
+    out.copy_to(buf, len)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn i64_to_str(val: i64, buf: *mut u8, len: usize) -> usize {
+    let mut out = Writer::new();
+
+    if val < 0 {
+        out.push(b'-');
+    }
+
+    format_u64(val.unsigned_abs(), &mut out);
+    out.copy_to(buf, len)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn u64_to_str(val: u64, buf: *mut u8, len: usize) -> usize {
+    let mut out = Writer::new();
+
+    format_u64(val, &mut out);
+    out.copy_to(buf, len)
+}
+
+/// Parses a float from the start of `buf` into `out`.
+/// Returns the number of bytes consumed, or 0 if `buf` does not start with a float.
+#[no_mangle]
+pub unsafe extern "C" fn str_to_f64(buf: *const u8, len: usize, out: *mut f64) -> usize {
+    match parse_f64(slice::from_raw_parts(buf, len)) {
+        | Some((val, n)) => {
+            *out = val;
+            n
+        },
+        | None => 0,
+    }
+}
+
+/// Parses an integer from the start of `buf` into `out`.
+/// Returns the number of bytes consumed, or 0 if there is no integer or it overflows.
+#[no_mangle]
+pub unsafe extern "C" fn str_to_i64(buf: *const u8, len: usize, out: *mut i64) -> usize {
+    let s = slice::from_raw_parts(buf, len);
+    let neg = s.first() == Some(&b'-');
+    let start = (neg || s.first() == Some(&b'+')) as usize;
+
+    match parse_u64(&s[start..]) {
+        | Some((val, n)) if neg && val <= 1 << 63 => {
+            *out = (val as i64).wrapping_neg();
+            start + n
+        },
+        | Some((val, n)) if !neg && val < 1 << 63 => {
+            *out = val as i64;
+            start + n
+        },
+        | _ => 0,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn str_to_u64(buf: *const u8, len: usize, out: *mut u64) -> usize {
+    let s = slice::from_raw_parts(buf, len);
+    let start = (s.first() == Some(&b'+')) as usize;
+
+    match parse_u64(&s[start..]) {
+        | Some((val, n)) => {
+            *out = val;
+            start + n
+        },
+        | None => 0,
+    }
+}
+
+struct Writer {
+    buf: [u8; 32],
+    len: usize,
+}
+
+impl Writer {
+    fn new() -> Self {
+        Writer { buf: [0; 32], len: 0 }
+    }
+
+    fn push(&mut self, b: u8) {
+        self.buf[self.len] = b;
+        self.len += 1;
+    }
+
+    fn push_all(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.push(b);
+        }
+    }
+
+    unsafe fn copy_to(&self, buf: *mut u8, len: usize) -> usize {
+        if self.len <= len {
+            buf.copy_from_nonoverlapping(self.buf.as_ptr(), self.len);
+        }
+
+        self.len
+    }
+}
+
+fn format_u64(mut val: u64, out: &mut Writer) {
+    let mut digits = [0; 20];
+    let mut n = 0;
+
+    loop {
+        digits[n] = b'0' + (val % 10) as u8;
+        val /= 10;
+        n += 1;
+
+        if val == 0 {
+            break;
+        }
+    }
+
+    for i in (0..n).rev() {
+        out.push(digits[i]);
+    }
+}
+
+/// Formats in decimal notation when the exponent is in -5..17 and in scientific notation otherwise.
+fn format_f64(bits: u64, out: &mut Writer) {
+    if bits & !(1 << 63) > INF_BITS {
+        return out.push_all(b"NaN");
+    }
+
+    if bits >> 63 != 0 {
+        out.push(b'-');
+    }
+
+    let bits = bits & !(1 << 63);
+
+    if bits == INF_BITS {
+        return out.push_all(b"inf");
+    } else if bits == 0 {
+        return out.push_all(b"0.0");
+    }
+
+    let mut digits = [0; 17];
+    let (n, exp) = shortest(bits, &mut digits);
+    let digits = &digits[..n];
+
+    if exp < -5 || exp >= 17 {
+        out.push(digits[0]);
+
+        if n > 1 {
+            out.push(b'.');
+            out.push_all(&digits[1..]);
+        }
+
+        out.push(b'e');
+
+        if exp < 0 {
+            out.push(b'-');
+        }
+
+        format_u64(exp.unsigned_abs() as u64, out);
+    } else if exp < 0 {
+        out.push_all(b"0.");
+
+        for _ in 0..-exp - 1 {
+            out.push(b'0');
+        }
+
+        out.push_all(digits);
+    } else {
+        let int = exp as usize + 1;
+
+        for i in 0..int {
+            out.push(digits.get(i).copied().unwrap_or(b'0'));
+        }
+
+        out.push(b'.');
+
+        if n > int {
+            out.push_all(&digits[int..]);
+        } else {
+            out.push(b'0');
+        }
+    }
+}
+
+/// Generates the shortest digits that uniquely identify a positive finite float
+/// (Steele & White's free-format algorithm). Returns the number of digits and the
+/// decimal exponent of the first one.
+fn shortest(bits: u64, digits: &mut [u8; 17]) -> (usize, i32) {
+    let (f, e) = decode(bits);
+    let even = f % 2 == 0;
+    let shift = lower_closer(bits) as u32;
+
+    // value = r / s, and the distances to the midpoints between the neighbouring floats are m_plus / s and m_minus / s
+    let mut r = Big::from_u64(f);
+    let mut s = Big::from_u64(2);
+    let mut m_plus = Big::from_u64(1);
+    let mut m_minus = Big::from_u64(1);
+
+    r.mul_pow2(1 + shift);
+    s.mul_pow2(shift);
+    m_plus.mul_pow2(shift);
+
+    if e >= 0 {
+        r.mul_pow2(e as u32);
+        m_plus.mul_pow2(e as u32);
+        m_minus.mul_pow2(e as u32);
+    } else {
+        s.mul_pow2(-e as u32);
+    }
+
+    // estimate of floor(log10(value)), which is at most the real exponent
+    let mut k = ((e + 64 - f.leading_zeros() as i32 - 1) * 78913) >> 18;
+
+    if k >= 0 {
+        s.mul_pow10(k as u32);
+    } else {
+        r.mul_pow10(-k as u32);
+        m_plus.mul_pow10(-k as u32);
+        m_minus.mul_pow10(-k as u32);
+    }
+
+    loop {
+        let mut high = r;
+
+        high.add(&m_plus);
+
+        match high.cmp(&s) {
+            | Ordering::Greater => {},
+            | Ordering::Equal if even => {},
+            | _ => break,
+        }
+
+        s.mul_small(10);
+        k += 1;
+    }
+
+    let mut n = 0;
+
+    loop {
+        r.mul_small(10);
+        m_plus.mul_small(10);
+        m_minus.mul_small(10);
+
+        let mut d = 0;
+
+        while r.cmp(&s) != Ordering::Less {
+            r.sub(&s);
+            d += 1;
+        }
+
+        let mut high = r;
+
+        high.add(&m_plus);
+
+        let low = match r.cmp(&m_minus) {
+            | Ordering::Less => true,
+            | Ordering::Equal => even,
+            | Ordering::Greater => false,
+        };
+
+        let high = match high.cmp(&s) {
+            | Ordering::Greater => true,
+            | Ordering::Equal => even,
+            | Ordering::Less => false,
+        };
+
+        if low && high {
+            let mut twice = r;
+
+            twice.mul_small(2);
+
+            if twice.cmp(&s) != Ordering::Less {
+                d += 1;
+            }
+        } else if high {
+            d += 1;
+        }
+
+        digits[n] = b'0' + d;
+        n += 1;
+
+        if low || high {
+            return (n, k - 1);
+        }
+    }
+}
+
+/// Parses `[+-]digits[.digits][(e|E)[+-]digits]`, `inf` or `NaN`.
+/// Digits beyond the 19th significant one are ignored.
+fn parse_f64(s: &[u8]) -> Option<(f64, usize)> {
+    let neg = s.first() == Some(&b'-');
+    let mut i = (neg || s.first() == Some(&b'+')) as usize;
+    let sign = (neg as u64) << 63;
+
+    if s[i..].starts_with(b"inf") {
+        return Some((f64::from_bits(INF_BITS | sign), i + 3));
+    } else if s[i..].starts_with(b"NaN") {
+        return Some((f64::NAN, i + 3));
+    }
+
+    let mut mantissa = 0u64;
+    let mut kept = 0;
+    let mut exp = 0i32;
+    let mut any = false;
+    let mut frac = false;
+
+    loop {
+        match s.get(i) {
+            | Some(c @ b'0'..=b'9') => {
+                if kept < 19 {
+                    if mantissa != 0 || *c != b'0' {
+                        mantissa = mantissa * 10 + (c - b'0') as u64;
+                        kept += 1;
+                    }
+
+                    exp -= frac as i32;
+                } else if !frac {
+                    exp += 1;
+                }
+
+                any = true;
+            },
+            | Some(b'.') if !frac => frac = true,
+            | _ => break,
+        }
+
+        i += 1;
+    }
+
+    if !any {
+        return None;
+    }
+
+    if let Some(b'e') | Some(b'E') = s.get(i) {
+        let neg = s.get(i + 1) == Some(&b'-');
+        let start = i + 1 + (neg || s.get(i + 1) == Some(&b'+')) as usize;
+        let mut e = 0i32;
+        let mut j = start;
+
+        while let Some(c @ b'0'..=b'9') = s.get(j) {
+            e = (e * 10 + (c - b'0') as i32).min(100_000);
+            j += 1;
+        }
+
+        if j > start {
+            exp += if neg { -e } else { e };
+            i = j;
+        }
+    }
+
+    let bits = if mantissa == 0 || exp + kept < -324 {
+        0
+    } else if exp + kept > 310 {
+        INF_BITS
+    } else {
+        refine(mantissa, exp, approx(mantissa, exp))
+    };
+
+    Some((f64::from_bits(bits | sign), i))
+}
+
+fn approx(mantissa: u64, mut exp: i32) -> u64 {
+    let mut val = mantissa as f64;
+
+    while exp > 0 {
+        let step = exp.min(22);
+
+        val *= POW10[step as usize];
+        exp -= step;
+    }
+
+    while exp < 0 {
+        let step = (-exp).min(22);
+
+        val /= POW10[step as usize];
+        exp += step;
+    }
+
+    val.to_bits().min(f64::MAX.to_bits())
+}
+
+/// Moves `bits` to the float nearest to `mantissa * 10^exp`, rounding ties to even.
+fn refine(mantissa: u64, exp: i32, mut bits: u64) -> u64 {
+    while bits < INF_BITS {
+        let (f, e) = decode(bits);
+
+        match cmp_scaled(mantissa, exp, 2 * f + 1, e - 1) {
+            | Ordering::Greater => {
+                bits += 1;
+                continue;
+            },
+            | Ordering::Equal => return bits + f % 2,
+            | Ordering::Less => {},
+        }
+
+        if bits == 0 {
+            break;
+        }
+
+        let ord = if lower_closer(bits) {
+            cmp_scaled(mantissa, exp, 4 * f - 1, e - 2)
+        } else {
+            cmp_scaled(mantissa, exp, 2 * f - 1, e - 1)
+        };
+
+        match ord {
+            | Ordering::Less => bits -= 1,
+            | Ordering::Equal => return bits - f % 2,
+            | Ordering::Greater => break,
+        }
+    }
+
+    bits
+}
+
+/// Compares `a * 10^e10` with `b * 2^e2`.
+fn cmp_scaled(a: u64, e10: i32, b: u64, e2: i32) -> Ordering {
+    let mut a = Big::from_u64(a);
+    let mut b = Big::from_u64(b);
+
+    if e10 >= 0 {
+        a.mul_pow10(e10 as u32);
+    } else {
+        b.mul_pow10(-e10 as u32);
+    }
+
+    if e2 >= 0 {
+        b.mul_pow2(e2 as u32);
+    } else {
+        a.mul_pow2(-e2 as u32);
+    }
+
+    a.cmp(&b)
+}
+
+/// Splits a positive finite float into `f * 2^e`.
+fn decode(bits: u64) -> (u64, i32) {
+    let exp = (bits >> 52) as i32;
+    let frac = bits & ((1 << 52) - 1);
+
+    if exp == 0 {
+        (frac, -1074)
+    } else {
+        (frac | 1 << 52, exp - 1075)
+    }
+}
+
+/// Whether the float below is closer than the float above, which happens at powers of two.
+fn lower_closer(bits: u64) -> bool {
+    bits & ((1 << 52) - 1) == 0 && bits >> 52 > 1
+}
+
+fn parse_u64(s: &[u8]) -> Option<(u64, usize)> {
+    let mut val = 0u64;
+    let mut i = 0;
+
+    while let Some(c @ b'0'..=b'9') = s.get(i) {
+        val = val.checked_mul(10)?.checked_add((c - b'0') as u64)?;
+        i += 1;
+    }
+
+    if i == 0 {
+        None
+    } else {
+        Some((val, i))
+    }
+}
+
+/// An unsigned integer large enough for the scaled values used when formatting and parsing floats.
+#[derive(Clone, Copy)]
+struct Big {
+    words: [u32; 40],
+    len: usize,
+}
+
+impl Big {
+    fn from_u64(val: u64) -> Self {
+        let mut big = Big { words: [0; 40], len: 2 };
+
+        big.words[0] = val as u32;
+        big.words[1] = (val >> 32) as u32;
+        big.trim();
+        big
+    }
+
+    fn trim(&mut self) {
+        while self.len > 0 && self.words[self.len - 1] == 0 {
+            self.len -= 1;
+        }
+    }
+
+    fn mul_small(&mut self, m: u32) {
+        let mut carry = 0u64;
+
+        for w in &mut self.words[..self.len] {
+            let v = *w as u64 * m as u64 + carry;
+
+            *w = v as u32;
+            carry = v >> 32;
+        }
+
+        if carry != 0 {
+            self.words[self.len] = carry as u32;
+            self.len += 1;
+        }
+    }
+
+    fn mul_pow2(&mut self, n: u32) {
+        let words = (n / 32) as usize;
+        let bits = n % 32;
+
+        if self.len == 0 {
+            return;
+        }
+
+        if bits != 0 {
+            self.words[self.len] = 0;
+            self.len += 1;
+
+            for i in (1..self.len).rev() {
+                self.words[i] = self.words[i] << bits | self.words[i - 1] >> (32 - bits);
+            }
+
+            self.words[0] <<= bits;
+        }
+
+        self.words.copy_within(..self.len, words);
+        self.words[..words].iter_mut().for_each(|w| *w = 0);
+        self.len += words;
+        self.trim();
+    }
+
+    fn mul_pow10(&mut self, mut n: u32) {
+        while n >= 9 {
+            self.mul_small(1_000_000_000);
+            n -= 9;
+        }
+
+        self.mul_small(10u32.pow(n));
+    }
+
+    fn add(&mut self, other: &Big) {
+        let len = self.len.max(other.len);
+        let mut carry = 0u64;
+
+        for i in 0..len {
+            let v = self.words[i] as u64 + other.words[i] as u64 + carry;
+
+            self.words[i] = v as u32;
+            carry = v >> 32;
+        }
+
+        self.len = len;
+
+        if carry != 0 {
+            self.words[len] = carry as u32;
+            self.len += 1;
+        }
+    }
+
+    /// Subtracts `other`, which must not be larger than `self`.
+    fn sub(&mut self, other: &Big) {
+        let mut borrow = 0i64;
+
+        for i in 0..self.len {
+            let v = self.words[i] as i64 - other.words[i] as i64 - borrow;
+
+            self.words[i] = v as u32;
+            borrow = (v < 0) as i64;
+        }
+
+        self.trim();
+    }
+
+    fn cmp(&self, other: &Big) -> Ordering {
+        self.len.cmp(&other.len).then_with(|| self.words[..self.len].iter().rev().cmp(other.words[..other.len].iter().rev()))
+    }
+}
@@ -2,8 +2,16 @@
 
 extern crate core;
 
+pub mod bigint;
+pub mod fmt;
+pub mod pool;
+pub mod types;
+
+#[cfg(feature = "stubs")]
+pub mod stubs;
+
 use core::mem::size_of;
-use libc::{c_void, free, malloc};
+use libc::{abort, c_char, c_void, free, malloc};
 
 #[repr(transparent)]
 pub struct Box {
@@ -15,16 +23,42 @@ pub struct Weak {
     ptr: *mut BoxInner,
 }
 
+#[repr(C)]
+pub struct TypeInfo {
+    size: usize,
+    align: usize,
+    stride: usize,
+    vwt: *const c_void,
+    /// A hash of the type, which is the same in every module.
+    id: usize,
+    /// The encoding of the type in hex, which tells types with the same `id` apart.
+    name: *const c_char,
+}
+
+/// The header every box points to. The value is freed once the last box to it is freed,
+/// the header once the last weak reference is freed as well.
 struct BoxInner {
+    /// The value, or null once it has been freed.
     ptr: *mut c_void,
     strong_count: usize,
     weak_count: usize,
 }
 
+/// Shows the linker that this library provides version 1 of the runtime, which compiled modules record as
+/// a requirement. Later versions keep the symbols of the earlier ones.
+#[no_mangle]
+pub extern "C" fn lowlang_runtime_v1() {}
+
+#[no_mangle]
 pub unsafe extern "C" fn box_alloc(size: usize) -> Box {
-    let val = malloc(size);
+    // the value is never null, because a null value marks a freed box
+    let val = malloc(size.max(1));
     let ptr = malloc(size_of::<BoxInner>()) as *mut BoxInner;
 
+    if val.is_null() || ptr.is_null() {
+        abort();
+    }
+
     *ptr = BoxInner {
         ptr: val,
         strong_count: 1,
@@ -34,31 +68,76 @@ pub unsafe extern "C" fn box_alloc(size: usize) -> Box {
     Box { ptr }
 }
 
+#[no_mangle]
 pub unsafe extern "C" fn box_copy(boxed: Box) {
     (*boxed.ptr).strong_count += 1;
 }
 
+#[no_mangle]
 pub unsafe extern "C" fn box_free(boxed: Box) {
-    let strong_count = (*boxed.ptr).strong_count - 1;
+    let inner = &mut *boxed.ptr;
 
-    if strong_count == 0 {
-        free((*boxed.ptr).ptr);
-        free(boxed.ptr as *mut c_void);
-    } else {
-        (*boxed.ptr).strong_count = strong_count;
+    inner.strong_count -= 1;
+
+    if inner.strong_count == 0 {
+        free(inner.ptr);
+        inner.ptr = core::ptr::null_mut();
+
+        if inner.weak_count == 0 {
+            free(boxed.ptr as *mut c_void);
+        }
     }
 }
 
+/// The address of the value of `boxed`, aborting if the value has already been freed.
+#[no_mangle]
+pub unsafe extern "C" fn box_addr_checked(boxed: Box) -> *mut c_void {
+    let ptr = (*boxed.ptr).ptr;
+
+    if ptr.is_null() {
+        abort();
+    }
+
+    ptr
+}
+
+#[no_mangle]
 pub unsafe extern "C" fn weak_alloc(boxed: Box) -> Weak {
     (*boxed.ptr).weak_count += 1;
 
     Weak { ptr: boxed.ptr }
 }
 
+#[no_mangle]
 pub unsafe extern "C" fn weak_copy(boxed: Weak) {
     (*boxed.ptr).weak_count += 1;
 }
 
+#[no_mangle]
 pub unsafe extern "C" fn weak_free(boxed: Weak) {
-    (*boxed.ptr).weak_count -= 1;
+    let inner = &mut *boxed.ptr;
+
+    inner.weak_count -= 1;
+
+    if inner.weak_count == 0 && inner.strong_count == 0 {
+        free(boxed.ptr as *mut c_void);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn copy_trivial(dst: *mut u8, src: *const u8, ty: *const TypeInfo) {
+    core::ptr::copy_nonoverlapping(src, dst, (*ty).size);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn move_trivial(dst: *mut u8, src: *const u8, ty: *const TypeInfo) {
+    core::ptr::copy_nonoverlapping(src, dst, (*ty).size);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn copy_move_nop(_dst: *mut u8, _src: *const u8, _ty: *const TypeInfo) {
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn drop_nop(_ptr: *mut u8, _ty: *const TypeInfo) {
 }
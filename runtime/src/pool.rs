@@ -0,0 +1,93 @@
+//! Pools of a fixed number of objects of the same size, which allocate and free in constant time.
+//! Free objects form a list through their first word, objects that were never allocated are taken in order.
+
+use libc::{c_void, free, malloc};
+
+#[repr(C)]
+pub struct Pool {
+    /// The size of every object, a multiple of its alignment and at least a word.
+    stride: usize,
+    capacity: usize,
+    /// The number of objects that were ever allocated, which are either in use or on the free list.
+    used: usize,
+    free_list: *mut FreeObject,
+    objects: *mut u8,
+    /// The allocation the objects were placed in, which may start before `objects` to align them.
+    alloc: *mut c_void,
+}
+
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+/// Creates a pool of `count` objects of `size` bytes aligned to `align`, which must be a power of two.
+/// Returns null if the memory could not be allocated.
+#[no_mangle]
+pub unsafe extern "C" fn pool_create(size: usize, align: usize, count: usize) -> *mut Pool {
+    let word = core::mem::size_of::<usize>();
+    let align = align.max(word);
+    let stride = (size.max(word) + align - 1) & !(align - 1);
+    let pool = malloc(core::mem::size_of::<Pool>()) as *mut Pool;
+    let alloc = match stride.checked_mul(count).and_then(|n| n.checked_add(align)) {
+        Some(n) => malloc(n),
+        None => core::ptr::null_mut(),
+    };
+
+    if pool.is_null() || alloc.is_null() {
+        free(pool as *mut c_void);
+        free(alloc);
+        return core::ptr::null_mut();
+    }
+
+    let offset = (align - alloc as usize % align) % align;
+
+    *pool = Pool {
+        stride,
+        capacity: count,
+        used: 0,
+        free_list: core::ptr::null_mut(),
+        objects: (alloc as *mut u8).add(offset),
+        alloc,
+    };
+
+    pool
+}
+
+/// Returns an object from `pool`, or null if all of its objects are in use.
+#[no_mangle]
+pub unsafe extern "C" fn pool_alloc(pool: *mut Pool) -> *mut u8 {
+    let pool = &mut *pool;
+
+    if !pool.free_list.is_null() {
+        let obj = pool.free_list;
+
+        pool.free_list = (*obj).next;
+        return obj as *mut u8;
+    }
+
+    if pool.used == pool.capacity {
+        return core::ptr::null_mut();
+    }
+
+    let obj = pool.objects.add(pool.used * pool.stride);
+
+    pool.used += 1;
+    obj
+}
+
+/// Returns `obj`, which must have been allocated from `pool`, to `pool`.
+#[no_mangle]
+pub unsafe extern "C" fn pool_free(pool: *mut Pool, obj: *mut u8) {
+    let pool = &mut *pool;
+    let obj = obj as *mut FreeObject;
+
+    (*obj).next = pool.free_list;
+    pool.free_list = obj;
+}
+
+/// Frees `pool` along with all of its objects, whether they are in use or not.
+#[no_mangle]
+pub unsafe extern "C" fn pool_destroy(pool: *mut Pool) {
+    free((*pool).alloc);
+    free(pool as *mut c_void);
+}
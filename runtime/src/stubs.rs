@@ -0,0 +1,42 @@
+// Deterministic replacements for `write`, `clock` and `rand`, matching
+// `eval::stubs::Stubs` so compiled and evaluated programs produce the same output.
+
+const OUTPUT_CAP: usize = 1 << 16;
+const CLOCK_STEP: u64 = 1000;
+const RAND_SEED: u64 = 42;
+
+static mut OUTPUT: [u8; OUTPUT_CAP] = [0; OUTPUT_CAP];
+static mut OUTPUT_LEN: usize = 0;
+static mut CLOCK: u64 = 0;
+static mut RAND: u64 = RAND_SEED;
+
+/// Records `len` bytes of `buf` instead of writing them to `fd`.
+#[no_mangle]
+pub unsafe extern "C" fn write(_fd: i32, buf: *const u8, len: usize) -> isize {
+    let n = core::cmp::min(len, OUTPUT_CAP - OUTPUT_LEN);
+
+    core::ptr::copy_nonoverlapping(buf, OUTPUT.as_mut_ptr().add(OUTPUT_LEN), n);
+    OUTPUT_LEN += n;
+    len as isize
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn clock() -> u64 {
+    let now = CLOCK;
+
+    CLOCK += CLOCK_STEP;
+    now
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rand() -> i32 {
+    RAND = RAND.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+    (RAND >> 33) as i32
+}
+
+/// The bytes recorded by `write` so far.
+#[no_mangle]
+pub unsafe extern "C" fn stub_output(len: *mut usize) -> *const u8 {
+    *len = OUTPUT_LEN;
+    OUTPUT.as_ptr()
+}
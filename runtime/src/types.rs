@@ -0,0 +1,45 @@
+//! The registry of the type info of every loaded module, which every module fills from a constructor
+//! that runs when it is loaded.
+
+use crate::TypeInfo;
+use libc::{abort, c_char, c_void, realloc, strcmp};
+
+static mut TYPES: *mut *const TypeInfo = core::ptr::null_mut();
+static mut LEN: usize = 0;
+static mut CAP: usize = 0;
+
+#[no_mangle]
+pub unsafe extern "C" fn type_info_register(info: *const TypeInfo) {
+    if LEN == CAP {
+        CAP = (CAP * 2).max(16);
+        TYPES = realloc(TYPES as *mut c_void, CAP * core::mem::size_of::<*const TypeInfo>()) as *mut *const TypeInfo;
+
+        if TYPES.is_null() {
+            abort();
+        }
+    }
+
+    *TYPES.add(LEN) = info;
+    LEN += 1;
+}
+
+/// Returns the first registered type info with the id `id` and the name `name`, or null if no loaded module uses the type.
+#[no_mangle]
+pub unsafe extern "C" fn type_info_find(id: usize, name: *const c_char) -> *const TypeInfo {
+    for i in 0..LEN {
+        let info = *TYPES.add(i);
+
+        if (*info).id == id && strcmp((*info).name, name) == 0 {
+            return info;
+        }
+    }
+
+    core::ptr::null()
+}
+
+/// Returns whether `a` and `b` describe the same type, which may be described by a different type info in every module.
+/// The ids are only hashes, so types with the same id are compared by name.
+#[no_mangle]
+pub unsafe extern "C" fn dyn_cast(a: *const TypeInfo, b: *const TypeInfo) -> bool {
+    a == b || ((*a).id == (*b).id && strcmp((*a).name, (*b).name) == 0)
+}
@@ -1,7 +1,14 @@
+pub mod signature;
+
 use ir::*;
 
 pub trait Transform {
     fn apply(&mut self, _module: &mut Module) {}
+
+    /// The statistics of the last `apply`, reported to the progress of the pass.
+    fn stats(&self) -> Vec<progress::Stat> {
+        Vec::new()
+    }
 }
 
 pub struct NullTransform;
@@ -16,6 +23,10 @@ impl<T: Transform> Transform for Vec<T> {
             trans.apply(module);
         }
     }
+
+    fn stats(&self) -> Vec<progress::Stat> {
+        self.iter().flat_map(|trans| trans.stats()).collect()
+    }
 }
 
 impl<T: Transform> Transform for Option<T> {
@@ -24,4 +35,8 @@ impl<T: Transform> Transform for Option<T> {
             trans.apply(module);
         }
     }
+
+    fn stats(&self) -> Vec<progress::Stat> {
+        self.as_ref().map(|trans| trans.stats()).unwrap_or_default()
+    }
 }
@@ -0,0 +1,240 @@
+use ir::visitor::{Visitor, VisitorMut};
+use ir::*;
+use std::collections::HashSet;
+
+/// Finds the functions whose address is used for anything other than a direct call.
+/// These may be called indirectly, so their signatures must not be changed.
+pub fn escaped(module: &Module) -> HashSet<DeclId> {
+    let mut escaped = Escaped::default();
+
+    escaped.visit_module(module);
+    escaped.decls
+}
+
+/// Removes the parameters at the given indices from `decl`. The corresponding
+/// locals in its body become variables and the arguments are dropped from every call.
+pub fn remove_params(module: &mut Module, decl: DeclId, params: &[usize]) {
+    let mut params = params.to_vec();
+
+    params.sort_unstable();
+    params.dedup();
+
+    if let Type::Func(sig) = &mut module.decls[decl].ty.kind {
+        for &i in params.iter().rev() {
+            sig.params.remove(i);
+        }
+    }
+
+    for body in module.bodies.iter_mut() {
+        if body.decl == decl {
+            let args = body.args().map(|a| a.id).collect::<Vec<_>>();
+
+            for &i in &params {
+                body.locals[args[i]].kind = LocalKind::Var;
+            }
+        }
+
+        for block in body.blocks.iter_mut() {
+            for stmt in &mut block.stmts {
+                if let Stmt::Call(_, Operand::Const(Const::Addr(callee)), args, _) = stmt {
+                    if *callee == decl {
+                        for &i in params.iter().rev() {
+                            args.remove(i);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Turns return value `ret` of `decl` into a pointer parameter the caller
+/// passes the destination through. Returns the index of the new parameter, or `None` without changing
+/// anything if `decl` may be called from somewhere its calls can not be rewritten.
+pub fn introduce_sret(module: &mut Module, decl: DeclId, ret: usize) -> Option<usize> {
+    let d = &module.decls[decl];
+
+    if d.linkage != Linkage::Local || d.attrs.c_abi || escaped(module).contains(&decl) {
+        return None;
+    }
+
+    let mut param = 0;
+
+    for body in module.bodies.iter_mut().filter(|b| b.decl == decl) {
+        let local = body.rets().nth(ret).unwrap().id;
+        let ty = body.locals[local].ty.clone();
+
+        param = body.args().take_while(|a| a.id < local).count();
+        body.locals[local].kind = LocalKind::Arg;
+        body.locals[local].ty = Ty::new(Type::Ptr(Box::new(ty)));
+
+        // the destination belongs to the caller, which initializes and drops it
+        for block in body.blocks.iter_mut() {
+            block.stmts.retain(|stmt| !matches!(stmt, Stmt::Init(l) | Stmt::Drop(l) if *l == local));
+        }
+
+        DerefLocal(local).visit_body(body);
+    }
+
+    if let Type::Func(sig) = &mut module.decls[decl].ty.kind {
+        let ty = sig.rets.remove(ret);
+
+        sig.params.insert(param, Ty::new(Type::Ptr(Box::new(ty))));
+    }
+
+    for body in module.bodies.iter_mut() {
+        for block in body.blocks.indices() {
+            let mut i = 0;
+
+            while i < body.blocks[block].stmts.len() {
+                let dest = match &mut body.blocks[block].stmts[i] {
+                    Stmt::Call(rets, Operand::Const(Const::Addr(callee)), _, _) if *callee == decl => rets.remove(ret),
+                    _ => {
+                        i += 1;
+                        continue;
+                    }
+                };
+
+                let ptr = body.locals.next_idx();
+                let ty = place_type(body, &dest);
+
+                body.locals.push(LocalData {
+                    id: ptr,
+                    kind: LocalKind::Tmp,
+                    ty: Ty::new(Type::Ptr(Box::new(ty))),
+                });
+
+                if let Stmt::Call(_, _, args, _) = &mut body.blocks[block].stmts[i] {
+                    args.insert(param, Operand::Place(Place::new(ptr)));
+                }
+
+                body.blocks[block].stmts.insert(i, Stmt::Assign(Place::new(ptr), RValue::AddrOf(dest)));
+                i += 2;
+            }
+        }
+    }
+
+    Some(param)
+}
+
+#[derive(Default)]
+struct Escaped {
+    decls: HashSet<DeclId>,
+}
+
+impl Visitor for Escaped {
+    fn visit_stmt(&mut self, stmt: &Stmt, loc: Location) {
+        if let Stmt::Call(rets, Operand::Const(Const::Addr(_)), args, _) = stmt {
+            for place in rets {
+                self.visit_place(place);
+            }
+
+            for op in args {
+                self.visit_op(op);
+            }
+        } else {
+            self.super_stmt(stmt, loc);
+        }
+    }
+
+    fn visit_impl_entry(&mut self, entry: &ImplEntry) {
+        if let ImplEntry::Func(_, decl) = entry {
+            self.decls.insert(*decl);
+        }
+    }
+
+    fn visit_const(&mut self, c: &Const) {
+        if let Const::Addr(decl) = c {
+            self.decls.insert(*decl);
+        }
+
+        self.super_const(c);
+    }
+}
+
+/// Replaces every use of a local with a dereference of it.
+struct DerefLocal(Local);
+
+impl VisitorMut for DerefLocal {
+    fn visit_place(&mut self, place: &mut Place) {
+        if place.local == self.0 {
+            place.elems.insert(0, PlaceElem::Deref);
+        }
+
+        self.super_place(place);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `@f` returns 1 through `_0`, which `@g` passes on.
+    const SRC: &str = "local @f :: () -> (i32)
+export @g :: () -> (i32)
+fn @f {
+    ret _0 :: i32
+%0:
+    _0 = 1 :: i32
+    return
+}
+fn @g {
+    ret _0 :: i32
+%0:
+    call @f() -> _0
+    return
+}
+";
+
+    fn module() -> Module {
+        let mut module = parser::parse(SRC).unwrap();
+
+        module.bodies[BodyId::new(0)].blocks[Block::new(0)].stmts.insert(0, Stmt::Init(Local::new(0)));
+        module
+    }
+
+    #[test]
+    fn sret() {
+        let mut module = module();
+        let expected = parser::parse(
+            "local @f :: (*i32) -> ()
+export @g :: () -> (i32)
+fn @f {
+    arg _0 :: *i32
+%0:
+    *_0 = 1 :: i32
+    return
+}
+fn @g {
+    ret _0 :: i32
+    tmp _1 :: *i32
+%0:
+    _1 = addrof _0
+    call @f(_1)
+    return
+}
+",
+        )
+        .unwrap();
+
+        assert_eq!(introduce_sret(&mut module, DeclId::new(0), 0), Some(0));
+        assert_eq!(module, expected);
+    }
+
+    #[test]
+    fn sret_escaped() {
+        let mut module = module();
+        let f = DeclId::new(0);
+        let g = &mut module.bodies[BodyId::new(1)];
+        let mut builder = Builder::new(g);
+        let addr = builder.create_tmp(module.decls[f].ty.clone());
+
+        // `@g` takes the address of `@f`, which may then be called with the old signature
+        g.blocks[Block::new(0)].stmts.push(Stmt::Assign(Place::new(addr), RValue::Use(Operand::Const(Const::Addr(f)))));
+
+        let before = module.clone();
+
+        assert_eq!(introduce_sret(&mut module, f, 0), None);
+        assert_eq!(module, before);
+    }
+}
@@ -0,0 +1,28 @@
+//! Generates random modules and runs each of them in the interpreter and compiled in memory, with and without
+//! optimizations, stopping at the first module on which they disagree.
+//!
+//! ```text
+//! cargo run -p lowlang --example fuzz [count] [first seed]
+//! ```
+
+use lowlang::diff::{diff, Generator};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let count = args.next().map_or(100, |a| a.parse().expect("the count must be a number"));
+    let start = args.next().map_or(0, |a| a.parse().expect("the seed must be a number"));
+
+    for seed in start..start + count {
+        let module = Generator::new(seed).module();
+
+        for &optimize in &[false, true] {
+            if let Err(e) = diff(&module, optimize) {
+                println!("seed {} ({}): {}", seed, if optimize { "optimized" } else { "unoptimized" }, e);
+                println!("{}", module);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    println!("{} modules ok", count);
+}
@@ -0,0 +1,461 @@
+//! Builds a set of programs with the builder API and runs each of them twice, once in the interpreter
+//! and once compiled ahead of time, checking that both agree.
+//!
+//! ```text
+//! cargo run -p lowlang --example suite [name]
+//! ```
+//!
+//! Every program exports a `main` returning its result, which becomes the exit status of the compiled program.
+//! Compiling needs a C compiler to link with, see `LinkOptions::linker`.
+
+use lowlang::eval::stubs::Externs;
+use lowlang::ir::{self, Builder, Const, Operand, Place, Ty, Type};
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+struct Example {
+    name: &'static str,
+    build: fn() -> ir::Module,
+    /// The value returned by `main`.
+    status: i32,
+    /// What the program prints.
+    output: &'static str,
+}
+
+const EXAMPLES: &[Example] = &[
+    Example {
+        name: "fibonacci",
+        build: fibonacci,
+        status: 55,
+        output: "",
+    },
+    Example {
+        name: "linked_list",
+        build: linked_list,
+        status: 6,
+        output: "",
+    },
+    Example {
+        name: "generic_swap",
+        build: generic_swap,
+        status: 21,
+        output: "",
+    },
+    Example {
+        name: "c_like_enum",
+        build: c_like_enum,
+        status: 7,
+        output: "",
+    },
+    Example {
+        name: "hello",
+        build: hello,
+        status: 0,
+        output: "hello 42\n",
+    },
+];
+
+fn main() {
+    let filter = std::env::args().nth(1);
+    let target = target_lexicon::Triple::host();
+    let dir = std::env::temp_dir().join("lowlang-examples");
+    let mut failed = 0;
+
+    std::fs::create_dir_all(&dir).unwrap();
+
+    for example in EXAMPLES.iter().filter(|e| filter.as_deref().map_or(true, |f| f == e.name)) {
+        let module = (example.build)();
+        let results = [("eval", interpret(&module, &target)), ("aot", compile(&module, example.name, &dir))];
+
+        for (path, result) in results.iter() {
+            match result {
+                Ok((status, output)) if *status == example.status && output == example.output => {
+                    println!("{:>12} {:<5} ok", example.name, path);
+                }
+                Ok((status, output)) => {
+                    failed += 1;
+                    println!("{:>12} {:<5} FAILED: returned {} and printed {:?}", example.name, path, status, output);
+                    println!("{:>12} {:<5} expected {} and {:?}", "", "", example.status, example.output);
+                }
+                Err(e) => {
+                    failed += 1;
+                    println!("{:>12} {:<5} FAILED: {}", example.name, path, e);
+                }
+            }
+        }
+    }
+
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// Evaluates `main` before any analysis has run, since the interpreter works on values rather than memory.
+fn interpret(module: &ir::Module, target: &target_lexicon::Triple) -> Result<(i32, String), String> {
+    let main = module.decls.iter().find(|d| d.name == "main").ok_or("no main function")?;
+    let body = module.bodies.iter().find(|b| b.decl == main.id).ok_or("main is not defined")?;
+    let printf = Rc::new(RefCell::new(Printf(String::new())));
+    let args = vec![Const::Scalar(0, Ty::new(Type::I32)), Const::Undefined(argv())];
+    let mut ctx = lowlang::eval::EvalCtx::new(module, body, target).with_args(args).with_externs(printf.clone());
+
+    ctx.eval().map_err(|e| e.to_string())?;
+
+    match ctx.finish().as_slice() {
+        [Const::Scalar(status, _)] => Ok((*status as i32, printf.borrow().0.clone())),
+        rets => Err(format!("main returned {:?}", rets)),
+    }
+}
+
+/// Compiles the program into an executable in `dir` and runs it.
+fn compile(module: &ir::Module, name: &str, dir: &Path) -> Result<(i32, String), String> {
+    let mut module = module.clone();
+    let target = target_lexicon::Triple::host();
+    let options = lowlang::analysis::Options {
+        witnesses: lowlang::analysis::type_info::Witnesses::Define,
+    };
+
+    lowlang::analysis::mandatory_with(&mut module, &target, options, &mut ir::progress::NoProgress, &ir::progress::CancellationToken::new())
+        .map_err(|e| e.to_string())?;
+
+    let obj = lowlang::assemble::assemble(&module, target);
+    let exe = dir.join(name);
+    let mut link = lowlang::codegen::link::LinkOptions::default();
+
+    link.output = exe.clone();
+    link.allow_undefined.push(String::from("printf"));
+    obj.link(&link).map_err(|e| e.to_string())?;
+
+    let out = std::process::Command::new(&exe).output().map_err(|e| e.to_string())?;
+    let status = out.status.code().ok_or_else(|| format!("{} was killed: {}", exe.display(), out.status))?;
+
+    Ok((status, String::from_utf8_lossy(&out.stdout).into_owned()))
+}
+
+/// Implements `printf` in the interpreter, supporting `%d` and `%s`.
+struct Printf(String);
+
+impl Externs for Printf {
+    fn call(
+        &mut self,
+        _: &ir::Module,
+        decl: &ir::Decl,
+        args: &[Const],
+        _: &target_lexicon::Triple,
+    ) -> Result<Vec<Const>, lowlang::eval::Error> {
+        if decl.name != "printf" {
+            return Err(lowlang::eval::Error::UnknownSymbol(decl.name.clone()));
+        }
+
+        let start = self.0.len();
+        let mut args = args.iter();
+        let format = c_str(args.next()).unwrap_or_default();
+        let mut chars = format.chars();
+
+        while let Some(c) = chars.next() {
+            match (c, chars.clone().next()) {
+                ('%', Some('d')) => {
+                    if let Some(Const::Scalar(val, _)) = args.next() {
+                        self.0.push_str(&(*val as i32).to_string());
+                    }
+
+                    chars.next();
+                }
+                ('%', Some('s')) => {
+                    self.0.push_str(&c_str(args.next()).unwrap_or_default());
+                    chars.next();
+                }
+                _ => self.0.push(c),
+            }
+        }
+
+        Ok(vec![Const::Scalar((self.0.len() - start) as u128, Ty::new(Type::I32))])
+    }
+}
+
+fn c_str(arg: Option<&Const>) -> Option<String> {
+    match arg? {
+        Const::Ptr(to) => match &**to {
+            Const::Bytes(b) => Some(String::from_utf8_lossy(b.split(|b| *b == 0).next()?).into_owned()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn argv() -> Ty {
+    Ty::new(Type::Ptr(Box::new(Ty::new(Type::Ptr(Box::new(Ty::new(Type::I8)))))))
+}
+
+fn func(module: &mut ir::Module, name: &str, linkage: ir::Linkage, params: Vec<Ty>, rets: Vec<Ty>) -> ir::DeclId {
+    let sig = ir::Signature {
+        params,
+        rets,
+        call_conv: ir::CallConv::Fluix,
+        variadic: false,
+    };
+
+    module.declare_global(name, linkage, Ty::new(Type::Func(sig)))
+}
+
+fn empty() -> ir::Module {
+    ir::Module {
+        decls: Default::default(),
+        impls: Default::default(),
+        bodies: Default::default(),
+    }
+}
+
+/// Declares `main` and builds its body with `f`, which receives the local holding the return value.
+fn main_body(module: &mut ir::Module, f: impl FnOnce(&mut Builder, ir::Local)) {
+    let main = func(module, "main", ir::Linkage::Export, vec![Ty::new(Type::I32), argv()], vec![Ty::new(Type::I32)]);
+    let mut body = ir::Body::new(module.bodies.next_idx(), main);
+    let mut builder = Builder::new(&mut body);
+    let ret = builder.create_ret(Ty::new(Type::I32));
+
+    builder.create_arg(Ty::new(Type::I32));
+    builder.create_arg(argv());
+
+    let entry = builder.create_block();
+
+    builder.set_block(entry);
+    f(&mut builder, ret);
+    module.bodies.push(body);
+}
+
+fn int(val: i32) -> Operand {
+    Operand::Const(Const::Scalar(val as u32 as u128, Ty::new(Type::I32)))
+}
+
+fn checked() -> Ty {
+    Ty::new(Type::Tuple(vec![Ty::new(Type::I32), Ty::new(Type::Bool)]))
+}
+
+fn op(place: Place) -> Operand {
+    Operand::Place(place)
+}
+
+/// Recursion, switches and checked arithmetic: `fib(10)`.
+fn fibonacci() -> ir::Module {
+    let mut module = empty();
+    let fib = func(&mut module, "fib", ir::Linkage::Local, vec![Ty::new(Type::I32)], vec![Ty::new(Type::I32)]);
+    let mut body = ir::Body::new(module.bodies.next_idx(), fib);
+    let mut builder = Builder::new(&mut body);
+    let ret = builder.create_ret(Ty::new(Type::I32));
+    let n = builder.create_arg(Ty::new(Type::I32));
+    let tmp = builder.create_tmp(checked());
+    let a = builder.create_tmp(Ty::new(Type::I32));
+    let b = builder.create_tmp(Ty::new(Type::I32));
+    let entry = builder.create_block();
+    let small = builder.create_block();
+    let recurse = builder.create_block();
+
+    builder.set_block(entry);
+    builder.switch(op(Place::new(n)), vec![0, 1], vec![small, small, recurse]);
+
+    builder.set_block(small);
+    builder.use_op(Place::new(ret), op(Place::new(n)));
+    builder.return_();
+
+    builder.set_block(recurse);
+
+    for (i, into) in [(1, a), (2, b)].iter() {
+        builder.intrinsic(Place::new(tmp), "sub_i32_checked", vec![op(Place::new(n)), int(*i)]);
+        builder.call(vec![Place::new(*into)], Operand::Const(Const::Addr(fib)), vec![op(Place::new(tmp).field(0))]);
+    }
+
+    builder.intrinsic(Place::new(tmp), "add_i32_checked", vec![op(Place::new(a)), op(Place::new(b))]);
+    builder.use_op(Place::new(ret), op(Place::new(tmp).field(0)));
+    builder.return_();
+    module.bodies.push(body);
+
+    main_body(&mut module, |builder, ret| {
+        builder.call(vec![Place::new(ret)], Operand::Const(Const::Addr(fib)), vec![int(10)]);
+        builder.return_();
+    });
+
+    module
+}
+
+/// Boxes and tagged unions: sums the list `1, 2, 3`, where every node is a box holding a value and
+/// an optional pointer to the next node.
+fn linked_list() -> ir::Module {
+    let next = Ty::new(Type::Tagged(vec![
+        Ty::new(Type::Tuple(Vec::new())),
+        Ty::new(Type::Tuple(vec![Ty::new(Type::Ptr(Box::new(Ty::new(Type::U8))))])),
+    ]));
+
+    let node = Ty::new(Type::Tuple(vec![Ty::new(Type::I32), next.clone()]));
+    let boxed = Ty::new(Type::Box(Box::new(node)));
+    let mut module = empty();
+    let sum = func(&mut module, "sum", ir::Linkage::Local, vec![boxed.clone()], vec![Ty::new(Type::I32)]);
+    let mut body = ir::Body::new(module.bodies.next_idx(), sum);
+    let mut builder = Builder::new(&mut body);
+    let ret = builder.create_ret(Ty::new(Type::I32));
+    let list = builder.create_arg(boxed.clone());
+    let tmp = builder.create_tmp(checked());
+    let discr = builder.create_tmp(Ty::new(Type::Discr(Box::new(next.clone()))));
+    let rest = builder.create_tmp(boxed.clone());
+    let entry = builder.create_block();
+    let last = builder.create_block();
+    let recurse = builder.create_block();
+
+    builder.set_block(entry);
+    builder.get_discr(Place::new(discr), Place::new(list).deref().field(1));
+    builder.switch(op(Place::new(discr)), vec![0], vec![last, recurse]);
+
+    builder.set_block(last);
+    builder.use_op(Place::new(ret), op(Place::new(list).deref().field(0)));
+    builder.return_();
+
+    builder.set_block(recurse);
+    builder.cast(Place::new(rest), Place::new(list).deref().field(1).downcast(1).field(0), boxed.clone());
+    builder.call(vec![Place::new(ret)], Operand::Const(Const::Addr(sum)), vec![op(Place::new(rest))]);
+    builder.intrinsic(Place::new(tmp), "add_i32_checked", vec![op(Place::new(ret)), op(Place::new(list).deref().field(0))]);
+    builder.use_op(Place::new(ret), op(Place::new(tmp).field(0)));
+    builder.return_();
+    module.bodies.push(body);
+
+    main_body(&mut module, |builder, ret| {
+        let ptr = builder.create_tmp(Ty::new(Type::Ptr(Box::new(Ty::new(Type::U8)))));
+        let nodes = (0..3).map(|_| builder.create_var(boxed.clone())).collect::<Vec<_>>();
+
+        for (i, node) in nodes.iter().enumerate().rev() {
+            let place = Place::new(*node).deref();
+
+            builder.init(*node);
+            builder.use_op(place.clone().field(0), int(i as i32 + 1));
+
+            if let Some(next) = nodes.get(i + 1) {
+                builder.cast(Place::new(ptr), Place::new(*next), Ty::new(Type::Ptr(Box::new(Ty::new(Type::U8)))));
+                builder.set_discr(place.clone().field(1), 1);
+                builder.use_op(place.field(1).downcast(1).field(0), op(Place::new(ptr)));
+            } else {
+                builder.set_discr(place.field(1), 0);
+            }
+        }
+
+        builder.call(vec![Place::new(ret)], Operand::Const(Const::Addr(sum)), vec![op(Place::new(nodes[0]))]);
+
+        for node in nodes {
+            builder.drop(node);
+        }
+
+        builder.return_();
+    });
+
+    module
+}
+
+/// Generic functions: swaps two integers with a function that works for values of any type.
+fn generic_swap() -> ir::Module {
+    let t = Ty::new(Type::Opaque(String::from("T")));
+    let mut module = empty();
+    let swap = func(&mut module, "swap", ir::Linkage::Local, vec![t.clone(), t.clone()], vec![t.clone(), t.clone()]);
+    let mut body = ir::Body::new(module.bodies.next_idx(), swap);
+    let mut builder = Builder::new(&mut body);
+    let rets = [builder.create_ret(t.clone()), builder.create_ret(t.clone())];
+    let args = [builder.create_arg(t.clone()), builder.create_arg(t)];
+    let entry = builder.create_block();
+
+    builder.set_block(entry);
+    builder.use_op(Place::new(rets[0]), op(Place::new(args[1])));
+    builder.use_op(Place::new(rets[1]), op(Place::new(args[0])));
+    builder.return_();
+    module.bodies.push(body);
+
+    main_body(&mut module, |builder, ret| {
+        let x = builder.create_var(Ty::new(Type::I32));
+        let y = builder.create_var(Ty::new(Type::I32));
+        let tmp = builder.create_tmp(checked());
+
+        builder.call(vec![Place::new(x), Place::new(y)], Operand::Const(Const::Addr(swap)), vec![int(1), int(2)]);
+
+        // x * 10 + y
+        builder.intrinsic(Place::new(tmp), "mul_i32_checked", vec![op(Place::new(x)), int(10)]);
+        builder.intrinsic(Place::new(tmp), "add_i32_checked", vec![op(Place::new(tmp).field(0)), op(Place::new(y))]);
+        builder.use_op(Place::new(ret), op(Place::new(tmp).field(0)));
+        builder.return_();
+    });
+
+    module
+}
+
+/// Enums without payloads, which are passed and switched on as their tag: cycles a colour from blue through
+/// red to green.
+fn c_like_enum() -> ir::Module {
+    let unit = Ty::new(Type::Tuple(Vec::new()));
+    let color = Ty::new(Type::Tagged(vec![unit.clone(), unit.clone(), unit]));
+    let variant = |idx| Operand::Const(Const::Variant(idx, Vec::new(), color.clone()));
+    let mut module = empty();
+    let next = func(&mut module, "next", ir::Linkage::Local, vec![color.clone()], vec![color.clone()]);
+    let mut body = ir::Body::new(module.bodies.next_idx(), next);
+    let mut builder = Builder::new(&mut body);
+    let ret = builder.create_ret(color.clone());
+    let arg = builder.create_arg(color.clone());
+    let discr = builder.create_tmp(Ty::new(Type::Discr(Box::new(color.clone()))));
+    let entry = builder.create_block();
+    let blocks = (0..3).map(|_| builder.create_block()).collect::<Vec<_>>();
+
+    builder.set_block(entry);
+    builder.get_discr(Place::new(discr), Place::new(arg));
+    builder.switch(op(Place::new(discr)), vec![0, 1], blocks.clone());
+
+    for (i, block) in blocks.into_iter().enumerate() {
+        builder.set_block(block);
+        builder.use_op(Place::new(ret), variant((i + 1) % 3));
+        builder.return_();
+    }
+
+    module.bodies.push(body);
+
+    main_body(&mut module, |builder, ret| {
+        let val = builder.create_var(color.clone());
+        let discr = builder.create_tmp(Ty::new(Type::Discr(Box::new(color.clone()))));
+        let green = builder.create_block();
+        let other = builder.create_block();
+
+        builder.use_op(Place::new(val), variant(2));
+        builder.call(vec![Place::new(val)], Operand::Const(Const::Addr(next)), vec![op(Place::new(val))]);
+        builder.call(vec![Place::new(val)], Operand::Const(Const::Addr(next)), vec![op(Place::new(val))]);
+        builder.get_discr(Place::new(discr), Place::new(val));
+        builder.switch(op(Place::new(discr)), vec![1], vec![green, other]);
+
+        builder.set_block(green);
+        builder.use_op(Place::new(ret), int(7));
+        builder.return_();
+
+        builder.set_block(other);
+        builder.use_op(Place::new(ret), int(0));
+        builder.return_();
+    });
+
+    module
+}
+
+/// Variadic calls to C: `printf("hello %d\n", 42)`.
+fn hello() -> ir::Module {
+    let str_ty = Ty::new(Type::Ptr(Box::new(Ty::new(Type::U8))));
+    let mut module = empty();
+    let sig = ir::Signature {
+        params: vec![str_ty.clone()],
+        rets: vec![Ty::new(Type::I32)],
+        call_conv: ir::CallConv::C,
+        variadic: true,
+    };
+
+    let printf = module.declare_global("printf", ir::Linkage::Import, Ty::new(Type::Func(sig)));
+
+    module.decls[printf].attrs.c_abi = true;
+
+    main_body(&mut module, |builder, ret| {
+        let format = Const::Ptr(Box::new(Const::Bytes(b"hello %d\n\0".to_vec().into_boxed_slice())));
+        let printed = builder.create_tmp(Ty::new(Type::I32));
+
+        builder.call(vec![Place::new(printed)], Operand::Const(Const::Addr(printf)), vec![Operand::Const(format), int(42)]);
+        builder.use_op(Place::new(ret), int(0));
+        builder.return_();
+    });
+
+    module
+}
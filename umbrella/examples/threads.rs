@@ -0,0 +1,75 @@
+//! Measures how much compiling functions on several threads speeds up code generation, using a module of
+//! generated functions.
+//!
+//! ```text
+//! cargo run --release -p lowlang --example threads [functions] [threads]
+//! ```
+
+use lowlang::diff::Generator;
+use lowlang::ir;
+use lowlang::ir::progress::{CancellationToken, NoProgress};
+use std::time::{Duration, Instant};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let count = args.next().map_or(500, |a| a.parse().expect("the number of functions must be a number"));
+    let threads = args.next().map_or_else(
+        || std::thread::available_parallelism().map_or(4, |n| n.get()),
+        |a| a.parse().expect("the number of threads must be a number"),
+    );
+
+    let mut module = ir::Module {
+        decls: Default::default(),
+        impls: Default::default(),
+        bodies: Default::default(),
+    };
+
+    for seed in 0..count {
+        let mut generated = Generator::new(seed as u64);
+
+        generated.steps = 40;
+
+        let generated = generated.module();
+        let mut body = generated.bodies.into_iter().next().unwrap();
+        let ty = generated.decls[body.decl].ty.clone();
+
+        body.decl = module.declare_global(&format!("f{}", seed), ir::Linkage::Export, ty);
+        body.id = module.bodies.next_idx();
+        module.bodies.push(body);
+    }
+
+    let target = target_lexicon::Triple::host();
+
+    lowlang::analysis::mandatory(&mut module, &target);
+
+    let (one, size) = build(&module, 1);
+    let (many, size_many) = build(&module, threads);
+
+    assert_eq!(size, size_many, "the objects differ in size");
+
+    println!("{} functions, {} bytes", count, size);
+    println!("1 thread:   {:?}", one);
+    println!("{} threads: {:?}", threads, many);
+    println!("speedup:    {:.2}x", one.as_secs_f64() / many.as_secs_f64());
+}
+
+fn build(module: &ir::Module, threads: usize) -> (Duration, u64) {
+    let options = lowlang::codegen::CodegenOptions {
+        threads,
+        ..Default::default()
+    };
+
+    let start = Instant::now();
+    let obj = lowlang::assemble::assemble_with(
+        module,
+        target_lexicon::Triple::host(),
+        options,
+        &mut NoProgress,
+        &CancellationToken::new(),
+    )
+    .unwrap();
+
+    let elapsed = start.elapsed();
+
+    (elapsed, std::fs::metadata(obj.path()).unwrap().len())
+}
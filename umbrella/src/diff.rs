@@ -0,0 +1,210 @@
+//! Differential testing: runs a module both in the interpreter and compiled in memory, and checks that they agree.
+
+use ir::rand::SplitMix64;
+use ir::{Builder, Const, Local, Operand, Place, Ty, Type};
+
+#[derive(Debug)]
+pub enum DiffError {
+    NoMain,
+    Eval(eval::Error),
+    /// The module could not be compiled or loaded.
+    Jit(String),
+    /// The interpreter returned something other than a single integer from `main`.
+    Returned(Vec<Const>),
+    Mismatch { eval: i32, jit: i32 },
+}
+
+/// Runs `main`, which must have the signature `() -> (i32)`, in the interpreter and compiled by cranelift,
+/// returning the status both agree on. The interpreter runs the module as it is, while the compiled module
+/// goes through the mandatory passes first, and through the optimizations as well if `optimize` is set.
+pub fn diff(module: &ir::Module, optimize: bool) -> Result<i32, DiffError> {
+    let target = target_lexicon::Triple::host();
+    let expected = interpret(module, &target)?;
+    let mut module = module.clone();
+
+    analysis::mandatory(&mut module, &target);
+
+    if optimize {
+        analysis::optimize(&mut module, &target);
+    }
+
+    let status = assemble::run_jit(&module).map_err(|e| DiffError::Jit(e.to_string()))?;
+
+    if status == expected {
+        Ok(status)
+    } else {
+        Err(DiffError::Mismatch { eval: expected, jit: status })
+    }
+}
+
+fn interpret(module: &ir::Module, target: &target_lexicon::Triple) -> Result<i32, DiffError> {
+    let main = module.decls.iter().find(|d| d.name == "main").ok_or(DiffError::NoMain)?;
+    let body = module.bodies.iter().find(|b| b.decl == main.id).ok_or(DiffError::NoMain)?;
+    let rets = eval::evaluate(module, body, target).map_err(DiffError::Eval)?;
+
+    match rets.as_slice() {
+        [Const::Scalar(status, _)] => Ok(*status as i32),
+        _ => Err(DiffError::Returned(rets)),
+    }
+}
+
+/// Generates random modules to `diff`. Their `main` computes with a few variables using the arithmetic
+/// intrinsics, and branches on comparisons of them. Branches always join again, so every module terminates,
+/// and divisions only divide by constants that can not trap.
+pub struct Generator {
+    rng: SplitMix64,
+    /// The number of variables `main` computes with.
+    pub vars: usize,
+    /// The number of operations and branches in `main`, not counting those inside the branches.
+    pub steps: usize,
+}
+
+impl Generator {
+    pub fn new(seed: u64) -> Self {
+        Generator {
+            rng: SplitMix64::new(seed),
+            vars: 4,
+            steps: 20,
+        }
+    }
+
+    pub fn module(&mut self) -> ir::Module {
+        let mut module = ir::Module {
+            decls: Default::default(),
+            impls: Default::default(),
+            bodies: Default::default(),
+        };
+
+        let sig = ir::Signature {
+            params: Vec::new(),
+            rets: vec![i32()],
+            call_conv: ir::CallConv::Fluix,
+            variadic: false,
+        };
+
+        let main = module.declare_global("main", ir::Linkage::Export, Ty::new(Type::Func(sig)));
+        let mut body = ir::Body::new(module.bodies.next_idx(), main);
+        let mut builder = Builder::new(&mut body);
+        let ret = builder.create_ret(i32());
+        let vars = (0..self.vars.max(1)).map(|_| builder.create_var(i32())).collect::<Vec<_>>();
+        let cond = builder.create_tmp(Ty::new(Type::Bool));
+        let entry = builder.create_block();
+
+        builder.set_block(entry);
+
+        for var in &vars {
+            let val = self.constant();
+
+            builder.use_op(Place::new(*var), val);
+        }
+
+        for _ in 0..self.steps {
+            if self.rng.below(4) == 0 {
+                self.branch(&mut builder, &vars, cond);
+            } else {
+                self.arith(&mut builder, &vars);
+            }
+        }
+
+        // every variable ends up in the result, so a wrong value anywhere changes it
+        builder.use_op(Place::new(ret), Operand::Place(Place::new(vars[0])));
+
+        for var in &vars[1..] {
+            builder.intrinsic(Place::new(ret), "add_i32", vec![
+                Operand::Place(Place::new(ret)),
+                Operand::Place(Place::new(*var)),
+            ]);
+        }
+
+        builder.return_();
+        module.bodies.push(body);
+        module
+    }
+
+    fn arith(&mut self, builder: &mut Builder, vars: &[Local]) {
+        let dest = Place::new(vars[self.rng.below(vars.len() as u64) as usize]);
+        let a = self.operand(vars);
+
+        match self.rng.below(8) {
+            0 => builder.intrinsic(dest, "add_i32", vec![a, self.operand(vars)]),
+            1 => builder.intrinsic(dest, "sub_i32", vec![a, self.operand(vars)]),
+            2 => builder.intrinsic(dest, "mul_i32", vec![a, self.operand(vars)]),
+            3 => builder.intrinsic(dest, "shl_i32", vec![a, int(self.rng.below(32) as i32)]),
+            4 => builder.intrinsic(dest, "div_i32", vec![a, self.divisor()]),
+            5 => builder.intrinsic(dest, "rem_i32", vec![a, self.divisor()]),
+            6 => builder.neg(dest, a),
+            _ => builder.bitnot(dest, a),
+        }
+    }
+
+    fn branch(&mut self, builder: &mut Builder, vars: &[Local], cond: Local) {
+        let cmp = ["lt", "le", "gt", "ge", "eq", "ne"][self.rng.below(6) as usize];
+        let args = vec![self.operand(vars), self.operand(vars)];
+        let then = builder.create_block();
+        let else_ = builder.create_block();
+        let join = builder.create_block();
+
+        builder.intrinsic(Place::new(cond), format!("{}_i32", cmp), args);
+        builder.br_if(Operand::Place(Place::new(cond)), then, else_);
+
+        for block in [then, else_].iter() {
+            builder.set_block(*block);
+
+            for _ in 0..=self.rng.below(3) {
+                self.arith(builder, vars);
+            }
+
+            builder.jump(join);
+        }
+
+        builder.set_block(join);
+    }
+
+    fn operand(&mut self, vars: &[Local]) -> Operand {
+        if self.rng.below(3) == 0 {
+            self.constant()
+        } else {
+            Operand::Place(Place::new(vars[self.rng.below(vars.len() as u64) as usize]))
+        }
+    }
+
+    /// Mostly small constants, since those are the ones that hit the edge cases of the peephole rules.
+    fn constant(&mut self) -> Operand {
+        if self.rng.below(2) == 0 {
+            int(self.rng.below(33) as i32 - 16)
+        } else {
+            int(self.rng.next_u64() as i32)
+        }
+    }
+
+    /// A divisor other than zero and minus one, which would trap.
+    fn divisor(&mut self) -> Operand {
+        let val = 2 + self.rng.below(30) as i32;
+
+        int(if self.rng.below(2) == 0 { val } else { -val })
+    }
+
+
+}
+
+fn i32() -> Ty {
+    Ty::new(Type::I32)
+}
+
+fn int(val: i32) -> Operand {
+    Operand::Const(Const::Scalar(val as u32 as u128, i32()))
+}
+
+impl std::fmt::Display for DiffError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DiffError::NoMain => write!(f, "main is not defined"),
+            DiffError::Eval(e) => write!(f, "the interpreter failed: {}", e),
+            DiffError::Jit(e) => write!(f, "the compiled module failed: {}", e),
+            DiffError::Returned(rets) => write!(f, "main returned {:?}", rets),
+            DiffError::Mismatch { eval, jit } => write!(f, "the interpreter returned {}, the compiled module {}", eval, jit),
+        }
+    }
+}
+
+impl std::error::Error for DiffError {}
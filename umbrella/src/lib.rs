@@ -1,3 +1,5 @@
+pub mod diff;
+
 pub use analysis;
 pub use assemble;
 pub use codegen;